@@ -0,0 +1,123 @@
+use crate::r2_client::R2Client;
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+
+// Client-side backups for buckets without versioning: before an upload replaces an
+// object, the current object is copied to `<key>.bak-<UTC timestamp>`. Timestamps sort
+// lexicographically, so the last backup listed is the latest.
+const BACKUP_MARKER: &str = ".bak-";
+
+pub const DEFAULT_KEEP_BACKUPS: usize = 5;
+
+/// Copy `key` to a new backup if it exists, then delete all but the newest `keep`
+/// backups of it. Returns the backup's key, or None if there was nothing to back up.
+pub async fn backup_before_overwrite(
+    client: &R2Client,
+    key: &str,
+    keep: usize,
+) -> Result<Option<String>> {
+    if !client.object_exists(key).await? {
+        return Ok(None);
+    }
+
+    let backup_key = format!(
+        "{}{}{}",
+        key,
+        BACKUP_MARKER,
+        Utc::now().format("%Y%m%dT%H%M%S%.3fZ")
+    );
+    client.copy_object(key, &backup_key).await?;
+    tracing::info!("Backed up {} to {}", key, backup_key);
+
+    let backups = list_backups(client, key).await?;
+    let excess = backups.len().saturating_sub(keep.max(1));
+    for old in &backups[..excess] {
+        client.delete_object(old).await?;
+        tracing::info!("Removed old backup {}", old);
+    }
+    Ok(Some(backup_key))
+}
+
+/// Backups of `key`, oldest first.
+pub async fn list_backups(client: &R2Client, key: &str) -> Result<Vec<String>> {
+    let prefix = format!("{}{}", key, BACKUP_MARKER);
+    let mut backups: Vec<String> = client
+        .list_objects(Some(&prefix))
+        .await?
+        .into_iter()
+        // Skip the backups of other keys that share this prefix, like "a.txt.bak-x.bak-y"
+        .filter(|k| is_timestamp(&k[prefix.len()..]))
+        .collect();
+    backups.sort();
+    Ok(backups)
+}
+
+/// Copy the latest backup of `key` back over it, returning the backup's key. The
+/// backup itself is kept.
+pub async fn restore_latest_backup(client: &R2Client, key: &str) -> Result<String> {
+    let latest = list_backups(client, key)
+        .await?
+        .pop()
+        .ok_or_else(|| anyhow!("No backups of {} found", key))?;
+    client.copy_object(&latest, key).await?;
+    Ok(latest)
+}
+
+fn is_timestamp(suffix: &str) -> bool {
+    suffix.len() == 20
+        && suffix.ends_with('Z')
+        && suffix
+            .chars()
+            .all(|c| c.is_ascii_digit() || matches!(c, 'T' | '.' | 'Z'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_r2::MockR2;
+    use bytes::Bytes;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn overwrites_keep_the_prior_content_as_a_backup() {
+        let mock = MockR2::start().await;
+        let client = mock.client().await;
+
+        // Nothing to back up before the first upload
+        assert_eq!(
+            backup_before_overwrite(&client, "report.csv", 2)
+                .await
+                .unwrap(),
+            None
+        );
+
+        for version in ["v1", "v2", "v3", "v4"] {
+            if client.object_exists("report.csv").await.unwrap() {
+                backup_before_overwrite(&client, "report.csv", 2)
+                    .await
+                    .unwrap()
+                    .unwrap();
+                // Backup names have millisecond timestamps
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+            client
+                .upload_object("report.csv", Bytes::from(version))
+                .await
+                .unwrap();
+        }
+
+        // Only the newest two backups are kept, holding what each upload replaced
+        let backups = list_backups(&client, "report.csv").await.unwrap();
+        assert_eq!(backups.len(), 2);
+        assert_eq!(mock.object(&backups[0]).unwrap().data, b"v2");
+        assert_eq!(mock.object(&backups[1]).unwrap().data, b"v3");
+        assert_eq!(mock.object("report.csv").unwrap().data, b"v4");
+
+        assert_eq!(
+            restore_latest_backup(&client, "report.csv").await.unwrap(),
+            backups[1]
+        );
+        assert_eq!(mock.object("report.csv").unwrap().data, b"v3");
+        assert_eq!(mock.object(&backups[1]).unwrap().data, b"v3");
+    }
+}
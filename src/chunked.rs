@@ -0,0 +1,817 @@
+use crate::crypto::PgpHandler;
+use crate::error::R2Error;
+use crate::r2_client::R2Client;
+use anyhow::{anyhow, Context, Result};
+use sha2::{Digest, Sha256};
+use std::io::{ErrorKind, Read, Write};
+
+// Chunked encryption splits a stream into fixed-size plaintext chunks and encrypts each
+// one as a complete PGP message, so neither side ever holds more than one chunk:
+//
+//   header:  MAGIC | plaintext chunk size (u32 BE)
+//   chunk:   ciphertext length (u32 BE, non-zero) | SHA-256 of ciphertext | ciphertext
+//   trailer: 0 (u32 BE) | chunk count (u64 BE) | total plaintext bytes (u64 BE)
+//
+// Each ciphertext decrypts to a random stream ID (16 bytes) | chunk index (u64 BE) | last
+// flag (u8) | plaintext. The checksums and the trailer are unkeyed, so they only catch
+// corruption; anyone can recompute them. What decryption trusts is inside the PGP
+// messages: a chunk from another object, or one moved, is refused because its stream ID
+// or index doesn't match, and a stream cut short at a chunk boundary is refused because
+// no chunk carries the last flag. The trailer must agree with the chunks decrypted, so
+// it can't be rewritten to hide either. Nothing may follow the trailer, so the last chunk
+// must end where the object does.
+pub const MAGIC: &[u8; 8] = b"R2PGPCH2";
+
+pub const DEFAULT_CHUNK_SIZE: usize = 8 * 1024 * 1024;
+
+// Refuse absurd chunk lengths from a corrupt header rather than allocating them
+const MAX_CHUNK_SIZE: usize = 256 * 1024 * 1024;
+
+const HEADER_LEN: u64 = 12; // MAGIC + chunk size
+const CHECKSUM_LEN: u64 = 32;
+
+const STREAM_ID_LEN: usize = 16;
+// Stream ID, chunk index and last flag, ahead of each chunk's plaintext
+const CHUNK_HEADER_LEN: usize = STREAM_ID_LEN + 8 + 1;
+
+// Where the first chunk's PGP message starts, after the header, its length and its checksum
+pub const FIRST_MESSAGE_OFFSET: usize = (HEADER_LEN + 4 + CHECKSUM_LEN) as usize;
+
+/// Outcome of checking a chunked stream's checksums without decrypting it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChunkedVerification {
+    Valid {
+        chunk_count: u64,
+        total_size: u64,
+    },
+    /// The first chunk whose ciphertext doesn't match its checksum. `offset` is the
+    /// position of the chunk's length field in the stream.
+    Corrupt {
+        index: u64,
+        offset: u64,
+    },
+}
+
+pub fn is_chunked(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// Incrementally encrypts a stream into the chunked format. Feed plaintext with
+/// `update` and write out whatever it returns, then write the output of `finish`.
+pub struct ChunkedEncryptor<'a> {
+    handler: &'a PgpHandler,
+    file_name: String,
+    chunk_size: usize,
+    stream_id: [u8; STREAM_ID_LEN],
+    buffer: Vec<u8>,
+    chunk_count: u64,
+    total_size: u64,
+    header_written: bool,
+}
+
+impl<'a> ChunkedEncryptor<'a> {
+    pub fn new(handler: &'a PgpHandler, file_name: &str, chunk_size: usize) -> Result<Self> {
+        if chunk_size == 0 || chunk_size > MAX_CHUNK_SIZE {
+            return Err(anyhow!("Chunk size must be between 1 byte and 256 MB"));
+        }
+        if handler.recipient_count() == 0 {
+            return Err(anyhow!("No public keys loaded for encryption"));
+        }
+        Ok(Self {
+            handler,
+            file_name: file_name.to_string(),
+            chunk_size,
+            stream_id: rand::random(),
+            buffer: Vec::with_capacity(chunk_size),
+            chunk_count: 0,
+            total_size: 0,
+            header_written: false,
+        })
+    }
+
+    /// Add plaintext, returning the encoded bytes for any chunks it completed.
+    pub fn update(&mut self, mut data: &[u8]) -> Result<Vec<u8>> {
+        let mut output = self.take_header();
+        while !data.is_empty() {
+            // A full chunk waits for more data, since the last one has to be flagged
+            if self.buffer.len() == self.chunk_size {
+                self.encrypt_buffer(&mut output, false)?;
+            }
+            let n = (self.chunk_size - self.buffer.len()).min(data.len());
+            self.buffer.extend_from_slice(&data[..n]);
+            data = &data[n..];
+        }
+        Ok(output)
+    }
+
+    /// Encrypt the buffered plaintext as the last chunk, which is empty for an empty
+    /// stream, and return the remaining bytes including the trailer.
+    pub fn finish(mut self) -> Result<Vec<u8>> {
+        let mut output = self.take_header();
+        self.encrypt_buffer(&mut output, true)?;
+        output.extend_from_slice(&0u32.to_be_bytes());
+        output.extend_from_slice(&self.chunk_count.to_be_bytes());
+        output.extend_from_slice(&self.total_size.to_be_bytes());
+        Ok(output)
+    }
+
+    fn take_header(&mut self) -> Vec<u8> {
+        if self.header_written {
+            return Vec::new();
+        }
+        self.header_written = true;
+        let mut header = MAGIC.to_vec();
+        header.extend_from_slice(&(self.chunk_size as u32).to_be_bytes());
+        header
+    }
+
+    fn encrypt_buffer(&mut self, output: &mut Vec<u8>, last: bool) -> Result<()> {
+        let mut payload = Vec::with_capacity(CHUNK_HEADER_LEN + self.buffer.len());
+        payload.extend_from_slice(&self.stream_id);
+        payload.extend_from_slice(&self.chunk_count.to_be_bytes());
+        payload.push(last as u8);
+        payload.extend_from_slice(&self.buffer);
+        let ciphertext = self
+            .handler
+            .encrypt_with_filename(&payload, &self.file_name)?;
+        output.extend_from_slice(&(ciphertext.len() as u32).to_be_bytes());
+        output.extend_from_slice(&Sha256::digest(&ciphertext));
+        output.extend_from_slice(&ciphertext);
+
+        self.chunk_count += 1;
+        self.total_size += self.buffer.len() as u64;
+        self.buffer.clear();
+        Ok(())
+    }
+}
+
+/// Decrypt a chunked stream to `writer`, returning the plaintext size. Each chunk's
+/// checksum is verified before it is decrypted, every chunk must belong to the stream and
+/// sit at its index, the last one must be flagged, and the trailer must match what was
+/// decrypted and end the stream. Chunks are written as they are decrypted, so on a
+/// truncated or tampered stream the caller must discard what was written.
+pub fn decrypt_chunked(
+    handler: &PgpHandler,
+    reader: &mut dyn Read,
+    writer: &mut dyn Write,
+) -> Result<u64> {
+    let mut magic = [0u8; 8];
+    reader
+        .read_exact(&mut magic)
+        .context("Failed to read chunked encryption header")?;
+    if &magic != MAGIC {
+        return Err(anyhow!("Not a chunked encrypted stream"));
+    }
+    let chunk_size = read_u32(reader)? as usize;
+
+    let mut stream_id = None;
+    let mut last = false;
+    let mut chunk_count = 0u64;
+    let mut total_size = 0u64;
+    loop {
+        let length = read_u32(reader).map_err(|e| truncated(e, chunk_count))?;
+        if length == 0 {
+            break;
+        }
+        if last {
+            return Err(anyhow!("Chunk {} follows the last chunk", chunk_count));
+        }
+        check_length(length, chunk_count)?;
+        let length = length as usize;
+
+        let mut checksum = [0u8; 32];
+        reader
+            .read_exact(&mut checksum)
+            .map_err(|e| truncated(e.into(), chunk_count))?;
+        let mut ciphertext = vec![0u8; length];
+        reader
+            .read_exact(&mut ciphertext)
+            .map_err(|e| truncated(e.into(), chunk_count))?;
+        if Sha256::digest(&ciphertext).as_slice() != checksum {
+            return Err(anyhow!("Chunk {} failed its checksum", chunk_count));
+        }
+
+        let payload = handler
+            .decrypt(&ciphertext)
+            .with_context(|| format!("Failed to decrypt chunk {}", chunk_count))?;
+        let plaintext;
+        (plaintext, last) = open_chunk(&payload, &mut stream_id, chunk_count)?;
+        if plaintext.len() > chunk_size {
+            return Err(anyhow!(
+                "Chunk {} is larger than the chunk size",
+                chunk_count
+            ));
+        }
+        writer.write_all(plaintext)?;
+        chunk_count += 1;
+        total_size += plaintext.len() as u64;
+    }
+
+    let declared_count = read_u64(reader).map_err(|e| truncated(e, chunk_count))?;
+    let declared_size = read_u64(reader).map_err(|e| truncated(e, chunk_count))?;
+    check_end(reader)?;
+    check_count(declared_count, chunk_count)?;
+    if !last {
+        return Err(anyhow!(
+            "Truncated encrypted object: none of its {} chunks is the last one",
+            chunk_count
+        ));
+    }
+    if declared_count != chunk_count || declared_size != total_size {
+        return Err(anyhow!(
+            "Chunked encrypted object is inconsistent: trailer declares {} chunks ({} bytes), found {} ({} bytes)",
+            declared_count,
+            declared_size,
+            chunk_count,
+            total_size
+        ));
+    }
+    Ok(total_size)
+}
+
+/// Walk a chunked stream and check every chunk's checksum, stopping at the first
+/// corrupt one. Nothing is decrypted, so no secret key is needed; this finds corruption,
+/// not tampering, which only decryption can detect.
+pub fn verify_chunked(reader: &mut dyn Read) -> Result<ChunkedVerification> {
+    let mut header = [0u8; HEADER_LEN as usize];
+    reader
+        .read_exact(&mut header)
+        .context("Failed to read chunked encryption header")?;
+    let chunk_size = parse_header(&header)?;
+
+    let mut offset = HEADER_LEN;
+    let mut chunk_count = 0u64;
+    loop {
+        let length = read_u32(reader).map_err(|e| truncated(e, chunk_count))?;
+        if length == 0 {
+            break;
+        }
+        check_length(length, chunk_count)?;
+
+        let mut checksum = [0u8; CHECKSUM_LEN as usize];
+        reader
+            .read_exact(&mut checksum)
+            .map_err(|e| truncated(e.into(), chunk_count))?;
+        let mut ciphertext = vec![0u8; length as usize];
+        reader
+            .read_exact(&mut ciphertext)
+            .map_err(|e| truncated(e.into(), chunk_count))?;
+        if Sha256::digest(&ciphertext).as_slice() != checksum {
+            return Ok(ChunkedVerification::Corrupt {
+                index: chunk_count,
+                offset,
+            });
+        }
+        offset += 4 + CHECKSUM_LEN + length as u64;
+        chunk_count += 1;
+    }
+
+    let declared_count = read_u64(reader).map_err(|e| truncated(e, chunk_count))?;
+    let declared_size = read_u64(reader).map_err(|e| truncated(e, chunk_count))?;
+    check_end(reader)?;
+    check_trailer(chunk_size, chunk_count, declared_count, declared_size)
+}
+
+/// Verify a chunked object in the bucket with one Range request per chunk, so only a
+/// single chunk is held in memory however large the object is.
+pub async fn verify_chunked_object(client: &R2Client, key: &str) -> Result<ChunkedVerification> {
+    // The header plus the first chunk's length
+    let head = read_range(client, key, 0, HEADER_LEN + 3).await?;
+    if head.len() < HEADER_LEN as usize {
+        return Err(anyhow!("Not a chunked encrypted stream"));
+    }
+    let chunk_size = parse_header(&head[..HEADER_LEN as usize])?;
+    if head.len() < HEADER_LEN as usize + 4 {
+        return Err(truncated_after(0));
+    }
+
+    let mut offset = HEADER_LEN;
+    let mut length = u32::from_be_bytes(head[HEADER_LEN as usize..].try_into()?);
+    let mut chunk_count = 0u64;
+    while length != 0 {
+        check_length(length, chunk_count)?;
+
+        // Checksum and ciphertext, plus the next chunk's length (or the trailer's zero)
+        let start = offset + 4;
+        let end = start + CHECKSUM_LEN + length as u64 + 4;
+        let data = read_range(client, key, start, end - 1).await?;
+        if (data.len() as u64) < CHECKSUM_LEN + length as u64 {
+            return Err(truncated_after(chunk_count));
+        }
+        let (checksum, rest) = data.split_at(CHECKSUM_LEN as usize);
+        let (ciphertext, next) = rest.split_at(length as usize);
+        if Sha256::digest(ciphertext).as_slice() != checksum {
+            return Ok(ChunkedVerification::Corrupt {
+                index: chunk_count,
+                offset,
+            });
+        }
+        chunk_count += 1;
+        // The chunk is whole, but the object may end before the next length
+        if next.len() < 4 {
+            return Err(truncated_after(chunk_count));
+        }
+        offset = end - 4;
+        length = u32::from_be_bytes(next.try_into()?);
+    }
+
+    // One byte past the trailer, which must not exist
+    let trailer = read_range(client, key, offset + 4, offset + 20).await?;
+    if trailer.len() < 16 {
+        return Err(truncated_after(chunk_count));
+    }
+    if trailer.len() > 16 {
+        return Err(trailing_data());
+    }
+    let declared_count = u64::from_be_bytes(trailer[..8].try_into()?);
+    let declared_size = u64::from_be_bytes(trailer[8..16].try_into()?);
+    check_trailer(chunk_size, chunk_count, declared_count, declared_size)
+}
+
+/// Decrypt the first `len` plaintext bytes of a chunked object in the bucket, fetching
+/// only the chunks they fall in with Range requests. Returns fewer bytes if the
+/// object's plaintext is shorter. The trailer isn't read, so this says nothing about
+/// whether the rest of the object is intact, but the chunks read are checked to be the
+/// object's own, in order.
+pub async fn decrypt_object_prefix(
+    client: &R2Client,
+    handler: &PgpHandler,
+    key: &str,
+    len: usize,
+) -> Result<Vec<u8>> {
+    let head = read_range(client, key, 0, HEADER_LEN + 3).await?;
+    if head.len() < HEADER_LEN as usize {
+        return Err(anyhow!("Not a chunked encrypted stream"));
+    }
+    parse_header(&head[..HEADER_LEN as usize])?;
+    if head.len() < HEADER_LEN as usize + 4 {
+        return Err(truncated_after(0));
+    }
+
+    let mut plaintext = Vec::new();
+    let mut stream_id = None;
+    let mut last = false;
+    let mut offset = HEADER_LEN;
+    let mut length = u32::from_be_bytes(head[HEADER_LEN as usize..].try_into()?);
+    let mut chunk_count = 0u64;
+    while length != 0 && !last && plaintext.len() < len {
+        check_length(length, chunk_count)?;
+
+        let start = offset + 4;
+        let end = start + CHECKSUM_LEN + length as u64 + 4;
+        let data = read_range(client, key, start, end - 1).await?;
+        if (data.len() as u64) < end - start {
+            return Err(truncated_after(chunk_count));
+        }
+        let (checksum, rest) = data.split_at(CHECKSUM_LEN as usize);
+        let (ciphertext, next) = rest.split_at(length as usize);
+        if Sha256::digest(ciphertext).as_slice() != checksum {
+            return Err(anyhow!("Chunk {} failed its checksum", chunk_count));
+        }
+        let payload = handler
+            .decrypt(ciphertext)
+            .with_context(|| format!("Failed to decrypt chunk {}", chunk_count))?;
+        let data;
+        (data, last) = open_chunk(&payload, &mut stream_id, chunk_count)?;
+        plaintext.extend_from_slice(data);
+        offset = end - 4;
+        length = u32::from_be_bytes(next.try_into()?);
+        chunk_count += 1;
+    }
+
+    plaintext.truncate(len);
+    Ok(plaintext)
+}
+
+fn parse_header(header: &[u8]) -> Result<usize> {
+    if &header[..8] != MAGIC {
+        return Err(anyhow!("Not a chunked encrypted stream"));
+    }
+    Ok(u32::from_be_bytes(header[8..12].try_into()?) as usize)
+}
+
+/// Split a decrypted chunk into its plaintext and last flag, checking that it belongs to
+/// the same stream as the chunks before it (the first sets `stream_id`) and sits at `index`.
+fn open_chunk<'p>(
+    payload: &'p [u8],
+    stream_id: &mut Option<[u8; STREAM_ID_LEN]>,
+    index: u64,
+) -> Result<(&'p [u8], bool)> {
+    if payload.len() < CHUNK_HEADER_LEN {
+        return Err(anyhow!("Chunk {} is missing its header", index));
+    }
+    let (id, rest) = payload.split_at(STREAM_ID_LEN);
+    let id: [u8; STREAM_ID_LEN] = id.try_into()?;
+    if *stream_id.get_or_insert(id) != id {
+        return Err(anyhow!("Chunk {} belongs to a different object", index));
+    }
+    let written_at = u64::from_be_bytes(rest[..8].try_into()?);
+    if written_at != index {
+        return Err(anyhow!(
+            "Chunk {} is out of place: it was written as chunk {}",
+            index,
+            written_at
+        ));
+    }
+    let last = match rest[8] {
+        0 => false,
+        1 => true,
+        flag => return Err(anyhow!("Chunk {} has an invalid flag {}", index, flag)),
+    };
+    Ok((&rest[9..], last))
+}
+
+fn check_length(length: u32, chunk_count: u64) -> Result<()> {
+    if length as usize > MAX_CHUNK_SIZE * 2 {
+        return Err(anyhow!(
+            "Corrupt chunk {}: implausible length {}",
+            chunk_count,
+            length
+        ));
+    }
+    Ok(())
+}
+
+// Plaintext sizes aren't known without decrypting, so the declared total can only be
+// checked against what the chunk count allows.
+fn check_trailer(
+    chunk_size: usize,
+    chunk_count: u64,
+    declared_count: u64,
+    declared_size: u64,
+) -> Result<ChunkedVerification> {
+    check_count(declared_count, chunk_count)?;
+    // Every chunk but the last is full, and only an empty stream's single chunk is empty
+    let max_size = chunk_count.saturating_mul(chunk_size as u64);
+    let min_size = match chunk_count {
+        0 | 1 => 0,
+        _ => (chunk_count - 1).saturating_mul(chunk_size as u64) + 1,
+    };
+    let size_plausible = chunk_count > 0 && (min_size..=max_size).contains(&declared_size);
+    if declared_count != chunk_count || !size_plausible {
+        return Err(anyhow!(
+            "Chunked encrypted object is inconsistent: trailer declares {} chunks ({} bytes), found {}",
+            declared_count,
+            declared_size,
+            chunk_count
+        ));
+    }
+    Ok(ChunkedVerification::Valid {
+        chunk_count,
+        total_size: declared_size,
+    })
+}
+
+// A trailer counting more chunks than were read means chunks went missing before it
+fn check_count(declared_count: u64, chunk_count: u64) -> Result<()> {
+    if declared_count > chunk_count {
+        return Err(anyhow!(
+            "Truncated encrypted object: expected {} chunks, found {}",
+            declared_count,
+            chunk_count
+        ));
+    }
+    Ok(())
+}
+
+// The trailer must be the end of the stream
+fn check_end(reader: &mut dyn Read) -> Result<()> {
+    let mut byte = [0u8; 1];
+    loop {
+        match reader.read(&mut byte) {
+            Ok(0) => return Ok(()),
+            Ok(_) => return Err(trailing_data()),
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+fn trailing_data() -> anyhow::Error {
+    anyhow!("Chunked encrypted object has data after its trailer; the last chunk doesn't end where the object does")
+}
+
+/// Fetch bytes `start..=end` of an object, or fewer where it ends sooner. A range that
+/// starts past the end is empty rather than an error, so a cut object reads as truncated.
+async fn read_range(client: &R2Client, key: &str, start: u64, end: u64) -> Result<bytes::Bytes> {
+    match client.download_range(key, start, end).await {
+        Err(e) if R2Error::from_anyhow(&e).and_then(R2Error::status) == Some(416) => {
+            Ok(bytes::Bytes::new())
+        }
+        result => result,
+    }
+}
+
+fn truncated_after(chunk_count: u64) -> anyhow::Error {
+    anyhow!(
+        "Truncated encrypted object: ended after {} complete chunks",
+        chunk_count
+    )
+}
+
+fn truncated(error: anyhow::Error, chunk_count: u64) -> anyhow::Error {
+    let eof = error
+        .downcast_ref::<std::io::Error>()
+        .is_some_and(|e| e.kind() == ErrorKind::UnexpectedEof);
+    if eof {
+        truncated_after(chunk_count)
+    } else {
+        error
+    }
+}
+
+fn read_u32(reader: &mut dyn Read) -> Result<u32> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_be_bytes(bytes))
+}
+
+fn read_u64(reader: &mut dyn Read) -> Result<u64> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_r2::MockR2;
+    use crate::test_support::{self, TempDir};
+
+    const CHUNK_SIZE: usize = 1000;
+
+    fn encrypt(handler: &PgpHandler, plaintext: &[u8]) -> Vec<u8> {
+        let mut encryptor = ChunkedEncryptor::new(handler, "data.bin", CHUNK_SIZE).unwrap();
+        let mut encrypted = encryptor.update(plaintext).unwrap();
+        encrypted.extend(encryptor.finish().unwrap());
+        encrypted
+    }
+
+    /// Where each chunk's length field is in `encrypted`.
+    fn chunk_offsets(encrypted: &[u8]) -> Vec<usize> {
+        let mut offsets = Vec::new();
+        let mut offset = HEADER_LEN as usize;
+        loop {
+            let length = u32::from_be_bytes(encrypted[offset..offset + 4].try_into().unwrap());
+            if length == 0 {
+                return offsets;
+            }
+            offsets.push(offset);
+            offset += 4 + CHECKSUM_LEN as usize + length as usize;
+        }
+    }
+
+    #[tokio::test]
+    async fn flipped_bytes_flag_the_chunk_they_are_in() {
+        let key = test_support::key("Chunks");
+        let handler = test_support::handler(&[&key], &[&key]);
+        let plaintext: Vec<u8> = (0..4 * CHUNK_SIZE + 10).map(|i| i as u8).collect();
+        let encrypted = encrypt(&handler, &plaintext);
+        let offsets = chunk_offsets(&encrypted);
+        assert_eq!(offsets.len(), 5);
+
+        let valid = ChunkedVerification::Valid {
+            chunk_count: 5,
+            total_size: plaintext.len() as u64,
+        };
+        assert_eq!(verify_chunked(&mut &encrypted[..]).unwrap(), valid);
+
+        // A byte in the middle of chunk 2's ciphertext
+        let mut corrupt = encrypted.clone();
+        corrupt[offsets[2] + 4 + CHECKSUM_LEN as usize + 100] ^= 0x01;
+        let flagged = ChunkedVerification::Corrupt {
+            index: 2,
+            offset: offsets[2] as u64,
+        };
+        assert_eq!(verify_chunked(&mut &corrupt[..]).unwrap(), flagged);
+
+        // The same through Range requests against the bucket
+        let mock = MockR2::start().await;
+        mock.put("good.bin.pgp", &encrypted);
+        mock.put("bad.bin.pgp", &corrupt);
+        let client = mock.client().await;
+        assert_eq!(
+            verify_chunked_object(&client, "good.bin.pgp")
+                .await
+                .unwrap(),
+            valid
+        );
+        assert_eq!(
+            verify_chunked_object(&client, "bad.bin.pgp").await.unwrap(),
+            flagged
+        );
+
+        // Decryption refuses the corrupt chunk rather than returning bad plaintext
+        let mut output = Vec::new();
+        assert!(decrypt_chunked(&handler, &mut &corrupt[..], &mut output).is_err());
+        let mut output = Vec::new();
+        decrypt_chunked(&handler, &mut &encrypted[..], &mut output).unwrap();
+        assert_eq!(output, plaintext);
+    }
+
+    /// Reassemble a stream from the chunk records of `sources`, picked as (source, index),
+    /// with a trailer that agrees with them so only decryption can tell.
+    fn splice(sources: &[&[u8]], picks: &[(usize, usize)], total_size: u64) -> Vec<u8> {
+        let mut spliced = sources[0][..HEADER_LEN as usize].to_vec();
+        for &(source, index) in picks {
+            let start = chunk_offsets(sources[source])[index];
+            let length = u32::from_be_bytes(sources[source][start..start + 4].try_into().unwrap());
+            let end = start + 4 + CHECKSUM_LEN as usize + length as usize;
+            spliced.extend_from_slice(&sources[source][start..end]);
+        }
+        spliced.extend_from_slice(&0u32.to_be_bytes());
+        spliced.extend_from_slice(&(picks.len() as u64).to_be_bytes());
+        spliced.extend_from_slice(&total_size.to_be_bytes());
+        spliced
+    }
+
+    #[test]
+    fn moved_foreign_and_dropped_chunks_are_refused() {
+        let key = test_support::key("Chunks");
+        let handler = test_support::handler(&[&key], &[&key]);
+        let plaintext = vec![7u8; 3 * CHUNK_SIZE];
+        let first = encrypt(&handler, &plaintext);
+        let second = encrypt(&handler, &plaintext);
+        let size = plaintext.len() as u64;
+
+        let tampered = [
+            // Two chunks swapped
+            (
+                splice(&[&first], &[(0, 1), (0, 0), (0, 2)], size),
+                "out of place",
+            ),
+            // A chunk from another object encrypted with the same key
+            (
+                splice(&[&first, &second], &[(0, 0), (1, 1), (0, 2)], size),
+                "different object",
+            ),
+            // The last chunk dropped and the trailer rewritten to match
+            (
+                splice(&[&first], &[(0, 0), (0, 1)], size - CHUNK_SIZE as u64),
+                "last one",
+            ),
+        ];
+        for (stream, reason) in tampered {
+            // The unkeyed checksums and trailer still add up
+            assert!(matches!(
+                verify_chunked(&mut &stream[..]).unwrap(),
+                ChunkedVerification::Valid { .. }
+            ));
+            let error = decrypt_chunked(&handler, &mut &stream[..], &mut Vec::new()).unwrap_err();
+            assert!(error.to_string().contains(reason), "{:#}", error);
+        }
+
+        let mut output = Vec::new();
+        decrypt_chunked(&handler, &mut &first[..], &mut output).unwrap();
+        assert_eq!(output, plaintext);
+
+        // An empty stream still has a last chunk to carry the flag
+        let empty = encrypt(&handler, b"");
+        assert_eq!(chunk_offsets(&empty).len(), 1);
+        let mut output = Vec::new();
+        assert_eq!(
+            decrypt_chunked(&handler, &mut &empty[..], &mut output).unwrap(),
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn truncated_objects_are_caught_wherever_they_are_cut() {
+        let key = test_support::key("Chunks");
+        let handler = test_support::handler(&[&key], &[&key]);
+        let plaintext: Vec<u8> = (0..3 * CHUNK_SIZE - 10).map(|i| i as u8).collect();
+        let encrypted = encrypt(&handler, &plaintext);
+        let offsets = chunk_offsets(&encrypted);
+        let terminator = encrypted.len() - 20;
+
+        // Chunk 2 lost along with its terminator, but the original trailer kept
+        let mut dropped = encrypted[..offsets[2]].to_vec();
+        dropped.extend_from_slice(&encrypted[terminator..]);
+        let mut extended = encrypted.clone();
+        extended.extend_from_slice(b"more");
+        let cases = [
+            (
+                encrypted[..offsets[1] + 100].to_vec(),
+                "ended after 1 complete chunks",
+            ),
+            (
+                encrypted[..offsets[2]].to_vec(),
+                "ended after 2 complete chunks",
+            ),
+            (
+                encrypted[..terminator + 4].to_vec(),
+                "ended after 3 complete chunks",
+            ),
+            (
+                encrypted[..encrypted.len() - 3].to_vec(),
+                "ended after 3 complete chunks",
+            ),
+            (dropped, "expected 3 chunks, found 2"),
+            (extended, "data after its trailer"),
+        ];
+
+        let mock = MockR2::start().await;
+        let client = mock.client().await;
+        for (i, (stream, reason)) in cases.iter().enumerate() {
+            let mut output = Vec::new();
+            let error = decrypt_chunked(&handler, &mut &stream[..], &mut output).unwrap_err();
+            assert!(error.to_string().contains(reason), "{}: {:#}", i, error);
+            let error = verify_chunked(&mut &stream[..]).unwrap_err();
+            assert!(error.to_string().contains(reason), "{}: {:#}", i, error);
+
+            // Through Range requests, and when downloading and decrypting the object
+            let key = format!("cut-{}.bin.pgp", i);
+            mock.put(&key, stream);
+            let error = verify_chunked_object(&client, &key).await.unwrap_err();
+            assert!(error.to_string().contains(reason), "{}: {:#}", i, error);
+            let data = client.download_object(&key).await.unwrap();
+            let error = handler
+                .decrypt_to_writer(&mut &data[..], &mut Vec::new())
+                .unwrap_err();
+            assert!(
+                format!("{:#}", error).contains(reason),
+                "{}: {:#}",
+                i,
+                error
+            );
+        }
+    }
+
+    /// Hashes what is written to it, so large plaintexts can be compared without keeping them.
+    #[derive(Default)]
+    struct HashingWriter(Sha256);
+
+    impl Write for HashingWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.update(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn files_of_hundreds_of_megabytes_round_trip_through_files() {
+        use rand::RngCore;
+        use std::fs::File;
+        use std::io::{BufReader, BufWriter};
+
+        const SIZE: usize = 200 * 1024 * 1024;
+        const BLOCK: usize = 1024 * 1024;
+
+        let key = test_support::key("Chunks");
+        let handler = test_support::handler(&[&key], &[&key]);
+        let dir = TempDir::new();
+
+        // Random data, so compression can't shrink the work
+        let source = dir.path().join("big.bin");
+        let mut expected = Sha256::new();
+        let mut file = BufWriter::new(File::create(&source).unwrap());
+        let mut block = vec![0u8; BLOCK];
+        for _ in 0..SIZE / BLOCK {
+            rand::thread_rng().fill_bytes(&mut block);
+            expected.update(&block);
+            file.write_all(&block).unwrap();
+        }
+        file.flush().unwrap();
+        drop(file);
+
+        // Read and encrypt a block at a time, as uploads do
+        let encrypted_path = dir.path().join("big.bin.pgp");
+        let mut input = File::open(&source).unwrap();
+        let mut output = BufWriter::new(File::create(&encrypted_path).unwrap());
+        let mut encryptor = ChunkedEncryptor::new(&handler, "big.bin", DEFAULT_CHUNK_SIZE).unwrap();
+        loop {
+            let n = input.read(&mut block).unwrap();
+            if n == 0 {
+                break;
+            }
+            output
+                .write_all(&encryptor.update(&block[..n]).unwrap())
+                .unwrap();
+        }
+        output.write_all(&encryptor.finish().unwrap()).unwrap();
+        output.flush().unwrap();
+        drop(output);
+
+        let chunk_count = SIZE.div_ceil(DEFAULT_CHUNK_SIZE) as u64;
+        let mut encrypted = BufReader::new(File::open(&encrypted_path).unwrap());
+        assert_eq!(
+            verify_chunked(&mut encrypted).unwrap(),
+            ChunkedVerification::Valid {
+                chunk_count,
+                total_size: SIZE as u64,
+            }
+        );
+
+        let mut encrypted = BufReader::new(File::open(&encrypted_path).unwrap());
+        let mut decrypted = HashingWriter::default();
+        let written = handler
+            .decrypt_to_writer(&mut encrypted, &mut decrypted)
+            .unwrap();
+        assert_eq!(written, SIZE as u64);
+        assert_eq!(decrypted.0.finalize(), expected.finalize());
+    }
+}
@@ -0,0 +1,162 @@
+use crate::crypto::PgpHandler;
+use crate::encryption_filter;
+use crate::folder_download::{self, PARTIAL_SUFFIX};
+use crate::r2_client::R2Client;
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// What `concat_prefix` joined.
+pub struct Concatenated {
+    pub keys: Vec<String>,
+    pub bytes: u64,
+    pub missing_parts: Vec<u64>, // Gaps in the part numbering
+}
+
+/// The part number in the last run of digits of a key's file name, as in "part-0003".
+pub fn part_number(key: &str) -> Option<u64> {
+    let name = key.rsplit('/').next().unwrap_or(key);
+    let end = name.rfind(|c: char| c.is_ascii_digit())? + 1;
+    let start = name[..end]
+        .rfind(|c: char| !c.is_ascii_digit())
+        .map_or(0, |i| i + 1);
+    name[start..end].parse().ok()
+}
+
+/// Join the objects under `prefix` into `output`, in part-number order when every key has
+/// one and in key order otherwise. Each object is streamed through a temporary file, so
+/// memory use doesn't grow with the object sizes. Encrypted parts are decrypted first if
+/// `decrypt` is set. Gaps in the part numbering are warned about, not fatal.
+pub async fn concat_prefix(
+    client: &R2Client,
+    pgp_handler: &PgpHandler,
+    prefix: &str,
+    output: &Path,
+    decrypt: bool,
+) -> Result<Concatenated> {
+    let mut keys: Vec<String> = client
+        .list_objects(Some(prefix))
+        .await?
+        .into_iter()
+        .filter(|key| !key.ends_with('/'))
+        .collect();
+    if keys.is_empty() {
+        bail!("No objects found under '{}'", prefix);
+    }
+
+    let mut missing_parts = Vec::new();
+    let numbers: Option<Vec<u64>> = keys.iter().map(|key| part_number(key)).collect();
+    match numbers {
+        Some(_) => {
+            keys.sort_by(|a, b| part_number(a).cmp(&part_number(b)).then(a.cmp(b)));
+            let numbers: Vec<u64> = keys.iter().filter_map(|key| part_number(key)).collect();
+            for pair in numbers.windows(2) {
+                missing_parts.extend(pair[0] + 1..pair[1]);
+            }
+            if !missing_parts.is_empty() {
+                tracing::warn!(
+                    "Part numbers under '{}' have gaps, missing: {:?}",
+                    prefix,
+                    missing_parts
+                );
+            }
+        }
+        None => keys.sort(),
+    }
+
+    let joined_path = PathBuf::from(format!("{}{}", output.display(), PARTIAL_SUFFIX));
+    let object_path = PathBuf::from(format!("{}.object{}", output.display(), PARTIAL_SUFFIX));
+    let mut writer =
+        BufWriter::new(fs::File::create(&joined_path).context("Failed to write output file")?);
+    let mut bytes = 0;
+
+    for key in &keys {
+        tracing::info!("Appending {}", key);
+        // A leftover file would be resumed from, which is wrong for a different object
+        let _ = fs::remove_file(&object_path);
+        client
+            .download_object_to_file(key, &object_path, |_| {})
+            .await
+            .with_context(|| format!("Failed to download {}", key))?;
+
+        let encrypted = encryption_filter::is_encrypted_extension(key)
+            || folder_download::file_looks_encrypted(&object_path)?;
+        let mut input =
+            BufReader::new(fs::File::open(&object_path).context("Failed to read downloaded file")?);
+        bytes += if encrypted && decrypt {
+            pgp_handler
+                .decrypt_to_writer(&mut input, &mut writer)
+                .with_context(|| format!("Failed to decrypt {}", key))?
+        } else {
+            if encrypted {
+                tracing::warn!(
+                    "{} is encrypted; appending it as is (pass --decrypt to decrypt it)",
+                    key
+                );
+            }
+            std::io::copy(&mut input, &mut writer).context("Failed to write output file")?
+        };
+    }
+
+    writer.flush().context("Failed to write output file")?;
+    drop(writer);
+    let _ = fs::remove_file(&object_path);
+    fs::rename(&joined_path, output).context("Failed to write output file")?;
+
+    Ok(Concatenated {
+        keys,
+        bytes,
+        missing_parts,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_r2::MockR2;
+    use crate::test_support::{self, TempDir};
+
+    #[tokio::test]
+    async fn parts_are_joined_in_part_number_order() {
+        let key = test_support::key("Concat");
+        let handler = test_support::handler(&[&key], &[&key]);
+        let mock = MockR2::start().await;
+        mock.put("split/part-10", b"ten;");
+        mock.put("split/part-2", b"two;");
+        mock.put("split/part-1", b"one;");
+        let encrypted = handler.encrypt_with_filename(b"three;", "part-3").unwrap();
+        mock.put("split/part-3.pgp", &encrypted);
+        mock.put("other/part-0", b"not this");
+        let client = mock.client().await;
+        let dir = TempDir::new();
+        let output = dir.path().join("joined.bin");
+
+        let joined = concat_prefix(&client, &handler, "split/", &output, true)
+            .await
+            .unwrap();
+        assert_eq!(
+            joined.keys,
+            [
+                "split/part-1",
+                "split/part-2",
+                "split/part-3.pgp",
+                "split/part-10"
+            ]
+        );
+        assert_eq!(fs::read(&output).unwrap(), b"one;two;three;ten;");
+        assert_eq!(joined.bytes, 18);
+        assert_eq!(joined.missing_parts, (4..10).collect::<Vec<u64>>());
+        // Only the output is left behind
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        // Without part numbers, key order decides
+        mock.put("names/b", b"b");
+        mock.put("names/a", b"a");
+        let joined = concat_prefix(&client, &handler, "names/", &output, false)
+            .await
+            .unwrap();
+        assert_eq!(joined.keys, ["names/a", "names/b"]);
+        assert_eq!(fs::read(&output).unwrap(), b"ab");
+    }
+}
@@ -0,0 +1,269 @@
+use anyhow::{anyhow, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::path::Path;
+
+/// The format of a config file, chosen by its extension: `.toml` is TOML and anything
+/// else is JSON. TOML is read into the same JSON values the config types deserialize
+/// from, so both formats take exactly the same fields:
+///
+///   [r2]
+///   account_id = "..."
+///   bucket_name = "backups"
+///
+///   [pgp]
+///   team_keys = ["keys/alice.asc", "keys/bob.asc"]
+///   key_sources = [{ gpg_key_id = "0x1234ABCD" }]
+///
+///   [profiles.staging]
+///   bucket_name = "app-staging"
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Json,
+    Toml,
+}
+
+impl ConfigFormat {
+    /// The format for `path`. YAML is out of scope, so YAML files are refused rather
+    /// than misread as JSON.
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_ascii_lowercase);
+        match extension.as_deref() {
+            Some("toml") => Ok(ConfigFormat::Toml),
+            Some("yaml" | "yml") => Err(anyhow!(
+                "YAML config files aren't supported ({}); convert it to TOML or JSON",
+                path.display()
+            )),
+            _ => Ok(ConfigFormat::Json),
+        }
+    }
+
+    pub fn parse<T: DeserializeOwned>(self, content: &str) -> Result<T> {
+        match self {
+            ConfigFormat::Json => Ok(serde_json::from_str(content)?),
+            ConfigFormat::Toml => {
+                let document: toml_edit::DocumentMut = content.parse()?;
+                Ok(serde_json::from_value(table_to_json(document.as_table()))?)
+            }
+        }
+    }
+
+    pub fn serialize<T: Serialize>(self, value: &T) -> Result<String> {
+        match self {
+            ConfigFormat::Json => Ok(serde_json::to_string_pretty(value)?),
+            ConfigFormat::Toml => match serde_json::to_value(value)? {
+                Value::Object(table) => {
+                    let mut out = String::new();
+                    write_toml_table(&mut out, &[], &table)?;
+                    Ok(out)
+                }
+                _ => Err(anyhow!("Only a table can be written as a TOML document")),
+            },
+        }
+    }
+}
+
+fn table_to_json(table: &toml_edit::Table) -> Value {
+    Value::Object(
+        table
+            .iter()
+            .filter_map(|(key, item)| Some((key.to_string(), item_to_json(item)?)))
+            .collect(),
+    )
+}
+
+fn item_to_json(item: &toml_edit::Item) -> Option<Value> {
+    match item {
+        toml_edit::Item::None => None,
+        toml_edit::Item::Value(value) => Some(value_to_json(value)),
+        toml_edit::Item::Table(table) => Some(table_to_json(table)),
+        toml_edit::Item::ArrayOfTables(tables) => {
+            Some(Value::Array(tables.iter().map(table_to_json).collect()))
+        }
+    }
+}
+
+fn value_to_json(value: &toml_edit::Value) -> Value {
+    match value {
+        toml_edit::Value::String(s) => Value::String(s.value().clone()),
+        toml_edit::Value::Integer(i) => Value::from(*i.value()),
+        toml_edit::Value::Float(f) => Value::from(*f.value()),
+        toml_edit::Value::Boolean(b) => Value::Bool(*b.value()),
+        // Dates are kept as text, which is how the config fields hold them
+        toml_edit::Value::Datetime(d) => Value::String(d.value().to_string()),
+        toml_edit::Value::Array(array) => Value::Array(array.iter().map(value_to_json).collect()),
+        toml_edit::Value::InlineTable(table) => Value::Object(
+            table
+                .iter()
+                .map(|(key, value)| (key.to_string(), value_to_json(value)))
+                .collect(),
+        ),
+    }
+}
+
+/// Write `table` as TOML: its plain values as `key = value` lines, then each nested
+/// table under its own `[path]` header. Tables inside arrays are written inline, and
+/// nulls are left out since TOML has none.
+fn write_toml_table(out: &mut String, path: &[String], table: &Map<String, Value>) -> Result<()> {
+    let (tables, values): (Vec<_>, Vec<_>) = table
+        .iter()
+        .filter(|(_, value)| !value.is_null())
+        .partition(|(_, value)| value.is_object());
+
+    if !path.is_empty() && (!values.is_empty() || tables.is_empty()) {
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        let header: Vec<String> = path.iter().map(|key| toml_key(key)).collect();
+        out.push_str(&format!("[{}]\n", header.join(".")));
+    }
+    for (key, value) in values {
+        out.push_str(&format!("{} = {}\n", toml_key(key), toml_inline(value)?));
+    }
+    for (key, value) in tables {
+        let mut child = path.to_vec();
+        child.push(key.clone());
+        if let Value::Object(table) = value {
+            write_toml_table(out, &child, table)?;
+        }
+    }
+    Ok(())
+}
+
+/// `value` as an inline TOML value.
+fn toml_inline(value: &Value) -> Result<String> {
+    Ok(match value {
+        Value::String(s) => toml_string(s),
+        Value::Number(n) => n.to_string(),
+        Value::Bool(b) => b.to_string(),
+        Value::Array(items) => {
+            let items = items
+                .iter()
+                .filter(|item| !item.is_null())
+                .map(toml_inline)
+                .collect::<Result<Vec<_>>>()?;
+            format!("[{}]", items.join(", "))
+        }
+        Value::Object(table) => {
+            let entries = table
+                .iter()
+                .filter(|(_, value)| !value.is_null())
+                .map(|(key, value)| Ok(format!("{} = {}", toml_key(key), toml_inline(value)?)))
+                .collect::<Result<Vec<_>>>()?;
+            if entries.is_empty() {
+                "{}".to_string()
+            } else {
+                format!("{{ {} }}", entries.join(", "))
+            }
+        }
+        Value::Null => return Err(anyhow!("TOML has no null value")),
+    })
+}
+
+/// `key` bare if TOML allows it, otherwise quoted.
+fn toml_key(key: &str) -> String {
+    let bare = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if bare {
+        key.to_string()
+    } else {
+        toml_string(key)
+    }
+}
+
+/// `s` as a TOML basic string. Unlike JSON, TOML doesn't allow DEL unescaped, so
+/// JSON's escaping can't be borrowed.
+fn toml_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_ascii_control() => out.push_str(&format!("\\u{:04X}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, R2Config};
+
+    fn r2(secret: &str, endpoint: Option<&str>) -> R2Config {
+        R2Config {
+            access_key_id: "AKIA\tkey".into(),
+            secret_access_key: secret.into(),
+            account_id: "account".into(),
+            bucket_name: "backups".into(),
+            endpoint: endpoint.map(String::from),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn configs_round_trip_through_every_format() {
+        let mut config = Config {
+            r2: r2(r#"se"cr\et # not a comment"#, None),
+            ..Default::default()
+        };
+        config.pgp.passphrase = Some("line one\nline \u{7f}two ünïcode".into());
+        config
+            .pgp
+            .key_passphrases
+            .insert("AB CD.12".into(), "p=\"q\"".into());
+        config.profiles.insert(
+            "eu west.prod".into(),
+            r2(
+                "'single' [brackets]",
+                Some("https://acct.eu.r2.cloudflarestorage.com"),
+            ),
+        );
+        config.profiles.insert("".into(), r2("empty name", None));
+
+        for format in [ConfigFormat::Json, ConfigFormat::Toml] {
+            let written = format.serialize(&config).unwrap();
+            let read: Config = format.parse(&written).unwrap();
+            assert_eq!(
+                serde_json::to_value(&read).unwrap(),
+                serde_json::to_value(&config).unwrap(),
+                "{:?} wrote:\n{}",
+                format,
+                written
+            );
+            assert_eq!(read.r2.endpoint, None);
+            assert_eq!(
+                read.profiles["eu west.prod"].endpoint.as_deref(),
+                Some("https://acct.eu.r2.cloudflarestorage.com")
+            );
+        }
+
+        // No endpoint line at all when there is none
+        let written = ConfigFormat::Toml.serialize(&config).unwrap();
+        assert_eq!(written.matches("endpoint = ").count(), 1);
+        assert!(written.contains("[profiles.\"eu west.prod\"]"));
+    }
+
+    #[test]
+    fn formats_follow_the_extension() {
+        let format = |name: &str| ConfigFormat::from_path(Path::new(name));
+        assert_eq!(format("config.toml").unwrap(), ConfigFormat::Toml);
+        assert_eq!(format("CONFIG.TOML").unwrap(), ConfigFormat::Toml);
+        assert_eq!(format("config.json").unwrap(), ConfigFormat::Json);
+        assert_eq!(format("config").unwrap(), ConfigFormat::Json);
+        assert!(format("config.yaml").is_err());
+        assert!(format("config.yml").is_err());
+    }
+}
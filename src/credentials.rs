@@ -0,0 +1,48 @@
+use crate::config::Config;
+use anyhow::{Context, Result};
+use std::path::PathBuf;
+
+#[derive(Clone, Debug)]
+pub struct Credentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: Option<String>, // Sent as x-amz-security-token for temporary credentials
+}
+
+/// Where a client's credentials came from, and so whether they can be reloaded
+/// after R2 reports them expired.
+#[derive(Clone, Debug, Default)]
+pub enum CredentialSource {
+    #[default]
+    Static, // Passed in directly; nothing to reload from
+    Environment, // R2_ACCESS_KEY_ID / R2_SECRET_ACCESS_KEY / R2_SESSION_TOKEN
+    ConfigFile(PathBuf, Option<String>), // A config file and profile, under any environment overrides
+}
+
+impl CredentialSource {
+    pub fn can_refresh(&self) -> bool {
+        !matches!(self, CredentialSource::Static)
+    }
+
+    /// Read the current credentials from the source. Returns None for static credentials.
+    pub fn load(&self) -> Result<Option<Credentials>> {
+        match self {
+            CredentialSource::Static => Ok(None),
+            CredentialSource::Environment => Ok(Some(Credentials {
+                access_key_id: std::env::var("R2_ACCESS_KEY_ID")
+                    .context("R2_ACCESS_KEY_ID environment variable not set")?,
+                secret_access_key: std::env::var("R2_SECRET_ACCESS_KEY")
+                    .context("R2_SECRET_ACCESS_KEY environment variable not set")?,
+                session_token: std::env::var("R2_SESSION_TOKEN").ok(),
+            })),
+            CredentialSource::ConfigFile(path, profile) => {
+                let (config, _) = Config::from_file_with_env_overrides(path, profile.as_deref())?;
+                Ok(Some(Credentials {
+                    access_key_id: config.r2.access_key_id,
+                    secret_access_key: config.r2.secret_access_key,
+                    session_token: None,
+                }))
+            }
+        }
+    }
+}
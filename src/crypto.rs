@@ -1,9 +1,17 @@
+use crate::chunked;
+use crate::pgp_stream;
 use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use pgp::composed::message::PlainSessionKey;
 use pgp::composed::{Deserializable, Message, SignedPublicKey, SignedSecretKey};
 use pgp::crypto::sym::SymmetricKeyAlgorithm;
-use pgp::types::{KeyTrait, SecretKeyTrait};
+use pgp::packet::{LiteralData, PublicKeyEncryptedSessionKey};
+use pgp::ser::Serialize;
+use pgp::types::{CompressionAlgorithm, KeyTrait, SecretKeyTrait, StringToKey};
 use pgp::ArmorOptions;
-use std::io::Cursor;
+use std::collections::HashMap;
+use std::io::{Cursor, Read, Write};
+use std::path::{Path, PathBuf};
 
 #[derive(Clone, Debug)]
 pub struct KeyInfo {
@@ -12,22 +20,175 @@ pub struct KeyInfo {
     #[allow(dead_code)]
     pub key_id: String,
     pub fingerprint: String,
+    pub expires_at: Option<DateTime<Utc>>, // None for a key that never expires
 }
 
+impl KeyInfo {
+    /// Whether the key's expiry date has passed.
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= Utc::now())
+    }
+
+    /// "Name <email> [ABCD1234]", ending in the last 8 digits of the fingerprint.
+    pub fn label(&self) -> String {
+        let tail = &self.fingerprint[self.fingerprint.len().saturating_sub(8)..];
+        let mut label = self.name.clone();
+        if !self.email.is_empty() {
+            label.push_str(&format!(" <{}>", self.email));
+        }
+        format!("{} [{}]", label, tail.to_uppercase())
+    }
+}
+
+/// A public key a message's session key is encrypted to.
+#[derive(Clone, Debug)]
+pub struct MessageRecipient {
+    pub key_id: String,    // 16 hex digits; all zeros for a hidden recipient
+    pub algorithm: String, // Public-key algorithm, e.g. RSA or ECDH
+}
+
+/// Who signed a message, from a signature that verified against a loaded key.
+#[derive(Clone, Debug)]
+pub struct SignatureInfo {
+    pub signer: KeyInfo,
+    pub created: Option<DateTime<Utc>>, // When the signature was made, as the signer's clock had it
+}
+
+/// What the packets at the start of an encrypted message say about who can open it.
+#[derive(Clone, Debug, Default)]
+pub struct MessageEnvelope {
+    pub recipients: Vec<MessageRecipient>,
+    pub passphrase: bool, // Also or only openable with a passphrase (SKESK packets)
+    pub cipher: Option<String>, // None when only the session key says, and it can't be decrypted here
+    pub aead: Option<String>,   // AEAD mode of a version 2 SEIPD packet
+}
+
+// Non-chunked messages up to this size are decrypted by the pgp crate, which holds a whole
+// message and its plaintext in memory. Larger ones are streamed through pgp_stream.
+const IN_MEMORY_DECRYPT_LIMIT: usize = 64 * 1024 * 1024;
+
+#[derive(Clone)]
 pub struct PgpHandler {
     public_keys: Vec<SignedPublicKey>, // Multiple public keys for team encryption
-    secret_key: Option<SignedSecretKey>,
+    secret_keys: Vec<SignedSecretKey>, // Every personal key, e.g. old and new keypairs; the first signs
     key_info: Vec<KeyInfo>,            // Metadata for loaded keys
     stored_passphrase: Option<String>, // Store passphrase for GPG fallback
+    key_passphrases: HashMap<String, String>, // Per secret key, by lowercase fingerprint
+    cipher_override: Option<SymmetricKeyAlgorithm>, // Skip negotiation and always use this cipher
+    encrypt_to_self: bool,             // Add the secret key's public half to every recipient set
+    armor: bool,        // ASCII-armor encrypted output; binary is about a quarter smaller
+    skip_expired: bool, // Refuse expired public keys instead of only warning
+    compression: CompressionAlgorithm, // Compress the plaintext inside the encrypted message
+}
+
+impl Default for PgpHandler {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl PgpHandler {
     pub fn new() -> Self {
         Self {
             public_keys: Vec::new(),
-            secret_key: None,
+            secret_keys: Vec::new(),
             key_info: Vec::new(),
             stored_passphrase: None,
+            key_passphrases: HashMap::new(),
+            cipher_override: None,
+            encrypt_to_self: false,
+            armor: true,
+            skip_expired: false,
+            compression: CompressionAlgorithm::Uncompressed,
+        }
+    }
+
+    /// Force a symmetric cipher for encryption. `None` restores negotiation from recipient preferences.
+    pub fn set_cipher_algorithm(&mut self, algorithm: Option<SymmetricKeyAlgorithm>) {
+        self.cipher_override = algorithm;
+    }
+
+    /// Also encrypt to the public half of the loaded secret key, so the uploader can
+    /// always decrypt their own uploads even if their key isn't in the team keys.
+    pub fn set_encrypt_to_self(&mut self, enabled: bool) {
+        self.encrypt_to_self = enabled;
+    }
+
+    /// Leave expired public keys out when loading them. Either way loading one logs a
+    /// warning, since messages encrypted to it can't be relied on.
+    pub fn set_skip_expired(&mut self, skip: bool) {
+        self.skip_expired = skip;
+    }
+
+    /// Add a parsed public key unless it is already loaded or, with `skip_expired`, has
+    /// expired. Returns whether it was added.
+    fn add_public_key(&mut self, key: SignedPublicKey, key_info: &KeyInfo) -> bool {
+        if self
+            .key_info
+            .iter()
+            .any(|k| k.fingerprint == key_info.fingerprint)
+        {
+            return false;
+        }
+        if key_info.is_expired() {
+            let expired = key_info
+                .expires_at
+                .map(|at| at.format("%Y-%m-%d").to_string())
+                .unwrap_or_default();
+            if self.skip_expired {
+                tracing::warn!(
+                    "Skipping {}, which expired on {}",
+                    key_info.label(),
+                    expired
+                );
+                return false;
+            }
+            tracing::warn!("Loaded {}, which expired on {}", key_info.label(), expired);
+        }
+        self.public_keys.push(key);
+        self.key_info.push(key_info.clone());
+        true
+    }
+
+    /// Write encrypted messages as binary PGP instead of ASCII armor. Decryption reads
+    /// both, whatever this is set to.
+    pub fn set_armor(&mut self, armor: bool) {
+        self.armor = armor;
+    }
+
+    /// Compress the plaintext inside encrypted messages with `algorithm` (ZIP or ZLIB),
+    /// since the ciphertext itself won't compress. `Uncompressed`, the default, turns it
+    /// off. Decryption decompresses whatever was used.
+    pub fn set_compression(&mut self, algorithm: CompressionAlgorithm) {
+        self.compression = algorithm;
+    }
+
+    /// `message` compressed as `set_compression` asks, or as it is.
+    fn compressed(&self, message: Message) -> Result<Message> {
+        if self.compression == CompressionAlgorithm::Uncompressed {
+            return Ok(message);
+        }
+        message
+            .compress(self.compression)
+            .context("Failed to compress message")
+    }
+
+    /// The public key encryption adds for `encrypt_to_self`, if it applies: that of the
+    /// first secret key loaded.
+    fn self_public_key(&self) -> Option<SignedPublicKey> {
+        let secret_key = self.secret_keys.first().filter(|_| self.encrypt_to_self)?;
+        Some(SignedPublicKey::from(secret_key.clone()))
+    }
+
+    /// Add a secret key unless one with the same fingerprint is already loaded.
+    fn add_secret_key(&mut self, secret_key: SignedSecretKey) {
+        if !self
+            .secret_keys
+            .iter()
+            .any(|key| key.fingerprint() == secret_key.fingerprint())
+        {
+            self.secret_keys.push(secret_key);
         }
     }
 
@@ -37,9 +198,10 @@ impl PgpHandler {
 
         // Extract metadata from the key
         let key_info = Self::extract_key_info(&public_key)?;
-
-        self.public_keys.push(public_key);
-        self.key_info.push(key_info.clone());
+        if !self.add_public_key(public_key, &key_info) && key_info.is_expired() && self.skip_expired
+        {
+            return Err(anyhow!("{} has expired", key_info.label()));
+        }
 
         Ok(key_info)
     }
@@ -77,6 +239,7 @@ impl PgpHandler {
             email,
             key_id,
             fingerprint,
+            expires_at: public_key.expires_at(),
         })
     }
 
@@ -140,15 +303,13 @@ impl PgpHandler {
             if let Ok((parsed_keys_iter, _)) =
                 SignedPublicKey::from_armor_many(Cursor::new(key_data))
             {
-                for key_result in parsed_keys_iter {
-                    if let Ok(key) = key_result {
-                        if let Ok(key_info) = Self::extract_key_info(&key) {
-                            if !keys
-                                .iter()
-                                .any(|k: &KeyInfo| k.fingerprint == key_info.fingerprint)
-                            {
-                                keys.push(key_info);
-                            }
+                for key in parsed_keys_iter.flatten() {
+                    if let Ok(key_info) = Self::extract_key_info(&key) {
+                        if !keys
+                            .iter()
+                            .any(|k: &KeyInfo| k.fingerprint == key_info.fingerprint)
+                        {
+                            keys.push(key_info);
                         }
                     }
                 }
@@ -171,6 +332,7 @@ impl PgpHandler {
 
     pub fn load_public_keys_from_bytes(&mut self, key_data: &[u8]) -> Result<Vec<KeyInfo>> {
         let mut loaded_keys = Vec::new();
+        let mut parsed_any = false; // Even if every key was a duplicate or expired
 
         // Convert to string for parsing
         let data_str = String::from_utf8_lossy(key_data);
@@ -205,18 +367,10 @@ impl PgpHandler {
                     Ok((key, _)) => {
                         match Self::extract_key_info(&key) {
                             Ok(key_info) => {
-                                // Check for duplicates by fingerprint
-                                if !self
-                                    .key_info
-                                    .iter()
-                                    .any(|k| k.fingerprint == key_info.fingerprint)
-                                {
-                                    // Key loaded successfully
-                                    self.public_keys.push(key);
-                                    self.key_info.push(key_info.clone());
+                                parsed_any = true;
+                                // Skips duplicates by fingerprint
+                                if self.add_public_key(key, &key_info) {
                                     loaded_keys.push(key_info);
-                                } else {
-                                    // Skipped duplicate key
                                 }
                             }
                             Err(_) => {
@@ -232,25 +386,16 @@ impl PgpHandler {
         }
 
         // If position-based extraction didn't work, try fallback methods
-        if loaded_keys.is_empty() {
+        if !parsed_any {
             // Try alternative parsing method
             // Try to parse as armored keyring with from_armor_many
             if let Ok((parsed_keys_iter, _)) =
                 SignedPublicKey::from_armor_many(Cursor::new(key_data))
             {
-                for key_result in parsed_keys_iter {
-                    if let Ok(key) = key_result {
-                        if let Ok(key_info) = Self::extract_key_info(&key) {
-                            if !self
-                                .key_info
-                                .iter()
-                                .any(|k| k.fingerprint == key_info.fingerprint)
-                            {
-                                // Key loaded successfully
-                                self.public_keys.push(key);
-                                self.key_info.push(key_info.clone());
-                                loaded_keys.push(key_info);
-                            }
+                for key in parsed_keys_iter.flatten() {
+                    if let Ok(key_info) = Self::extract_key_info(&key) {
+                        if self.add_public_key(key, &key_info) {
+                            loaded_keys.push(key_info);
                         }
                     }
                 }
@@ -260,9 +405,9 @@ impl PgpHandler {
                 // Try single key parsing
                 // Fallback to single key parsing
                 if let Ok(key_info) = Self::extract_key_info(&single_key) {
-                    self.public_keys.push(single_key);
-                    self.key_info.push(key_info.clone());
-                    loaded_keys.push(key_info);
+                    if self.add_public_key(single_key, &key_info) {
+                        loaded_keys.push(key_info);
+                    }
                 }
             }
         }
@@ -281,31 +426,105 @@ impl PgpHandler {
         self.key_info.clear();
     }
 
+    /// Stop encrypting to the key with this fingerprint. Returns false if it wasn't loaded.
+    pub fn remove_public_key(&mut self, fingerprint: &str) -> bool {
+        let Some(index) = self
+            .key_info
+            .iter()
+            .position(|info| info.fingerprint.eq_ignore_ascii_case(fingerprint))
+        else {
+            return false;
+        };
+        self.public_keys.remove(index);
+        self.key_info.remove(index);
+        true
+    }
+
     pub fn public_key_count(&self) -> usize {
         self.public_keys.len()
     }
 
-    #[allow(dead_code)]
+    /// How many keys encryption will use: the loaded public keys plus our own key
+    /// when encrypting to self and it isn't already among them.
+    pub fn recipient_count(&self) -> usize {
+        let self_key_is_extra = self.self_public_key().is_some_and(|self_key| {
+            !self
+                .public_keys
+                .iter()
+                .any(|key| key.fingerprint() == self_key.fingerprint())
+        });
+        self.public_keys.len() + usize::from(self_key_is_extra)
+    }
+
+    /// The keys encryption will use: the loaded public keys, or only those listed in
+    /// `fingerprints` as `encrypt_to` takes them, plus our own key when encrypting to self.
+    pub fn planned_recipients(&self, fingerprints: Option<&[String]>) -> Vec<KeyInfo> {
+        let mut recipients: Vec<KeyInfo> = self
+            .key_info
+            .iter()
+            .filter(|info| {
+                fingerprints.is_none_or(|fingerprints| {
+                    fingerprints
+                        .iter()
+                        .any(|fp| fp.eq_ignore_ascii_case(&info.fingerprint))
+                })
+            })
+            .cloned()
+            .collect();
+        if let Some(self_info) = self
+            .self_public_key()
+            .and_then(|key| Self::extract_key_info(&key).ok())
+        {
+            if !recipients
+                .iter()
+                .any(|info| info.fingerprint == self_info.fingerprint)
+            {
+                recipients.push(self_info);
+            }
+        }
+        recipients
+    }
+
     pub fn get_loaded_keys(&self) -> &[KeyInfo] {
         &self.key_info
     }
 
+    /// Load a secret key, unlocking it with `passphrase`, which is also kept for keys
+    /// without a passphrase of their own and for the GPG fallback.
     pub fn load_secret_key(&mut self, key_data: &[u8], passphrase: Option<&str>) -> Result<()> {
+        if let Some(pass) = passphrase {
+            self.stored_passphrase = Some(pass.to_string());
+        }
+        self.load_secret_key_with(key_data, |_| passphrase.map(str::to_string))
+    }
+
+    /// Load a secret key, unlocking it with whatever `passphrase_for` returns for it.
+    /// The passphrase is remembered for that key only.
+    pub fn load_secret_key_with(
+        &mut self,
+        key_data: &[u8],
+        passphrase_for: impl Fn(&KeyInfo) -> Option<String>,
+    ) -> Result<()> {
         let (secret_key, _) = SignedSecretKey::from_armor_single(Cursor::new(key_data))
             .context("Failed to parse secret key")?;
 
-        if let Some(pass) = passphrase {
-            self.stored_passphrase = Some(pass.to_string());
-            let password_fn = || pass.to_string();
+        let key_info = Self::secret_key_info(&secret_key)?;
+        if let Some(pass) = passphrase_for(&key_info) {
+            let password_fn = || pass.clone();
             secret_key
                 .unlock(password_fn, |_| Ok(()))
-                .context("Failed to unlock secret key with passphrase")?;
+                .with_context(|| {
+                    format!("Failed to unlock {} with passphrase", key_info.label())
+                })?;
+            self.key_passphrases.insert(key_info.fingerprint, pass);
         }
 
-        self.secret_key = Some(secret_key);
+        self.add_secret_key(secret_key);
         Ok(())
     }
 
+    /// Load every public and private key in a keyring, trying `passphrase` on each
+    /// secret key. It is also kept for the GPG fallback.
     pub fn load_keyring(
         &mut self,
         key_data: &[u8],
@@ -315,7 +534,16 @@ impl PgpHandler {
         if let Some(pass) = passphrase {
             self.stored_passphrase = Some(pass.to_string());
         }
+        self.load_keyring_with(key_data, |_| passphrase.map(str::to_string))
+    }
 
+    /// Like `load_keyring`, with a passphrase for each secret key from `passphrase_for`.
+    /// A secret key the passphrase doesn't unlock is still loaded, but stays locked.
+    pub fn load_keyring_with(
+        &mut self,
+        key_data: &[u8],
+        passphrase_for: impl Fn(&KeyInfo) -> Option<String>,
+    ) -> Result<(Vec<KeyInfo>, bool)> {
         // Load both public and private keys from a keyring file
         let mut public_keys_loaded = Vec::new();
         let mut private_key_loaded = false;
@@ -357,18 +585,26 @@ impl PgpHandler {
                         // Found private key
 
                         // Try to unlock if passphrase provided
-                        if let Some(pass) = passphrase {
-                            let password_fn = || pass.to_string();
-                            match secret_key.unlock(password_fn, |_| Ok(())) {
-                                Ok(_) => { /* Private key unlocked */ },
-                                Err(_) => { /* Could not unlock private key */ },
+                        if let Ok(key_info) = Self::secret_key_info(&secret_key) {
+                            if let Some(pass) = passphrase_for(&key_info) {
+                                let password_fn = || pass.clone();
+                                match secret_key.unlock(password_fn, |_| Ok(())) {
+                                    Ok(_) => {
+                                        self.key_passphrases.insert(key_info.fingerprint, pass);
+                                    }
+                                    Err(_) => {
+                                        tracing::warn!(
+                                            "The passphrase doesn't unlock {}",
+                                            key_info.label()
+                                        );
+                                    }
+                                }
                             }
                         }
 
-                        self.secret_key = Some(secret_key);
+                        self.add_secret_key(secret_key);
                         private_key_loaded = true;
                         // Private key loaded from keyring
-                        break; // Only load the first private key
                     }
                     Err(_) => {
                         // Failed to parse private key block
@@ -384,35 +620,260 @@ impl PgpHandler {
         }
     }
 
+    /// Load every key file in `dir` as a keyring. Files that don't contain keys are
+    /// skipped, and keys already loaded are deduplicated by fingerprint.
+    pub fn load_keyring_dir(
+        &mut self,
+        dir: &Path,
+        passphrase: Option<&str>,
+    ) -> Result<(Vec<KeyInfo>, bool)> {
+        let mut public_keys_loaded = Vec::new();
+        let mut private_key_loaded = false;
+
+        for path in find_keyring_files(dir)? {
+            let Ok(key_data) = std::fs::read(&path) else {
+                continue;
+            };
+            if let Ok((keys, private_loaded)) = self.load_keyring(&key_data, passphrase) {
+                public_keys_loaded.extend(keys);
+                private_key_loaded |= private_loaded;
+            }
+        }
+
+        Ok((public_keys_loaded, private_key_loaded))
+    }
+
     pub fn has_secret_key(&self) -> bool {
-        self.secret_key.is_some()
+        !self.secret_keys.is_empty()
+    }
+
+    pub fn secret_key_count(&self) -> usize {
+        self.secret_keys.len()
+    }
+
+    fn secret_key_info(secret_key: &SignedSecretKey) -> Result<KeyInfo> {
+        Self::extract_key_info(&SignedPublicKey::from(secret_key.clone()))
+    }
+
+    /// The passphrase to use for `secret_key`: its own, else the shared one, else empty.
+    fn passphrase_for(&self, secret_key: &SignedSecretKey) -> String {
+        self.key_passphrases
+            .get(&hex::encode(secret_key.fingerprint()))
+            .or(self.stored_passphrase.as_ref())
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Loaded secret keys that the passphrases given so far don't unlock.
+    pub fn locked_secret_keys(&self) -> Vec<KeyInfo> {
+        self.secret_keys
+            .iter()
+            .filter(|key| {
+                let passphrase = self.passphrase_for(key);
+                key.unlock(|| passphrase, |_| Ok(())).is_err()
+            })
+            .filter_map(|key| Self::secret_key_info(key).ok())
+            .collect()
+    }
+
+    /// Set the passphrase of the loaded secret key with `fingerprint`, checking first
+    /// that it unlocks the key.
+    pub fn set_key_passphrase(&mut self, fingerprint: &str, passphrase: &str) -> Result<()> {
+        let fingerprint = fingerprint.to_ascii_lowercase();
+        let secret_key = self
+            .secret_keys
+            .iter()
+            .find(|key| hex::encode(key.fingerprint()) == fingerprint)
+            .ok_or_else(|| anyhow!("No secret key with fingerprint {} is loaded", fingerprint))?;
+        let password_fn = || passphrase.to_string();
+        secret_key
+            .unlock(password_fn, |_| Ok(()))
+            .context("The passphrase doesn't unlock this key")?;
+        self.key_passphrases
+            .insert(fingerprint, passphrase.to_string());
+        Ok(())
+    }
+
+    /// Apply per-key passphrases by fingerprint, as configured in `key_passphrases`.
+    /// Ones for keys that aren't loaded, or that don't unlock their key, are warned about.
+    pub fn set_key_passphrases(&mut self, passphrases: &HashMap<String, String>) {
+        for (fingerprint, passphrase) in passphrases {
+            if let Err(e) = self.set_key_passphrase(fingerprint, passphrase) {
+                tracing::warn!("Passphrase for {}: {:#}", fingerprint, e);
+            }
+        }
     }
 
     pub fn encrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
-        if self.public_keys.is_empty() {
+        self.encrypt_with_filename(data, "data")
+    }
+
+    /// Encrypt to all loaded keys, recording `file_name` in the literal data packet.
+    pub fn encrypt_with_filename(&self, data: &[u8], file_name: &str) -> Result<Vec<u8>> {
+        if self.recipient_count() == 0 {
             return Err(anyhow!("No public keys loaded for encryption"));
         }
 
+        // Collect references to all public keys for multi-recipient encryption
+        let key_refs: Vec<&SignedPublicKey> = self.public_keys.iter().collect();
+
         // Create a binary literal message instead of converting to string
+        let message = Message::new_literal_bytes(file_name, data);
+        self.encrypt_to_key_refs(message, &key_refs, None, self.armor)
+    }
+
+    /// Encrypt only to the loaded public keys whose fingerprints are listed.
+    pub fn encrypt_to(
+        &self,
+        data: &[u8],
+        file_name: &str,
+        fingerprints: &[String],
+    ) -> Result<Vec<u8>> {
+        let key_refs: Vec<&SignedPublicKey> = self
+            .public_keys
+            .iter()
+            .zip(&self.key_info)
+            .filter(|(_, info)| {
+                fingerprints
+                    .iter()
+                    .any(|fp| fp.eq_ignore_ascii_case(&info.fingerprint))
+            })
+            .map(|(key, _)| key)
+            .collect();
+
+        if key_refs.is_empty() {
+            return Err(anyhow!("None of the requested recipients are loaded"));
+        }
+
+        // Create a binary literal message instead of converting to string
+        let message = Message::new_literal_bytes(file_name, data);
+        self.encrypt_to_key_refs(message, &key_refs, None, self.armor)
+    }
+
+    /// Encrypt to all loaded keys with `algorithm`, whatever `set_cipher_algorithm` chose.
+    pub fn encrypt_with_algo(
+        &self,
+        data: &[u8],
+        algorithm: SymmetricKeyAlgorithm,
+    ) -> Result<Vec<u8>> {
+        if self.recipient_count() == 0 {
+            return Err(anyhow!("No public keys loaded for encryption"));
+        }
+
+        let key_refs: Vec<&SignedPublicKey> = self.public_keys.iter().collect();
         let message = Message::new_literal_bytes("data", data);
+        self.encrypt_to_key_refs(message, &key_refs, Some(algorithm), self.armor)
+    }
+
+    /// Encrypt to all loaded keys as binary PGP, whatever `set_armor` chose.
+    pub fn encrypt_binary(&self, data: &[u8]) -> Result<Vec<u8>> {
+        if self.recipient_count() == 0 {
+            return Err(anyhow!("No public keys loaded for encryption"));
+        }
 
-        // Collect references to all public keys for multi-recipient encryption
         let key_refs: Vec<&SignedPublicKey> = self.public_keys.iter().collect();
+        let message = Message::new_literal_bytes("data", data);
+        self.encrypt_to_key_refs(message, &key_refs, None, false)
+    }
 
-        let encrypted = message
-            .encrypt_to_keys(
-                &mut rand::thread_rng(),
-                SymmetricKeyAlgorithm::AES256,
-                &key_refs,
-            )
+    /// Encrypt `message` to `key_refs`, plus the uploader's own key with `encrypt_to_self`,
+    /// armored or binary. Without an `algorithm` the configured or negotiated cipher is used.
+    fn encrypt_to_key_refs(
+        &self,
+        message: Message,
+        key_refs: &[&SignedPublicKey],
+        algorithm: Option<SymmetricKeyAlgorithm>,
+        armor: bool,
+    ) -> Result<Vec<u8>> {
+        let self_key = self.self_public_key();
+        let mut key_refs = key_refs.to_vec();
+        if let Some(self_key) = &self_key {
+            if !key_refs
+                .iter()
+                .any(|key| key.fingerprint() == self_key.fingerprint())
+            {
+                key_refs.push(self_key);
+            }
+        }
+
+        let algorithm = match algorithm.or(self.cipher_override) {
+            Some(algorithm) => algorithm,
+            None => negotiate_cipher(&key_refs),
+        };
+        tracing::info!("Encrypting with {:?}", algorithm);
+
+        let encrypted = self
+            .compressed(message)?
+            .encrypt_to_keys(&mut rand::thread_rng(), algorithm, &key_refs)
             .context("Failed to encrypt message")?;
+        write_message(&encrypted, armor)
+    }
 
-        let mut output = Vec::new();
-        encrypted
-            .to_armored_writer(&mut output, ArmorOptions::default())
-            .context("Failed to write encrypted message")?;
+    /// Encrypt with `passphrase` alone, for people without a keypair: the message has a
+    /// symmetric-key session key packet (SKESK) instead of one per recipient key, and
+    /// the passphrase is stretched with iterated and salted S2K. Uses the cipher from
+    /// `set_cipher_algorithm`, or AES-256.
+    pub fn encrypt_symmetric(&self, data: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+        if passphrase.is_empty() {
+            return Err(anyhow!("An empty passphrase can't be used for encryption"));
+        }
+        let mut rng = rand::thread_rng();
+        let algorithm = self
+            .cipher_override
+            .unwrap_or(SymmetricKeyAlgorithm::AES256);
+        tracing::info!("Encrypting with {:?} and a passphrase", algorithm);
+
+        let s2k = StringToKey::new_default(&mut rng);
+        let passphrase = passphrase.to_string();
+        let encrypted = self
+            .compressed(Message::new_literal_bytes("data", data))?
+            .encrypt_with_password(&mut rng, s2k, algorithm, || passphrase)
+            .context("Failed to encrypt message")?;
+        write_message(&encrypted, self.armor)
+    }
 
-        Ok(output)
+    /// Decrypt a message made by `encrypt_symmetric` (or `gpg --symmetric`).
+    pub fn decrypt_symmetric(&self, encrypted_data: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+        let message = if encrypted_data.starts_with(b"-----BEGIN PGP MESSAGE-----") {
+            Message::from_armor_single(Cursor::new(encrypted_data))
+                .map(|(msg, _)| msg)
+                .context("Failed to parse encrypted message")?
+        } else {
+            Message::from_bytes(Cursor::new(encrypted_data))
+                .context("Failed to parse encrypted message")?
+        };
+
+        let passphrase = passphrase.to_string();
+        let decrypted = message
+            .decrypt_with_password(|| passphrase)
+            .context("Failed to decrypt with the passphrase; is it the right one?")?
+            .decompress()
+            .context("Failed to decompress decrypted message")?;
+        let literal = decrypted
+            .get_literal()
+            .context("Decrypted message has no literal data")?;
+        Ok(literal.data().to_vec())
+    }
+
+    /// Sign with the loaded secret key, then encrypt to all loaded keys like
+    /// `encrypt_with_filename`, in one message, so recipients can check on decryption
+    /// who it came from.
+    pub fn encrypt_and_sign(&self, data: &[u8], file_name: &str) -> Result<Vec<u8>> {
+        let secret_key = self
+            .secret_keys
+            .first()
+            .context("No secret key loaded for signing")?;
+        if self.recipient_count() == 0 {
+            return Err(anyhow!("No public keys loaded for encryption"));
+        }
+
+        let password = || self.passphrase_for(secret_key);
+        let signed = Message::new_literal_bytes(file_name, data)
+            .sign(secret_key, password, Default::default())
+            .context("Failed to sign message")?;
+
+        let key_refs: Vec<&SignedPublicKey> = self.public_keys.iter().collect();
+        self.encrypt_to_key_refs(signed, &key_refs, None, self.armor)
     }
 
     pub fn is_pgp_encrypted(data: &[u8]) -> bool {
@@ -421,70 +882,447 @@ impl PgpHandler {
             return true;
         }
 
-        // Check for binary PGP message (starts with 0x85 or 0x84 for encrypted data)
-        if data.len() > 2 && (data[0] == 0x85 || data[0] == 0x84) {
+        // Check for binary PGP message (starts with a session key packet)
+        if data.len() > 2 && starts_with_session_key_packet(data) {
             return true;
         }
 
-        false
+        chunked::is_chunked(data)
+    }
+
+    /// Key IDs (16 hex digits) of the loaded secret keys and their subkeys.
+    pub fn secret_key_ids(&self) -> Vec<String> {
+        self.secret_keys
+            .iter()
+            .flat_map(|secret_key| {
+                std::iter::once(secret_key.key_id())
+                    .chain(secret_key.secret_subkeys.iter().map(|k| k.key_id()))
+            })
+            .map(hex::encode_upper)
+            .collect()
+    }
+
+    /// Key IDs (16 hex digits) a message's session key is encrypted to, read from the
+    /// PKESK packets at its start, so the first few kilobytes of the message are enough.
+    /// For a chunked stream these are the first chunk's recipients. An all-zero ID is a
+    /// hidden recipient.
+    pub fn list_recipients(data: &[u8]) -> Result<Vec<String>> {
+        use pgp::packet::Packet;
+
+        let mut recipients = Vec::new();
+        for packet in message_packets(data)? {
+            match packet {
+                Ok(Packet::PublicKeyEncryptedSessionKey(pkesk)) => {
+                    recipients.push(hex::encode_upper(pkesk.id()))
+                }
+                Ok(Packet::SymKeyEncryptedSessionKey(_)) | Ok(Packet::Marker(_)) => {}
+                // The encrypted data follows the session keys, and may be cut short here
+                _ => break,
+            }
+        }
+        if recipients.is_empty() {
+            return Err(anyhow!("No public-key recipients found in the message"));
+        }
+        Ok(recipients)
+    }
+
+    /// Recipients, public-key algorithms and cipher of a message, read from its first
+    /// packets without decrypting it, so the first few kilobytes are enough. The cipher
+    /// of a public-key encrypted message is inside the session key; it is only known
+    /// when the loaded secret key is one of the recipients.
+    pub fn inspect_message(&self, data: &[u8]) -> Result<MessageEnvelope> {
+        use pgp::crypto::public_key::PublicKeyAlgorithm;
+        use pgp::packet::{Data, Packet};
+
+        let mut envelope = MessageEnvelope::default();
+        for packet in message_packets(data)? {
+            match packet {
+                Ok(Packet::PublicKeyEncryptedSessionKey(pkesk)) => {
+                    // The algorithm follows the version and key ID, but has no getter
+                    let algorithm = pkesk
+                        .to_bytes()
+                        .ok()
+                        .and_then(|bytes| bytes.get(9).copied())
+                        .map(|id| format!("{:?}", PublicKeyAlgorithm::from(id)))
+                        .unwrap_or_else(|| "unknown".to_string());
+                    if envelope.cipher.is_none() {
+                        envelope.cipher = self.session_key_cipher(&pkesk);
+                    }
+                    envelope.recipients.push(MessageRecipient {
+                        key_id: hex::encode_upper(pkesk.id()),
+                        algorithm,
+                    });
+                }
+                Ok(Packet::SymKeyEncryptedSessionKey(skesk)) => {
+                    envelope.passphrase = true;
+                    // Without an encrypted session key, the passphrase-derived key is the session key
+                    if skesk.encrypted_key().is_none() {
+                        envelope.cipher = Some(format!("{:?}", skesk.sym_algorithm()));
+                    }
+                }
+                Ok(Packet::SymEncryptedProtectedData(seipd)) => {
+                    if let Data::V2 { sym_alg, aead, .. } = seipd.data() {
+                        envelope.cipher = Some(format!("{:?}", sym_alg));
+                        envelope.aead = Some(format!("{:?}", aead));
+                    }
+                    break;
+                }
+                Ok(Packet::Marker(_)) => {}
+                _ => break,
+            }
+        }
+        if envelope.recipients.is_empty() && !envelope.passphrase {
+            return Err(anyhow!("No session key packets found in the message"));
+        }
+        Ok(envelope)
+    }
+
+    /// The cipher of the session key in `pkesk`, if a loaded secret key can decrypt it.
+    fn session_key_cipher(
+        &self,
+        pkesk: &pgp::packet::PublicKeyEncryptedSessionKey,
+    ) -> Option<String> {
+        match self.session_key(pkesk)? {
+            PlainSessionKey::V4 { sym_alg, .. } => Some(format!("{:?}", sym_alg)),
+            _ => None,
+        }
+    }
+
+    /// The session key in `pkesk`, unwrapped with the loaded secret key or subkey it is for.
+    fn session_key(
+        &self,
+        pkesk: &pgp::packet::PublicKeyEncryptedSessionKey,
+    ) -> Option<PlainSessionKey> {
+        use pgp::composed::message::decrypt_session_key;
+
+        let secret_key = self.secret_keys.iter().find(|key| {
+            key.key_id() == *pkesk.id()
+                || key
+                    .secret_subkeys
+                    .iter()
+                    .any(|subkey| subkey.key_id() == *pkesk.id())
+        })?;
+        let password = || self.passphrase_for(secret_key);
+        let session_key = if secret_key.key_id() == *pkesk.id() {
+            decrypt_session_key(secret_key, password, pkesk.mpis())
+        } else {
+            let subkey = secret_key
+                .secret_subkeys
+                .iter()
+                .find(|subkey| subkey.key_id() == *pkesk.id())?;
+            decrypt_session_key(subkey, password, pkesk.mpis())
+        };
+        session_key.ok()
+    }
+
+    /// The loaded public key with primary or subkey ID `key_id` (16 hex digits).
+    pub fn key_for_id(&self, key_id: &str) -> Option<&KeyInfo> {
+        self.public_keys
+            .iter()
+            .zip(&self.key_info)
+            .find(|(key, _)| {
+                std::iter::once(key.key_id())
+                    .chain(key.public_subkeys.iter().map(|k| k.key_id()))
+                    .any(|id| hex::encode_upper(id) == key_id)
+            })
+            .map(|(_, info)| info)
     }
 
     pub fn decrypt(&self, encrypted_data: &[u8]) -> Result<Vec<u8>> {
+        let (decrypted, _) = self.decrypt_with_filename(encrypted_data)?;
+        Ok(decrypted)
+    }
+
+    /// Decrypt and also return the filename stored in the literal data packet, if any.
+    /// The GPG fallback cannot report a filename.
+    pub fn decrypt_with_filename(
+        &self,
+        encrypted_data: &[u8],
+    ) -> Result<(Vec<u8>, Option<String>)> {
         // Check if the data is actually encrypted
         if !Self::is_pgp_encrypted(encrypted_data) {
             // Data does not appear to be PGP encrypted
-            return Ok(encrypted_data.to_vec());
+            return Ok((encrypted_data.to_vec(), None));
+        }
+
+        if chunked::is_chunked(encrypted_data) {
+            let mut decrypted = Vec::new();
+            chunked::decrypt_chunked(self, &mut Cursor::new(encrypted_data), &mut decrypted)?;
+            return Ok((decrypted, None));
         }
 
         // First try with the pgp crate
-        if let Some(ref secret_key) = self.secret_key {
-            // Attempting decryption with pgp crate
+        if let Some(decrypted) = self.decrypt_with_pgp(encrypted_data) {
+            return Ok(decrypted);
+        }
 
-            // Try to parse as armored first, then fall back to binary
-            let message_result = if encrypted_data.starts_with(b"-----BEGIN PGP MESSAGE-----") {
-                // ASCII armored format
-                Message::from_armor_single(Cursor::new(encrypted_data)).map(|(msg, _)| msg)
-            } else {
-                // Binary format
-                Message::from_bytes(Cursor::new(encrypted_data))
-            };
+        // Fallback to GPG command-line
+        Ok((self.decrypt_with_gpg(encrypted_data)?, None))
+    }
 
-            if let Ok(message) = message_result {
-                let password_fn = || String::new();
-                let decrypt_result = message.decrypt(password_fn, &[secret_key]);
+    /// Decrypt `encrypted` and stream the plaintext to `writer`, returning the number of
+    /// bytes written. Chunked streams are decrypted a chunk at a time. Other messages up to
+    /// `IN_MEMORY_DECRYPT_LIMIT` are decrypted whole; larger ones for a loaded secret key
+    /// are decrypted as they are read, so memory use stays bounded whatever the size of
+    /// the object. Messages for no loaded key are piped through the user's own `gpg`.
+    /// A large message whose integrity check fails at the end has already been written,
+    /// so on error the caller must discard the output.
+    pub fn decrypt_to_writer(
+        &self,
+        encrypted: &mut (dyn Read + Send),
+        writer: &mut dyn Write,
+    ) -> Result<u64> {
+        self.decrypt_to_writer_within(encrypted, writer, IN_MEMORY_DECRYPT_LIMIT)
+    }
 
-                if let Ok((decrypted, _)) = decrypt_result {
-                    if let Ok(Some(content)) = decrypted.get_content() {
-                        // Successfully decrypted
-                        return Ok(content.clone());
-                    }
-                } else {
-                    // Try GPG fallback
+    fn decrypt_to_writer_within(
+        &self,
+        encrypted: &mut (dyn Read + Send),
+        writer: &mut dyn Write,
+        memory_limit: usize,
+    ) -> Result<u64> {
+        let mut header = Vec::with_capacity(64);
+        (&mut *encrypted).take(64).read_to_end(&mut header)?;
+        let is_encrypted = Self::is_pgp_encrypted(&header);
+        let is_chunked = chunked::is_chunked(&header);
+        let mut input = Cursor::new(header).chain(encrypted);
+
+        if !is_encrypted {
+            // Data does not appear to be PGP encrypted
+            return Ok(std::io::copy(&mut input, writer)?);
+        }
+
+        if is_chunked {
+            return chunked::decrypt_chunked(self, &mut input, writer);
+        }
+
+        if self.has_secret_key() {
+            let mut ciphertext = Vec::new();
+            (&mut input)
+                .take(memory_limit as u64 + 1)
+                .read_to_end(&mut ciphertext)?;
+            if ciphertext.len() > memory_limit {
+                let for_loaded_key = self.secret_key_for(&ciphertext).is_some();
+                let mut rest = Cursor::new(ciphertext).chain(input);
+                if for_loaded_key {
+                    let session_key =
+                        |pkesk: &PublicKeyEncryptedSessionKey| match self.session_key(pkesk)? {
+                            PlainSessionKey::V4 { sym_alg, key } => Some((sym_alg, key)),
+                            _ => None,
+                        };
+                    return pgp_stream::decrypt_message(&mut rest, writer, &session_key);
                 }
+                // Not for a loaded key, so only the user's own keyring can open it
+                return self.decrypt_stream_with_gpg(&mut rest, writer);
+            }
+            if let Some((plaintext, _)) = self.decrypt_with_pgp(&ciphertext) {
+                writer.write_all(&plaintext)?;
+                return Ok(plaintext.len() as u64);
             }
+            return self.decrypt_stream_with_gpg(&mut Cursor::new(ciphertext), writer);
         }
 
-        // Fallback to GPG command-line
-        self.decrypt_with_gpg(encrypted_data)
+        self.decrypt_stream_with_gpg(&mut input, writer)
     }
 
-    fn decrypt_with_gpg(&self, encrypted_data: &[u8]) -> Result<Vec<u8>> {
-        use std::process::Command;
+    /// The loaded secret key, or the one owning the subkey, that a message starting with
+    /// `data` is encrypted to.
+    fn secret_key_for(&self, data: &[u8]) -> Option<&SignedSecretKey> {
+        let recipients = Self::list_recipients(data).ok()?;
+        self.secret_keys.iter().find(|secret_key| {
+            std::iter::once(secret_key.key_id())
+                .chain(secret_key.secret_subkeys.iter().map(|k| k.key_id()))
+                .any(|id| recipients.contains(&hex::encode_upper(id)))
+        })
+    }
 
-        // Using GPG command-line for decryption
+    fn decrypt_with_pgp(&self, encrypted_data: &[u8]) -> Option<(Vec<u8>, Option<String>)> {
+        let decrypted = self.decrypt_message_with_pgp(encrypted_data)?;
+        let literal = decrypted.get_literal()?;
+        Some((literal.data().to_vec(), literal_file_name(literal)))
+    }
+
+    /// The decrypted and decompressed message inside `encrypted_data`, if one of the
+    /// loaded secret keys can open it. Each is tried in turn.
+    fn decrypt_message_with_pgp(&self, encrypted_data: &[u8]) -> Option<Message> {
+        if self.secret_keys.is_empty() {
+            return None;
+        }
+
+        // Try to parse as armored first, then fall back to binary
+        let message = if encrypted_data.starts_with(b"-----BEGIN PGP MESSAGE-----") {
+            // ASCII armored format
+            Message::from_armor_single(Cursor::new(encrypted_data))
+                .map(|(msg, _)| msg)
+                .ok()?
+        } else {
+            // Binary format
+            Message::from_bytes(Cursor::new(encrypted_data)).ok()?
+        };
+
+        self.secret_keys.iter().find_map(|secret_key| {
+            let password_fn = || self.passphrase_for(secret_key);
+            let (decrypted, _) = message.decrypt(password_fn, &[secret_key]).ok()?;
+            decrypted.decompress().ok()
+        })
+    }
+
+    /// Decrypt like `decrypt`, then check the signature of a signed message against the
+    /// loaded public keys and the public half of the loaded secret key. Returns who signed
+    /// it, or None if it isn't signed. A signature that doesn't verify, or one made by a
+    /// key that isn't loaded, is an error rather than plaintext of unknown origin.
+    /// Chunked uploads carry no signature, so they always come back unsigned.
+    pub fn decrypt_verified(&self, data: &[u8]) -> Result<(Vec<u8>, Option<SignatureInfo>)> {
+        if !Self::is_pgp_encrypted(data) {
+            return Ok((data.to_vec(), None));
+        }
+        if chunked::is_chunked(data) {
+            let mut decrypted = Vec::new();
+            chunked::decrypt_chunked(self, &mut Cursor::new(data), &mut decrypted)?;
+            return Ok((decrypted, None));
+        }
 
-        // Check data size for proper handling
-        // (Debug logging removed for security)
+        if let Some(message) = self.decrypt_message_with_pgp(data) {
+            let signature = self.verify_message_signature(&message)?;
+            let literal = message
+                .get_literal()
+                .context("Decrypted message has no literal data")?;
+            return Ok((literal.data().to_vec(), signature));
+        }
+
+        // Let gpg check the signature and report the result on its status lines
+        let mut decrypted = Vec::new();
+        let status = self
+            .run_gpg_decrypt(&mut Cursor::new(data), &mut decrypted, true)?
+            .1;
+        let signature = self.signature_from_gpg_status(&status)?;
+        Ok((decrypted, signature))
+    }
+
+    /// The signer of `message` if it is signed, checked against every loaded key's
+    /// primary key and subkeys.
+    fn verify_message_signature(&self, message: &Message) -> Result<Option<SignatureInfo>> {
+        let Message::Signed {
+            message: Some(_),
+            signature,
+            ..
+        } = message
+        else {
+            return Ok(None);
+        };
+        let issuers: Vec<String> = signature.issuer().iter().map(hex::encode_upper).collect();
+
+        let own_keys: Vec<SignedPublicKey> = self
+            .secret_keys
+            .iter()
+            .map(|key| SignedPublicKey::from(key.clone()))
+            .collect();
+        let mut candidates: Vec<(&SignedPublicKey, KeyInfo)> = self
+            .public_keys
+            .iter()
+            .zip(self.key_info.iter().cloned())
+            .collect();
+        for key in &own_keys {
+            candidates.push((key, Self::extract_key_info(key)?));
+        }
+
+        for (key, info) in candidates {
+            let mut signing_key_ids = std::iter::once(key.key_id())
+                .chain(key.public_subkeys.iter().map(|k| k.key_id()))
+                .map(hex::encode_upper);
+            if !issuers.is_empty() && !signing_key_ids.any(|id| issuers.contains(&id)) {
+                continue;
+            }
+            let verified = message.verify(key).is_ok()
+                || key
+                    .public_subkeys
+                    .iter()
+                    .any(|subkey| message.verify(subkey).is_ok());
+            if verified {
+                return Ok(Some(SignatureInfo {
+                    signer: info,
+                    created: signature.created().copied(),
+                }));
+            }
+            if !issuers.is_empty() {
+                return Err(anyhow!(
+                    "Invalid signature: the message claims to be signed by {} but the signature doesn't verify",
+                    info.label()
+                ));
+            }
+        }
+        Err(anyhow!(
+            "Message is signed by {}, which isn't a loaded key",
+            if issuers.is_empty() {
+                "an unnamed key".to_string()
+            } else {
+                issuers.join(", ")
+            }
+        ))
+    }
+
+    /// The signer gpg reported in `status` (its `--status-fd` lines), if the message
+    /// was signed. Only signatures by loaded keys are accepted.
+    fn signature_from_gpg_status(&self, status: &str) -> Result<Option<SignatureInfo>> {
+        let lines: Vec<Vec<&str>> = status
+            .lines()
+            .filter_map(|line| line.strip_prefix("[GNUPG:] "))
+            .map(|line| line.split_whitespace().collect())
+            .collect();
+
+        for fields in &lines {
+            match fields.first().copied() {
+                Some("BADSIG") => {
+                    return Err(anyhow!(
+                        "Invalid signature by key {}",
+                        fields.get(1).unwrap_or(&"unknown")
+                    ))
+                }
+                Some("ERRSIG") => {
+                    return Err(anyhow!(
+                        "Message is signed by {}, which gpg can't check",
+                        fields.get(1).unwrap_or(&"an unknown key")
+                    ))
+                }
+                _ => {}
+            }
+        }
+
+        // VALIDSIG <signing key fpr> <date> <timestamp> ... <primary key fpr>
+        let Some(fields) = lines
+            .iter()
+            .find(|fields| fields.first() == Some(&"VALIDSIG"))
+        else {
+            return Ok(None);
+        };
+        let primary = fields
+            .get(10)
+            .or(fields.get(1))
+            .copied()
+            .unwrap_or_default();
+        let signer = self
+            .key_info
+            .iter()
+            .find(|info| info.fingerprint.eq_ignore_ascii_case(primary))
+            .cloned()
+            .ok_or_else(|| anyhow!("Message is signed by {}, which isn't a loaded key", primary))?;
+        let created = fields
+            .get(3)
+            .and_then(|timestamp| timestamp.parse::<i64>().ok())
+            .and_then(|timestamp| DateTime::from_timestamp(timestamp, 0));
+        Ok(Some(SignatureInfo { signer, created }))
+    }
+
+    fn decrypt_with_gpg(&self, encrypted_data: &[u8]) -> Result<Vec<u8>> {
+        // Using GPG command-line for decryption
 
         // Check if this looks like PGP data
         let is_armored = encrypted_data.starts_with(b"-----BEGIN PGP MESSAGE-----");
-        let is_binary = encrypted_data.len() > 2
-            && (encrypted_data[0] == 0x85
-                || encrypted_data[0] == 0x84
-                || encrypted_data[0] == 0x8c);
-        println!(
-            "Debug: Is ASCII armored: {}, Is binary PGP: {}",
-            is_armored, is_binary
+        let is_binary = encrypted_data.len() > 2 && starts_with_session_key_packet(encrypted_data);
+        tracing::debug!(
+            "Is ASCII armored: {}, Is binary PGP: {}",
+            is_armored,
+            is_binary
         );
 
         // If it doesn't look like PGP data, don't try to decrypt
@@ -493,6 +1331,36 @@ impl PgpHandler {
             return Err(anyhow!("Data does not appear to be PGP encrypted"));
         }
 
+        let mut decrypted_data = Vec::new();
+        self.decrypt_stream_with_gpg(&mut Cursor::new(encrypted_data), &mut decrypted_data)?;
+
+        tracing::info!(
+            "Successfully decrypted with GPG (size: {} bytes)",
+            decrypted_data.len()
+        );
+        Ok(decrypted_data)
+    }
+
+    /// Pipe ciphertext through `gpg --decrypt`, copying its stdout straight to `writer`.
+    fn decrypt_stream_with_gpg(
+        &self,
+        encrypted: &mut (dyn Read + Send),
+        writer: &mut dyn Write,
+    ) -> Result<u64> {
+        Ok(self.run_gpg_decrypt(encrypted, writer, false)?.0)
+    }
+
+    /// Run `gpg --decrypt` as `decrypt_stream_with_gpg` does, also returning its stderr.
+    /// With `status`, gpg's machine-readable status lines are written there too, and a
+    /// bad signature is reported as such rather than as a failed decryption.
+    fn run_gpg_decrypt(
+        &self,
+        encrypted: &mut (dyn Read + Send),
+        writer: &mut dyn Write,
+        status_lines: bool,
+    ) -> Result<(u64, String)> {
+        use std::process::{Command, Stdio};
+
         // Check if GPG is available
         let gpg_check = Command::new("gpg").arg("--version").output();
 
@@ -500,40 +1368,56 @@ impl PgpHandler {
             return Err(anyhow!("GPG is not installed or not in PATH"));
         }
 
-        // Create a temporary file for the encrypted data
-        let temp_dir = std::env::temp_dir();
-        let temp_encrypted = temp_dir.join(format!("rust_r2_encrypted_{}.gpg", std::process::id()));
-        let temp_decrypted = temp_dir.join(format!("rust_r2_decrypted_{}", std::process::id()));
-
-        // Write encrypted data to temp file
-        std::fs::write(&temp_encrypted, encrypted_data)
-            .context("Failed to write temporary encrypted file")?;
-
-        // Try to decrypt with GPG
         let mut gpg_cmd = Command::new("gpg");
         gpg_cmd.arg("--batch").arg("--yes").arg("--quiet");
+        if status_lines {
+            gpg_cmd.arg("--status-fd").arg("2");
+        }
 
-        // Add passphrase if we have one stored (though GPG agent usually handles this)
         if let Some(passphrase) = &self.stored_passphrase {
+            // Add passphrase if we have one stored (though GPG agent usually handles this)
             if !passphrase.is_empty() {
                 gpg_cmd.arg("--passphrase").arg(passphrase);
             }
         }
 
-        gpg_cmd
+        // With no input file gpg reads ciphertext from stdin and writes plaintext to stdout
+        let mut child = gpg_cmd
             .arg("--decrypt")
-            .arg("--output")
-            .arg(&temp_decrypted)
-            .arg(&temp_encrypted);
-
-        let output = gpg_cmd.output().context("Failed to execute GPG")?;
-
-        // Clean up encrypted temp file
-        let _ = std::fs::remove_file(&temp_encrypted);
-
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("Failed to execute GPG")?;
+
+        let mut stdin = child.stdin.take().context("Failed to open GPG stdin")?;
+        let mut stdout = child.stdout.take().context("Failed to open GPG stdout")?;
+        let mut stderr = child.stderr.take().context("Failed to open GPG stderr")?;
+
+        // Feed stdin from another thread so a full stdout pipe can't deadlock us
+        let (written, stderr_text) = std::thread::scope(|scope| {
+            scope.spawn(move || {
+                // gpg closes stdin early on failure; that error is reported via its exit status
+                let _ = std::io::copy(encrypted, &mut stdin);
+            });
+            let stderr_reader = scope.spawn(move || {
+                let mut text = String::new();
+                let _ = stderr.read_to_string(&mut text);
+                text
+            });
+            let written = std::io::copy(&mut stdout, writer);
+            (written, stderr_reader.join().unwrap_or_default())
+        });
+
+        let status = child.wait().context("Failed to execute GPG")?;
+
+        if !status.success() {
+            let stderr = stderr_text;
+
+            // gpg fails on a bad signature after writing the plaintext
+            if status_lines && stderr.contains("[GNUPG:] BADSIG") {
+                return Err(anyhow!("Invalid signature: {}", stderr));
+            }
             // Check if it's a passphrase issue
             if stderr.contains("No secret key") {
                 return Err(anyhow!(
@@ -549,31 +1433,21 @@ impl PgpHandler {
             }
         }
 
-        // Read the decrypted file
-        let decrypted_data =
-            std::fs::read(&temp_decrypted).context("Failed to read decrypted file")?;
-
-        // Clean up decrypted temp file
-        let _ = std::fs::remove_file(&temp_decrypted);
-
-        println!(
-            "Successfully decrypted with GPG (size: {} bytes)",
-            decrypted_data.len()
-        );
-        Ok(decrypted_data)
+        let written = written.context("Failed to write decrypted output")?;
+        Ok((written, stderr_text))
     }
 
     #[allow(dead_code)]
     pub fn sign(&self, data: &[u8]) -> Result<Vec<u8>> {
         let secret_key = self
-            .secret_key
-            .as_ref()
+            .secret_keys
+            .first()
             .context("No secret key loaded for signing")?;
 
         // Create a binary literal message instead of converting to string
         let message = Message::new_literal_bytes("data", data);
 
-        let password_fn = || String::new();
+        let password_fn = || self.passphrase_for(secret_key);
 
         let signed = message
             .sign(secret_key, password_fn, Default::default())
@@ -617,3 +1491,510 @@ impl PgpHandler {
         ))
     }
 }
+
+// Ciphers we're willing to negotiate, strongest first
+const NEGOTIABLE_CIPHERS: &[SymmetricKeyAlgorithm] = &[
+    SymmetricKeyAlgorithm::AES256,
+    SymmetricKeyAlgorithm::AES192,
+    SymmetricKeyAlgorithm::AES128,
+];
+
+/// Pick the strongest cipher every recipient advertises in its preferred-symmetric-algorithms
+/// subpacket. Keys without preferences accept anything. With no common AES variant, fall back
+/// to AES128, which every OpenPGP implementation must support.
+pub fn negotiate_cipher(keys: &[&SignedPublicKey]) -> SymmetricKeyAlgorithm {
+    let preferences: Vec<Vec<SymmetricKeyAlgorithm>> =
+        keys.iter().map(|key| preferred_ciphers(key)).collect();
+
+    NEGOTIABLE_CIPHERS
+        .iter()
+        .copied()
+        .find(|algorithm| {
+            preferences
+                .iter()
+                .all(|prefs| prefs.is_empty() || prefs.contains(algorithm))
+        })
+        .unwrap_or(SymmetricKeyAlgorithm::AES128)
+}
+
+/// The cipher preferences from a key's self-signatures (direct-key first, then user IDs).
+fn preferred_ciphers(key: &SignedPublicKey) -> Vec<SymmetricKeyAlgorithm> {
+    key.details
+        .direct_signatures
+        .iter()
+        .chain(key.details.users.iter().flat_map(|user| &user.signatures))
+        .map(|signature| signature.preferred_symmetric_algs())
+        .find(|prefs| !prefs.is_empty())
+        .map(<[SymmetricKeyAlgorithm]>::to_vec)
+        .unwrap_or_default()
+}
+
+/// Parse a cipher name such as "aes256" for the encryption override.
+pub fn parse_cipher_algorithm(name: &str) -> Result<SymmetricKeyAlgorithm> {
+    match name.to_lowercase().replace('-', "").as_str() {
+        "aes128" => Ok(SymmetricKeyAlgorithm::AES128),
+        "aes192" => Ok(SymmetricKeyAlgorithm::AES192),
+        "aes256" => Ok(SymmetricKeyAlgorithm::AES256),
+        _ => Err(anyhow!(
+            "Unsupported cipher '{}', expected aes128, aes192 or aes256",
+            name
+        )),
+    }
+}
+
+/// The compression algorithm called `name`: none, zip or zlib.
+pub fn parse_compression_algorithm(name: &str) -> Result<CompressionAlgorithm> {
+    match name.to_lowercase().as_str() {
+        "none" => Ok(CompressionAlgorithm::Uncompressed),
+        "zip" => Ok(CompressionAlgorithm::ZIP),
+        "zlib" => Ok(CompressionAlgorithm::ZLIB),
+        _ => Err(anyhow!(
+            "Unsupported compression '{}', expected none, zip or zlib",
+            name
+        )),
+    }
+}
+
+// File extensions treated as key files when scanning a folder
+pub const KEYRING_EXTENSIONS: &[&str] = &["asc", "gpg", "pgp", "key"];
+
+/// The packets of an armored or binary message, or of the first message of a chunked
+/// stream.
+fn message_packets(
+    data: &[u8],
+) -> Result<Box<dyn Iterator<Item = pgp::errors::Result<pgp::packet::Packet>> + '_>> {
+    use pgp::packet::PacketParser;
+
+    let data = if chunked::is_chunked(data) {
+        data.get(chunked::FIRST_MESSAGE_OFFSET..)
+            .ok_or_else(|| anyhow!("Chunked stream is too short"))?
+    } else {
+        data
+    };
+    Ok(if data.starts_with(b"-----BEGIN PGP MESSAGE-----") {
+        Box::new(PacketParser::new(pgp::armor::Dearmor::new(Cursor::new(
+            data,
+        ))))
+    } else {
+        Box::new(PacketParser::new(Cursor::new(data)))
+    })
+}
+
+/// List the key files directly inside `dir`, sorted so loading order is stable.
+pub fn find_keyring_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let entries = std::fs::read_dir(dir)
+        .with_context(|| format!("Failed to read keyring folder {}", dir.display()))?;
+
+    let mut files: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .filter(|path| {
+            path.extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| KEYRING_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+        })
+        .collect();
+    files.sort();
+
+    Ok(files)
+}
+
+/// Whether binary `data` starts with a public-key (tag 1) or symmetric-key (tag 3)
+/// session key packet, as an encrypted message does. Both the old packet format (0x84,
+/// 0x85, 0x8C, ...) and the new one (0xC1, 0xC3) are recognised.
+fn starts_with_session_key_packet(data: &[u8]) -> bool {
+    let Some(&first) = data.first() else {
+        return false;
+    };
+    let tag = match first & 0xC0 {
+        0xC0 => first & 0x3F,
+        0x80 => (first & 0x3C) >> 2,
+        _ => return false,
+    };
+    tag == 1 || tag == 3
+}
+
+/// `message` as ASCII armor, or as binary packets without `armor`.
+fn write_message(message: &Message, armor: bool) -> Result<Vec<u8>> {
+    let mut output = Vec::new();
+    if armor {
+        message
+            .to_armored_writer(&mut output, ArmorOptions::default())
+            .context("Failed to write encrypted message")?;
+    } else {
+        message
+            .to_writer(&mut output)
+            .context("Failed to write encrypted message")?;
+    }
+    Ok(output)
+}
+
+/// Read the filename out of a literal data packet. pgp does not expose it directly,
+/// so serialize just the packet header: mode byte, name length, name.
+fn literal_file_name(literal: &LiteralData) -> Option<String> {
+    struct HeaderSink(Vec<u8>);
+
+    impl std::io::Write for HeaderSink {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            // Keep at most the mode, length and a 255-byte name; discard the payload
+            let room = 257usize.saturating_sub(self.0.len());
+            self.0.extend_from_slice(&buf[..buf.len().min(room)]);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut sink = HeaderSink(Vec::new());
+    literal.to_writer(&mut sink).ok()?;
+    let name_len = *sink.0.get(1)? as usize;
+    let name = String::from_utf8_lossy(sink.0.get(2..2 + name_len)?).into_owned();
+
+    // "data" is the placeholder older uploads used
+    if name.is_empty() || name == "data" {
+        None
+    } else {
+        Some(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, TempDir};
+
+    fn test_handler() -> PgpHandler {
+        let key = test_support::key("Test");
+        test_support::handler(&[&key], &[&key])
+    }
+
+    fn gpg_available() -> bool {
+        std::process::Command::new("gpg")
+            .arg("--version")
+            .output()
+            .is_ok_and(|output| output.status.success())
+    }
+
+    #[test]
+    fn decrypts_small_messages_in_process() {
+        let handler = test_handler();
+        let plaintext = b"small enough to hold".repeat(10);
+        let encrypted = handler
+            .encrypt_with_filename(&plaintext, "small.txt")
+            .unwrap();
+
+        let mut output = Vec::new();
+        let written = handler
+            .decrypt_to_writer(&mut Cursor::new(encrypted), &mut output)
+            .unwrap();
+        assert_eq!(written, plaintext.len() as u64);
+        assert_eq!(output, plaintext);
+    }
+
+    #[test]
+    fn streams_messages_over_the_limit_in_process() {
+        // Random, so it stays over the limit when compressed
+        let plaintext: Vec<u8> = (0..200_000).map(|_| rand::random::<u8>()).collect();
+        let settings = [
+            (true, CompressionAlgorithm::Uncompressed),
+            (false, CompressionAlgorithm::Uncompressed),
+            (true, CompressionAlgorithm::ZIP),
+            (false, CompressionAlgorithm::ZLIB),
+        ];
+        for (armor, compression) in settings {
+            let mut handler = test_handler();
+            handler.set_armor(armor);
+            handler.set_compression(compression);
+            let encrypted = handler
+                .encrypt_with_filename(&plaintext, "large.bin")
+                .unwrap();
+
+            // Over the limit, so the pgp crate never sees the whole message
+            let mut output = Vec::new();
+            let written = handler
+                .decrypt_to_writer_within(&mut Cursor::new(encrypted), &mut output, 64 * 1024)
+                .unwrap();
+            assert_eq!(written, plaintext.len() as u64, "{:?}", compression);
+            assert_eq!(output, plaintext);
+        }
+    }
+
+    #[test]
+    fn streamed_messages_fail_on_a_modified_or_cut_ciphertext() {
+        let mut handler = test_handler();
+        handler.set_armor(false);
+        let plaintext: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        let encrypted = handler.encrypt(&plaintext).unwrap();
+
+        let mut flipped = encrypted.clone();
+        let middle = flipped.len() / 2;
+        flipped[middle] ^= 0x01;
+        let error = handler
+            .decrypt_to_writer_within(&mut Cursor::new(flipped), &mut Vec::new(), 64 * 1024)
+            .unwrap_err();
+        assert!(error.to_string().contains("integrity check"), "{:#}", error);
+
+        let cut = &encrypted[..encrypted.len() - 100];
+        let error = handler
+            .decrypt_to_writer_within(&mut Cursor::new(cut), &mut Vec::new(), 64 * 1024)
+            .unwrap_err();
+        assert!(error.to_string().contains("truncated"), "{:#}", error);
+    }
+
+    #[test]
+    fn streams_messages_gpg_wrote_with_partial_lengths() {
+        if !gpg_available() {
+            eprintln!("gpg not found, skipping");
+            return;
+        }
+        let key = test_support::key("Test");
+        let home = TempDir::new();
+        let public_key = home.write("public.asc", &key.public);
+        let plaintext: Vec<u8> = (0..300_000).map(|_| rand::random::<u8>()).collect();
+        let input = home.write("large.bin", &plaintext);
+        let gpg = |args: &[&std::ffi::OsStr]| {
+            let status = std::process::Command::new("gpg")
+                .arg("--homedir")
+                .arg(home.path())
+                .args(["--batch", "--quiet", "--yes"])
+                .args(args)
+                .status()
+                .unwrap();
+            assert!(status.success());
+        };
+        gpg(&["--import".as_ref(), public_key.as_os_str()]);
+        let encrypted = home.path().join("large.bin.gpg");
+        gpg(&[
+            "--trust-model".as_ref(),
+            "always".as_ref(),
+            "--recipient".as_ref(),
+            key.fingerprint.as_ref(),
+            "--output".as_ref(),
+            encrypted.as_os_str(),
+            "--encrypt".as_ref(),
+            input.as_os_str(),
+        ]);
+        assert!(std::fs::metadata(&encrypted).unwrap().len() > 64 * 1024);
+
+        let handler = test_handler();
+        let mut output = Vec::new();
+        let written = handler
+            .decrypt_to_writer_within(
+                &mut std::fs::File::open(&encrypted).unwrap(),
+                &mut output,
+                64 * 1024,
+            )
+            .unwrap();
+        assert_eq!(written, plaintext.len() as u64);
+        assert_eq!(output, plaintext);
+    }
+
+    #[test]
+    fn filenames_with_spaces_survive_the_round_trip() {
+        let handler = test_handler();
+        let encrypted = handler
+            .encrypt_with_filename(b"figures", "Quarterly report 2024.xlsx")
+            .unwrap();
+
+        let (plaintext, file_name) = handler.decrypt_with_filename(&encrypted).unwrap();
+        assert_eq!(plaintext, b"figures");
+        assert_eq!(file_name.as_deref(), Some("Quarterly report 2024.xlsx"));
+    }
+
+    #[test]
+    fn keyring_dirs_load_every_key_file() {
+        let (alice, bob, carol) = (
+            test_support::key("Alice"),
+            test_support::key("Bob"),
+            test_support::key("Carol"),
+        );
+        let dir = TempDir::new();
+        dir.write("alice.asc", &alice.public);
+        dir.write("bob.GPG", &bob.public);
+        dir.write("carol.key", &carol.secret);
+        dir.write("notes.txt", &alice.public); // Not a key file extension
+        dir.write("broken.asc", b"not a key");
+
+        let mut handler = PgpHandler::new();
+        let (loaded, private_loaded) = handler
+            .load_keyring_dir(dir.path(), Some(test_support::PASSPHRASE))
+            .unwrap();
+
+        let mut fingerprints: Vec<&str> = loaded.iter().map(|k| k.fingerprint.as_str()).collect();
+        fingerprints.sort();
+        let mut expected = vec![alice.fingerprint.as_str(), bob.fingerprint.as_str()];
+        expected.sort();
+        assert_eq!(fingerprints, expected);
+        assert!(private_loaded);
+        assert_eq!(handler.secret_key_count(), 1);
+    }
+
+    #[test]
+    fn ciphers_are_negotiated_down_to_what_every_recipient_prefers() {
+        use SymmetricKeyAlgorithm::{AES128, AES192, AES256};
+        let dana = test_support::key_preferring("Dana", &[AES256, AES128]);
+        let eli = test_support::key_preferring("Eli", &[AES192, AES128]);
+        let parse = |key: &test_support::TestKey| {
+            SignedPublicKey::from_armor_single(Cursor::new(&key.public))
+                .unwrap()
+                .0
+        };
+        let (dana_key, eli_key) = (parse(&dana), parse(&eli));
+
+        assert_eq!(negotiate_cipher(&[&dana_key, &eli_key]), AES128);
+        assert_eq!(negotiate_cipher(&[&dana_key]), AES256);
+        assert_eq!(negotiate_cipher(&[&eli_key]), AES192);
+
+        // The encrypted message really uses the negotiated cipher
+        let handler = test_support::handler(&[&dana, &eli], &[&dana]);
+        let encrypted = handler.encrypt(b"for both").unwrap();
+        let envelope = handler.inspect_message(&encrypted).unwrap();
+        assert_eq!(envelope.cipher.as_deref(), Some("AES128"));
+    }
+
+    #[test]
+    fn encrypting_to_self_lets_the_uploader_decrypt() {
+        let teammate = test_support::key("Teammate");
+        let uploader = test_support::key("Uploader");
+        let mut handler = test_support::handler(&[&teammate], &[&uploader]);
+        let for_team = handler.encrypt(b"team only").unwrap();
+        assert!(handler.decrypt(&for_team).is_err());
+
+        handler.set_encrypt_to_self(true);
+        assert_eq!(handler.recipient_count(), 2);
+        let recipients: Vec<String> = handler
+            .planned_recipients(None)
+            .into_iter()
+            .map(|info| info.fingerprint)
+            .collect();
+        assert_eq!(
+            recipients,
+            [teammate.fingerprint.clone(), uploader.fingerprint.clone()]
+        );
+
+        let encrypted = handler.encrypt(b"my own backup").unwrap();
+        assert_eq!(handler.decrypt(&encrypted).unwrap(), b"my own backup");
+        let team = test_support::handler(&[], &[&teammate]);
+        assert_eq!(team.decrypt(&encrypted).unwrap(), b"my own backup");
+
+        // Choosing recipients by fingerprint still adds our own key
+        let only_team = [teammate.fingerprint.clone()];
+        let encrypted = handler
+            .encrypt_to(b"chosen", "chosen.txt", &only_team)
+            .unwrap();
+        assert_eq!(handler.decrypt(&encrypted).unwrap(), b"chosen");
+    }
+
+    #[test]
+    fn planned_recipients_are_the_ones_encrypted_to() {
+        let alice = test_support::key("Alice");
+        let bob = test_support::key("Bob");
+        let carol = test_support::key("Carol");
+        let mut handler = test_support::handler(&[&alice, &bob], &[&carol]);
+
+        // PKESK packets name recipients by key ID, the fingerprint's last 16 digits
+        let key_ids = |recipients: &[KeyInfo]| {
+            let mut ids: Vec<String> = recipients
+                .iter()
+                .map(|info| info.fingerprint[info.fingerprint.len() - 16..].to_uppercase())
+                .collect();
+            ids.sort();
+            ids
+        };
+        let encrypted_to = |message: &[u8]| {
+            let mut ids = PgpHandler::list_recipients(message).unwrap();
+            ids.sort();
+            ids
+        };
+
+        let planned = handler.planned_recipients(None);
+        let labels: Vec<String> = planned.iter().map(KeyInfo::label).collect();
+        let tail = |key: &test_support::TestKey| key.fingerprint[32..].to_uppercase();
+        assert_eq!(
+            labels,
+            [
+                format!("Alice <alice@example.com> [{}]", tail(&alice)),
+                format!("Bob <bob@example.com> [{}]", tail(&bob)),
+            ]
+        );
+        let encrypted = handler.encrypt(b"for everyone").unwrap();
+        assert_eq!(key_ids(&planned), encrypted_to(&encrypted));
+
+        // A selection, and then our own key on top of it
+        let only_bob = [bob.fingerprint.to_lowercase()];
+        let planned = handler.planned_recipients(Some(&only_bob));
+        assert_eq!(planned.len(), 1);
+        let encrypted = handler
+            .encrypt_to(b"for bob", "bob.txt", &only_bob)
+            .unwrap();
+        assert_eq!(key_ids(&planned), encrypted_to(&encrypted));
+
+        handler.set_encrypt_to_self(true);
+        let planned = handler.planned_recipients(Some(&only_bob));
+        assert_eq!(planned.len(), 2);
+        let encrypted = handler
+            .encrypt_to(b"for bob", "bob.txt", &only_bob)
+            .unwrap();
+        assert_eq!(key_ids(&planned), encrypted_to(&encrypted));
+        let planned = handler.planned_recipients(None);
+        assert_eq!(planned.len(), 3);
+        let encrypted = handler.encrypt(b"for everyone").unwrap();
+        assert_eq!(key_ids(&planned), encrypted_to(&encrypted));
+    }
+
+    #[test]
+    fn signed_messages_name_their_signer() {
+        let teammate = test_support::key("Teammate");
+        let uploader = test_support::key("Uploader");
+        let mut handler = test_support::handler(&[&teammate], &[&uploader]);
+        handler.set_encrypt_to_self(true);
+        handler.set_compression(CompressionAlgorithm::ZLIB);
+        let encrypted = handler
+            .encrypt_and_sign(b"signed figures", "report.csv")
+            .unwrap();
+
+        // The uploader can open it too, and the file name is kept
+        let (plaintext, file_name) = handler.decrypt_with_filename(&encrypted).unwrap();
+        assert_eq!(plaintext, b"signed figures");
+        assert_eq!(file_name.as_deref(), Some("report.csv"));
+
+        let team = test_support::handler(&[&uploader], &[&teammate]);
+        let (plaintext, signature) = team.decrypt_verified(&encrypted).unwrap();
+        assert_eq!(plaintext, b"signed figures");
+        let signature = signature.expect("message should be signed");
+        assert!(signature
+            .signer
+            .fingerprint
+            .eq_ignore_ascii_case(&uploader.fingerprint));
+
+        // Plain encryption verifies as unsigned, and a signer we don't know is refused
+        let unsigned = handler.encrypt(b"unsigned").unwrap();
+        let (_, signature) = team.decrypt_verified(&unsigned).unwrap();
+        assert!(signature.is_none());
+        let stranger = test_support::handler(&[], &[&teammate]);
+        assert!(stranger.decrypt_verified(&encrypted).is_err());
+    }
+
+    #[test]
+    fn binary_messages_round_trip_bytes_that_are_not_utf8() {
+        let handler = test_handler();
+        let data: Vec<u8> = (0..=255u8)
+            .cycle()
+            .take(4096)
+            .chain([0xC3, 0x28, 0xFF])
+            .collect();
+        assert!(std::str::from_utf8(&data).is_err());
+
+        // Armor stays on for `encrypt`, but never applies to `encrypt_binary`
+        let armored = handler.encrypt(&data).unwrap();
+        let binary = handler.encrypt_binary(&data).unwrap();
+        assert!(armored.starts_with(b"-----BEGIN PGP MESSAGE-----"));
+        assert!(!binary.starts_with(b"-----BEGIN"));
+        assert!(binary.len() < armored.len());
+        assert!(PgpHandler::is_pgp_encrypted(&binary));
+        assert_eq!(handler.decrypt(&binary).unwrap(), data);
+    }
+}
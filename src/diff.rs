@@ -0,0 +1,209 @@
+use crate::crypto::PgpHandler;
+use crate::encryption_filter;
+use crate::integrity;
+use crate::r2_client::R2Client;
+use anyhow::Result;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+/// How the two sides of a `diff` compare.
+#[derive(Debug, PartialEq)]
+pub enum DiffOutcome {
+    Identical {
+        bytes: u64,
+        sha256: String,
+    },
+    SizesDiffer {
+        left_size: u64,
+        right_size: u64,
+    },
+    DifferAt {
+        offset: u64, // First differing byte
+        left_size: u64,
+        right_size: u64,
+    },
+}
+
+/// Compare two sides, decrypting encrypted ones when given a handler. Sizes are
+/// compared first, as that needs no download, unless encryption makes them meaningless.
+pub async fn compare(
+    r2_client: &R2Client,
+    pgp_handler: Option<&PgpHandler>,
+    left: &DiffSide,
+    right: &DiffSide,
+) -> Result<DiffOutcome> {
+    // Sizes are free to compare, but ciphertext sizes say nothing about the plaintexts
+    let left_size = left.size(r2_client).await?;
+    let right_size = right.size(r2_client).await?;
+    let comparable =
+        pgp_handler.is_none() || (!left.is_encrypted_name() && !right.is_encrypted_name());
+    if let (Some(left_size), Some(right_size), true) = (left_size, right_size, comparable) {
+        if left_size != right_size {
+            return Ok(DiffOutcome::SizesDiffer {
+                left_size,
+                right_size,
+            });
+        }
+    }
+
+    let mut temp_files = Vec::new();
+    let result = async {
+        let left_path = left
+            .plaintext_file(r2_client, pgp_handler, &mut temp_files)
+            .await?;
+        let right_path = right
+            .plaintext_file(r2_client, pgp_handler, &mut temp_files)
+            .await?;
+        let difference = integrity::first_difference(&left_path, &right_path)?;
+        let left_size = fs::metadata(&left_path)?.len();
+        let right_size = fs::metadata(&right_path)?.len();
+
+        Ok(match difference {
+            None => DiffOutcome::Identical {
+                bytes: left_size,
+                sha256: integrity::sha256_file(&left_path)?,
+            },
+            Some(offset) => DiffOutcome::DifferAt {
+                offset,
+                left_size,
+                right_size,
+            },
+        })
+    }
+    .await;
+
+    for path in &temp_files {
+        let _ = fs::remove_file(path);
+    }
+    result
+}
+
+/// One side of a `diff`: an object in the bucket or a local file.
+pub enum DiffSide {
+    Key(String),
+    Local(PathBuf),
+}
+
+impl std::fmt::Display for DiffSide {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DiffSide::Key(key) => write!(f, "{}", key),
+            DiffSide::Local(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+impl DiffSide {
+    pub async fn size(&self, r2_client: &R2Client) -> Result<Option<u64>> {
+        match self {
+            DiffSide::Key(key) => Ok(r2_client.head_object(key).await?.content_length),
+            DiffSide::Local(path) => Ok(Some(fs::metadata(path)?.len())),
+        }
+    }
+
+    pub fn is_encrypted_name(&self) -> bool {
+        encryption_filter::is_encrypted_extension(&self.to_string())
+    }
+
+    /// Stream this side to a local file, decrypting it if it is encrypted and a handler is
+    /// given. Files created along the way are added to `temp_files` for the caller to remove.
+    pub async fn plaintext_file(
+        &self,
+        r2_client: &R2Client,
+        pgp_handler: Option<&PgpHandler>,
+        temp_files: &mut Vec<PathBuf>,
+    ) -> Result<PathBuf> {
+        let path = match self {
+            DiffSide::Key(key) => {
+                let path = temp_path(temp_files);
+                r2_client
+                    .download_object_to_file(key, &path, |_| {})
+                    .await?;
+                path
+            }
+            DiffSide::Local(path) => path.clone(),
+        };
+
+        let Some(handler) = pgp_handler else {
+            return Ok(path);
+        };
+        let mut header = Vec::with_capacity(64);
+        fs::File::open(&path)?.take(64).read_to_end(&mut header)?;
+        if !PgpHandler::is_pgp_encrypted(&header) {
+            return Ok(path);
+        }
+
+        let decrypted = temp_path(temp_files);
+        let mut input = fs::File::open(&path)?;
+        let mut output = std::io::BufWriter::new(fs::File::create(&decrypted)?);
+        handler.decrypt_to_writer(&mut input, &mut output)?;
+        output.flush()?;
+        Ok(decrypted)
+    }
+}
+
+fn temp_path(temp_files: &mut Vec<PathBuf>) -> PathBuf {
+    let id: [u8; 8] = rand::random();
+    let path = std::env::temp_dir().join(format!("rust-r2-diff-{}", hex::encode(id)));
+    // A leftover file would be treated as a partial download and resumed
+    let _ = fs::remove_file(&path);
+    temp_files.push(path.clone());
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_r2::MockR2;
+    use crate::test_support::TempDir;
+
+    #[tokio::test]
+    async fn diff_tells_identical_size_and_content_differences_apart() {
+        let mock = MockR2::start().await;
+        mock.put("a.txt", b"same content");
+        mock.put("b.txt", b"same content");
+        mock.put("longer.txt", b"same content, and more");
+        mock.put("changed.txt", b"same CONTENT");
+        let client = mock.client().await;
+        let folder = TempDir::new();
+        let local = folder.write("a.txt", b"same content");
+        let key = |key: &str| DiffSide::Key(key.to_string());
+
+        let outcome = compare(&client, None, &key("a.txt"), &DiffSide::Local(local))
+            .await
+            .unwrap();
+        assert_eq!(
+            outcome,
+            DiffOutcome::Identical {
+                bytes: 12,
+                sha256: integrity::sha256_hex(b"same content"),
+            }
+        );
+        assert!(matches!(
+            compare(&client, None, &key("a.txt"), &key("b.txt"))
+                .await
+                .unwrap(),
+            DiffOutcome::Identical { .. }
+        ));
+        assert_eq!(
+            compare(&client, None, &key("a.txt"), &key("longer.txt"))
+                .await
+                .unwrap(),
+            DiffOutcome::SizesDiffer {
+                left_size: 12,
+                right_size: 22,
+            }
+        );
+        assert_eq!(
+            compare(&client, None, &key("a.txt"), &key("changed.txt"))
+                .await
+                .unwrap(),
+            DiffOutcome::DifferAt {
+                offset: 5,
+                left_size: 12,
+                right_size: 12,
+            }
+        );
+    }
+}
@@ -0,0 +1,67 @@
+use crate::progress::format_size;
+use anyhow::{anyhow, Result};
+use std::path::Path;
+
+/// Free bytes this user can write on the volume holding `path`, which may be a file or
+/// folder that doesn't exist yet. None when the platform or filesystem can't say.
+pub fn available_space(path: &Path) -> Option<u64> {
+    // Ask about the nearest folder that exists, so the volume is the one `path` will be on
+    let path = std::path::absolute(path).ok()?;
+    let existing = path.ancestors().find(|dir| dir.is_dir())?;
+    free_bytes(existing)
+}
+
+/// Fail if writing `needed` more bytes at `path` would run its volume out of space, so a
+/// transfer stops before it starts instead of partway through. Passes when free space
+/// can't be read.
+pub fn ensure_space(path: &Path, needed: u64) -> Result<()> {
+    match available_space(path) {
+        Some(available) if available < needed => Err(anyhow!(
+            "Not enough disk space for {}: {} needed, {} free",
+            path.display(),
+            format_size(needed),
+            format_size(available)
+        )),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(unix)]
+fn free_bytes(dir: &Path) -> Option<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = CString::new(dir.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: c_path is NUL-terminated and stat is a valid out-pointer for the call
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    // f_bavail leaves out blocks reserved for root. Both fields are narrower on some platforms
+    #[allow(clippy::useless_conversion)]
+    Some(u64::from(stat.f_bavail).saturating_mul(u64::from(stat.f_frsize)))
+}
+
+#[cfg(windows)]
+fn free_bytes(dir: &Path) -> Option<u64> {
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::Storage::FileSystem::GetDiskFreeSpaceExW;
+
+    let wide: Vec<u16> = dir.as_os_str().encode_wide().chain(Some(0)).collect();
+    let mut available = 0u64;
+    // SAFETY: wide is NUL-terminated; the other outputs are optional and left null
+    let ok = unsafe {
+        GetDiskFreeSpaceExW(
+            wide.as_ptr(),
+            &mut available,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+        )
+    };
+    (ok != 0).then_some(available)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn free_bytes(_dir: &Path) -> Option<u64> {
+    None
+}
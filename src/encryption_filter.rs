@@ -0,0 +1,246 @@
+use crate::crypto::PgpHandler;
+use crate::r2_client::{ObjectInfo, R2Client, ENCRYPTED_METADATA};
+use anyhow::Result;
+use futures_util::stream::{self, StreamExt, TryStreamExt};
+use std::sync::RwLock;
+
+// Enough of the object to recognise an OpenPGP or armored message header
+const PROBE_BYTES: u64 = 64;
+
+// Objects checked in parallel when probing
+const PROBE_CONCURRENCY: usize = 8;
+
+// Added to the key of objects encrypted here
+pub const ENCRYPTED_EXTENSION: &str = ".pgp";
+
+// Recognised as encrypted when the config doesn't list its own extensions
+pub const DEFAULT_ENCRYPTED_EXTENSIONS: &[&str] = &[".pgp", ".gpg", ".asc"];
+
+// Set from the config at startup; empty means the defaults
+static ENCRYPTED_EXTENSIONS: RwLock<Vec<String>> = RwLock::new(Vec::new());
+
+/// Restrict an operation to encrypted or to plaintext objects.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EncryptionFilter {
+    #[default]
+    All,
+    Encrypted,
+    Plaintext,
+}
+
+impl EncryptionFilter {
+    pub fn from_flags(only_encrypted: bool, only_plaintext: bool) -> Self {
+        match (only_encrypted, only_plaintext) {
+            (true, _) => EncryptionFilter::Encrypted,
+            (_, true) => EncryptionFilter::Plaintext,
+            _ => EncryptionFilter::All,
+        }
+    }
+
+    pub fn matches(self, encrypted: bool) -> bool {
+        match self {
+            EncryptionFilter::All => true,
+            EncryptionFilter::Encrypted => encrypted,
+            EncryptionFilter::Plaintext => !encrypted,
+        }
+    }
+}
+
+/// Replace the extensions recognised as encrypted, e.g. from `pgp.encrypted_extensions`
+/// in the config. The leading dot is optional and case is ignored; an empty list
+/// restores the defaults.
+pub fn set_encrypted_extensions(extensions: &[String]) {
+    let normalized = extensions
+        .iter()
+        .map(|ext| ext.trim().trim_start_matches('.').to_ascii_lowercase())
+        .filter(|ext| !ext.is_empty())
+        .map(|ext| format!(".{}", ext))
+        .collect();
+    *ENCRYPTED_EXTENSIONS.write().unwrap() = normalized;
+}
+
+fn encrypted_extension_of(key: &str) -> Option<usize> {
+    let matches = |ext: &str| {
+        key.len() > ext.len()
+            && key.is_char_boundary(key.len() - ext.len())
+            && key[key.len() - ext.len()..].eq_ignore_ascii_case(ext)
+    };
+    let configured = ENCRYPTED_EXTENSIONS.read().unwrap();
+    if configured.is_empty() {
+        DEFAULT_ENCRYPTED_EXTENSIONS
+            .iter()
+            .find(|ext| matches(ext))
+            .map(|ext| ext.len())
+    } else {
+        configured.iter().find(|ext| matches(ext)).map(String::len)
+    }
+}
+
+/// Whether a key or file name ends in one of the encrypted extensions (`.pgp`, `.gpg`
+/// and `.asc` unless configured otherwise).
+pub fn is_encrypted_extension(key: &str) -> bool {
+    encrypted_extension_of(key).is_some()
+}
+
+/// The key without its encrypted extension, if it has one.
+pub fn strip_encrypted_extension(key: &str) -> Option<&str> {
+    encrypted_extension_of(key).map(|len| &key[..key.len() - len])
+}
+
+/// The key to store encrypted data under: `key` itself if it already has an encrypted
+/// extension, otherwise `key` with `.pgp` appended.
+pub fn with_encrypted_extension(key: &str) -> String {
+    if is_encrypted_extension(key) {
+        key.to_string()
+    } else {
+        format!("{}{}", key, ENCRYPTED_EXTENSION)
+    }
+}
+
+/// Decide whether an object is encrypted. An encrypted extension is trusted;
+/// otherwise, when `probe` is set, the `encrypted` metadata flag and then the
+/// first bytes of the object are checked.
+pub async fn is_object_encrypted(client: &R2Client, key: &str, probe: bool) -> Result<bool> {
+    if is_encrypted_extension(key) {
+        return Ok(true);
+    }
+    if !probe {
+        return Ok(false);
+    }
+
+    let head = client.head_object(key).await?;
+    if head
+        .metadata
+        .get(ENCRYPTED_METADATA)
+        .is_some_and(|v| v == "true")
+    {
+        return Ok(true);
+    }
+    if head.content_length == Some(0) {
+        return Ok(false);
+    }
+
+    probe_content(client, key).await
+}
+
+/// Read the first bytes of a non-empty object and check them for a PGP message or
+/// chunked encryption header.
+pub async fn probe_content(client: &R2Client, key: &str) -> Result<bool> {
+    let header = client.download_range(key, 0, PROBE_BYTES - 1).await?;
+    Ok(PgpHandler::is_pgp_encrypted(&header))
+}
+
+/// Keep the objects that pass `filter`, probing their content if `probe` is set.
+pub async fn filter_objects(
+    client: &R2Client,
+    objects: Vec<ObjectInfo>,
+    filter: EncryptionFilter,
+    probe: bool,
+) -> Result<Vec<ObjectInfo>> {
+    if filter == EncryptionFilter::All {
+        return Ok(objects);
+    }
+
+    let checked: Vec<(ObjectInfo, bool)> = stream::iter(objects)
+        .map(|object| async move {
+            let encrypted = is_object_encrypted(client, &object.key, probe).await?;
+            Ok::<_, anyhow::Error>((object, encrypted))
+        })
+        .buffered(PROBE_CONCURRENCY)
+        .try_collect()
+        .await?;
+
+    Ok(checked
+        .into_iter()
+        .filter(|(_, encrypted)| filter.matches(*encrypted))
+        .map(|(object, _)| object)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_r2::MockR2;
+
+    async fn filtered(client: &R2Client, filter: EncryptionFilter, probe: bool) -> Vec<String> {
+        let objects = client.list_objects_detailed(Some("mixed/")).await.unwrap();
+        filter_objects(client, objects, filter, probe)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|object| object.key)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn filters_split_a_mixed_bucket() {
+        let mock = MockR2::start().await;
+        mock.put("mixed/report.pdf.PGP", b"extension says encrypted");
+        mock.put_with_metadata("mixed/flagged.bin", b"opaque", &[("encrypted", "true")]);
+        mock.put(
+            "mixed/sniffed.dat",
+            b"-----BEGIN PGP MESSAGE-----\n\nhQIMA...",
+        );
+        mock.put("mixed/notes.txt", b"plain text");
+        mock.put("mixed/empty.txt", b"");
+        let client = mock.client().await;
+
+        let all = filtered(&client, EncryptionFilter::All, true).await;
+        assert_eq!(all.len(), 5);
+        assert_eq!(
+            filtered(&client, EncryptionFilter::Encrypted, true).await,
+            [
+                "mixed/flagged.bin",
+                "mixed/report.pdf.PGP",
+                "mixed/sniffed.dat"
+            ]
+        );
+        assert_eq!(
+            filtered(&client, EncryptionFilter::Plaintext, true).await,
+            ["mixed/empty.txt", "mixed/notes.txt"]
+        );
+
+        // Without probing only the extension counts
+        assert_eq!(
+            filtered(&client, EncryptionFilter::Encrypted, false).await,
+            ["mixed/report.pdf.PGP"]
+        );
+        assert_eq!(
+            filtered(&client, EncryptionFilter::Plaintext, false)
+                .await
+                .len(),
+            4
+        );
+    }
+
+    #[test]
+    fn encrypted_extensions_are_added_once_and_stripped() {
+        assert_eq!(with_encrypted_extension("a/report.pdf"), "a/report.pdf.pgp");
+        for ext in [".pgp", ".gpg", ".asc", ".ASC"] {
+            let key = format!("a/report.pdf{}", ext);
+            assert!(is_encrypted_extension(&key), "{}", key);
+            assert_eq!(with_encrypted_extension(&key), key);
+            assert_eq!(strip_encrypted_extension(&key), Some("a/report.pdf"));
+        }
+        assert_eq!(strip_encrypted_extension("report.pdf"), None);
+        // A bare extension is a name, not an extension
+        assert!(!is_encrypted_extension(".pgp"));
+    }
+
+    #[tokio::test]
+    async fn every_encrypted_extension_is_trusted_without_probing() {
+        let mock = MockR2::start().await;
+        for key in ["in/a.pgp", "in/b.gpg", "in/c.asc", "in/d.txt"] {
+            mock.put(key, b"not actually a PGP message");
+        }
+        let client = mock.client().await;
+
+        for key in ["in/a.pgp", "in/b.gpg", "in/c.asc"] {
+            assert!(is_object_encrypted(&client, key, false).await.unwrap());
+            assert!(is_object_encrypted(&client, key, true).await.unwrap());
+        }
+        assert!(!is_object_encrypted(&client, "in/d.txt", true)
+            .await
+            .unwrap());
+    }
+}
@@ -0,0 +1,110 @@
+use thiserror::Error;
+
+/// Errors reported by R2 itself, as opposed to local I/O or network failures.
+/// These travel inside `anyhow::Error`; use `R2Error::from_anyhow` to inspect them.
+#[derive(Debug, Error)]
+pub enum R2Error {
+    #[error("R2 {operation} failed with status {status}: {body}")]
+    Service {
+        operation: &'static str,
+        status: u16,
+        code: Option<String>, // S3 error code from the XML body, e.g. "NoSuchKey"
+        body: String,
+    },
+
+    #[error("R2 credentials expired or were rotated ({code}), please re-authenticate")]
+    CredentialsExpired { code: String },
+}
+
+impl R2Error {
+    pub fn from_anyhow(error: &anyhow::Error) -> Option<&R2Error> {
+        error.downcast_ref::<R2Error>()
+    }
+
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            R2Error::Service { status, .. } => Some(*status),
+            R2Error::CredentialsExpired { .. } => None,
+        }
+    }
+
+    /// True for a missing object or bucket. HEAD responses have no body, so only the
+    /// status tells.
+    pub fn is_not_found(&self) -> bool {
+        self.status() == Some(404) || matches!(self.code(), Some("NoSuchKey" | "NoSuchBucket"))
+    }
+
+    pub fn code(&self) -> Option<&str> {
+        match self {
+            R2Error::Service { code, .. } => code.as_deref(),
+            R2Error::CredentialsExpired { code } => Some(code),
+        }
+    }
+}
+
+/// A transfer that moved no data for too long, typically on a half-open connection.
+#[derive(Debug, Error)]
+#[error("No data moved for {}s, the connection looks stalled", .0.as_secs())]
+pub struct TransferStalled(pub std::time::Duration);
+
+/// The circuit breaker gave up on R2 after too many failures in a row.
+#[derive(Debug, Error)]
+#[error("R2 appears unavailable: {failures} requests in a row failed, including after a pause")]
+pub struct R2Unavailable {
+    pub failures: u32,
+}
+
+/// The operation used up its retries, see `retry_budget::RetryPolicy::max_retries`.
+#[derive(Debug, Error)]
+#[error("Gave up after using all {0} retries allowed for this operation")]
+pub struct RetryBudgetExhausted(pub u32);
+
+/// True if `error` is R2 reporting that the object (or bucket) doesn't exist, as
+/// opposed to a network failure or any other error.
+pub fn is_not_found(error: &anyhow::Error) -> bool {
+    R2Error::from_anyhow(error).is_some_and(R2Error::is_not_found)
+}
+
+/// Error codes meaning the signing credentials are no longer valid and may be reloaded.
+pub fn is_credential_error(code: &str) -> bool {
+    matches!(
+        code,
+        "ExpiredToken" | "InvalidAccessKeyId" | "TokenRefreshRequired" | "InvalidToken"
+    )
+}
+
+/// Pull the `<Code>` element out of an S3-style XML error body.
+pub fn parse_error_code(body: &str) -> Option<String> {
+    let start = body.find("<Code>")? + "<Code>".len();
+    let end = start + body[start..].find("</Code>")?;
+    Some(body[start..end].trim().to_string())
+}
+
+/// True if `error` is a network-level failure (refused, reset or timed-out connection)
+/// rather than a response from R2, so the request may succeed on a fresh connection.
+pub fn is_connection_error(error: &anyhow::Error) -> bool {
+    if R2Error::from_anyhow(error).is_some() {
+        return false;
+    }
+    if error.is::<TransferStalled>() {
+        return true;
+    }
+
+    error.chain().any(|cause| {
+        if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+            return e.status().is_none() && (e.is_connect() || e.is_timeout() || e.is_request());
+        }
+        cause.downcast_ref::<std::io::Error>().is_some_and(|e| {
+            matches!(
+                e.kind(),
+                std::io::ErrorKind::ConnectionRefused
+                    | std::io::ErrorKind::ConnectionReset
+                    | std::io::ErrorKind::ConnectionAborted
+                    | std::io::ErrorKind::NotConnected
+                    | std::io::ErrorKind::BrokenPipe
+                    | std::io::ErrorKind::TimedOut
+                    | std::io::ErrorKind::UnexpectedEof
+            )
+        })
+    })
+}
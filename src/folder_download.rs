@@ -0,0 +1,767 @@
+use crate::crypto::PgpHandler;
+use crate::disk_space;
+use crate::encryption_filter;
+use crate::error::R2Error;
+use crate::glob_filter::GlobFilter;
+use crate::integrity;
+use crate::progress::{ItemOutcome, ProgressReporter};
+use crate::r2_client::{ObjectHead, ObjectInfo, R2Client};
+use crate::transfer;
+use anyhow::{anyhow, Context, Result};
+use futures_util::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+pub const MANIFEST_FILE_NAME: &str = ".download-manifest.json";
+
+// Suffix for files still being downloaded, so an interrupted file is never mistaken for a complete one
+pub const PARTIAL_SUFFIX: &str = ".part";
+
+// Objects at least this large are downloaded by the CLI through a resumable partial file
+pub const RESUMABLE_DOWNLOAD_THRESHOLD: u64 = 100 * 1024 * 1024;
+
+// Attempts at a single large download before giving up; each one resumes from the partial file
+const DOWNLOAD_ATTEMPTS: u32 = 5;
+
+/// Record of files a folder download has completed, kept in the destination folder.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct DownloadManifest {
+    pub prefix: String,
+    #[serde(default)]
+    pub files: BTreeMap<String, ManifestEntry>, // Keyed by object key
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub local_path: String, // Relative to the destination folder
+    pub etag: Option<String>,
+    pub size: u64, // Size of the local file, after any decryption
+    pub sha256: String,
+}
+
+impl DownloadManifest {
+    pub fn load(dest_dir: &Path) -> Self {
+        std::fs::read_to_string(dest_dir.join(MANIFEST_FILE_NAME))
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, dest_dir: &Path) -> Result<()> {
+        // Write then rename so an interrupted save never leaves a truncated manifest
+        let path = dest_dir.join(MANIFEST_FILE_NAME);
+        let tmp_path = dest_dir.join(format!("{}.tmp", MANIFEST_FILE_NAME));
+        let content = serde_json::to_string_pretty(self).context("Failed to serialize manifest")?;
+        std::fs::write(&tmp_path, content).context("Failed to write download manifest")?;
+        std::fs::rename(&tmp_path, &path).context("Failed to write download manifest")?;
+        Ok(())
+    }
+
+    /// True if the entry for `object` still describes the file on disk and the object is unchanged.
+    fn is_complete(&self, dest_dir: &Path, object: &ObjectInfo) -> bool {
+        let Some(entry) = self.files.get(&object.key) else {
+            return false;
+        };
+        if entry.etag.is_some() && entry.etag != object.etag {
+            return false;
+        }
+
+        let path = dest_dir.join(&entry.local_path);
+        match std::fs::metadata(&path) {
+            Ok(metadata) if metadata.len() == entry.size => {
+                integrity::sha256_file(&path).is_ok_and(|hash| hash == entry.sha256)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// A resumable download in progress, recorded in the user's cache folder so resume-all
+/// can find partial files after a crash. Removed once the download completes.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DownloadSession {
+    pub key: String,
+    pub partial: PathBuf,
+    pub expected_size: u64,
+}
+
+impl DownloadSession {
+    fn sessions_dir(client: &R2Client) -> Result<PathBuf> {
+        Ok(client.cache_dir()?.join("downloads"))
+    }
+
+    fn path_for(client: &R2Client, partial: &Path) -> Result<PathBuf> {
+        use sha2::{Digest, Sha256};
+        let hash = Sha256::digest(partial.to_string_lossy().as_bytes());
+        Ok(Self::sessions_dir(client)?.join(format!("{}.json", hex::encode(hash))))
+    }
+
+    fn save(&self, client: &R2Client) -> Result<PathBuf> {
+        let path = Self::path_for(client, &self.partial)?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).context("Failed to create download session folder")?;
+        }
+        std::fs::write(&path, serde_json::to_string_pretty(self)?)
+            .context("Failed to save download session")?;
+        Ok(path)
+    }
+
+    /// Every session saved by `client`'s transfers, with the file it is stored in.
+    /// Unreadable files are skipped.
+    pub fn list_all(client: &R2Client) -> Result<Vec<(PathBuf, Self)>> {
+        let Ok(entries) = std::fs::read_dir(Self::sessions_dir(client)?) else {
+            return Ok(Vec::new());
+        };
+        let mut sessions: Vec<(PathBuf, Self)> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .filter_map(|path| {
+                let content = std::fs::read_to_string(&path).ok()?;
+                Some((path, serde_json::from_str(&content).ok()?))
+            })
+            .collect();
+        sessions.sort_by(|a, b| a.1.key.cmp(&b.1.key));
+        Ok(sessions)
+    }
+
+    /// Where the file goes once complete: the partial path without its suffix.
+    pub fn final_path(&self) -> PathBuf {
+        let partial = self.partial.to_string_lossy();
+        PathBuf::from(partial.strip_suffix(PARTIAL_SUFFIX).unwrap_or(&partial))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct FolderDownloadOptions {
+    pub concurrency: usize,
+    pub decrypt: bool, // Decrypt objects that look encrypted, dropping their .pgp/.gpg extension
+    pub only_keys: Option<HashSet<String>>, // Restrict the download to these keys
+    pub paths: GlobFilter, // Matched against each key relative to the prefix
+}
+
+impl Default for FolderDownloadOptions {
+    fn default() -> Self {
+        Self {
+            concurrency: 4,
+            decrypt: false,
+            only_keys: None,
+            paths: GlobFilter::default(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct FolderDownloadSummary {
+    pub downloaded: Vec<(String, PathBuf)>,
+    pub skipped: Vec<String>, // Already complete according to the manifest
+    pub failed: Vec<(String, String)>,
+}
+
+/// Download every object under `prefix` into `dest_dir`, preserving the key layout
+/// beneath the prefix. Objects are fetched concurrently and completed files are
+/// recorded in `.download-manifest.json`, so re-running after an interruption
+/// skips them and resumes any partially downloaded file.
+pub async fn download_prefix(
+    client: &R2Client,
+    pgp_handler: Option<Arc<PgpHandler>>,
+    prefix: &str,
+    dest_dir: &Path,
+    options: &FolderDownloadOptions,
+    progress: &dyn ProgressReporter,
+) -> Result<FolderDownloadSummary> {
+    std::fs::create_dir_all(dest_dir).context("Failed to create destination folder")?;
+
+    let objects: Vec<ObjectInfo> = client
+        .list_objects_detailed(Some(prefix))
+        .await?
+        .into_iter()
+        // Skip folder placeholder objects
+        .filter(|o| !o.key.ends_with('/'))
+        .filter(|o| {
+            options
+                .only_keys
+                .as_ref()
+                .is_none_or(|keys| keys.contains(&o.key))
+        })
+        .filter(|o| {
+            let relative = transfer::relative_key(&o.key, prefix).trim_start_matches('/');
+            options.paths.matches(relative)
+        })
+        .collect();
+
+    let mut manifest = DownloadManifest::load(dest_dir);
+    manifest.prefix = prefix.to_string();
+    let manifest = Mutex::new(manifest);
+
+    progress.set_length(objects.iter().map(|o| o.size).sum());
+    let transferred = AtomicU64::new(0);
+
+    let (done, pending): (Vec<&ObjectInfo>, Vec<&ObjectInfo>) = objects
+        .iter()
+        .partition(|o| manifest.lock().unwrap().is_complete(dest_dir, o));
+
+    let mut summary = FolderDownloadSummary::default();
+    for object in done {
+        transferred.fetch_add(object.size, Ordering::SeqCst);
+        let path = manifest
+            .lock()
+            .unwrap()
+            .files
+            .get(&object.key)
+            .map(|entry| dest_dir.join(&entry.local_path));
+        progress.item_finished(&ItemOutcome::skipped(
+            "download",
+            &object.key,
+            path,
+            object.size,
+        ));
+        summary.skipped.push(object.key.clone());
+    }
+    progress.set_position(transferred.load(Ordering::SeqCst));
+
+    let results: Vec<(String, Result<PathBuf>)> = stream::iter(pending)
+        .map(|object| {
+            let manifest = &manifest;
+            let transferred = &transferred;
+            let pgp_handler = pgp_handler.as_ref();
+            async move {
+                progress.set_message(transfer::relative_key(&object.key, prefix));
+                let result = download_one(
+                    client,
+                    pgp_handler,
+                    prefix,
+                    dest_dir,
+                    object,
+                    options.decrypt,
+                    manifest,
+                    |bytes| {
+                        let position = transferred.fetch_add(bytes, Ordering::SeqCst) + bytes;
+                        progress.set_position(position);
+                    },
+                )
+                .await;
+                match &result {
+                    Ok(path) => {
+                        progress.item_completed(&object.key);
+                        progress.item_finished(&ItemOutcome::ok(
+                            "download",
+                            &object.key,
+                            Some(path.clone()),
+                            object.size,
+                        ));
+                    }
+                    Err(e) => progress.item_finished(&ItemOutcome::failed(
+                        "download",
+                        &object.key,
+                        None,
+                        &e.to_string(),
+                    )),
+                }
+                (object.key.clone(), result)
+            }
+        })
+        .buffer_unordered(options.concurrency.max(1))
+        .collect()
+        .await;
+
+    for (key, result) in results {
+        match result {
+            Ok(path) => summary.downloaded.push((key, path)),
+            Err(e) => summary.failed.push((key, e.to_string())),
+        }
+    }
+
+    progress.finish("done");
+    Ok(summary)
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn download_one<F>(
+    client: &R2Client,
+    pgp_handler: Option<&Arc<PgpHandler>>,
+    prefix: &str,
+    dest_dir: &Path,
+    object: &ObjectInfo,
+    decrypt: bool,
+    manifest: &Mutex<DownloadManifest>,
+    on_chunk: F,
+) -> Result<PathBuf>
+where
+    F: FnMut(u64),
+{
+    let relative_key = transfer::relative_key(&object.key, prefix).trim_start_matches('/');
+    let relative_path = safe_relative_path(relative_key)?;
+    let target = dest_dir.join(&relative_path);
+    if let Some(parent) = target.parent() {
+        std::fs::create_dir_all(parent).context("Failed to create destination folder")?;
+    }
+
+    let partial = PathBuf::from(format!("{}{}", target.display(), PARTIAL_SUFFIX));
+    let head = client
+        .download_object_to_file(&object.key, &partial, on_chunk)
+        .await?;
+
+    // Isolate corrupt downloads rather than letting them reach the output path
+    let etag = object.etag.as_deref().or(head.etag.as_deref());
+    if let Some(reason) = integrity::verify_download(&partial, object.size, etag)? {
+        let quarantined =
+            integrity::quarantine(dest_dir, &relative_path, &partial, &object.key, &reason)?;
+        return Err(anyhow!(
+            "Integrity check failed ({}), quarantined to {}",
+            reason,
+            quarantined.display()
+        ));
+    }
+
+    // Decrypt per policy: only objects that are actually encrypted, and only if asked to
+    let handler = pgp_handler.filter(|_| decrypt);
+    let encrypted = handler.is_some()
+        && (encryption_filter::is_encrypted_extension(&object.key)
+            || file_looks_encrypted(&partial)?);
+
+    let final_path = match handler {
+        Some(handler) if encrypted => {
+            // Stream straight from the partial file to the output so large objects aren't buffered
+            let decrypted_path =
+                target.with_file_name(transfer::download_file_name(&object.key, None, true));
+            // Decryption is slow CPU work, so it runs on the blocking pool, in parallel
+            // with the other objects' and without holding up the async workers
+            let handler = handler.clone();
+            let (input_path, output_path) = (partial.clone(), decrypted_path.clone());
+            tokio::task::spawn_blocking(move || -> Result<()> {
+                let mut input =
+                    File::open(&input_path).context("Failed to read downloaded file")?;
+                let mut output = BufWriter::new(
+                    File::create(&output_path).context("Failed to write decrypted file")?,
+                );
+                handler.decrypt_to_writer(&mut input, &mut output)?;
+                output.flush().context("Failed to write decrypted file")
+            })
+            .await??;
+            std::fs::remove_file(&partial)?;
+            decrypted_path
+        }
+        _ => {
+            std::fs::rename(&partial, &target)?;
+            target
+        }
+    };
+
+    let size = std::fs::metadata(&final_path)?.len();
+    let entry = ManifestEntry {
+        local_path: final_path
+            .strip_prefix(dest_dir)
+            .unwrap_or(&final_path)
+            .to_string_lossy()
+            .into_owned(),
+        etag: object.etag.clone().or(head.etag),
+        size,
+        sha256: integrity::sha256_file(&final_path)?,
+    };
+
+    let mut manifest = manifest.lock().unwrap();
+    manifest.files.insert(object.key.clone(), entry);
+    manifest.save(dest_dir)?;
+
+    Ok(final_path)
+}
+
+/// Download `key` to `partial`, resuming from whatever an earlier run left there and
+/// retrying dropped connections and server errors with backoff. Fails if the file
+/// doesn't end up `expected_size` bytes long; the partial file is kept, and recorded as a
+/// `DownloadSession`, so a later run or resume-all can pick up where this one stopped.
+pub async fn download_resumable(
+    client: &R2Client,
+    key: &str,
+    partial: &Path,
+    expected_size: u64,
+    progress: &dyn ProgressReporter,
+) -> Result<ObjectHead> {
+    progress.set_length(expected_size);
+    let partial = &std::path::absolute(partial).unwrap_or_else(|_| partial.to_path_buf());
+    // What is left of the object must fit beside whatever an earlier run downloaded
+    let existing = std::fs::metadata(partial).map_or(0, |m| m.len());
+    disk_space::ensure_space(partial, expected_size.saturating_sub(existing))?;
+    let session_path = DownloadSession {
+        key: key.to_string(),
+        partial: partial.clone(),
+        expected_size,
+    }
+    .save(client)
+    .inspect_err(|e| tracing::warn!("Failed to record download session: {:#}", e))
+    .ok();
+    let mut attempt = 1;
+    let head = loop {
+        let mut position = 0;
+        let result = client
+            .download_object_to_file(key, partial, |bytes| {
+                position += bytes;
+                progress.set_position(position);
+            })
+            .await;
+        match result {
+            Ok(head) => break head,
+            // Requests R2 refused won't succeed on a retry
+            Err(e)
+                if R2Error::from_anyhow(&e)
+                    .and_then(R2Error::status)
+                    .is_some_and(|status| status < 500) =>
+            {
+                return Err(e)
+            }
+            Err(e) if attempt < DOWNLOAD_ATTEMPTS => {
+                tracing::warn!(
+                    "Download of {} interrupted ({:#}), resuming (attempt {} of {})",
+                    key,
+                    e,
+                    attempt + 1,
+                    DOWNLOAD_ATTEMPTS
+                );
+                client.start_retry().map_err(|budget| e.context(budget))?;
+                tokio::time::sleep(Duration::from_secs(1 << attempt)).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e.context("Download interrupted; re-run to resume")),
+        }
+    };
+
+    let size = std::fs::metadata(partial)?.len();
+    if size != expected_size {
+        return Err(anyhow!(
+            "Downloaded {} bytes of {}, expected {}; re-run to resume",
+            size,
+            key,
+            expected_size
+        ));
+    }
+    if let Some(session_path) = session_path {
+        let _ = std::fs::remove_file(session_path);
+    }
+    Ok(head)
+}
+
+/// Turn a key suffix into a relative path, refusing anything that would escape the destination.
+pub fn safe_relative_path(relative_key: &str) -> Result<PathBuf> {
+    let path = PathBuf::from(relative_key);
+    if path
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_)))
+    {
+        return Err(anyhow!(
+            "Refusing to write outside the destination: {}",
+            relative_key
+        ));
+    }
+    Ok(path)
+}
+
+pub fn file_looks_encrypted(path: &Path) -> Result<bool> {
+    let mut header = Vec::with_capacity(64);
+    File::open(path)?.take(64).read_to_end(&mut header)?;
+    Ok(PgpHandler::is_pgp_encrypted(&header))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_r2::MockR2;
+    use crate::progress::ConsoleProgress;
+    use crate::test_support::{self, TempDir};
+
+    #[tokio::test]
+    async fn second_run_skips_completed_files() {
+        let mock = MockR2::start().await;
+        mock.put("photos/a.jpg", b"first photo");
+        mock.put("photos/2024/b.jpg", b"second photo");
+        let client = mock.client().await;
+        let dest = TempDir::new();
+        let options = FolderDownloadOptions::default();
+
+        let first = download_prefix(
+            &client,
+            None,
+            "photos/",
+            dest.path(),
+            &options,
+            &ConsoleProgress::new(0),
+        )
+        .await
+        .unwrap();
+        assert_eq!(first.downloaded.len(), 2);
+
+        // As if the first run had been interrupted before finishing b.jpg
+        std::fs::remove_file(dest.path().join("2024/b.jpg")).unwrap();
+        let second = download_prefix(
+            &client,
+            None,
+            "photos/",
+            dest.path(),
+            &options,
+            &ConsoleProgress::new(0),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(second.skipped, ["photos/a.jpg"]);
+        let downloaded: Vec<&str> = second.downloaded.iter().map(|(k, _)| k.as_str()).collect();
+        assert_eq!(downloaded, ["photos/2024/b.jpg"]);
+        assert_eq!(
+            std::fs::read(dest.path().join("2024/b.jpg")).unwrap(),
+            b"second photo"
+        );
+    }
+
+    #[tokio::test]
+    async fn corrupt_objects_are_quarantined_and_logged() {
+        let mock = MockR2::start().await;
+        mock.put("docs/good.txt", b"intact");
+        mock.put("docs/sub/bad.txt", b"will be corrupted");
+        mock.corrupt("docs/sub/bad.txt");
+        let client = mock.client().await;
+        let dest = TempDir::new();
+
+        let summary = download_prefix(
+            &client,
+            None,
+            "docs/",
+            dest.path(),
+            &FolderDownloadOptions::default(),
+            &ConsoleProgress::new(0),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(summary.downloaded.len(), 1);
+        assert_eq!(summary.failed.len(), 1);
+        assert_eq!(summary.failed[0].0, "docs/sub/bad.txt");
+        assert!(!dest.path().join("sub/bad.txt").exists());
+        let quarantined = dest
+            .path()
+            .join(integrity::QUARANTINE_DIR)
+            .join("sub/bad.txt");
+        assert!(quarantined.exists());
+
+        let log = std::fs::read_to_string(dest.path().join(integrity::FAILURE_LOG)).unwrap();
+        assert_eq!(log.lines().count(), 1);
+        assert!(
+            log.contains("docs/sub/bad.txt\tchecksum mismatch"),
+            "{}",
+            log
+        );
+        // Not recorded as complete, so the next run tries it again
+        assert!(!DownloadManifest::load(dest.path())
+            .files
+            .contains_key("docs/sub/bad.txt"));
+    }
+
+    #[tokio::test]
+    async fn partial_downloads_resume_with_range_requests() {
+        let data: Vec<u8> = (0..300_000u32).map(|i| (i % 241) as u8).collect();
+        let mock = MockR2::start().await;
+        mock.put("backups/db.dump", &data);
+        let dest = TempDir::new();
+        // What an interrupted earlier run left behind
+        let partial = dest.write("db.dump.partial", &data[..100_000]);
+        let client = mock.client().await;
+        let etag = client.head_object("backups/db.dump").await.unwrap().etag;
+        dest.write("db.dump.partial.etag", etag.unwrap().as_bytes());
+
+        download_resumable(
+            &client,
+            "backups/db.dump",
+            &partial,
+            data.len() as u64,
+            &ConsoleProgress::new(0),
+        )
+        .await
+        .unwrap();
+        assert!(std::fs::read(&partial).unwrap() == data);
+        assert_eq!(client.metrics().snapshot().bytes_downloaded, 200_000);
+        let sessions = DownloadSession::list_all(&client).unwrap();
+        assert!(!sessions.iter().any(|(_, s)| s.partial == partial));
+    }
+
+    #[tokio::test]
+    async fn partials_of_a_replaced_object_start_over() {
+        let old: Vec<u8> = (0..300_000u32).map(|i| (i % 241) as u8).collect();
+        let new: Vec<u8> = (0..300_000u32).map(|i| (i % 239) as u8).collect();
+        let mock = MockR2::start().await;
+        mock.put("backups/db.dump", &old);
+        let client = mock.client().await;
+        let old_etag = client.head_object("backups/db.dump").await.unwrap().etag;
+        // Replaced by an object of the same size after the earlier run was cut off
+        mock.put("backups/db.dump", &new);
+        let dest = TempDir::new();
+        let partial = dest.write("db.dump.partial", &old[..100_000]);
+        dest.write("db.dump.partial.etag", old_etag.unwrap().as_bytes());
+
+        download_resumable(
+            &client,
+            "backups/db.dump",
+            &partial,
+            new.len() as u64,
+            &ConsoleProgress::new(0),
+        )
+        .await
+        .unwrap();
+        assert!(std::fs::read(&partial).unwrap() == new);
+        assert!(!dest.path().join("db.dump.partial.etag").exists());
+
+        // Without a recorded ETag nothing says the partial bytes are still current
+        let partial = dest.write("other.partial", &new[..100_000]);
+        client
+            .download_object_to_file("backups/db.dump", &partial, |_| {})
+            .await
+            .unwrap();
+        assert!(std::fs::read(&partial).unwrap() == new);
+        assert_eq!(client.metrics().snapshot().bytes_downloaded, 600_000);
+    }
+
+    #[tokio::test]
+    async fn refused_downloads_keep_their_session() {
+        let mock = MockR2::start().await;
+        mock.put("backups/db.dump", b"0123456789");
+        mock.deny("GET");
+        let dest = TempDir::new();
+        let partial = dest.write("db.dump.partial", b"01234");
+        let client = mock.client().await;
+
+        let result = download_resumable(
+            &client,
+            "backups/db.dump",
+            &partial,
+            10,
+            &ConsoleProgress::new(0),
+        )
+        .await;
+        assert!(result.is_err());
+        assert_eq!(std::fs::read(&partial).unwrap(), b"01234");
+        // Recorded, so resume-all can pick it up later
+        let (session_path, session) = DownloadSession::list_all(&client)
+            .unwrap()
+            .into_iter()
+            .find(|(_, s)| s.partial == partial)
+            .unwrap();
+        assert_eq!(session.key, "backups/db.dump");
+        assert_eq!(session.expected_size, 10);
+        std::fs::remove_file(session_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn decrypted_downloads_drop_each_encrypted_extension() {
+        let key = test_support::key("Team");
+        let handler = test_support::handler(&[&key], &[&key]);
+        let mock = MockR2::start().await;
+        for ext in ["pgp", "gpg", "asc"] {
+            let plaintext = format!("encrypted as .{}", ext);
+            mock.put(
+                &format!("vault/{}/notes.txt.{}", ext, ext),
+                &handler.encrypt(plaintext.as_bytes()).unwrap(),
+            );
+        }
+        let client = mock.client().await;
+        let dest = TempDir::new();
+        let options = FolderDownloadOptions {
+            decrypt: true,
+            ..Default::default()
+        };
+
+        let summary = download_prefix(
+            &client,
+            Some(Arc::new(handler)),
+            "vault/",
+            dest.path(),
+            &options,
+            &ConsoleProgress::new(0),
+        )
+        .await
+        .unwrap();
+        assert_eq!(summary.downloaded.len(), 3);
+        for ext in ["pgp", "gpg", "asc"] {
+            let dir = dest.path().join(ext);
+            assert_eq!(
+                std::fs::read_to_string(dir.join("notes.txt")).unwrap(),
+                format!("encrypted as .{}", ext)
+            );
+            assert!(!dir.join(format!("notes.txt.{}", ext)).exists());
+        }
+    }
+
+    /// A report destination the test can read back while the progress owns it.
+    #[derive(Clone, Default)]
+    struct Report(std::sync::Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Report {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn item_reports_have_a_json_line_per_object() {
+        let mock = MockR2::start().await;
+        mock.put("batch/done.txt", b"already here");
+        let client = mock.client().await;
+        let dest = TempDir::new();
+        let options = FolderDownloadOptions::default();
+        download_prefix(
+            &client,
+            None,
+            "batch/",
+            dest.path(),
+            &options,
+            &ConsoleProgress::new(0),
+        )
+        .await
+        .unwrap();
+
+        mock.put("batch/new.txt", b"fresh");
+        mock.put("batch/bad.txt", b"will be corrupted");
+        mock.corrupt("batch/bad.txt");
+        let report = Report::default();
+        let progress = ConsoleProgress::new(0).with_item_report_to(report.clone());
+        download_prefix(&client, None, "batch/", dest.path(), &options, &progress)
+            .await
+            .unwrap();
+
+        let report = String::from_utf8(report.0.lock().unwrap().clone()).unwrap();
+        let mut lines: Vec<serde_json::Value> = report
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        lines.sort_by_key(|line| line["key"].as_str().unwrap().to_string());
+        assert_eq!(lines.len(), 3);
+
+        let (bad, done, new) = (&lines[0], &lines[1], &lines[2]);
+        assert_eq!(bad["key"], "batch/bad.txt");
+        assert_eq!(bad["result"], "failed");
+        assert!(bad["path"].is_null());
+        assert!(bad["error"]
+            .as_str()
+            .unwrap()
+            .contains("Integrity check failed"));
+        assert_eq!(done["result"], "skipped");
+        assert_eq!(done["bytes"], 12);
+        for (line, file) in [(done, "done.txt"), (new, "new.txt")] {
+            assert_eq!(line["action"], "download");
+            assert_eq!(
+                line["path"].as_str().unwrap(),
+                dest.path().join(file).to_str().unwrap()
+            );
+            assert!(line.get("error").is_none());
+        }
+        assert_eq!(new["result"], "ok");
+        assert_eq!(new["bytes"], 5);
+    }
+}
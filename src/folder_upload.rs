@@ -0,0 +1,391 @@
+use crate::config::CompletionHook;
+use crate::crypto::PgpHandler;
+use crate::encryption_filter;
+use crate::error;
+use crate::glob_filter::GlobFilter;
+use crate::hooks;
+use crate::memory_budget;
+use crate::multipart_upload::{
+    MultipartUploader, DEFAULT_PART_CONCURRENCY, DEFAULT_PART_SIZE, MULTIPART_THRESHOLD,
+};
+use crate::progress::{format_size, ItemOutcome, ProgressReporter};
+use crate::r2_client::{R2Client, UploadOptions};
+use crate::transfer;
+use anyhow::{anyhow, Context, Result};
+use bytes::Bytes;
+use std::path::Path;
+use std::sync::Arc;
+
+#[derive(Clone)]
+pub struct FolderUploadOptions {
+    pub encrypt: bool,
+    pub skip_unchanged: bool, // Skip files whose size and mtime match their object's stamp
+    pub dry_run: bool,        // Upload nothing and run no hook; only list what would be uploaded
+    pub paths: GlobFilter,    // Matched against each file's path relative to the folder
+    pub upload_options: UploadOptions, // Applied to every object
+    pub hook: Option<CompletionHook>,
+    pub part_concurrency: usize, // Parts sent at once for files large enough to go up in parts
+}
+
+impl Default for FolderUploadOptions {
+    fn default() -> Self {
+        Self {
+            encrypt: false,
+            skip_unchanged: false,
+            dry_run: false,
+            paths: GlobFilter::default(),
+            upload_options: UploadOptions::default(),
+            hook: None,
+            part_concurrency: DEFAULT_PART_CONCURRENCY,
+        }
+    }
+}
+
+/// What a folder upload stored: the object keys and their plaintext bytes, the keys
+/// left alone because they were already up to date, and the files that failed.
+#[derive(Debug, Default)]
+pub struct FolderUploadSummary {
+    pub uploaded: Vec<String>,
+    pub skipped: Vec<String>,
+    pub failed: Vec<(String, String)>,  // Relative path and why
+    pub planned: Vec<(String, String)>, // Dry run: relative path and key that would be uploaded
+    pub bytes: u64,
+}
+
+/// Reports one file's multipart upload as a stretch of the folder's aggregate bar.
+struct OffsetProgress<'a> {
+    inner: &'a dyn ProgressReporter,
+    base: u64, // Bytes of the folder already done when this file started
+}
+
+impl ProgressReporter for OffsetProgress<'_> {
+    fn set_length(&self, _length: u64) {}
+
+    fn set_position(&self, position: u64) {
+        self.inner.set_position(self.base + position);
+    }
+
+    fn set_message(&self, message: &str) {
+        self.inner.set_message(message);
+    }
+
+    fn finish(&self, _message: &str) {}
+}
+
+/// Upload every file under `folder` that the options' filter lets through to
+/// `prefix/<relative path>`, reporting one aggregate progress bar across the total bytes
+/// of all files. A dry run counts the files that would be uploaded as uploaded. Files
+/// that fail are listed in the summary rather than stopping the others.
+pub async fn upload_folder(
+    r2_client: &R2Client,
+    pgp_handler: &PgpHandler,
+    folder: &Path,
+    prefix: &str,
+    options: &FolderUploadOptions,
+    progress: Arc<dyn ProgressReporter>,
+) -> Result<FolderUploadSummary> {
+    let encrypt = options.encrypt;
+    if encrypt && pgp_handler.recipient_count() == 0 {
+        return Err(anyhow!(
+            "No public keys loaded for encryption. Please configure team keys."
+        ));
+    }
+
+    let mut files = transfer::scan_directory(folder);
+    files.retain(|file| options.paths.matches(&file.relative_path));
+    let mut total_bytes: u64 = files.iter().map(|f| f.size).sum();
+    tracing::info!(
+        "Uploading {} files ({}) from {}",
+        files.len(),
+        format_size(total_bytes),
+        folder.display()
+    );
+
+    progress.set_length(total_bytes);
+    let mut completed_bytes = 0u64;
+    let mut summary = FolderUploadSummary::default();
+
+    for file in &files {
+        progress.set_message(&file.relative_path);
+        let mut key = transfer::join_key(prefix, &file.relative_path);
+        if encrypt {
+            key = encryption_filter::with_encrypted_extension(&key);
+        }
+
+        if options.skip_unchanged {
+            match r2_client.head_object(&key).await {
+                Ok(head) if head.source_stamp() == Some((file.size, file.modified)) => {
+                    completed_bytes += file.size;
+                    progress.set_position(completed_bytes);
+                    progress.item_finished(&ItemOutcome::skipped(
+                        "upload",
+                        &key,
+                        Some(file.path.clone()),
+                        file.size,
+                    ));
+                    summary.skipped.push(key);
+                    continue;
+                }
+                Ok(_) => {}
+                Err(e) if error::is_not_found(&e) => {}
+                Err(e) => {
+                    completed_bytes += file.size;
+                    progress.set_position(completed_bytes);
+                    tracing::warn!("Failed to check {}: {:#}", key, e);
+                    progress.item_finished(&ItemOutcome::failed(
+                        "upload",
+                        &key,
+                        Some(file.path.clone()),
+                        &format!("{:#}", e),
+                    ));
+                    summary
+                        .failed
+                        .push((file.relative_path.clone(), format!("{:#}", e)));
+                    continue;
+                }
+            }
+        }
+
+        if options.dry_run {
+            completed_bytes += file.size;
+            summary.bytes += file.size;
+            progress.set_position(completed_bytes);
+            summary
+                .planned
+                .push((file.relative_path.clone(), key.clone()));
+            summary.uploaded.push(key);
+            continue;
+        }
+
+        let result = async {
+            // The file may have changed size since the scan; keep the total honest
+            let size = std::fs::metadata(&file.path)
+                .context("Failed to read input file")?
+                .len();
+            if size != file.size {
+                total_bytes = total_bytes - file.size + size;
+                progress.set_length(total_bytes);
+            }
+
+            let file_name = transfer::upload_file_name(&file.path);
+            let upload_options = options
+                .upload_options
+                .clone()
+                .with_original_filename(&file_name)
+                .with_encrypted(encrypt)
+                .with_content_type_for(&file_name, encrypt)
+                .with_source_stamp(size, file.modified);
+
+            // Large files go up in parts, as a single-file upload does
+            if size >= MULTIPART_THRESHOLD {
+                let reporter = OffsetProgress {
+                    inner: progress.as_ref(),
+                    base: completed_bytes,
+                };
+                let stored = MultipartUploader::upload_file(
+                    r2_client,
+                    &file.path,
+                    &key,
+                    &upload_options,
+                    DEFAULT_PART_SIZE,
+                    options.part_concurrency,
+                    encrypt.then_some(pgp_handler),
+                    &reporter,
+                )
+                .await?;
+                return Ok::<(u64, Option<String>), anyhow::Error>((stored, None));
+            }
+
+            // Scale transfer progress to plaintext bytes so encrypted files count their real size
+            let reporter = progress.clone();
+            let base = completed_bytes;
+            let on_progress = move |sent: u64, body_total: u64| {
+                let scaled = size as u128 * sent as u128 / body_total.max(1) as u128;
+                reporter.set_position(base + scaled as u64);
+            };
+
+            let etag = if encrypt {
+                // A PGP message is built in memory, so hold the plaintext and ciphertext
+                // against the memory budget while this file is being sent
+                let _reservation = memory_budget::reserve(2 * size as usize).await;
+                let data = std::fs::read(&file.path).context("Failed to read input file")?;
+                let body = Bytes::from(pgp_handler.encrypt_with_filename(&data, &file_name)?);
+                drop(data);
+                r2_client
+                    .upload_object_with_progress(&key, body, &upload_options, on_progress)
+                    .await?
+            } else {
+                let reader = tokio::fs::File::open(&file.path)
+                    .await
+                    .context("Failed to open input file")?;
+                r2_client
+                    .upload_object_streaming_with_progress(
+                        &key,
+                        reader,
+                        size,
+                        &upload_options,
+                        on_progress,
+                    )
+                    .await?
+            };
+
+            Ok((size, etag))
+        }
+        .await;
+
+        if let Some(hook) = options.hook.as_ref().filter(|hook| hook.scope.each_file()) {
+            let event = match &result {
+                Ok((size, etag)) => hooks::CompletionEvent::succeeded(&key, *size, etag.clone()),
+                Err(e) => hooks::CompletionEvent::failed(&key, &format!("{:#}", e)),
+            };
+            hooks::on_complete(hook, &event).await;
+        }
+
+        match result {
+            Ok((size, _)) => {
+                completed_bytes += size;
+                summary.bytes += size;
+                progress.item_completed(&key);
+                progress.item_finished(&ItemOutcome::ok(
+                    "upload",
+                    &key,
+                    Some(file.path.clone()),
+                    size,
+                ));
+                summary.uploaded.push(key);
+            }
+            Err(e) => {
+                completed_bytes += file.size;
+                tracing::warn!("Failed to upload {}: {:#}", file.relative_path, e);
+                progress.item_finished(&ItemOutcome::failed(
+                    "upload",
+                    &key,
+                    Some(file.path.clone()),
+                    &format!("{:#}", e),
+                ));
+                summary
+                    .failed
+                    .push((file.relative_path.clone(), format!("{:#}", e)));
+            }
+        }
+        progress.set_position(completed_bytes);
+    }
+
+    progress.finish("done");
+
+    // Nothing was stored by a dry run, so there is nothing to tell the hook
+    let batch_hook = options.hook.as_ref().filter(|hook| hook.scope.batch());
+    if let (Some(hook), false) = (batch_hook, options.dry_run) {
+        let failed = summary.failed.len();
+        let event = hooks::CompletionEvent {
+            key: prefix.to_string(),
+            bytes: summary.bytes,
+            success: failed == 0,
+            etag: None,
+            error: (failed > 0).then(|| format!("{} files failed to upload", failed)),
+            files: Some(files.len()),
+        };
+        hooks::on_complete(hook, &event).await;
+    }
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_r2::MockR2;
+    use crate::progress::ConsoleProgress;
+    use crate::test_support::TempDir;
+
+    #[tokio::test]
+    async fn folder_upload_progress_adds_up_to_every_file() {
+        let folder = TempDir::new();
+        folder.write("a.txt", &[b'a'; 1000]);
+        folder.write("nested/b.bin", &[b'b'; 250_000]);
+        folder.write("nested/c.txt", b"");
+        let mock = MockR2::start().await;
+        let client = mock.client().await;
+        let progress = Arc::new(ConsoleProgress::new(0));
+
+        let summary = upload_folder(
+            &client,
+            &PgpHandler::new(),
+            folder.path(),
+            "backup",
+            &FolderUploadOptions::default(),
+            progress.clone(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(progress.length(), 251_000);
+        assert_eq!(summary.bytes, progress.length());
+        assert_eq!(progress.position(), progress.length());
+        assert_eq!(progress.completed_items().len(), 3);
+        assert_eq!(
+            mock.keys(),
+            ["backup/a.txt", "backup/nested/b.bin", "backup/nested/c.txt"]
+        );
+    }
+
+    #[tokio::test]
+    async fn large_files_go_up_in_parts_and_small_ones_are_streamed() {
+        let large: Vec<u8> = (0..MULTIPART_THRESHOLD + 17)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let folder = TempDir::new();
+        folder.write("large.bin", &large);
+        folder.write("small.txt", b"small file");
+        let mock = MockR2::start().await;
+        let client = mock.client().await;
+        let progress = Arc::new(ConsoleProgress::new(0));
+
+        let summary = upload_folder(
+            &client,
+            &PgpHandler::new(),
+            folder.path(),
+            "backup",
+            &FolderUploadOptions::default(),
+            progress.clone(),
+        )
+        .await
+        .unwrap();
+
+        assert!(summary.failed.is_empty());
+        assert_eq!(summary.bytes, large.len() as u64 + 10);
+        assert_eq!(progress.position(), progress.length());
+        assert_eq!(mock.completed_uploads(), ["backup/large.bin"]);
+        assert!(mock.object("backup/large.bin").unwrap().data == large);
+        assert_eq!(mock.object("backup/small.txt").unwrap().data, b"small file");
+    }
+
+    #[tokio::test]
+    async fn encrypted_folder_uploads_decrypt_back() {
+        let key = crate::test_support::key("Alice");
+        let folder = TempDir::new();
+        folder.write("notes.txt", b"encrypted notes");
+        let mock = MockR2::start().await;
+        let client = mock.client().await;
+
+        let options = FolderUploadOptions {
+            encrypt: true,
+            ..Default::default()
+        };
+        let summary = upload_folder(
+            &client,
+            &crate::test_support::handler(&[&key], &[]),
+            folder.path(),
+            "backup",
+            &options,
+            Arc::new(ConsoleProgress::new(0)),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(summary.uploaded, ["backup/notes.txt.pgp"]);
+        let data = mock.object("backup/notes.txt.pgp").unwrap().data;
+        let handler = crate::test_support::handler(&[], &[&key]);
+        assert_eq!(handler.decrypt(&data).unwrap(), b"encrypted notes");
+    }
+}
@@ -0,0 +1,126 @@
+use anyhow::{anyhow, Result};
+use regex::RegexSet;
+
+/// Include and exclude glob patterns, matched against '/'-separated relative paths and
+/// object keys. A path passes if it matches any include pattern (or there are none) and
+/// no exclude pattern; exclude wins when both match.
+///
+/// Patterns follow the usual glob syntax: `?` matches one character, `*` any run of
+/// characters including `/` (so `*.pdf` finds PDFs in every folder), `**/` any number of
+/// leading folders, `[abc]` / `[!abc]` a character class and `{a,b}` alternatives.
+#[derive(Clone, Debug, Default)]
+pub struct GlobFilter {
+    include: Option<RegexSet>,
+    exclude: Option<RegexSet>,
+}
+
+impl GlobFilter {
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Self> {
+        Ok(Self {
+            include: compile(include)?,
+            exclude: compile(exclude)?,
+        })
+    }
+
+    /// Parse a space-separated list of patterns, where a leading `!` marks an exclude,
+    /// e.g. `*.pdf *.docx !drafts/**`.
+    pub fn parse(patterns: &str) -> Result<Self> {
+        let (exclude, include): (Vec<&str>, Vec<&str>) = patterns
+            .split_whitespace()
+            .partition(|pattern| pattern.starts_with('!'));
+        let include: Vec<String> = include.into_iter().map(str::to_string).collect();
+        let exclude: Vec<String> = exclude
+            .into_iter()
+            .map(|pattern| pattern[1..].to_string())
+            .filter(|pattern| !pattern.is_empty())
+            .collect();
+        Self::new(&include, &exclude)
+    }
+
+    /// True when no patterns were given, so every path passes.
+    pub fn is_empty(&self) -> bool {
+        self.include.is_none() && self.exclude.is_none()
+    }
+
+    pub fn matches(&self, path: &str) -> bool {
+        if self.exclude.as_ref().is_some_and(|set| set.is_match(path)) {
+            return false;
+        }
+        self.include.as_ref().is_none_or(|set| set.is_match(path))
+    }
+}
+
+fn compile(patterns: &[String]) -> Result<Option<RegexSet>> {
+    if patterns.is_empty() {
+        return Ok(None);
+    }
+    let regexes = patterns
+        .iter()
+        .map(|pattern| glob_to_regex(pattern))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Some(RegexSet::new(regexes)?))
+}
+
+/// Translate a glob into an anchored regular expression.
+fn glob_to_regex(glob: &str) -> Result<String> {
+    let invalid = |reason: &str| anyhow!("Invalid pattern '{}': {}", glob, reason);
+    let mut regex = String::from("^");
+    let mut chars = glob.chars().peekable();
+    let mut braces = 0;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    regex.push_str("(?:.*/)?");
+                } else {
+                    regex.push_str(".*");
+                }
+            }
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            '[' => {
+                regex.push('[');
+                if matches!(chars.peek(), Some('!') | Some('^')) {
+                    chars.next();
+                    regex.push('^');
+                }
+                // A ']' straight after the opening bracket is part of the class
+                if chars.peek() == Some(&']') {
+                    chars.next();
+                    regex.push_str("\\]");
+                }
+                loop {
+                    match chars.next() {
+                        Some(']') => break,
+                        Some(c @ ('\\' | '[' | '&' | '~')) => {
+                            regex.push('\\');
+                            regex.push(c);
+                        }
+                        Some(c) => regex.push(c),
+                        None => return Err(invalid("unclosed '['")),
+                    }
+                }
+                regex.push(']');
+            }
+            '{' => {
+                braces += 1;
+                regex.push_str("(?:");
+            }
+            '}' if braces > 0 => {
+                braces -= 1;
+                regex.push(')');
+            }
+            ',' if braces > 0 => regex.push('|'),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    if braces > 0 {
+        return Err(invalid("unclosed '{'"));
+    }
+
+    regex.push('$');
+    Ok(regex)
+}
@@ -1,6 +1,15 @@
+use super::console::{ConsolePanel, LogBuffer};
+use super::preview::PreviewCache;
 use super::tabs::{BucketTab, ConfigTab, DownloadTab, UploadTab};
 use eframe::egui;
-use rust_r2::{config::Config, crypto::PgpHandler, r2_client::R2Client};
+use rust_r2::{
+    config::Config,
+    crypto::{find_keyring_files, PgpHandler},
+    error::is_connection_error,
+    progress::format_size,
+    r2_client::R2Client,
+    trust_store::{KeyTrust, TrustStore},
+};
 use std::sync::{Arc, Mutex};
 use tokio::runtime::Runtime;
 
@@ -11,6 +20,9 @@ pub struct AppState {
     pub pgp_handler: Arc<Mutex<PgpHandler>>,
     pub is_connected: bool,
     pub status_message: String,
+    pub log_buffer: Arc<LogBuffer>,
+    pub unverified_keys: usize, // Loaded public keys awaiting a trust decision
+    pub preview_cache: Arc<Mutex<PreviewCache>>,
 }
 
 impl Default for AppState {
@@ -21,10 +33,78 @@ impl Default for AppState {
             pgp_handler: Arc::new(Mutex::new(PgpHandler::new())),
             is_connected: false,
             status_message: "Ready".to_string(),
+            log_buffer: Arc::new(LogBuffer::new()),
+            unverified_keys: 0,
+            preview_cache: Arc::new(Mutex::new(PreviewCache::default())),
         }
     }
 }
 
+/// Run `operation` with the connected client. If it fails at the network level, usually
+/// because pooled connections died while the app sat idle, rebuild the client from the
+/// stored config and retry once. Errors from R2 itself, such as bad credentials, are
+/// returned as they are.
+pub async fn with_reconnect<T, F, Fut>(
+    state: &Arc<Mutex<AppState>>,
+    operation: F,
+) -> anyhow::Result<T>
+where
+    F: Fn(Arc<R2Client>) -> Fut,
+    Fut: std::future::Future<Output = anyhow::Result<T>>,
+{
+    with_reconnect_using(state, operation, connect_from_config).await
+}
+
+/// Build a fresh client from the stored config.
+async fn connect_from_config(config: Config) -> anyhow::Result<R2Client> {
+    let client_config = config.client_config();
+    let r2 = config.r2;
+    R2Client::new(
+        r2.access_key_id,
+        r2.secret_access_key,
+        r2.account_id,
+        r2.bucket_name,
+    )
+    .await?
+    .with_client_config(client_config)
+}
+
+/// `with_reconnect`, rebuilding the client with `connect`.
+async fn with_reconnect_using<T, F, Fut, C, CFut>(
+    state: &Arc<Mutex<AppState>>,
+    operation: F,
+    connect: C,
+) -> anyhow::Result<T>
+where
+    F: Fn(Arc<R2Client>) -> Fut,
+    Fut: std::future::Future<Output = anyhow::Result<T>>,
+    C: FnOnce(Config) -> CFut,
+    CFut: std::future::Future<Output = anyhow::Result<R2Client>>,
+{
+    let client = state
+        .lock()
+        .unwrap()
+        .r2_client
+        .clone()
+        .ok_or_else(|| anyhow::anyhow!("No R2 client available"))?;
+
+    match operation(client).await {
+        Err(e) if is_connection_error(&e) => {
+            tracing::warn!("Connection to R2 failed ({:#}), reconnecting", e);
+            let config = state.lock().unwrap().config.clone();
+            let client = Arc::new(connect(config).await?);
+            {
+                let mut state = state.lock().unwrap();
+                state.r2_client = Some(client.clone());
+                state.status_message = "Reconnected to R2".to_string();
+            }
+            tracing::info!("Reconnected to R2, retrying");
+            operation(client).await
+        }
+        result => result,
+    }
+}
+
 #[derive(PartialEq)]
 enum Tab {
     Config,
@@ -42,19 +122,28 @@ pub struct R2App {
     upload_tab: UploadTab,
     download_tab: DownloadTab,
     bucket_tab: BucketTab,
+    console: ConsolePanel,
+    show_console: bool,
+    show_stats: bool,
+    trust_store: Option<TrustStore>,
 }
 
 impl R2App {
-    pub fn new(_cc: &eframe::CreationContext<'_>) -> Self {
-        let mut app_state = AppState::default();
+    pub fn new(_cc: &eframe::CreationContext<'_>, log_buffer: Arc<LogBuffer>) -> Self {
+        let mut app_state = AppState {
+            log_buffer: log_buffer.clone(),
+            ..Default::default()
+        };
         
-        // Try to auto-load config.json from current directory
-        let config_path = std::path::Path::new("config.json");
-        if config_path.exists() {
+        // Try to auto-load config.json or config.toml from current directory
+        let config_path = rust_r2::config::default_config_path();
+        if let Some(config_path) = &config_path {
             if let Ok(config) = Config::from_file(config_path) {
-                // Auto-loaded config.json from current directory
+                rust_r2::encryption_filter::set_encrypted_extensions(
+                    &config.pgp.encrypted_extensions,
+                );
                 app_state.config = config;
-                app_state.status_message = "Auto-loaded config.json".to_string();
+                app_state.status_message = format!("Auto-loaded {}", config_path.display());
             }
         }
         
@@ -64,24 +153,24 @@ impl R2App {
         let mut config_tab = ConfigTab::new(state.clone(), runtime.clone());
         
         // If we loaded a config, update the ConfigTab and try to auto-load keyrings
-        if config_path.exists() {
+        if config_path.is_some() {
             config_tab.load_from_current_config();
             
-            // Look for keyring files in current directory
-            let keyring_extensions = vec!["asc", "gpg", "pgp", "key"];
+            // Look for keyring files in the configured keyring folder, or the current directory
+            let keyring_dir = state
+                .lock()
+                .unwrap()
+                .config
+                .pgp
+                .keyring_dir
+                .clone()
+                .unwrap_or_else(|| ".".to_string());
             let mut keyring_loaded = false;
-            
-            for ext in keyring_extensions {
-                for entry in std::fs::read_dir(".").unwrap_or_else(|_| std::fs::read_dir("/dev/null").unwrap()) {
-                    if let Ok(entry) = entry {
-                        let path = entry.path();
-                        if path.extension().and_then(|s| s.to_str()) == Some(ext) {
-                            // Found keyring file
-                            if config_tab.try_load_keyring(&path) {
-                                keyring_loaded = true;
-                            }
-                        }
-                    }
+
+            for path in find_keyring_files(std::path::Path::new(&keyring_dir)).unwrap_or_default() {
+                // Found keyring file
+                if config_tab.try_load_keyring(&path) {
+                    keyring_loaded = true;
                 }
             }
             
@@ -101,8 +190,137 @@ impl R2App {
             upload_tab: UploadTab::new(state.clone(), runtime.clone()),
             download_tab: DownloadTab::new(state.clone(), runtime.clone()),
             bucket_tab: BucketTab::new(state.clone(), runtime.clone()),
+            console: ConsolePanel::new(log_buffer),
+            show_console: false,
+            show_stats: false,
+            trust_store: TrustStore::open_default()
+                .map_err(|e| tracing::warn!("Key trust store unavailable: {:#}", e))
+                .ok(),
         }
     }
+
+    /// Requests and bytes moved since connecting, to help estimate R2 costs.
+    fn show_stats_window(&mut self, ctx: &egui::Context) {
+        let client = self.state.lock().unwrap().r2_client.clone();
+        egui::Window::new("📊 Transfer Stats")
+            .open(&mut self.show_stats)
+            .resizable(false)
+            .show(ctx, |ui| {
+                let Some(client) = client else {
+                    ui.label("Not connected");
+                    return;
+                };
+                let stats = client.metrics().snapshot();
+                egui::Grid::new("stats_grid").num_columns(2).show(ui, |ui| {
+                    ui.label("Class A (writes, listings)");
+                    ui.label(stats.class_a().to_string());
+                    ui.end_row();
+                    ui.label("Class B (reads)");
+                    ui.label(stats.class_b().to_string());
+                    ui.end_row();
+                    ui.label("Deletes");
+                    ui.label(stats.delete.to_string());
+                    ui.end_row();
+                    ui.label("Retries");
+                    ui.label(stats.retries.to_string());
+                    ui.end_row();
+                    ui.label("Uploaded");
+                    ui.label(format_size(stats.bytes_uploaded));
+                    ui.end_row();
+                    ui.label("Downloaded");
+                    ui.label(format_size(stats.bytes_downloaded));
+                    ui.end_row();
+                });
+                ui.small("Counted since connecting");
+            });
+        // Transfers update the counters from background threads
+        ctx.request_repaint_after(std::time::Duration::from_millis(500));
+    }
+
+    /// Ask about loaded public keys the trust store hasn't seen, and drop rejected ones.
+    fn show_key_trust_banner(&mut self, ctx: &egui::Context) {
+        let Some(store) = self.trust_store.as_mut() else {
+            return;
+        };
+        let pgp_handler = self.state.lock().unwrap().pgp_handler.clone();
+        let keys = pgp_handler.lock().unwrap().get_loaded_keys().to_vec();
+
+        let mut pending = Vec::new();
+        for key in keys {
+            match store.check(&key) {
+                KeyTrust::Trusted => {}
+                KeyTrust::Rejected => {
+                    pgp_handler
+                        .lock()
+                        .unwrap()
+                        .remove_public_key(&key.fingerprint);
+                }
+                trust => pending.push((key, trust)),
+            }
+        }
+        self.state.lock().unwrap().unverified_keys = pending.len();
+        if pending.is_empty() {
+            return;
+        }
+
+        let mut decisions = Vec::new();
+        egui::TopBottomPanel::top("key_trust_banner").show(ctx, |ui| {
+            for (key, trust) in &pending {
+                ui.horizontal(|ui| {
+                    match trust {
+                        KeyTrust::Changed { previous } => {
+                            ui.colored_label(
+                                egui::Color32::RED,
+                                format!(
+                                    "⚠ KEY CHANGED for <{}>: trusted {}, now {}",
+                                    key.email,
+                                    previous.join(", "),
+                                    key.fingerprint
+                                ),
+                            );
+                        }
+                        _ => {
+                            ui.colored_label(
+                                egui::Color32::YELLOW,
+                                format!(
+                                    "🔑 New key {} <{}>: {}",
+                                    key.name, key.email, key.fingerprint
+                                ),
+                            );
+                        }
+                    }
+                    if ui
+                        .button("✓ Trust")
+                        .on_hover_text("Only if the owner confirmed this fingerprint")
+                        .clicked()
+                    {
+                        decisions.push((key.clone(), true));
+                    }
+                    if ui.button("✗ Reject").clicked() {
+                        decisions.push((key.clone(), false));
+                    }
+                });
+            }
+        });
+
+        if decisions.is_empty() {
+            return;
+        }
+        for (key, trusted) in &decisions {
+            store.record(key, *trusted);
+            if !trusted {
+                pgp_handler
+                    .lock()
+                    .unwrap()
+                    .remove_public_key(&key.fingerprint);
+            }
+        }
+        let message = match store.save() {
+            Ok(()) => "Recorded key trust decision".to_string(),
+            Err(e) => format!("Failed to save key trust store: {:#}", e),
+        };
+        self.state.lock().unwrap().status_message = message;
+    }
 }
 
 impl eframe::App for R2App {
@@ -121,7 +339,7 @@ impl eframe::App for R2App {
 
                 // Show PGP status
                 ui.separator();
-                let recipient_count = state.config.pgp.team_keys.len();
+                let recipient_count = state.config.pgp.all_key_sources().len();
                 let has_decrypt_key = state.config.pgp.secret_key_path.is_some();
 
                 if recipient_count > 0 {
@@ -139,13 +357,45 @@ impl eframe::App for R2App {
             });
         });
 
+        self.show_key_trust_banner(ctx);
+
+        {
+            // Keep credentials out of the console even if something logs them
+            let state = self.state.lock().unwrap();
+            let secrets = [
+                Some(state.config.r2.secret_access_key.clone()),
+                state.config.pgp.passphrase.clone(),
+            ];
+            let key_passphrases = state.config.pgp.key_passphrases.values().cloned();
+            state
+                .log_buffer
+                .set_secrets(secrets.into_iter().flatten().chain(key_passphrases));
+        }
+
         egui::TopBottomPanel::bottom("status_bar").show(ctx, |ui| {
             ui.horizontal(|ui| {
                 let status = self.state.lock().unwrap().status_message.clone();
                 ui.label(format!("Status: {}", status));
+                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                    ui.toggle_value(&mut self.show_console, "🖥 Console");
+                    ui.toggle_value(&mut self.show_stats, "📊 Stats");
+                });
             });
         });
 
+        if self.show_stats {
+            self.show_stats_window(ctx);
+        }
+
+        if self.show_console {
+            egui::TopBottomPanel::bottom("console_panel")
+                .resizable(true)
+                .default_height(200.0)
+                .show(ctx, |ui| self.console.show(ui));
+            // New records arrive from background threads without an input event
+            ctx.request_repaint_after(std::time::Duration::from_millis(500));
+        }
+
         egui::SidePanel::left("side_panel")
             .default_width(150.0)
             .show(ctx, |ui| {
@@ -191,3 +441,72 @@ impl eframe::App for R2App {
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_r2::MockR2;
+
+    // A client whose endpoint refuses connections, as after the pooled ones have died
+    async fn dead_client(mock: &MockR2) -> Arc<R2Client> {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+        Arc::new(mock.client().await.with_endpoint(&endpoint))
+    }
+
+    fn state_with(client: Arc<R2Client>) -> Arc<Mutex<AppState>> {
+        Arc::new(Mutex::new(AppState {
+            r2_client: Some(client),
+            is_connected: true,
+            ..AppState::default()
+        }))
+    }
+
+    #[tokio::test]
+    async fn dead_connections_reconnect_and_retry() {
+        let mock = MockR2::start().await;
+        mock.put("notes.txt", b"hello");
+        let state = state_with(dead_client(&mock).await);
+
+        let data = with_reconnect_using(
+            &state,
+            |client| async move { client.download_object("notes.txt").await },
+            |_| async { Ok(mock.client().await) },
+        )
+        .await
+        .unwrap();
+        assert_eq!(&data[..], b"hello");
+
+        // Later operations use the new client without reconnecting again
+        let keys = with_reconnect_using(
+            &state,
+            |client| async move { client.list_objects(None).await },
+            |_| async { panic!("reconnected a working client") },
+        )
+        .await
+        .unwrap();
+        assert_eq!(keys, ["notes.txt"]);
+        assert_eq!(state.lock().unwrap().status_message, "Reconnected to R2");
+    }
+
+    #[tokio::test]
+    async fn r2_errors_are_returned_without_reconnecting() {
+        let mock = MockR2::start().await;
+        mock.put("notes.txt", b"hello");
+        mock.deny("GET");
+        let client = Arc::new(mock.client().await);
+        let state = state_with(client.clone());
+
+        let result = with_reconnect_using(
+            &state,
+            |client| async move { client.download_object("notes.txt").await },
+            |_| async { panic!("reconnected after an R2 error") },
+        )
+        .await;
+        assert!(result.is_err());
+        let state = state.lock().unwrap();
+        assert!(Arc::ptr_eq(state.r2_client.as_ref().unwrap(), &client));
+        assert_eq!(state.status_message, "Ready");
+    }
+}
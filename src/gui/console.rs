@@ -0,0 +1,198 @@
+use eframe::egui;
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::sync::{Arc, Mutex};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+
+// Oldest records are dropped beyond this, so a long session can't grow without bound
+const MAX_RECORDS: usize = 2000;
+
+// Field names whose values are never shown
+const SECRET_FIELDS: &[&str] = &["secret", "passphrase", "password", "token"];
+
+const REDACTED: &str = "[redacted]";
+
+#[derive(Clone)]
+pub struct LogRecord {
+    pub time: chrono::DateTime<chrono::Local>,
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+}
+
+impl LogRecord {
+    fn to_line(&self) -> String {
+        format!(
+            "{} {:>5} {}: {}",
+            self.time.format("%H:%M:%S"),
+            self.level,
+            self.target,
+            self.message
+        )
+    }
+}
+
+/// Ring buffer of recent log records shown in the console panel.
+#[derive(Default)]
+pub struct LogBuffer {
+    records: Mutex<VecDeque<LogRecord>>,
+    secrets: Mutex<Vec<String>>,
+}
+
+impl LogBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Values (credentials, passphrases) to mask if they ever appear in a message.
+    pub fn set_secrets<I: IntoIterator<Item = String>>(&self, secrets: I) {
+        // Very short values would mask unrelated text
+        *self.secrets.lock().unwrap() = secrets.into_iter().filter(|s| s.len() >= 4).collect();
+    }
+
+    pub fn records(&self) -> Vec<LogRecord> {
+        self.records.lock().unwrap().iter().cloned().collect()
+    }
+
+    pub fn clear(&self) {
+        self.records.lock().unwrap().clear();
+    }
+
+    fn push(&self, mut record: LogRecord) {
+        for secret in self.secrets.lock().unwrap().iter() {
+            if record.message.contains(secret.as_str()) {
+                record.message = record.message.replace(secret.as_str(), REDACTED);
+            }
+        }
+
+        let mut records = self.records.lock().unwrap();
+        if records.len() >= MAX_RECORDS {
+            records.pop_front();
+        }
+        records.push_back(record);
+    }
+}
+
+/// `tracing` layer that copies this application's events into a `LogBuffer`.
+pub struct ConsoleLayer {
+    buffer: Arc<LogBuffer>,
+}
+
+impl ConsoleLayer {
+    pub fn new(buffer: Arc<LogBuffer>) -> Self {
+        Self { buffer }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for ConsoleLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        // Only our own crates; dependencies are far too chatty at debug level
+        if !metadata.target().starts_with("rust_r2") || *metadata.level() > Level::DEBUG {
+            return;
+        }
+
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+        self.buffer.push(LogRecord {
+            time: chrono::Local::now(),
+            level: *metadata.level(),
+            target: metadata.target().to_string(),
+            message: visitor.message,
+        });
+    }
+}
+
+#[derive(Default)]
+struct MessageVisitor {
+    message: String,
+}
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        let name = field.name();
+        if name == "message" {
+            let _ = write!(self.message, "{:?}", value);
+            return;
+        }
+
+        if !self.message.is_empty() {
+            self.message.push(' ');
+        }
+        if SECRET_FIELDS.iter().any(|s| name.contains(s)) {
+            let _ = write!(self.message, "{}={}", name, REDACTED);
+        } else {
+            let _ = write!(self.message, "{}={:?}", name, value);
+        }
+    }
+}
+
+/// Collapsible bottom panel listing recent log records.
+pub struct ConsolePanel {
+    buffer: Arc<LogBuffer>,
+    min_level: Level,
+}
+
+impl ConsolePanel {
+    pub fn new(buffer: Arc<LogBuffer>) -> Self {
+        Self {
+            buffer,
+            min_level: Level::INFO,
+        }
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui) {
+        let records: Vec<LogRecord> = self
+            .buffer
+            .records()
+            .into_iter()
+            .filter(|r| r.level <= self.min_level)
+            .collect();
+
+        ui.horizontal(|ui| {
+            ui.strong("Console");
+            ui.separator();
+
+            egui::ComboBox::from_id_salt("console_level")
+                .selected_text(self.min_level.as_str())
+                .show_ui(ui, |ui| {
+                    for level in [Level::ERROR, Level::WARN, Level::INFO, Level::DEBUG] {
+                        ui.selectable_value(&mut self.min_level, level, level.as_str());
+                    }
+                });
+
+            if ui.button("📋 Copy all").clicked() {
+                let text: Vec<String> = records.iter().map(LogRecord::to_line).collect();
+                ui.output_mut(|o| o.copied_text = text.join("\n"));
+            }
+            if ui.button("🗑 Clear").clicked() {
+                self.buffer.clear();
+            }
+        });
+        ui.separator();
+
+        egui::ScrollArea::vertical()
+            .auto_shrink([false, false])
+            .stick_to_bottom(true)
+            .show(ui, |ui| {
+                if records.is_empty() {
+                    ui.weak("No log messages yet");
+                }
+                for record in &records {
+                    let color = match record.level {
+                        Level::ERROR => egui::Color32::RED,
+                        Level::WARN => egui::Color32::YELLOW,
+                        Level::INFO => ui.visuals().text_color(),
+                        _ => egui::Color32::GRAY,
+                    };
+                    ui.label(
+                        egui::RichText::new(record.to_line())
+                            .monospace()
+                            .color(color),
+                    );
+                }
+            });
+    }
+}
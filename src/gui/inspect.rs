@@ -0,0 +1,178 @@
+use eframe::egui;
+use rust_r2::crypto::PgpHandler;
+
+// Enough of an object to read the session key packets at its start
+pub const HEADER_BYTES: u64 = 64 * 1024;
+
+/// A key an inspected object's session key is encrypted to.
+#[derive(Clone)]
+pub struct InspectedRecipient {
+    pub key_id: String,
+    pub algorithm: String,
+    pub owner: Option<String>, // "Name <email>" of the loaded key with this ID
+    pub ours: bool,            // The loaded secret key can open the object
+}
+
+#[derive(Clone)]
+pub struct InspectionDetails {
+    pub recipients: Vec<InspectedRecipient>,
+    pub passphrase: bool,
+    pub cipher: Option<String>,
+    pub aead: Option<String>,
+}
+
+/// Who can open an encrypted object, shown in the inspect window.
+#[derive(Clone)]
+pub struct Inspection {
+    pub key: String,
+    pub details: Result<InspectionDetails, String>,
+}
+
+impl Inspection {
+    /// Read the session key packets in `header`, the start of object `key`, and match
+    /// their key IDs against the keys `handler` has loaded.
+    pub fn new(key: String, header: &[u8], handler: &PgpHandler) -> Self {
+        let secret_ids = handler.secret_key_ids();
+        let details = handler
+            .inspect_message(header)
+            .map(|envelope| InspectionDetails {
+                recipients: envelope
+                    .recipients
+                    .into_iter()
+                    .map(|recipient| InspectedRecipient {
+                        owner: handler
+                            .key_for_id(&recipient.key_id)
+                            .map(|info| format!("{} <{}>", info.name, info.email)),
+                        ours: secret_ids.contains(&recipient.key_id),
+                        key_id: recipient.key_id,
+                        algorithm: recipient.algorithm,
+                    })
+                    .collect(),
+                passphrase: envelope.passphrase,
+                cipher: envelope.cipher,
+                aead: envelope.aead,
+            })
+            .map_err(|e| format!("{:#}", e));
+        Self { key, details }
+    }
+}
+
+/// Draw the inspect window. Returns false once the user closes it.
+pub fn show_inspection(ctx: &egui::Context, inspection: &Inspection) -> bool {
+    let mut open = true;
+    egui::Window::new(format!("🔍 {}", inspection.key))
+        .id(egui::Id::new("object_inspection"))
+        .open(&mut open)
+        .default_size([500.0, 300.0])
+        .show(ctx, |ui| {
+            let details = match &inspection.details {
+                Ok(details) => details,
+                Err(e) => {
+                    ui.colored_label(
+                        egui::Color32::YELLOW,
+                        format!("⚠ Can't read the encryption header: {}", e),
+                    );
+                    return;
+                }
+            };
+
+            let cipher = match (&details.cipher, &details.aead) {
+                (Some(cipher), Some(aead)) => format!("{} ({})", cipher, aead),
+                (Some(cipher), None) => cipher.clone(),
+                // Only the session key says, and only a recipient's secret key can read it
+                (None, _) => "unknown (needs a recipient's secret key)".to_string(),
+            };
+            ui.label(format!("Cipher: {}", cipher));
+            if details.passphrase {
+                ui.label("🔑 Can be opened with a passphrase");
+            }
+            ui.separator();
+
+            if details.recipients.is_empty() {
+                ui.label("No public-key recipients: only the passphrase opens it.");
+                return;
+            }
+            ui.strong(format!("Encrypted to {} keys:", details.recipients.len()));
+            egui::Grid::new("inspection_recipients")
+                .striped(true)
+                .num_columns(3)
+                .spacing([20.0, 4.0])
+                .show(ui, |ui| {
+                    for recipient in &details.recipients {
+                        ui.monospace(&recipient.key_id);
+                        ui.label(&recipient.algorithm);
+                        let owner = if recipient.key_id.chars().all(|c| c == '0') {
+                            "hidden recipient".to_string()
+                        } else {
+                            recipient
+                                .owner
+                                .clone()
+                                .unwrap_or_else(|| "not a loaded key".to_string())
+                        };
+                        if recipient.ours {
+                            ui.colored_label(
+                                egui::Color32::GREEN,
+                                format!("{} (your secret key)", owner),
+                            );
+                        } else {
+                            ui.label(owner);
+                        }
+                        ui.end_row();
+                    }
+                });
+        });
+    open
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    #[test]
+    fn recipients_of_a_multi_recipient_message_are_named() {
+        let alice = test_support::key("Alice");
+        let bob = test_support::key("Bob");
+        let carol = test_support::key("Carol");
+        let sender = test_support::handler(&[&alice, &bob, &carol], &[]);
+        let large: Vec<u8> = (0..200_000).map(|i| (i % 251) as u8).collect();
+        let encrypted = sender.encrypt(&large).unwrap();
+
+        // Alice inspects it knowing Bob's public key but not Carol's
+        let viewer = test_support::handler(&[&alice, &bob], &[&alice]);
+        let header = &encrypted[..HEADER_BYTES as usize];
+        let details = Inspection::new("big.bin.pgp".into(), header, &viewer)
+            .details
+            .unwrap();
+        assert!(!details.passphrase);
+        assert_eq!(details.recipients.len(), 3);
+        let mut owners: Vec<(Option<&str>, bool)> = details
+            .recipients
+            .iter()
+            .map(|r| (r.owner.as_deref(), r.ours))
+            .collect();
+        owners.sort();
+        assert_eq!(
+            owners,
+            [
+                (None, false),
+                (Some("Alice <alice@example.com>"), true),
+                (Some("Bob <bob@example.com>"), false),
+            ]
+        );
+        // Alice's key opens the session key, so the cipher is known
+        assert!(details.cipher.is_some());
+        let carol_id = &test_support::handler(&[], &[&carol]).secret_key_ids()[0];
+        assert!(details.recipients.iter().any(|r| &r.key_id == carol_id));
+
+        // A passphrase-only object has no recipients to list
+        let symmetric = sender.encrypt_symmetric(b"shared", "hunter2").unwrap();
+        let details = Inspection::new("shared.pgp".into(), &symmetric, &viewer)
+            .details
+            .unwrap();
+        assert!(details.passphrase && details.recipients.is_empty());
+
+        let plain = Inspection::new("plain.txt".into(), b"just text", &viewer);
+        assert!(plain.details.is_err());
+    }
+}
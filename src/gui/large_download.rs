@@ -0,0 +1,106 @@
+use crate::app::AppState;
+use eframe::egui;
+use rust_r2::progress::format_size;
+use std::sync::{Arc, Mutex};
+
+/// A download held back until the user confirms its size.
+#[derive(Clone)]
+pub struct LargeDownload {
+    pub description: String, // The key, or a summary like "12 files" for folders
+    pub size: u64,
+}
+
+pub enum Confirmation {
+    Pending,
+    Confirmed,
+    Cancelled,
+}
+
+/// The size of a download that must be confirmed first: one not yet `confirmed` whose
+/// known size is over the configured warning threshold.
+pub fn size_to_confirm(
+    state: &Arc<Mutex<AppState>>,
+    size: Option<u64>,
+    confirmed: bool,
+) -> Option<u64> {
+    let gui = &state.lock().unwrap().config.gui;
+    size.filter(|&size| !confirmed && gui.needs_download_confirmation(size))
+}
+
+/// Draw the confirmation window for `download` and report the user's choice.
+pub fn show_confirmation(ctx: &egui::Context, download: &LargeDownload) -> Confirmation {
+    let mut confirmation = Confirmation::Pending;
+    egui::Window::new("⚠ Large download")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+        .show(ctx, |ui| {
+            ui.label(format!(
+                "{} is {}.",
+                download.description,
+                format_size(download.size)
+            ));
+            ui.label(format!(
+                "Downloading will use about {} of disk space.",
+                format_size(download.size)
+            ));
+            ui.add_space(10.0);
+            ui.horizontal(|ui| {
+                if ui.button("⬇️ Download anyway").clicked() {
+                    confirmation = Confirmation::Confirmed;
+                }
+                if ui.button("Cancel").clicked() {
+                    confirmation = Confirmation::Cancelled;
+                }
+            });
+        });
+    confirmation
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_r2::MockR2;
+
+    fn state_warning_at(megabytes: u64) -> Arc<Mutex<AppState>> {
+        let mut state = AppState::default();
+        state.config.gui.large_download_warning_mb = megabytes;
+        Arc::new(Mutex::new(state))
+    }
+
+    #[test]
+    fn sizes_over_the_threshold_need_confirming() {
+        let state = state_warning_at(1);
+        let mb = 1024 * 1024;
+        assert_eq!(size_to_confirm(&state, Some(2 * mb), false), Some(2 * mb));
+        assert_eq!(size_to_confirm(&state, Some(mb), false), None);
+        assert_eq!(size_to_confirm(&state, Some(2 * mb), true), None);
+        assert_eq!(size_to_confirm(&state, None, false), None);
+
+        // 0 turns the warning off
+        let state = state_warning_at(0);
+        assert_eq!(size_to_confirm(&state, Some(u64::MAX), false), None);
+    }
+
+    #[tokio::test]
+    async fn head_sizes_trigger_the_confirmation() {
+        let mock = MockR2::start().await;
+        mock.put("video.mp4", &vec![0u8; 3 * 1024 * 1024]);
+        mock.put("notes.txt", b"small");
+        let client = mock.client().await;
+        let state = state_warning_at(2);
+
+        let size = |key: &'static str| {
+            let client = &client;
+            async move { client.head_object(key).await.unwrap().content_length }
+        };
+        assert_eq!(
+            size_to_confirm(&state, size("video.mp4").await, false),
+            Some(3 * 1024 * 1024)
+        );
+        assert_eq!(
+            size_to_confirm(&state, size("notes.txt").await, false),
+            None
+        );
+    }
+}
@@ -1,11 +1,37 @@
 mod app;
+mod console;
+mod inspect;
+mod large_download;
+mod preview;
+mod progress;
+mod recipient_memory;
 mod tabs;
 
+// Test helpers shared with the library's tests; each binary uses only part of them
+#[cfg(test)]
+#[allow(dead_code)]
+#[path = "../mock_r2.rs"]
+mod mock_r2;
+#[cfg(test)]
+#[allow(dead_code)]
+#[path = "../test_support.rs"]
+mod test_support;
+#[cfg(test)]
+use rust_r2::{crypto, r2_client};
+
 use eframe::egui;
+use std::sync::Arc;
+use tracing_subscriber::layer::SubscriberExt;
 
 fn main() -> eframe::Result<()> {
     env_logger::init();
 
+    // Capture tracing output for the console panel
+    let log_buffer = Arc::new(console::LogBuffer::new());
+    let subscriber =
+        tracing_subscriber::registry().with(console::ConsoleLayer::new(log_buffer.clone()));
+    let _ = tracing::subscriber::set_global_default(subscriber);
+
     let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
             .with_inner_size([1200.0, 800.0])
@@ -18,7 +44,7 @@ fn main() -> eframe::Result<()> {
         options,
         Box::new(|cc| {
             egui_extras::install_image_loaders(&cc.egui_ctx);
-            Ok(Box::new(app::R2App::new(cc)))
+            Ok(Box::new(app::R2App::new(cc, log_buffer)))
         }),
     )
 }
@@ -0,0 +1,424 @@
+use crate::app::{with_reconnect, AppState};
+use eframe::egui;
+use rust_r2::encryption_filter;
+use std::collections::VecDeque;
+use std::io::Cursor;
+use std::sync::{Arc, Mutex};
+
+// Only the start of the content is drawn
+const DISPLAY_LIMIT: usize = 64 * 1024;
+const HEX_DUMP_LIMIT: usize = 512;
+
+const CACHE_MAX_ENTRIES: usize = 32;
+const CACHE_MAX_BYTES: usize = 64 * 1024 * 1024;
+
+/// Content shown in the preview window.
+#[derive(Clone)]
+pub struct Preview {
+    pub key: String,
+    pub content: Arc<Vec<u8>>,
+    pub decrypted: bool,
+    pub from_cache: bool,            // R2 answered 304 Not Modified
+    pub image: Option<ImagePreview>, // Set when the content looks like an image
+}
+
+/// How an image is shown. Anything but a decoded image falls back to the hex dump.
+#[derive(Clone)]
+pub enum ImagePreview {
+    Decoded(egui::TextureHandle),
+    TooLarge {
+        width: u32,
+        height: u32,
+        max_pixels: u64,
+    },
+    Unsupported(&'static str), // The format, recognised but not decodable here
+    Failed(String),
+}
+
+#[derive(Clone)]
+pub struct CachedPreview {
+    pub key: String,
+    pub etag: String,
+    pub content: Arc<Vec<u8>>, // Decrypted content for encrypted objects
+    pub encrypted: bool,
+    pub decrypted: bool,
+}
+
+/// Previewed objects tagged with the ETag they were downloaded at. A repeat preview sends
+/// that ETag as If-None-Match and reuses the cached content when R2 answers 304, which
+/// also skips decrypting again. The least recently used entries are evicted once there
+/// are too many or they take too much memory.
+#[derive(Default)]
+pub struct PreviewCache {
+    entries: VecDeque<CachedPreview>, // Most recently used last
+    total_bytes: usize,
+}
+
+impl PreviewCache {
+    /// The cached preview of `key`, marking it as recently used.
+    pub fn get(&mut self, key: &str) -> Option<CachedPreview> {
+        let index = self.entries.iter().position(|e| e.key == key)?;
+        let entry = self.entries.remove(index)?;
+        self.entries.push_back(entry.clone());
+        Some(entry)
+    }
+
+    pub fn insert(&mut self, entry: CachedPreview) {
+        self.remove(&entry.key);
+        if entry.content.len() > CACHE_MAX_BYTES {
+            return;
+        }
+        self.total_bytes += entry.content.len();
+        self.entries.push_back(entry);
+        while self.entries.len() > CACHE_MAX_ENTRIES || self.total_bytes > CACHE_MAX_BYTES {
+            let Some(evicted) = self.entries.pop_front() else {
+                break;
+            };
+            self.total_bytes -= evicted.content.len();
+        }
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        if let Some(index) = self.entries.iter().position(|e| e.key == key) {
+            if let Some(removed) = self.entries.remove(index) {
+                self.total_bytes -= removed.content.len();
+            }
+        }
+    }
+}
+
+/// The content to preview, before any image in it is decoded.
+pub struct FetchedPreview {
+    pub content: Arc<Vec<u8>>,
+    pub encrypted: bool,
+    pub decrypted: bool,
+    pub from_cache: bool, // R2 answered 304 Not Modified
+}
+
+/// Fetch `key` for previewing. When it was previewed before, its cached ETag goes with
+/// the request and the cached content is reused if R2 answers 304. New content is
+/// decrypted if it is encrypted and a secret key is loaded, then cached.
+pub async fn fetch_preview(
+    state: &Arc<Mutex<AppState>>,
+    key: &str,
+) -> anyhow::Result<FetchedPreview> {
+    let cache = state.lock().unwrap().preview_cache.clone();
+    let cached = cache.lock().unwrap().get(key);
+    let etag = cached.as_ref().map(|cached| cached.etag.as_str());
+
+    let fetched = with_reconnect(state, |client| async move {
+        client.download_object_if_changed(key, etag).await
+    })
+    .await?;
+    let Some((data, head)) = fetched else {
+        // Unchanged since the cached preview
+        let cached = cached.ok_or_else(|| {
+            // Only possible if the cache entry was evicted meanwhile
+            anyhow::anyhow!("the cached copy is gone, try again")
+        })?;
+        return Ok(FetchedPreview {
+            content: cached.content,
+            encrypted: cached.encrypted,
+            decrypted: cached.decrypted,
+            from_cache: true,
+        });
+    };
+
+    let encrypted = encryption_filter::is_encrypted_extension(key)
+        || rust_r2::crypto::PgpHandler::is_pgp_encrypted(&data);
+    let decrypted = if encrypted {
+        let pgp_handler = state.lock().unwrap().pgp_handler.clone();
+        let handler = pgp_handler.lock().unwrap();
+        if handler.has_secret_key() {
+            handler.decrypt(&data).ok()
+        } else {
+            None
+        }
+    } else {
+        None
+    };
+
+    let preview = FetchedPreview {
+        decrypted: decrypted.is_some(),
+        content: Arc::new(decrypted.unwrap_or_else(|| data.to_vec())),
+        encrypted,
+        from_cache: false,
+    };
+    if let Some(etag) = head.etag {
+        cache.lock().unwrap().insert(CachedPreview {
+            key: key.to_string(),
+            etag,
+            content: preview.content.clone(),
+            encrypted: preview.encrypted,
+            decrypted: preview.decrypted,
+        });
+    }
+    Ok(preview)
+}
+
+/// The image format `data` starts with, if any.
+fn sniff_image(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("PNG")
+    } else if data.starts_with(b"BM") && data.len() >= 26 {
+        Some("BMP")
+    } else if data.starts_with(&[0xff, 0xd8, 0xff]) {
+        Some("JPEG")
+    } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        Some("GIF")
+    } else if data.len() >= 12 && data.starts_with(b"RIFF") && &data[8..12] == b"WEBP" {
+        Some("WebP")
+    } else {
+        None
+    }
+}
+
+/// Decode `content` into a texture if it is an image. Called from the preview thread so
+/// decoding never stalls the UI. The dimensions are read from the header first and images
+/// over `max_pixels` aren't decoded at all; decoder errors and panics become `Failed`.
+pub fn load_image(
+    ctx: &egui::Context,
+    key: &str,
+    content: &[u8],
+    max_pixels: u64,
+) -> Option<ImagePreview> {
+    let format = match sniff_image(content)? {
+        "PNG" => image::ImageFormat::Png,
+        "BMP" => image::ImageFormat::Bmp,
+        other => return Some(ImagePreview::Unsupported(other)),
+    };
+
+    let decoded = std::panic::catch_unwind(|| {
+        let (width, height) = image::ImageReader::with_format(Cursor::new(content), format)
+            .into_dimensions()
+            .map_err(|e| e.to_string())?;
+        if u64::from(width) * u64::from(height) > max_pixels {
+            return Ok(Err(ImagePreview::TooLarge {
+                width,
+                height,
+                max_pixels,
+            }));
+        }
+
+        let mut reader = image::ImageReader::with_format(Cursor::new(content), format);
+        let mut limits = image::Limits::default();
+        limits.max_image_width = Some(width);
+        limits.max_image_height = Some(height);
+        reader.limits(limits);
+        let rgba = reader.decode().map_err(|e| e.to_string())?.to_rgba8();
+        Ok(Ok(egui::ColorImage::from_rgba_unmultiplied(
+            [width as usize, height as usize],
+            rgba.as_raw(),
+        )))
+    });
+
+    Some(match decoded {
+        Ok(Ok(Ok(image))) => ImagePreview::Decoded(ctx.load_texture(
+            format!("preview:{}", key),
+            image,
+            egui::TextureOptions::default(),
+        )),
+        Ok(Ok(Err(too_large))) => too_large,
+        Ok(Err(e)) => ImagePreview::Failed(e),
+        Err(_) => ImagePreview::Failed("the decoder crashed".to_string()),
+    })
+}
+
+/// Draw the preview window. Returns false once the user closes it.
+pub fn show_preview(ctx: &egui::Context, preview: &Preview) -> bool {
+    let mut open = true;
+    egui::Window::new(format!("👁 {}", preview.key))
+        .id(egui::Id::new("object_preview"))
+        .open(&mut open)
+        .default_size([600.0, 400.0])
+        .show(ctx, |ui| {
+            let mut details = format!("{} bytes", preview.content.len());
+            if preview.decrypted {
+                details.push_str(", decrypted");
+            }
+            if preview.from_cache {
+                details.push_str(", unchanged since last preview (cached)");
+            }
+            ui.small(details);
+            ui.separator();
+
+            if let Some(image) = &preview.image {
+                match image {
+                    ImagePreview::Decoded(texture) => {
+                        let [width, height] = texture.size();
+                        ui.small(format!("{}×{} image", width, height));
+                        egui::ScrollArea::both().show(ui, |ui| {
+                            ui.add(
+                                egui::Image::from_texture(egui::load::SizedTexture::from_handle(
+                                    texture,
+                                ))
+                                .shrink_to_fit(),
+                            );
+                        });
+                        return;
+                    }
+                    ImagePreview::TooLarge {
+                        width,
+                        height,
+                        max_pixels,
+                    } => {
+                        ui.colored_label(
+                            egui::Color32::YELLOW,
+                            format!(
+                                "⚠ {}×{} image is too large to preview (over {} megapixels)",
+                                width,
+                                height,
+                                max_pixels / 1_000_000
+                            ),
+                        );
+                    }
+                    ImagePreview::Unsupported(format) => {
+                        ui.colored_label(
+                            egui::Color32::YELLOW,
+                            format!("⚠ {} images can't be previewed", format),
+                        );
+                    }
+                    ImagePreview::Failed(e) => {
+                        ui.colored_label(
+                            egui::Color32::YELLOW,
+                            format!("⚠ Couldn't decode the image: {}", e),
+                        );
+                    }
+                }
+            }
+
+            let content = preview.content.as_slice();
+            let text = std::str::from_utf8(&content[..content.len().min(DISPLAY_LIMIT)])
+                .ok()
+                .filter(|text| !text.contains('\0'));
+            egui::ScrollArea::both().show(ui, |ui| match text {
+                Some(text) => {
+                    ui.add(egui::Label::new(egui::RichText::new(text).monospace()));
+                    if content.len() > DISPLAY_LIMIT {
+                        ui.small(format!("… showing the first {} bytes", DISPLAY_LIMIT));
+                    }
+                }
+                None => {
+                    ui.label("Binary content:");
+                    ui.add(egui::Label::new(
+                        egui::RichText::new(hex_dump(
+                            &content[..content.len().min(HEX_DUMP_LIMIT)],
+                        ))
+                        .monospace(),
+                    ));
+                }
+            });
+        });
+    open
+}
+
+fn hex_dump(data: &[u8]) -> String {
+    data.chunks(16)
+        .enumerate()
+        .map(|(row, bytes)| {
+            let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+            let ascii: String = bytes
+                .iter()
+                .map(|&b| if b.is_ascii_graphic() { b as char } else { '.' })
+                .collect();
+            format!("{:08x}  {:<48}  {}", row * 16, hex.join(" "), ascii)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_r2::MockR2;
+    use crate::test_support;
+
+    #[tokio::test]
+    async fn repeat_previews_of_an_unchanged_object_use_the_cache() {
+        let key = test_support::key("Preview");
+        let handler = test_support::handler(&[&key], &[&key]);
+        let mock = MockR2::start().await;
+        mock.put(
+            "notes.txt.pgp",
+            &handler
+                .encrypt_with_filename(b"first", "notes.txt")
+                .unwrap(),
+        );
+        let state = Arc::new(Mutex::new(AppState {
+            r2_client: Some(Arc::new(mock.client().await)),
+            pgp_handler: Arc::new(Mutex::new(handler)),
+            ..AppState::default()
+        }));
+
+        let first = fetch_preview(&state, "notes.txt.pgp").await.unwrap();
+        assert!(!first.from_cache && first.encrypted && first.decrypted);
+        assert_eq!(*first.content, b"first");
+
+        // The second preview sends the ETag and R2 answers 304
+        let second = fetch_preview(&state, "notes.txt.pgp").await.unwrap();
+        assert!(second.from_cache && second.decrypted);
+        assert!(Arc::ptr_eq(&first.content, &second.content));
+        assert_eq!(mock.not_modified_count(), 1);
+
+        // A changed object is downloaded and decrypted again
+        let encrypted = state
+            .lock()
+            .unwrap()
+            .pgp_handler
+            .lock()
+            .unwrap()
+            .encrypt_with_filename(b"second", "notes.txt")
+            .unwrap();
+        mock.put("notes.txt.pgp", &encrypted);
+        let third = fetch_preview(&state, "notes.txt.pgp").await.unwrap();
+        assert!(!third.from_cache && third.decrypted);
+        assert_eq!(*third.content, b"second");
+        assert_eq!(mock.not_modified_count(), 1);
+    }
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut encoded = Cursor::new(Vec::new());
+        image::RgbaImage::new(width, height)
+            .write_to(&mut encoded, image::ImageFormat::Png)
+            .unwrap();
+        encoded.into_inner()
+    }
+
+    #[test]
+    fn images_that_cant_be_shown_fall_back_gracefully() {
+        let ctx = egui::Context::default();
+        let load = |content: &[u8], max_pixels| load_image(&ctx, "img", content, max_pixels);
+
+        let Some(ImagePreview::Decoded(texture)) = load(&png(3, 2), 100) else {
+            panic!("a small PNG should decode");
+        };
+        assert_eq!(texture.size(), [3, 2]);
+
+        // The header is enough to refuse an image that is too big
+        assert!(matches!(
+            load(&png(3, 2), 5),
+            Some(ImagePreview::TooLarge {
+                width: 3,
+                height: 2,
+                max_pixels: 5
+            })
+        ));
+
+        // A PNG cut short, and one that is only a signature, fail without panicking
+        let valid = png(16, 16);
+        for malformed in [&valid[..valid.len() / 2], &valid[..8]] {
+            assert!(matches!(
+                load(malformed, 1000),
+                Some(ImagePreview::Failed(_))
+            ));
+            // The window then shows the raw bytes instead
+            assert!(hex_dump(malformed).starts_with("00000000  89 50 4e 47"));
+        }
+
+        assert!(matches!(
+            load(b"\xff\xd8\xff\xe0 not really a JPEG", 100),
+            Some(ImagePreview::Unsupported("JPEG"))
+        ));
+        assert!(load(b"plain text", 100).is_none());
+    }
+}
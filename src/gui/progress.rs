@@ -0,0 +1,62 @@
+use eframe::egui;
+use rust_r2::progress::ProgressReporter;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Feeds transfer progress into the fraction and file label a tab draws from.
+pub struct GuiProgress {
+    fraction: Arc<Mutex<f32>>,
+    current_file: Arc<Mutex<String>>,
+    length: AtomicU64,
+    span: (f32, f32), // Start and width of the part of the bar this transfer fills
+    ctx: egui::Context,
+}
+
+impl GuiProgress {
+    pub fn new(
+        fraction: Arc<Mutex<f32>>,
+        current_file: Arc<Mutex<String>>,
+        ctx: egui::Context,
+    ) -> Self {
+        Self {
+            fraction,
+            current_file,
+            length: AtomicU64::new(0),
+            span: (0.0, 1.0),
+            ctx,
+        }
+    }
+
+    /// Fill only `width` of the bar from `start`, e.g. for one file of a batch.
+    pub fn with_span(mut self, start: f32, width: f32) -> Self {
+        self.span = (start, width);
+        self
+    }
+}
+
+impl ProgressReporter for GuiProgress {
+    fn set_length(&self, length: u64) {
+        self.length.store(length, Ordering::SeqCst);
+    }
+
+    fn set_position(&self, position: u64) {
+        let length = self.length.load(Ordering::SeqCst);
+        let fraction = if length == 0 {
+            0.0
+        } else {
+            (position as f64 / length as f64).min(1.0) as f32
+        };
+        *self.fraction.lock().unwrap() = self.span.0 + self.span.1 * fraction;
+        self.ctx.request_repaint();
+    }
+
+    fn set_message(&self, message: &str) {
+        *self.current_file.lock().unwrap() = message.to_string();
+        self.ctx.request_repaint();
+    }
+
+    fn finish(&self, _message: &str) {
+        *self.fraction.lock().unwrap() = self.span.0 + self.span.1;
+        self.ctx.request_repaint();
+    }
+}
@@ -0,0 +1,134 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+
+/// The recipients last used for encrypted uploads to one destination prefix.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RememberedRecipients {
+    pub fingerprints: Vec<String>,
+    pub encrypt_to_self: bool,
+}
+
+/// Recipient choices by destination prefix, kept in `recipient_selections.json` in the
+/// per-user config folder so the next upload to the same place starts with them.
+pub struct RecipientMemory {
+    path: Option<PathBuf>,
+    selections: BTreeMap<String, RememberedRecipients>,
+}
+
+impl RecipientMemory {
+    /// Load the remembered selections. A missing or unreadable file starts empty.
+    pub fn load() -> Self {
+        Self::load_from(
+            dirs::config_dir().map(|dir| dir.join("rust-r2").join("recipient_selections.json")),
+        )
+    }
+
+    /// Load the selections kept in `path`; with no path nothing is saved.
+    fn load_from(path: Option<PathBuf>) -> Self {
+        let selections = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+        Self { path, selections }
+    }
+
+    pub fn get(&self, prefix: &str) -> Option<&RememberedRecipients> {
+        self.selections.get(normalize_prefix(prefix))
+    }
+
+    /// Remember `recipients` for `prefix` and write the file.
+    pub fn remember(&mut self, prefix: &str, recipients: RememberedRecipients) -> Result<()> {
+        self.selections
+            .insert(normalize_prefix(prefix).to_string(), recipients);
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).context("Failed to create config folder")?;
+        }
+        let content = serde_json::to_string_pretty(&self.selections)
+            .context("Failed to serialize recipient selections")?;
+        fs::write(path, content).context("Failed to write recipient selections")
+    }
+}
+
+impl RememberedRecipients {
+    /// Which of the `loaded` fingerprints to leave out so only the remembered ones are
+    /// selected, and how many remembered keys are no longer loaded. When none of them is
+    /// loaded any more, nothing is left out.
+    pub fn exclusions(&self, loaded: &[String]) -> (HashSet<String>, usize) {
+        let is_remembered =
+            |fp: &String| self.fingerprints.iter().any(|r| r.eq_ignore_ascii_case(fp));
+        let missing = self
+            .fingerprints
+            .iter()
+            .filter(|r| !loaded.iter().any(|fp| fp.eq_ignore_ascii_case(r)))
+            .count();
+        let excluded = if loaded.iter().any(is_remembered) {
+            loaded
+                .iter()
+                .filter(|fp| !is_remembered(fp))
+                .cloned()
+                .collect()
+        } else {
+            HashSet::new()
+        };
+        (excluded, missing)
+    }
+}
+
+/// "docs/2024/" and "docs/2024" are the same destination.
+fn normalize_prefix(prefix: &str) -> &str {
+    prefix.trim_matches('/')
+}
+
+/// The folder part of an object key, used as the destination of a single-file upload.
+pub fn key_prefix(key: &str) -> &str {
+    key.rsplit_once('/').map_or("", |(prefix, _)| prefix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    #[test]
+    fn remembered_recipients_are_restored_for_the_same_prefix() {
+        let folder = TempDir::new();
+        let path = folder.path().join("recipient_selections.json");
+        let mut memory = RecipientMemory::load_from(Some(path.clone()));
+        let finance = RememberedRecipients {
+            fingerprints: vec!["AAAA".into(), "BBBB".into()],
+            encrypt_to_self: true,
+        };
+        memory.remember("finance/2024/", finance).unwrap();
+
+        let memory = RecipientMemory::load_from(Some(path));
+        let restored = memory.get(key_prefix("finance/2024/report.xlsx")).unwrap();
+        assert_eq!(restored.fingerprints, ["AAAA", "BBBB"]);
+        assert!(restored.encrypt_to_self);
+        assert!(memory.get("finance").is_none());
+        assert!(memory.get("").is_none());
+    }
+
+    #[test]
+    fn unloaded_keys_drop_out_of_the_selection() {
+        let remembered = RememberedRecipients {
+            fingerprints: vec!["aaaa".into(), "GONE".into()],
+            encrypt_to_self: false,
+        };
+        let loaded = ["AAAA".to_string(), "CCCC".to_string()];
+        let (excluded, missing) = remembered.exclusions(&loaded);
+        assert_eq!(excluded, HashSet::from(["CCCC".to_string()]));
+        assert_eq!(missing, 1);
+
+        // With none of them loaded any more, every key is selected
+        let (excluded, missing) = remembered.exclusions(&["CCCC".to_string()]);
+        assert!(excluded.is_empty());
+        assert_eq!(missing, 2);
+    }
+}
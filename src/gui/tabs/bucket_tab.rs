@@ -1,15 +1,90 @@
-use crate::app::AppState;
+use crate::app::{with_reconnect, AppState};
+use crate::inspect::{self, Inspection};
+use crate::large_download::{self, Confirmation, LargeDownload};
+use crate::preview::{self, Preview};
 use eframe::egui;
+use futures_util::stream::{self, StreamExt};
+use rust_r2::encryption_filter;
+use rust_r2::progress::format_size;
+use rust_r2::r2_client::{Permission, DELETE_BATCH_LIMIT};
+use std::collections::HashSet;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tokio::runtime::Runtime;
 
 #[derive(Clone)]
 pub struct BucketObject {
     pub key: String,
-    #[allow(dead_code)]
-    pub size: Option<usize>,
-    #[allow(dead_code)]
-    pub last_modified: Option<String>,
+    pub size: Option<u64>,
+    pub last_modified: Option<String>, // ISO 8601 as R2 lists it
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ObjectSort {
+    Key,
+    Size,     // Largest first
+    Modified, // Newest first
+}
+
+// Keys per DeleteObjects request; small enough for the progress bar to move
+const DELETE_BATCH_SIZE: usize = 50;
+
+// How long a copied share link stays valid
+const SHARE_LINK_EXPIRY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Progress of a "Delete Selected" run.
+#[derive(Clone, Default)]
+struct BatchDelete {
+    total: usize,
+    deleted: usize,
+    failed: Vec<(String, String)>, // Key and reason
+    running: bool,
+}
+
+/// Delete `keys` with DeleteObjects requests of `DELETE_BATCH_SIZE` keys, `concurrency`
+/// of them at a time, passing the keys each request deleted and the ones it couldn't, with
+/// the reason, to `on_batch` as it finishes. A request that fails outright fails its keys.
+async fn delete_in_batches(
+    app_state: &Arc<Mutex<AppState>>,
+    keys: &[String],
+    concurrency: usize,
+    mut on_batch: impl FnMut(Vec<String>, Vec<(String, String)>),
+) {
+    let mut results = stream::iter(keys.chunks(DELETE_BATCH_SIZE))
+        .map(|batch| async move {
+            let result = with_reconnect(app_state, |client| async move {
+                client.delete_objects(batch).await
+            })
+            .await;
+            (batch, result)
+        })
+        .buffered(concurrency);
+
+    while let Some((batch, result)) = results.next().await {
+        match result {
+            Ok(outcome) => on_batch(outcome.deleted, outcome.failed),
+            Err(e) => on_batch(
+                Vec::new(),
+                batch
+                    .iter()
+                    .map(|key| (key.clone(), e.to_string()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// Keys a folder delete would remove, listed before the user commits to it.
+#[derive(Clone)]
+struct FolderPreview {
+    prefix: String,
+    keys: Option<Result<Vec<String>, String>>, // None while listing
+}
+
+/// A folder delete waiting for the user to type the folder name.
+struct FolderDeleteConfirm {
+    prefix: String,
+    typed: String,
 }
 
 #[derive(Clone, Default)]
@@ -18,6 +93,7 @@ struct BucketState {
     loading: bool,
     error: Option<String>,
     last_refresh: Option<std::time::Instant>,
+    sorted_by: Option<ObjectSort>, // None until the listed objects are sorted
 }
 
 pub struct BucketTab {
@@ -26,9 +102,17 @@ pub struct BucketTab {
     bucket_state: Arc<Mutex<BucketState>>,
     selected_objects: Vec<String>,
     filter_prefix: String,
+    sort: ObjectSort,
     folder_to_delete: String,
     needs_refresh: bool,
     delete_in_progress: Arc<Mutex<bool>>,
+    folder_preview: Arc<Mutex<Option<FolderPreview>>>,
+    folder_delete_confirm: Option<FolderDeleteConfirm>, // Confirmation window open
+    recently_deleted: Arc<Mutex<Vec<String>>>,
+    large_download: Arc<Mutex<Option<LargeDownload>>>, // Awaiting confirmation
+    preview: Arc<Mutex<Option<Preview>>>,
+    inspection: Arc<Mutex<Option<Inspection>>>,
+    batch_delete: Arc<Mutex<Option<BatchDelete>>>,
 }
 
 impl BucketTab {
@@ -39,9 +123,17 @@ impl BucketTab {
             bucket_state: Arc::new(Mutex::new(BucketState::default())),
             selected_objects: Vec::new(),
             filter_prefix: String::new(),
+            sort: ObjectSort::Key,
             folder_to_delete: String::new(),
             needs_refresh: true,
             delete_in_progress: Arc::new(Mutex::new(false)),
+            folder_preview: Arc::new(Mutex::new(None)),
+            folder_delete_confirm: None,
+            recently_deleted: Arc::new(Mutex::new(Vec::new())),
+            large_download: Arc::new(Mutex::new(None)),
+            preview: Arc::new(Mutex::new(None)),
+            inspection: Arc::new(Mutex::new(None)),
+            batch_delete: Arc::new(Mutex::new(None)),
         }
     }
 
@@ -60,6 +152,54 @@ impl BucketTab {
             return;
         }
 
+        let pending = self.large_download.lock().unwrap().clone();
+        if let Some(download) = pending {
+            match large_download::show_confirmation(ctx, &download) {
+                Confirmation::Pending => {}
+                Confirmation::Confirmed => {
+                    *self.large_download.lock().unwrap() = None;
+                    self.download_object(download.description, true, ctx);
+                }
+                Confirmation::Cancelled => {
+                    *self.large_download.lock().unwrap() = None;
+                    self.state.lock().unwrap().status_message = "Download cancelled".to_string();
+                }
+            }
+        }
+
+        if let Some(mut confirm) = self.folder_delete_confirm.take() {
+            let preview = self.folder_preview.lock().unwrap().clone();
+            let keys = preview
+                .filter(|p| p.prefix == confirm.prefix)
+                .and_then(|p| p.keys);
+            match show_folder_delete_confirmation(ctx, &mut confirm, keys.as_ref()) {
+                Confirmation::Pending => self.folder_delete_confirm = Some(confirm),
+                Confirmation::Confirmed => {
+                    if let Some(Ok(keys)) = keys {
+                        self.delete_folder(confirm.prefix, keys, ctx);
+                    }
+                }
+                Confirmation::Cancelled => {
+                    self.state.lock().unwrap().status_message =
+                        "Folder delete cancelled".to_string();
+                }
+            }
+        }
+
+        let shown_preview = self.preview.lock().unwrap().clone();
+        if let Some(shown) = shown_preview {
+            if !preview::show_preview(ctx, &shown) {
+                *self.preview.lock().unwrap() = None;
+            }
+        }
+
+        let shown_inspection = self.inspection.lock().unwrap().clone();
+        if let Some(shown) = shown_inspection {
+            if !inspect::show_inspection(ctx, &shown) {
+                *self.inspection.lock().unwrap() = None;
+            }
+        }
+
         // Auto-refresh logic: refresh if needed and not already loading
         if self.needs_refresh {
             let is_loading = self.bucket_state.lock().unwrap().loading;
@@ -69,8 +209,15 @@ impl BucketTab {
             }
         }
 
-        // Get current state
-        let state = self.bucket_state.lock().unwrap().clone();
+        // Get current state, sorting newly listed objects once rather than every frame
+        let state = {
+            let mut state = self.bucket_state.lock().unwrap();
+            if state.sorted_by != Some(self.sort) {
+                sort_objects(&mut state.objects, self.sort);
+                state.sorted_by = Some(self.sort);
+            }
+            state.clone()
+        };
 
         ui.horizontal(|ui| {
             ui.label("Filter prefix:");
@@ -96,19 +243,29 @@ impl BucketTab {
 
             ui.separator();
 
-            if !self.selected_objects.is_empty() {
-                if ui
-                    .button(format!(
-                        "🗑️ Delete Selected ({})",
-                        self.selected_objects.len()
-                    ))
+            let batch_running = self
+                .batch_delete
+                .lock()
+                .unwrap()
+                .as_ref()
+                .is_some_and(|batch| batch.running);
+            if !self.selected_objects.is_empty()
+                && ui
+                    .add_enabled(
+                        !batch_running,
+                        egui::Button::new(format!(
+                            "🗑️ Delete Selected ({})",
+                            self.selected_objects.len()
+                        )),
+                    )
                     .clicked()
-                {
-                    self.delete_selected(ctx);
-                }
+            {
+                self.delete_selected(ctx);
             }
         });
 
+        self.show_batch_delete(ui, ctx);
+
         // Show any errors
         if let Some(error) = &state.error {
             ui.colored_label(egui::Color32::RED, format!("Error: {}", error));
@@ -120,7 +277,7 @@ impl BucketTab {
         ui.separator();
         ui.collapsing("🗂️ Folder Operations", |ui| {
             // Extract folders from current objects
-            let mut folders = std::collections::HashSet::new();
+            let mut folders = HashSet::new();
             for obj in &state.objects {
                 if let Some(pos) = obj.key.rfind('/') {
                     let folder = &obj.key[..=pos];
@@ -169,18 +326,94 @@ impl BucketTab {
                 ctx.request_repaint_after(std::time::Duration::from_millis(100));
             } else {
                 let can_delete = !self.folder_to_delete.is_empty();
-                if ui
-                    .add_enabled(can_delete, egui::Button::new("🗑️ Delete Entire Folder"))
-                    .on_hover_text("⚠️ This will delete ALL files with this prefix!")
-                    .clicked()
-                {
-                    self.delete_folder(ctx);
+                ui.horizontal(|ui| {
+                    if ui
+                        .add_enabled(can_delete, egui::Button::new("👁 Preview"))
+                        .on_hover_text("List the objects this prefix matches without deleting them")
+                        .clicked()
+                    {
+                        self.preview_folder_delete(ctx);
+                    }
+                    if ui
+                        .add_enabled(can_delete, egui::Button::new("🗑️ Delete Entire Folder"))
+                        .on_hover_text("⚠️ This will delete ALL files with this prefix!")
+                        .clicked()
+                    {
+                        self.request_folder_delete(ctx);
+                    }
+                });
+            }
+
+            // Only show a preview for the prefix currently entered
+            let preview = self.folder_preview.lock().unwrap().clone();
+            if let Some(preview) = preview.filter(|p| p.prefix == self.folder_to_delete) {
+                match &preview.keys {
+                    None => {
+                        ui.horizontal(|ui| {
+                            ui.spinner();
+                            ui.label("Listing matching objects...");
+                        });
+                    }
+                    Some(Err(e)) => {
+                        ui.colored_label(egui::Color32::RED, format!("✗ Preview failed: {}", e));
+                    }
+                    Some(Ok(keys)) => {
+                        ui.label(format!(
+                            "{} objects would be deleted from '{}':",
+                            keys.len(),
+                            preview.prefix
+                        ));
+                        egui::ScrollArea::vertical()
+                            .id_salt("folder_delete_preview")
+                            .max_height(150.0)
+                            .show(ui, |ui| {
+                                for key in keys {
+                                    ui.monospace(key);
+                                }
+                            });
+                    }
                 }
             }
         });
         ui.separator();
 
-        ui.label(format!("Total objects: {}", state.objects.len()));
+        let recently_deleted = self.recently_deleted.lock().unwrap().clone();
+        if !recently_deleted.is_empty() {
+            ui.collapsing(
+                format!("↩ Recently deleted ({})", recently_deleted.len()),
+                |ui| {
+                    egui::ScrollArea::vertical()
+                        .id_salt("recently_deleted")
+                        .max_height(120.0)
+                        .show(ui, |ui| {
+                            for key in &recently_deleted {
+                                ui.horizontal(|ui| {
+                                    if ui
+                                        .small_button("↩ Undo Delete")
+                                        .on_hover_text(
+                                            "Remove the delete marker (versioned buckets only)",
+                                        )
+                                        .clicked()
+                                    {
+                                        self.restore_object(key.clone(), ctx);
+                                    }
+                                    ui.label(key);
+                                });
+                            }
+                        });
+                },
+            );
+            ui.separator();
+        }
+
+        ui.horizontal(|ui| {
+            ui.label(format!("Total objects: {}", state.objects.len()));
+            ui.separator();
+            ui.label("Sort by:");
+            ui.selectable_value(&mut self.sort, ObjectSort::Key, "Name");
+            ui.selectable_value(&mut self.sort, ObjectSort::Size, "Size");
+            ui.selectable_value(&mut self.sort, ObjectSort::Modified, "Last modified");
+        });
 
         ui.separator();
 
@@ -190,11 +423,13 @@ impl BucketTab {
             } else {
                 egui::Grid::new("bucket_grid")
                     .striped(true)
-                    .num_columns(3)
+                    .num_columns(5)
                     .spacing([40.0, 4.0])
                     .show(ui, |ui| {
                         ui.strong("Select");
                         ui.strong("Object Key");
+                        ui.strong("Size");
+                        ui.strong("Last Modified");
                         ui.strong("Actions");
                         ui.end_row();
 
@@ -214,24 +449,47 @@ impl BucketTab {
 
                             // Show object key with encryption indicator
                             ui.horizontal(|ui| {
-                                if obj.key.ends_with(".pgp") {
+                                if let Some(display_name) =
+                                    encryption_filter::strip_encrypted_extension(&obj.key)
+                                {
                                     ui.colored_label(egui::Color32::from_rgb(255, 200, 0), "🔐");
-                                    // Show original filename without .pgp extension
-                                    let display_name = if obj.key.ends_with(".pgp") {
-                                        &obj.key[..obj.key.len() - 4]
-                                    } else {
-                                        &obj.key
-                                    };
+                                    // Show original filename without the encrypted extension
                                     ui.label(format!("{} (encrypted)", display_name));
                                 } else {
                                     ui.label(&obj.key);
                                 }
                             });
 
+                            ui.label(obj.size.map(format_size).unwrap_or_default());
+                            ui.label(
+                                obj.last_modified
+                                    .as_deref()
+                                    .map(format_last_modified)
+                                    .unwrap_or_default(),
+                            );
+
                             ui.horizontal(|ui| {
+                                if ui.small_button("👁").on_hover_text("Preview").clicked() {
+                                    actions_to_perform.push(("preview", obj.key.clone()));
+                                }
+                                if encryption_filter::is_encrypted_extension(&obj.key)
+                                    && ui
+                                        .small_button("🔍")
+                                        .on_hover_text("Inspect recipients and cipher")
+                                        .clicked()
+                                {
+                                    actions_to_perform.push(("inspect", obj.key.clone()));
+                                }
                                 if ui.small_button("⬇️").on_hover_text("Download").clicked() {
                                     actions_to_perform.push(("download", obj.key.clone()));
                                 }
+                                if ui
+                                    .small_button("🔗")
+                                    .on_hover_text("Copy a share link valid for 24 hours")
+                                    .clicked()
+                                {
+                                    actions_to_perform.push(("share", obj.key.clone()));
+                                }
                                 if ui.small_button("🗑️").on_hover_text("Delete").clicked() {
                                     actions_to_perform.push(("delete", obj.key.clone()));
                                 }
@@ -243,7 +501,10 @@ impl BucketTab {
                         // Perform actions after iteration
                         for (action, key) in actions_to_perform {
                             match action {
-                                "download" => self.download_object(key),
+                                "preview" => self.preview_object(key, ctx),
+                                "inspect" => self.inspect_object(key, ctx),
+                                "download" => self.download_object(key, false, ctx),
+                                "share" => self.copy_share_link(key, ctx),
                                 "delete" => self.delete_object(key, ctx),
                                 _ => {}
                             }
@@ -279,26 +540,27 @@ impl BucketTab {
                 // Small delay to show loading state
                 tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
 
-                let result = if let Some(client) = app_state.lock().unwrap().r2_client.clone() {
-                    client.list_objects(prefix.as_deref()).await
-                } else {
-                    Err(anyhow::anyhow!("No R2 client connected"))
-                };
+                let prefix = prefix.as_deref();
+                let result = with_reconnect(&app_state, |client| async move {
+                    client.list_objects_detailed(prefix).await
+                })
+                .await;
 
                 // Update state based on result
                 let mut state = bucket_state.lock().unwrap();
                 match result {
-                    Ok(keys) => {
-                        state.objects = keys
+                    Ok(objects) => {
+                        state.objects = objects
                             .into_iter()
-                            .map(|key| BucketObject {
-                                key,
-                                size: None,
-                                last_modified: None,
+                            .map(|object| BucketObject {
+                                key: object.key,
+                                size: Some(object.size),
+                                last_modified: object.last_modified,
                             })
                             .collect();
                         state.error = None;
                         state.last_refresh = Some(std::time::Instant::now());
+                        state.sorted_by = None;
 
                         // Update app status
                         let mut app = app_state.lock().unwrap();
@@ -320,11 +582,54 @@ impl BucketTab {
         });
     }
 
-    fn delete_folder(&mut self, ctx: &egui::Context) {
+    /// List the keys under `folder_to_delete` so the user can see what a folder delete
+    /// would remove. Nothing is deleted.
+    fn preview_folder_delete(&mut self, ctx: &egui::Context) {
+        let folder_prefix = self.folder_to_delete.clone();
+        *self.folder_preview.lock().unwrap() = Some(FolderPreview {
+            prefix: folder_prefix.clone(),
+            keys: None,
+        });
+
+        let app_state = self.state.clone();
+        let runtime = self.runtime.clone();
+        let folder_preview = self.folder_preview.clone();
+        let ctx = ctx.clone();
+
+        std::thread::spawn(move || {
+            runtime.block_on(async {
+                let prefix = folder_prefix.as_str();
+                let keys = with_reconnect(&app_state, |client| async move {
+                    client.list_objects(Some(prefix)).await
+                })
+                .await
+                .map_err(|e| e.to_string());
+
+                // A newer preview for another prefix wins
+                let mut preview = folder_preview.lock().unwrap();
+                if let Some(preview) = preview.as_mut().filter(|p| p.prefix == folder_prefix) {
+                    preview.keys = Some(keys);
+                }
+                ctx.request_repaint();
+            });
+        });
+    }
+
+    /// Open the confirmation window for deleting `folder_to_delete`, counting its objects
+    /// in the background.
+    fn request_folder_delete(&mut self, ctx: &egui::Context) {
         if self.folder_to_delete.is_empty() {
             return;
         }
+        self.preview_folder_delete(ctx);
+        self.folder_delete_confirm = Some(FolderDeleteConfirm {
+            prefix: self.folder_to_delete.clone(),
+            typed: String::new(),
+        });
+    }
 
+    /// Delete `objects`, the keys under `folder_prefix` the user confirmed.
+    fn delete_folder(&mut self, folder_prefix: String, objects: Vec<String>, ctx: &egui::Context) {
         // Check if already deleting
         {
             let mut deleting = self.delete_in_progress.lock().unwrap();
@@ -333,82 +638,101 @@ impl BucketTab {
             }
             *deleting = true;
         }
+        // The listing is about to change
+        *self.folder_preview.lock().unwrap() = None;
 
         let app_state = self.state.clone();
         let runtime = self.runtime.clone();
         let bucket_state = self.bucket_state.clone();
-        let folder_prefix = self.folder_to_delete.clone();
         let ctx = ctx.clone();
         let delete_in_progress = self.delete_in_progress.clone();
+        let recently_deleted = self.recently_deleted.clone();
 
         std::thread::spawn(move || {
             runtime.block_on(async {
-                // First, list all objects with the prefix
-                let objects_to_delete = async {
-                    let client = app_state
-                        .lock()
-                        .unwrap()
-                        .r2_client
-                        .clone()
-                        .ok_or_else(|| anyhow::anyhow!("No R2 client available"))?;
-
-                    let objects = client.list_objects(Some(&folder_prefix)).await?;
-                    Ok::<Vec<String>, anyhow::Error>(objects)
-                }
+                // Make sure every object can be deleted before deleting any of them, so a
+                // missing permission doesn't leave the folder half deleted
+                let folder_prefix = folder_prefix.as_str();
+                let permitted = with_reconnect(&app_state, |client| async move {
+                    client
+                        .check_permissions(
+                            folder_prefix,
+                            &[Permission::List, Permission::Read, Permission::Delete],
+                        )
+                        .await
+                })
                 .await;
+                if let Err(e) = permitted {
+                    app_state.lock().unwrap().status_message =
+                        format!("✗ Folder not deleted: {}", e);
+                    *delete_in_progress.lock().unwrap() = false;
+                    ctx.request_repaint();
+                    return;
+                }
 
-                match objects_to_delete {
-                    Ok(objects) => {
-                        let total = objects.len();
-                        let mut deleted = 0;
-                        let mut failed = 0;
+                let total = objects.len();
+                let mut deleted = 0;
+                let mut failed = 0;
 
-                        // Update status
-                        {
-                            let mut app = app_state.lock().unwrap();
-                            app.status_message = format!(
-                                "Deleting {} objects from folder '{}'...",
-                                total, folder_prefix
-                            );
-                        }
+                // Update status
+                {
+                    let mut app = app_state.lock().unwrap();
+                    app.status_message = format!(
+                        "Deleting {} objects from folder '{}'...",
+                        total, folder_prefix
+                    );
+                }
 
-                        // Delete each object
-                        for key in objects {
-                            if let Some(client) = app_state.lock().unwrap().r2_client.clone() {
-                                match client.delete_object(&key).await {
-                                    Ok(_) => {
-                                        deleted += 1;
-                                        // Remove from bucket state
-                                        let mut state = bucket_state.lock().unwrap();
-                                        state.objects.retain(|obj| obj.key != key);
-                                    }
-                                    Err(e) => {
-                                        // Failed to delete object
-                                        failed += 1;
-                                    }
-                                }
-                            }
+                // Delete up to DELETE_BATCH_LIMIT objects per request
+                for batch in objects.chunks(DELETE_BATCH_LIMIT) {
+                    let result = with_reconnect(&app_state, |client| async move {
+                        client.delete_objects(batch).await
+                    })
+                    .await;
+                    match result {
+                        Ok(outcome) => {
+                            deleted += outcome.deleted.len();
+                            failed += outcome.failed.len();
+                            // Remove from bucket state
+                            let removed: HashSet<&str> =
+                                outcome.deleted.iter().map(String::as_str).collect();
+                            bucket_state
+                                .lock()
+                                .unwrap()
+                                .objects
+                                .retain(|obj| !removed.contains(obj.key.as_str()));
+                            let mut recent = recently_deleted.lock().unwrap();
+                            recent.retain(|k| !removed.contains(k.as_str()));
+                            recent.extend(outcome.deleted.iter().cloned());
                         }
-
-                        // Update final status
-                        {
-                            let mut app = app_state.lock().unwrap();
-                            if failed == 0 {
-                                app.status_message = format!(
-                                    "✓ Deleted {} objects from folder '{}'",
-                                    deleted, folder_prefix
-                                );
-                            } else {
-                                app.status_message = format!(
-                                    "Deleted {} objects, {} failed from folder '{}'",
-                                    deleted, failed, folder_prefix
-                                );
-                            }
+                        Err(_) => {
+                            // The whole request failed, so none of the batch was deleted
+                            failed += batch.len();
                         }
                     }
-                    Err(e) => {
-                        let mut app = app_state.lock().unwrap();
-                        app.status_message = format!("✗ Failed to list folder contents: {}", e);
+
+                    app_state.lock().unwrap().status_message = format!(
+                        "Deleting {} objects from folder '{}'... {} done",
+                        total,
+                        folder_prefix,
+                        deleted + failed
+                    );
+                    ctx.request_repaint();
+                }
+
+                // Update final status
+                {
+                    let mut app = app_state.lock().unwrap();
+                    if failed == 0 {
+                        app.status_message = format!(
+                            "✓ Deleted {} objects from folder '{}'",
+                            deleted, folder_prefix
+                        );
+                    } else {
+                        app.status_message = format!(
+                            "Deleted {} objects, {} failed from folder '{}'",
+                            deleted, failed, folder_prefix
+                        );
                     }
                 }
 
@@ -422,6 +746,7 @@ impl BucketTab {
         let app_state = self.state.clone();
         let runtime = self.runtime.clone();
         let bucket_state = self.bucket_state.clone();
+        let recently_deleted = self.recently_deleted.clone();
         let ctx = ctx.clone();
         let key_clone = key.clone();
 
@@ -433,11 +758,11 @@ impl BucketTab {
 
         std::thread::spawn(move || {
             runtime.block_on(async {
-                let result = if let Some(client) = app_state.lock().unwrap().r2_client.clone() {
-                    client.delete_object(&key_clone).await
-                } else {
-                    Err(anyhow::anyhow!("No R2 client available"))
-                };
+                let key = key_clone.as_str();
+                let result = with_reconnect(&app_state, |client| async move {
+                    client.delete_object(key).await
+                })
+                .await;
 
                 match result {
                     Ok(_) => {
@@ -446,6 +771,11 @@ impl BucketTab {
                             let mut state = bucket_state.lock().unwrap();
                             state.objects.retain(|obj| obj.key != key_clone);
                         }
+                        {
+                            let mut deleted = recently_deleted.lock().unwrap();
+                            deleted.retain(|k| k != &key_clone);
+                            deleted.push(key_clone.clone());
+                        }
 
                         // Update status
                         {
@@ -464,36 +794,324 @@ impl BucketTab {
         });
     }
 
+    /// Switch `key` between the private and public-read canned ACLs.
+    /// Read the start of encrypted object `key` and show who it is encrypted to.
+    fn inspect_object(&self, key: String, ctx: &egui::Context) {
+        let app_state = self.state.clone();
+        let runtime = self.runtime.clone();
+        let shown_inspection = self.inspection.clone();
+        let ctx = ctx.clone();
+
+        app_state.lock().unwrap().status_message = format!("Inspecting {}...", key);
+
+        std::thread::spawn(move || {
+            runtime.block_on(async {
+                let key_ref = key.as_str();
+                let result = with_reconnect(&app_state, |client| async move {
+                    client
+                        .download_range(key_ref, 0, inspect::HEADER_BYTES - 1)
+                        .await
+                })
+                .await;
+
+                match result {
+                    Ok(header) => {
+                        let pgp_handler = app_state.lock().unwrap().pgp_handler.clone();
+                        let inspection =
+                            Inspection::new(key.clone(), &header, &pgp_handler.lock().unwrap());
+                        *shown_inspection.lock().unwrap() = Some(inspection);
+                        app_state.lock().unwrap().status_message = format!("Inspected {}", key);
+                    }
+                    Err(e) => {
+                        app_state.lock().unwrap().status_message =
+                            format!("✗ Failed to inspect {}: {:#}", key, e);
+                    }
+                }
+                ctx.request_repaint();
+            });
+        });
+    }
+
+    // R2 has no per-object ACLs, so a presigned GET is how one object gets shared
+    fn copy_share_link(&mut self, key: String, ctx: &egui::Context) {
+        let mut app = self.state.lock().unwrap();
+        let url = app
+            .r2_client
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("No R2 client available"))
+            .and_then(|client| client.presign_get_url(&key, SHARE_LINK_EXPIRY));
+        app.status_message = match url {
+            Ok(url) => {
+                ctx.output_mut(|o| o.copied_text = url);
+                format!("✓ Copied a share link for {} (valid for 24 hours)", key)
+            }
+            Err(e) => format!("✗ Failed to create a share link for {}: {:#}", key, e),
+        };
+    }
+
+    fn restore_object(&mut self, key: String, ctx: &egui::Context) {
+        let app_state = self.state.clone();
+        let runtime = self.runtime.clone();
+        let recently_deleted = self.recently_deleted.clone();
+        let ctx = ctx.clone();
+
+        {
+            let mut app = app_state.lock().unwrap();
+            app.status_message = format!("Restoring {}...", key);
+        }
+
+        std::thread::spawn(move || {
+            runtime.block_on(async {
+                let key_ref = key.as_str();
+                let result = with_reconnect(&app_state, |client| async move {
+                    client.restore_object(key_ref).await
+                })
+                .await;
+
+                match result {
+                    Ok(_) => {
+                        recently_deleted.lock().unwrap().retain(|k| k != &key);
+                        let mut app = app_state.lock().unwrap();
+                        app.status_message = format!("✓ Restored: {}", key);
+                    }
+                    Err(e) => {
+                        let mut app = app_state.lock().unwrap();
+                        app.status_message = format!("✗ Failed to restore {}: {:#}", key, e);
+                    }
+                }
+
+                ctx.request_repaint();
+            });
+        });
+    }
+
+    /// Progress bar for a running "Delete Selected", then the keys that failed.
+    fn show_batch_delete(&mut self, ui: &mut egui::Ui, ctx: &egui::Context) {
+        let Some(batch) = self.batch_delete.lock().unwrap().clone() else {
+            return;
+        };
+        let done = batch.deleted + batch.failed.len();
+
+        if batch.running {
+            ui.add(
+                egui::ProgressBar::new(done as f32 / batch.total.max(1) as f32)
+                    .text(format!("Deleted {}/{}", batch.deleted, batch.total)),
+            );
+            ctx.request_repaint_after(std::time::Duration::from_millis(100));
+        } else if !batch.failed.is_empty() {
+            let mut dismissed = false;
+            ui.collapsing(
+                format!("✗ {} of {} deletes failed", batch.failed.len(), batch.total),
+                |ui| {
+                    egui::ScrollArea::vertical()
+                        .id_salt("failed_deletes")
+                        .max_height(120.0)
+                        .show(ui, |ui| {
+                            for (key, reason) in &batch.failed {
+                                ui.label(format!("{}: {}", key, reason));
+                            }
+                        });
+                    dismissed = ui.small_button("Dismiss").clicked();
+                },
+            );
+            if dismissed {
+                *self.batch_delete.lock().unwrap() = None;
+            }
+        }
+    }
+
+    /// Delete the selected objects in batches of `DELETE_BATCH_SIZE` keys, in key order,
+    /// with at most `gui.delete_concurrency` requests in flight.
     fn delete_selected(&mut self, ctx: &egui::Context) {
-        let keys_to_delete = self.selected_objects.clone();
-        for key in keys_to_delete {
-            self.delete_object(key, ctx);
+        let mut keys = std::mem::take(&mut self.selected_objects);
+        keys.sort();
+        let total = keys.len();
+
+        let app_state = self.state.clone();
+        let runtime = self.runtime.clone();
+        let bucket_state = self.bucket_state.clone();
+        let recently_deleted = self.recently_deleted.clone();
+        let batch_delete = self.batch_delete.clone();
+        let ctx = ctx.clone();
+        let concurrency = app_state
+            .lock()
+            .unwrap()
+            .config
+            .gui
+            .delete_concurrency
+            .max(1);
+
+        *batch_delete.lock().unwrap() = Some(BatchDelete {
+            total,
+            running: true,
+            ..Default::default()
+        });
+        app_state.lock().unwrap().status_message = format!("Deleting {} objects...", total);
+
+        std::thread::spawn(move || {
+            runtime.block_on(async {
+                delete_in_batches(&app_state, &keys, concurrency, |deleted, failed| {
+                    let removed: HashSet<&str> = deleted.iter().map(String::as_str).collect();
+                    bucket_state
+                        .lock()
+                        .unwrap()
+                        .objects
+                        .retain(|obj| !removed.contains(obj.key.as_str()));
+                    {
+                        let mut recent = recently_deleted.lock().unwrap();
+                        recent.retain(|k| !removed.contains(k.as_str()));
+                        recent.extend(deleted.iter().cloned());
+                    }
+                    if let Some(progress) = batch_delete.lock().unwrap().as_mut() {
+                        progress.deleted += deleted.len();
+                        progress.failed.extend(failed);
+                    }
+                    ctx.request_repaint();
+                })
+                .await;
+
+                let mut batch_guard = batch_delete.lock().unwrap();
+                if let Some(progress) = batch_guard.as_mut() {
+                    progress.running = false;
+                    app_state.lock().unwrap().status_message = if progress.failed.is_empty() {
+                        format!("✓ Deleted {} objects", progress.deleted)
+                    } else {
+                        format!(
+                            "⚠ Deleted {} of {} objects, {} failed",
+                            progress.deleted,
+                            progress.total,
+                            progress.failed.len()
+                        )
+                    };
+                    if progress.failed.is_empty() {
+                        *batch_guard = None;
+                    }
+                }
+                ctx.request_repaint();
+            });
+        });
+    }
+
+    /// Show `key` in the preview window, reusing the cached content if its ETag is unchanged.
+    fn preview_object(&self, key: String, ctx: &egui::Context) {
+        let (max_bytes, max_pixels) = {
+            let app_state = self.state.lock().unwrap();
+            (
+                app_state.config.gui.preview_max_bytes(),
+                app_state.config.gui.preview_max_pixels(),
+            )
+        };
+        let known_size = self
+            .bucket_state
+            .lock()
+            .unwrap()
+            .objects
+            .iter()
+            .find(|obj| obj.key == key)
+            .and_then(|obj| obj.size);
+        if known_size.is_some_and(|size| size > max_bytes) {
+            self.state.lock().unwrap().status_message = format!(
+                "{} is too large to preview (over {})",
+                key,
+                format_size(max_bytes)
+            );
+            return;
         }
-        self.selected_objects.clear();
+        self.state.lock().unwrap().status_message = format!("Loading preview of {}...", key);
+
+        let state = self.state.clone();
+        let runtime = self.runtime.clone();
+        let shown_preview = self.preview.clone();
+        let ctx = ctx.clone();
+
+        std::thread::spawn(move || {
+            runtime.block_on(async {
+                let status = match preview::fetch_preview(&state, &key).await {
+                    Ok(fetched) => {
+                        let status = if fetched.from_cache {
+                            format!("Previewing {} (cached)", key)
+                        } else if fetched.encrypted && !fetched.decrypted {
+                            format!("⚠ Showing {} encrypted (no key to decrypt it)", key)
+                        } else {
+                            format!("Previewing {}", key)
+                        };
+                        *shown_preview.lock().unwrap() = Some(Preview {
+                            key: key.clone(),
+                            image: preview::load_image(&ctx, &key, &fetched.content, max_pixels),
+                            content: fetched.content,
+                            decrypted: fetched.decrypted,
+                            from_cache: fetched.from_cache,
+                        });
+                        status
+                    }
+                    Err(e) => format!("✗ Preview of {} failed: {:#}", key, e),
+                };
+                state.lock().unwrap().status_message = status;
+                ctx.request_repaint();
+            });
+        });
     }
 
-    fn download_object(&self, key: String) {
+    /// Download `key` to a location picked by the user. Unless `confirmed`, objects
+    /// over the size threshold are held back for the user to confirm first.
+    fn download_object(&self, key: String, confirmed: bool, ctx: &egui::Context) {
+        // The listing usually knows the size; otherwise it is checked after the HEAD below
+        let known_size = self
+            .bucket_state
+            .lock()
+            .unwrap()
+            .objects
+            .iter()
+            .find(|obj| obj.key == key)
+            .and_then(|obj| obj.size);
+        if let Some(size) = large_download::size_to_confirm(&self.state, known_size, confirmed) {
+            *self.large_download.lock().unwrap() = Some(LargeDownload {
+                description: key,
+                size,
+            });
+            return;
+        }
+        let check_head_size = !confirmed && known_size.is_none();
+
         // Update status immediately
         {
             let mut app = self.state.lock().unwrap();
             app.status_message = format!("Preparing to download {}...", key);
         }
 
-        // Extract just the filename from the key for the save dialog
-        let mut filename = key.rsplit('/').next().unwrap_or(&key).to_string();
-        
-        // If it's a .pgp file, suggest removing the extension for the saved file
-        if filename.ends_with(".pgp") || filename.ends_with(".gpg") {
-            filename = filename[..filename.len() - 4].to_string();
-        }
-        
         // Clone everything we need before the dialog
         let state = self.state.clone();
         let runtime = self.runtime.clone();
         let key_clone = key.clone();
+        let large_download = self.large_download.clone();
+        let ctx = ctx.clone();
 
         // Show file dialog in a non-blocking way
         std::thread::spawn(move || {
+            let client = state.lock().unwrap().r2_client.clone();
+            let head =
+                client.and_then(|client| runtime.block_on(client.head_object(&key_clone)).ok());
+
+            let size = large_download::size_to_confirm(
+                &state,
+                head.as_ref().and_then(|head| head.content_length),
+                !check_head_size,
+            );
+            if let Some(size) = size {
+                *large_download.lock().unwrap() = Some(LargeDownload {
+                    description: key_clone,
+                    size,
+                });
+                state.lock().unwrap().status_message = "Waiting for confirmation".to_string();
+                ctx.request_repaint();
+                return;
+            }
+
+            // Suggest the filename recorded at upload, else the key without .pgp/.gpg
+            let restored_name = head.and_then(|head| head.original_filename());
+            let filename =
+                rust_r2::transfer::download_file_name(&key_clone, restored_name.as_deref(), true);
+
             // File dialog must be called from a thread
             if let Some(path) = rfd::FileDialog::new().set_file_name(&filename).save_file() {
                 // Update status
@@ -502,10 +1120,9 @@ impl BucketTab {
                     app.status_message = format!("Downloading {}...", key_clone);
                 }
 
-                // Get the client before spawning
-                let client = state.lock().unwrap().r2_client.clone();
-                
-                if let Some(client) = client {
+                let connected = state.lock().unwrap().r2_client.is_some();
+
+                if connected {
                     let state_clone = state.clone();
                     let key_for_download = key_clone.clone();
                     let path_string = path.to_string_lossy().to_string();
@@ -514,13 +1131,18 @@ impl BucketTab {
                     let handle = runtime.handle().clone();
                     
                     handle.spawn(async move {
-                        match client.download_object(&key_for_download).await {
+                        let key = key_for_download.as_str();
+                        let result = with_reconnect(&state_clone, |client| async move {
+                            client.download_object(key).await
+                        })
+                        .await;
+                        match result {
                             Ok(data) => {
                                 // Check if it's encrypted and auto-decrypt if we have keys
-                                let is_encrypted = key_for_download.ends_with(".pgp") || 
-                                                  key_for_download.ends_with(".gpg") ||
-                                                  rust_r2::crypto::PgpHandler::is_pgp_encrypted(&data);
-                                
+                                let is_encrypted =
+                                    encryption_filter::is_encrypted_extension(&key_for_download)
+                                        || rust_r2::crypto::PgpHandler::is_pgp_encrypted(&data);
+
                                 let final_data = if is_encrypted {
                                     // Try to decrypt
                                     let pgp_handler = state_clone.lock().unwrap().pgp_handler.clone();
@@ -587,3 +1209,125 @@ impl BucketTab {
         });
     }
 }
+
+fn sort_objects(objects: &mut [BucketObject], sort: ObjectSort) {
+    match sort {
+        ObjectSort::Key => objects.sort_by(|a, b| a.key.cmp(&b.key)),
+        ObjectSort::Size => objects.sort_by_key(|o| std::cmp::Reverse(o.size)),
+        // ISO 8601 timestamps in UTC sort correctly as strings
+        ObjectSort::Modified => objects.sort_by(|a, b| b.last_modified.cmp(&a.last_modified)),
+    }
+}
+
+/// An ISO 8601 timestamp from a listing in local time, or as listed if it doesn't parse.
+fn format_last_modified(timestamp: &str) -> String {
+    chrono::DateTime::parse_from_rfc3339(timestamp)
+        .map(|time| {
+            time.with_timezone(&chrono::Local)
+                .format("%Y-%m-%d %H:%M")
+                .to_string()
+        })
+        .unwrap_or_else(|_| timestamp.to_string())
+}
+
+/// Draw the confirmation window for a folder delete. The delete button only unlocks once
+/// the objects are counted and the folder name is typed exactly.
+fn show_folder_delete_confirmation(
+    ctx: &egui::Context,
+    confirm: &mut FolderDeleteConfirm,
+    keys: Option<&Result<Vec<String>, String>>,
+) -> Confirmation {
+    let mut confirmation = Confirmation::Pending;
+    egui::Window::new("⚠ Delete folder")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+        .show(ctx, |ui| {
+            let count = match keys {
+                None => {
+                    ui.horizontal(|ui| {
+                        ui.spinner();
+                        ui.label(format!("Counting objects under '{}'...", confirm.prefix));
+                    });
+                    None
+                }
+                Some(Err(e)) => {
+                    ui.colored_label(
+                        egui::Color32::RED,
+                        format!("✗ Could not list '{}': {}", confirm.prefix, e),
+                    );
+                    None
+                }
+                Some(Ok(keys)) if keys.is_empty() => {
+                    ui.label(format!("No objects under '{}'.", confirm.prefix));
+                    None
+                }
+                Some(Ok(keys)) => {
+                    ui.label(format!(
+                        "{} objects under '{}' will be permanently deleted.",
+                        keys.len(),
+                        confirm.prefix
+                    ));
+                    Some(keys.len())
+                }
+            };
+            ui.add_space(10.0);
+            ui.label("Type the folder name to confirm:");
+            ui.text_edit_singleline(&mut confirm.typed);
+            ui.add_space(10.0);
+            ui.horizontal(|ui| {
+                let typed = confirm.typed.trim() == confirm.prefix;
+                let label = format!("🗑️ Yes, delete {} objects", count.unwrap_or(0));
+                if ui
+                    .add_enabled(typed && count.is_some(), egui::Button::new(label))
+                    .clicked()
+                {
+                    confirmation = Confirmation::Confirmed;
+                }
+                if ui.button("Cancel").clicked() {
+                    confirmation = Confirmation::Cancelled;
+                }
+            });
+        });
+    confirmation
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_r2::MockR2;
+
+    #[tokio::test]
+    async fn batch_deletes_count_every_key_and_stay_within_the_concurrency() {
+        let mock = MockR2::start().await;
+        let keys: Vec<String> = (0..230).map(|i| format!("logs/{:03}.txt", i)).collect();
+        for key in &keys {
+            mock.put(key, b"log");
+        }
+        mock.refuse_delete("logs/007.txt");
+        mock.refuse_delete("logs/199.txt");
+        // Slow enough for the batches to overlap
+        mock.delay("", Duration::from_millis(50));
+        let state = Arc::new(Mutex::new(AppState {
+            r2_client: Some(Arc::new(mock.client().await)),
+            ..AppState::default()
+        }));
+
+        let mut batches = 0;
+        let mut deleted = Vec::new();
+        let mut failed = Vec::new();
+        delete_in_batches(&state, &keys, 2, |batch_deleted, batch_failed| {
+            batches += 1;
+            deleted.extend(batch_deleted);
+            failed.extend(batch_failed);
+        })
+        .await;
+
+        assert_eq!(batches, 5);
+        assert_eq!(deleted.len(), 228);
+        let failed: Vec<&str> = failed.iter().map(|(key, _)| key.as_str()).collect();
+        assert_eq!(failed, ["logs/007.txt", "logs/199.txt"]);
+        assert_eq!(mock.keys(), ["logs/007.txt", "logs/199.txt"]);
+        assert_eq!(mock.max_concurrent_requests(), 2);
+    }
+}
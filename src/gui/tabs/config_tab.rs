@@ -1,6 +1,7 @@
 use crate::app::AppState;
 use eframe::egui;
-use rust_r2::crypto::KeyInfo;
+use rust_r2::crypto::{KeyInfo, PgpHandler};
+use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 use tokio::runtime::Runtime;
 
@@ -18,6 +19,9 @@ pub struct ConfigTab {
     test_in_progress: Arc<Mutex<bool>>,
     dropped_files: Vec<egui::DroppedFile>,
     private_key_loaded_from_keyring: bool,
+    locked_keys: Vec<KeyInfo>, // Secret keys no passphrase given so far unlocks
+    locked_keys_checked: Option<Arc<Mutex<PgpHandler>>>, // The handler `locked_keys` is from
+    key_passphrase_inputs: HashMap<String, String>, // Typed passphrase per locked key fingerprint
 }
 
 impl ConfigTab {
@@ -53,6 +57,9 @@ impl ConfigTab {
             test_in_progress: Arc::new(Mutex::new(false)),
             dropped_files: Vec::new(),
             private_key_loaded_from_keyring: false,
+            locked_keys: Vec::new(),
+            locked_keys_checked: None,
+            key_passphrase_inputs: HashMap::new(),
         }
     }
     
@@ -132,6 +139,7 @@ impl ConfigTab {
             runtime.spawn(async move {
                 let config = state.lock().unwrap().config.clone();
                 
+                let client_config = config.client_config();
                 match rust_r2::r2_client::R2Client::new(
                     config.r2.access_key_id,
                     config.r2.secret_access_key,
@@ -139,6 +147,7 @@ impl ConfigTab {
                     config.r2.bucket_name.clone(),
                 )
                 .await
+                .and_then(|client| client.with_client_config(client_config))
                 {
                     Ok(client) => {
                         // Try to list objects to verify connection
@@ -342,7 +351,7 @@ impl ConfigTab {
                 if self.private_key_loaded_from_keyring {
                     status_text.push_str(" + private key");
                     ui.colored_label(egui::Color32::GREEN, format!("✓ Loaded: {}", status_text));
-                } else if self.team_keys.len() > 0 {
+                } else if !self.team_keys.is_empty() {
                     ui.label(format!("Loaded: {}", status_text));
                 } else {
                     ui.label("No keys loaded");
@@ -443,7 +452,7 @@ impl ConfigTab {
                                         self.update_pgp_handler_in_state();
                                     }
                                 }
-                                Err(e) => {
+                                Err(_) => {
                                     // Failed to parse keys
                                 }
                             }
@@ -501,7 +510,7 @@ impl ConfigTab {
                                             self.update_pgp_handler_in_state();
                                         }
                                     }
-                                    Err(e) => {
+                                    Err(_) => {
                                         // Failed to parse keys
                                     }
                                 }
@@ -526,6 +535,12 @@ impl ConfigTab {
                                 ui.strong(&info.name);
                                 ui.label(format!("<{}>", info.email));
                                 ui.label(format!("[{}]", &info.key_id[info.key_id.len().saturating_sub(8)..]));
+                                if let Some(expires_at) = info.expires_at.filter(|_| info.is_expired()) {
+                                    ui.colored_label(
+                                        egui::Color32::RED,
+                                        format!("expired {}", expires_at.format("%Y-%m-%d")),
+                                    );
+                                }
 
                                 ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
                                     if ui.button("❌").clicked() {
@@ -542,15 +557,72 @@ impl ConfigTab {
                         }
 
                         if let Some(idx) = to_remove {
-                            self.team_keys.remove(idx);
+                            let (_, info) = self.team_keys.remove(idx);
+                            // Stop encrypting to it now rather than on the next reload
+                            let pgp_handler = self.state.lock().unwrap().pgp_handler.clone();
+                            let removed =
+                                pgp_handler.lock().unwrap().remove_public_key(&info.fingerprint);
+                            if removed {
+                                self.state.lock().unwrap().status_message =
+                                    format!("Removed key {}", info.label());
+                            }
                         }
                     });
 
                 ui.separator();
                 if ui.button("Clear All Keys").clicked() {
-                    self.team_keys.clear();
+                    let pgp_handler = self.state.lock().unwrap().pgp_handler.clone();
+                    let mut handler = pgp_handler.lock().unwrap();
+                    for (_, info) in self.team_keys.drain(..) {
+                        handler.remove_public_key(&info.fingerprint);
+                    }
                 }
             }
+
+            // Unlocking is slow, so only check again once the handler is replaced
+            let pgp_handler = self.state.lock().unwrap().pgp_handler.clone();
+            let checked = self
+                .locked_keys_checked
+                .as_ref()
+                .is_some_and(|handler| Arc::ptr_eq(handler, &pgp_handler));
+            if !checked {
+                self.locked_keys = pgp_handler.lock().unwrap().locked_secret_keys();
+                self.locked_keys_checked = Some(pgp_handler);
+            }
+
+            if !self.locked_keys.is_empty() {
+                ui.separator();
+                ui.label("🔒 Secret keys that need their own passphrase:");
+                self.show_locked_keys(ui);
+            }
+        });
+
+        ui.add_space(10.0);
+
+        ui.horizontal(|ui| {
+            ui.label("Ask before downloads larger than:");
+            let mut state = self.state.lock().unwrap();
+            ui.add(
+                egui::DragValue::new(&mut state.config.gui.large_download_warning_mb).suffix(" MB"),
+            )
+            .on_hover_text("0 never asks");
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Preview objects up to:");
+            let mut state = self.state.lock().unwrap();
+            ui.add(
+                egui::DragValue::new(&mut state.config.gui.preview_max_mb)
+                    .range(1..=1024)
+                    .suffix(" MB"),
+            );
+            ui.label("and images up to:");
+            ui.add(
+                egui::DragValue::new(&mut state.config.gui.preview_max_megapixels)
+                    .range(1..=256)
+                    .suffix(" MP"),
+            )
+            .on_hover_text("Larger images are described instead of decoded");
         });
 
         ui.add_space(20.0);
@@ -602,6 +674,7 @@ impl ConfigTab {
 
         if let Some(path) = rfd::FileDialog::new()
             .add_filter("JSON", &["json"])
+            .add_filter("TOML", &["toml"])
             .set_file_name("r2-config.json")
             .save_file()
         {
@@ -615,7 +688,7 @@ impl ConfigTab {
 
     fn load_config(&mut self) {
         if let Some(path) = rfd::FileDialog::new()
-            .add_filter("JSON", &["json"])
+            .add_filter("Config", &["json", "toml"])
             .pick_file()
         {
             match rust_r2::config::Config::from_file(&path) {
@@ -649,6 +722,9 @@ impl ConfigTab {
                         }
                     }
 
+                    rust_r2::encryption_filter::set_encrypted_extensions(
+                        &config.pgp.encrypted_extensions,
+                    );
                     let mut state = self.state.lock().unwrap();
                     state.config = config;
                     // Clear the existing connection when loading new config
@@ -664,9 +740,67 @@ impl ConfigTab {
         }
     }
 
+    /// A passphrase field per locked secret key. A passphrase that unlocks its key is
+    /// applied to the live handler and kept in the config by fingerprint.
+    fn show_locked_keys(&mut self, ui: &mut egui::Ui) {
+        let mut unlocked = None;
+        for info in &self.locked_keys {
+            ui.horizontal(|ui| {
+                ui.strong(info.label());
+                let input = self
+                    .key_passphrase_inputs
+                    .entry(info.fingerprint.clone())
+                    .or_default();
+                let response = ui.add(egui::TextEdit::singleline(input).password(true));
+                let submitted =
+                    response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                if ui.button("🔓 Unlock").clicked() || submitted {
+                    let pgp_handler = self.state.lock().unwrap().pgp_handler.clone();
+                    let result = pgp_handler
+                        .lock()
+                        .unwrap()
+                        .set_key_passphrase(&info.fingerprint, input);
+                    let mut state = self.state.lock().unwrap();
+                    match result {
+                        Ok(()) => {
+                            state
+                                .config
+                                .pgp
+                                .key_passphrases
+                                .insert(info.fingerprint.clone(), input.clone());
+                            state.status_message = format!("Unlocked {}", info.label());
+                            unlocked = Some(info.fingerprint.clone());
+                        }
+                        Err(e) => {
+                            state.status_message = format!("{}: {}", info.label(), e);
+                        }
+                    }
+                }
+            });
+        }
+
+        if let Some(fingerprint) = unlocked {
+            self.locked_keys
+                .retain(|info| info.fingerprint != fingerprint);
+            self.key_passphrase_inputs.remove(&fingerprint);
+        }
+    }
+
     fn update_pgp_handler_in_state(&mut self) {
         // Update the PGP handler in AppState with the currently loaded keys
         let mut pgp_handler = rust_r2::crypto::PgpHandler::new();
+        let (cipher, skip_expired, key_passphrases) = {
+            let state = self.state.lock().unwrap();
+            (
+                state.config.pgp.cipher.clone(),
+                state.config.pgp.skip_expired_keys,
+                state.config.pgp.key_passphrases.clone(),
+            )
+        };
+        if let Some(cipher) = cipher.as_deref() {
+            pgp_handler.set_cipher_algorithm(rust_r2::crypto::parse_cipher_algorithm(cipher).ok());
+        }
+        pgp_handler.set_skip_expired(skip_expired);
 
         // Collect unique key paths
         let mut unique_paths = std::collections::HashSet::new();
@@ -686,8 +820,24 @@ impl ConfigTab {
             }
         }
 
-        // Load separate secret key if specified and not already loaded
-        if !pgp_handler.has_secret_key() && !self.secret_key_path.is_empty() {
+        // Keys from sources other than files, which the key list doesn't edit
+        let key_sources = self.state.lock().unwrap().config.pgp.key_sources.clone();
+        let pass_opt = if self.passphrase.is_empty() {
+            None
+        } else {
+            Some(self.passphrase.as_str())
+        };
+        for source in &key_sources {
+            let loaded = self
+                .runtime
+                .block_on(source.load_into(&mut pgp_handler, pass_opt));
+            if let Err(e) = loaded {
+                tracing::warn!("{:#}", e);
+            }
+        }
+
+        // Load separate secret key too; keys already in a keyring are skipped
+        if !self.secret_key_path.is_empty() {
             if let Ok(key_data) = std::fs::read(&self.secret_key_path) {
                 let pass_opt = if self.passphrase.is_empty() {
                     None
@@ -697,6 +847,7 @@ impl ConfigTab {
                 let _ = pgp_handler.load_secret_key(&key_data, pass_opt);
             }
         }
+        pgp_handler.set_key_passphrases(&key_passphrases);
 
         // Update the AppState AND the config
         let mut state = self.state.lock().unwrap();
@@ -750,6 +901,7 @@ impl ConfigTab {
 
             let config = state.lock().unwrap().config.clone();
 
+            let client_config = config.client_config();
             match rust_r2::r2_client::R2Client::new(
                 config.r2.access_key_id,
                 config.r2.secret_access_key,
@@ -757,46 +909,58 @@ impl ConfigTab {
                 config.r2.bucket_name.clone(),
             )
             .await
+            .and_then(|client| client.with_client_config(client_config))
             {
                 Ok(client) => {
                     // Try to list objects to verify connection
                     match client.list_objects(None).await {
                         Ok(_) => {
-                            let mut app_state = state.lock().unwrap();
-                            app_state.r2_client = Some(Arc::new(client));
-                            app_state.is_connected = true;
-                            app_state.status_message = "Successfully connected to R2!".to_string();
-
                             // Load PGP keys
                             let mut pgp_handler = rust_r2::crypto::PgpHandler::new();
-
-                            // Load team keys (may include keyrings with private keys)
-                            for key_path in &config.pgp.team_keys {
-                                if let Ok(key_data) = std::fs::read(key_path) {
-                                    // Try to load as keyring (handles both public and private keys)
-                                    let _ = pgp_handler
-                                        .load_keyring(&key_data, config.pgp.passphrase.as_deref());
+                            if let Some(cipher) = config.pgp.cipher.as_deref() {
+                                pgp_handler.set_cipher_algorithm(
+                                    rust_r2::crypto::parse_cipher_algorithm(cipher).ok(),
+                                );
+                            }
+                            pgp_handler.set_skip_expired(config.pgp.skip_expired_keys);
+
+                            // Load team keys and other key sources (may include keyrings
+                            // with private keys)
+                            for source in config.pgp.all_key_sources() {
+                                let loaded = source
+                                    .load_into(&mut pgp_handler, config.pgp.passphrase.as_deref())
+                                    .await;
+                                if let Err(e) = loaded {
+                                    tracing::warn!("{:#}", e);
                                 }
                             }
 
-                            // Load separate secret key if specified and not already loaded
-                            if !pgp_handler.has_secret_key() {
-                                if let Some(secret_path) = &config.pgp.secret_key_path {
-                                    if let Ok(key_data) = std::fs::read(secret_path) {
-                                        let _ = pgp_handler.load_secret_key(
-                                            &key_data,
-                                            config.pgp.passphrase.as_deref(),
-                                        );
-                                    }
+                            // Load separate secret key too; keys already in a keyring are skipped
+                            if let Some(secret_path) = &config.pgp.secret_key_path {
+                                if let Ok(key_data) = std::fs::read(secret_path) {
+                                    let _ = pgp_handler.load_secret_key(
+                                        &key_data,
+                                        config.pgp.passphrase.as_deref(),
+                                    );
                                 }
                             }
+                            pgp_handler.set_key_passphrases(&config.pgp.key_passphrases);
 
+                            let mut app_state = state.lock().unwrap();
+                            app_state.r2_client = Some(Arc::new(client));
+                            app_state.is_connected = true;
+                            app_state.status_message = "Successfully connected to R2!".to_string();
                             app_state.pgp_handler = Arc::new(Mutex::new(pgp_handler));
                         }
                         Err(e) => {
+                            // Ping to tell a wrong account, bad credentials and a missing bucket apart
+                            let reason = match client.ping().await {
+                                Ok(ping) if !ping.is_ok() => ping.diagnosis.to_string(),
+                                _ => e.to_string(),
+                            };
                             let mut app_state = state.lock().unwrap();
                             app_state.is_connected = false;
-                            app_state.status_message = format!("Connection failed: {}", e);
+                            app_state.status_message = format!("Connection failed: {}", reason);
                         }
                     }
                 }
@@ -1,6 +1,10 @@
-use crate::app::AppState;
+use crate::app::{with_reconnect, AppState};
+use crate::large_download::{self, Confirmation, LargeDownload};
+use crate::progress::GuiProgress;
 use chrono::Local;
 use eframe::egui;
+use rust_r2::encryption_filter;
+use rust_r2::folder_download::{download_prefix, FolderDownloadOptions};
 use std::collections::HashSet;
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
@@ -24,6 +28,7 @@ enum DownloadMode {
 struct FolderObject {
     key: String,
     relative_path: String,
+    size: u64,
     selected: bool,
 }
 
@@ -55,6 +60,7 @@ pub struct DownloadTab {
     needs_refresh: bool,
     download_mode: DownloadMode,
     filter_text: String,
+    large_download: Arc<Mutex<Option<(DownloadMode, LargeDownload)>>>, // Awaiting confirmation
 }
 
 impl DownloadTab {
@@ -77,6 +83,7 @@ impl DownloadTab {
             needs_refresh: true,
             download_mode: DownloadMode::SingleFile,
             filter_text: String::new(),
+            large_download: Arc::new(Mutex::new(None)),
         }
     }
 
@@ -95,6 +102,8 @@ impl DownloadTab {
             return;
         }
 
+        self.show_large_download_confirmation(ctx);
+
         // Auto-refresh on first view
         if self.needs_refresh {
             let is_loading = self.download_state.lock().unwrap().loading;
@@ -249,11 +258,12 @@ impl DownloadTab {
                         let is_selected = self.selected_object.as_ref() == Some(obj);
 
                         // Show with encryption indicator
-                        let label = if obj.ends_with(".pgp") {
-                            format!("🔐 {} (encrypted)", &obj[..obj.len() - 4])
-                        } else {
-                            obj.clone()
-                        };
+                        let label =
+                            if let Some(name) = encryption_filter::strip_encrypted_extension(obj) {
+                                format!("🔐 {} (encrypted)", name)
+                            } else {
+                                obj.clone()
+                            };
 
                         if ui.selectable_label(is_selected, label).clicked() {
                             self.selected_object = Some(obj.clone());
@@ -292,7 +302,7 @@ impl DownloadTab {
         ui.add_space(10.0);
 
         // Auto-decryption notice
-        if self.object_key.ends_with(".pgp") {
+        if encryption_filter::is_encrypted_extension(&self.object_key) {
             ui.horizontal(|ui| {
                 ui.colored_label(egui::Color32::from_rgb(255, 200, 0), "ℹ️");
                 ui.label("This file appears to be encrypted and will be auto-decrypted if you have the key.");
@@ -324,7 +334,7 @@ impl DownloadTab {
                 .add_enabled(can_download, egui::Button::new("⬇️ Download from R2"))
                 .clicked()
             {
-                self.start_single_download(ctx);
+                self.start_single_download(ctx, false);
             }
         }
     }
@@ -437,13 +447,15 @@ impl DownloadTab {
 
                                 // Show encryption indicator
                                 ui.horizontal(|ui| {
-                                    if obj.relative_path.ends_with(".pgp") {
+                                    if let Some(display_name) =
+                                        encryption_filter::strip_encrypted_extension(
+                                            &obj.relative_path,
+                                        )
+                                    {
                                         ui.colored_label(
                                             egui::Color32::from_rgb(255, 200, 0),
                                             "🔐",
                                         );
-                                        let display_name =
-                                            &obj.relative_path[..obj.relative_path.len() - 4];
                                         ui.label(format!("{} (will auto-decrypt)", display_name));
                                     } else {
                                         ui.label(&obj.relative_path);
@@ -492,7 +504,32 @@ impl DownloadTab {
                 )
                 .clicked()
             {
-                self.start_folder_download(ctx);
+                self.start_folder_download(ctx, false);
+            }
+        }
+    }
+
+    fn show_large_download_confirmation(&mut self, ctx: &egui::Context) {
+        let pending = self.large_download.lock().unwrap().clone();
+        let Some((mode, download)) = pending else {
+            return;
+        };
+
+        match large_download::show_confirmation(ctx, &download) {
+            Confirmation::Pending => {}
+            Confirmation::Confirmed => {
+                *self.large_download.lock().unwrap() = None;
+                match mode {
+                    DownloadMode::SingleFile => {
+                        self.object_key = download.description;
+                        self.start_single_download(ctx, true);
+                    }
+                    DownloadMode::Folder => self.start_folder_download(ctx, true),
+                }
+            }
+            Confirmation::Cancelled => {
+                *self.large_download.lock().unwrap() = None;
+                self.state.lock().unwrap().status_message = "Download cancelled".to_string();
             }
         }
     }
@@ -532,18 +569,12 @@ impl DownloadTab {
 
         std::thread::spawn(move || {
             runtime.block_on(async {
-                let result = async {
-                    let client = state
-                        .lock()
-                        .unwrap()
-                        .r2_client
-                        .clone()
-                        .ok_or_else(|| anyhow::anyhow!("No R2 client available"))?;
-
-                    let objects = client.list_objects(None).await?;
-                    Ok::<Vec<String>, anyhow::Error>(objects)
-                }
-                .await;
+                let result =
+                    with_reconnect(
+                        &state,
+                        |client| async move { client.list_objects(None).await },
+                    )
+                    .await;
 
                 let mut ds = download_state.lock().unwrap();
                 ds.loading = false;
@@ -595,32 +626,23 @@ impl DownloadTab {
 
         std::thread::spawn(move || {
             runtime.block_on(async {
-                let result = async {
-                    let client = state
-                        .lock()
-                        .unwrap()
-                        .r2_client
-                        .clone()
-                        .ok_or_else(|| anyhow::anyhow!("No R2 client available"))?;
-
-                    let objects = client.list_objects(Some(&folder_prefix)).await?;
-                    Ok::<Vec<String>, anyhow::Error>(objects)
-                }
+                let prefix = folder_prefix.as_str();
+                let result = with_reconnect(&state, |client| async move {
+                    client.list_objects_detailed(Some(prefix)).await
+                })
                 .await;
 
                 if let Ok(objects) = result {
                     let mut folder_objs = folder_objects.lock().unwrap();
-                    for key in objects {
-                        // Calculate relative path
-                        let relative_path = if key.starts_with(&folder_prefix) {
-                            key[folder_prefix.len()..].to_string()
-                        } else {
-                            key.clone()
-                        };
+                    for object in objects {
+                        let relative_path =
+                            rust_r2::transfer::relative_key(&object.key, &folder_prefix)
+                                .to_string();
 
                         folder_objs.push(FolderObject {
-                            key,
+                            key: object.key,
                             relative_path,
+                            size: object.size,
                             selected: true,
                         });
                     }
@@ -631,7 +653,9 @@ impl DownloadTab {
         });
     }
 
-    fn start_single_download(&mut self, ctx: &egui::Context) {
+    /// Download the object in `object_key`. Unless `confirmed`, objects over the size
+    /// threshold are held back for the user to confirm first.
+    fn start_single_download(&mut self, ctx: &egui::Context, confirmed: bool) {
         // Check if already downloading
         {
             let mut downloading = self.download_in_progress.lock().unwrap();
@@ -653,27 +677,39 @@ impl DownloadTab {
         let download_progress = self.download_progress.clone();
         let current_download_file = self.current_download_file.clone();
         let recent_downloads = self.recent_downloads.clone();
+        let large_download = self.large_download.clone();
 
         std::thread::spawn(move || {
-            // Extract just the filename from the object key (remove path)
-            let base_filename = object_key
-                .rsplit('/')
-                .next()
-                .unwrap_or(&object_key)
-                .to_string();
-            
-            // Determine the suggested filename
-            let suggested_filename =
-                if decrypt && (base_filename.ends_with(".gpg") || base_filename.ends_with(".pgp")) {
-                    // Remove the encryption extension for decrypted files
-                    if base_filename.ends_with(".gpg") {
-                        base_filename[..base_filename.len() - 4].to_string()
-                    } else {
-                        base_filename[..base_filename.len() - 4].to_string()
-                    }
-                } else {
-                    base_filename
-                };
+            let client = state.lock().unwrap().r2_client.clone();
+            let head =
+                client.and_then(|client| runtime.block_on(client.head_object(&object_key)).ok());
+
+            let size = large_download::size_to_confirm(
+                &state,
+                head.as_ref().and_then(|head| head.content_length),
+                confirmed,
+            );
+            if let Some(size) = size {
+                *large_download.lock().unwrap() = Some((
+                    DownloadMode::SingleFile,
+                    LargeDownload {
+                        description: object_key,
+                        size,
+                    },
+                ));
+                *download_in_progress.lock().unwrap() = false;
+                *current_download_file.lock().unwrap() = String::new();
+                ctx.request_repaint();
+                return;
+            }
+
+            // Prefer the filename recorded at upload; fall back to the key without .pgp/.gpg
+            let restored_name = head.and_then(|head| head.original_filename());
+            let suggested_filename = rust_r2::transfer::download_file_name(
+                &object_key,
+                restored_name.as_deref().filter(|_| decrypt),
+                decrypt,
+            );
 
             // Show file dialog
             let save_path = rfd::FileDialog::new()
@@ -686,30 +722,27 @@ impl DownloadTab {
                     ctx.request_repaint();
 
                     let result = async {
-                        let client = state
-                            .lock()
-                            .unwrap()
-                            .r2_client
-                            .clone()
-                            .ok_or_else(|| anyhow::anyhow!("No R2 client available"))?;
-
                         *download_progress.lock().unwrap() = 0.3;
                         ctx.request_repaint();
 
-                        let data = client.download_object(&object_key).await?;
+                        let key = object_key.as_str();
+                        let data = with_reconnect(&state, |client| async move {
+                            client.download_object(key).await
+                        })
+                        .await?;
 
                         *download_progress.lock().unwrap() = 0.7;
                         ctx.request_repaint();
 
-                        let final_data = if decrypt {
+                        let (final_data, signature) = if decrypt {
                             let pgp_handler = state.lock().unwrap().pgp_handler.clone();
                             let decrypted = {
                                 let handler = pgp_handler.lock().unwrap();
-                                handler.decrypt(&data)?
+                                handler.decrypt_verified(&data)?
                             };
                             decrypted
                         } else {
-                            data.to_vec()
+                            (data.to_vec(), None)
                         };
 
                         *download_progress.lock().unwrap() = 0.9;
@@ -720,7 +753,7 @@ impl DownloadTab {
                         *download_progress.lock().unwrap() = 1.0;
                         ctx.request_repaint();
 
-                        Ok::<(), anyhow::Error>(())
+                        Ok::<_, anyhow::Error>(signature)
                     }
                     .await;
 
@@ -740,9 +773,16 @@ impl DownloadTab {
                     }
 
                     match result {
-                        Ok(_) => {
+                        Ok(signature) => {
                             let mut state = state.lock().unwrap();
-                            state.status_message = format!("✓ Downloaded: {}", object_key);
+                            state.status_message = match signature {
+                                Some(signature) => format!(
+                                    "✓ Downloaded: {} (verified from {})",
+                                    object_key,
+                                    signature.signer.label()
+                                ),
+                                None => format!("✓ Downloaded: {}", object_key),
+                            };
                         }
                         Err(e) => {
                             let mut state = state.lock().unwrap();
@@ -761,7 +801,9 @@ impl DownloadTab {
         });
     }
 
-    fn start_folder_download(&mut self, ctx: &egui::Context) {
+    /// Download the selected folder objects. Sizes come from the listing, so the
+    /// total is checked against the threshold here unless already `confirmed`.
+    fn start_folder_download(&mut self, ctx: &egui::Context, confirmed: bool) {
         let selected_objects: Vec<FolderObject> = self
             .folder_objects
             .lock()
@@ -775,6 +817,20 @@ impl DownloadTab {
             return;
         }
 
+        let total_size: u64 = selected_objects.iter().map(|o| o.size).sum();
+        if let Some(size) =
+            large_download::size_to_confirm(&self.state, Some(total_size), confirmed)
+        {
+            *self.large_download.lock().unwrap() = Some((
+                DownloadMode::Folder,
+                LargeDownload {
+                    description: format!("{} selected files", selected_objects.len()),
+                    size,
+                },
+            ));
+            return;
+        }
+
         // Check if already downloading
         {
             let mut downloading = self.download_in_progress.lock().unwrap();
@@ -796,100 +852,95 @@ impl DownloadTab {
         let current_download_file = self.current_download_file.clone();
         let recent_downloads = self.recent_downloads.clone();
 
+        let folder_prefix = self.folder_prefix.clone();
+
         std::thread::spawn(move || {
             runtime.block_on(async {
-                let total_files = selected_objects.len();
-                let mut completed_files = 0;
-                let mut success_count = 0;
-                let mut failed_count = 0;
-
-                for obj in selected_objects {
-                    *current_download_file.lock().unwrap() = obj.relative_path.clone();
-
-                    let progress = completed_files as f32 / total_files as f32;
-                    *download_progress.lock().unwrap() = progress;
-                    ctx.request_repaint();
-
-                    // Create the full path for saving
-                    let save_path = save_folder.join(&obj.relative_path);
+                let options = FolderDownloadOptions {
+                    decrypt,
+                    only_keys: Some(selected_objects.iter().map(|o| o.key.clone()).collect()),
+                    ..Default::default()
+                };
+                let progress = GuiProgress::new(
+                    download_progress.clone(),
+                    current_download_file.clone(),
+                    ctx.clone(),
+                );
+
+                // A copy, so decrypting doesn't hold the shared handler's lock
+                let pgp_handler = state.lock().unwrap().pgp_handler.clone();
+                let pgp_handler = Arc::new(pgp_handler.lock().unwrap().clone());
+
+                // A retry after reconnecting resumes from the download manifest
+                let (pgp_handler, folder_prefix, save_folder, options, progress) = (
+                    &pgp_handler,
+                    &folder_prefix,
+                    &save_folder,
+                    &options,
+                    &progress,
+                );
+                let result = with_reconnect(&state, |client| async move {
+                    download_prefix(
+                        &client,
+                        Some(pgp_handler.clone()),
+                        folder_prefix,
+                        save_folder,
+                        options,
+                        progress,
+                    )
+                    .await
+                })
+                .await;
 
-                    // Create parent directories if needed
-                    if let Some(parent) = save_path.parent() {
-                        if let Err(e) = std::fs::create_dir_all(parent) {
-                            // Failed to create directory
-                            failed_count += 1;
-                            completed_files += 1;
-                            continue;
+                match result {
+                    Ok(summary) => {
+                        // Record downloads
+                        {
+                            let mut downloads = recent_downloads.lock().unwrap();
+                            for (key, path) in &summary.downloaded {
+                                downloads.push(DownloadRecord {
+                                    object_key: key.clone(),
+                                    save_path: path.display().to_string(),
+                                    decrypted: decrypt,
+                                    timestamp: Local::now(),
+                                    success: true,
+                                });
+                            }
+                            for (key, _) in &summary.failed {
+                                downloads.push(DownloadRecord {
+                                    object_key: key.clone(),
+                                    save_path: save_folder.display().to_string(),
+                                    decrypted: decrypt,
+                                    timestamp: Local::now(),
+                                    success: false,
+                                });
+                            }
                         }
-                    }
-
-                    let result = async {
-                        let client = state
-                            .lock()
-                            .unwrap()
-                            .r2_client
-                            .clone()
-                            .ok_or_else(|| anyhow::anyhow!("No R2 client available"))?;
-
-                        let data = client.download_object(&obj.key).await?;
 
-                        let final_data = if decrypt {
-                            let pgp_handler = state.lock().unwrap().pgp_handler.clone();
-                            let decrypted = {
-                                let handler = pgp_handler.lock().unwrap();
-                                handler.decrypt(&data)?
-                            };
-                            decrypted
+                        let mut state = state.lock().unwrap();
+                        let skipped = if summary.skipped.is_empty() {
+                            String::new()
                         } else {
-                            data.to_vec()
+                            format!(", {} already complete", summary.skipped.len())
                         };
-
-                        std::fs::write(&save_path, final_data)?;
-
-                        Ok::<(), anyhow::Error>(())
-                    }
-                    .await;
-
-                    // Record download
-                    let download_record = DownloadRecord {
-                        object_key: obj.key.clone(),
-                        save_path: save_path.display().to_string(),
-                        decrypted: decrypt,
-                        timestamp: Local::now(),
-                        success: result.is_ok(),
-                    };
-
-                    // Add to recent downloads
-                    {
-                        let mut downloads = recent_downloads.lock().unwrap();
-                        downloads.push(download_record);
-                    }
-
-                    match result {
-                        Ok(_) => success_count += 1,
-                        Err(e) => {
-                            // Failed to download file
-                            failed_count += 1;
+                        if summary.failed.is_empty() {
+                            state.status_message = format!(
+                                "✓ Downloaded {} files to folder{}",
+                                summary.downloaded.len(),
+                                skipped
+                            );
+                        } else {
+                            state.status_message = format!(
+                                "Downloaded {} files, {} failed{} - download again to resume",
+                                summary.downloaded.len(),
+                                summary.failed.len(),
+                                skipped
+                            );
                         }
                     }
-
-                    completed_files += 1;
-                }
-
-                *download_progress.lock().unwrap() = 1.0;
-                ctx.request_repaint();
-
-                // Update status message
-                {
-                    let mut state = state.lock().unwrap();
-                    if failed_count == 0 {
-                        state.status_message =
-                            format!("✓ Downloaded {} files to folder", success_count);
-                    } else {
-                        state.status_message = format!(
-                            "Downloaded {} files, {} failed",
-                            success_count, failed_count
-                        );
+                    Err(e) => {
+                        state.lock().unwrap().status_message =
+                            format!("❌ Folder download failed: {}", e);
                     }
                 }
 
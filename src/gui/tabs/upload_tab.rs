@@ -1,7 +1,16 @@
-use crate::app::AppState;
+use crate::app::{with_reconnect, AppState};
+use crate::progress::GuiProgress;
+use crate::recipient_memory::{self, RecipientMemory, RememberedRecipients};
 use bytes::Bytes;
 use chrono::{DateTime, Local};
 use eframe::egui;
+use rust_r2::crypto::KeyInfo;
+use rust_r2::encryption_filter;
+use rust_r2::glob_filter::GlobFilter;
+use rust_r2::multipart_upload::{self, MultipartUploader};
+use rust_r2::progress::ProgressReporter;
+use rust_r2::r2_client::UploadOptions;
+use rust_r2::transfer;
 use std::collections::HashSet;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
@@ -25,15 +34,42 @@ struct FolderFile {
     selected: bool,
 }
 
+// Files handed from the scan thread to the UI at a time
+const SCAN_BATCH_SIZE: usize = 500;
+
+#[derive(Clone, Copy, PartialEq)]
+enum FolderSort {
+    Name,
+    Size,
+}
+
+/// Shared with the background thread scanning the selected folder.
+#[derive(Default)]
+struct FolderScan {
+    pending: Vec<FolderFile>, // Found but not yet moved into folder_files
+    scanned: usize,
+    scanning: bool,
+    generation: u64, // Bumped to abandon a running scan
+}
+
 #[derive(Clone, PartialEq)]
 enum UploadMode {
     SingleFile,
     Folder,
 }
 
+/// An encrypted upload waiting for its recipients to be confirmed.
+struct PendingUpload {
+    mode: UploadMode,
+    selection: Option<Vec<String>>, // As selected_recipients returned it
+    encrypt_to_self: bool,
+    recipients: Vec<KeyInfo>, // Every key the upload will be encrypted to
+}
+
 #[derive(Clone, Default)]
 struct BucketState {
-    folders: Vec<String>,
+    browse_prefix: String, // Folder whose subfolders are listed, "" for the root
+    folders: Vec<String>,  // Subfolders of browse_prefix, without the trailing '/'
     loading: bool,
     last_refresh: Option<std::time::Instant>,
 }
@@ -44,10 +80,21 @@ pub struct UploadTab {
     selected_file: Option<PathBuf>,
     selected_folder: Option<PathBuf>,
     folder_files: Vec<FolderFile>,
+    folder_scan: Arc<Mutex<FolderScan>>,
+    folder_sort: FolderSort,
+    folder_files_sorted: bool,
+    max_displayed_files: usize,
     object_key: String,
     folder_prefix: String,
     selected_bucket_folder: Option<String>,
     encrypt_before_upload: bool,
+    encrypt_to_self: bool,
+    excluded_recipients: HashSet<String>, // Fingerprints of loaded keys not to encrypt to
+    recipient_memory: RecipientMemory,
+    remembered_prefix: Option<String>, // Destination whose remembered recipients were applied
+    pending_upload: Option<PendingUpload>,
+    cache_control: String,
+    expires: String, // RFC 3339 date, HTTP date or a duration from now
     upload_in_progress: Arc<Mutex<bool>>,
     upload_progress: Arc<Mutex<f32>>,
     current_upload_file: Arc<Mutex<String>>,
@@ -55,6 +102,8 @@ pub struct UploadTab {
     upload_mode: UploadMode,
     show_folder_contents: bool,
     filter_text: String,
+    pattern_text: String, // Glob patterns, with a leading ! to exclude
+    pattern: Result<GlobFilter, String>,
     bucket_state: Arc<Mutex<BucketState>>,
     needs_refresh: bool,
 }
@@ -67,10 +116,21 @@ impl UploadTab {
             selected_file: None,
             selected_folder: None,
             folder_files: Vec::new(),
+            folder_scan: Arc::new(Mutex::new(FolderScan::default())),
+            folder_sort: FolderSort::Name,
+            folder_files_sorted: true,
+            max_displayed_files: 1000,
             object_key: String::new(),
             folder_prefix: String::new(),
             selected_bucket_folder: None,
             encrypt_before_upload: false,
+            encrypt_to_self: true,
+            excluded_recipients: HashSet::new(),
+            recipient_memory: RecipientMemory::load(),
+            remembered_prefix: None,
+            pending_upload: None,
+            cache_control: String::new(),
+            expires: String::new(),
             upload_in_progress: Arc::new(Mutex::new(false)),
             upload_progress: Arc::new(Mutex::new(0.0)),
             current_upload_file: Arc::new(Mutex::new(String::new())),
@@ -78,6 +138,8 @@ impl UploadTab {
             upload_mode: UploadMode::SingleFile,
             show_folder_contents: false,
             filter_text: String::new(),
+            pattern_text: String::new(),
+            pattern: Ok(GlobFilter::default()),
             bucket_state: Arc::new(Mutex::new(BucketState::default())),
             needs_refresh: true,
         }
@@ -119,6 +181,7 @@ impl UploadTab {
                 .clicked()
             {
                 self.selected_folder = None;
+                self.cancel_folder_scan();
                 self.folder_files.clear();
                 self.show_folder_contents = false;
             }
@@ -131,12 +194,28 @@ impl UploadTab {
             }
         });
 
+        let dropped: Vec<PathBuf> = ctx.input(|i| {
+            i.raw
+                .dropped_files
+                .iter()
+                .filter_map(|f| f.path.clone())
+                .collect()
+        });
+        if !dropped.is_empty() {
+            self.handle_dropped_paths(dropped, ctx);
+        }
+
+        ui.add_space(10.0);
+        self.show_drop_zone(ui, ctx);
         ui.add_space(10.0);
 
+        self.apply_remembered_recipients();
+
         match self.upload_mode {
             UploadMode::SingleFile => self.show_single_file_upload(ui, ctx),
             UploadMode::Folder => self.show_folder_upload(ui, ctx),
         }
+        self.show_upload_confirmation(ctx);
 
         ui.add_space(20.0);
         ui.separator();
@@ -219,25 +298,41 @@ impl UploadTab {
             });
     }
 
+    fn show_drop_zone(&self, ui: &mut egui::Ui, ctx: &egui::Context) {
+        let is_being_dragged_over = ctx.input(|i| !i.raw.hovered_files.is_empty());
+
+        let (response, painter) =
+            ui.allocate_painter(egui::vec2(ui.available_width(), 60.0), egui::Sense::hover());
+        let rect = response.rect;
+        painter.rect_filled(
+            rect,
+            5.0,
+            if is_being_dragged_over {
+                egui::Color32::from_rgb(100, 100, 150)
+            } else {
+                egui::Color32::from_rgb(50, 50, 50)
+            },
+        );
+        painter.rect_stroke(
+            rect,
+            5.0,
+            egui::Stroke::new(2.0, egui::Color32::from_rgb(100, 100, 200)),
+        );
+        painter.text(
+            rect.center(),
+            egui::Align2::CENTER_CENTER,
+            "📥 Drag & Drop files or folders here to upload",
+            egui::FontId::proportional(16.0),
+            egui::Color32::from_rgb(200, 200, 200),
+        );
+    }
+
     fn show_single_file_upload(&mut self, ui: &mut egui::Ui, ctx: &egui::Context) {
         ui.horizontal(|ui| {
             ui.label("Select File:");
             if ui.button("📁 Browse...").clicked() {
                 if let Some(path) = rfd::FileDialog::new().pick_file() {
-                    let filename = path
-                        .file_name()
-                        .and_then(|n| n.to_str())
-                        .unwrap_or("file")
-                        .to_string();
-
-                    // If a folder is selected, prepend it to the object key
-                    if let Some(ref folder) = self.selected_bucket_folder {
-                        self.object_key = format!("{}/{}", folder, filename);
-                    } else {
-                        self.object_key = filename;
-                    }
-
-                    self.selected_file = Some(path);
+                    self.select_file(path);
                 }
             }
 
@@ -248,9 +343,12 @@ impl UploadTab {
 
         ui.add_space(10.0);
 
-        // Folder selection
-        let folders = self.bucket_state.lock().unwrap().folders.clone();
-        if !folders.is_empty() {
+        // Folder selection, browsed one level at a time
+        let (folders, browse_prefix) = {
+            let state = self.bucket_state.lock().unwrap();
+            (state.folders.clone(), state.browse_prefix.clone())
+        };
+        if !folders.is_empty() || !browse_prefix.is_empty() {
             ui.separator();
             ui.label("📁 Choose destination folder (optional):");
 
@@ -270,25 +368,49 @@ impl UploadTab {
                 }
             });
 
+            let mut open_folder = None;
+            if !browse_prefix.is_empty() {
+                ui.horizontal(|ui| {
+                    if ui.small_button("⬆ Up").clicked() {
+                        open_folder = Some(parent_folder(&browse_prefix).to_string());
+                    }
+                    ui.label(format!("📂 /{}", browse_prefix));
+                });
+            }
+
             egui::ScrollArea::vertical()
                 .max_height(150.0)
                 .show(ui, |ui| {
                     for folder in &folders {
-                        let is_selected = self.selected_bucket_folder.as_ref() == Some(folder);
-                        if ui
-                            .selectable_label(is_selected, format!("📁 {}", folder))
-                            .clicked()
-                        {
-                            self.selected_bucket_folder = Some(folder.clone());
-                            // Update object key with folder prefix
-                            if let Some(ref path) = self.selected_file {
-                                if let Some(filename) = path.file_name().and_then(|n| n.to_str()) {
-                                    self.object_key = format!("{}/{}", folder, filename);
+                        ui.horizontal(|ui| {
+                            let is_selected = self.selected_bucket_folder.as_ref() == Some(folder);
+                            if ui
+                                .selectable_label(is_selected, format!("📁 {}", folder))
+                                .clicked()
+                            {
+                                self.selected_bucket_folder = Some(folder.clone());
+                                // Update object key with folder prefix
+                                if let Some(ref path) = self.selected_file {
+                                    if let Some(filename) =
+                                        path.file_name().and_then(|n| n.to_str())
+                                    {
+                                        self.object_key = format!("{}/{}", folder, filename);
+                                    }
                                 }
                             }
-                        }
+                            if ui
+                                .small_button("▶")
+                                .on_hover_text("Show the folders inside")
+                                .clicked()
+                            {
+                                open_folder = Some(format!("{}/", folder));
+                            }
+                        });
                     }
                 });
+            if let Some(prefix) = open_folder {
+                self.open_bucket_folder(prefix, ctx);
+            }
 
             ui.horizontal(|ui| {
                 if ui.small_button("🔄 Refresh folders").clicked() {
@@ -346,6 +468,9 @@ impl UploadTab {
             &mut self.encrypt_before_upload,
             "🔐 Encrypt before upload (requires PGP public key)",
         );
+        self.show_encrypt_to_self_toggle(ui);
+        self.show_recipient_selection(ui);
+        self.show_caching_headers(ui);
 
         ui.add_space(20.0);
 
@@ -366,7 +491,7 @@ impl UploadTab {
                 .add_enabled(can_upload, egui::Button::new("⬆️ Upload to R2"))
                 .clicked()
             {
-                self.start_single_upload(ctx);
+                self.request_upload(UploadMode::SingleFile, ctx);
             }
         }
     }
@@ -376,19 +501,14 @@ impl UploadTab {
             ui.label("Select Folder:");
             if ui.button("📁 Browse...").clicked() {
                 if let Some(path) = rfd::FileDialog::new().pick_folder() {
-                    self.selected_folder = Some(path.clone());
-                    self.folder_prefix = path
-                        .file_name()
-                        .and_then(|n| n.to_str())
-                        .unwrap_or("folder")
-                        .to_string();
-                    self.scan_folder(&path);
-                    self.show_folder_contents = true;
+                    self.select_folder(path, ctx);
                 }
             }
 
             if let Some(ref path) = self.selected_folder {
                 ui.label(format!("Selected: {}", path.display()));
+            } else if !self.folder_files.is_empty() {
+                ui.label("Selected: dropped files");
             }
         });
 
@@ -406,6 +526,37 @@ impl UploadTab {
             &mut self.encrypt_before_upload,
             "🔐 Encrypt all files before upload",
         );
+        self.show_encrypt_to_self_toggle(ui);
+        self.show_recipient_selection(ui);
+        self.show_caching_headers(ui);
+
+        // Take whatever the scan thread has found since the last frame
+        let (scanning, scanned) = {
+            let mut scan = self.folder_scan.lock().unwrap();
+            if !scan.pending.is_empty() {
+                self.folder_files.append(&mut scan.pending);
+                self.folder_files_sorted = false;
+            }
+            (scan.scanning, scan.scanned)
+        };
+        // Sorting a growing list every frame is wasted work, so wait for the scan to finish
+        if !scanning && !self.folder_files_sorted {
+            match self.folder_sort {
+                FolderSort::Name => self
+                    .folder_files
+                    .sort_by(|a, b| a.relative_path.cmp(&b.relative_path)),
+                FolderSort::Size => self.folder_files.sort_by_key(|f| std::cmp::Reverse(f.size)),
+            }
+            self.folder_files_sorted = true;
+        }
+
+        if scanning {
+            ui.add_space(10.0);
+            ui.horizontal(|ui| {
+                ui.spinner();
+                ui.label(format!("Scanned {} files...", scanned));
+            });
+        }
 
         if self.show_folder_contents && !self.folder_files.is_empty() {
             ui.add_space(10.0);
@@ -428,8 +579,40 @@ impl UploadTab {
                 }
                 ui.label("Filter:");
                 ui.text_edit_singleline(&mut self.filter_text);
+                ui.label("Pattern:");
+                let pattern_edit = ui
+                    .text_edit_singleline(&mut self.pattern_text)
+                    .on_hover_text(
+                        "Glob patterns separated by spaces, e.g. *.pdf docs/** !*.tmp\n\
+                         Only matching files are uploaded; a leading ! excludes",
+                    );
+                if pattern_edit.changed() {
+                    self.pattern = GlobFilter::parse(&self.pattern_text).map_err(|e| e.to_string());
+                }
+                if let Err(e) = &self.pattern {
+                    ui.colored_label(egui::Color32::RED, e);
+                }
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Sort by:");
+                let before = self.folder_sort;
+                ui.selectable_value(&mut self.folder_sort, FolderSort::Name, "Name");
+                ui.selectable_value(&mut self.folder_sort, FolderSort::Size, "Size");
+                if self.folder_sort != before {
+                    self.folder_files_sorted = false;
+                }
+                ui.separator();
+                ui.label("Show at most:");
+                ui.add(
+                    egui::DragValue::new(&mut self.max_displayed_files)
+                        .range(100..=100_000)
+                        .speed(100),
+                );
+                ui.label("files");
             });
 
+            let mut hidden = 0;
             egui::ScrollArea::vertical()
                 .max_height(300.0)
                 .show(ui, |ui| {
@@ -444,12 +627,22 @@ impl UploadTab {
                             ui.end_row();
 
                             let filter = self.filter_text.to_lowercase();
+                            let pattern = self.pattern.as_ref().ok();
+                            let mut shown = 0;
                             for file in &mut self.folder_files {
                                 if !filter.is_empty()
                                     && !file.relative_path.to_lowercase().contains(&filter)
                                 {
                                     continue;
                                 }
+                                if pattern.is_some_and(|p| !p.matches(&file.relative_path)) {
+                                    continue;
+                                }
+                                if shown >= self.max_displayed_files {
+                                    hidden += 1;
+                                    continue;
+                                }
+                                shown += 1;
 
                                 ui.checkbox(&mut file.selected, "");
                                 ui.label(&file.relative_path);
@@ -458,12 +651,18 @@ impl UploadTab {
                             }
                         });
                 });
+            if hidden > 0 {
+                ui.weak(format!(
+                    "{} more files not shown; selected files are still uploaded",
+                    hidden
+                ));
+            }
 
-            let selected_count = self.folder_files.iter().filter(|f| f.selected).count();
+            let selected_count = self.folder_files.iter().filter(|f| self.uploads(f)).count();
             let total_size: u64 = self
                 .folder_files
                 .iter()
-                .filter(|f| f.selected)
+                .filter(|f| self.uploads(f))
                 .map(|f| f.size)
                 .sum();
 
@@ -488,70 +687,410 @@ impl UploadTab {
             }
             ctx.request_repaint_after(std::time::Duration::from_millis(100));
         } else {
-            let has_selected = self.folder_files.iter().any(|f| f.selected);
-            let can_upload = self.selected_folder.is_some() && has_selected;
+            let has_selected = self.folder_files.iter().any(|f| self.uploads(f));
+            let can_upload = has_selected && !scanning;
             if ui
                 .add_enabled(can_upload, egui::Button::new("⬆️ Upload Selected Files"))
                 .clicked()
             {
-                self.start_folder_upload(ctx);
+                self.request_upload(UploadMode::Folder, ctx);
             }
         }
     }
 
-    fn scan_folder(&mut self, folder: &Path) {
+    fn select_file(&mut self, path: PathBuf) {
+        let filename = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("file")
+            .to_string();
+
+        // If a folder is selected, prepend it to the object key
+        if let Some(ref folder) = self.selected_bucket_folder {
+            self.object_key = format!("{}/{}", folder, filename);
+        } else {
+            self.object_key = filename;
+        }
+
+        self.selected_file = Some(path);
+    }
+
+    fn select_folder(&mut self, path: PathBuf, ctx: &egui::Context) {
+        self.folder_prefix = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("folder")
+            .to_string();
+        self.scan_folders(vec![(path.clone(), String::new())], ctx);
+        self.selected_folder = Some(path);
+        self.show_folder_contents = true;
+    }
+
+    /// Queue files and folders dropped onto the tab. A lone file or folder is treated
+    /// exactly like one picked with Browse; anything else is queued as a folder upload
+    /// under the selected destination folder, each folder keeping its own name.
+    fn handle_dropped_paths(&mut self, paths: Vec<PathBuf>, ctx: &egui::Context) {
+        let (folders, files): (Vec<PathBuf>, Vec<PathBuf>) =
+            paths.into_iter().partition(|p| p.is_dir());
+
+        match (files.len(), folders.len()) {
+            (0, 0) => {}
+            (1, 0) => {
+                self.upload_mode = UploadMode::SingleFile;
+                self.cancel_folder_scan();
+                self.selected_folder = None;
+                self.folder_files.clear();
+                self.show_folder_contents = false;
+                self.select_file(files.into_iter().next().unwrap());
+            }
+            (0, 1) => {
+                self.upload_mode = UploadMode::Folder;
+                self.selected_file = None;
+                self.object_key.clear();
+                self.select_folder(folders.into_iter().next().unwrap(), ctx);
+            }
+            _ => {
+                self.upload_mode = UploadMode::Folder;
+                self.selected_file = None;
+                self.object_key.clear();
+                self.selected_folder = None;
+                self.folder_prefix = self.selected_bucket_folder.clone().unwrap_or_default();
+
+                let roots = folders
+                    .into_iter()
+                    .map(|folder| {
+                        let name = folder
+                            .file_name()
+                            .and_then(|n| n.to_str())
+                            .unwrap_or("folder")
+                            .to_string();
+                        (folder, name)
+                    })
+                    .collect();
+                self.scan_folders(roots, ctx);
+
+                for path in files {
+                    let size = std::fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+                    let relative_path = path
+                        .file_name()
+                        .and_then(|n| n.to_str())
+                        .unwrap_or("file")
+                        .to_string();
+                    self.folder_files.push(FolderFile {
+                        path,
+                        relative_path,
+                        size,
+                        selected: true,
+                    });
+                }
+                self.folder_files_sorted = false;
+                self.show_folder_contents = true;
+            }
+        }
+    }
+
+    /// Scan each `(folder, relative prefix)` on a background thread, feeding files to
+    /// `folder_files` as they are found.
+    fn scan_folders(&mut self, roots: Vec<(PathBuf, String)>, ctx: &egui::Context) {
         self.folder_files.clear();
-        self.scan_folder_recursive(folder, folder, "");
-    }
-
-    fn scan_folder_recursive(&mut self, base_folder: &Path, current_folder: &Path, prefix: &str) {
-        if let Ok(entries) = std::fs::read_dir(current_folder) {
-            for entry in entries.flatten() {
-                let path = entry.path();
-                if path.is_file() {
-                    if let Ok(metadata) = entry.metadata() {
-                        let relative_path = if prefix.is_empty() {
-                            path.file_name()
-                                .and_then(|n| n.to_str())
-                                .unwrap_or("unknown")
-                                .to_string()
-                        } else {
-                            format!(
-                                "{}/{}",
-                                prefix,
-                                path.file_name()
-                                    .and_then(|n| n.to_str())
-                                    .unwrap_or("unknown")
-                            )
-                        };
+        self.folder_files_sorted = true;
+        start_folder_scan(&self.folder_scan, roots, ctx.clone());
+    }
 
-                        self.folder_files.push(FolderFile {
-                            path,
-                            relative_path,
-                            size: metadata.len(),
-                            selected: true,
-                        });
-                    }
-                } else if path.is_dir() {
-                    // Skip hidden directories like .git
-                    if let Some(name) = path.file_name() {
-                        if let Some(name_str) = name.to_str() {
-                            if !name_str.starts_with('.') {
-                                let new_prefix = if prefix.is_empty() {
-                                    name_str.to_string()
-                                } else {
-                                    format!("{}/{}", prefix, name_str)
-                                };
-                                self.scan_folder_recursive(base_folder, &path, &new_prefix);
+    fn cancel_folder_scan(&mut self) {
+        let mut scan = self.folder_scan.lock().unwrap();
+        scan.generation += 1;
+        scan.pending.clear();
+        scan.scanning = false;
+    }
+
+    fn show_caching_headers(&mut self, ui: &mut egui::Ui) {
+        egui::CollapsingHeader::new("🕒 Caching headers")
+            .id_salt("caching_headers")
+            .show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Cache-Control:");
+                    ui.text_edit_singleline(&mut self.cache_control);
+                    ui.label("(e.g. 'public, max-age=86400')");
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Expires:");
+                    ui.text_edit_singleline(&mut self.expires);
+                    ui.label("(e.g. '2025-01-31T00:00:00Z' or '7d')");
+                });
+            });
+    }
+
+    /// Upload options carrying the caching headers, or None (with the problem in the
+    /// status bar) if one of them doesn't parse.
+    fn caching_options(&self) -> Option<UploadOptions> {
+        let cache_control =
+            Some(self.cache_control.trim().to_string()).filter(|value| !value.is_empty());
+        if let Some(value) = &cache_control {
+            if let Err(e) = rust_r2::r2_client::validate_cache_control(value) {
+                self.state.lock().unwrap().status_message = format!("✗ {}", e);
+                return None;
+            }
+        }
+        let expires = match self.expires.trim() {
+            "" => None,
+            value => match rust_r2::transfer::parse_expires(value) {
+                Ok(expires) => Some(expires),
+                Err(e) => {
+                    self.state.lock().unwrap().status_message = format!("✗ {}", e);
+                    return None;
+                }
+            },
+        };
+        Some(UploadOptions {
+            cache_control,
+            expires,
+            ..Default::default()
+        })
+    }
+
+    fn show_encrypt_to_self_toggle(&mut self, ui: &mut egui::Ui) {
+        if !self.encrypt_before_upload {
+            return;
+        }
+        ui.horizontal(|ui| {
+            ui.add_space(20.0);
+            ui.checkbox(&mut self.encrypt_to_self, "👤 Also encrypt to my own key")
+                .on_hover_text(
+                    "Adds the public half of your secret key as a recipient, so you can always decrypt your own uploads",
+                );
+        });
+    }
+
+    fn show_recipient_selection(&mut self, ui: &mut egui::Ui) {
+        if !self.encrypt_before_upload {
+            return;
+        }
+        let pgp_handler = self.state.lock().unwrap().pgp_handler.clone();
+        let keys = pgp_handler.lock().unwrap().get_loaded_keys().to_vec();
+        if keys.is_empty() {
+            return;
+        }
+        let selected = keys
+            .iter()
+            .filter(|k| !self.excluded_recipients.contains(&k.fingerprint))
+            .count();
+        ui.horizontal(|ui| {
+            ui.add_space(20.0);
+            egui::CollapsingHeader::new(format!("👥 Recipients ({} of {})", selected, keys.len()))
+                .id_salt("upload_recipients")
+                .show(ui, |ui| {
+                    for key in &keys {
+                        let mut included = !self.excluded_recipients.contains(&key.fingerprint);
+                        let short_fingerprint =
+                            &key.fingerprint[key.fingerprint.len().saturating_sub(16)..];
+                        let label = format!("{} <{}> {}", key.name, key.email, short_fingerprint);
+                        if ui.checkbox(&mut included, label).changed() {
+                            if included {
+                                self.excluded_recipients.remove(&key.fingerprint);
+                            } else {
+                                self.excluded_recipients.insert(key.fingerprint.clone());
                             }
                         }
                     }
+                    ui.small("Remembered for uploads to this folder");
+                });
+        });
+    }
+
+    /// Where the current upload goes, used to remember its recipients.
+    fn destination_prefix(&self) -> String {
+        match self.upload_mode {
+            UploadMode::SingleFile => recipient_memory::key_prefix(&self.object_key).to_string(),
+            UploadMode::Folder => self.folder_prefix.clone(),
+        }
+    }
+
+    /// When the destination changes, select the recipients last used for it. Keys that
+    /// have since been unloaded are dropped from the selection.
+    fn apply_remembered_recipients(&mut self) {
+        let prefix = self.destination_prefix();
+        if self.remembered_prefix.as_deref() == Some(prefix.as_str()) {
+            return;
+        }
+        self.remembered_prefix = Some(prefix.clone());
+        let Some(remembered) = self.recipient_memory.get(&prefix).cloned() else {
+            return;
+        };
+
+        let pgp_handler = self.state.lock().unwrap().pgp_handler.clone();
+        let loaded: Vec<String> = pgp_handler
+            .lock()
+            .unwrap()
+            .get_loaded_keys()
+            .iter()
+            .map(|k| k.fingerprint.clone())
+            .collect();
+        let (excluded, missing) = remembered.exclusions(&loaded);
+
+        self.encrypt_to_self = remembered.encrypt_to_self;
+        self.excluded_recipients = excluded;
+        if missing > 0 {
+            self.state.lock().unwrap().status_message = format!(
+                "{} of the recipients last used for '{}' are no longer loaded",
+                missing, prefix
+            );
+        }
+    }
+
+    /// The fingerprints to encrypt to, or None to encrypt to every loaded key.
+    fn selected_recipients(&self) -> Option<Vec<String>> {
+        let pgp_handler = self.state.lock().unwrap().pgp_handler.clone();
+        let handler = pgp_handler.lock().unwrap();
+        let keys = handler.get_loaded_keys();
+        let selected: Vec<String> = keys
+            .iter()
+            .map(|k| k.fingerprint.clone())
+            .filter(|fp| !self.excluded_recipients.contains(fp))
+            .collect();
+        (selected.len() < keys.len()).then_some(selected)
+    }
+
+    /// The recipients to upload with, as `selected_recipients`. Records the choice for
+    /// the destination prefix.
+    fn take_recipients(&mut self) -> Option<Vec<String>> {
+        if !self.encrypt_before_upload {
+            return None;
+        }
+        let selection = self.selected_recipients();
+        let fingerprints = selection.clone().unwrap_or_else(|| {
+            let pgp_handler = self.state.lock().unwrap().pgp_handler.clone();
+            let handler = pgp_handler.lock().unwrap();
+            handler
+                .get_loaded_keys()
+                .iter()
+                .map(|k| k.fingerprint.clone())
+                .collect()
+        });
+
+        let remembered = RememberedRecipients {
+            fingerprints,
+            encrypt_to_self: self.encrypt_to_self,
+        };
+        let prefix = self.destination_prefix();
+        if let Err(e) = self.recipient_memory.remember(&prefix, remembered) {
+            tracing::warn!("Failed to remember recipients for '{}': {:#}", prefix, e);
+        }
+        selection
+    }
+
+    /// Start an upload. Encrypted uploads first show every key they will be encrypted
+    /// to, and only start once that list is confirmed.
+    fn request_upload(&mut self, mode: UploadMode, ctx: &egui::Context) {
+        if !self.encrypt_before_upload {
+            self.start_upload(mode, ctx);
+        } else if !self.encryption_blocked() {
+            self.pending_upload = Some(self.plan_upload(mode));
+        }
+    }
+
+    fn plan_upload(&self, mode: UploadMode) -> PendingUpload {
+        let selection = self.selected_recipients();
+        let pgp_handler = self.state.lock().unwrap().pgp_handler.clone();
+        let recipients = {
+            let mut handler = pgp_handler.lock().unwrap();
+            handler.set_encrypt_to_self(self.encrypt_to_self);
+            handler.planned_recipients(selection.as_deref())
+        };
+        PendingUpload {
+            mode,
+            selection,
+            encrypt_to_self: self.encrypt_to_self,
+            recipients,
+        }
+    }
+
+    fn start_upload(&mut self, mode: UploadMode, ctx: &egui::Context) {
+        match mode {
+            UploadMode::SingleFile => self.start_single_upload(ctx),
+            UploadMode::Folder => self.start_folder_upload(ctx),
+        }
+    }
+
+    fn show_upload_confirmation(&mut self, ctx: &egui::Context) {
+        let Some(pending) = &self.pending_upload else {
+            return;
+        };
+        let mut confirmed = false;
+        let mut cancelled = false;
+        egui::Window::new("🔐 Confirm recipients")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                ui.label(format!("Encrypting to {} keys:", pending.recipients.len()));
+                for recipient in &pending.recipients {
+                    ui.monospace(recipient.label());
                 }
+                ui.add_space(8.0);
+                ui.horizontal(|ui| {
+                    confirmed = ui
+                        .add_enabled(
+                            !pending.recipients.is_empty(),
+                            egui::Button::new("⬆️ Upload"),
+                        )
+                        .clicked();
+                    cancelled = ui.button("Cancel").clicked();
+                });
+            });
+
+        if cancelled {
+            self.pending_upload = None;
+            self.state.lock().unwrap().status_message = "Upload cancelled".to_string();
+        } else if confirmed {
+            let Some(pending) = self.pending_upload.take() else {
+                return;
+            };
+            // The recipients can still be changed while the window is open
+            let unchanged = self.encrypt_before_upload
+                && self.encrypt_to_self == pending.encrypt_to_self
+                && self.selected_recipients() == pending.selection;
+            if unchanged {
+                self.start_upload(pending.mode, ctx);
+            } else {
+                self.state.lock().unwrap().status_message =
+                    "Recipients changed, check them again".to_string();
+                self.request_upload(pending.mode, ctx);
             }
         }
     }
 
+    /// Encrypting is refused while new public keys wait for their fingerprints to be
+    /// checked, or when every recipient has been deselected.
+    fn encryption_blocked(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if !self.encrypt_before_upload {
+            return false;
+        }
+        if state.unverified_keys > 0 {
+            state.status_message =
+                "✗ Trust or reject the new public keys before encrypting".to_string();
+            return true;
+        }
+        let keys = state.pgp_handler.lock().unwrap().get_loaded_keys().to_vec();
+        if !keys.is_empty()
+            && keys
+                .iter()
+                .all(|k| self.excluded_recipients.contains(&k.fingerprint))
+        {
+            state.status_message = "✗ Select at least one recipient".to_string();
+            return true;
+        }
+        false
+    }
+
     fn start_single_upload(&mut self, ctx: &egui::Context) {
+        if self.encryption_blocked() {
+            return;
+        }
+        let Some(base_options) = self.caching_options() else {
+            return;
+        };
         if let Some(file_path) = self.selected_file.clone() {
             // Check if already uploading
             {
@@ -570,6 +1109,8 @@ impl UploadTab {
             let runtime = self.runtime.clone();
             let object_key = self.object_key.clone();
             let encrypt = self.encrypt_before_upload;
+            let encrypt_to_self = self.encrypt_to_self;
+            let recipients = self.take_recipients();
             let ctx = ctx.clone();
             let upload_in_progress = self.upload_in_progress.clone();
             let upload_progress = self.upload_progress.clone();
@@ -583,9 +1124,9 @@ impl UploadTab {
                     *upload_progress.lock().unwrap() = 0.1;
                     ctx.request_repaint();
 
-                    // Add .pgp extension if encrypting and not already present
-                    let final_object_key = if encrypt && !object_key.ends_with(".pgp") {
-                        format!("{}.pgp", object_key)
+                    // Add .pgp extension if encrypting and the key has no encrypted extension
+                    let final_object_key = if encrypt {
+                        encryption_filter::with_encrypted_extension(&object_key)
                     } else {
                         object_key.clone()
                     };
@@ -593,42 +1134,105 @@ impl UploadTab {
                     let upload_key = final_object_key.clone();
                     
                     let result = async {
-                        let file_data = std::fs::read(&file_path)?;
+                        let file_name = file_path
+                            .file_name()
+                            .map(|n| n.to_string_lossy().into_owned())
+                            .unwrap_or_else(|| "data".to_string());
+
+                        // The bar follows the bytes actually sent, after the 10% for reading
+                        let network_progress = Arc::new(
+                            GuiProgress::new(
+                                upload_progress.clone(),
+                                current_upload_file.clone(),
+                                ctx.clone(),
+                            )
+                            .with_span(0.1, 0.9),
+                        );
+
+                        if !encrypt {
+                            let options = base_options
+                                .clone()
+                                .with_original_filename(&file_name)
+                                .with_content_type_for(&file_name, false);
+                            upload_file_streaming(
+                                &state,
+                                &file_path,
+                                &upload_key,
+                                &options,
+                                &network_progress,
+                            )
+                            .await?;
+                            network_progress.finish("done");
+                            return Ok(());
+                        }
 
-                        // Set progress to 30% after reading
-                        *upload_progress.lock().unwrap() = 0.3;
-                        ctx.request_repaint();
+                        // Large files are encrypted in chunks as they are read, not in memory
+                        let file_size = std::fs::metadata(&file_path)?.len();
+                        if file_size >= multipart_upload::MULTIPART_THRESHOLD {
+                            let options = base_options
+                                .clone()
+                                .with_original_filename(&file_name)
+                                .with_encrypted(true)
+                                .with_content_type_for(&file_name, true);
+                            let progress = GuiProgress::new(
+                                upload_progress.clone(),
+                                current_upload_file.clone(),
+                                ctx.clone(),
+                            );
+                            return upload_file_encrypted_streaming(
+                                &state,
+                                &file_path,
+                                &upload_key,
+                                &options,
+                                encrypt_to_self,
+                                recipients.as_deref(),
+                                &progress,
+                            )
+                            .await;
+                        }
+
+                        let file_data = std::fs::read(&file_path)?;
 
                         let final_data = if encrypt {
                             let pgp_handler = state.lock().unwrap().pgp_handler.clone();
                             let encrypted = {
-                                let handler = pgp_handler.lock().unwrap();
-                                handler.encrypt(&file_data)?
+                                let mut handler = pgp_handler.lock().unwrap();
+                                handler.set_encrypt_to_self(encrypt_to_self);
+                                match &recipients {
+                                    Some(recipients) => {
+                                        handler.encrypt_to(&file_data, &file_name, recipients)?
+                                    }
+                                    None => {
+                                        handler.encrypt_with_filename(&file_data, &file_name)?
+                                    }
+                                }
                             };
-                            // Set progress to 50% after encryption
-                            *upload_progress.lock().unwrap() = 0.5;
-                            ctx.request_repaint();
                             Bytes::from(encrypted)
                         } else {
                             Bytes::from(file_data)
                         };
 
-                        let client = state
-                            .lock()
-                            .unwrap()
-                            .r2_client
+                        let options = base_options
                             .clone()
-                            .ok_or_else(|| anyhow::anyhow!("No R2 client available"))?;
-
-                        // Set progress to 70% before upload
-                        *upload_progress.lock().unwrap() = 0.7;
-                        ctx.request_repaint();
-
-                        client.upload_object(&upload_key, final_data).await?;
-
-                        // Set progress to 100% after upload
-                        *upload_progress.lock().unwrap() = 1.0;
-                        ctx.request_repaint();
+                            .with_original_filename(&file_name)
+                            .with_encrypted(encrypt)
+                            .with_content_type_for(&file_name, encrypt);
+                        let (key, options) = (upload_key.as_str(), &options);
+                        with_reconnect(&state, |client| {
+                            let data = final_data.clone();
+                            let progress = network_progress.clone();
+                            async move {
+                                let on_sent = move |sent, total| {
+                                    progress.set_length(total);
+                                    progress.set_position(sent);
+                                };
+                                client
+                                    .upload_object_with_progress(key, data, options, on_sent)
+                                    .await
+                            }
+                        })
+                        .await?;
+                        network_progress.finish("done");
 
                         Ok::<(), anyhow::Error>(())
                     }
@@ -676,17 +1280,29 @@ impl UploadTab {
         }
     }
 
+    /// True if `file` is selected and passes the pattern, so a folder upload includes it.
+    fn uploads(&self, file: &FolderFile) -> bool {
+        file.selected
+            && self
+                .pattern
+                .as_ref()
+                .is_ok_and(|pattern| pattern.matches(&file.relative_path))
+    }
+
     fn start_folder_upload(&mut self, ctx: &egui::Context) {
         let selected_files: Vec<FolderFile> = self
             .folder_files
             .iter()
-            .filter(|f| f.selected)
+            .filter(|f| self.uploads(f))
             .cloned()
             .collect();
 
-        if selected_files.is_empty() {
+        if selected_files.is_empty() || self.encryption_blocked() {
             return;
         }
+        let Some(base_options) = self.caching_options() else {
+            return;
+        };
 
         // Check if already uploading
         {
@@ -704,6 +1320,8 @@ impl UploadTab {
         let runtime = self.runtime.clone();
         let folder_prefix = self.folder_prefix.clone();
         let encrypt = self.encrypt_before_upload;
+        let encrypt_to_self = self.encrypt_to_self;
+        let recipients = self.take_recipients();
         let ctx = ctx.clone();
         let upload_in_progress = self.upload_in_progress.clone();
         let upload_progress = self.upload_progress.clone();
@@ -731,33 +1349,106 @@ impl UploadTab {
                         format!("{}/{}", folder_prefix, file.relative_path)
                     };
                     
-                    // Add .pgp extension if encrypting and not already present
-                    if encrypt && !object_key.ends_with(".pgp") {
-                        object_key.push_str(".pgp");
+                    // Add .pgp extension if encrypting and the key has no encrypted extension
+                    if encrypt {
+                        object_key = encryption_filter::with_encrypted_extension(&object_key);
                     }
 
                     let result = async {
+                        let file_name = file
+                            .path
+                            .file_name()
+                            .map(|n| n.to_string_lossy().into_owned())
+                            .unwrap_or_else(|| "data".to_string());
+
+                        if !encrypt {
+                            let options = base_options
+                                .clone()
+                                .with_original_filename(&file_name)
+                                .with_content_type_for(&file_name, false);
+                            let progress = Arc::new(
+                                GuiProgress::new(
+                                    upload_progress.clone(),
+                                    current_upload_file.clone(),
+                                    ctx.clone(),
+                                )
+                                .with_span(
+                                    completed_files as f32 / total_files as f32,
+                                    1.0 / total_files as f32,
+                                ),
+                            );
+                            return upload_file_streaming(
+                                &state,
+                                &file.path,
+                                &object_key,
+                                &options,
+                                &progress,
+                            )
+                            .await;
+                        }
+
+                        // Large files are encrypted in chunks as they are read, not in memory
+                        if file.size >= multipart_upload::MULTIPART_THRESHOLD {
+                            let options = base_options
+                                .clone()
+                                .with_original_filename(&file_name)
+                                .with_encrypted(true)
+                                .with_content_type_for(&file_name, true);
+                            let progress = GuiProgress::new(
+                                upload_progress.clone(),
+                                current_upload_file.clone(),
+                                ctx.clone(),
+                            )
+                            .with_span(
+                                completed_files as f32 / total_files as f32,
+                                1.0 / total_files as f32,
+                            );
+                            return upload_file_encrypted_streaming(
+                                &state,
+                                &file.path,
+                                &object_key,
+                                &options,
+                                encrypt_to_self,
+                                recipients.as_deref(),
+                                &progress,
+                            )
+                            .await;
+                        }
+
                         let file_data = std::fs::read(&file.path)?;
 
                         let final_data = if encrypt {
                             let pgp_handler = state.lock().unwrap().pgp_handler.clone();
                             let encrypted = {
-                                let handler = pgp_handler.lock().unwrap();
-                                handler.encrypt(&file_data)?
+                                let mut handler = pgp_handler.lock().unwrap();
+                                handler.set_encrypt_to_self(encrypt_to_self);
+                                match &recipients {
+                                    Some(recipients) => {
+                                        handler.encrypt_to(&file_data, &file_name, recipients)?
+                                    }
+                                    None => {
+                                        handler.encrypt_with_filename(&file_data, &file_name)?
+                                    }
+                                }
                             };
                             Bytes::from(encrypted)
                         } else {
                             Bytes::from(file_data)
                         };
 
-                        let client = state
-                            .lock()
-                            .unwrap()
-                            .r2_client
+                        let options = base_options
                             .clone()
-                            .ok_or_else(|| anyhow::anyhow!("No R2 client available"))?;
-
-                        client.upload_object(&object_key, final_data).await?;
+                            .with_original_filename(&file_name)
+                            .with_encrypted(encrypt)
+                            .with_content_type_for(&file_name, encrypt);
+                        let (key, options) = (object_key.as_str(), &options);
+                        with_reconnect(&state, |client| {
+                            let data = final_data.clone();
+                            async move {
+                                client.upload_object_with_options(key, data, options).await
+                            }
+                        })
+                        .await?;
 
                         Ok::<(), anyhow::Error>(())
                     }
@@ -778,7 +1469,7 @@ impl UploadTab {
                         uploads.push(upload_record);
                     }
 
-                    if let Err(e) = result {
+                    if result.is_err() {
                         // Failed to upload file
                     }
 
@@ -810,15 +1501,30 @@ impl UploadTab {
         });
     }
 
+    /// Browse to `prefix` ("" for the root, otherwise ending in '/') and list its subfolders.
+    fn open_bucket_folder(&mut self, prefix: String, ctx: &egui::Context) {
+        {
+            let mut state = self.bucket_state.lock().unwrap();
+            if state.loading {
+                return;
+            }
+            state.browse_prefix = prefix;
+        }
+        self.refresh_folders(ctx);
+    }
+
+    /// List the folders directly under the browsed folder. Only that one level is
+    /// fetched, so deep buckets don't have every key listed.
     fn refresh_folders(&mut self, ctx: &egui::Context) {
         // Check if already loading
-        {
+        let browse_prefix = {
             let mut state = self.bucket_state.lock().unwrap();
             if state.loading {
                 return;
             }
             state.loading = true;
-        }
+            state.browse_prefix.clone()
+        };
 
         let app_state = self.state.clone();
         let runtime = self.runtime.clone();
@@ -827,45 +1533,28 @@ impl UploadTab {
 
         std::thread::spawn(move || {
             runtime.block_on(async {
-                let result = async {
-                    let client = app_state
-                        .lock()
-                        .unwrap()
-                        .r2_client
-                        .clone()
-                        .ok_or_else(|| anyhow::anyhow!("No R2 client available"))?;
-
-                    let objects = client.list_objects(None).await?;
-                    Ok::<Vec<String>, anyhow::Error>(objects)
-                }
+                let prefix = Some(browse_prefix.as_str()).filter(|p| !p.is_empty());
+                let result = with_reconnect(&app_state, |client| async move {
+                    client.list_folder(prefix).await
+                })
                 .await;
 
                 let mut state = bucket_state.lock().unwrap();
                 state.loading = false;
 
                 match result {
-                    Ok(objects) => {
-                        // Extract unique folders from object keys
-                        let mut folders = HashSet::new();
-                        for obj in &objects {
-                            if let Some(pos) = obj.rfind('/') {
-                                let folder = &obj[..=pos];
-                                // Add all parent folders
-                                let parts: Vec<&str> =
-                                    folder.split('/').filter(|s| !s.is_empty()).collect();
-                                for i in 1..=parts.len() {
-                                    let partial = parts[..i].join("/");
-                                    folders.insert(partial);
-                                }
-                            }
-                        }
-
-                        let mut folder_list: Vec<String> = folders.into_iter().collect();
+                    Ok(listing) => {
+                        let mut folder_list: Vec<String> = listing
+                            .folders
+                            .iter()
+                            .map(|folder| folder.trim_end_matches('/').to_string())
+                            .filter(|folder| !folder.is_empty())
+                            .collect();
                         folder_list.sort();
                         state.folders = folder_list;
                         state.last_refresh = Some(std::time::Instant::now());
                     }
-                    Err(e) => {
+                    Err(_) => {
                         // Failed to refresh folders
                         state.folders.clear();
                     }
@@ -877,6 +1566,150 @@ impl UploadTab {
     }
 }
 
+/// Upload the file at `path` from disk without reading it into memory. The file is
+/// reopened if the upload is retried after a reconnect.
+async fn upload_file_streaming(
+    state: &Arc<Mutex<AppState>>,
+    path: &Path,
+    key: &str,
+    options: &UploadOptions,
+    progress: &Arc<GuiProgress>,
+) -> anyhow::Result<()> {
+    with_reconnect(state, |client| {
+        let progress = progress.clone();
+        async move {
+            let file = tokio::fs::File::open(path).await?;
+            let length = file.metadata().await?.len();
+            progress.set_length(length);
+            client
+                .upload_object_streaming_with_progress(
+                    key,
+                    file,
+                    length,
+                    options,
+                    move |sent, _| progress.set_position(sent),
+                )
+                .await
+        }
+    })
+    .await?;
+    Ok(())
+}
+
+/// Encrypt the file at `path` into the chunked format and upload it in parts, so it is
+/// never held in memory whole. Encrypts to `recipients` when given, else to every loaded
+/// key. A copy of the handler is used so the upload doesn't hold its lock.
+async fn upload_file_encrypted_streaming(
+    state: &Arc<Mutex<AppState>>,
+    path: &Path,
+    key: &str,
+    options: &UploadOptions,
+    encrypt_to_self: bool,
+    recipients: Option<&[String]>,
+    progress: &dyn ProgressReporter,
+) -> anyhow::Result<()> {
+    let (pgp_handler, client) = {
+        let state = state.lock().unwrap();
+        (state.pgp_handler.clone(), state.r2_client.clone())
+    };
+    let client = client.ok_or_else(|| anyhow::anyhow!("No R2 client available"))?;
+    let mut handler = pgp_handler.lock().unwrap().clone();
+    handler.set_encrypt_to_self(encrypt_to_self);
+    if let Some(recipients) = recipients {
+        let excluded: Vec<String> = handler
+            .get_loaded_keys()
+            .iter()
+            .filter(|info| {
+                !recipients
+                    .iter()
+                    .any(|fp| fp.eq_ignore_ascii_case(&info.fingerprint))
+            })
+            .map(|info| info.fingerprint.clone())
+            .collect();
+        for fingerprint in &excluded {
+            handler.remove_public_key(fingerprint);
+        }
+    }
+
+    MultipartUploader::upload_file(
+        &client,
+        path,
+        key,
+        options,
+        multipart_upload::DEFAULT_PART_SIZE,
+        multipart_upload::DEFAULT_PART_CONCURRENCY,
+        Some(&handler),
+        progress,
+    )
+    .await?;
+    Ok(())
+}
+
+/// Walk each `(folder, relative prefix)` on a new thread, handing the files found to
+/// `folder_scan` in batches. A scan already running is abandoned.
+fn start_folder_scan(
+    folder_scan: &Arc<Mutex<FolderScan>>,
+    roots: Vec<(PathBuf, String)>,
+    ctx: egui::Context,
+) -> std::thread::JoinHandle<()> {
+    let generation = {
+        let mut scan = folder_scan.lock().unwrap();
+        scan.generation += 1;
+        scan.pending.clear();
+        scan.scanned = 0;
+        scan.scanning = true;
+        scan.generation
+    };
+
+    let folder_scan = folder_scan.clone();
+    std::thread::spawn(move || {
+        // Returns false once a newer scan has replaced this one
+        let hand_over = |batch: &mut Vec<FolderFile>, done: bool| {
+            let mut scan = folder_scan.lock().unwrap();
+            if scan.generation != generation {
+                return false;
+            }
+            scan.scanned += batch.len();
+            scan.pending.append(batch);
+            scan.scanning = !done;
+            ctx.request_repaint();
+            true
+        };
+
+        let mut batch = Vec::new();
+        let mut current = true;
+        for (folder, prefix) in &roots {
+            transfer::scan_directory_with(folder, &mut |file| {
+                batch.push(FolderFile {
+                    path: file.path,
+                    relative_path: transfer::join_key(prefix, &file.relative_path),
+                    size: file.size,
+                    selected: true,
+                });
+                if batch.len() >= SCAN_BATCH_SIZE {
+                    current = hand_over(&mut batch, false);
+                }
+                current
+            });
+            if !current {
+                break;
+            }
+        }
+        if current {
+            hand_over(&mut batch, true);
+        }
+    })
+}
+
+/// The folder containing `prefix` ("a/b/" gives "a/", "a/" gives "").
+fn parent_folder(prefix: &str) -> &str {
+    let trimmed = prefix.trim_end_matches('/');
+    match trimmed.rfind('/') {
+        Some(pos) => &prefix[..=pos],
+        None => "",
+    }
+}
+
 fn format_size(bytes: u64) -> String {
     const UNITS: &[&str] = &["B", "KB", "MB", "GB"];
     let mut size = bytes as f64;
@@ -893,3 +1726,52 @@ fn format_size(bytes: u64) -> String {
         format!("{:.2} {}", size, UNITS[unit_index])
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    #[test]
+    fn folder_scans_run_in_the_background_and_find_every_file() {
+        let folder = TempDir::new();
+        for i in 0..1200 {
+            folder.write(&format!("batch{}/file{}.txt", i % 7, i), b"x");
+        }
+        folder.write(".git/config", b"hidden");
+        let small = TempDir::new();
+        small.write("only.txt", b"x");
+        let folder_scan = Arc::new(Mutex::new(FolderScan::default()));
+        let roots = |dir: &TempDir| vec![(dir.path().to_path_buf(), "docs".to_string())];
+
+        // A newer scan replaces one still running, whose files are all dropped
+        let abandoned = start_folder_scan(&folder_scan, roots(&folder), egui::Context::default());
+        let latest = start_folder_scan(&folder_scan, roots(&small), egui::Context::default());
+        abandoned.join().unwrap();
+        latest.join().unwrap();
+        {
+            let scan = folder_scan.lock().unwrap();
+            assert!(!scan.scanning);
+            let found: Vec<&str> = scan
+                .pending
+                .iter()
+                .map(|f| f.relative_path.as_str())
+                .collect();
+            assert_eq!(found, ["docs/only.txt"]);
+        }
+
+        start_folder_scan(&folder_scan, roots(&folder), egui::Context::default())
+            .join()
+            .unwrap();
+        let scan = folder_scan.lock().unwrap();
+        assert!(!scan.scanning);
+        assert_eq!(scan.scanned, 1200);
+        let found: HashSet<&str> = scan
+            .pending
+            .iter()
+            .map(|f| f.relative_path.as_str())
+            .collect();
+        assert_eq!(found.len(), 1200);
+        assert!(found.contains("docs/batch3/file10.txt"));
+    }
+}
@@ -0,0 +1,242 @@
+use crate::config::CompletionHook;
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+use std::time::Duration;
+
+// A hook that hangs mustn't hold up the transfers after it
+const HOOK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// What a completion hook is told: the JSON body POSTed to its URL, the JSON on its
+/// command's stdin, and the values of the command's placeholders.
+#[derive(Clone, Debug, Serialize)]
+pub struct CompletionEvent {
+    pub key: String, // The object, or the prefix of a whole folder upload
+    pub bytes: u64,
+    pub success: bool,
+    pub etag: Option<String>,
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub files: Option<usize>, // Files attempted, only for the end of a folder upload
+}
+
+impl CompletionEvent {
+    pub fn succeeded(key: &str, bytes: u64, etag: Option<String>) -> Self {
+        Self {
+            key: key.to_string(),
+            bytes,
+            success: true,
+            etag,
+            error: None,
+            files: None,
+        }
+    }
+
+    pub fn failed(key: &str, error: &str) -> Self {
+        Self {
+            key: key.to_string(),
+            bytes: 0,
+            success: false,
+            etag: None,
+            error: Some(error.to_string()),
+            files: None,
+        }
+    }
+}
+
+/// Run `hook` for `event`. A failing hook is logged and otherwise ignored, so it never
+/// fails the transfer it reports on.
+pub async fn on_complete(hook: &CompletionHook, event: &CompletionEvent) {
+    if let Some(command) = &hook.command {
+        if let Err(e) = run_command(command, event).await {
+            tracing::warn!("on_complete command for {} failed: {:#}", event.key, e);
+        }
+    }
+    if let Some(url) = &hook.url {
+        if let Err(e) = post_event(url, event).await {
+            tracing::warn!("on_complete webhook for {} failed: {:#}", event.key, e);
+        }
+    }
+}
+
+async fn run_command(template: &str, event: &CompletionEvent) -> Result<()> {
+    let command = render_command(template, event);
+    let payload = serde_json::to_vec(event)?;
+
+    #[cfg(windows)]
+    let mut child = tokio::process::Command::new("cmd");
+    #[cfg(windows)]
+    child.arg("/C").arg(&command);
+    #[cfg(not(windows))]
+    let mut child = tokio::process::Command::new("sh");
+    #[cfg(not(windows))]
+    child.arg("-c").arg(&command);
+
+    let mut child = child
+        .stdin(std::process::Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to start '{}'", command))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        use tokio::io::AsyncWriteExt;
+        // A command that doesn't read its stdin closes the pipe early, which is fine
+        let _ = stdin.write_all(&payload).await;
+    }
+    let status = tokio::time::timeout(HOOK_TIMEOUT, child.wait())
+        .await
+        .map_err(|_| anyhow!("'{}' didn't finish within {:?}", command, HOOK_TIMEOUT))??;
+    if !status.success() {
+        return Err(anyhow!("'{}' exited with {}", command, status));
+    }
+    Ok(())
+}
+
+async fn post_event(url: &str, event: &CompletionEvent) -> Result<()> {
+    reqwest::Client::new()
+        .post(url)
+        .json(event)
+        .timeout(HOOK_TIMEOUT)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+/// Fill the placeholders of a hook command. Values are shell-quoted, since keys and
+/// error messages can contain anything.
+fn render_command(template: &str, event: &CompletionEvent) -> String {
+    let mut command = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        command.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let value = rest.find('}').and_then(|end| {
+            let value = match &rest[1..end] {
+                "key" => event.key.clone(),
+                "bytes" => event.bytes.to_string(),
+                "success" => event.success.to_string(),
+                "etag" => event.etag.clone().unwrap_or_default(),
+                "error" => event.error.clone().unwrap_or_default(),
+                _ => return None,
+            };
+            Some((value, end))
+        });
+        match value {
+            Some((value, end)) => {
+                command.push_str(&shell_quote(&value));
+                rest = &rest[end + 1..];
+            }
+            // Not a placeholder, e.g. a shell brace expansion
+            None => {
+                command.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    command.push_str(rest);
+    command
+}
+
+#[cfg(not(windows))]
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+#[cfg(windows)]
+fn shell_quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// Accept one POST on a local port and return its URL and, once it has arrived, its body.
+    async fn receive_post() -> (String, tokio::task::JoinHandle<serde_json::Value>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hooks/upload", listener.local_addr().unwrap());
+        let body = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 4096];
+            loop {
+                let n = socket.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+                let text = String::from_utf8_lossy(&request).to_ascii_lowercase();
+                if let Some(end) = text.find("\r\n\r\n") {
+                    let length: usize = text
+                        .lines()
+                        .find_map(|line| line.strip_prefix("content-length:"))
+                        .map_or(0, |value| value.trim().parse().unwrap());
+                    if request.len() >= end + 4 + length {
+                        socket
+                            .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                            .await
+                            .unwrap();
+                        return serde_json::from_slice(&request[end + 4..]).unwrap();
+                    }
+                }
+            }
+        });
+        (url, body)
+    }
+
+    #[cfg(not(windows))]
+    #[tokio::test]
+    async fn hooks_receive_the_event_of_each_outcome() {
+        let dir = TempDir::new();
+        let events = [
+            CompletionEvent::succeeded("docs/it's here.txt", 42, Some("\"abc123\"".into())),
+            CompletionEvent::failed("docs/missing.txt", "Connection reset by peer"),
+        ];
+        for (i, event) in events.iter().enumerate() {
+            let (url, posted) = receive_post().await;
+            let stdin_file = dir.path().join(format!("stdin-{}.json", i));
+            let args_file = dir.path().join(format!("args-{}.txt", i));
+            let hook = CompletionHook {
+                command: Some(format!(
+                    "cat > '{}'; printf '%s|' {{key}} {{bytes}} {{success}} {{etag}} {{error}} > '{}'",
+                    stdin_file.display(),
+                    args_file.display()
+                )),
+                url: Some(url),
+                ..Default::default()
+            };
+            on_complete(&hook, event).await;
+
+            let expected = serde_json::to_value(event).unwrap();
+            let stdin: serde_json::Value =
+                serde_json::from_slice(&std::fs::read(&stdin_file).unwrap()).unwrap();
+            assert_eq!(stdin, expected);
+            assert_eq!(posted.await.unwrap(), expected);
+            let args = std::fs::read_to_string(&args_file).unwrap();
+            let expected_args = format!(
+                "{}|{}|{}|{}|{}|",
+                event.key,
+                event.bytes,
+                event.success,
+                event.etag.as_deref().unwrap_or_default(),
+                event.error.as_deref().unwrap_or_default()
+            );
+            assert_eq!(args, expected_args);
+        }
+        assert_eq!(
+            serde_json::to_value(&events[1]).unwrap(),
+            serde_json::json!({
+                "key": "docs/missing.txt",
+                "bytes": 0,
+                "success": false,
+                "etag": null,
+                "error": "Connection reset by peer",
+            })
+        );
+
+        // A hook that fails is only logged
+        let hook = CompletionHook {
+            command: Some("exit 3".into()),
+            url: Some("http://127.0.0.1:1/unreachable".into()),
+            ..Default::default()
+        };
+        on_complete(&hook, &events[0]).await;
+    }
+}
@@ -0,0 +1,133 @@
+use anyhow::{Context, Result};
+use chrono::Utc;
+use md5::{Digest, Md5};
+use sha2::Sha256;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+// Folder (inside a download destination) that holds downloads which failed verification
+pub const QUARANTINE_DIR: &str = ".quarantine";
+pub const FAILURE_LOG: &str = "verification-failures.log";
+
+/// Check a downloaded file against the size and ETag from the bucket listing.
+/// Returns a description of the mismatch, or None if the file checks out.
+/// Multipart ETags ("<md5>-<parts>") aren't a content hash, so only the size is checked for those.
+pub fn verify_download(
+    path: &Path,
+    expected_size: u64,
+    etag: Option<&str>,
+) -> Result<Option<String>> {
+    let actual_size = std::fs::metadata(path)?.len();
+    if actual_size != expected_size {
+        return Ok(Some(format!(
+            "size mismatch: expected {} bytes, got {}",
+            expected_size, actual_size
+        )));
+    }
+
+    if let Some(etag) = etag.map(|e| e.trim_matches('"')) {
+        if etag.len() == 32 && etag.chars().all(|c| c.is_ascii_hexdigit()) {
+            let actual_md5 = md5_file(path)?;
+            if !actual_md5.eq_ignore_ascii_case(etag) {
+                return Ok(Some(format!(
+                    "checksum mismatch: ETag {} but content MD5 is {}",
+                    etag, actual_md5
+                )));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// Move a bad download into `<dest_dir>/.quarantine/<relative_path>`, write a `.reason`
+/// sidecar next to it and append the failure to `<dest_dir>/verification-failures.log`.
+/// Returns the quarantined path.
+pub fn quarantine(
+    dest_dir: &Path,
+    relative_path: &Path,
+    bad_file: &Path,
+    key: &str,
+    reason: &str,
+) -> Result<PathBuf> {
+    let quarantined = dest_dir.join(QUARANTINE_DIR).join(relative_path);
+    if let Some(parent) = quarantined.parent() {
+        std::fs::create_dir_all(parent).context("Failed to create quarantine folder")?;
+    }
+    std::fs::rename(bad_file, &quarantined).context("Failed to move file to quarantine")?;
+
+    let timestamp = Utc::now().to_rfc3339();
+    let reason_path = PathBuf::from(format!("{}.reason", quarantined.display()));
+    std::fs::write(
+        &reason_path,
+        format!("key: {}\ntime: {}\nreason: {}\n", key, timestamp, reason),
+    )
+    .context("Failed to write quarantine reason")?;
+
+    let mut log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dest_dir.join(FAILURE_LOG))
+        .context("Failed to open verification failure log")?;
+    writeln!(log, "{}\t{}\t{}", timestamp, key, reason)
+        .context("Failed to write verification failure log")?;
+
+    Ok(quarantined)
+}
+
+/// Compare two files byte for byte, returning the offset of the first difference.
+/// If one file is a prefix of the other, the difference is at the shorter file's length.
+pub fn first_difference(left: &Path, right: &Path) -> Result<Option<u64>> {
+    let mut left = BufReader::with_capacity(64 * 1024, File::open(left)?);
+    let mut right = BufReader::with_capacity(64 * 1024, File::open(right)?);
+    let mut offset = 0u64;
+
+    loop {
+        let a = left.fill_buf()?;
+        let b = right.fill_buf()?;
+        if a.is_empty() || b.is_empty() {
+            return Ok((a.len() != b.len()).then_some(offset));
+        }
+
+        let n = a.len().min(b.len());
+        if let Some(i) = a[..n].iter().zip(&b[..n]).position(|(x, y)| x != y) {
+            return Ok(Some(offset + i as u64));
+        }
+        left.consume(n);
+        right.consume(n);
+        offset += n as u64;
+    }
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+fn md5_file(path: &Path) -> Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Md5::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
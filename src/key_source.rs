@@ -0,0 +1,240 @@
+use crate::crypto::{KeyInfo, PgpHandler};
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::process::Command;
+use std::time::Duration;
+
+// Keyservers that don't answer in this time are treated as unreachable
+const KEYSERVER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Somewhere to load PGP keys from. In the config file each source is an object with
+/// one field naming its kind:
+///
+///   {"file": "keys/alice.asc"}
+///   {"gpg_key_id": "0x1234ABCD"}     exported from the local GPG keyring
+///   {"keyserver": {"url": "https://keys.openpgp.org", "query": "bob@example.com"}}
+///   {"armored": "-----BEGIN PGP PUBLIC KEY BLOCK-----..."}
+///   {"env_var": "R2_TEAM_KEY"}       holding an armored key
+///
+/// Every source yields key data that is loaded as a keyring, so any of them may also
+/// carry a secret key.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeySource {
+    File(String),
+    GpgKeyId(String),
+    Keyserver { url: String, query: String },
+    Armored(String),
+    EnvVar(String),
+}
+
+impl fmt::Display for KeySource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeySource::File(path) => write!(f, "{}", path),
+            KeySource::GpgKeyId(id) => write!(f, "GPG key {}", id),
+            KeySource::Keyserver { url, query } => write!(f, "{} on {}", query, url),
+            KeySource::Armored(_) => write!(f, "armored key in the config"),
+            KeySource::EnvVar(name) => write!(f, "${}", name),
+        }
+    }
+}
+
+impl KeySource {
+    /// Read the key data this source points at.
+    pub async fn fetch(&self) -> Result<Vec<u8>> {
+        match self {
+            KeySource::File(path) => {
+                std::fs::read(path).with_context(|| format!("Failed to read key file {}", path))
+            }
+            KeySource::GpgKeyId(id) => export_from_gpg(id),
+            KeySource::Keyserver { url, query } => fetch_from_keyserver(url, query).await,
+            KeySource::Armored(armored) => Ok(armored.clone().into_bytes()),
+            KeySource::EnvVar(name) => std::env::var(name)
+                .map(String::into_bytes)
+                .with_context(|| format!("Environment variable {} is not set", name)),
+        }
+    }
+
+    /// Fetch this source's keys and load them into `handler`, returning the public keys
+    /// and whether a secret key was loaded too. `passphrase` unlocks a secret key.
+    pub async fn load_into(
+        &self,
+        handler: &mut PgpHandler,
+        passphrase: Option<&str>,
+    ) -> Result<(Vec<KeyInfo>, bool)> {
+        let key_data = self.fetch().await?;
+        handler
+            .load_keyring(&key_data, passphrase)
+            .with_context(|| format!("Failed to load keys from {}", self))
+    }
+}
+
+/// Export the public key `id` (a key ID, fingerprint or email) from the local GPG keyring.
+fn export_from_gpg(id: &str) -> Result<Vec<u8>> {
+    let output = Command::new("gpg")
+        .args(["--batch", "--armor", "--export", id])
+        .output()
+        .context("Failed to run gpg; is it installed?")?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "gpg --export {} failed: {}",
+            id,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    // gpg exits successfully with no output for keys it doesn't have
+    if output.stdout.is_empty() {
+        return Err(anyhow!("No key {} in the GPG keyring", id));
+    }
+    Ok(output.stdout)
+}
+
+/// Look up `query` (a key ID, fingerprint or email) on an HKP keyserver.
+async fn fetch_from_keyserver(url: &str, query: &str) -> Result<Vec<u8>> {
+    let mut lookup = url::Url::parse(url)
+        .with_context(|| format!("Invalid keyserver URL {}", url))?
+        .join("/pks/lookup")?;
+    lookup
+        .query_pairs_mut()
+        .append_pair("op", "get")
+        .append_pair("options", "mr")
+        .append_pair("search", query);
+
+    let response = reqwest::Client::new()
+        .get(lookup)
+        .timeout(KEYSERVER_TIMEOUT)
+        .send()
+        .await
+        .with_context(|| format!("Failed to reach keyserver {}", url))?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Err(anyhow!("No key matching {} on {}", query, url));
+    }
+    let response = response
+        .error_for_status()
+        .with_context(|| format!("Keyserver {} refused the lookup of {}", url, query))?;
+    Ok(response.bytes().await?.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, TempDir};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    async fn loaded_fingerprints(source: &KeySource) -> Vec<String> {
+        let mut handler = PgpHandler::new();
+        let (keys, secret) = source.load_into(&mut handler, None).await.unwrap();
+        assert!(!secret);
+        keys.into_iter().map(|key| key.fingerprint).collect()
+    }
+
+    /// A keyserver answering one lookup with `key`, and the request line it was sent.
+    async fn serve_key(key: Vec<u8>) -> (String, tokio::task::JoinHandle<String>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let request_line = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 4096];
+            while !request.ends_with(b"\r\n\r\n") {
+                let n = socket.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            let head = format!("HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n", key.len());
+            socket.write_all(head.as_bytes()).await.unwrap();
+            socket.write_all(&key).await.unwrap();
+            String::from_utf8_lossy(&request)
+                .lines()
+                .next()
+                .unwrap()
+                .to_string()
+        });
+        (url, request_line)
+    }
+
+    #[tokio::test]
+    async fn keys_load_from_every_kind_of_source() {
+        let key = test_support::key("Source");
+        let armored = String::from_utf8(key.public.clone()).unwrap();
+        let expected = [key.fingerprint.clone()];
+
+        let dir = TempDir::new();
+        let path = dir.write("keys/source.asc", &key.public);
+        let file = KeySource::File(path.display().to_string());
+        assert_eq!(loaded_fingerprints(&file).await, expected);
+
+        let inline = KeySource::Armored(armored.clone());
+        assert_eq!(loaded_fingerprints(&inline).await, expected);
+
+        std::env::set_var("RUST_R2_TEST_KEY_SOURCE", &armored);
+        let env = KeySource::EnvVar("RUST_R2_TEST_KEY_SOURCE".into());
+        assert_eq!(loaded_fingerprints(&env).await, expected);
+
+        let (url, request_line) = serve_key(key.public.clone()).await;
+        let keyserver = KeySource::Keyserver {
+            url,
+            query: "source@example.com".into(),
+        };
+        assert_eq!(loaded_fingerprints(&keyserver).await, expected);
+        let request_line = request_line.await.unwrap();
+        assert!(
+            request_line.starts_with("GET /pks/lookup?"),
+            "{}",
+            request_line
+        );
+        assert!(request_line.contains("op=get"), "{}", request_line);
+        assert!(
+            request_line.contains("search=source%40example.com"),
+            "{}",
+            request_line
+        );
+
+        // Secret keys load with their passphrase from a source held only in memory
+        let mut handler = PgpHandler::new();
+        let with_secret = KeySource::Armored(String::from_utf8(key.secret.clone()).unwrap());
+        let (_, secret) = with_secret
+            .load_into(&mut handler, Some(test_support::PASSPHRASE))
+            .await
+            .unwrap();
+        assert!(secret);
+
+        // A missing file or variable names what was missing
+        let missing = KeySource::File(dir.path().join("gone.asc").display().to_string());
+        let error = missing.load_into(&mut PgpHandler::new(), None).await;
+        assert!(format!("{:#}", error.unwrap_err()).contains("gone.asc"));
+        let unset = KeySource::EnvVar("RUST_R2_TEST_KEY_SOURCE_UNSET".into());
+        let error = unset.load_into(&mut PgpHandler::new(), None).await;
+        assert!(format!("{:#}", error.unwrap_err()).contains("RUST_R2_TEST_KEY_SOURCE_UNSET"));
+    }
+
+    #[tokio::test]
+    async fn keys_load_from_the_gpg_keyring() {
+        if Command::new("gpg").arg("--version").output().is_err() {
+            eprintln!("gpg is not installed, skipping");
+            return;
+        }
+        let key = test_support::key("Keyring");
+
+        // A keyring of its own, holding only the public key
+        let home = TempDir::new();
+        std::env::set_var("GNUPGHOME", home.path());
+        let public = home.write("import.asc", &key.public);
+        let status = Command::new("gpg")
+            .args(["--batch", "--import"])
+            .arg(&public)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success());
+
+        let source = KeySource::GpgKeyId(key.fingerprint.clone());
+        assert_eq!(loaded_fingerprints(&source).await, [key.fingerprint]);
+
+        let unknown = KeySource::GpgKeyId("nobody@example.com".into());
+        let error = unknown.load_into(&mut PgpHandler::new(), None).await;
+        assert!(format!("{:#}", error.unwrap_err()).contains("nobody@example.com"));
+        std::env::remove_var("GNUPGHOME");
+    }
+}
@@ -1,5 +1,40 @@
 // Core modules for rust-r2
 // Version 0.1.0 - Enterprise R2 storage solution with PGP encryption
+pub mod backup;
+pub mod chunked;
+pub mod concat;
 pub mod config;
+pub mod config_format;
+pub mod credentials;
 pub mod crypto;
+pub mod diff;
+pub mod disk_space;
+pub mod encryption_filter;
+pub mod error;
+pub mod folder_download;
+pub mod folder_upload;
+pub mod glob_filter;
+pub mod hooks;
+pub mod integrity;
+pub mod key_source;
+pub mod memory_budget;
+pub mod metrics;
+#[cfg(test)]
+#[allow(dead_code)]
+pub(crate) mod mock_r2;
+pub mod multipart_upload;
+pub mod pgp_stream;
+pub mod progress;
 pub mod r2_client;
+pub mod resume;
+pub mod retry_budget;
+pub mod sample;
+pub mod sidecar;
+pub mod tail;
+pub mod tar_stream;
+pub mod terminal;
+#[cfg(test)]
+#[allow(dead_code)]
+pub(crate) mod test_support;
+pub mod transfer;
+pub mod trust_store;
@@ -1,14 +1,75 @@
-mod config;
-mod crypto;
-mod r2_client;
-
 // R2 Bucket Manager - Enterprise Cloudflare R2 client
 use anyhow::{Context, Result};
 use bytes::Bytes;
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use futures_util::stream::{self, StreamExt};
+use rust_r2::credentials::CredentialSource;
+use rust_r2::encryption_filter::{self, EncryptionFilter};
+use rust_r2::error::{self, R2Error};
+use rust_r2::glob_filter::GlobFilter;
+use rust_r2::multipart_upload::{self, MultipartUploader};
+use rust_r2::progress::{format_size, ConsoleProgress, ProgressReporter};
+use rust_r2::trust_store::{KeyTrust, TrustStore};
+use rust_r2::{
+    backup, chunked, concat, config, crypto, diff, disk_space, folder_download, folder_upload,
+    hooks, integrity, memory_budget, r2_client, resume, sample, sidecar, tail, tar_stream,
+    terminal, transfer,
+};
+use serde_json::{json, Value};
+use std::collections::HashSet;
 use std::fs;
-use std::path::PathBuf;
+use std::io::{IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
 use tracing::info;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+
+// Test helpers shared with the library's tests; each binary uses only part of them
+#[cfg(test)]
+#[allow(dead_code)]
+#[path = "mock_r2.rs"]
+mod mock_r2;
+#[cfg(test)]
+#[allow(dead_code)]
+#[path = "test_support.rs"]
+mod test_support;
+
+// Exit status when --deadline stops a batch operation, matching timeout(1)
+const EXIT_DEADLINE: i32 = 124;
+
+// Set by --output json to the running command's name, which labels the result object
+static JSON_OUTPUT: OnceLock<&'static str> = OnceLock::new();
+
+// Set by --report jsonl, whose lines own stdout
+static ITEM_REPORT: OnceLock<ReportFormat> = OnceLock::new();
+
+// Set by cat and list --format json, which write their data straight to stdout
+static RAW_OUTPUT: OnceLock<()> = OnceLock::new();
+
+/// println! for human-readable output, which moves to stderr when stdout carries a JSON
+/// result, a per-item report, an object's bytes or a JSON listing.
+macro_rules! say {
+    ($($arg:tt)*) => {
+        if JSON_OUTPUT.get().is_some() || ITEM_REPORT.get().is_some() || RAW_OUTPUT.get().is_some() {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum ReportFormat {
+    Jsonl,
+}
 
 #[derive(Parser)]
 #[command(name = "rust-r2")]
@@ -17,45 +78,633 @@ struct Cli {
     #[arg(short, long, value_name = "FILE")]
     config: Option<PathBuf>,
 
+    #[arg(
+        long,
+        value_name = "NAME",
+        help = "Use this entry of `profiles` in the config file instead of the top-level r2 section"
+    )]
+    profile: Option<String>,
+
     #[arg(short, long)]
     verbose: bool,
 
+    #[arg(
+        long,
+        value_name = "DIR",
+        help = "Load every key file in this folder (overrides pgp.keyring_dir)"
+    )]
+    keyring_dir: Option<PathBuf>,
+
+    #[arg(
+        long,
+        global = true,
+        value_name = "CIPHER",
+        help = "Force aes128, aes192 or aes256 instead of negotiating from recipient keys"
+    )]
+    cipher: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = transfer::parse_duration,
+        help = "Stop folder transfers and reencrypt after this long, e.g. 30m or 1h30m"
+    )]
+    deadline: Option<Duration>,
+
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = transfer::parse_duration,
+        help = "Send a part upload again if it moves no data for this long (default 60s)"
+    )]
+    timeout_per_part: Option<Duration>,
+
+    #[arg(
+        long,
+        value_name = "SIZE",
+        value_parser = transfer::parse_size,
+        help = "Limit the upload parts buffered by all parallel transfers together, e.g. 512M or 2G"
+    )]
+    max_memory: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = multipart_upload::DEFAULT_PART_CONCURRENCY,
+        help = "Parts of each multipart upload sent at the same time"
+    )]
+    part_concurrency: usize,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = OutputFormat::Text,
+        help = "json prints one result object to stdout and sends logs to stderr"
+    )]
+    output: OutputFormat,
+
+    #[arg(
+        long,
+        value_enum,
+        help = "Print a line per file to stdout as folder transfers and resume-all finish each one (logs go to stderr)"
+    )]
+    report: Option<ReportFormat>,
+
+    #[arg(
+        long,
+        help = "Trust public keys not seen before without asking (changed keys still ask)"
+    )]
+    trust_new_keys: bool,
+
+    #[arg(
+        long,
+        help = "Also encrypt to your own secret key's public key so you can decrypt your uploads"
+    )]
+    encrypt_to_self: bool,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Don't ask before encrypting to the listed recipients (the list is still logged) or before delete --recursive"
+    )]
+    yes: bool,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Show which objects delete, sync, uploads --abort and reencrypt would change, changing nothing"
+    )]
+    dry_run: bool,
+
+    #[arg(
+        long,
+        help = "Print request counts and bytes transferred when the command finishes (always on with --verbose)"
+    )]
+    stats: bool,
+
     #[command(subcommand)]
     command: Commands,
 }
 
+// Select encrypted or plaintext objects by suffix, or by content with --verify-encryption.
+// A plain comment, since clap would take a doc comment as the about text of every
+// subcommand that flattens this.
+#[derive(Args)]
+struct EncryptionFilterArgs {
+    #[arg(
+        long,
+        conflicts_with = "only_plaintext",
+        help = "Only encrypted objects"
+    )]
+    only_encrypted: bool,
+
+    #[arg(long, help = "Only objects that are not encrypted")]
+    only_plaintext: bool,
+
+    #[arg(
+        long,
+        help = "Check metadata and content, not just the encrypted extension, when filtering"
+    )]
+    verify_encryption: bool,
+}
+
+impl EncryptionFilterArgs {
+    fn filter(&self) -> EncryptionFilter {
+        EncryptionFilter::from_flags(self.only_encrypted, self.only_plaintext)
+    }
+}
+
+// Select files or objects by glob pattern, matched against relative paths and keys.
+// Not a doc comment, for the same reason as EncryptionFilterArgs.
+#[derive(Args)]
+struct GlobFilterArgs {
+    #[arg(
+        long,
+        value_name = "GLOB",
+        help = "Only files or objects matching this pattern, e.g. '*.pdf'; repeat for more"
+    )]
+    include: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "GLOB",
+        help = "Skip files or objects matching this pattern, even if included; repeat for more"
+    )]
+    exclude: Vec<String>,
+}
+
+impl GlobFilterArgs {
+    fn filter(&self) -> Result<GlobFilter> {
+        GlobFilter::new(&self.include, &self.exclude)
+    }
+}
+
 #[derive(Subcommand)]
 enum Commands {
     Download {
         #[arg(help = "Object key in R2 bucket")]
         key: String,
 
-        #[arg(short, long, help = "Output file path")]
-        output: PathBuf,
+        #[arg(
+            short,
+            long,
+            help = "Output file or folder (defaults to the original filename in the current folder)"
+        )]
+        output: Option<PathBuf>,
 
         #[arg(short, long, help = "Decrypt the downloaded file")]
         decrypt: bool,
+
+        #[arg(
+            long,
+            help = "Inflate the body if the object is stored with Content-Encoding: gzip"
+        )]
+        decompress: bool,
+
+        #[arg(
+            long,
+            value_name = "DIR",
+            conflicts_with_all = ["output", "decompress"],
+            help = "Extract the object as a tar archive into this folder"
+        )]
+        untar: Option<PathBuf>,
     },
 
-    Upload {
-        #[arg(help = "Local file path")]
-        file: PathBuf,
+    Cat {
+        #[arg(help = "Object key to write to stdout")]
+        key: String,
+
+        #[arg(
+            short,
+            long,
+            help = "Decrypt the object (encrypted objects are detected and decrypted anyway)"
+        )]
+        decrypt: bool,
+
+        #[arg(
+            long,
+            conflicts_with = "decrypt",
+            help = "Write the stored bytes as they are, without decrypting"
+        )]
+        raw: bool,
+    },
+
+    DownloadFolder {
+        #[arg(help = "Key prefix to download")]
+        prefix: String,
+
+        #[arg(help = "Local folder to download into")]
+        dest: PathBuf,
+
+        #[arg(short, long, help = "Decrypt encrypted objects after download")]
+        decrypt: bool,
+
+        #[arg(
+            long,
+            default_value_t = 4,
+            help = "Number of objects to download concurrently"
+        )]
+        concurrency: usize,
+
+        #[command(flatten)]
+        encryption: EncryptionFilterArgs,
+
+        #[command(flatten)]
+        paths: GlobFilterArgs,
+    },
+
+    UploadUrl {
+        #[arg(help = "http:// or https:// URL to fetch")]
+        url: String,
 
         #[arg(help = "Object key in R2 bucket")]
         key: String,
 
+        #[arg(
+            short,
+            long,
+            help = "Encrypt while streaming (chunked format, decrypted by download --decrypt)"
+        )]
+        encrypt: bool,
+    },
+
+    Upload {
+        #[arg(help = "Local file or folder path")]
+        file: PathBuf,
+
+        #[arg(
+            help = "Object key in R2 bucket (key prefix when uploading a folder); defaults to the file name"
+        )]
+        key: Option<String>,
+
+        #[arg(
+            long,
+            conflicts_with = "key",
+            help = "Build the key from a template, e.g. 'backups/{Y}/{m}/{d}/{name}' (default: upload.key_template)"
+        )]
+        key_template: Option<String>,
+
         #[arg(short, long, help = "Encrypt the file before upload")]
         encrypt: bool,
+
+        #[arg(
+            long,
+            conflicts_with = "encrypt",
+            help = "Encrypt with a passphrase instead of the team keys ($R2_ENCRYPTION_PASSPHRASE, or asked for)"
+        )]
+        passphrase_encrypt: bool,
+
+        #[arg(
+            long,
+            overrides_with = "no_armor",
+            help = "Store encrypted files ASCII-armored (the default)"
+        )]
+        armor: bool,
+
+        #[arg(
+            long,
+            overrides_with = "armor",
+            help = "Store encrypted files as binary PGP, about a quarter smaller than armored"
+        )]
+        no_armor: bool,
+
+        #[arg(
+            long,
+            value_name = "ALGORITHM",
+            value_parser = crypto::parse_compression_algorithm,
+            help = "Compress before encrypting: zip, zlib or none (the default)"
+        )]
+        compress: Option<pgp::types::CompressionAlgorithm>,
+
+        #[arg(
+            long,
+            requires = "encrypt",
+            help = "Also sign with your secret key, so recipients can check who uploaded the file (single files under 100 MB)"
+        )]
+        sign: bool,
+
+        #[arg(
+            long,
+            requires = "encrypt",
+            conflicts_with = "backup_on_overwrite",
+            help = "Store under a random key, keeping the real key, filename and metadata in an encrypted <key>.meta.pgp sidecar"
+        )]
+        private_name: bool,
+
+        #[arg(
+            long,
+            requires = "encrypt",
+            help = "Store the SHA-256 of the unencrypted file as object metadata, so download can check what it decrypts; the hash is readable without the key"
+        )]
+        record_plaintext_hash: bool,
+
+        #[arg(
+            long,
+            help = "Content-Encoding of the file as stored, e.g. gzip for pre-compressed assets"
+        )]
+        content_encoding: Option<String>,
+
+        #[arg(
+            long,
+            help = "Cache-Control to store with the object, e.g. \"public, max-age=86400\""
+        )]
+        cache_control: Option<String>,
+
+        #[arg(
+            long,
+            value_parser = transfer::parse_expires,
+            help = "Expires time to store with the object: RFC 3339 date, HTTP date or a duration like 7d"
+        )]
+        expires: Option<chrono::DateTime<chrono::Utc>>,
+
+        #[arg(
+            long,
+            value_name = "KEY=VALUE",
+            value_parser = transfer::parse_metadata,
+            help = "Store custom metadata as an x-amz-meta-KEY header; repeat for more"
+        )]
+        metadata: Vec<(String, String)>,
+
+        #[arg(
+            long,
+            help = "Copy the object being replaced to <key>.bak-<timestamp> first"
+        )]
+        backup_on_overwrite: bool,
+
+        #[arg(
+            long,
+            default_value_t = backup::DEFAULT_KEEP_BACKUPS,
+            help = "Backups to keep per key with --backup-on-overwrite; older ones are deleted"
+        )]
+        keep_backups: usize,
+
+        #[command(flatten)]
+        paths: GlobFilterArgs,
+    },
+
+    UploadTar {
+        #[arg(help = "Local folder to archive")]
+        dir: PathBuf,
+
+        #[arg(help = "Object key for the archive, e.g. backup/dir.tar.pgp")]
+        key: String,
+
+        #[arg(short, long, help = "Encrypt the archive as it is uploaded")]
+        encrypt: bool,
+    },
+
+    Sync {
+        #[arg(help = "Local folder to mirror")]
+        dir: PathBuf,
+
+        #[arg(help = "Key prefix to upload the folder's files under")]
+        prefix: String,
+
+        #[arg(short, long, help = "Encrypt files before upload")]
+        encrypt: bool,
+
+        #[command(flatten)]
+        paths: GlobFilterArgs,
     },
 
     List {
         #[arg(short, long, help = "Prefix to filter objects")]
         prefix: Option<String>,
+
+        #[arg(long, help = "Show keys relative to the prefix")]
+        relative: bool,
+
+        #[arg(
+            long,
+            help = "List every profile in the config file, tagging keys with their profile"
+        )]
+        all_profiles: bool,
+
+        #[arg(
+            long,
+            value_enum,
+            default_value_t = OutputFormat::Text,
+            conflicts_with = "all_profiles",
+            help = "json prints a JSON array of objects with key, size and last_modified"
+        )]
+        format: OutputFormat,
+
+        #[command(flatten)]
+        encryption: EncryptionFilterArgs,
     },
 
     Delete {
+        #[arg(help = "Object key in R2 bucket, or a prefix with --recursive")]
+        key: String,
+
+        #[arg(
+            short,
+            long,
+            help = "Delete every object whose key starts with KEY (asks first unless --yes)"
+        )]
+        recursive: bool,
+
+        #[command(flatten)]
+        encryption: EncryptionFilterArgs,
+    },
+
+    Presign {
         #[arg(help = "Object key in R2 bucket")]
         key: String,
+
+        #[arg(
+            long,
+            default_value = "3600",
+            value_parser = transfer::parse_duration,
+            help = "How long the URL stays valid, e.g. 3600, 30m or 7d (at most 7 days)"
+        )]
+        expires: Duration,
+
+        #[arg(
+            long,
+            help = "Sign a URL for uploading to the key instead of downloading it"
+        )]
+        put: bool,
+    },
+
+    Copy {
+        #[arg(help = "Object key to copy")]
+        source_key: String,
+
+        #[arg(help = "Object key to copy it to")]
+        dest_key: String,
+    },
+
+    Restore {
+        #[arg(help = "Deleted object key to restore from its previous version")]
+        key: String,
+    },
+
+    RestoreBackup {
+        #[arg(help = "Object key to restore from its latest .bak- backup")]
+        key: String,
+    },
+
+    VerifyEncrypted {
+        #[arg(help = "Chunked encrypted object to check")]
+        key: String,
+    },
+
+    #[command(visible_alias = "head")]
+    Stat {
+        #[arg(help = "Object key to show")]
+        key: String,
+
+        #[arg(long, help = "Print the result as JSON, like --output json")]
+        json: bool,
+    },
+
+    Inspect {
+        #[arg(help = "Encrypted object to inspect, or a local file with --local")]
+        target: String,
+
+        #[arg(long, help = "Read TARGET from the local disk instead of the bucket")]
+        local: bool,
+    },
+
+    Tail {
+        #[arg(help = "Prefix to watch for new objects")]
+        prefix: String,
+
+        #[arg(long, help = "Download new objects into this folder")]
+        download_dir: Option<PathBuf>,
+
+        #[arg(short, long, help = "Decrypt downloaded objects that are encrypted")]
+        decrypt: bool,
+
+        #[arg(
+            long,
+            default_value = "10s",
+            value_parser = transfer::parse_duration,
+            help = "How often to list the prefix, e.g. 30s or 5m"
+        )]
+        interval: Duration,
+
+        #[arg(long, help = "Also report the objects already there when tail starts")]
+        from_start: bool,
+    },
+
+    ResumeAll {
+        #[arg(
+            long,
+            default_value_t = 4,
+            help = "Number of transfers to resume concurrently"
+        )]
+        concurrency: usize,
+    },
+
+    Uploads {
+        #[arg(short, long, help = "Only list uploads to keys under this prefix")]
+        prefix: Option<String>,
+
+        #[arg(long, help = "Abort the listed uploads, deleting their stored parts")]
+        abort: bool,
+
+        #[arg(
+            long,
+            value_parser = transfer::parse_duration,
+            help = "Only include uploads started longer ago than this, e.g. 1d or 12h"
+        )]
+        older_than: Option<Duration>,
+    },
+
+    Concat {
+        #[arg(help = "Prefix whose objects are joined, e.g. backups/archive.tar.part-")]
+        prefix: String,
+
+        #[arg(help = "Local file to write")]
+        output: PathBuf,
+
+        #[arg(short, long, help = "Decrypt encrypted parts before joining them")]
+        decrypt: bool,
+    },
+
+    Sample {
+        #[arg(help = "Prefix whose objects are sampled")]
+        prefix: String,
+
+        #[arg(
+            short,
+            long,
+            default_value_t = 1024,
+            help = "Bytes to read from the start of each object"
+        )]
+        bytes: usize,
+
+        #[arg(
+            short,
+            long,
+            help = "Save each sample under this folder instead of printing it"
+        )]
+        output: Option<PathBuf>,
+
+        #[arg(long, help = "Show encrypted objects as stored instead of decrypting")]
+        raw: bool,
+
+        #[arg(
+            long,
+            default_value_t = 8,
+            help = "Number of objects to sample concurrently"
+        )]
+        concurrency: usize,
+    },
+
+    Ping,
+
+    ShowConfig,
+
+    ImportRclone {
+        #[arg(
+            value_name = "REMOTE:BUCKET",
+            help = "rclone remote and bucket, as in rclone paths, e.g. r2:backups"
+        )]
+        remote: String,
+
+        #[arg(long, help = "Replace the config file if it already exists")]
+        force: bool,
+    },
+
+    SetCredentials {
+        #[arg(
+            long,
+            help = "Update this entry of `profiles` instead of the top-level r2 section"
+        )]
+        profile: Option<String>,
+
+        #[arg(long, help = "New access key ID (asked for if omitted)")]
+        access_key_id: Option<String>,
+
+        #[arg(long, help = "Also change the account ID")]
+        account_id: Option<String>,
+
+        #[arg(long, help = "Also change the bucket")]
+        bucket: Option<String>,
+
+        #[arg(
+            long,
+            help = "Save even if the new credentials fail the check against R2"
+        )]
+        force: bool,
+    },
+
+    Diff {
+        #[arg(help = "Object key in R2 bucket")]
+        left: String,
+
+        #[arg(help = "Object key, or a local file if one exists at this path")]
+        right: String,
+
+        #[arg(
+            short,
+            long,
+            help = "Decrypt encrypted sides and compare the plaintexts"
+        )]
+        decrypt: bool,
     },
 
     Process {
@@ -67,122 +716,321 @@ enum Commands {
 
         #[arg(short, long, help = "Local temporary file (optional)")]
         temp_file: Option<PathBuf>,
+
+        #[arg(
+            long,
+            help = "Content-Type for the uploaded object (defaults to the source's)"
+        )]
+        content_type: Option<String>,
+    },
+
+    Reencrypt {
+        #[arg(help = "Prefix of the objects to re-encrypt")]
+        prefix: String,
+
+        #[arg(long, help = "Recipient group from pgp.recipient_groups to encrypt to")]
+        to_group: String,
+
+        #[arg(
+            long,
+            help = "Upload re-encrypted objects under this prefix instead of in place"
+        )]
+        dest_prefix: Option<String>,
+
+        #[arg(
+            long,
+            default_value_t = 4,
+            help = "Number of objects to process concurrently"
+        )]
+        concurrency: usize,
+
+        #[arg(
+            long,
+            requires = "dry_run",
+            help = "With --dry-run, download and decrypt every object instead of checking its recipients"
+        )]
+        deep: bool,
     },
 }
 
+impl Commands {
+    /// The subcommand as typed on the command line.
+    fn name(&self) -> &'static str {
+        match self {
+            Commands::Download { .. } => "download",
+            Commands::Cat { .. } => "cat",
+            Commands::DownloadFolder { .. } => "download-folder",
+            Commands::UploadUrl { .. } => "upload-url",
+            Commands::Upload { .. } => "upload",
+            Commands::UploadTar { .. } => "upload-tar",
+            Commands::Sync { .. } => "sync",
+            Commands::List { .. } => "list",
+            Commands::Delete { .. } => "delete",
+            Commands::Presign { .. } => "presign",
+            Commands::Copy { .. } => "copy",
+            Commands::Restore { .. } => "restore",
+            Commands::RestoreBackup { .. } => "restore-backup",
+            Commands::VerifyEncrypted { .. } => "verify-encrypted",
+            Commands::Stat { .. } => "stat",
+            Commands::Inspect { .. } => "inspect",
+            Commands::Tail { .. } => "tail",
+            Commands::Concat { .. } => "concat",
+            Commands::Sample { .. } => "sample",
+            Commands::ResumeAll { .. } => "resume-all",
+            Commands::Uploads { .. } => "uploads",
+            Commands::Ping => "ping",
+            Commands::ShowConfig => "show-config",
+            Commands::ImportRclone { .. } => "import-rclone",
+            Commands::SetCredentials { .. } => "set-credentials",
+            Commands::Diff { .. } => "diff",
+            Commands::Process { .. } => "process",
+            Commands::Reencrypt { .. } => "reencrypt",
+        }
+    }
+
+    /// Whether the command honours --dry-run. Anything that deletes or overwrites objects
+    /// in bulk belongs here.
+    fn supports_dry_run(&self) -> bool {
+        matches!(
+            self,
+            Commands::Delete { .. }
+                | Commands::Sync { .. }
+                | Commands::Uploads { .. }
+                | Commands::Reencrypt { .. }
+        )
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
 
+    let json_output = cli.output == OutputFormat::Json
+        || matches!(cli.command, Commands::Stat { json: true, .. });
+    if json_output {
+        let _ = JSON_OUTPUT.set(cli.command.name());
+    }
+    if let Some(report) = cli.report {
+        let _ = ITEM_REPORT.set(report);
+    }
+    if let Some(bytes) = cli.max_memory {
+        memory_budget::set_memory_budget(bytes);
+    }
+    if cli.dry_run && !cli.command.supports_dry_run() {
+        return Err(anyhow::anyhow!(
+            "--dry-run only works with delete, sync, uploads and reencrypt, not {}",
+            cli.command.name()
+        ));
+    }
+
+    if matches!(
+        cli.command,
+        Commands::Cat { .. }
+            | Commands::List {
+                format: OutputFormat::Json,
+                ..
+            }
+    ) {
+        let _ = RAW_OUTPUT.set(());
+    }
+
+    // Keep stdout clean for the JSON result, the per-item report and raw output
+    let writer = if json_output || cli.report.is_some() || RAW_OUTPUT.get().is_some() {
+        BoxMakeWriter::new(std::io::stderr)
+    } else {
+        BoxMakeWriter::new(std::io::stdout)
+    };
     let subscriber = tracing_subscriber::FmtSubscriber::builder()
         .with_max_level(if cli.verbose {
             tracing::Level::DEBUG
         } else {
             tracing::Level::INFO
         })
+        .with_writer(writer)
         .finish();
 
     tracing::subscriber::set_global_default(subscriber)
         .context("Failed to set tracing subscriber")?;
 
-    let config = if let Some(config_path) = cli.config {
-        config::Config::from_file(&config_path)?
-    } else {
-        // Try to load config.json from current directory first
-        let default_config = std::path::Path::new("config.json");
-        if default_config.exists() {
-            info!("Auto-loading config.json from current directory");
-            config::Config::from_file(default_config)?
-        } else {
-            config::Config::from_env()?
+    let result = run(cli).await;
+    if let Some(exceeded) = result
+        .as_ref()
+        .err()
+        .and_then(|e| e.downcast_ref::<DeadlineExceeded>())
+    {
+        exceeded.report();
+        std::process::exit(EXIT_DEADLINE);
+    }
+    let Some(command) = JSON_OUTPUT.get() else {
+        return result.map(|_| ());
+    };
+    match result {
+        Ok(fields) => {
+            println!("{}", result_json(command, fields));
+            Ok(())
+        }
+        Err(e) => {
+            println!("{}", error_json(command, &e));
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Run the parsed command, returning the fields of its JSON result.
+async fn run(mut cli: Cli) -> Result<Value> {
+    // Rotating credentials has to work when the current ones no longer do, so it
+    // runs before any client is set up
+    if let Commands::SetCredentials {
+        profile,
+        access_key_id,
+        account_id,
+        bucket,
+        force,
+    } = &cli.command
+    {
+        let path = match cli.config.or_else(config::default_config_path) {
+            Some(path) => path,
+            None => {
+                return Err(anyhow::anyhow!(
+                "set-credentials needs a config file: pass --config or run it next to config.json"
+            ))
+            }
+        };
+        return set_credentials(
+            &path,
+            profile.as_deref(),
+            access_key_id.clone(),
+            None,
+            account_id.clone(),
+            bucket.clone(),
+            *force,
+        )
+        .await;
+    }
+
+    // Importing creates the config file everything below reads
+    if let Commands::ImportRclone { remote, force } = &cli.command {
+        let path = cli.config.unwrap_or_else(|| PathBuf::from("config.json"));
+        return import_rclone(&path, remote, *force);
+    }
+
+    // A config file in the current folder comes before the environment
+    let config_path = cli.config.clone().or_else(|| {
+        let default_config = config::default_config_path()?;
+        info!(
+            "Auto-loading {} from current directory",
+            default_config.display()
+        );
+        Some(default_config)
+    });
+    // Remember where credentials came from so they can be reloaded if they expire mid-run
+    let (config, credential_source) = match config_path {
+        Some(config_path) => {
+            let (config, overrides) =
+                config::Config::from_file_with_env_overrides(&config_path, cli.profile.as_deref())?;
+            if let Some(profile) = cli.profile.as_ref().or(config.default_profile.as_ref()) {
+                info!("Using profile '{}' of {}", profile, config_path.display());
+            }
+            if !overrides.is_empty() {
+                info!(
+                    "Overriding {} with {}",
+                    config_path.display(),
+                    overrides.join(", ")
+                );
+            }
+            let profile = cli.profile.clone().or(config.default_profile.clone());
+            (config, CredentialSource::ConfigFile(config_path, profile))
+        }
+        None if cli.profile.is_some() => {
+            return Err(anyhow::anyhow!(
+                "--profile needs a config file: pass --config or run it next to config.json"
+            ))
         }
+        None => (config::Config::from_env()?, CredentialSource::Environment),
     };
 
+    if let Commands::ShowConfig = cli.command {
+        return Ok(show_config(&config, &credential_source, &cli));
+    }
+
     let r2_client = r2_client::R2Client::new(
         config.r2.access_key_id.clone(),
         config.r2.secret_access_key.clone(),
         config.r2.account_id.clone(),
         config.r2.bucket_name.clone(),
     )
-    .await?;
+    .await?
+    .with_client_config(config.client_config())?
+    .with_credential_source(credential_source)
+    .with_retry_policy(config.retry.policy())
+    .with_part_stall_timeout(
+        cli.timeout_per_part
+            .unwrap_or(r2_client::DEFAULT_PART_STALL_TIMEOUT),
+    );
 
-    let mut pgp_handler = crypto::PgpHandler::new();
+    encryption_filter::set_encrypted_extensions(&config.pgp.encrypted_extensions);
 
-    // Load team keys (handles keyrings with both public and private keys)
-    for key_path in &config.pgp.team_keys {
-        match fs::read(key_path) {
-            Ok(key_data) => {
-                match pgp_handler.load_keyring(&key_data, config.pgp.passphrase.as_deref()) {
-                    Ok((key_infos, private_key_loaded)) => {
-                        info!("Loaded {} public keys from {}", key_infos.len(), key_path);
-                        for key_info in key_infos {
-                            info!("  - {} <{}>", key_info.name, key_info.email);
-                        }
-                        if private_key_loaded {
-                            info!("Also loaded private key from {}", key_path);
-                        }
-                    }
-                    Err(e) => {
-                        tracing::warn!("Failed to load keyring from {}: {}", key_path, e);
-                    }
-                }
-            }
-            Err(e) => {
-                tracing::warn!("Failed to read key file {}: {}", key_path, e);
-            }
+    let mut pgp_handler = crypto::PgpHandler::new();
+    if let Some(cipher) = cli.cipher.as_deref().or(config.pgp.cipher.as_deref()) {
+        pgp_handler.set_cipher_algorithm(Some(crypto::parse_cipher_algorithm(cipher)?));
+    }
+    pgp_handler.set_skip_expired(config.pgp.skip_expired_keys);
+    if let Commands::Upload {
+        no_armor, compress, ..
+    } = &cli.command
+    {
+        pgp_handler.set_armor(!no_armor);
+        if let Some(compression) = compress {
+            pgp_handler.set_compression(*compression);
         }
     }
 
-    // Load legacy public_key_paths for backward compatibility
-    for key_path in &config.pgp.public_key_paths {
-        match fs::read(key_path) {
-            Ok(key_data) => match pgp_handler.load_public_key(&key_data) {
-                Ok(key_info) => {
-                    info!(
-                        "Loaded public key: {} <{}> from {}",
-                        key_info.name, key_info.email, key_path
-                    );
+    // Load team keys and other key sources (keyrings may hold a private key too)
+    for source in config.pgp.all_key_sources() {
+        match source
+            .load_into(&mut pgp_handler, config.pgp.passphrase.as_deref())
+            .await
+        {
+            Ok((key_infos, private_key_loaded)) => {
+                info!("Loaded {} public keys from {}", key_infos.len(), source);
+                for key_info in key_infos {
+                    info!("  - {} <{}>", key_info.name, key_info.email);
                 }
-                Err(e) => {
-                    tracing::warn!("Failed to load key from {}: {}", key_path, e);
+                if private_key_loaded {
+                    info!("Also loaded private key from {}", source);
                 }
-            },
+            }
             Err(e) => {
-                tracing::warn!("Failed to read key file {}: {}", key_path, e);
+                tracing::warn!("{:#}", e);
             }
         }
     }
 
-    // Load legacy team_keys_detailed for backward compatibility
-    for team_key in &config.pgp.team_keys_detailed {
-        if team_key.enabled {
-            match fs::read(&team_key.public_key_path) {
-                Ok(key_data) => match pgp_handler.load_public_key(&key_data) {
-                    Ok(key_info) => {
-                        info!(
-                            "Loaded team key: {} <{}> from {}",
-                            key_info.name, key_info.email, team_key.public_key_path
-                        );
-                    }
-                    Err(e) => {
-                        tracing::warn!(
-                            "Failed to load key from {}: {}",
-                            team_key.public_key_path,
-                            e
-                        );
-                    }
-                },
-                Err(e) => {
-                    tracing::warn!(
-                        "Failed to read key file {}: {}",
-                        team_key.public_key_path,
-                        e
-                    );
+    // Load every key file from the keyring folder, if one is configured
+    let keyring_dir = cli
+        .keyring_dir
+        .clone()
+        .or_else(|| config.pgp.keyring_dir.as_ref().map(PathBuf::from));
+    if let Some(dir) = &keyring_dir {
+        match pgp_handler.load_keyring_dir(dir, config.pgp.passphrase.as_deref()) {
+            Ok((key_infos, private_key_loaded)) => {
+                info!(
+                    "Loaded {} keys from keyring folder {}",
+                    key_infos.len(),
+                    dir.display()
+                );
+                if private_key_loaded {
+                    info!("Also loaded private key from {}", dir.display());
                 }
             }
+            Err(e) => {
+                tracing::warn!("Failed to load keyring folder {}: {}", dir.display(), e);
+            }
         }
     }
 
+    verify_key_trust(&mut pgp_handler, cli.trust_new_keys)?;
+
     if pgp_handler.public_key_count() > 0 {
         info!(
             "Loaded {} public keys for encryption",
@@ -190,34 +1038,101 @@ async fn main() -> Result<()> {
         );
     }
 
-    // Load separate secret key if specified and not already loaded from a keyring
-    if !pgp_handler.has_secret_key() {
-        if let Some(secret_key_path) = &config.pgp.secret_key_path {
-            let key_data = fs::read(secret_key_path).context("Failed to read secret key file")?;
-            pgp_handler.load_secret_key(&key_data, config.pgp.passphrase.as_deref())?;
-            info!("Loaded secret key from {}", secret_key_path);
+    // Load the separate secret key too; duplicates of keyring keys are skipped
+    if let Some(secret_key_path) = &config.pgp.secret_key_path {
+        let key_data = fs::read(secret_key_path).context("Failed to read secret key file")?;
+        pgp_handler.load_secret_key(&key_data, config.pgp.passphrase.as_deref())?;
+        info!("Loaded secret key from {}", secret_key_path);
+    }
+
+    pgp_handler.set_key_passphrases(&config.pgp.key_passphrases);
+
+    if pgp_handler.secret_key_count() > 1 {
+        info!(
+            "Loaded {} secret keys for decryption",
+            pgp_handler.secret_key_count()
+        );
+    }
+
+    if cli.encrypt_to_self || config.pgp.encrypt_to_self {
+        if pgp_handler.has_secret_key() {
+            pgp_handler.set_encrypt_to_self(true);
+            info!("Encrypting to self as well as the loaded public keys");
+        } else {
+            tracing::warn!("--encrypt-to-self needs a secret key, but none is loaded");
         }
-    } else {
-        info!("Secret key already loaded from keyring");
     }
 
-    match cli.command {
+    // Show exactly who encrypted uploads will be readable by before anything is sent
+    let encrypts_upload = matches!(
+        cli.command,
+        Commands::Upload { encrypt: true, .. }
+            | Commands::UploadTar { encrypt: true, .. }
+            | Commands::Sync { encrypt: true, .. }
+            | Commands::UploadUrl { encrypt: true, .. }
+    );
+    if encrypts_upload && !cli.dry_run && pgp_handler.recipient_count() > 0 {
+        confirm_recipients(&pgp_handler.planned_recipients(None), cli.yes)?;
+    }
+
+    let show_stats = cli.stats || cli.verbose;
+    // Single-object uploads tell the on_complete hook once the command finishes; folder
+    // uploads do it themselves, per file or at the end
+    let completion_hook = config.hooks.on_complete.clone();
+    let hook_target = resolve_upload_key(&mut cli.command, &config)?;
+    let result: Result<Value> = async {
+    Ok(match cli.command {
         Commands::Download {
             key,
             output,
             mut decrypt,
+            decompress,
+            untar,
         } => {
             info!("Downloading object: {}", key);
-            let data = r2_client.download_object(&key).await?;
+            if let Some(dest) = untar {
+                return download_untar(&r2_client, &pgp_handler, &key, &dest).await;
+            }
 
-            // Auto-detect encryption if file has .pgp extension or contains PGP data
-            let is_encrypted = key.ends_with(".pgp") || crypto::PgpHandler::is_pgp_encrypted(&data);
+            // Large objects stream to disk, so they resume after an interruption and
+            // never sit in memory
+            let size = r2_client
+                .head_object(&key)
+                .await?
+                .content_length
+                .unwrap_or(0);
+            if size >= folder_download::RESUMABLE_DOWNLOAD_THRESHOLD {
+                return download_large(
+                    &r2_client,
+                    &pgp_handler,
+                    &key,
+                    output,
+                    decrypt,
+                    decompress,
+                    size,
+                )
+                .await;
+            }
 
-            if is_encrypted && !decrypt {
+            let (data, head) = r2_client.download_object_with_metadata(&key).await?;
+
+            let data = if decompress && transfer::is_gzip_encoding(head.content_encoding.as_deref())
+            {
+                info!("Decompressing gzip Content-Encoding");
+                Bytes::from(transfer::gunzip(&data)?)
+            } else {
+                data
+            };
+
+            // Auto-detect encryption from the key's extension or the PGP data itself
+            let has_extension = encryption_filter::is_encrypted_extension(&key);
+            let is_encrypted = has_extension || crypto::PgpHandler::is_pgp_encrypted(&data);
+
+            if is_encrypted && !decrypt {
                 info!(
                     "Auto-detected encrypted file ({})",
-                    if key.ends_with(".pgp") {
-                        ".pgp extension"
+                    if has_extension {
+                        "encrypted extension"
                     } else {
                         "PGP headers"
                     }
@@ -225,167 +1140,3509 @@ async fn main() -> Result<()> {
                 decrypt = true;
             }
 
+            let mut restored_name = head.original_filename();
             let final_data = if decrypt {
                 if !is_encrypted {
                     info!("Warning: File does not appear to be encrypted, skipping decryption");
                     data
                 } else {
                     info!("Decrypting downloaded data");
-                    let decrypted = pgp_handler.decrypt(&data)?;
-                    Bytes::from(decrypted)
+                    if is_passphrase_only(&pgp_handler, &data) {
+                        let passphrase = encryption_passphrase(false)?;
+                        Bytes::from(pgp_handler.decrypt_symmetric(&data, &passphrase)?)
+                    } else {
+                        let (decrypted, literal_name) = pgp_handler.decrypt_with_filename(&data)?;
+                        restored_name = restored_name.or(literal_name);
+                        if head.plaintext_sha256().is_some() {
+                            let sha256 = integrity::sha256_hex(&decrypted);
+                            verify_plaintext_sha256(&key, &head, &sha256)?;
+                        }
+                        Bytes::from(decrypted)
+                    }
                 }
             } else {
                 data
             };
+            let private =
+                private_metadata(&r2_client, &pgp_handler, &key, decrypt && is_encrypted).await?;
+            if let Some(private) = &private {
+                restored_name = Some(private.file_name.clone());
+            }
+
+            // Only restore the original name for decrypted output, otherwise keep the key's name
+            let default_name = transfer::download_file_name(
+                &key,
+                restored_name.as_deref().filter(|_| decrypt && is_encrypted),
+                decrypt,
+            );
+            let output = match output {
+                Some(path) if path.is_dir() => path.join(&default_name),
+                Some(path) => path,
+                None => PathBuf::from(&default_name),
+            };
 
             fs::write(&output, &final_data).context("Failed to write output file")?;
             info!("Downloaded to: {}", output.display());
+            json!({
+                "key": key,
+                "path": output.display().to_string(),
+                "bytes": final_data.len(),
+                "decrypted": decrypt && is_encrypted,
+                "private_name": private.as_ref().map(|p| &p.key),
+                "metadata": private.as_ref().map(|p| &p.metadata),
+            })
+        }
+
+        Commands::Cat { key, decrypt, raw } => {
+            if JSON_OUTPUT.get().is_some() {
+                return Err(anyhow::anyhow!(
+                    "cat writes the object to stdout, so it can't be combined with --output json"
+                ));
+            }
+            info!("Reading object: {}", key);
+            let data = r2_client.download_object(&key).await?;
+
+            // Auto-detect encryption from the key's extension or the PGP data itself
+            let is_encrypted = encryption_filter::is_encrypted_extension(&key)
+                || crypto::PgpHandler::is_pgp_encrypted(&data);
+            if decrypt && !is_encrypted {
+                info!("Warning: Object does not appear to be encrypted, skipping decryption");
+            }
+
+            // Binary data goes out as-is; Rust's stdout never translates line endings
+            let mut stdout = std::io::stdout().lock();
+            let result = if is_encrypted && !raw {
+                info!("Decrypting object");
+                if is_passphrase_only(&pgp_handler, &data) {
+                    let passphrase = encryption_passphrase(false)?;
+                    let plaintext = pgp_handler.decrypt_symmetric(&data, &passphrase)?;
+                    stdout.write_all(&plaintext).map(|_| plaintext.len() as u64)
+                } else {
+                    let mut encrypted = std::io::Cursor::new(&data);
+                    match pgp_handler.decrypt_to_writer(&mut encrypted, &mut stdout) {
+                        Ok(bytes) => Ok(bytes),
+                        Err(e) if is_broken_pipe(&e) => Err(std::io::ErrorKind::BrokenPipe.into()),
+                        Err(e) => return Err(e),
+                    }
+                }
+            } else {
+                stdout.write_all(&data).map(|_| data.len() as u64)
+            }
+            .and_then(|bytes| stdout.flush().map(|_| bytes));
+
+            // A reader that stops early, like `head`, isn't a failure
+            let bytes = match result {
+                Ok(bytes) => bytes,
+                Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => return Ok(json!({})),
+                Err(e) => return Err(e).context("Failed to write to stdout"),
+            };
+            info!("Wrote {} to stdout", format_size(bytes));
+            json!({})
+        }
+
+        Commands::DownloadFolder {
+            prefix,
+            dest,
+            decrypt,
+            concurrency,
+            encryption,
+            paths,
+        } => {
+            info!("Downloading {} to {}", prefix, dest.display());
+            let only_keys = if encryption.filter() == EncryptionFilter::All {
+                None
+            } else {
+                let objects = r2_client.list_objects_detailed(Some(&prefix)).await?;
+                let selected = encryption_filter::filter_objects(
+                    &r2_client,
+                    objects,
+                    encryption.filter(),
+                    encryption.verify_encryption,
+                )
+                .await?;
+                Some(selected.into_iter().map(|o| o.key).collect())
+            };
+            let options = folder_download::FolderDownloadOptions {
+                concurrency,
+                decrypt,
+                only_keys,
+                paths: paths.filter()?,
+            };
+            let pgp_handler = Arc::new(pgp_handler);
+            let progress = batch_progress();
+
+            let summary = with_deadline(
+                &r2_client,
+                cli.deadline,
+                || progress.completed_items(),
+                folder_download::download_prefix(
+                    &r2_client,
+                    Some(pgp_handler),
+                    &prefix,
+                    &dest,
+                    &options,
+                    &progress,
+                ),
+            )
+            .await?;
+
+            for (key, error) in &summary.failed {
+                tracing::warn!("Failed to download {}: {}", key, error);
+            }
+            say!(
+                "Folder download complete: {} downloaded, {} already complete, {} failed",
+                summary.downloaded.len(),
+                summary.skipped.len(),
+                summary.failed.len()
+            );
+
+            if !summary.failed.is_empty() {
+                return Err(anyhow::anyhow!(
+                    "{} files failed to download; re-run to resume",
+                    summary.failed.len()
+                ));
+            }
+            let downloaded: Vec<&String> = summary.downloaded.iter().map(|(key, _)| key).collect();
+            json!({
+                "prefix": prefix,
+                "dest": dest.display().to_string(),
+                "downloaded": downloaded,
+                "skipped": summary.skipped,
+            })
         }
 
         Commands::Upload {
             file,
-            mut key,
+            key,
+            key_template: _,
             encrypt,
+            passphrase_encrypt,
+            armor: _,
+            no_armor: _,
+            compress: _,
+            sign,
+            private_name,
+            record_plaintext_hash,
+            content_encoding,
+            cache_control,
+            expires,
+            metadata,
+            backup_on_overwrite,
+            keep_backups,
+            paths,
         } => {
+            // resolve_upload_key has already settled the key
+            let mut key = key.unwrap_or_else(|| transfer::upload_file_name(&file));
+            if let Some(cache_control) = &cache_control {
+                r2_client::validate_cache_control(cache_control)?;
+            }
+            if let Some(encoding) = &content_encoding {
+                r2_client::validate_content_encoding(encoding)?;
+                // The stored body would be ciphertext, not the encoded file
+                if encrypt || passphrase_encrypt {
+                    return Err(anyhow::anyhow!(
+                        "--content-encoding cannot be combined with --encrypt"
+                    ));
+                }
+            }
+            let base_options = r2_client::UploadOptions {
+                metadata: metadata.into_iter().collect(),
+                content_encoding,
+                cache_control,
+                expires,
+                ..Default::default()
+            };
+
+            if passphrase_encrypt {
+                if file.is_dir() {
+                    return Err(anyhow::anyhow!(
+                        "--passphrase-encrypt is only supported when uploading a single file"
+                    ));
+                }
+                return upload_with_passphrase(
+                    &r2_client,
+                    &pgp_handler,
+                    &file,
+                    key,
+                    base_options,
+                    backup_on_overwrite.then_some(keep_backups),
+                )
+                .await;
+            }
+
+            let paths = paths.filter()?;
+            if !paths.is_empty() && !file.is_dir() {
+                return Err(anyhow::anyhow!(
+                    "--include and --exclude only apply when uploading a folder"
+                ));
+            }
+
+            if file.is_dir() {
+                if private_name {
+                    return Err(anyhow::anyhow!(
+                        "--private-name is only supported when uploading a single file"
+                    ));
+                }
+                if sign {
+                    return Err(anyhow::anyhow!(
+                        "--sign is only supported when uploading a single file"
+                    ));
+                }
+                if backup_on_overwrite {
+                    return Err(anyhow::anyhow!(
+                        "--backup-on-overwrite is only supported when uploading a single file"
+                    ));
+                }
+                let progress = Arc::new(batch_progress());
+                let summary = with_deadline(
+                    &r2_client,
+                    cli.deadline,
+                    || progress.completed_items(),
+                    upload_folder(
+                        &r2_client,
+                        &pgp_handler,
+                        &file,
+                        &key,
+                        &folder_upload::FolderUploadOptions {
+                            encrypt,
+                            paths,
+                            upload_options: base_options,
+                            hook: config.hooks.on_complete.clone(),
+                            part_concurrency: cli.part_concurrency,
+                            ..Default::default()
+                        },
+                        progress.clone(),
+                    ),
+                )
+                .await?;
+                return Ok(json!({
+                    "key": key,
+                    "uploaded": summary.uploaded,
+                    "bytes": summary.bytes,
+                    "encrypted": encrypt,
+                }));
+            }
+
             info!("Uploading file: {} to {}", file.display(), key);
+            let file_name = transfer::upload_file_name(&file);
+
+            // With --private-name the key asked for only survives inside the sidecar
+            let private = private_name.then(|| sidecar::PrivateMetadata {
+                key: encryption_filter::with_encrypted_extension(&key),
+                file_name: file_name.clone(),
+                content_type: base_options
+                    .content_type
+                    .clone()
+                    .or_else(|| r2_client::guess_content_type(&file_name)),
+                metadata: base_options.metadata.clone(),
+            });
+            if private.is_some() {
+                key = sidecar::opaque_key(&key);
+                info!("Storing under private name {}", key);
+            }
+            let object_options = |options: r2_client::UploadOptions| match &private {
+                Some(_) => sidecar::private_upload_options(&options),
+                None => options
+                    .with_original_filename(&file_name)
+                    .with_encrypted(encrypt)
+                    .with_content_type_for(&file_name, encrypt),
+            };
+
+            // Large files go up in parts, so an interrupted upload can resume
+            let file_size = fs::metadata(&file)
+                .context("Failed to read input file")?
+                .len();
+            if file_size >= multipart_upload::MULTIPART_THRESHOLD {
+                // Large files are encrypted in chunks, which carry no signature
+                if sign {
+                    return Err(anyhow::anyhow!(
+                        "--sign is only supported for files under 100 MB"
+                    ));
+                }
+                if encrypt && pgp_handler.recipient_count() == 0 {
+                    return Err(anyhow::anyhow!(
+                        "No public keys loaded for encryption. Please configure team keys."
+                    ));
+                }
+                let backup = if backup_on_overwrite {
+                    backup::backup_before_overwrite(&r2_client, &key, keep_backups).await?
+                } else {
+                    None
+                };
+                // Read the file once more to record what decryption should give back
+                let options = if record_plaintext_hash {
+                    info!("Hashing {} before encrypting it", file.display());
+                    base_options.with_plaintext_sha256(&integrity::sha256_file(&file)?)
+                } else {
+                    base_options
+                };
+                let options = object_options(options);
+                let progress = ConsoleProgress::new(file_size);
+                progress.set_message(&file_name);
+                let stored = MultipartUploader::upload_file(
+                    &r2_client,
+                    &file,
+                    &key,
+                    &options,
+                    multipart_upload::DEFAULT_PART_SIZE,
+                    cli.part_concurrency,
+                    encrypt.then_some(&pgp_handler),
+                    &progress,
+                )
+                .await?;
+                progress.finish("done");
+                if let Some(private) = &private {
+                    sidecar::upload_sidecar(&r2_client, &pgp_handler, &key, private).await?;
+                }
+                info!("Successfully uploaded to: {}", key);
+                return Ok(json!({
+                    "key": key,
+                    "etag": null,
+                    "bytes": stored,
+                    "encrypted": encrypt,
+                    "signed": sign,
+                    "backup": backup,
+                    "private_name": private.as_ref().map(|p| &p.key),
+                }));
+            }
+
             let data = fs::read(&file).context("Failed to read input file")?;
+            let plaintext_sha256 = record_plaintext_hash.then(|| integrity::sha256_hex(&data));
 
             let final_data = if encrypt {
-                if pgp_handler.public_key_count() == 0 {
+                if pgp_handler.recipient_count() == 0 {
                     return Err(anyhow::anyhow!(
                         "No public keys loaded for encryption. Please configure team keys."
                     ));
                 }
                 info!(
                     "Encrypting file data for {} recipients",
-                    pgp_handler.public_key_count()
+                    pgp_handler.recipient_count()
                 );
-                let encrypted = pgp_handler.encrypt(&data)?;
-
-                // Add .pgp extension if not already present
-                if !key.ends_with(".pgp") {
-                    key = format!("{}.pgp", key);
-                    info!("Added .pgp extension to object key: {}", key);
-                }
-
+                let encrypted = if sign {
+                    pgp_handler.encrypt_and_sign(&data, &file_name)?
+                } else {
+                    pgp_handler.encrypt_with_filename(&data, &file_name)?
+                };
                 Bytes::from(encrypted)
             } else {
                 Bytes::from(data)
             };
 
-            r2_client.upload_object(&key, final_data).await?;
+            let backup = if backup_on_overwrite {
+                backup::backup_before_overwrite(&r2_client, &key, keep_backups).await?
+            } else {
+                None
+            };
+            let options = match &plaintext_sha256 {
+                Some(sha256) => object_options(base_options.with_plaintext_sha256(sha256)),
+                None => object_options(base_options),
+            };
+            let bytes = final_data.len();
+            let etag = r2_client
+                .upload_object_with_options(&key, final_data, &options)
+                .await?;
+            if let Some(private) = &private {
+                sidecar::upload_sidecar(&r2_client, &pgp_handler, &key, private).await?;
+            }
             info!("Successfully uploaded to: {}", key);
+            json!({
+                "key": key,
+                "etag": etag,
+                "bytes": bytes,
+                "encrypted": encrypt,
+                "signed": sign,
+                "backup": backup,
+                "private_name": private.as_ref().map(|p| &p.key),
+            })
+        }
+
+        Commands::Sync {
+            dir,
+            prefix,
+            encrypt,
+            paths,
+        } => {
+            if !dir.is_dir() {
+                return Err(anyhow::anyhow!("{} is not a folder", dir.display()));
+            }
+            let progress = Arc::new(batch_progress());
+            let summary = with_deadline(
+                &r2_client,
+                cli.deadline,
+                || progress.completed_items(),
+                upload_folder(
+                    &r2_client,
+                    &pgp_handler,
+                    &dir,
+                    &prefix,
+                    &folder_upload::FolderUploadOptions {
+                        encrypt,
+                        skip_unchanged: true,
+                        dry_run: cli.dry_run,
+                        paths: paths.filter()?,
+                        upload_options: r2_client::UploadOptions::default(),
+                        hook: config.hooks.on_complete.clone(),
+                        part_concurrency: cli.part_concurrency,
+                    },
+                    progress.clone(),
+                ),
+            )
+            .await?;
+            json!({
+                "prefix": prefix,
+                "uploaded": summary.uploaded,
+                "skipped": summary.skipped,
+                "bytes": summary.bytes,
+                "encrypted": encrypt,
+                "dry_run": cli.dry_run,
+            })
+        }
+
+        Commands::UploadTar { dir, key, encrypt } => {
+            if encrypt && pgp_handler.recipient_count() == 0 {
+                return Err(anyhow::anyhow!(
+                    "No public keys loaded for encryption. Please configure team keys."
+                ));
+            }
+            info!("Archiving {} to {}", dir.display(), key);
+
+            let options = r2_client::UploadOptions::default()
+                .with_encrypted(encrypt)
+                .with_content_type_for(&key, encrypt);
+            let progress = ConsoleProgress::new(0);
+            let upload = tar_stream::upload_tar(
+                &r2_client,
+                &dir,
+                &key,
+                &options,
+                multipart_upload::DEFAULT_PART_SIZE,
+                cli.part_concurrency,
+                encrypt.then_some(&pgp_handler),
+                &progress,
+            )
+            .await?;
+            progress.finish("done");
+            info!(
+                "Uploaded {} files ({}) to: {}",
+                upload.files,
+                format_size(upload.tar_bytes),
+                key
+            );
+            json!({
+                "key": key,
+                "files": upload.files,
+                "tar_bytes": upload.tar_bytes,
+                "bytes": upload.stored_bytes,
+                "encrypted": encrypt,
+            })
+        }
+
+        Commands::UploadUrl { url, key, encrypt } => {
+            upload_url(&r2_client, &pgp_handler, &url, key, encrypt, cli.part_concurrency).await?
         }
 
-        Commands::List { prefix } => {
+        Commands::List {
+            prefix,
+            relative,
+            all_profiles,
+            format,
+            encryption,
+        } => {
+            if all_profiles {
+                return list_all_profiles(&config, prefix, relative, &encryption).await;
+            }
+            if format == OutputFormat::Json && JSON_OUTPUT.get().is_some() {
+                return Err(anyhow::anyhow!(
+                    "--format json can't be combined with --output json, whose result already lists the objects"
+                ));
+            }
             info!("Listing objects with prefix: {:?}", prefix);
-            let objects = r2_client.list_objects(prefix.as_deref()).await?;
+            let objects = r2_client.list_objects_detailed(prefix.as_deref()).await?;
+            let objects = encryption_filter::filter_objects(
+                &r2_client,
+                objects,
+                encryption.filter(),
+                encryption.verify_encryption,
+            )
+            .await?;
+
+            let keys: Vec<&str> = objects
+                .iter()
+                .map(|obj| match prefix.as_deref() {
+                    Some(p) if relative => transfer::relative_key(&obj.key, p),
+                    _ => &obj.key,
+                })
+                .collect();
+            // "key" is always the full key, so it can be passed back to other commands
+            let records: Vec<Value> = objects
+                .iter()
+                .zip(&keys)
+                .map(|(obj, key)| {
+                    let mut record = json!({
+                        "key": obj.key,
+                        "size": obj.size,
+                        "last_modified": obj.last_modified,
+                    });
+                    if relative && prefix.is_some() {
+                        record["relative_key"] = json!(key);
+                    }
+                    record
+                })
+                .collect();
+            if format == OutputFormat::Json {
+                println!("{}", serde_json::to_string(&records)?);
+            } else if keys.is_empty() {
+                say!("No objects found");
+            } else {
+                say!("Objects in bucket:");
+                for key in &keys {
+                    say!("  {}", key);
+                }
+            }
+            json!({ "prefix": prefix, "keys": keys, "objects": records })
+        }
+
+        Commands::SetCredentials { .. }
+        | Commands::ShowConfig
+        | Commands::ImportRclone { .. } => {
+            unreachable!("runs before the client is set up")
+        }
+
+        Commands::Ping => {
+            let ping = r2_client.ping().await?;
+            say!("Endpoint:  {}", ping.endpoint);
+            if !ping.addresses.is_empty() {
+                let addresses: Vec<String> = ping.addresses.iter().map(|a| a.to_string()).collect();
+                say!("Resolved:  {}", addresses.join(", "));
+            }
+            if let Some(latency) = ping.latency {
+                say!("Latency:   {} ms", latency.as_millis());
+            }
+            say!("Bucket:    {}", config.r2.bucket_name);
+            say!("Status:    {}", ping.diagnosis);
+
+            if !ping.is_ok() {
+                return Err(anyhow::anyhow!("Ping failed: {}", ping.diagnosis));
+            }
+
+            let permissions = r2_client.probe_permissions("").await?;
+            let summary: Vec<String> = permissions
+                .iter()
+                .map(|(permission, allowed)| {
+                    let mark = match allowed {
+                        Some(true) => "✓",
+                        Some(false) => "✗",
+                        None => "?",
+                    };
+                    format!("{} {}", permission, mark)
+                })
+                .collect();
+            say!("Access:    {}", summary.join(", "));
 
-            if objects.is_empty() {
-                println!("No objects found");
+            let permissions: serde_json::Map<String, Value> = permissions
+                .into_iter()
+                .map(|(permission, allowed)| (permission.to_string(), json!(allowed)))
+                .collect();
+            json!({
+                "endpoint": ping.endpoint,
+                "addresses": ping.addresses.iter().map(|a| a.to_string()).collect::<Vec<_>>(),
+                "latency_ms": ping.latency.map(|l| l.as_millis() as u64),
+                "bucket": config.r2.bucket_name,
+                "status": ping.diagnosis.to_string(),
+                "permissions": permissions,
+            })
+        }
+
+        Commands::Diff {
+            left,
+            right,
+            decrypt,
+        } => {
+            let left = diff::DiffSide::Key(left);
+            let right = if Path::new(&right).is_file() {
+                diff::DiffSide::Local(PathBuf::from(right))
             } else {
-                println!("Objects in bucket:");
-                for obj in objects {
-                    println!("  {}", obj);
+                diff::DiffSide::Key(right)
+            };
+
+            let pgp_handler = decrypt.then_some(&pgp_handler);
+            match diff::compare(&r2_client, pgp_handler, &left, &right).await? {
+                diff::DiffOutcome::Identical { bytes, sha256 } => {
+                    say!("Identical: {} bytes, sha256 {}", bytes, sha256);
+                    json!({
+                        "left": left.to_string(),
+                        "right": right.to_string(),
+                        "identical": true,
+                        "bytes": bytes,
+                        "sha256": sha256,
+                    })
+                }
+                diff::DiffOutcome::SizesDiffer {
+                    left_size,
+                    right_size,
+                } => {
+                    say!(
+                        "Sizes differ: {} is {} bytes, {} is {} bytes",
+                        left,
+                        left_size,
+                        right,
+                        right_size
+                    );
+                    return Err(anyhow::anyhow!("{} and {} differ", left, right));
+                }
+                diff::DiffOutcome::DifferAt {
+                    offset,
+                    left_size,
+                    right_size,
+                } => {
+                    say!(
+                        "First difference at byte {}: {} is {} bytes, {} is {} bytes",
+                        offset,
+                        left,
+                        left_size,
+                        right,
+                        right_size
+                    );
+                    return Err(anyhow::anyhow!("{} and {} differ", left, right));
                 }
             }
         }
 
-        Commands::Delete { key } => {
+        Commands::Delete {
+            key,
+            recursive: true,
+            encryption,
+        } => delete_prefix(&r2_client, &key, &encryption, cli.dry_run, cli.yes).await?,
+
+        Commands::Delete {
+            key, encryption, ..
+        } => {
+            let filter = encryption.filter();
+            if filter != EncryptionFilter::All {
+                let encrypted = encryption_filter::is_object_encrypted(
+                    &r2_client,
+                    &key,
+                    encryption.verify_encryption,
+                )
+                .await?;
+                if !filter.matches(encrypted) {
+                    say!(
+                        "Skipped {}: it is {}",
+                        key,
+                        if encrypted {
+                            "encrypted"
+                        } else {
+                            "not encrypted"
+                        }
+                    );
+                    return Ok(json!({ "key": key, "deleted": false, "encrypted": encrypted }));
+                }
+            }
+
+            // An object stored with --private-name takes its metadata sidecar with it
+            let sidecar_key = sidecar::sidecar_key(&key);
+            let has_sidecar = encryption_filter::is_encrypted_extension(&key)
+                && !sidecar::is_sidecar(&key)
+                && r2_client.object_exists(&sidecar_key).await?;
+
+            if cli.dry_run {
+                say!("Would delete: {}", key);
+                if has_sidecar {
+                    say!("Would delete: {}", sidecar_key);
+                }
+                return Ok(json!({
+                    "key": key,
+                    "deleted": false,
+                    "sidecar_deleted": false,
+                    "dry_run": true,
+                    "would_delete": std::iter::once(key.clone())
+                        .chain(has_sidecar.then_some(sidecar_key))
+                        .collect::<Vec<_>>(),
+                }));
+            }
+
             info!("Deleting object: {}", key);
             r2_client.delete_object(&key).await?;
             info!("Successfully deleted: {}", key);
+
+            if has_sidecar {
+                r2_client.delete_object(&sidecar_key).await?;
+                info!("Deleted its metadata sidecar: {}", sidecar_key);
+            }
+            json!({ "key": key, "deleted": true, "sidecar_deleted": has_sidecar, "dry_run": false })
         }
 
-        Commands::Process {
+        Commands::Presign { key, expires, put } => {
+            let (method, url) = if put {
+                ("PUT", r2_client.presign_put_url(&key, expires)?)
+            } else {
+                ("GET", r2_client.presign_get_url(&key, expires)?)
+            };
+            let expires_at = chrono::Utc::now() + chrono::Duration::from_std(expires)?;
+            info!("{} URL for {} valid until {}", method, key, expires_at.to_rfc3339());
+            say!("{}", url);
+            json!({
+                "key": key,
+                "method": method,
+                "url": url,
+                "expires_at": expires_at.to_rfc3339(),
+            })
+        }
+
+        Commands::Copy {
             source_key,
-            mut dest_key,
-            temp_file,
+            dest_key,
         } => {
-            info!("Processing: {} -> {}", source_key, dest_key);
+            r2_client.copy_object(&source_key, &dest_key).await?;
+            say!("✓ Copied {} to {}", source_key, dest_key);
+            json!({
+                "source_key": source_key,
+                "dest_key": dest_key,
+            })
+        }
+
+        Commands::Restore { key } => {
+            info!("Restoring object: {}", key);
+            let version_id = r2_client.restore_object(&key).await?;
+            say!("Restored {} (removed delete marker {})", key, version_id);
+            json!({ "key": key, "version_id": version_id })
+        }
 
-            info!("Downloading from R2");
-            let downloaded_data = r2_client.download_object(&source_key).await?;
+        Commands::RestoreBackup { key } => {
+            let backup_key = backup::restore_latest_backup(&r2_client, &key).await?;
+            say!("Restored {} from {}", key, backup_key);
+            json!({ "key": key, "backup": backup_key })
+        }
+
+        Commands::Stat { key, json: _ } => {
+            let head = r2_client.head_object(&key).await?;
+            let size = head.content_length.unwrap_or(0);
+            let etag = head.etag.as_deref().map(|etag| etag.trim_matches('"').to_string());
+            let encrypted =
+                size > 0 && encryption_filter::probe_content(&r2_client, &key).await?;
+
+            say!("{}", key);
+            say!("  Size: {} ({} bytes)", format_size(size), size);
+            let fields = [
+                ("Content-Type", &head.content_type),
+                ("Content-Encoding", &head.content_encoding),
+                ("Cache-Control", &head.cache_control),
+                ("Expires", &head.expires),
+                ("Last-Modified", &head.last_modified),
+                ("ETag", &etag),
+            ];
+            for (name, value) in fields {
+                if let Some(value) = value {
+                    say!("  {}: {}", name, value);
+                }
+            }
+            say!("  PGP encrypted: {}", if encrypted { "yes" } else { "no" });
+            for (name, value) in &head.metadata {
+                say!("  x-amz-meta-{}: {}", name, value);
+            }
 
-            // Check if source is encrypted
-            let is_encrypted = source_key.ends_with(".pgp")
-                || crypto::PgpHandler::is_pgp_encrypted(&downloaded_data);
+            json!({
+                "key": key,
+                "size": size,
+                "content_type": head.content_type,
+                "content_encoding": head.content_encoding,
+                "cache_control": head.cache_control,
+                "expires": head.expires,
+                "last_modified": head.last_modified,
+                "etag": etag,
+                "encrypted": encrypted,
+                "metadata": head.metadata,
+            })
+        }
 
-            let decrypted_data = if is_encrypted {
-                info!("Decrypting source file");
-                pgp_handler.decrypt(&downloaded_data)?
+        Commands::Inspect { target, local } => {
+            // The session key packets all come before the encrypted data
+            let header = if local {
+                let mut header = Vec::new();
+                fs::File::open(&target)
+                    .with_context(|| format!("Failed to open {}", target))?
+                    .take(INSPECT_HEADER_BYTES)
+                    .read_to_end(&mut header)?;
+                header
             } else {
-                info!("Source file is not encrypted");
-                downloaded_data.to_vec()
+                r2_client
+                    .download_range(&target, 0, INSPECT_HEADER_BYTES - 1)
+                    .await?
+                    .to_vec()
             };
+            inspect_encryption(&pgp_handler, &target, &header)?
+        }
 
-            if let Some(temp_path) = &temp_file {
-                info!(
-                    "Saving decrypted data to temporary file: {}",
-                    temp_path.display()
-                );
-                fs::write(temp_path, &decrypted_data).context("Failed to write temporary file")?;
+        Commands::VerifyEncrypted { key } => {
+            info!("Verifying chunk checksums of {}", key);
+            match chunked::verify_chunked_object(&r2_client, &key).await? {
+                chunked::ChunkedVerification::Valid {
+                    chunk_count,
+                    total_size,
+                } => {
+                    say!(
+                        "✓ {}: {} chunks ({} of plaintext), all checksums match",
+                        key,
+                        chunk_count,
+                        format_size(total_size)
+                    );
+                    json!({ "key": key, "chunks": chunk_count, "size": total_size })
+                }
+                chunked::ChunkedVerification::Corrupt { index, offset } => {
+                    return Err(anyhow::anyhow!(
+                        "{}: chunk {} (at byte {}) failed its checksum",
+                        key,
+                        index,
+                        offset
+                    ));
+                }
+            }
+        }
+
+        Commands::Tail {
+            prefix,
+            download_dir,
+            decrypt,
+            interval,
+            from_start,
+        } => {
+            let pgp_handler = Arc::new(pgp_handler);
+            let options = folder_download::FolderDownloadOptions {
+                decrypt,
+                ..Default::default()
+            };
+            let reported = tail_prefix(
+                &r2_client,
+                &pgp_handler,
+                &prefix,
+                download_dir.as_deref(),
+                &options,
+                interval,
+                from_start,
+            )
+            .await?;
+            json!({ "prefix": prefix, "new_objects": reported })
+        }
 
-                println!("Decrypted file saved to: {}", temp_path.display());
-                println!("You can now modify the file. Press Enter when ready to re-encrypt and upload...");
+        Commands::ResumeAll { concurrency } => {
+            let progress = batch_progress();
+            let results = resume::resume_all(&r2_client, concurrency, cli.part_concurrency, &progress).await?;
+            progress.finish("done");
 
-                let mut input = String::new();
-                std::io::stdin().read_line(&mut input)?;
+            let count = |status| results.iter().filter(|r| r.status == status).count();
+            for result in &results {
+                let kind = match result.kind {
+                    resume::TransferKind::Upload => "upload",
+                    resume::TransferKind::Download => "download",
+                };
+                let status = match result.status {
+                    resume::ResumeStatus::Completed => "completed",
+                    resume::ResumeStatus::Stale => "discarded (stale)",
+                    resume::ResumeStatus::Failed => "failed",
+                };
+                match &result.detail {
+                    Some(detail) => say!("{} {}: {} - {}", kind, result.key, status, detail),
+                    None => say!("{} {}: {}", kind, result.key, status),
+                }
+            }
+            say!(
+                "Resumed {} transfers: {} completed, {} discarded as stale, {} failed",
+                results.len(),
+                count(resume::ResumeStatus::Completed),
+                count(resume::ResumeStatus::Stale),
+                count(resume::ResumeStatus::Failed)
+            );
 
-                let modified_data = fs::read(temp_path).context("Failed to read modified file")?;
+            let failed = count(resume::ResumeStatus::Failed);
+            if failed > 0 {
+                return Err(anyhow::anyhow!(
+                    "{} transfers failed to resume; re-run to try again",
+                    failed
+                ));
+            }
+            json!({ "transfers": results })
+        }
 
-                if pgp_handler.public_key_count() > 0 {
-                    info!(
-                        "Encrypting modified data for {} recipients",
-                        pgp_handler.public_key_count()
-                    );
-                    let encrypted_data = pgp_handler.encrypt(&modified_data)?;
+        Commands::Uploads {
+            prefix,
+            abort,
+            older_than,
+        } => {
+            let mut uploads = r2_client.list_multipart_uploads(prefix.as_deref()).await?;
+            if let Some(older_than) = older_than {
+                let cutoff = chrono::Utc::now() - chrono::Duration::from_std(older_than)?;
+                // Keep uploads whose start time can't be read out of an abort
+                uploads.retain(|upload| {
+                    upload
+                        .initiated
+                        .as_deref()
+                        .and_then(|initiated| chrono::DateTime::parse_from_rfc3339(initiated).ok())
+                        .is_some_and(|initiated| initiated < cutoff)
+                });
+            }
 
-                    // Add .pgp extension if not present
-                    if !dest_key.ends_with(".pgp") {
-                        dest_key = format!("{}.pgp", dest_key);
-                        info!("Added .pgp extension to destination key: {}", dest_key);
+            // Uploads with a saved session can still be finished with resume-all
+            let sessions: Vec<String> = multipart_upload::UploadSession::list_all(&r2_client)?
+                .into_iter()
+                .map(|(_, session)| session.upload_id)
+                .collect();
+
+            let mut results = Vec::new();
+            let mut failed = 0;
+            for upload in &uploads {
+                let resumable = sessions.contains(&upload.upload_id);
+                let initiated = upload.initiated.as_deref().unwrap_or("unknown");
+                let mut status = if resumable { "resumable" } else { "orphaned" };
+                let mut error = None;
+
+                if abort && cli.dry_run {
+                    status = "would_abort";
+                } else if abort {
+                    match r2_client
+                        .abort_multipart_upload(&upload.key, &upload.upload_id)
+                        .await
+                    {
+                        Ok(()) => {
+                            multipart_upload::UploadSession::remove_for_upload(&r2_client, &upload.upload_id)?;
+                            status = "aborted";
+                        }
+                        Err(e) => {
+                            failed += 1;
+                            status = "failed";
+                            error = Some(format!("{:#}", e));
+                        }
                     }
+                }
 
-                    info!("Uploading encrypted data to R2");
-                    r2_client
-                        .upload_object(&dest_key, Bytes::from(encrypted_data))
-                        .await?;
-                } else {
-                    info!("No encryption keys configured, uploading unencrypted");
-                    r2_client
-                        .upload_object(&dest_key, Bytes::from(modified_data))
-                        .await?;
+                match &error {
+                    Some(e) => say!("{}  {}  {}: {} - {}", initiated, upload.key, upload.upload_id, status, e),
+                    None => say!("{}  {}  {}: {}", initiated, upload.key, upload.upload_id, status),
                 }
+                results.push(json!({
+                    "key": upload.key,
+                    "upload_id": upload.upload_id,
+                    "initiated": upload.initiated,
+                    "status": status,
+                    "error": error,
+                }));
+            }
+
+            if abort && cli.dry_run {
+                say!("Dry run: would abort {} multipart uploads", uploads.len());
+            } else if abort {
+                say!("Aborted {} of {} multipart uploads", uploads.len() - failed, uploads.len());
             } else {
-                if pgp_handler.public_key_count() > 0 {
-                    info!(
-                        "Re-encrypting data for {} recipients",
-                        pgp_handler.public_key_count()
-                    );
-                    let encrypted_data = pgp_handler.encrypt(&decrypted_data)?;
+                say!("{} multipart uploads in progress", uploads.len());
+            }
+            if failed > 0 {
+                return Err(anyhow::anyhow!(
+                    "{} multipart uploads could not be aborted",
+                    failed
+                ));
+            }
+            json!({ "uploads": results, "dry_run": cli.dry_run })
+        }
 
-                    // Add .pgp extension if not present
-                    if !dest_key.ends_with(".pgp") {
-                        dest_key = format!("{}.pgp", dest_key);
-                        info!("Added .pgp extension to destination key: {}", dest_key);
-                    }
+        Commands::Concat {
+            prefix,
+            output,
+            decrypt,
+        } => {
+            let joined = concat::concat_prefix(&r2_client, &pgp_handler, &prefix, &output, decrypt).await?;
+            say!(
+                "Joined {} objects into {} ({})",
+                joined.keys.len(),
+                output.display(),
+                format_size(joined.bytes)
+            );
+            json!({
+                "prefix": prefix,
+                "path": output.display().to_string(),
+                "objects": joined.keys,
+                "bytes": joined.bytes,
+                "missing_parts": joined.missing_parts,
+            })
+        }
 
-                    info!("Uploading encrypted data to R2");
-                    r2_client
-                        .upload_object(&dest_key, Bytes::from(encrypted_data))
-                        .await?;
+        Commands::Sample {
+            prefix,
+            bytes,
+            output,
+            raw,
+            concurrency,
+        } => {
+            let objects = r2_client.list_objects_detailed(Some(&prefix)).await?;
+            info!("Sampling {} objects under {}", objects.len(), prefix);
+
+            let samples: Vec<sample::ObjectSample> = stream::iter(objects)
+                .map(|object| sample::sample_object(&r2_client, &pgp_handler, object, bytes, raw))
+                .buffered(concurrency.max(1))
+                .collect()
+                .await;
+
+            let mut results = Vec::new();
+            for sample in &samples {
+                let mut result = json!({
+                    "key": sample.key,
+                    "size": sample.size,
+                    "bytes": sample.data.len(),
+                    "short": sample.short,
+                    "decrypted": sample.decrypted,
+                    "note": sample.note,
+                });
+                let mut status = Vec::new();
+                if sample.decrypted {
+                    status.push("decrypted".to_string());
+                }
+                if sample.short {
+                    status.push(format!("whole object, smaller than {} bytes", bytes));
+                }
+                if let Some(note) = &sample.note {
+                    status.push(note.clone());
+                }
+                let status = if status.is_empty() {
+                    String::new()
                 } else {
-                    info!("No encryption keys configured, uploading unencrypted");
-                    r2_client
-                        .upload_object(&dest_key, Bytes::from(decrypted_data))
-                        .await?;
+                    format!(" [{}]", status.join("; "))
+                };
+
+                match &output {
+                    Some(dir) => {
+                        if !sample.data.is_empty() {
+                            let relative = transfer::relative_key(&sample.key, &prefix);
+                            let path = dir.join(folder_download::safe_relative_path(relative)?);
+                            if let Some(parent) = path.parent() {
+                                fs::create_dir_all(parent)?;
+                            }
+                            fs::write(&path, &sample.data)
+                                .with_context(|| format!("Failed to write {}", path.display()))?;
+                            result["path"] = json!(path.display().to_string());
+                        }
+                        say!("{} ({} bytes){}", sample.key, sample.data.len(), status);
+                    }
+                    None => {
+                        say!("== {} ({} of {}){}", sample.key, sample.data.len(), format_size(sample.size), status);
+                        if !sample.data.is_empty() {
+                            say!("{}", sample::printable_sample(&sample.data));
+                        }
+                        if JSON_OUTPUT.get().is_some() {
+                            use base64::Engine;
+                            result["data"] = json!(base64::engine::general_purpose::STANDARD
+                                .encode(&sample.data));
+                        }
+                    }
+                }
+                results.push(result);
+            }
+
+            json!({
+                "prefix": prefix,
+                "bytes": bytes,
+                "samples": results,
+            })
+        }
+
+        Commands::Process {
+            source_key,
+            dest_key,
+            temp_file,
+            content_type,
+        } => {
+            process_object(
+                &r2_client,
+                &pgp_handler,
+                &source_key,
+                dest_key,
+                temp_file.as_deref(),
+                content_type,
+            )
+            .await?
+        }
+
+        Commands::Reencrypt {
+            prefix,
+            to_group,
+            dest_prefix,
+            concurrency,
+            deep,
+        } => {
+            let dry_run = cli.dry_run;
+            let group_paths = config
+                .pgp
+                .recipient_groups
+                .get(&to_group)
+                .ok_or_else(|| anyhow::anyhow!("Unknown recipient group: {}", to_group))?;
+
+            // Resolve the group's key files to fingerprints, loading any keys we don't have yet
+            let mut recipients = Vec::new();
+            for key_path in group_paths {
+                let key_data = fs::read(key_path)
+                    .with_context(|| format!("Failed to read key file {}", key_path))?;
+                for key_info in crypto::PgpHandler::get_all_keys_from_bytes(&key_data)? {
+                    info!("  - {} <{}>", key_info.name, key_info.email);
+                    recipients.push(key_info.fingerprint);
+                }
+                // Keys that are already loaded are skipped as duplicates
+                let _ = pgp_handler.load_public_keys_from_bytes(&key_data);
+            }
+            info!(
+                "Re-encrypting to group '{}' ({} recipients)",
+                to_group,
+                recipients.len()
+            );
+            if !dry_run {
+                confirm_recipients(&pgp_handler.planned_recipients(Some(&recipients)), cli.yes)?;
+            }
+
+            let objects = r2_client.list_objects(Some(&prefix)).await?;
+            let (candidates, plaintext): (Vec<String>, Vec<String>) = objects
+                .into_iter()
+                .partition(|key| encryption_filter::is_encrypted_extension(key));
+
+            let targets: Vec<(String, String)> = candidates
+                .into_iter()
+                .map(|key| {
+                    let dest_key = match &dest_prefix {
+                        Some(dest) => {
+                            format!("{}{}", dest, key.strip_prefix(&prefix).unwrap_or(&key))
+                        }
+                        None => key.clone(),
+                    };
+                    (key, dest_key)
+                })
+                .collect();
+
+            if dry_run {
+                // Find objects encrypted to keys we no longer hold before anything is rewritten
+                let checks: Vec<((String, String), Result<()>)> = stream::iter(targets)
+                    .map(|target| {
+                        let (r2_client, pgp_handler) = (&r2_client, &pgp_handler);
+                        async move {
+                            let check =
+                                check_decryptable(r2_client, pgp_handler, &target.0, deep).await;
+                            (target, check)
+                        }
+                    })
+                    .buffered(concurrency.max(1))
+                    .collect()
+                    .await;
+
+                for ((key, dest_key), check) in &checks {
+                    match check {
+                        Ok(()) => say!("Would re-encrypt: {} -> {}", key, dest_key),
+                        Err(e) => say!("Cannot decrypt: {} ({:#})", key, e),
+                    }
+                }
+                let undecryptable = checks.iter().filter(|(_, check)| check.is_err()).count();
+                say!(
+                    "Dry run: {} objects would be re-encrypted, {} cannot be decrypted, {} skipped (not encrypted)",
+                    checks.len() - undecryptable,
+                    undecryptable,
+                    plaintext.len()
+                );
+                if undecryptable > 0 {
+                    return Err(anyhow::anyhow!(
+                        "{} objects cannot be decrypted with the loaded secret key",
+                        undecryptable
+                    ));
                 }
+                let targets: Vec<Value> = checks
+                    .iter()
+                    .map(|((key, dest_key), _)| json!({ "key": key, "dest_key": dest_key }))
+                    .collect();
+                return Ok(json!({
+                    "prefix": prefix,
+                    "dry_run": true,
+                    "targets": targets,
+                    "skipped": plaintext.len(),
+                }));
             }
 
-            info!("Successfully processed: {} -> {}", source_key, dest_key);
+            let total = targets.len();
+            let done = AtomicUsize::new(0);
+            let completed = Mutex::new(Vec::new());
+            let pgp_handler = &pgp_handler;
+            let r2_client = &r2_client;
+            let recipients = &recipients;
+
+            let reencrypt_all = stream::iter(targets)
+                .map(|(key, dest_key)| {
+                    let done = &done;
+                    let completed = &completed;
+                    async move {
+                        let result =
+                            reencrypt_object(r2_client, pgp_handler, &key, &dest_key, recipients)
+                                .await;
+                        let n = done.fetch_add(1, Ordering::SeqCst) + 1;
+                        match &result {
+                            Ok(true) => {
+                                info!("[{}/{}] Re-encrypted {} -> {}", n, total, key, dest_key);
+                                completed.lock().unwrap().push(key.clone());
+                            }
+                            Ok(false) => info!("[{}/{}] Skipped {} (not encrypted)", n, total, key),
+                            Err(e) => tracing::warn!("[{}/{}] Failed {}: {}", n, total, key, e),
+                        }
+                        (key, result)
+                    }
+                })
+                .buffer_unordered(concurrency.max(1))
+                .collect::<Vec<(String, Result<bool>)>>();
+            let results =
+                with_deadline(r2_client, cli.deadline, || completed.lock().unwrap().clone(), async {
+                    Ok(reencrypt_all.await)
+                })
+                .await?;
+
+            let reencrypted = results
+                .iter()
+                .filter(|(_, r)| matches!(r, Ok(true)))
+                .count();
+            let skipped = plaintext.len()
+                + results
+                    .iter()
+                    .filter(|(_, r)| matches!(r, Ok(false)))
+                    .count();
+            let failed: Vec<&String> = results
+                .iter()
+                .filter(|(_, r)| r.is_err())
+                .map(|(key, _)| key)
+                .collect();
+
+            say!(
+                "Re-encryption complete: {} re-encrypted, {} skipped, {} failed",
+                reencrypted,
+                skipped,
+                failed.len()
+            );
+            for key in &failed {
+                say!("  failed: {}", key);
+            }
+
+            if !failed.is_empty() {
+                return Err(anyhow::anyhow!(
+                    "{} objects failed to re-encrypt",
+                    failed.len()
+                ));
+            }
+            json!({
+                "prefix": prefix,
+                "dry_run": false,
+                "reencrypted": reencrypted,
+                "skipped": skipped,
+            })
         }
+    })
     }
+    .await;
 
-    Ok(())
+    if let (Some(hook), Some(target)) = (&completion_hook, &hook_target) {
+        let event = match &result {
+            Ok(fields) => hooks::CompletionEvent::succeeded(
+                fields["key"].as_str().unwrap_or(target),
+                fields["bytes"].as_u64().unwrap_or(0),
+                fields["etag"].as_str().map(str::to_string),
+            ),
+            Err(e) => hooks::CompletionEvent::failed(target, &format!("{:#}", e)),
+        };
+        hooks::on_complete(hook, &event).await;
+    }
+
+    // Printed whether or not the command succeeded, since failed runs are billed too
+    if !show_stats {
+        return result;
+    }
+    let stats = r2_client.metrics().snapshot();
+    say!("{}", stats.summary());
+    let mut fields = result?;
+    if let Value::Object(fields) = &mut fields {
+        fields.insert("stats".to_string(), json!(stats));
+    }
+    Ok(fields)
+}
+
+/// Trust-on-first-use check of the loaded public keys against the trust store. A new
+/// fingerprint must be confirmed (or accepted with --trust-new-keys), and a changed
+/// fingerprint for a known email always needs an interactive yes. Keys that aren't
+/// trusted are dropped so they never become recipients.
+fn verify_key_trust(pgp_handler: &mut crypto::PgpHandler, trust_new_keys: bool) -> Result<()> {
+    let keys = pgp_handler.get_loaded_keys().to_vec();
+    if keys.is_empty() {
+        return Ok(());
+    }
+    let mut store = TrustStore::open_default()?;
+    let interactive = std::io::stdin().is_terminal();
+    let mut store_changed = false;
+
+    for key in keys {
+        // None means no decision was made, so nothing is recorded
+        let decision = match store.check(&key) {
+            KeyTrust::Trusted => continue,
+            KeyTrust::Rejected => {
+                tracing::warn!(
+                    "Not using {} <{}>: fingerprint {} was rejected",
+                    key.name,
+                    key.email,
+                    key.fingerprint
+                );
+                None
+            }
+            KeyTrust::New => {
+                eprintln!("New public key: {} <{}>", key.name, key.email);
+                eprintln!("  Fingerprint: {}", key.fingerprint);
+                if trust_new_keys {
+                    Some(true)
+                } else if interactive {
+                    Some(prompt_yes_no(
+                        "Check this fingerprint with the key's owner. Trust it?",
+                    )?)
+                } else {
+                    tracing::warn!(
+                        "Not using unverified key {} <{}>; run interactively or pass --trust-new-keys",
+                        key.name,
+                        key.email
+                    );
+                    None
+                }
+            }
+            KeyTrust::Changed { previous } => {
+                eprintln!();
+                eprintln!("WARNING: THE PUBLIC KEY FOR <{}> HAS CHANGED", key.email);
+                eprintln!("  Trusted fingerprint: {}", previous.join(", "));
+                eprintln!("  New fingerprint:     {}", key.fingerprint);
+                eprintln!("  The key may have been replaced by someone else.");
+                if interactive {
+                    Some(prompt_yes_no(
+                        "Only continue if the owner confirmed the new key. Trust it?",
+                    )?)
+                } else {
+                    tracing::warn!(
+                        "Not using changed key for <{}>; confirm it interactively",
+                        key.email
+                    );
+                    None
+                }
+            }
+        };
+
+        if let Some(trusted) = decision {
+            store.record(&key, trusted);
+            store_changed = true;
+        }
+        if decision != Some(true) {
+            pgp_handler.remove_public_key(&key.fingerprint);
+        }
+    }
+
+    if store_changed {
+        store.save()?;
+        info!("Recorded key decisions in {}", store.path().display());
+    }
+    Ok(())
+}
+
+/// Print the configuration this run resolved to, with secrets redacted and the
+/// global options that override it applied, for sharing in bug reports.
+fn show_config(config: &config::Config, source: &CredentialSource, cli: &Cli) -> Value {
+    let mut effective = config.redacted();
+    if let Some(cipher) = &cli.cipher {
+        effective.pgp.cipher = Some(cipher.clone());
+    }
+    if let Some(dir) = &cli.keyring_dir {
+        effective.pgp.keyring_dir = Some(dir.display().to_string());
+    }
+    effective.pgp.encrypt_to_self |= cli.encrypt_to_self;
+
+    let source = match source {
+        CredentialSource::ConfigFile(path, None) => path.display().to_string(),
+        CredentialSource::ConfigFile(path, Some(profile)) => {
+            format!("{} (profile {})", path.display(), profile)
+        }
+        CredentialSource::Environment => "environment".to_string(),
+        CredentialSource::Static => "static".to_string(),
+    };
+    let effective = serde_json::to_value(&effective).unwrap_or_default();
+    if JSON_OUTPUT.get().is_none() {
+        println!("# Loaded from {}", source);
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&effective).unwrap_or_default()
+        );
+    }
+    json!({ "source": source, "config": effective })
+}
+
+/// Write a new config file at `path` from the rclone remote and bucket in `remote`
+/// (`name:bucket`), refusing to replace an existing file unless `force`.
+fn import_rclone(path: &Path, remote: &str, force: bool) -> Result<Value> {
+    let (name, bucket) = remote
+        .split_once(':')
+        .map(|(name, path)| (name, path.split('/').next().unwrap_or_default()))
+        .filter(|(_, bucket)| !bucket.is_empty())
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "Give the remote and bucket as REMOTE:BUCKET, e.g. r2:backups (rclone keeps the bucket out of its config)"
+            )
+        })?;
+    if path.exists() && !force {
+        return Err(anyhow::anyhow!(
+            "{} already exists, pass --force to replace it",
+            path.display()
+        ));
+    }
+
+    let config = config::Config::from_rclone(name, bucket)?;
+    config.save_to_file(path)?;
+    say!(
+        "✓ Wrote {} for bucket {} in account {} from rclone remote '{}'",
+        path.display(),
+        bucket,
+        config.r2.account_id,
+        name
+    );
+    say!("  Add your PGP keys under \"pgp\", then check it with: rust-r2-cli ping");
+    Ok(json!({
+        "config": path,
+        "remote": name,
+        "account_id": config.r2.account_id,
+        "bucket": bucket,
+    }))
+}
+
+/// Replace the R2 credentials of `profile` (or the top-level `r2` section) in the config
+/// file at `path`, keeping the previous file as `<path>.bak`. Nothing is saved unless
+/// the new credentials can list the bucket, or `force` is set. The access key ID and
+/// secret are prompted for when not given.
+async fn set_credentials(
+    path: &Path,
+    profile: Option<&str>,
+    access_key_id: Option<String>,
+    secret_access_key: Option<String>,
+    account_id: Option<String>,
+    bucket: Option<String>,
+    force: bool,
+) -> Result<Value> {
+    let mut config = config::Config::from_file(path)?;
+    let r2 = match profile {
+        Some(name) if name != "default" || config.profiles.contains_key(name) => config
+            .profiles
+            .get_mut(name)
+            .ok_or_else(|| anyhow::anyhow!("No profile named '{}' in {}", name, path.display()))?,
+        _ => &mut config.r2,
+    };
+
+    let access_key_id = match access_key_id {
+        Some(id) => id,
+        None => prompt_line("New access key ID: ")?,
+    };
+    let secret_access_key = match secret_access_key {
+        Some(secret) => secret,
+        None => prompt_secret("New secret access key: ")?,
+    };
+    if access_key_id.is_empty() || secret_access_key.is_empty() {
+        return Err(anyhow::anyhow!(
+            "The access key ID and secret access key can't be empty"
+        ));
+    }
+    r2.access_key_id = access_key_id;
+    r2.secret_access_key = secret_access_key;
+    if let Some(account_id) = account_id {
+        r2.account_id = account_id;
+    }
+    if let Some(bucket) = bucket {
+        r2.bucket_name = bucket;
+    }
+    let updated = r2.clone();
+
+    let client = r2_client::R2Client::new(
+        updated.access_key_id.clone(),
+        updated.secret_access_key.clone(),
+        updated.account_id.clone(),
+        updated.bucket_name.clone(),
+    )
+    .await?
+    .with_client_config(updated.client_config(&config.retry))?;
+    let validated = match client.verify_credentials().await {
+        Ok(()) => {
+            say!(
+                "✓ The new credentials can list bucket {}",
+                updated.bucket_name
+            );
+            true
+        }
+        Err(e) if force => {
+            tracing::warn!(
+                "The new credentials failed the check against R2, saving them anyway: {:#}",
+                e
+            );
+            false
+        }
+        Err(e) => return Err(e.context(format!(
+            "The new credentials failed the check against R2, so {} was left unchanged (--force saves them anyway)",
+            path.display()
+        ))),
+    };
+
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(".bak");
+    let backup = PathBuf::from(backup);
+    fs::copy(path, &backup).with_context(|| {
+        format!(
+            "Failed to back up {} to {}",
+            path.display(),
+            backup.display()
+        )
+    })?;
+    config.save_to_file(path)?;
+
+    let profile = profile.unwrap_or("default");
+    say!(
+        "✓ Updated the credentials of profile '{}' in {} (previous file kept as {})",
+        profile,
+        path.display(),
+        backup.display()
+    );
+    Ok(json!({
+        "config": path,
+        "profile": profile,
+        "account_id": updated.account_id,
+        "bucket": updated.bucket_name,
+        "validated": validated,
+        "backup": backup,
+    }))
+}
+
+/// Read a line from the terminal, or from stdin when it is piped.
+fn prompt_line(prompt: &str) -> Result<String> {
+    eprint!("{}", prompt);
+    std::io::stderr().flush()?;
+    let mut line = String::new();
+    std::io::stdin().read_line(&mut line)?;
+    Ok(line.trim().to_string())
+}
+
+/// Like `prompt_line`, but with terminal echo turned off so a secret doesn't show on
+/// screen. Secrets are never taken as arguments since those end up in shell history.
+fn prompt_secret(prompt: &str) -> Result<String> {
+    let interactive = std::io::stdin().is_terminal();
+    let echo_off = interactive.then(terminal::EchoGuard::disable).flatten();
+    if interactive && echo_off.is_none() {
+        tracing::warn!("Couldn't turn off terminal echo, the secret will be visible as you type");
+    }
+    let line = prompt_line(prompt);
+    if let Some(guard) = echo_off {
+        drop(guard);
+        eprintln!();
+    }
+    line
+}
+
+// Enough of a message to read the session key packets at its start
+const INSPECT_HEADER_BYTES: u64 = 64 * 1024;
+
+/// `inspect`: list the keys `header`, the start of encrypted `target`, is encrypted to,
+/// naming the owners of loaded keys and marking the ones the loaded secret key opens.
+fn inspect_encryption(
+    pgp_handler: &crypto::PgpHandler,
+    target: &str,
+    header: &[u8],
+) -> Result<Value> {
+    let envelope = pgp_handler
+        .inspect_message(header)
+        .with_context(|| format!("Failed to read the encryption header of {}", target))?;
+    let secret_ids = pgp_handler.secret_key_ids();
+
+    say!("{}", target);
+    let cipher = match (&envelope.cipher, &envelope.aead) {
+        (Some(cipher), Some(aead)) => format!("{} ({})", cipher, aead),
+        (Some(cipher), None) => cipher.clone(),
+        // Only the session key says, and only a recipient's secret key can read it
+        (None, _) => "unknown (needs a recipient's secret key)".to_string(),
+    };
+    say!("  Cipher: {}", cipher);
+    if envelope.passphrase {
+        say!("  Can be opened with a passphrase");
+    }
+    let mut recipients = Vec::new();
+    for recipient in &envelope.recipients {
+        let owner = pgp_handler
+            .key_for_id(&recipient.key_id)
+            .map(|info| info.label());
+        let ours = secret_ids.contains(&recipient.key_id);
+        say!(
+            "  {} {} {}{}",
+            recipient.key_id,
+            recipient.algorithm,
+            owner.as_deref().unwrap_or("(key not loaded)"),
+            if ours {
+                " - your secret key opens it"
+            } else {
+                ""
+            }
+        );
+        recipients.push(json!({
+            "key_id": recipient.key_id,
+            "algorithm": recipient.algorithm,
+            "owner": owner,
+            "ours": ours,
+        }));
+    }
+    Ok(json!({
+        "target": target,
+        "recipients": recipients,
+        "passphrase": envelope.passphrase,
+        "cipher": envelope.cipher,
+        "aead": envelope.aead,
+    }))
+}
+
+/// The environment variable `--passphrase-encrypt` takes its passphrase from
+const ENCRYPTION_PASSPHRASE_VAR: &str = "R2_ENCRYPTION_PASSPHRASE";
+
+/// The passphrase for symmetric encryption: `$R2_ENCRYPTION_PASSPHRASE`, or else asked
+/// for on the terminal, twice when `confirm` so a typo doesn't lock the file away.
+fn encryption_passphrase(confirm: bool) -> Result<String> {
+    if let Some(passphrase) = std::env::var(ENCRYPTION_PASSPHRASE_VAR)
+        .ok()
+        .filter(|v| !v.is_empty())
+    {
+        return Ok(passphrase);
+    }
+    if !std::io::stdin().is_terminal() {
+        return Err(anyhow::anyhow!(
+            "No passphrase: set {} or run on a terminal",
+            ENCRYPTION_PASSPHRASE_VAR
+        ));
+    }
+    let passphrase = prompt_secret("Encryption passphrase: ")?;
+    if confirm && prompt_secret("Repeat the passphrase: ")? != passphrase {
+        return Err(anyhow::anyhow!("The passphrases don't match"));
+    }
+    Ok(passphrase)
+}
+
+/// True if `error` came from writing to a pipe whose reader has gone away.
+fn is_broken_pipe(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        cause
+            .downcast_ref::<std::io::Error>()
+            .is_some_and(|e| e.kind() == std::io::ErrorKind::BrokenPipe)
+    })
+}
+
+/// Whether `data` is a message only a passphrase opens, with no public-key recipients.
+fn is_passphrase_only(pgp_handler: &crypto::PgpHandler, data: &[u8]) -> bool {
+    pgp_handler
+        .inspect_message(data)
+        .is_ok_and(|envelope| envelope.passphrase && envelope.recipients.is_empty())
+}
+
+/// Work out the key an upload stores its object under (the prefix, for a folder), and
+/// put it back in `command`: the key template expanded, and `.pgp` added when encrypting
+/// a single object. Done once before the transfer, so the on_complete hook reports the
+/// key that was actually tried whether or not it succeeds. Returns that key, or None
+/// for folder uploads, which tell the hook themselves, and for other commands.
+fn resolve_upload_key(command: &mut Commands, config: &config::Config) -> Result<Option<String>> {
+    let (key, encrypt) = match command {
+        Commands::Upload {
+            file,
+            key,
+            key_template,
+            encrypt,
+            passphrase_encrypt,
+            ..
+        } => {
+            let resolved = match (key.take(), key_template.take()) {
+                (Some(key), _) => key,
+                (None, template) => match template.or(config.upload.key_template.clone()) {
+                    Some(template) => {
+                        let resolved =
+                            transfer::render_key_template(&template, file, chrono::Local::now())?;
+                        info!("Key template '{}' gives key {}", template, resolved);
+                        resolved
+                    }
+                    None => transfer::upload_file_name(file),
+                },
+            };
+            let key = key.insert(resolved);
+            if file.is_dir() {
+                return Ok(None);
+            }
+            (key, *encrypt || *passphrase_encrypt)
+        }
+        Commands::UploadTar { key, encrypt, .. } | Commands::UploadUrl { key, encrypt, .. } => {
+            (key, *encrypt)
+        }
+        _ => return Ok(None),
+    };
+    if encrypt && !encryption_filter::is_encrypted_extension(key) {
+        *key = encryption_filter::with_encrypted_extension(key);
+        info!("Added .pgp extension to object key: {}", key);
+    }
+    Ok(Some(key.clone()))
+}
+
+/// `upload --passphrase-encrypt`: encrypt `file` in memory with a passphrase rather than
+/// the team keys and store it under `key` with an encrypted extension. `keep_backups`
+/// is set for --backup-on-overwrite.
+async fn upload_with_passphrase(
+    r2_client: &r2_client::R2Client,
+    pgp_handler: &crypto::PgpHandler,
+    file: &Path,
+    key: String,
+    options: r2_client::UploadOptions,
+    keep_backups: Option<usize>,
+) -> Result<Value> {
+    let file_size = fs::metadata(file)
+        .context("Failed to read input file")?
+        .len();
+    if file_size >= multipart_upload::MULTIPART_THRESHOLD {
+        return Err(anyhow::anyhow!(
+            "--passphrase-encrypt is limited to files under {} MB",
+            multipart_upload::MULTIPART_THRESHOLD / (1024 * 1024)
+        ));
+    }
+    let passphrase = encryption_passphrase(true)?;
+    let data = fs::read(file).context("Failed to read input file")?;
+    info!("Encrypting file data with a passphrase");
+    let encrypted = Bytes::from(pgp_handler.encrypt_symmetric(&data, &passphrase)?);
+
+    let key = if encryption_filter::is_encrypted_extension(&key) {
+        key
+    } else {
+        encryption_filter::with_encrypted_extension(&key)
+    };
+    let backup = match keep_backups {
+        Some(keep) => backup::backup_before_overwrite(r2_client, &key, keep).await?,
+        None => None,
+    };
+    let options = options
+        .with_original_filename(&transfer::upload_file_name(file))
+        .with_encrypted(true)
+        .with_content_type_for(&key, true);
+    let bytes = encrypted.len();
+    let etag = r2_client
+        .upload_object_with_options(&key, encrypted, &options)
+        .await?;
+    info!("Successfully uploaded to: {}", key);
+    Ok(json!({
+        "key": key,
+        "etag": etag,
+        "bytes": bytes,
+        "encrypted": true,
+        "passphrase": true,
+        "backup": backup,
+        "private_name": null,
+    }))
+}
+
+/// Log the keys data is about to be encrypted to and, on a terminal, ask before going
+/// ahead unless --yes was given. Fails if the answer is no.
+fn confirm_recipients(recipients: &[crypto::KeyInfo], yes: bool) -> Result<()> {
+    let labels: Vec<String> = recipients.iter().map(crypto::KeyInfo::label).collect();
+    info!("Encrypting to: {}", labels.join(", "));
+    if yes || !std::io::stdin().is_terminal() {
+        return Ok(());
+    }
+    if prompt_yes_no(&format!("Encrypt to these {} keys?", recipients.len()))? {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!(
+            "Cancelled: not encrypting to these recipients"
+        ))
+    }
+}
+
+/// Gate a prefix-wide delete. With `yes` it goes ahead; otherwise the user must agree on
+/// the terminal, and without a terminal it is refused.
+fn confirm_delete(prefix: &str, count: usize, yes: bool) -> Result<()> {
+    if yes {
+        return Ok(());
+    }
+    if !std::io::stdin().is_terminal() {
+        return Err(anyhow::anyhow!(
+            "Refusing to delete {} objects under {} without --yes",
+            count,
+            prefix
+        ));
+    }
+    if prompt_yes_no(&format!("Delete {} objects under {}?", count, prefix))? {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!("Cancelled: nothing was deleted"))
+    }
+}
+
+/// Ask a yes/no question on the terminal. Anything but "y" or "yes" is a no.
+fn prompt_yes_no(question: &str) -> Result<bool> {
+    eprint!("{} [y/N] ", question);
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// The JSON printed when `command` succeeds: its name followed by its result fields.
+fn result_json(command: &str, fields: Value) -> Value {
+    let mut output = serde_json::Map::new();
+    output.insert("command".to_string(), json!(command));
+    if let Value::Object(fields) = fields {
+        output.extend(fields);
+    }
+    Value::Object(output)
+}
+
+/// The JSON result of a failed command. The code is the S3 error code when R2
+/// returned one, otherwise a broad category.
+fn error_json(command: &str, error: &anyhow::Error) -> Value {
+    let r2_error = R2Error::from_anyhow(error);
+    let code = match r2_error {
+        Some(e) => e
+            .code()
+            .map(str::to_string)
+            .unwrap_or_else(|| "ServiceError".to_string()),
+        None if error::is_connection_error(error) => "ConnectionError".to_string(),
+        None => "Error".to_string(),
+    };
+    json!({
+        "command": command,
+        "error": {
+            "code": code,
+            "status": r2_error.and_then(R2Error::status),
+            "message": format!("{:#}", error),
+        },
+    })
+}
+
+/// Run a batch operation, giving up once `deadline` passes. Timing out drops the
+/// operation, which cancels any in-flight requests and aborts the multipart uploads it
+/// left open in `r2_client`'s bucket, and fails with `DeadlineExceeded` listing the
+/// items `completed` reports as finished.
+async fn with_deadline<T, F, C>(
+    r2_client: &r2_client::R2Client,
+    deadline: Option<Duration>,
+    completed: C,
+    operation: F,
+) -> Result<T>
+where
+    F: std::future::Future<Output = Result<T>>,
+    C: Fn() -> Vec<String>,
+{
+    let Some(deadline) = deadline else {
+        return operation.await;
+    };
+
+    match tokio::time::timeout(deadline, operation).await {
+        Ok(result) => result,
+        Err(_) => {
+            multipart_upload::abort_abandoned(r2_client).await;
+            Err(DeadlineExceeded {
+                deadline,
+                completed: completed(),
+            }
+            .into())
+        }
+    }
+}
+
+/// A batch operation stopped by --deadline. `main` reports it and exits with status 124.
+#[derive(Debug)]
+struct DeadlineExceeded {
+    deadline: Duration,
+    completed: Vec<String>, // Items finished before the deadline
+}
+
+impl DeadlineExceeded {
+    fn report(&self) {
+        eprintln!();
+        say!(
+            "Deadline of {}s reached; remaining transfers were cancelled. {} items completed before the deadline:",
+            self.deadline.as_secs(),
+            self.completed.len()
+        );
+        for item in &self.completed {
+            say!("  {}", item);
+        }
+        if let Some(command) = JSON_OUTPUT.get() {
+            println!(
+                "{}",
+                json!({
+                    "command": command,
+                    "error": {
+                        "code": "DeadlineExceeded",
+                        "message": self.to_string(),
+                    },
+                    "completed": self.completed,
+                })
+            );
+        }
+    }
+}
+
+impl std::fmt::Display for DeadlineExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Deadline of {}s reached", self.deadline.as_secs())
+    }
+}
+
+impl std::error::Error for DeadlineExceeded {}
+
+/// Download `source_key`, decrypting it if needed, let the user edit it in `temp_file`
+/// if given, then upload it to `dest_key`, encrypted when public keys are loaded. The
+/// source's metadata and headers are kept.
+async fn process_object(
+    r2_client: &r2_client::R2Client,
+    pgp_handler: &crypto::PgpHandler,
+    source_key: &str,
+    mut dest_key: String,
+    temp_file: Option<&Path>,
+    content_type: Option<String>,
+) -> Result<Value> {
+    info!("Processing: {} -> {}", source_key, dest_key);
+
+    // With nothing to decrypt, edit, encrypt or relabel, R2 can copy the object
+    // itself instead of it being downloaded and uploaded again
+    if temp_file.is_none()
+        && content_type.is_none()
+        && pgp_handler.public_key_count() == 0
+        && !may_be_encrypted(r2_client, source_key).await?
+    {
+        info!("Nothing to change; copying on the server");
+        r2_client.copy_object(source_key, &dest_key).await?;
+        info!("Successfully processed: {} -> {}", source_key, dest_key);
+        return Ok(json!({
+            "source_key": source_key,
+            "dest_key": dest_key,
+            "encrypted": false,
+            "content_type": null,
+            "copied": true,
+        }));
+    }
+
+    info!("Downloading from R2");
+    let (downloaded_data, head) = r2_client.download_object_with_metadata(source_key).await?;
+
+    // Check if source is encrypted
+    let is_encrypted = encryption_filter::is_encrypted_extension(source_key)
+        || crypto::PgpHandler::is_pgp_encrypted(&downloaded_data);
+
+    let decrypted_data = if is_encrypted {
+        info!("Decrypting source file");
+        pgp_handler.decrypt(&downloaded_data)?
+    } else {
+        info!("Source file is not encrypted");
+        downloaded_data.to_vec()
+    };
+
+    let modified_data = if let Some(temp_path) = &temp_file {
+        info!(
+            "Saving decrypted data to temporary file: {}",
+            temp_path.display()
+        );
+        fs::write(temp_path, &decrypted_data).context("Failed to write temporary file")?;
+
+        say!("Decrypted file saved to: {}", temp_path.display());
+        say!("You can now modify the file. Press Enter when ready to re-encrypt and upload...");
+
+        let mut input = String::new();
+        std::io::stdin().read_line(&mut input)?;
+
+        fs::read(temp_path).context("Failed to read modified file")?
+    } else {
+        decrypted_data
+    };
+
+    // Keep the source's metadata, Content-Type and caching headers so the edited
+    // object serves the same way, except that ciphertext is always stored as
+    // application/pgp-encrypted
+    let encrypt = pgp_handler.public_key_count() > 0;
+    let mut metadata = head.metadata.clone();
+    metadata.remove(r2_client::ENCRYPTED_METADATA);
+    let options = r2_client::UploadOptions {
+        metadata,
+        content_type: content_type.or(if encrypt {
+            Some(r2_client::PGP_CONTENT_TYPE.to_string())
+        } else {
+            head.content_type
+                .filter(|_| !is_encrypted)
+                .or_else(|| r2_client::guess_content_type(&dest_key))
+        }),
+        cache_control: head.cache_control,
+        expires: head
+            .expires
+            .and_then(|expires| chrono::DateTime::parse_from_rfc2822(&expires).ok())
+            .map(|expires| expires.with_timezone(&chrono::Utc)),
+        ..Default::default()
+    }
+    .with_encrypted(encrypt);
+
+    let upload_data = if encrypt {
+        info!(
+            "Encrypting modified data for {} recipients",
+            pgp_handler.public_key_count()
+        );
+        // Add .pgp extension unless the key already has an encrypted extension
+        if !encryption_filter::is_encrypted_extension(&dest_key) {
+            dest_key = encryption_filter::with_encrypted_extension(&dest_key);
+            info!("Added .pgp extension to destination key: {}", dest_key);
+        }
+        pgp_handler.encrypt(&modified_data)?
+    } else {
+        info!("No encryption keys configured, uploading unencrypted");
+        modified_data
+    };
+
+    info!("Uploading to R2");
+    r2_client
+        .upload_object_with_options(&dest_key, Bytes::from(upload_data), &options)
+        .await?;
+
+    info!("Successfully processed: {} -> {}", source_key, dest_key);
+    Ok(json!({
+        "source_key": source_key,
+        "dest_key": dest_key,
+        "encrypted": encrypt,
+        "content_type": options.content_type,
+        "copied": false,
+    }))
+}
+
+/// Stream the file at `url` into a multipart upload at `key`, encrypting it chunk by
+/// chunk if asked. The length may be unknown, so nothing is buffered beyond one part.
+async fn upload_url(
+    r2_client: &r2_client::R2Client,
+    pgp_handler: &crypto::PgpHandler,
+    url: &str,
+    mut key: String,
+    encrypt: bool,
+    part_concurrency: usize,
+) -> Result<Value> {
+    let url = url::Url::parse(url).context("Invalid URL")?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(anyhow::anyhow!(
+            "Unsupported URL scheme '{}', only http and https are allowed",
+            url.scheme()
+        ));
+    }
+    if encrypt && pgp_handler.recipient_count() == 0 {
+        return Err(anyhow::anyhow!(
+            "No public keys loaded for encryption. Please configure team keys."
+        ));
+    }
+
+    let response = reqwest::get(url.clone())
+        .await
+        .with_context(|| format!("Failed to fetch {}", url))?
+        .error_for_status()
+        .with_context(|| format!("Failed to fetch {}", url))?;
+
+    let file_name = url
+        .path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .filter(|name| !name.is_empty())
+        .unwrap_or("data")
+        .to_string();
+    if encrypt {
+        key = encryption_filter::with_encrypted_extension(&key);
+    }
+    info!("Streaming {} to {}", url, key);
+
+    let options = r2_client::UploadOptions::default()
+        .with_original_filename(&file_name)
+        .with_encrypted(encrypt)
+        .with_content_type_for(&file_name, encrypt);
+    // The length may be unknown, so always go through a multipart upload
+    let mut uploader = MultipartUploader::start(
+        r2_client,
+        &key,
+        &options,
+        multipart_upload::DEFAULT_PART_SIZE,
+        part_concurrency,
+    )
+    .await?;
+    let mut encryptor = if encrypt {
+        Some(chunked::ChunkedEncryptor::new(
+            pgp_handler,
+            &file_name,
+            chunked::DEFAULT_CHUNK_SIZE,
+        )?)
+    } else {
+        None
+    };
+
+    let progress = ConsoleProgress::new(response.content_length().unwrap_or(0));
+    progress.set_message(&file_name);
+    let result = async {
+        let mut body = response.bytes_stream();
+        let mut fetched = 0u64;
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk.context("Failed to read from URL")?;
+            fetched += chunk.len() as u64;
+            match encryptor.as_mut() {
+                Some(encryptor) => uploader.write(&encryptor.update(&chunk)?).await?,
+                None => uploader.write(&chunk).await?,
+            }
+            progress.set_position(fetched);
+        }
+        if let Some(encryptor) = encryptor.take() {
+            uploader.write(&encryptor.finish()?).await?;
+        }
+        Ok::<u64, anyhow::Error>(fetched)
+    }
+    .await;
+
+    let fetched = match result {
+        Ok(fetched) => fetched,
+        Err(e) => {
+            // Don't leave orphaned parts consuming storage
+            if let Err(abort_error) = uploader.abort().await {
+                tracing::warn!("Failed to abort multipart upload: {}", abort_error);
+            }
+            return Err(e);
+        }
+    };
+    let stored = uploader.finish().await?;
+    progress.finish("done");
+    info!(
+        "Uploaded {} from {} to {} ({} stored)",
+        format_size(fetched),
+        url,
+        key,
+        format_size(stored)
+    );
+    Ok(json!({
+        "url": url.as_str(),
+        "key": key,
+        "bytes": fetched,
+        "stored": stored,
+        "encrypted": encrypt,
+    }))
+}
+
+/// `download` for objects of `RESUMABLE_DOWNLOAD_THRESHOLD` or more. The object is
+/// streamed to a partial file that a re-run resumes, then decrypted or decompressed
+/// from disk into the output.
+async fn download_large(
+    client: &r2_client::R2Client,
+    pgp_handler: &crypto::PgpHandler,
+    key: &str,
+    output: Option<PathBuf>,
+    mut decrypt: bool,
+    decompress: bool,
+    size: u64,
+) -> Result<Value> {
+    let output_path = |name: &str| match &output {
+        Some(path) if path.is_dir() => path.join(name),
+        Some(path) => path.clone(),
+        None => PathBuf::from(name),
+    };
+    let partial = PathBuf::from(format!(
+        "{}{}",
+        output_path(&transfer::download_file_name(key, None, false)).display(),
+        folder_download::PARTIAL_SUFFIX
+    ));
+
+    let progress = ConsoleProgress::new(size);
+    let head = folder_download::download_resumable(client, key, &partial, size, &progress).await?;
+    progress.finish("done");
+
+    let is_encrypted = encryption_filter::is_encrypted_extension(key)
+        || folder_download::file_looks_encrypted(&partial)?;
+    if is_encrypted && !decrypt {
+        info!("Auto-detected encrypted file");
+        decrypt = true;
+    }
+    let decrypted = decrypt && is_encrypted;
+    let private = private_metadata(client, pgp_handler, key, decrypted).await?;
+    let restored_name = match &private {
+        Some(private) => Some(private.file_name.clone()),
+        None => head.original_filename().filter(|_| decrypted),
+    };
+    let output = output_path(&transfer::download_file_name(
+        key,
+        restored_name.as_deref(),
+        decrypt,
+    ));
+
+    let bytes = if decrypted
+        || decompress && transfer::is_gzip_encoding(head.content_encoding.as_deref())
+    {
+        // The output is written beside the partial file, which is only removed afterwards.
+        // Plaintext is about the size of the ciphertext; decompressed data is larger still
+        disk_space::ensure_space(&output, size)?;
+        // Nothing appears at the output path until the plaintext has been checked
+        let temp = PathBuf::from(format!("{}.tmp", output.display()));
+        let input = fs::File::open(&partial).context("Failed to read downloaded file")?;
+        let mut writer = std::io::BufWriter::new(
+            fs::File::create(&temp).context("Failed to write output file")?,
+        );
+        let written = (|| {
+            let bytes = if decrypted {
+                info!("Decrypting downloaded data");
+                pgp_handler.decrypt_to_writer(&mut std::io::BufReader::new(input), &mut writer)?
+            } else {
+                info!("Decompressing gzip Content-Encoding");
+                std::io::copy(&mut flate2::read::MultiGzDecoder::new(input), &mut writer)?
+            };
+            writer.flush().context("Failed to write output file")?;
+            drop(writer);
+            if decrypted && head.plaintext_sha256().is_some() {
+                verify_plaintext_sha256(key, &head, &integrity::sha256_file(&temp)?)?;
+            }
+            Ok::<u64, anyhow::Error>(bytes)
+        })();
+        let bytes = match written {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                let _ = fs::remove_file(&temp);
+                return Err(e);
+            }
+        };
+        fs::rename(&temp, &output).context("Failed to write output file")?;
+        fs::remove_file(&partial)?;
+        bytes
+    } else {
+        fs::rename(&partial, &output).context("Failed to write output file")?;
+        size
+    };
+
+    info!("Downloaded to: {}", output.display());
+    Ok(json!({
+        "key": key,
+        "path": output.display().to_string(),
+        "bytes": bytes,
+        "decrypted": decrypted,
+        "private_name": private.as_ref().map(|p| &p.key),
+        "metadata": private.as_ref().map(|p| &p.metadata),
+    }))
+}
+
+/// Compare the SHA-256 of what `key` decrypted to with the one recorded at upload.
+fn verify_plaintext_sha256(key: &str, head: &r2_client::ObjectHead, actual: &str) -> Result<()> {
+    match head.plaintext_sha256() {
+        Some(expected) if !expected.eq_ignore_ascii_case(actual) => Err(anyhow::anyhow!(
+            "Decrypted {} doesn't match the SHA-256 recorded at upload: expected {}, got {}",
+            key,
+            expected,
+            actual
+        )),
+        Some(_) => {
+            info!("Decrypted content matches the SHA-256 recorded at upload");
+            Ok(())
+        }
+        None => Ok(()),
+    }
+}
+
+/// The sidecar of an object uploaded with --private-name, when it is being decrypted.
+async fn private_metadata(
+    client: &r2_client::R2Client,
+    pgp_handler: &crypto::PgpHandler,
+    key: &str,
+    decrypted: bool,
+) -> Result<Option<sidecar::PrivateMetadata>> {
+    if !decrypted || sidecar::is_sidecar(key) {
+        return Ok(None);
+    }
+    let private = sidecar::fetch_sidecar(client, pgp_handler, key).await?;
+    if let Some(private) = &private {
+        info!(
+            "Restoring private name {} from its metadata sidecar",
+            private.key
+        );
+        for (name, value) in &private.metadata {
+            info!("  {}: {}", name, value);
+        }
+    }
+    Ok(private)
+}
+
+/// Whether `key` is, or might be, encrypted, judging by its name, its metadata and the
+/// first bytes of its content.
+async fn may_be_encrypted(r2_client: &r2_client::R2Client, key: &str) -> Result<bool> {
+    if encryption_filter::is_encrypted_extension(key) {
+        return Ok(true);
+    }
+    let head = r2_client.head_object(key).await?;
+    if head.metadata.contains_key(r2_client::ENCRYPTED_METADATA) {
+        return Ok(true);
+    }
+    if head.content_length == Some(0) {
+        return Ok(false);
+    }
+    let start = r2_client.download_range(key, 0, 63).await?;
+    Ok(crypto::PgpHandler::is_pgp_encrypted(&start))
+}
+
+/// Download an archive made by upload-tar (or any tar) and extract it into `dest`,
+/// decrypting on the way if it is encrypted. The object goes through a resumable
+/// partial file, removed afterwards, and is then decrypted straight into the extractor,
+/// so an encrypted archive is never written out as a plaintext tar file. Memory use is
+/// bounded as in `PgpHandler::decrypt_to_writer`: chunked archives are decrypted a chunk
+/// at a time, and single messages over 64 MiB are streamed through gpg.
+async fn download_untar(
+    client: &r2_client::R2Client,
+    pgp_handler: &crypto::PgpHandler,
+    key: &str,
+    dest: &Path,
+) -> Result<Value> {
+    let size = client.head_object(key).await?.content_length.unwrap_or(0);
+    fs::create_dir_all(dest).context("Failed to create output folder")?;
+    let partial = dest.join(format!(
+        ".{}{}",
+        transfer::download_file_name(key, None, false),
+        folder_download::PARTIAL_SUFFIX
+    ));
+
+    let progress = ConsoleProgress::new(size);
+    folder_download::download_resumable(client, key, &partial, size, &progress).await?;
+    progress.finish("done");
+
+    let decrypted = encryption_filter::is_encrypted_extension(key)
+        || folder_download::file_looks_encrypted(&partial)?;
+    let input = fs::File::open(&partial).context("Failed to read downloaded file")?;
+    let mut extractor = tar_stream::TarExtractor::new(dest)?;
+    if decrypted {
+        info!("Decrypting and extracting into {}", dest.display());
+    } else {
+        info!("Extracting into {}", dest.display());
+    }
+    pgp_handler.decrypt_to_writer(&mut std::io::BufReader::new(input), &mut extractor)?;
+    let files = extractor.finish()?;
+    fs::remove_file(&partial)?;
+
+    info!("Extracted {} files to: {}", files.len(), dest.display());
+    Ok(json!({
+        "key": key,
+        "path": dest.display().to_string(),
+        "files": files.len(),
+        "decrypted": decrypted,
+    }))
+}
+
+/// Poll `prefix` every `interval` and report objects that weren't there before, or that
+/// were overwritten, oldest first. Unless `from_start`, what is already there when
+/// watching starts only sets the baseline. Runs until Ctrl-C, returning the number of
+/// objects reported.
+async fn tail_prefix(
+    client: &r2_client::R2Client,
+    pgp_handler: &Arc<crypto::PgpHandler>,
+    prefix: &str,
+    download_dir: Option<&Path>,
+    options: &folder_download::FolderDownloadOptions,
+    interval: Duration,
+    from_start: bool,
+) -> Result<usize> {
+    let mut watcher = tail::PrefixWatcher::new(prefix, from_start);
+    let mut reported = 0;
+
+    info!("Watching {} every {:?}, Ctrl-C to stop", prefix, interval);
+    loop {
+        match watcher.poll(client).await {
+            Ok(new_objects) => {
+                for object in &new_objects {
+                    say!(
+                        "{}  {:>10}  {}",
+                        object.last_modified.as_deref().unwrap_or("-"),
+                        format_size(object.size),
+                        object.key
+                    );
+                }
+                reported += new_objects.len();
+
+                if let (Some(dir), false) = (download_dir, new_objects.is_empty()) {
+                    let options = folder_download::FolderDownloadOptions {
+                        only_keys: Some(new_objects.into_iter().map(|o| o.key).collect()),
+                        ..options.clone()
+                    };
+                    let progress = batch_progress();
+                    let summary = folder_download::download_prefix(
+                        client,
+                        Some(pgp_handler.clone()),
+                        prefix,
+                        dir,
+                        &options,
+                        &progress,
+                    )
+                    .await?;
+                    for (key, error) in &summary.failed {
+                        tracing::warn!("Failed to download {}: {}", key, error);
+                    }
+                }
+            }
+            // Keep watching through transient failures
+            Err(e) => tracing::warn!("Failed to list {}: {:#}", prefix, e),
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = tokio::signal::ctrl_c() => return Ok(reported),
+        }
+    }
+}
+
+/// How many profiles `list --all-profiles` queries at once
+const PROFILE_CONCURRENCY: usize = 4;
+
+/// `list` against every configured profile. A profile that fails is reported without
+/// stopping the others.
+async fn list_all_profiles(
+    config: &config::Config,
+    prefix: Option<String>,
+    relative: bool,
+    encryption: &EncryptionFilterArgs,
+) -> Result<Value> {
+    let results: Vec<(String, String, Result<Vec<String>>)> = stream::iter(config.all_profiles())
+        .map(|(name, r2)| {
+            let prefix = prefix.clone();
+            async move {
+                let bucket = r2.bucket_name.clone();
+                let keys = async {
+                    let client_config = r2.client_config(&config.retry);
+                    let client = r2_client::R2Client::new(
+                        r2.access_key_id,
+                        r2.secret_access_key,
+                        r2.account_id,
+                        r2.bucket_name,
+                    )
+                    .await?
+                    .with_client_config(client_config)?;
+                    let objects = client.list_objects_detailed(prefix.as_deref()).await?;
+                    let objects = encryption_filter::filter_objects(
+                        &client,
+                        objects,
+                        encryption.filter(),
+                        encryption.verify_encryption,
+                    )
+                    .await?;
+                    Ok(objects.into_iter().map(|o| o.key).collect())
+                }
+                .await;
+                (name, bucket, keys)
+            }
+        })
+        .buffered(PROFILE_CONCURRENCY)
+        .collect()
+        .await;
+
+    let mut records = Vec::new();
+    let mut failed = Vec::new();
+    for (profile, bucket, keys) in results {
+        say!("[{}] {}:", profile, bucket);
+        match keys {
+            Ok(keys) if keys.is_empty() => say!("  No objects found"),
+            Ok(keys) => {
+                for key in &keys {
+                    let mut record = json!({ "profile": profile, "bucket": bucket, "key": key });
+                    let shown = match prefix.as_deref() {
+                        Some(p) if relative => {
+                            let relative_key = transfer::relative_key(key, p);
+                            record["relative_key"] = json!(relative_key);
+                            relative_key
+                        }
+                        _ => key,
+                    };
+                    say!("  {}", shown);
+                    records.push(record);
+                }
+            }
+            Err(e) => {
+                say!("  ✗ {:#}", e);
+                failed.push(
+                    json!({ "profile": profile, "bucket": bucket, "error": format!("{:#}", e) }),
+                );
+            }
+        }
+    }
+    if !failed.is_empty() {
+        tracing::warn!("{} profiles could not be listed", failed.len());
+    }
+    Ok(json!({ "prefix": prefix, "objects": records, "failed": failed }))
+}
+
+/// Delete the objects under `prefix` that pass the encryption filter, and their sidecars,
+/// once `confirm_delete` allows it. With `dry_run` they are only listed.
+async fn delete_prefix(
+    r2_client: &r2_client::R2Client,
+    prefix: &str,
+    encryption: &EncryptionFilterArgs,
+    dry_run: bool,
+    yes: bool,
+) -> Result<Value> {
+    if prefix.is_empty() {
+        return Err(anyhow::anyhow!(
+            "Refusing to delete the whole bucket; give a prefix to delete"
+        ));
+    }
+
+    info!("Listing objects to delete with prefix: {}", prefix);
+    let listed = r2_client.list_objects_detailed(Some(prefix)).await?;
+    let all_keys: Vec<String> = listed.iter().map(|obj| obj.key.clone()).collect();
+    let objects = encryption_filter::filter_objects(
+        r2_client,
+        listed,
+        encryption.filter(),
+        encryption.verify_encryption,
+    )
+    .await?;
+
+    // A --private-name object takes its metadata sidecar with it, even when a filter
+    // would have left the sidecar out
+    let mut keys: Vec<String> = objects.into_iter().map(|obj| obj.key).collect();
+    let all_keys: HashSet<&str> = all_keys.iter().map(String::as_str).collect();
+    let selected: HashSet<&str> = keys.iter().map(String::as_str).collect();
+    let sidecars: Vec<String> = keys
+        .iter()
+        .filter(|key| encryption_filter::is_encrypted_extension(key) && !sidecar::is_sidecar(key))
+        .map(|key| sidecar::sidecar_key(key))
+        .filter(|sidecar_key| {
+            all_keys.contains(sidecar_key.as_str()) && !selected.contains(sidecar_key.as_str())
+        })
+        .collect();
+    keys.extend(sidecars);
+
+    if keys.is_empty() {
+        say!("No objects found under {}", prefix);
+        return Ok(json!({ "prefix": prefix, "deleted": [], "dry_run": dry_run }));
+    }
+
+    if dry_run {
+        for key in &keys {
+            say!("Would delete: {}", key);
+        }
+        say!("Dry run: {} objects would be deleted", keys.len());
+        return Ok(json!({
+            "prefix": prefix,
+            "deleted": [],
+            "would_delete": keys,
+            "dry_run": true,
+        }));
+    }
+
+    // Refuse up front, rather than after a partial delete, if the token can't delete here
+    let needed = [r2_client::Permission::Read, r2_client::Permission::Delete];
+    r2_client.check_permissions(prefix, &needed).await?;
+    confirm_delete(prefix, keys.len(), yes)?;
+
+    info!("Deleting {} objects under {}", keys.len(), prefix);
+    let outcome = r2_client.delete_objects(&keys).await?;
+    for (key, reason) in &outcome.failed {
+        tracing::warn!("Failed to delete {}: {}", key, reason);
+    }
+    say!(
+        "Deleted {} of {} objects under {}",
+        outcome.deleted.len(),
+        keys.len(),
+        prefix
+    );
+    if !outcome.failed.is_empty() {
+        return Err(anyhow::anyhow!(
+            "{} objects could not be deleted",
+            outcome.failed.len()
+        ));
+    }
+    Ok(json!({ "prefix": prefix, "deleted": outcome.deleted, "dry_run": false }))
+}
+
+/// Run `folder_upload::upload_folder`, print what it did, and fail if any file failed.
+async fn upload_folder(
+    r2_client: &r2_client::R2Client,
+    pgp_handler: &crypto::PgpHandler,
+    folder: &Path,
+    prefix: &str,
+    options: &folder_upload::FolderUploadOptions,
+    progress: Arc<ConsoleProgress>,
+) -> Result<folder_upload::FolderUploadSummary> {
+    let summary =
+        folder_upload::upload_folder(r2_client, pgp_handler, folder, prefix, options, progress)
+            .await?;
+    let failed = summary.failed.len();
+
+    if options.dry_run {
+        for (relative_path, key) in &summary.planned {
+            say!("Would upload: {} -> {}", relative_path, key);
+        }
+        say!(
+            "Dry run: {} would be uploaded, {} skipped, {} failed",
+            summary.uploaded.len(),
+            summary.skipped.len(),
+            failed
+        );
+        if failed > 0 {
+            return Err(anyhow::anyhow!("{} files could not be checked", failed));
+        }
+        return Ok(summary);
+    }
+    if options.skip_unchanged {
+        say!(
+            "Sync complete: {} uploaded, {} skipped, {} failed",
+            summary.uploaded.len(),
+            summary.skipped.len(),
+            failed
+        );
+    } else {
+        say!(
+            "Folder upload complete: {} uploaded, {} failed",
+            summary.uploaded.len(),
+            failed
+        );
+    }
+    if failed > 0 {
+        return Err(anyhow::anyhow!("{} files failed to upload", failed));
+    }
+    Ok(summary)
+}
+
+/// Progress bar for a batch operation, writing the per-item report if one was asked for.
+fn batch_progress() -> ConsoleProgress {
+    ConsoleProgress::new(0).with_item_report(ITEM_REPORT.get().is_some())
+}
+
+// Enough of an encrypted object to read the recipients of its session key
+const RECIPIENT_PROBE_BYTES: u64 = 64 * 1024;
+
+/// Check that `key` can be decrypted with what is loaded. Normally this only reads the
+/// start of the object and compares its recipients with the secret key's IDs; with
+/// `deep` the whole object is downloaded and decrypted, which also covers keys that
+/// only the gpg agent holds. The error says why the object can't be decrypted.
+async fn check_decryptable(
+    r2_client: &r2_client::R2Client,
+    pgp_handler: &crypto::PgpHandler,
+    key: &str,
+    deep: bool,
+) -> Result<()> {
+    if deep {
+        let data = r2_client.download_object(key).await?;
+        pgp_handler.decrypt_to_writer(&mut std::io::Cursor::new(data), &mut std::io::sink())?;
+        return Ok(());
+    }
+
+    let start = r2_client
+        .download_range(key, 0, RECIPIENT_PROBE_BYTES - 1)
+        .await?;
+    let recipients = crypto::PgpHandler::list_recipients(&start)?;
+    let secret_ids = pgp_handler.secret_key_ids();
+    if secret_ids.is_empty() {
+        anyhow::bail!("no secret key loaded; pass --deep to try the gpg agent");
+    }
+    if recipients.iter().any(|id| secret_ids.contains(id)) {
+        return Ok(());
+    }
+    if recipients.iter().any(|id| id.chars().all(|c| c == '0')) {
+        anyhow::bail!("hidden recipient; pass --deep to try decrypting it");
+    }
+    anyhow::bail!(
+        "encrypted to key IDs {}, none of which is the loaded secret key",
+        recipients.join(", ")
+    )
+}
+
+/// Download, decrypt and re-encrypt a single object to the given recipients.
+/// Returns `Ok(false)` when the object turned out not to be encrypted.
+async fn reencrypt_object(
+    r2_client: &r2_client::R2Client,
+    pgp_handler: &crypto::PgpHandler,
+    key: &str,
+    dest_key: &str,
+    recipients: &[String],
+) -> Result<bool> {
+    let (data, head) = r2_client.download_object_with_metadata(key).await?;
+    if !crypto::PgpHandler::is_pgp_encrypted(&data) {
+        return Ok(false);
+    }
+
+    // Keep the stored filename, both in the literal packet and in object metadata
+    let (plaintext, file_name) = pgp_handler.decrypt_with_filename(&data)?;
+    let file_name = file_name.or_else(|| head.original_filename());
+    let encrypted = pgp_handler.encrypt_to(
+        &plaintext,
+        file_name.as_deref().unwrap_or("data"),
+        recipients,
+    )?;
+    let options = r2_client::UploadOptions {
+        metadata: head.metadata,
+        ..Default::default()
+    }
+    .with_content_type_for(dest_key, true);
+    r2_client
+        .upload_object_with_options(dest_key, Bytes::from(encrypted), &options)
+        .await?;
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_r2::MockR2;
+    use crate::test_support::TempDir;
+
+    #[tokio::test]
+    async fn reencrypting_leaves_only_the_new_recipient_able_to_decrypt() {
+        let old_key = test_support::key("Old");
+        let new_key = test_support::key("New");
+        let mut handler = test_support::handler(&[&old_key], &[&old_key]);
+        handler.load_public_key(&new_key.public).unwrap();
+        let mock = MockR2::start().await;
+        mock.put("docs/a.txt.pgp", &handler.encrypt(b"rotate me").unwrap());
+        let client = mock.client().await;
+
+        let recipients = [new_key.fingerprint.clone()];
+        let rotated = reencrypt_object(
+            &client,
+            &handler,
+            "docs/a.txt.pgp",
+            "docs/a.txt.pgp",
+            &recipients,
+        )
+        .await
+        .unwrap();
+        assert!(rotated);
+
+        let data = mock.object("docs/a.txt.pgp").unwrap().data;
+        let new_handler = test_support::handler(&[], &[&new_key]);
+        assert_eq!(new_handler.decrypt(&data).unwrap(), b"rotate me");
+        let old_handler = test_support::handler(&[], &[&old_key]);
+        assert!(old_handler.decrypt(&data).is_err());
+    }
+
+    #[tokio::test]
+    async fn dry_run_checks_tell_decryptable_objects_from_the_rest() {
+        let held = test_support::key("Held");
+        let lost = test_support::key("Lost");
+        let encrypt_to = |keys: &[&test_support::TestKey], data: &[u8]| {
+            test_support::handler(keys, &[]).encrypt(data).unwrap()
+        };
+        let mock = MockR2::start().await;
+        mock.put("docs/mine.txt.pgp", &encrypt_to(&[&held], b"mine"));
+        mock.put("docs/both.txt.pgp", &encrypt_to(&[&held, &lost], b"both"));
+        mock.put("docs/theirs.txt.pgp", &encrypt_to(&[&lost], b"theirs"));
+        let client = mock.client().await;
+        let handler = test_support::handler(&[], &[&held]);
+
+        for deep in [false, true] {
+            for key in ["docs/mine.txt.pgp", "docs/both.txt.pgp"] {
+                check_decryptable(&client, &handler, key, deep)
+                    .await
+                    .unwrap();
+            }
+            let error = check_decryptable(&client, &handler, "docs/theirs.txt.pgp", deep)
+                .await
+                .unwrap_err();
+            if !deep {
+                // The reason names the key the object is encrypted to
+                let lost_id = test_support::handler(&[], &[&lost]).secret_key_ids()[0].clone();
+                assert!(format!("{:#}", error).contains(&lost_id), "{:#}", error);
+            }
+        }
+
+        // Without a secret key, only --deep could still find one in the gpg agent
+        let error = check_decryptable(
+            &client,
+            &crypto::PgpHandler::new(),
+            "docs/mine.txt.pgp",
+            false,
+        )
+        .await
+        .unwrap_err();
+        assert!(error.to_string().contains("no secret key loaded"));
+    }
+
+    #[tokio::test]
+    async fn gzip_assets_upload_as_is_and_download_inflated() {
+        use std::io::Write as _;
+        let plaintext = b"body { color: red }\n".repeat(500);
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&plaintext).unwrap();
+        let compressed = encoder.finish().unwrap();
+        let mock = MockR2::start().await;
+        let client = mock.client().await;
+
+        let options = r2_client::UploadOptions {
+            content_encoding: Some("gzip".into()),
+            ..Default::default()
+        };
+        client
+            .upload_object_with_options("site/app.css", Bytes::from(compressed.clone()), &options)
+            .await
+            .unwrap();
+        assert_eq!(mock.object("site/app.css").unwrap().data, compressed);
+        let head = client.head_object("site/app.css").await.unwrap();
+        assert_eq!(head.content_encoding.as_deref(), Some("gzip"));
+
+        let folder = TempDir::new();
+        let output = folder.path().join("app.css");
+        download_large(
+            &client,
+            &crypto::PgpHandler::new(),
+            "site/app.css",
+            Some(output.clone()),
+            false,
+            true,
+            compressed.len() as u64,
+        )
+        .await
+        .unwrap();
+        assert_eq!(fs::read(&output).unwrap(), plaintext);
+    }
+
+    #[tokio::test]
+    async fn mismatched_plaintext_hashes_leave_no_output() {
+        let key = test_support::key("Alice");
+        let handler = test_support::handler(&[&key], &[&key]);
+        let encrypted = handler
+            .encrypt_with_filename(b"what was uploaded", "a.txt")
+            .unwrap();
+        let mock = MockR2::start().await;
+        mock.put_with_metadata(
+            "a.txt.pgp",
+            &encrypted,
+            &[(
+                "plaintext-sha256",
+                &integrity::sha256_hex(b"something else"),
+            )],
+        );
+        let client = mock.client().await;
+        let folder = TempDir::new();
+        let output = folder.path().join("a.txt");
+
+        let result = download_large(
+            &client,
+            &handler,
+            "a.txt.pgp",
+            Some(output.clone()),
+            true,
+            false,
+            encrypted.len() as u64,
+        )
+        .await;
+        assert!(format!("{:#}", result.unwrap_err()).contains("doesn't match the SHA-256"));
+        assert!(!output.exists());
+        assert!(!folder.path().join("a.txt.tmp").exists());
+
+        mock.put_with_metadata(
+            "a.txt.pgp",
+            &encrypted,
+            &[(
+                "plaintext-sha256",
+                &integrity::sha256_hex(b"what was uploaded"),
+            )],
+        );
+        download_large(
+            &client,
+            &handler,
+            "a.txt.pgp",
+            Some(output.clone()),
+            true,
+            false,
+            encrypted.len() as u64,
+        )
+        .await
+        .unwrap();
+        assert_eq!(fs::read(&output).unwrap(), b"what was uploaded");
+    }
+
+    #[tokio::test]
+    async fn deadlines_abort_the_multipart_uploads_they_cut_off() {
+        let mock = MockR2::start().await;
+        let client = mock.client().await;
+
+        let result = with_deadline(&client, Some(Duration::from_millis(500)), Vec::new, async {
+            let mut uploader = MultipartUploader::start(
+                &client,
+                "slow.bin",
+                &r2_client::UploadOptions::default(),
+                multipart_upload::MIN_PART_SIZE,
+                2,
+            )
+            .await?;
+            uploader.write(b"the rest never comes").await?;
+            tokio::time::sleep(Duration::from_secs(30)).await;
+            uploader.finish().await
+        })
+        .await;
+
+        assert!(result.unwrap_err().is::<DeadlineExceeded>());
+        assert!(mock.open_uploads().is_empty());
+    }
+
+    #[tokio::test]
+    async fn deadlines_stop_batches_on_a_slow_server() {
+        let folder = TempDir::new();
+        folder.write("a.txt", b"quick");
+        folder.write("b.txt", b"slow");
+        folder.write("c.txt", b"quick");
+        let mock = MockR2::start().await;
+        mock.delay("backup/b.txt", Duration::from_secs(30));
+        let client = mock.client().await;
+        let progress = Arc::new(ConsoleProgress::new(0));
+
+        let result = with_deadline(
+            &client,
+            Some(Duration::from_millis(500)),
+            || progress.completed_items(),
+            upload_folder(
+                &client,
+                &crypto::PgpHandler::new(),
+                folder.path(),
+                "backup",
+                &folder_upload::FolderUploadOptions::default(),
+                progress.clone(),
+            ),
+        )
+        .await;
+
+        let e = result.err().unwrap();
+        let exceeded = e.downcast_ref::<DeadlineExceeded>().unwrap();
+        // Files are uploaded in directory order, so any of the quick ones may be done
+        let mut completed = exceeded.completed.clone();
+        completed.sort();
+        assert!(!completed.contains(&"backup/b.txt".to_string()));
+        assert_eq!(mock.keys(), completed);
+    }
+
+    #[tokio::test]
+    async fn url_uploads_stream_into_multipart_uploads() {
+        // Another mock bucket serves as the remote web server; it answers unsigned GETs
+        let origin = MockR2::start().await;
+        let large: Vec<u8> = (0..9 * 1024 * 1024 + 17).map(|i| (i % 251) as u8).collect();
+        origin.put("files/large.bin", &large);
+        origin.put("files/notes.txt", b"fetched and encrypted");
+        let store = MockR2::start().await;
+        let client = store.client().await;
+        let key = test_support::key("Uploader");
+        let handler = test_support::handler(&[&key], &[&key]);
+        let url = |name: &str| format!("{}/bucket/files/{}", origin.endpoint(), name);
+
+        let result = upload_url(
+            &client,
+            &handler,
+            &url("large.bin"),
+            "in/large.bin".into(),
+            false,
+            2,
+        )
+        .await
+        .unwrap();
+        assert_eq!(result["bytes"], large.len() as u64);
+        assert_eq!(store.object("in/large.bin").unwrap().data, large);
+
+        let result = upload_url(
+            &client,
+            &handler,
+            &url("notes.txt"),
+            "in/notes.txt".into(),
+            true,
+            2,
+        )
+        .await
+        .unwrap();
+        assert_eq!(result["key"], "in/notes.txt.pgp");
+        let stored = store.object("in/notes.txt.pgp").unwrap();
+        let mut plaintext = Vec::new();
+        chunked::decrypt_chunked(&handler, &mut &stored.data[..], &mut plaintext).unwrap();
+        assert_eq!(plaintext, b"fetched and encrypted");
+
+        // Missing files and other schemes fail before anything is uploaded
+        assert!(upload_url(
+            &client,
+            &handler,
+            &url("missing"),
+            "in/missing".into(),
+            false,
+            2
+        )
+        .await
+        .is_err());
+        let ftp = "ftp://example.com/file.bin";
+        let error = upload_url(&client, &handler, ftp, "in/ftp".into(), false, 2)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("Unsupported URL scheme 'ftp'"));
+        assert_eq!(store.keys(), ["in/large.bin", "in/notes.txt.pgp"]);
+    }
+
+    /// Run the CLI with `args` against a config pointing at `endpoint`, returning the JSON
+    /// that `--output json` prints.
+    async fn run_json(endpoint: &str, args: &[&str]) -> Value {
+        let folder = TempDir::new();
+        let config = json!({
+            "r2": {
+                "access_key_id": "test-access-key",
+                "secret_access_key": "test-secret-key",
+                "account_id": "test-account",
+                "bucket_name": "bucket",
+                "endpoint": endpoint,
+            },
+            "retry": { "request_retries": 0 },
+        });
+        let config_path = folder.write("config.json", config.to_string().as_bytes());
+        let config_path = config_path.to_str().unwrap();
+        let mut argv = vec!["rust-r2-cli", "--config", config_path, "--output", "json"];
+        argv.extend_from_slice(args);
+        let cli = Cli::parse_from(argv);
+        let command = cli.command.name();
+        match run(cli).await {
+            Ok(fields) => result_json(command, fields),
+            Err(e) => error_json(command, &e),
+        }
+    }
+
+    #[test]
+    fn upload_keys_are_resolved_once_for_the_transfer_and_the_hook() {
+        let folder = TempDir::new();
+        let file = folder.write("notes.txt", b"notes");
+        let file = file.to_str().unwrap();
+        let config = config::Config::default();
+        let resolve = |args: &[&str]| {
+            let mut argv = vec!["rust-r2-cli"];
+            argv.extend_from_slice(args);
+            let mut command = Cli::parse_from(argv).command;
+            let target = resolve_upload_key(&mut command, &config).unwrap();
+            let key = match command {
+                Commands::Upload { key, .. } => key,
+                Commands::UploadUrl { key, .. } => Some(key),
+                _ => None,
+            };
+            (target, key)
+        };
+        let both = |key: &str| (Some(key.to_string()), Some(key.to_string()));
+
+        assert_eq!(resolve(&["upload", file]), both("notes.txt"));
+        assert_eq!(
+            resolve(&["upload", file, "--key-template", "docs/{basename}.{ext}", "-e"]),
+            both("docs/notes.txt.pgp")
+        );
+        assert_eq!(
+            resolve(&["upload", file, "docs/notes.gpg", "-e"]),
+            both("docs/notes.gpg")
+        );
+        assert_eq!(
+            resolve(&["upload", file, "docs/notes", "--passphrase-encrypt"]),
+            both("docs/notes.pgp")
+        );
+        assert_eq!(
+            resolve(&["upload-url", "https://example.com/a.iso", "isos/a.iso", "-e"]),
+            both("isos/a.iso.pgp")
+        );
+
+        // A folder gets its prefix from the template, but reports to the hook itself
+        let dir = folder.path().to_str().unwrap();
+        let (target, prefix) = resolve(&["upload", dir, "--key-template", "up/{name}", "-e"]);
+        assert_eq!(target, None);
+        assert!(prefix.unwrap().starts_with("up/"));
+    }
+
+    #[tokio::test]
+    async fn json_results_have_a_stable_shape() {
+        let mock = MockR2::start().await;
+        let folder = TempDir::new();
+        let file = folder.write("notes.txt", b"hello json");
+        let file = file.to_str().unwrap();
+
+        let uploaded = run_json(mock.endpoint(), &["upload", file, "docs/notes.txt"]).await;
+        assert_eq!(uploaded["command"], "upload");
+        assert_eq!(uploaded["key"], "docs/notes.txt");
+        assert_eq!(uploaded["bytes"], 10);
+        assert_eq!(uploaded["encrypted"], false);
+        assert!(uploaded["etag"].is_string());
+        assert!(uploaded.get("error").is_none());
+
+        let copied = run_json(
+            mock.endpoint(),
+            &["copy", "docs/notes.txt", "docs/copy.txt"],
+        )
+        .await;
+        assert_eq!(copied["command"], "copy");
+        assert_eq!(copied["source_key"], "docs/notes.txt");
+        assert_eq!(copied["dest_key"], "docs/copy.txt");
+
+        let deleted = run_json(mock.endpoint(), &["delete", "docs/copy.txt"]).await;
+        assert_eq!(deleted["command"], "delete");
+        assert_eq!(deleted["key"], "docs/copy.txt");
+        assert_eq!(deleted["deleted"], true);
+        assert_eq!(mock.keys(), ["docs/notes.txt"]);
+    }
+
+    #[tokio::test]
+    async fn json_errors_carry_the_code_and_status() {
+        let mock = MockR2::start().await;
+        let output = TempDir::new();
+        let output_path = output.path().join("missing.txt");
+
+        // HEAD responses have no body, so only the status is known
+        let missing = run_json(
+            mock.endpoint(),
+            &[
+                "download",
+                "docs/missing.txt",
+                "-o",
+                output_path.to_str().unwrap(),
+            ],
+        )
+        .await;
+        assert_eq!(missing["command"], "download");
+        assert_eq!(missing["error"]["code"], "ServiceError");
+        assert_eq!(missing["error"]["status"], 404);
+        assert!(missing["error"]["message"].is_string());
+        let missing = run_json(mock.endpoint(), &["copy", "docs/missing.txt", "docs/b.txt"]).await;
+        assert_eq!(missing["command"], "copy");
+        assert_eq!(missing["error"]["code"], "NoSuchKey");
+        assert_eq!(missing["error"]["status"], 404);
+
+        mock.put("docs/kept.txt", b"kept");
+        mock.deny("DELETE");
+        let denied = run_json(mock.endpoint(), &["delete", "docs/kept.txt"]).await;
+        assert_eq!(denied["command"], "delete");
+        assert_eq!(denied["error"]["code"], "AccessDenied");
+        assert_eq!(denied["error"]["status"], 403);
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let closed = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+        let unreachable = run_json(&closed, &["stat", "docs/kept.txt"]).await;
+        assert_eq!(unreachable["command"], "stat");
+        assert_eq!(unreachable["error"]["code"], "ConnectionError");
+        assert_eq!(unreachable["error"]["status"], Value::Null);
+    }
+
+    #[tokio::test]
+    async fn all_profiles_listings_aggregate_every_bucket() {
+        let staging = MockR2::start().await;
+        staging.put("logs/a.txt", b"a");
+        staging.put("other/x.txt", b"x");
+        let prod = MockR2::start().await;
+        prod.put("logs/b.txt", b"b");
+        prod.put("logs/c.txt", b"c");
+        let broken = MockR2::start().await;
+        broken.deny("GET");
+        let profile = |mock: &MockR2| config::R2Config {
+            access_key_id: "test-access-key".into(),
+            secret_access_key: "test-secret-key".into(),
+            account_id: "test-account".into(),
+            bucket_name: "bucket".into(),
+            endpoint: Some(mock.endpoint().to_string()),
+            ..Default::default()
+        };
+        let mut config = config::Config {
+            r2: profile(&staging),
+            ..Default::default()
+        };
+        config.profiles.insert("prod".into(), profile(&prod));
+        config.profiles.insert("archive".into(), profile(&broken));
+        let encryption = EncryptionFilterArgs {
+            only_encrypted: false,
+            only_plaintext: false,
+            verify_encryption: false,
+        };
+
+        let result = list_all_profiles(&config, Some("logs/".into()), true, &encryption)
+            .await
+            .unwrap();
+        let records: Vec<(&str, &str)> = result["objects"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| {
+                (
+                    r["profile"].as_str().unwrap(),
+                    r["relative_key"].as_str().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            records,
+            [("default", "a.txt"), ("prod", "b.txt"), ("prod", "c.txt")]
+        );
+        // The failing profile is reported without stopping the others
+        let failed = result["failed"].as_array().unwrap();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0]["profile"], "archive");
+        assert!(failed[0]["error"].as_str().unwrap().contains("403"));
+    }
+
+    #[tokio::test]
+    async fn processing_keeps_the_content_type_and_metadata() {
+        let mock = MockR2::start().await;
+        let client = mock.client().await;
+        let options = r2_client::UploadOptions {
+            metadata: [("owner".to_string(), "finance".to_string())].into(),
+            content_type: Some("text/csv".into()),
+            cache_control: Some("max-age=60".into()),
+            ..Default::default()
+        };
+        let csv = Bytes::from_static(b"month,total\njan,10\n");
+        client
+            .upload_object_with_options("reports/jan.csv", csv.clone(), &options)
+            .await
+            .unwrap();
+        let key = test_support::key("Processor");
+        let plain = crypto::PgpHandler::new();
+        let encrypting = test_support::handler(&[&key], &[&key]);
+        let decrypting = test_support::handler(&[], &[&key]);
+
+        // Unencrypted edits keep the source's Content-Type and metadata
+        let result = process_object(
+            &client,
+            &plain,
+            "reports/jan.csv",
+            "copy/jan.csv".into(),
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(result["copied"], true);
+        let head = client.head_object("copy/jan.csv").await.unwrap();
+        assert_eq!(head.content_type.as_deref(), Some("text/csv"));
+        assert_eq!(head.metadata["owner"], "finance");
+
+        // Encrypted copies are stored as ciphertext under a .pgp key
+        let result = process_object(
+            &client,
+            &encrypting,
+            "reports/jan.csv",
+            "secure/jan.csv".into(),
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(result["dest_key"], "secure/jan.csv.pgp");
+        let head = client.head_object("secure/jan.csv.pgp").await.unwrap();
+        assert_eq!(
+            head.content_type.as_deref(),
+            Some(r2_client::PGP_CONTENT_TYPE)
+        );
+        assert_eq!(head.metadata["owner"], "finance");
+        assert_eq!(head.cache_control.as_deref(), Some("max-age=60"));
+
+        // Decrypting back gets a Content-Type from the plaintext key
+        process_object(
+            &client,
+            &decrypting,
+            "secure/jan.csv.pgp",
+            "restored/jan.csv".into(),
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+        let (data, head) = client
+            .download_object_with_metadata("restored/jan.csv")
+            .await
+            .unwrap();
+        assert_eq!(data, csv);
+        assert_eq!(head.content_type.as_deref(), Some("text/csv"));
+        assert_eq!(head.metadata["owner"], "finance");
+        assert!(!head.metadata.contains_key(r2_client::ENCRYPTED_METADATA));
+
+        // An explicit Content-Type wins
+        let result = process_object(
+            &client,
+            &decrypting,
+            "secure/jan.csv.pgp",
+            "restored/jan.txt".into(),
+            None,
+            Some("text/plain".into()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(result["content_type"], "text/plain");
+        let head = client.head_object("restored/jan.txt").await.unwrap();
+        assert_eq!(head.content_type.as_deref(), Some("text/plain"));
+    }
+
+    #[tokio::test]
+    async fn directories_round_trip_through_upload_tar_and_untar() {
+        let key = test_support::key("Team");
+        let handler = test_support::handler(&[&key], &[&key]);
+        let source = TempDir::new();
+        let big: Vec<u8> = (0..3 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+        let files: [(&str, &[u8]); 4] = [
+            ("project/readme.md", b"# Project\n"),
+            ("project/src/main.rs", b"fn main() {}\n"),
+            ("project/empty.txt", b""),
+            ("project/data/blob.bin", &big),
+        ];
+        for (path, data) in files {
+            source.write(path, data);
+        }
+        let mock = MockR2::start().await;
+        let client = mock.client().await;
+
+        for (object, encryption) in [
+            ("backup/project.tar", None),
+            ("backup/project.tar.pgp", Some(&handler)),
+        ] {
+            let upload = tar_stream::upload_tar(
+                &client,
+                &source.path().join("project"),
+                object,
+                &r2_client::UploadOptions::default(),
+                multipart_upload::MIN_PART_SIZE,
+                2,
+                encryption,
+                &ConsoleProgress::new(0),
+            )
+            .await
+            .unwrap();
+            assert_eq!(upload.files, files.len());
+            let stored = mock.object(object).unwrap().data;
+            assert_eq!(stored.len() as u64, upload.stored_bytes);
+            assert_eq!(
+                encryption.is_some(),
+                crypto::PgpHandler::is_pgp_encrypted(&stored)
+            );
+
+            let dest = TempDir::new();
+            let result = download_untar(&client, &handler, object, dest.path())
+                .await
+                .unwrap();
+            assert_eq!(result["files"], files.len());
+            assert_eq!(result["decrypted"], encryption.is_some());
+            for (path, data) in files {
+                let relative = path.strip_prefix("project/").unwrap();
+                assert_eq!(
+                    fs::read(dest.path().join(relative)).unwrap(),
+                    data,
+                    "{}",
+                    path
+                );
+            }
+            // The partial download is cleaned up, leaving only the extracted tree
+            let mut entries: Vec<_> = fs::read_dir(dest.path())
+                .unwrap()
+                .map(|e| e.unwrap().file_name().into_string().unwrap())
+                .collect();
+            entries.sort();
+            assert_eq!(entries, ["data", "empty.txt", "readme.md", "src"]);
+        }
+    }
+
+    #[tokio::test]
+    async fn private_names_are_only_recoverable_through_the_sidecar() {
+        let team = test_support::key("Team");
+        let handler = test_support::handler(&[&team], &[&team]);
+        let mock = MockR2::start().await;
+        let client = mock.client().await;
+
+        let private = sidecar::PrivateMetadata {
+            key: "hr/Q3 salaries.xlsx.pgp".into(),
+            file_name: "Q3 salaries.xlsx".into(),
+            content_type: r2_client::guess_content_type("Q3 salaries.xlsx"),
+            metadata: [("owner".to_string(), "payroll".to_string())].into(),
+        };
+        let options = r2_client::UploadOptions {
+            metadata: private.metadata.clone(),
+            ..Default::default()
+        }
+        .with_original_filename(&private.file_name);
+        let key = sidecar::opaque_key(&private.key);
+        client
+            .upload_object_with_options(
+                &key,
+                Bytes::from(handler.encrypt(b"salaries").unwrap()),
+                &sidecar::private_upload_options(&options),
+            )
+            .await
+            .unwrap();
+        sidecar::upload_sidecar(&client, &handler, &key, &private)
+            .await
+            .unwrap();
+
+        // Nothing stored in the clear gives the name away
+        assert!(key.starts_with("hr/") && key.ends_with(".pgp"));
+        for stored in mock.keys() {
+            assert!(!stored.contains("salaries"), "{}", stored);
+        }
+        let head = client.head_object(&key).await.unwrap();
+        assert_eq!(head.original_filename(), None);
+        assert!(!head.metadata.contains_key("owner"));
+        assert_ne!(
+            head.content_type,
+            r2_client::guess_content_type("Q3 salaries.xlsx")
+        );
+
+        let dest = TempDir::new();
+        let result = download_large(
+            &client,
+            &handler,
+            &key,
+            Some(dest.path().to_path_buf()),
+            false,
+            false,
+            head.content_length.unwrap(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(result["private_name"], "hr/Q3 salaries.xlsx.pgp");
+        assert_eq!(result["metadata"]["owner"], "payroll");
+        assert_eq!(
+            fs::read(dest.path().join("Q3 salaries.xlsx")).unwrap(),
+            b"salaries"
+        );
+
+        // Without the secret key the sidecar stays sealed
+        let outsider = test_support::handler(&[], &[&test_support::key("Outsider")]);
+        assert!(sidecar::fetch_sidecar(&client, &outsider, &key)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn credentials_that_fail_the_check_are_not_saved() {
+        let mock = MockR2::start().await;
+        mock.expire_access_key("revoked-key");
+        let dir = TempDir::new();
+        let path = dir.path().join("config.json");
+        let config = config::Config {
+            r2: config::R2Config {
+                access_key_id: "old-key".into(),
+                secret_access_key: "old-secret".into(),
+                account_id: "test-account".into(),
+                bucket_name: "bucket".into(),
+                endpoint: Some(mock.endpoint().to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        config.save_to_file(&path).unwrap();
+        let original = fs::read(&path).unwrap();
+        let backup = dir.path().join("config.json.bak");
+        let rotate = |access_key_id: &str, force| {
+            set_credentials(
+                &path,
+                None,
+                Some(access_key_id.to_string()),
+                Some(format!("{}-secret", access_key_id)),
+                None,
+                None,
+                force,
+            )
+        };
+
+        let error = rotate("revoked-key", false).await.unwrap_err();
+        assert!(
+            format!("{:#}", error).contains("left unchanged"),
+            "{:#}",
+            error
+        );
+        assert_eq!(fs::read(&path).unwrap(), original);
+        assert!(!backup.exists());
+
+        // --force saves them anyway, keeping the old file
+        let result = rotate("revoked-key", true).await.unwrap();
+        assert_eq!(result["validated"], false);
+        assert_eq!(fs::read(&backup).unwrap(), original);
+        let saved = config::Config::from_file(&path).unwrap();
+        assert_eq!(saved.r2.access_key_id, "revoked-key");
+        assert_eq!(saved.r2.secret_access_key, "revoked-key-secret");
+
+        let result = rotate("new-key", false).await.unwrap();
+        assert_eq!(result["validated"], true);
+        let saved = config::Config::from_file(&path).unwrap();
+        assert_eq!(saved.r2.access_key_id, "new-key");
+        assert_eq!(saved.r2.endpoint.as_deref(), Some(mock.endpoint()));
+    }
 }
@@ -0,0 +1,106 @@
+use std::sync::OnceLock;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+// Reservations are counted in KiB, since a semaphore hands out at most u32::MAX permits at once
+const UNIT: u64 = 1024;
+
+// Set from --max-memory at startup; unset means no limit
+static BUDGET: OnceLock<MemoryBudget> = OnceLock::new();
+
+/// A limit on the bytes held in part buffers by all transfers together. Each part
+/// reserves its size before it is buffered and gives it back once it has been sent, so
+/// parallel transfers wait for each other instead of multiplying memory use.
+struct MemoryBudget {
+    semaphore: Semaphore,
+    units: u32,
+}
+
+impl MemoryBudget {
+    fn new(bytes: u64) -> Self {
+        let units = (bytes / UNIT).clamp(1, u64::from(u32::MAX)) as u32;
+        Self {
+            semaphore: Semaphore::new(units as usize),
+            units,
+        }
+    }
+
+    async fn reserve(&'static self, bytes: usize) -> MemoryReservation {
+        let units = (bytes as u64)
+            .div_ceil(UNIT)
+            .clamp(1, u64::from(self.units)) as u32;
+        MemoryReservation {
+            // The semaphore is never closed, so this only fails in theory
+            _permit: self.semaphore.acquire_many(units).await.ok(),
+        }
+    }
+}
+
+/// Limit the part buffers of all transfers to `bytes` in total. Only the first call has
+/// an effect.
+pub fn set_memory_budget(bytes: u64) {
+    let _ = BUDGET.set(MemoryBudget::new(bytes));
+}
+
+/// Bytes reserved from the budget; dropping it gives them back.
+pub struct MemoryReservation {
+    _permit: Option<SemaphorePermit<'static>>,
+}
+
+/// Wait until `bytes` fit in the budget and reserve them. Returns at once when there is
+/// no budget. A reservation larger than the whole budget waits until nothing else is
+/// reserved and then goes ahead alone, so an oversized part can't wait forever.
+pub async fn reserve(bytes: usize) -> MemoryReservation {
+    let Some(budget) = BUDGET.get() else {
+        return MemoryReservation { _permit: None };
+    };
+    budget.reserve(bytes).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::future::join_all;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    const MIB: usize = 1024 * 1024;
+
+    #[tokio::test]
+    async fn buffered_bytes_stay_within_the_budget() {
+        // The process-wide budget would throttle every other test, so this one has its own
+        let budget: &'static MemoryBudget = Box::leak(Box::new(MemoryBudget::new(10 * MIB as u64)));
+        let held = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+
+        // Eight workers sending 3 MiB parts would hold 24 MiB without the budget
+        let worker = |part_size: usize| {
+            let (held, peak) = (&held, &peak);
+            async move {
+                for _ in 0..4 {
+                    let _reservation = budget.reserve(part_size).await;
+                    let now = held.fetch_add(part_size, Ordering::SeqCst) + part_size;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+                    held.fetch_sub(part_size, Ordering::SeqCst);
+                }
+            }
+        };
+        join_all((0..8).map(|_| worker(3 * MIB))).await;
+        let peak_bytes = peak.load(Ordering::SeqCst);
+        assert!(
+            peak_bytes <= 10 * MIB,
+            "{} bytes buffered at once",
+            peak_bytes
+        );
+        assert_eq!(
+            peak_bytes,
+            9 * MIB,
+            "three parts fit, so three should run at once"
+        );
+
+        // A part bigger than the whole budget still goes through, alone
+        peak.store(0, Ordering::SeqCst);
+        join_all([worker(12 * MIB), worker(3 * MIB), worker(3 * MIB)]).await;
+        assert_eq!(peak.load(Ordering::SeqCst), 12 * MIB);
+    }
+}
@@ -0,0 +1,169 @@
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// R2 bills requests in two classes: writes and listings are Class A, reads are Class B,
+// and deletes are free. Counting requests by kind is enough to estimate the cost of a run.
+
+/// Request and byte counters for an `R2Client`, updated as requests are sent.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    get: AtomicU64,
+    put: AtomicU64,
+    post: AtomicU64,
+    delete: AtomicU64,
+    list: AtomicU64,
+    head: AtomicU64,
+    retries: AtomicU64,
+    bytes_uploaded: AtomicU64,
+    bytes_downloaded: AtomicU64,
+}
+
+/// The kind of request, as R2 bills it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RequestKind {
+    Get,
+    Put,
+    Post, // Creating and completing multipart uploads
+    Delete,
+    List,
+    Head,
+}
+
+/// Counter values at one point in time.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct MetricsSnapshot {
+    pub get: u64,
+    pub put: u64,
+    pub post: u64,
+    pub delete: u64,
+    pub list: u64,
+    pub head: u64,
+    pub retries: u64,
+    pub bytes_uploaded: u64,
+    pub bytes_downloaded: u64,
+}
+
+impl Metrics {
+    pub fn record_request(&self, kind: RequestKind) {
+        let counter = match kind {
+            RequestKind::Get => &self.get,
+            RequestKind::Put => &self.put,
+            RequestKind::Post => &self.post,
+            RequestKind::Delete => &self.delete,
+            RequestKind::List => &self.list,
+            RequestKind::Head => &self.head,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_retry(&self) {
+        self.retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_uploaded(&self, bytes: u64) {
+        self.bytes_uploaded.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn record_downloaded(&self, bytes: u64) {
+        self.bytes_downloaded.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        MetricsSnapshot {
+            get: load(&self.get),
+            put: load(&self.put),
+            post: load(&self.post),
+            delete: load(&self.delete),
+            list: load(&self.list),
+            head: load(&self.head),
+            retries: load(&self.retries),
+            bytes_uploaded: load(&self.bytes_uploaded),
+            bytes_downloaded: load(&self.bytes_downloaded),
+        }
+    }
+}
+
+impl MetricsSnapshot {
+    /// Writes and listings, the more expensive operations.
+    pub fn class_a(&self) -> u64 {
+        self.put + self.post + self.list
+    }
+
+    /// Reads.
+    pub fn class_b(&self) -> u64 {
+        self.get + self.head
+    }
+
+    pub fn requests(&self) -> u64 {
+        self.class_a() + self.class_b() + self.delete
+    }
+
+    /// One line for the end of a CLI run.
+    pub fn summary(&self) -> String {
+        format!(
+            "{} requests ({} Class A, {} Class B, {} delete), {} uploaded, {} downloaded, {} retries",
+            self.requests(),
+            self.class_a(),
+            self.class_b(),
+            self.delete,
+            crate::progress::format_size(self.bytes_uploaded),
+            crate::progress::format_size(self.bytes_downloaded),
+            self.retries
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mock_r2::MockR2;
+    use bytes::Bytes;
+
+    #[tokio::test]
+    async fn counters_add_up_across_a_batch() {
+        let mock = MockR2::start().await;
+        let client = mock.client().await;
+        for name in ["a", "b", "c"] {
+            let key = format!("batch/{}.txt", name);
+            client
+                .upload_object(&key, Bytes::from(vec![b'x'; 1000]))
+                .await
+                .unwrap();
+        }
+        assert_eq!(client.metrics().snapshot().bytes_uploaded, 3000);
+        let keys = client.list_objects(Some("batch/")).await.unwrap();
+        let listed = client.metrics().snapshot().bytes_downloaded;
+        for key in &keys[..2] {
+            client.head_object(key).await.unwrap();
+            client.download_object(key).await.unwrap();
+        }
+        let downloaded = client.metrics().snapshot().bytes_downloaded - listed;
+        assert_eq!(downloaded, 2000);
+        client.delete_objects(&keys).await.unwrap();
+        client.delete_object("batch/a.txt").await.unwrap();
+
+        let stats = client.metrics().snapshot();
+        assert_eq!((stats.put, stats.list, stats.head, stats.get), (3, 1, 2, 2));
+        // The batch delete is a POST, the single one a DELETE
+        assert_eq!((stats.post, stats.delete, stats.retries), (1, 1, 0));
+        // Request and response bodies count too, such as the batch delete's XML
+        assert!(stats.bytes_uploaded > 3000);
+        assert_eq!(
+            (stats.class_a(), stats.class_b(), stats.requests()),
+            (5, 4, 10)
+        );
+
+        // A server error is retried and counted as another request
+        mock.fail_part(1);
+        let upload_id = client
+            .create_multipart_upload("batch/big.bin", &Default::default())
+            .await
+            .unwrap();
+        client
+            .upload_part("batch/big.bin", &upload_id, 1, Bytes::from_static(b"part"))
+            .await
+            .unwrap();
+        let stats = client.metrics().snapshot();
+        assert_eq!((stats.post, stats.put, stats.retries), (2, 5, 1));
+    }
+}
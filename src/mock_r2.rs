@@ -0,0 +1,795 @@
+use crate::r2_client::R2Client;
+use crate::test_support::TempDir;
+use md5::{Digest, Md5};
+use quick_xml::escape::{escape, unescape};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+const BUCKET: &str = "bucket";
+
+// Headers stored with an object on upload and sent back with it
+const STORED_HEADERS: &[&str] = &[
+    "cache-control",
+    "content-disposition",
+    "content-encoding",
+    "content-language",
+    "expires",
+];
+const LAST_MODIFIED: &str = "Wed, 01 Jan 2025 00:00:00 GMT";
+const LAST_MODIFIED_ISO: &str = "2025-01-01T00:00:00.000Z";
+
+#[derive(Clone, Debug, Default)]
+pub struct MockObject {
+    pub data: Vec<u8>,
+    pub metadata: BTreeMap<String, String>, // Without the x-amz-meta- prefix
+    pub content_type: Option<String>,
+    pub headers: BTreeMap<String, String>, // Other stored headers, by lowercase name
+    pub etag: Option<String>, // Served instead of the MD5 of data, as after silent corruption
+}
+
+impl MockObject {
+    fn etag(&self) -> String {
+        self.etag.clone().unwrap_or_else(|| etag(&self.data))
+    }
+}
+
+#[derive(Default)]
+struct MockUpload {
+    key: String,
+    object: MockObject, // Metadata and content type, filled in on completion
+    parts: BTreeMap<u32, Vec<u8>>,
+}
+
+#[derive(Default)]
+struct State {
+    objects: BTreeMap<String, MockObject>,
+    uploads: HashMap<String, MockUpload>, // By upload ID
+    next_upload: u32,
+    completed_uploads: Vec<String>, // Keys stored by completing a multipart upload
+    denied: HashSet<String>,        // Methods answered with 403 AccessDenied
+    expired_keys: HashSet<String>,  // Access key IDs answered with ExpiredToken
+    delays: HashMap<String, Duration>, // Wait before answering requests for these keys
+    stalled_parts: Vec<u32>,        // Part numbers whose next upload is never read
+    failing_parts: Vec<u32>,        // Part numbers whose next upload gets a 500
+    not_modified: usize,            // Conditional GETs answered 304
+    unavailable: bool,              // Every request is answered 503, as in an outage
+    undeletable: HashSet<String>,   // Keys a DeleteObjects batch refuses
+    requests: usize,
+    in_flight: usize,
+    max_in_flight: usize, // Most requests ever handled at once
+    versioned: bool,
+    delete_markers: BTreeMap<String, (String, MockObject)>, // Marker version ID, hidden object
+    next_marker: u32,
+}
+
+/// An in-memory stand-in for the parts of R2's S3 API that `R2Client` uses, served over
+/// plain HTTP on a local port so tests can run the real client against it. Signatures are
+/// not checked, only which access key signed, and listings are never paged.
+pub struct MockR2 {
+    state: Arc<Mutex<State>>,
+    endpoint: String,
+    cache: TempDir, // Sessions of its clients' transfers, apart from other tests'
+}
+
+impl MockR2 {
+    /// Start serving an empty bucket on a free local port.
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let state = Arc::new(Mutex::new(State::default()));
+        let server_state = state.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(stream, server_state.clone()));
+            }
+        });
+        Self {
+            state,
+            endpoint,
+            cache: TempDir::new(),
+        }
+    }
+
+    /// A client for the mock bucket.
+    pub async fn client(&self) -> R2Client {
+        R2Client::new(
+            "test-access-key".into(),
+            "test-secret-key".into(),
+            "test-account".into(),
+            BUCKET.into(),
+        )
+        .await
+        .unwrap()
+        .with_endpoint(&self.endpoint)
+        .with_cache_dir(self.cache.path())
+    }
+
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    pub fn put(&self, key: &str, data: &[u8]) {
+        self.put_with_metadata(key, data, &[]);
+    }
+
+    pub fn put_with_metadata(&self, key: &str, data: &[u8], metadata: &[(&str, &str)]) {
+        let object = MockObject {
+            data: data.to_vec(),
+            metadata: metadata
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            content_type: None,
+            headers: BTreeMap::new(),
+            etag: None,
+        };
+        self.state
+            .lock()
+            .unwrap()
+            .objects
+            .insert(key.to_string(), object);
+    }
+
+    /// Flip a byte of a stored object while it keeps the ETag of its original content.
+    pub fn corrupt(&self, key: &str) {
+        let mut state = self.state.lock().unwrap();
+        let object = state.objects.get_mut(key).unwrap();
+        object.etag = Some(object.etag());
+        object.data[0] ^= 0xff;
+    }
+
+    pub fn object(&self, key: &str) -> Option<MockObject> {
+        self.state.lock().unwrap().objects.get(key).cloned()
+    }
+
+    pub fn keys(&self) -> Vec<String> {
+        self.state.lock().unwrap().objects.keys().cloned().collect()
+    }
+
+    /// Keys stored by completing a multipart upload, in completion order.
+    pub fn completed_uploads(&self) -> Vec<String> {
+        self.state.lock().unwrap().completed_uploads.clone()
+    }
+
+    /// Keys of the multipart uploads neither completed nor aborted yet.
+    pub fn open_uploads(&self) -> Vec<String> {
+        let state = self.state.lock().unwrap();
+        let mut keys: Vec<String> = state.uploads.values().map(|u| u.key.clone()).collect();
+        keys.sort();
+        keys
+    }
+
+    /// How many conditional GETs were answered 304 Not Modified.
+    pub fn not_modified_count(&self) -> usize {
+        self.state.lock().unwrap().not_modified
+    }
+
+    /// Stop reading the next upload of part `part_number` after its headers, leaving the
+    /// connection open, as a half-open connection would.
+    pub fn stall_part(&self, part_number: u32) {
+        self.state.lock().unwrap().stalled_parts.push(part_number);
+    }
+
+    /// Answer the next upload of part `part_number` with 500 InternalError.
+    pub fn fail_part(&self, part_number: u32) {
+        self.state.lock().unwrap().failing_parts.push(part_number);
+    }
+
+    /// Refuse every request with this method, as a token without that permission would.
+    pub fn deny(&self, method: &str) {
+        self.state.lock().unwrap().denied.insert(method.to_string());
+    }
+
+    /// Refuse to delete `key` in a DeleteObjects batch, reporting it as an error in the result.
+    pub fn refuse_delete(&self, key: &str) {
+        self.state
+            .lock()
+            .unwrap()
+            .undeletable
+            .insert(key.to_string());
+    }
+
+    /// Answer every request from now on with 503 ServiceUnavailable, as an outage would.
+    pub fn go_down(&self) {
+        self.state.lock().unwrap().unavailable = true;
+    }
+
+    /// How many requests have been answered.
+    pub fn request_count(&self) -> usize {
+        self.state.lock().unwrap().requests
+    }
+
+    /// The most requests that were being handled at the same time.
+    pub fn max_concurrent_requests(&self) -> usize {
+        self.state.lock().unwrap().max_in_flight
+    }
+
+    /// Answer every request for `key` only after `delay`, as a slow or overloaded server would.
+    /// An empty key delays requests to the bucket itself, such as listings and batch deletes.
+    pub fn delay(&self, key: &str, delay: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.delays.insert(key.to_string(), delay);
+    }
+
+    /// Keep deleted objects behind a delete marker, as a versioned bucket does.
+    pub fn enable_versioning(&self) {
+        self.state.lock().unwrap().versioned = true;
+    }
+
+    /// Reject requests signed with `access_key_id` as R2 does once a token has expired.
+    pub fn expire_access_key(&self, access_key_id: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.expired_keys.insert(access_key_id.to_string());
+    }
+}
+
+struct Request {
+    method: String,
+    target: String,
+    headers: HashMap<String, String>, // Lowercase names
+    body: Vec<u8>,
+}
+
+struct Reply {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Reply {
+    fn new(status: u16, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: body.into(),
+        }
+    }
+
+    fn error(status: u16, code: &str) -> Self {
+        Self::new(
+            status,
+            format!(
+                "<Error><Code>{}</Code><Message>{}</Message></Error>",
+                code, code
+            ),
+        )
+    }
+
+    fn header(mut self, name: &str, value: impl Into<String>) -> Self {
+        self.headers.push((name.to_string(), value.into()));
+        self
+    }
+}
+
+async fn serve(mut stream: TcpStream, state: Arc<Mutex<State>>) {
+    let mut buffer = Vec::new();
+    while let Some(request) = read_request(&mut stream, &mut buffer, &state).await {
+        let head_only = request.method == "HEAD";
+        let path = request.target.split('?').next().unwrap_or_default();
+        let delay = {
+            let mut state = state.lock().unwrap();
+            state.in_flight += 1;
+            state.max_in_flight = state.max_in_flight.max(state.in_flight);
+            let key = object_key(path).unwrap_or_default();
+            state.delays.get(&key).copied()
+        };
+        if let Some(delay) = delay {
+            tokio::time::sleep(delay).await;
+        }
+        let reply = handle(&state, request);
+        state.lock().unwrap().in_flight -= 1;
+        let mut out = format!("HTTP/1.1 {} Mock\r\n", reply.status);
+        for (name, value) in &reply.headers {
+            out.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        if !reply
+            .headers
+            .iter()
+            .any(|(name, _)| name == "content-length")
+        {
+            out.push_str(&format!("content-length: {}\r\n", reply.body.len()));
+        }
+        out.push_str("\r\n");
+        let mut out = out.into_bytes();
+        if !head_only {
+            out.extend_from_slice(&reply.body);
+        }
+        if stream.write_all(&out).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// The next request on the connection, or None once the client has closed it.
+async fn read_request(
+    stream: &mut TcpStream,
+    buffer: &mut Vec<u8>,
+    state: &Mutex<State>,
+) -> Option<Request> {
+    let head_end = loop {
+        if let Some(end) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+            break end;
+        }
+        let mut chunk = [0u8; 8192];
+        let n = stream.read(&mut chunk).await.ok().filter(|&n| n > 0)?;
+        buffer.extend_from_slice(&chunk[..n]);
+    };
+    let head = String::from_utf8_lossy(&buffer[..head_end]).into_owned();
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next()?.split(' ');
+    let method = request_line.next()?.to_string();
+    let target = request_line.next()?.to_string();
+    let headers: HashMap<String, String> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_string()))
+        .collect();
+
+    if take_stall(state, &target) {
+        std::future::pending::<()>().await;
+    }
+
+    let length: usize = headers
+        .get("content-length")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0);
+    let body_start = head_end + 4;
+    while buffer.len() < body_start + length {
+        let mut chunk = [0u8; 65536];
+        let n = stream.read(&mut chunk).await.ok().filter(|&n| n > 0)?;
+        buffer.extend_from_slice(&chunk[..n]);
+    }
+    let body = buffer[body_start..body_start + length].to_vec();
+    buffer.drain(..body_start + length);
+
+    Some(Request {
+        method,
+        target,
+        headers,
+        body,
+    })
+}
+
+/// Whether `target` uploads a part that should stall, which it then does only once.
+fn take_stall(state: &Mutex<State>, target: &str) -> bool {
+    let part_number = target
+        .split(['?', '&'])
+        .find_map(|pair| pair.strip_prefix("partNumber=")?.parse::<u32>().ok());
+    let mut state = state.lock().unwrap();
+    let Some(index) = part_number.and_then(|n| state.stalled_parts.iter().position(|&p| p == n))
+    else {
+        return false;
+    };
+    state.stalled_parts.remove(index);
+    true
+}
+
+fn handle(state: &Mutex<State>, request: Request) -> Reply {
+    let mut state = state.lock().unwrap();
+    state.requests += 1;
+    if state.unavailable {
+        return Reply::error(503, "ServiceUnavailable");
+    }
+    if state.denied.contains(&request.method) {
+        return Reply::error(403, "AccessDenied");
+    }
+    // Authorization: AWS4-HMAC-SHA256 Credential=<access key>/<scope>, ...
+    let access_key = request
+        .headers
+        .get("authorization")
+        .and_then(|value| value.split_once("Credential=")?.1.split_once('/'))
+        .map(|(access_key, _)| access_key);
+    if access_key.is_some_and(|key| state.expired_keys.contains(key)) {
+        return Reply::error(400, "ExpiredToken");
+    }
+
+    let (path, query) = request
+        .target
+        .split_once('?')
+        .unwrap_or((request.target.as_str(), ""));
+    let query: HashMap<String, String> = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (name.to_string(), decode(value))
+        })
+        .collect();
+    if path != format!("/{}", BUCKET) && !path.starts_with(&format!("/{}/", BUCKET)) {
+        return Reply::error(404, "NoSuchBucket");
+    }
+    let key = object_key(path);
+
+    let upload_id = query.get("uploadId").cloned();
+    match (request.method.as_str(), key) {
+        ("HEAD", None) => Reply::new(200, Vec::new()),
+        ("GET", None) if query.contains_key("list-type") => list(&state, &query),
+        ("GET", None) if query.contains_key("versions") => list_versions(&state, &query),
+        ("POST", None) if query.contains_key("delete") => delete_batch(&mut state, &request),
+        ("POST", Some(key)) if query.contains_key("uploads") => {
+            create_upload(&mut state, key, &request)
+        }
+        (_, Some(_)) if upload_id.is_some() => {
+            let upload_id = upload_id.unwrap_or_default();
+            let part_number = query.get("partNumber").and_then(|n| n.parse().ok());
+            let failing = part_number
+                .and_then(|n: u32| state.failing_parts.iter().position(|&p| p == n))
+                .filter(|_| request.method == "PUT");
+            if let Some(index) = failing {
+                state.failing_parts.remove(index);
+                return Reply::error(500, "InternalError");
+            }
+            let Some(upload) = state.uploads.get_mut(&upload_id) else {
+                return Reply::error(404, "NoSuchUpload");
+            };
+            match request.method.as_str() {
+                "PUT" => {
+                    let tag = etag(&request.body);
+                    upload.parts.insert(part_number.unwrap_or(0), request.body);
+                    Reply::new(200, Vec::new()).header("etag", tag)
+                }
+                "GET" => list_parts(upload),
+                "POST" => complete_upload(&mut state, &upload_id, &request),
+                "DELETE" => {
+                    state.uploads.remove(&upload_id);
+                    Reply::new(204, Vec::new())
+                }
+                _ => Reply::error(501, "NotImplemented"),
+            }
+        }
+        ("GET" | "HEAD", Some(key)) => get(&mut state, &key, &request),
+        ("PUT", Some(key)) if query.is_empty() => put(&mut state, key, request),
+        ("DELETE", Some(key)) if query.is_empty() => delete(&mut state, key),
+        ("DELETE", Some(key)) if query.contains_key("versionId") => {
+            remove_marker(&mut state, &key, &query["versionId"])
+        }
+        _ => Reply::error(501, "NotImplemented"),
+    }
+}
+
+fn list(state: &State, query: &HashMap<String, String>) -> Reply {
+    let prefix = query.get("prefix").map(String::as_str).unwrap_or("");
+    let delimiter = query.get("delimiter").filter(|d| !d.is_empty());
+    let mut contents = String::new();
+    let mut common_prefixes = Vec::new();
+    for (key, object) in state.objects.range(prefix.to_string()..) {
+        let Some(rest) = key.strip_prefix(prefix) else {
+            break;
+        };
+        if let Some(delimiter) = delimiter {
+            if let Some(end) = rest.find(delimiter.as_str()) {
+                let common = format!("{}{}", prefix, &rest[..end + delimiter.len()]);
+                if !common_prefixes.contains(&common) {
+                    common_prefixes.push(common);
+                }
+                continue;
+            }
+        }
+        contents.push_str(&format!(
+            "<Contents><Key>{}</Key><Size>{}</Size><ETag>{}</ETag>\
+             <LastModified>{}</LastModified></Contents>",
+            escape(key.as_str()),
+            object.data.len(),
+            escape(object.etag().as_str()),
+            LAST_MODIFIED_ISO
+        ));
+    }
+    for common in common_prefixes {
+        contents.push_str(&format!(
+            "<CommonPrefixes><Prefix>{}</Prefix></CommonPrefixes>",
+            escape(common.as_str())
+        ));
+    }
+    Reply::new(
+        200,
+        format!(
+            "<ListBucketResult><Name>{}</Name><Prefix>{}</Prefix>\
+             <IsTruncated>false</IsTruncated>{}</ListBucketResult>",
+            BUCKET,
+            escape(prefix),
+            contents
+        ),
+    )
+}
+
+/// Live objects as their only version, and deleted ones as a marker over the old version.
+/// Unversioned buckets report every object as version "null".
+fn list_versions(state: &State, query: &HashMap<String, String>) -> Reply {
+    let prefix = query.get("prefix").map(String::as_str).unwrap_or("");
+    let version = |key: &str, id: &str, latest: bool, object: &MockObject| {
+        format!(
+            "<Version><Key>{}</Key><VersionId>{}</VersionId><IsLatest>{}</IsLatest>\
+             <Size>{}</Size><LastModified>{}</LastModified></Version>",
+            escape(key),
+            id,
+            latest,
+            object.data.len(),
+            LAST_MODIFIED_ISO
+        )
+    };
+    let version_id = |object: &MockObject| match state.versioned {
+        true => object.etag().trim_matches('"').to_string(),
+        false => "null".to_string(),
+    };
+    let mut versions = String::new();
+    for (key, object) in state.objects.range(prefix.to_string()..) {
+        if !key.starts_with(prefix) {
+            break;
+        }
+        versions.push_str(&version(key, &version_id(object), true, object));
+    }
+    for (key, (marker, object)) in state.delete_markers.range(prefix.to_string()..) {
+        if !key.starts_with(prefix) {
+            break;
+        }
+        versions.push_str(&format!(
+            "<DeleteMarker><Key>{}</Key><VersionId>{}</VersionId><IsLatest>true</IsLatest>\
+             <LastModified>{}</LastModified></DeleteMarker>",
+            escape(key.as_str()),
+            marker,
+            LAST_MODIFIED_ISO
+        ));
+        versions.push_str(&version(key, &version_id(object), false, object));
+    }
+    Reply::new(
+        200,
+        format!(
+            "<ListVersionsResult><Name>{}</Name><IsTruncated>false</IsTruncated>{}\
+             </ListVersionsResult>",
+            BUCKET, versions
+        ),
+    )
+}
+
+fn get(state: &mut State, key: &str, request: &Request) -> Reply {
+    let Some(object) = state.objects.get(key) else {
+        return Reply::error(404, "NoSuchKey");
+    };
+    if request.headers.get("if-none-match") == Some(&object.etag()) {
+        let etag = object.etag();
+        state.not_modified += 1;
+        return Reply::new(304, Vec::new()).header("etag", etag);
+    }
+    let size = object.data.len();
+    let range = request
+        .headers
+        .get("range")
+        .and_then(|range| range.strip_prefix("bytes="))
+        .and_then(|range| range.split_once('-'))
+        .and_then(|(start, end)| {
+            let start: usize = start.parse().ok()?;
+            let end = end.parse().map_or(size, |end: usize| (end + 1).min(size));
+            Some((start, end))
+        })
+        // A range that depends on an outdated ETag gets the whole current object
+        .filter(|_| {
+            request
+                .headers
+                .get("if-range")
+                .is_none_or(|etag| *etag == object.etag())
+        });
+
+    let mut reply = match range {
+        Some((start, _)) if start >= size => return Reply::error(416, "InvalidRange"),
+        Some((start, end)) => Reply::new(206, object.data[start..end].to_vec()).header(
+            "content-range",
+            format!("bytes {}-{}/{}", start, end - 1, size),
+        ),
+        None => Reply::new(200, object.data.clone()),
+    };
+    reply = reply
+        .header("etag", object.etag())
+        .header("last-modified", LAST_MODIFIED);
+    if let Some(content_type) = &object.content_type {
+        reply = reply.header("content-type", content_type.clone());
+    }
+    for (name, value) in &object.headers {
+        reply = reply.header(name, value.clone());
+    }
+    for (name, value) in &object.metadata {
+        reply = reply.header(&format!("x-amz-meta-{}", name), value.clone());
+    }
+    if request.method == "HEAD" {
+        let length = reply.body.len().to_string();
+        reply = reply.header("content-length", length);
+    }
+    reply
+}
+
+fn put(state: &mut State, key: String, request: Request) -> Reply {
+    let object = match request.headers.get("x-amz-copy-source") {
+        Some(source) => {
+            let source = decode(source);
+            let source = source
+                .trim_start_matches('/')
+                .strip_prefix(&format!("{}/", BUCKET))
+                .unwrap_or_default();
+            match state.objects.get(source) {
+                Some(object) => object.clone(),
+                None => return Reply::error(404, "NoSuchKey"),
+            }
+        }
+        None => {
+            let data = request.body.clone();
+            object_from_headers(&request, data)
+        }
+    };
+    let tag = object.etag();
+    state.delete_markers.remove(&key);
+    state.objects.insert(key, object);
+    Reply::new(200, Vec::new()).header("etag", tag)
+}
+
+/// The key of the object a request path names, if it names one.
+fn object_key(path: &str) -> Option<String> {
+    let key = path.strip_prefix(&format!("/{}/", BUCKET))?;
+    Some(decode(key)).filter(|key| !key.is_empty())
+}
+
+fn create_upload(state: &mut State, key: String, request: &Request) -> Reply {
+    state.next_upload += 1;
+    let upload_id = format!("upload-{}", state.next_upload);
+    let upload = MockUpload {
+        key: key.clone(),
+        object: object_from_headers(request, Vec::new()),
+        parts: BTreeMap::new(),
+    };
+    state.uploads.insert(upload_id.clone(), upload);
+    Reply::new(
+        200,
+        format!(
+            "<InitiateMultipartUploadResult><Bucket>{}</Bucket><Key>{}</Key>\
+             <UploadId>{}</UploadId></InitiateMultipartUploadResult>",
+            BUCKET,
+            escape(key.as_str()),
+            upload_id
+        ),
+    )
+}
+
+fn list_parts(upload: &MockUpload) -> Reply {
+    let mut parts = String::new();
+    for (number, data) in &upload.parts {
+        parts.push_str(&format!(
+            "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag><Size>{}</Size></Part>",
+            number,
+            escape(etag(data).as_str()),
+            data.len()
+        ));
+    }
+    Reply::new(
+        200,
+        format!(
+            "<ListPartsResult><IsTruncated>false</IsTruncated>{}</ListPartsResult>",
+            parts
+        ),
+    )
+}
+
+/// Join the parts the completion request lists, in its order, into the object.
+fn complete_upload(state: &mut State, upload_id: &str, request: &Request) -> Reply {
+    let Some(mut upload) = state.uploads.remove(upload_id) else {
+        return Reply::error(404, "NoSuchUpload");
+    };
+    let body = String::from_utf8_lossy(&request.body);
+    for part in body.split("<PartNumber>").skip(1) {
+        let number = part
+            .split_once("</PartNumber>")
+            .and_then(|(n, _)| n.parse().ok());
+        match number.and_then(|n: u32| upload.parts.get(&n)) {
+            Some(data) => upload.object.data.extend_from_slice(data),
+            None => return Reply::error(400, "InvalidPart"),
+        }
+    }
+    let key = upload.key.clone();
+    state.objects.insert(key.clone(), upload.object);
+    state.completed_uploads.push(key.clone());
+    Reply::new(
+        200,
+        format!(
+            "<CompleteMultipartUploadResult><Key>{}</Key></CompleteMultipartUploadResult>",
+            escape(key.as_str())
+        ),
+    )
+}
+
+/// Remove `key`, or in a versioned bucket hide it behind a new delete marker.
+fn delete(state: &mut State, key: String) -> Reply {
+    let Some(object) = state.objects.remove(&key) else {
+        return Reply::new(204, Vec::new());
+    };
+    if !state.versioned {
+        return Reply::new(204, Vec::new());
+    }
+    state.next_marker += 1;
+    let marker = format!("marker-{}", state.next_marker);
+    state.delete_markers.insert(key, (marker.clone(), object));
+    Reply::new(204, Vec::new())
+        .header("x-amz-delete-marker", "true")
+        .header("x-amz-version-id", marker)
+}
+
+/// Remove the delete marker `version_id` from `key`, making the hidden version current.
+fn remove_marker(state: &mut State, key: &str, version_id: &str) -> Reply {
+    match state.delete_markers.get(key) {
+        Some((marker, _)) if marker == version_id => {
+            let (_, object) = state.delete_markers.remove(key).unwrap_or_default();
+            state.objects.insert(key.to_string(), object);
+            Reply::new(204, Vec::new())
+                .header("x-amz-delete-marker", "true")
+                .header("x-amz-version-id", version_id)
+        }
+        _ => Reply::error(404, "NoSuchVersion"),
+    }
+}
+
+fn delete_batch(state: &mut State, request: &Request) -> Reply {
+    let body = String::from_utf8_lossy(&request.body);
+    let mut result = String::from("<DeleteResult>");
+    for part in body.split("<Key>").skip(1) {
+        let Some((key, _)) = part.split_once("</Key>") else {
+            continue;
+        };
+        let key = unescape(key).map(|k| k.into_owned()).unwrap_or_default();
+        if state.denied.contains("DELETE") || state.undeletable.contains(&key) {
+            result.push_str(&format!(
+                "<Error><Key>{}</Key><Code>AccessDenied</Code><Message>Access Denied</Message></Error>",
+                escape(key.as_str())
+            ));
+        } else {
+            state.objects.remove(&key);
+        }
+    }
+    result.push_str("</DeleteResult>");
+    Reply::new(200, result)
+}
+
+/// An object holding `data`, with the metadata and content type `request` sets.
+fn object_from_headers(request: &Request, data: Vec<u8>) -> MockObject {
+    MockObject {
+        metadata: request
+            .headers
+            .iter()
+            .filter_map(|(name, value)| {
+                Some((name.strip_prefix("x-amz-meta-")?.to_string(), value.clone()))
+            })
+            .collect(),
+        content_type: request.headers.get("content-type").cloned(),
+        headers: STORED_HEADERS
+            .iter()
+            .filter_map(|name| Some((name.to_string(), request.headers.get(*name)?.clone())))
+            .collect(),
+        etag: None,
+        data,
+    }
+}
+
+fn etag(data: &[u8]) -> String {
+    format!("\"{}\"", hex::encode(Md5::digest(data)))
+}
+
+/// Percent-decode a path or query value; S3 clients encode spaces as %20, not +.
+fn decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
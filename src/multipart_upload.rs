@@ -0,0 +1,845 @@
+use crate::chunked::{self, ChunkedEncryptor};
+use crate::crypto::PgpHandler;
+use crate::error::R2Error;
+use crate::memory_budget::{self, MemoryReservation};
+use crate::progress::ProgressReporter;
+use crate::r2_client::{CompletedPart, R2Client, UploadOptions};
+use crate::transfer;
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use futures_util::future::{BoxFuture, FutureExt};
+use futures_util::stream::{self, FuturesUnordered, StreamExt};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// R2 rejects parts smaller than this, except the last one
+pub const MIN_PART_SIZE: usize = 5 * 1024 * 1024;
+
+pub const DEFAULT_PART_SIZE: usize = 8 * 1024 * 1024;
+
+// Parts of one upload sent at the same time, see --part-concurrency
+pub const DEFAULT_PART_CONCURRENCY: usize = 4;
+
+// Files at least this large are uploaded in parts by the CLI, so they can resume
+pub const MULTIPART_THRESHOLD: u64 = 100 * 1024 * 1024;
+
+// Uploads whose `MultipartUploader` was dropped before it finished or aborted, as when a
+// deadline cancels the transfer: bucket URL, key and upload ID. Dropping can't wait for a
+// request, so `abort_abandoned` aborts them afterwards.
+static ABANDONED: Mutex<Vec<(String, String, String)>> = Mutex::new(Vec::new());
+
+/// Progress of a resumable file upload, saved after every part so an interrupted
+/// upload picks up where it stopped. Each part records the exact byte range it was
+/// read from, so a resume re-reads the same bytes whatever the part size is now.
+/// Parts finish in any order, so the recorded ones may have gaps between them.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UploadSession {
+    pub key: String,
+    pub upload_id: String,
+    pub file_size: u64,
+    pub modified: u64, // File mtime in seconds, to notice the file changing between runs
+    pub part_size: usize,
+    pub parts: Vec<SessionPart>,
+    #[serde(default)]
+    pub local_path: Option<PathBuf>, // The file being uploaded, so resume-all can find it
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SessionPart {
+    pub part_number: u32,
+    pub offset: u64,
+    pub size: u64,
+    pub sha256: String,
+    pub etag: String,
+}
+
+impl UploadSession {
+    /// Where the session for uploading `path` to `key` is kept, in `client`'s cache folder.
+    pub fn path_for(client: &R2Client, path: &Path, key: &str) -> Result<PathBuf> {
+        let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        let mut hasher = Sha256::new();
+        hasher.update(path.to_string_lossy().as_bytes());
+        hasher.update([0]);
+        hasher.update(key.as_bytes());
+        Ok(Self::sessions_dir(client)?.join(format!("{}.json", hex::encode(hasher.finalize()))))
+    }
+
+    fn sessions_dir(client: &R2Client) -> Result<PathBuf> {
+        Ok(client.cache_dir()?.join("uploads"))
+    }
+
+    /// Every session saved by `client`'s uploads, with the file it is stored in.
+    /// Unreadable files are skipped.
+    pub fn list_all(client: &R2Client) -> Result<Vec<(PathBuf, Self)>> {
+        let Ok(entries) = fs::read_dir(Self::sessions_dir(client)?) else {
+            return Ok(Vec::new());
+        };
+        let mut sessions: Vec<(PathBuf, Self)> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .filter_map(|path| Some((path.clone(), Self::load(&path)?)))
+            .collect();
+        sessions.sort_by(|a, b| a.1.key.cmp(&b.1.key));
+        Ok(sessions)
+    }
+
+    /// Delete the saved session for `upload_id`, if there is one, once the upload has been
+    /// aborted so resume-all doesn't try to pick it up again.
+    pub fn remove_for_upload(client: &R2Client, upload_id: &str) -> Result<()> {
+        for (session_path, session) in Self::list_all(client)? {
+            if session.upload_id == upload_id {
+                fs::remove_file(&session_path).context("Failed to remove upload session")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// True if `path` still has the size and modification time the session was started with.
+    pub fn matches_file(&self, path: &Path) -> bool {
+        fs::metadata(path).is_ok_and(|metadata| {
+            metadata.len() == self.file_size && transfer::modified_secs(&metadata) == self.modified
+        })
+    }
+
+    fn load(session_path: &Path) -> Option<Self> {
+        let content = fs::read_to_string(session_path).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    fn save(&self, session_path: &Path) -> Result<()> {
+        if let Some(parent) = session_path.parent() {
+            fs::create_dir_all(parent).context("Failed to create upload session folder")?;
+        }
+        fs::write(session_path, serde_json::to_string_pretty(self)?)
+            .context("Failed to save upload session")
+    }
+
+    /// Part number, offset and size of every part not uploaded yet. Part `n` starts at
+    /// `(n - 1) * part_size`. An empty file still has one, empty, part.
+    fn missing_parts(&self) -> Vec<(u32, u64, u64)> {
+        let part_size = self.part_size as u64;
+        let part_count = self.file_size.div_ceil(part_size).max(1);
+        (1..=part_count)
+            .map(|n| {
+                let offset = (n - 1) * part_size;
+                (n as u32, offset, (self.file_size - offset).min(part_size))
+            })
+            .filter(|(n, _, _)| !self.parts.iter().any(|p| p.part_number == *n))
+            .collect()
+    }
+}
+
+/// Uploads a stream of unknown length as a multipart upload, sending up to
+/// `concurrency` parts at once while the next one fills. Write data with `write`, then
+/// call `finish` to assemble the object, or `abort` to discard the parts already stored.
+/// Each part buffer is reserved from the memory budget until the part has been sent.
+///
+/// The parts in flight are driven by the uploader itself: a part is started as soon as it
+/// is queued, moved along on every `write`, and driven to completion whenever the uploader
+/// has to wait for memory or for a free slot.
+pub struct MultipartUploader<'a> {
+    client: &'a R2Client,
+    key: String,
+    upload_id: String,
+    part_size: usize,
+    concurrency: usize,
+    buffer: Vec<u8>,
+    reservation: Option<MemoryReservation>, // Held while `buffer` has data
+    in_flight: FuturesUnordered<BoxFuture<'a, Result<CompletedPart>>>,
+    parts: Vec<CompletedPart>,
+    part_count: u32, // Parts handed out so far, sent or in flight
+    uploaded: u64,
+    closed: bool, // Completed or aborted, so there is nothing to clean up on drop
+}
+
+impl<'a> MultipartUploader<'a> {
+    pub async fn start(
+        client: &'a R2Client,
+        key: &str,
+        options: &UploadOptions,
+        part_size: usize,
+        concurrency: usize,
+    ) -> Result<Self> {
+        let upload_id = client.create_multipart_upload(key, options).await?;
+        Ok(Self {
+            client,
+            key: key.to_string(),
+            upload_id,
+            part_size: part_size.max(MIN_PART_SIZE),
+            concurrency: concurrency.max(1),
+            buffer: Vec::new(),
+            reservation: None,
+            in_flight: FuturesUnordered::new(),
+            parts: Vec::new(),
+            part_count: 0,
+            uploaded: 0,
+            closed: false,
+        })
+    }
+
+    pub fn upload_id(&self) -> &str {
+        &self.upload_id
+    }
+
+    /// Bytes written so far, including any not yet sent as a part.
+    pub fn bytes_written(&self) -> u64 {
+        self.uploaded + self.buffer.len() as u64
+    }
+
+    pub async fn write(&mut self, mut data: &[u8]) -> Result<()> {
+        self.poll_in_flight()?;
+        while !data.is_empty() {
+            if self.reservation.is_none() {
+                self.reserve_buffer().await?;
+            }
+            let n = (self.part_size - self.buffer.len()).min(data.len());
+            self.buffer.extend_from_slice(&data[..n]);
+            data = &data[n..];
+            if self.buffer.len() == self.part_size {
+                self.send_part().await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Upload the remaining data and complete the upload, returning the object size. If
+    /// that fails the upload is aborted, so its parts don't linger in the bucket.
+    pub async fn finish(mut self) -> Result<u64> {
+        if let Err(e) = self.complete().await {
+            if let Err(abort_error) = self.abort().await {
+                tracing::warn!("Failed to abort multipart upload: {}", abort_error);
+            }
+            return Err(e);
+        }
+        self.closed = true;
+        Ok(self.uploaded)
+    }
+
+    async fn complete(&mut self) -> Result<()> {
+        // Every multipart upload needs at least one part, even if it is empty
+        if !self.buffer.is_empty() || self.part_count == 0 {
+            self.send_part().await?;
+        }
+        while let Some(part) = self.in_flight.next().await {
+            self.parts.push(part?);
+        }
+        // Parts finish in any order, but have to be listed in ascending order
+        self.parts.sort_by_key(|part| part.part_number);
+        self.client
+            .complete_multipart_upload(&self.key, &self.upload_id, &self.parts)
+            .await
+    }
+
+    /// Upload a local file in parts, up to `concurrency` at once, returning the number
+    /// of bytes stored.
+    ///
+    /// Without `encryption` the file's bytes are sent as they are, never touching the PGP
+    /// handler, and the upload is resumable through an `UploadSession`. On a re-run each
+    /// recorded part's range is re-read and checked against its checksum; parts that no
+    /// longer match are sent again under the same part number, so a resumed upload stores
+    /// exactly the bytes an uninterrupted one would. This is what makes files encrypted
+    /// beforehand (e.g. with gpg) safe to resume: they are just arbitrary binary data.
+    ///
+    /// With `encryption` the file is streamed through the chunked format instead. That
+    /// can't resume, since encrypting again never reproduces the same ciphertext, so a
+    /// failed encrypted upload is aborted.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn upload_file(
+        client: &'a R2Client,
+        path: &Path,
+        key: &str,
+        options: &UploadOptions,
+        part_size: usize,
+        concurrency: usize,
+        encryption: Option<&PgpHandler>,
+        progress: &dyn ProgressReporter,
+    ) -> Result<u64> {
+        match encryption {
+            Some(handler) => {
+                Self::upload_file_encrypted(
+                    client,
+                    path,
+                    key,
+                    options,
+                    part_size,
+                    concurrency,
+                    handler,
+                    progress,
+                )
+                .await
+            }
+            None => {
+                Self::upload_file_resumable(
+                    client,
+                    path,
+                    key,
+                    options,
+                    part_size,
+                    concurrency,
+                    progress,
+                )
+                .await
+            }
+        }
+    }
+
+    async fn upload_file_resumable(
+        client: &'a R2Client,
+        path: &Path,
+        key: &str,
+        options: &UploadOptions,
+        part_size: usize,
+        concurrency: usize,
+        progress: &dyn ProgressReporter,
+    ) -> Result<u64> {
+        let upload = || {
+            Self::upload_file_session(client, path, key, options, part_size, concurrency, progress)
+        };
+        match upload().await {
+            // The recorded upload expired or was aborted elsewhere; its session was
+            // dropped, so this starts a new multipart upload
+            Err(e) if is_no_such_upload(&e) => {
+                tracing::warn!(
+                    "Multipart upload of {} no longer exists, starting it over",
+                    key
+                );
+                upload().await
+            }
+            result => result,
+        }
+    }
+
+    async fn upload_file_session(
+        client: &'a R2Client,
+        path: &Path,
+        key: &str,
+        options: &UploadOptions,
+        part_size: usize,
+        concurrency: usize,
+        progress: &dyn ProgressReporter,
+    ) -> Result<u64> {
+        let part_size = part_size.max(MIN_PART_SIZE);
+        let metadata = fs::metadata(path).context("Failed to read input file")?;
+        let file_size = metadata.len();
+        let modified = transfer::modified_secs(&metadata);
+        let session_path = UploadSession::path_for(client, path, key)?;
+
+        let mut session = match UploadSession::load(&session_path) {
+            Some(session)
+                if session.key == key
+                    && session.file_size == file_size
+                    && session.modified == modified =>
+            {
+                tracing::info!(
+                    "Resuming upload of {} ({} parts already uploaded)",
+                    key,
+                    session.parts.len()
+                );
+                session
+            }
+            stale => {
+                // The file changed since the last attempt, so its parts are useless
+                if let Some(stale) = stale {
+                    if let Err(e) = client
+                        .abort_multipart_upload(&stale.key, &stale.upload_id)
+                        .await
+                    {
+                        tracing::warn!("Failed to abort stale multipart upload: {}", e);
+                    }
+                }
+                let upload_id = client.create_multipart_upload(key, options).await?;
+                UploadSession {
+                    key: key.to_string(),
+                    upload_id,
+                    file_size,
+                    modified,
+                    part_size,
+                    parts: Vec::new(),
+                    local_path: None,
+                }
+            }
+        };
+        session.local_path = Some(fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()));
+        session.save(&session_path)?;
+
+        let result = Self::upload_session_parts(
+            client,
+            path,
+            key,
+            &mut session,
+            &session_path,
+            concurrency,
+            progress,
+        )
+        .await;
+        if let Err(e) = &result {
+            // A session for an upload that is gone can never be resumed
+            if is_no_such_upload(e) {
+                let _ = fs::remove_file(&session_path);
+            }
+        }
+        result
+    }
+
+    /// Send whatever `session` is missing and complete its upload.
+    async fn upload_session_parts(
+        client: &'a R2Client,
+        path: &Path,
+        key: &str,
+        session: &mut UploadSession,
+        session_path: &Path,
+        concurrency: usize,
+        progress: &dyn ProgressReporter,
+    ) -> Result<u64> {
+        let file_size = session.file_size;
+
+        let mut file = File::open(path).context("Failed to open input file")?;
+        progress.set_length(file_size);
+
+        // Re-read every recorded range; anything that no longer matches is sent again
+        for i in 0..session.parts.len() {
+            let part = session.parts[i].clone();
+            let _reservation = memory_budget::reserve(part.size as usize).await;
+            let data = read_range(&mut file, part.offset, part.size)?;
+            if hex::encode(Sha256::digest(&data)) != part.sha256 {
+                tracing::warn!(
+                    "Part {} of {} changed since it was uploaded, sending it again",
+                    part.part_number,
+                    key
+                );
+                let etag = client
+                    .upload_part(key, &session.upload_id, part.part_number, Bytes::from(data))
+                    .await
+                    .context("Upload interrupted; re-run to resume")?;
+                session.parts[i].etag = etag;
+                session.save(session_path)?;
+            }
+        }
+
+        // Send the missing parts concurrently, recording each one as soon as it is stored
+        let mut uploaded: u64 = session.parts.iter().map(|p| p.size).sum();
+        progress.set_position(uploaded);
+        let upload_id = session.upload_id.clone();
+        let mut uploads = stream::iter(session.missing_parts())
+            .map(|(part_number, offset, size)| {
+                let upload_id = &upload_id;
+                async move {
+                    let _reservation = memory_budget::reserve(size as usize).await;
+                    let mut file = File::open(path).context("Failed to open input file")?;
+                    let data = read_range(&mut file, offset, size)?;
+                    let sha256 = hex::encode(Sha256::digest(&data));
+                    let etag = client
+                        .upload_part(key, upload_id, part_number, Bytes::from(data))
+                        .await
+                        .context("Upload interrupted; re-run to resume")?;
+                    Ok::<_, anyhow::Error>(SessionPart {
+                        part_number,
+                        offset,
+                        size,
+                        sha256,
+                        etag,
+                    })
+                }
+            })
+            .buffer_unordered(concurrency.max(1));
+        while let Some(part) = uploads.next().await {
+            let part = part?;
+            uploaded += part.size;
+            session.parts.push(part);
+            session.save(session_path)?;
+            progress.set_position(uploaded);
+        }
+        drop(uploads);
+
+        session.parts.sort_by_key(|p| p.part_number);
+        let parts: Vec<CompletedPart> = session
+            .parts
+            .iter()
+            .map(|p| CompletedPart {
+                part_number: p.part_number,
+                etag: p.etag.clone(),
+            })
+            .collect();
+        client
+            .complete_multipart_upload(key, &session.upload_id, &parts)
+            .await?;
+
+        let _ = fs::remove_file(session_path);
+        Ok(file_size)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn upload_file_encrypted(
+        client: &'a R2Client,
+        path: &Path,
+        key: &str,
+        options: &UploadOptions,
+        part_size: usize,
+        concurrency: usize,
+        handler: &PgpHandler,
+        progress: &dyn ProgressReporter,
+    ) -> Result<u64> {
+        let file_name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_else(|| "data".to_string());
+        let mut encryptor =
+            ChunkedEncryptor::new(handler, &file_name, chunked::DEFAULT_CHUNK_SIZE)?;
+        let mut file = File::open(path).context("Failed to open input file")?;
+        progress.set_length(file.metadata()?.len());
+
+        let mut uploader = Self::start(client, key, options, part_size, concurrency).await?;
+        let result = async {
+            let mut buf = vec![0u8; chunked::DEFAULT_CHUNK_SIZE];
+            let mut read = 0u64;
+            loop {
+                let n = file.read(&mut buf)?;
+                if n == 0 {
+                    break;
+                }
+                uploader.write(&encryptor.update(&buf[..n])?).await?;
+                read += n as u64;
+                progress.set_position(read);
+            }
+            uploader.write(&encryptor.finish()?).await
+        }
+        .await;
+
+        if let Err(e) = result {
+            // Don't leave orphaned parts consuming storage
+            if let Err(abort_error) = uploader.abort().await {
+                tracing::warn!("Failed to abort multipart upload: {}", abort_error);
+            }
+            return Err(e);
+        }
+        uploader.finish().await
+    }
+
+    pub async fn abort(mut self) -> Result<()> {
+        // Stop sending parts first, so none is stored after the upload is gone
+        self.in_flight = FuturesUnordered::new();
+        let result = self
+            .client
+            .abort_multipart_upload(&self.key, &self.upload_id)
+            .await;
+        self.closed = true;
+        result
+    }
+
+    /// Reserve memory for the next part buffer. The parts in flight hold reservations of
+    /// their own and only give them back once they are stored, so they are driven while
+    /// waiting; otherwise a budget smaller than the parts in flight could never free up.
+    async fn reserve_buffer(&mut self) -> Result<()> {
+        let reserve = memory_budget::reserve(self.part_size);
+        tokio::pin!(reserve);
+        loop {
+            tokio::select! {
+                reservation = &mut reserve => {
+                    self.reservation = Some(reservation);
+                    return Ok(());
+                }
+                Some(part) = self.in_flight.next(), if !self.in_flight.is_empty() => {
+                    self.parts.push(part?);
+                }
+            }
+        }
+    }
+
+    /// Give every part in flight a turn without waiting, collecting the ones stored.
+    fn poll_in_flight(&mut self) -> Result<()> {
+        while let Some(Some(part)) = self.in_flight.next().now_or_never() {
+            self.parts.push(part?);
+        }
+        Ok(())
+    }
+
+    /// Start sending the buffer as the next part, first waiting for one in flight to
+    /// finish if `concurrency` are already being sent.
+    async fn send_part(&mut self) -> Result<()> {
+        if self.in_flight.len() >= self.concurrency {
+            if let Some(part) = self.in_flight.next().await {
+                self.parts.push(part?);
+            }
+        }
+
+        self.part_count += 1;
+        let part_number = self.part_count;
+        let data = Bytes::from(std::mem::take(&mut self.buffer));
+        self.uploaded += data.len() as u64;
+        // The part leaves memory once it is sent, whether or not it was stored
+        let reservation = self.reservation.take();
+        let (client, key, upload_id) = (self.client, self.key.clone(), self.upload_id.clone());
+        self.in_flight.push(Box::pin(async move {
+            let _reservation = reservation;
+            let etag = client
+                .upload_part(&key, &upload_id, part_number, data)
+                .await?;
+            Ok(CompletedPart { part_number, etag })
+        }));
+        // Futures do nothing until polled, so start the request now
+        self.poll_in_flight()
+    }
+}
+
+impl Drop for MultipartUploader<'_> {
+    fn drop(&mut self) {
+        if !self.closed {
+            ABANDONED.lock().unwrap().push((
+                self.client.bucket_url(),
+                self.key.clone(),
+                self.upload_id.clone(),
+            ));
+        }
+    }
+}
+
+/// Abort the uploads in `client`'s bucket whose uploader was dropped unfinished, such as
+/// those a deadline cut off. Failures are only logged.
+pub async fn abort_abandoned(client: &R2Client) {
+    let bucket_url = client.bucket_url();
+    let abandoned: Vec<(String, String, String)> = {
+        let mut all = ABANDONED.lock().unwrap();
+        let (ours, others) = all.drain(..).partition(|(url, _, _)| *url == bucket_url);
+        *all = others;
+        ours
+    };
+    for (_, key, upload_id) in abandoned {
+        tracing::info!("Aborting unfinished multipart upload of {}", key);
+        if let Err(e) = client.abort_multipart_upload(&key, &upload_id).await {
+            tracing::warn!("Failed to abort multipart upload of {}: {}", key, e);
+        }
+    }
+}
+
+fn is_no_such_upload(error: &anyhow::Error) -> bool {
+    R2Error::from_anyhow(error).and_then(R2Error::code) == Some("NoSuchUpload")
+}
+
+fn read_range(file: &mut File, offset: u64, size: u64) -> Result<Vec<u8>> {
+    let mut data = vec![0u8; size as usize];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut data)
+        .context("Input file is shorter than when the upload started")?;
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_r2::MockR2;
+    use crate::progress::ConsoleProgress;
+    use crate::r2_client::R2ClientConfig;
+    use crate::test_support::TempDir;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn stalled_parts_are_retried() {
+        let mock = MockR2::start().await;
+        // Large enough that the stalled body fills the socket buffers and blocks
+        let part_size = 12 * 1024 * 1024;
+        mock.stall_part(2);
+        let client = mock
+            .client()
+            .await
+            .with_part_stall_timeout(Duration::from_millis(300));
+
+        let data: Vec<u8> = (0..2 * part_size + 1000).map(|i| (i % 251) as u8).collect();
+        let mut uploader =
+            MultipartUploader::start(&client, "big.bin", &UploadOptions::default(), part_size, 2)
+                .await
+                .unwrap();
+        uploader.write(&data).await.unwrap();
+        assert_eq!(uploader.finish().await.unwrap(), data.len() as u64);
+
+        assert!(mock.object("big.bin").unwrap().data == data);
+        assert!(client.metrics().snapshot().retries >= 1);
+    }
+
+    fn session(file_size: u64, part_size: usize, done: &[u32]) -> UploadSession {
+        let parts = done
+            .iter()
+            .map(|&part_number| SessionPart {
+                part_number,
+                offset: (part_number as u64 - 1) * part_size as u64,
+                size: part_size as u64,
+                sha256: String::new(),
+                etag: String::new(),
+            })
+            .collect();
+        UploadSession {
+            key: "key".to_string(),
+            upload_id: "upload".to_string(),
+            file_size,
+            modified: 0,
+            part_size,
+            parts,
+            local_path: None,
+        }
+    }
+
+    #[test]
+    fn missing_parts_fill_the_gaps_between_uploaded_ones() {
+        assert_eq!(
+            session(25, 10, &[2]).missing_parts(),
+            [(1, 0, 10), (3, 20, 5)]
+        );
+        assert_eq!(session(30, 10, &[1, 3]).missing_parts(), [(2, 10, 10)]);
+        assert!(session(30, 10, &[3, 1, 2]).missing_parts().is_empty());
+        // An empty file is one empty part
+        assert_eq!(session(0, 10, &[]).missing_parts(), [(1, 0, 0)]);
+    }
+
+    async fn upload(client: &R2Client, path: &Path, key: &str) -> Result<u64> {
+        let options = UploadOptions::default();
+        let progress = ConsoleProgress::new(0);
+        MultipartUploader::upload_file(
+            client,
+            path,
+            key,
+            &options,
+            MIN_PART_SIZE,
+            1,
+            None,
+            &progress,
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn interrupted_binary_uploads_resume_to_identical_objects() {
+        // Arbitrary binary data, as a file encrypted beforehand with gpg would be
+        let mut seed = 0x2545_f491_4f6c_dd1du64;
+        let data: Vec<u8> = (0..2 * MIN_PART_SIZE + 12345)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+                seed as u8
+            })
+            .collect();
+        let folder = TempDir::new();
+        let path = folder.write("backup.tar.gpg", &data);
+        let mock = MockR2::start().await;
+        let no_retries = R2ClientConfig {
+            max_retries: 0,
+            ..Default::default()
+        };
+        let client = || async {
+            mock.client()
+                .await
+                .with_client_config(no_retries.clone())
+                .unwrap()
+        };
+        let first = client().await;
+        mock.fail_part(3);
+        assert!(upload(&first, &path, "backup.tar.gpg").await.is_err());
+        let session_path = UploadSession::path_for(&first, &path, "backup.tar.gpg").unwrap();
+        let session = UploadSession::load(&session_path).unwrap();
+        assert_eq!(
+            session.missing_parts(),
+            [(3, 2 * MIN_PART_SIZE as u64, 12345)]
+        );
+        assert!(mock.keys().is_empty());
+
+        // Only the missing part is sent again
+        let resumed = client().await;
+        assert_eq!(
+            upload(&resumed, &path, "backup.tar.gpg").await.unwrap(),
+            data.len() as u64
+        );
+        assert_eq!(resumed.metrics().snapshot().put, 1);
+        assert!(!session_path.exists());
+
+        let uninterrupted = client().await;
+        upload(&uninterrupted, &path, "uninterrupted.gpg")
+            .await
+            .unwrap();
+        let stored = mock.object("backup.tar.gpg").unwrap().data;
+        assert!(stored == data);
+        assert!(stored == mock.object("uninterrupted.gpg").unwrap().data);
+    }
+
+    #[tokio::test]
+    async fn resumes_of_an_expired_upload_start_a_new_one() {
+        let data: Vec<u8> = (0..MIN_PART_SIZE + 4321).map(|i| (i % 251) as u8).collect();
+        let folder = TempDir::new();
+        let path = folder.write("expired.bin", &data);
+        let mock = MockR2::start().await;
+        let no_retries = R2ClientConfig {
+            max_retries: 0,
+            ..Default::default()
+        };
+        let client = mock.client().await.with_client_config(no_retries).unwrap();
+        mock.fail_part(2);
+        assert!(upload(&client, &path, "expired.bin").await.is_err());
+        let session_path = UploadSession::path_for(&client, &path, "expired.bin").unwrap();
+        let session = UploadSession::load(&session_path).unwrap();
+
+        // R2 drops multipart uploads left unfinished for too long
+        client
+            .abort_multipart_upload("expired.bin", &session.upload_id)
+            .await
+            .unwrap();
+        assert_eq!(
+            upload(&client, &path, "expired.bin").await.unwrap(),
+            data.len() as u64
+        );
+        assert!(mock.object("expired.bin").unwrap().data == data);
+        assert!(!session_path.exists());
+        assert!(mock.open_uploads().is_empty());
+    }
+
+    #[tokio::test]
+    async fn dropped_uploads_are_aborted_afterwards() {
+        let mock = MockR2::start().await;
+        let client = mock.client().await;
+        let mut uploader = MultipartUploader::start(
+            &client,
+            "cut-off.bin",
+            &UploadOptions::default(),
+            MIN_PART_SIZE,
+            2,
+        )
+        .await
+        .unwrap();
+        uploader
+            .write(&vec![7u8; MIN_PART_SIZE + 10])
+            .await
+            .unwrap();
+
+        // As when a deadline drops the transfer mid-way
+        drop(uploader);
+        assert_eq!(mock.open_uploads(), ["cut-off.bin"]);
+
+        abort_abandoned(&client).await;
+        assert!(mock.open_uploads().is_empty());
+        assert!(mock.keys().is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_completions_abort_the_upload() {
+        let mock = MockR2::start().await;
+        let client = mock.client().await;
+        let mut uploader = MultipartUploader::start(
+            &client,
+            "never.bin",
+            &UploadOptions::default(),
+            MIN_PART_SIZE,
+            2,
+        )
+        .await
+        .unwrap();
+        uploader.write(b"some data").await.unwrap();
+
+        // Completing is a POST, like starting
+        mock.deny("POST");
+        assert!(uploader.finish().await.is_err());
+        assert!(mock.open_uploads().is_empty());
+        abort_abandoned(&client).await;
+    }
+}
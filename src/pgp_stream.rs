@@ -0,0 +1,417 @@
+use anyhow::{anyhow, Context, Result};
+use flate2::read::{DeflateDecoder, ZlibDecoder};
+use pgp::crypto::sym::SymmetricKeyAlgorithm;
+use pgp::packet::PublicKeyEncryptedSessionKey;
+use pgp::types::Version;
+use sha1::{Digest, Sha1};
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+
+// Streaming decryption of a single (non-chunked) OpenPGP message, for messages too large
+// to hand to the pgp crate, which parses a whole message and its plaintext into memory.
+// Only what this tool and gpg write by default is understood:
+//
+//   session keys:   PKESK packets, unwrapped by the caller; SKESK and marker packets skipped
+//   encrypted data: a version 1 SEIPD packet (CFB with an MDC), never plain SED
+//   inside it:      literal data, optionally compressed (ZIP or ZLIB) and optionally
+//                   one-pass signed; signatures are skipped, not verified
+//
+// The MDC covers the whole plaintext, so it can only be checked once everything has been
+// written. A failed check returns an error, and as with a truncated chunked stream the
+// caller must discard what was written.
+
+const BUFFER_SIZE: usize = 64 * 1024; // A multiple of every cipher's block size
+const MDC_LEN: usize = 22; // Tag, length and SHA-1 of the modification detection code
+const MAX_SESSION_KEY_PACKET: u64 = 64 * 1024;
+const MAX_NESTING: usize = 8; // Compressed packets inside compressed packets
+
+/// Unwraps a PKESK packet's session key with whichever loaded secret key it is for,
+/// returning its cipher and key, or None when no loaded key is a recipient.
+pub type SessionKeyFn<'a> =
+    dyn Fn(&PublicKeyEncryptedSessionKey) -> Option<(SymmetricKeyAlgorithm, Vec<u8>)> + 'a;
+
+/// Decrypt the message in `reader` to `writer`, returning the plaintext size.
+pub fn decrypt_message(
+    reader: &mut dyn Read,
+    writer: &mut dyn Write,
+    session_key: &SessionKeyFn,
+) -> Result<u64> {
+    let mut input = BufReader::new(reader);
+    if input
+        .fill_buf()?
+        .starts_with(b"-----BEGIN PGP MESSAGE-----")
+    {
+        let mut dearmored = pgp::armor::Dearmor::new(input);
+        return decrypt_packets(&mut dearmored, writer, session_key);
+    }
+    decrypt_packets(&mut input, writer, session_key)
+}
+
+fn decrypt_packets(
+    reader: &mut dyn Read,
+    writer: &mut dyn Write,
+    session_key: &SessionKeyFn,
+) -> Result<u64> {
+    let mut unwrapped = None;
+    while let Some(header) = read_header(reader)? {
+        let mut body = PacketBody::new(reader, header.length);
+        match header.tag {
+            1 => {
+                let mut packet = Vec::new();
+                (&mut body)
+                    .take(MAX_SESSION_KEY_PACKET)
+                    .read_to_end(&mut packet)?;
+                let pkesk = PublicKeyEncryptedSessionKey::from_slice(header.version, &packet)
+                    .context("Failed to parse a session key packet")?;
+                if unwrapped.is_none() {
+                    unwrapped = session_key(&pkesk);
+                }
+            }
+            3 | 10 => {
+                io::copy(&mut body, &mut io::sink())?;
+            }
+            18 => {
+                let (algorithm, key) = unwrapped.ok_or_else(|| {
+                    anyhow!("None of the loaded secret keys is a recipient of this message")
+                })?;
+                let mut version = [0u8; 1];
+                body.read_exact(&mut version).map_err(truncated)?;
+                if version[0] != 1 {
+                    return Err(anyhow!(
+                        "Version {} encrypted data (AEAD) can't be streamed",
+                        version[0]
+                    ));
+                }
+                let mut plaintext = Decryptor::new(body, algorithm, key)?;
+                return copy_literal(&mut plaintext, writer, 0).map_err(unwrap_io);
+            }
+            9 => {
+                return Err(anyhow!(
+                    "Refusing to decrypt a message without integrity protection"
+                ))
+            }
+            tag => return Err(anyhow!("Unexpected packet (tag {}) in message", tag)),
+        }
+    }
+    Err(anyhow!("Message has no encrypted data"))
+}
+
+/// Write the literal data among the packets in `reader` to `writer`, unpacking compressed
+/// packets, and read `reader` to its end so the MDC around it is checked.
+fn copy_literal(reader: &mut dyn Read, writer: &mut dyn Write, depth: usize) -> Result<u64> {
+    let mut written = 0;
+    while let Some(header) = read_header(reader)? {
+        let mut body = PacketBody::new(reader, header.length);
+        match header.tag {
+            8 => {
+                if depth >= MAX_NESTING {
+                    return Err(anyhow!("Message nests too many compressed packets"));
+                }
+                let mut algorithm = [0u8; 1];
+                body.read_exact(&mut algorithm).map_err(truncated)?;
+                written += match algorithm[0] {
+                    0 => copy_literal(&mut body, writer, depth + 1)?,
+                    1 => copy_literal(&mut DeflateDecoder::new(&mut body), writer, depth + 1)?,
+                    2 => copy_literal(&mut ZlibDecoder::new(&mut body), writer, depth + 1)?,
+                    other => {
+                        return Err(anyhow!("Compression algorithm {} can't be streamed", other))
+                    }
+                };
+            }
+            11 => {
+                // Format, file name length, file name and date come before the data
+                let mut format_and_length = [0u8; 2];
+                body.read_exact(&mut format_and_length).map_err(truncated)?;
+                let mut name_and_date = vec![0u8; format_and_length[1] as usize + 4];
+                body.read_exact(&mut name_and_date).map_err(truncated)?;
+                written += io::copy(&mut body, writer)?;
+            }
+            2 | 4 | 10 => {}
+            tag => return Err(anyhow!("Unexpected packet (tag {}) in plaintext", tag)),
+        }
+        io::copy(&mut body, &mut io::sink())?;
+    }
+    Ok(written)
+}
+
+enum Length {
+    Fixed(u64),
+    Partial(u64),  // The first part; more follow, each with its own length
+    Indeterminate, // Old format: the packet runs to the end of its container
+}
+
+struct Header {
+    tag: u8,
+    version: Version,
+    length: Length,
+}
+
+/// The next packet header, or None at the end of the input.
+fn read_header(reader: &mut dyn Read) -> Result<Option<Header>> {
+    let mut first = [0u8; 1];
+    loop {
+        match reader.read(&mut first) {
+            Ok(0) => return Ok(None),
+            Ok(_) => break,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(unwrap_io(e.into())),
+        }
+    }
+    let first = first[0];
+    if first & 0x80 == 0 {
+        return Err(anyhow!("Not an OpenPGP packet header: {:#04x}", first));
+    }
+
+    if first & 0x40 != 0 {
+        let (length, partial) = read_new_length(reader).map_err(truncated)?;
+        return Ok(Some(Header {
+            tag: first & 0x3f,
+            version: Version::New,
+            length: if partial {
+                Length::Partial(length)
+            } else {
+                Length::Fixed(length)
+            },
+        }));
+    }
+
+    let length = match first & 0x03 {
+        3 => Length::Indeterminate,
+        size => {
+            let mut bytes = [0u8; 4];
+            let size = 1 << size;
+            reader
+                .read_exact(&mut bytes[4 - size..])
+                .map_err(truncated)?;
+            Length::Fixed(u32::from_be_bytes(bytes) as u64)
+        }
+    };
+    Ok(Some(Header {
+        tag: (first >> 2) & 0x0f,
+        version: Version::Old,
+        length,
+    }))
+}
+
+/// A new-format body length, and whether it is a partial length with more parts after it.
+fn read_new_length(reader: &mut dyn Read) -> io::Result<(u64, bool)> {
+    let mut first = [0u8; 1];
+    reader.read_exact(&mut first)?;
+    Ok(match first[0] {
+        length @ 0..=191 => (length as u64, false),
+        192..=223 => {
+            let mut second = [0u8; 1];
+            reader.read_exact(&mut second)?;
+            (
+                (((first[0] as u64) - 192) << 8) + second[0] as u64 + 192,
+                false,
+            )
+        }
+        255 => {
+            let mut bytes = [0u8; 4];
+            reader.read_exact(&mut bytes)?;
+            (u32::from_be_bytes(bytes) as u64, false)
+        }
+        partial => (1 << (partial & 0x1f), true),
+    })
+}
+
+/// The body of one packet, joining partial-length parts.
+struct PacketBody<'a> {
+    reader: &'a mut dyn Read,
+    remaining: u64, // In the current part
+    more_parts: bool,
+    to_end: bool,
+}
+
+impl<'a> PacketBody<'a> {
+    fn new(reader: &'a mut dyn Read, length: Length) -> Self {
+        let (remaining, more_parts, to_end) = match length {
+            Length::Fixed(length) => (length, false, false),
+            Length::Partial(length) => (length, true, false),
+            Length::Indeterminate => (0, false, true),
+        };
+        Self {
+            reader,
+            remaining,
+            more_parts,
+            to_end,
+        }
+    }
+}
+
+impl Read for PacketBody<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.to_end {
+            return self.reader.read(buf);
+        }
+        while self.remaining == 0 {
+            if !self.more_parts {
+                return Ok(0);
+            }
+            let (length, partial) = read_new_length(self.reader)?;
+            self.remaining = length;
+            self.more_parts = partial;
+        }
+        let len = buf.len().min(self.remaining as usize);
+        let n = self.reader.read(&mut buf[..len])?;
+        if n == 0 && len > 0 {
+            return Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "Encrypted message is truncated",
+            ));
+        }
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+}
+
+/// Decrypts a version 1 SEIPD packet body as it is read. OpenPGP's CFB mode without
+/// resynchronisation is plain CFB with a zero IV, so it can be decrypted in pieces as
+/// long as each piece but the last is whole blocks. The MDC is held back from the
+/// plaintext and checked once the body ends.
+struct Decryptor<'a> {
+    body: PacketBody<'a>,
+    algorithm: SymmetricKeyAlgorithm,
+    key: Vec<u8>,
+    iv: Vec<u8>,
+    plaintext: Vec<u8>,
+    position: usize,
+    hash: Sha1,
+    finished: bool,
+}
+
+impl<'a> Decryptor<'a> {
+    fn new(body: PacketBody<'a>, algorithm: SymmetricKeyAlgorithm, key: Vec<u8>) -> Result<Self> {
+        let block_size = algorithm.block_size();
+        if block_size == 0 || key.len() != algorithm.key_size() {
+            return Err(anyhow!("Unsupported session key cipher {:?}", algorithm));
+        }
+        let mut decryptor = Self {
+            body,
+            algorithm,
+            key,
+            iv: vec![0u8; block_size],
+            plaintext: Vec::new(),
+            position: 0,
+            hash: Sha1::new(),
+            finished: false,
+        };
+
+        // Random prefix whose last two bytes repeat, a quick check of the session key
+        let prefix_len = block_size + 2;
+        while decryptor.plaintext.len() < prefix_len + MDC_LEN {
+            if !decryptor.decrypt_more().map_err(truncated)? {
+                return Err(truncated(ErrorKind::UnexpectedEof.into()));
+            }
+        }
+        let prefix = &decryptor.plaintext[..prefix_len];
+        if prefix[block_size - 2..block_size] != prefix[block_size..] {
+            return Err(anyhow!("Failed to decrypt the message: wrong session key"));
+        }
+        decryptor.hash.update(prefix);
+        decryptor.position = prefix_len;
+        Ok(decryptor)
+    }
+
+    /// Decrypt the next piece of the body, returning false at its end.
+    fn decrypt_more(&mut self) -> io::Result<bool> {
+        let mut ciphertext = vec![0u8; BUFFER_SIZE];
+        let mut filled = 0;
+        while filled < ciphertext.len() {
+            match self.body.read(&mut ciphertext[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        if filled == 0 {
+            return Ok(false);
+        }
+        ciphertext.truncate(filled);
+
+        // Only a short final piece can end mid-block, and no IV is needed after it
+        let next_iv = ciphertext[filled.saturating_sub(self.iv.len())..].to_vec();
+        self.algorithm
+            .decrypt_with_iv_regular(&self.key, &self.iv, &mut ciphertext)
+            .map_err(|e| io::Error::new(ErrorKind::InvalidData, e.to_string()))?;
+        self.iv = next_iv;
+
+        self.plaintext.drain(..self.position);
+        self.position = 0;
+        self.plaintext.extend_from_slice(&ciphertext);
+        Ok(true)
+    }
+
+    /// Check the MDC left at the end of the plaintext once the body is exhausted.
+    fn check_mdc(&mut self) -> io::Result<()> {
+        let mdc = &self.plaintext[self.position..];
+        let valid = mdc.len() == MDC_LEN && mdc[0] == 0xD3 && mdc[1] == 0x14 && {
+            let mut hash = self.hash.clone();
+            hash.update(&mdc[..2]);
+            hash.finalize().as_slice() == &mdc[2..]
+        };
+        if !valid {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                IntegrityError("Decrypted message failed its integrity check (MDC); it has been modified or cut short"),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Read for Decryptor<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let available = self.plaintext.len() - self.position;
+            if available > MDC_LEN {
+                let n = buf.len().min(available - MDC_LEN);
+                let data = &self.plaintext[self.position..self.position + n];
+                buf[..n].copy_from_slice(data);
+                self.hash.update(data);
+                self.position += n;
+                return Ok(n);
+            }
+            if self.finished {
+                return Ok(0);
+            }
+            if !self.decrypt_more()? {
+                self.check_mdc()?;
+                self.finished = true;
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct IntegrityError(&'static str);
+
+impl std::fmt::Display for IntegrityError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+impl std::error::Error for IntegrityError {}
+
+fn truncated(error: io::Error) -> anyhow::Error {
+    if error.kind() == ErrorKind::UnexpectedEof {
+        anyhow!("Encrypted message is truncated")
+    } else {
+        unwrap_io(error.into())
+    }
+}
+
+/// Report a failed integrity check by its own message rather than as an I/O error.
+fn unwrap_io(error: anyhow::Error) -> anyhow::Error {
+    let integrity = error
+        .downcast_ref::<io::Error>()
+        .and_then(|e| e.get_ref())
+        .and_then(|inner| inner.downcast_ref::<IntegrityError>())
+        .map(|inner| inner.0);
+    match integrity {
+        Some(message) => anyhow!(message),
+        None => error,
+    }
+}
@@ -0,0 +1,229 @@
+use serde::Serialize;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Receives progress updates from long-running transfers.
+pub trait ProgressReporter: Send + Sync {
+    fn set_length(&self, length: u64);
+    fn set_position(&self, position: u64);
+    fn set_message(&self, message: &str);
+    fn finish(&self, message: &str);
+
+    /// Called when one item of a batch (a file or object) has finished successfully.
+    fn item_completed(&self, _item: &str) {}
+
+    /// Called when one item of a batch has finished, whether it succeeded, failed or was
+    /// skipped, with the details a per-item report needs.
+    fn item_finished(&self, _outcome: &ItemOutcome) {}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ItemResult {
+    Ok,
+    Skipped,
+    Failed,
+}
+
+/// How one item of a batch operation ended.
+#[derive(Clone, Debug, Serialize)]
+pub struct ItemOutcome {
+    pub path: Option<PathBuf>, // Local file, if the item has one
+    pub key: String,
+    pub action: &'static str, // What was done to the item, e.g. "upload" or "download"
+    pub bytes: u64,
+    pub result: ItemResult,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ItemOutcome {
+    pub fn ok(action: &'static str, key: &str, path: Option<PathBuf>, bytes: u64) -> Self {
+        Self {
+            path,
+            key: key.to_string(),
+            action,
+            bytes,
+            result: ItemResult::Ok,
+            error: None,
+        }
+    }
+
+    pub fn skipped(action: &'static str, key: &str, path: Option<PathBuf>, bytes: u64) -> Self {
+        Self {
+            result: ItemResult::Skipped,
+            ..Self::ok(action, key, path, bytes)
+        }
+    }
+
+    pub fn failed(action: &'static str, key: &str, path: Option<PathBuf>, error: &str) -> Self {
+        Self {
+            result: ItemResult::Failed,
+            error: Some(error.to_string()),
+            ..Self::ok(action, key, path, 0)
+        }
+    }
+}
+
+/// Single-line progress bar rendered to stderr, so stdout stays clean for piping.
+pub struct ConsoleProgress {
+    length: AtomicU64,
+    position: AtomicU64,
+    message: Mutex<String>,
+    last_draw: Mutex<Option<Instant>>,
+    completed: Mutex<Vec<String>>,
+    report: Option<Mutex<Box<dyn Write + Send>>>, // Where finished items go as JSON lines
+}
+
+const BAR_WIDTH: usize = 30;
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+impl ConsoleProgress {
+    pub fn new(length: u64) -> Self {
+        Self {
+            length: AtomicU64::new(length),
+            position: AtomicU64::new(0),
+            message: Mutex::new(String::new()),
+            last_draw: Mutex::new(None),
+            completed: Mutex::new(Vec::new()),
+            report: None,
+        }
+    }
+
+    /// Also write each finished batch item to stdout as a line of JSON.
+    pub fn with_item_report(self, enabled: bool) -> Self {
+        if enabled {
+            self.with_item_report_to(std::io::stdout())
+        } else {
+            self
+        }
+    }
+
+    /// Write each finished batch item to `out` as a line of JSON.
+    pub fn with_item_report_to(mut self, out: impl Write + Send + 'static) -> Self {
+        self.report = Some(Mutex::new(Box::new(out)));
+        self
+    }
+
+    pub fn length(&self) -> u64 {
+        self.length.load(Ordering::SeqCst)
+    }
+
+    pub fn position(&self) -> u64 {
+        self.position.load(Ordering::SeqCst)
+    }
+
+    /// Items reported through `item_completed`, in completion order.
+    pub fn completed_items(&self) -> Vec<String> {
+        self.completed.lock().unwrap().clone()
+    }
+
+    fn draw(&self, force: bool) {
+        {
+            let mut last_draw = self.last_draw.lock().unwrap();
+            if !force {
+                if let Some(instant) = *last_draw {
+                    if instant.elapsed() < REDRAW_INTERVAL {
+                        return;
+                    }
+                }
+            }
+            *last_draw = Some(Instant::now());
+        }
+
+        let length = self.length();
+        let message = self.message.lock().unwrap().clone();
+
+        // With no known length there is nothing to fill a bar against, so count bytes instead
+        if length == 0 && self.position() > 0 {
+            let mut stderr = std::io::stderr().lock();
+            let _ = write!(
+                stderr,
+                "\r\x1b[2K{} transferred {}",
+                format_size(self.position()),
+                message
+            );
+            let _ = stderr.flush();
+            return;
+        }
+
+        let position = self.position().min(length);
+        let fraction = if length == 0 {
+            1.0
+        } else {
+            position as f64 / length as f64
+        };
+        let filled = (fraction * BAR_WIDTH as f64) as usize;
+
+        let mut stderr = std::io::stderr().lock();
+        let _ = write!(
+            stderr,
+            "\r\x1b[2K[{}{}] {:>3}% of {} {}",
+            "#".repeat(filled),
+            "-".repeat(BAR_WIDTH - filled),
+            (fraction * 100.0) as u32,
+            format_size(length),
+            message
+        );
+        let _ = stderr.flush();
+    }
+}
+
+impl ProgressReporter for ConsoleProgress {
+    fn set_length(&self, length: u64) {
+        self.length.store(length, Ordering::SeqCst);
+        self.draw(false);
+    }
+
+    fn set_position(&self, position: u64) {
+        self.position.store(position, Ordering::SeqCst);
+        self.draw(false);
+    }
+
+    fn set_message(&self, message: &str) {
+        *self.message.lock().unwrap() = message.to_string();
+        self.draw(true);
+    }
+
+    fn finish(&self, message: &str) {
+        self.position.store(self.length(), Ordering::SeqCst);
+        *self.message.lock().unwrap() = message.to_string();
+        self.draw(true);
+        eprintln!();
+    }
+
+    fn item_completed(&self, item: &str) {
+        self.completed.lock().unwrap().push(item.to_string());
+    }
+
+    fn item_finished(&self, outcome: &ItemOutcome) {
+        let Some(report) = &self.report else {
+            return;
+        };
+        if let Ok(line) = serde_json::to_string(outcome) {
+            let mut out = report.lock().unwrap();
+            let _ = writeln!(out, "{}", line);
+            let _ = out.flush();
+        }
+    }
+}
+
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit_index = 0;
+
+    while size >= 1024.0 && unit_index < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit_index += 1;
+    }
+
+    if unit_index == 0 {
+        format!("{} {}", size as u64, UNITS[unit_index])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit_index])
+    }
+}
@@ -1,22 +1,879 @@
+use crate::credentials::{CredentialSource, Credentials};
+use crate::error::{self, R2Error, TransferStalled};
+use crate::metrics::{Metrics, RequestKind};
+use crate::retry_budget::{RetryBudget, RetryPolicy};
 use anyhow::{anyhow, Context, Result};
 use bytes::Bytes;
 use chrono::{DateTime, Utc};
+use futures_util::stream::{self, StreamExt};
 use hmac::{Hmac, Mac};
+use http_body_util::BodyDataStream;
+use quick_xml::events::Event;
 use reqwest::{
-    header::{HeaderMap, HeaderValue},
-    Client, Method,
+    header::{
+        HeaderMap, HeaderName, HeaderValue, CACHE_CONTROL, CONTENT_ENCODING, CONTENT_LENGTH,
+        CONTENT_TYPE, ETAG, EXPIRES, IF_NONE_MATCH, IF_RANGE, RANGE,
+    },
+    Client, Method, Response, StatusCode,
 };
 use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tokio::io::AsyncRead;
+use tokio::io::AsyncWriteExt;
+use tokio_util::io::{ReaderStream, StreamReader};
 
 type HmacSha256 = Hmac<Sha256>;
 
+// Standard headers that are stored with the object, so they're signed when present
+const SIGNED_CONTENT_HEADERS: &[&str] = &[
+    "cache-control",
+    "content-disposition",
+    "content-encoding",
+    "content-type",
+    "expires",
+];
+
+// Sent in x-amz-content-sha256 in place of the hash of a body that isn't hashed
+const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+
+// Granularity of progress callbacks while streaming an upload body
+const UPLOAD_CHUNK_SIZE: usize = 64 * 1024;
+
+// A part upload that moves no data for this long is abandoned and sent again
+pub const DEFAULT_PART_STALL_TIMEOUT: Duration = Duration::from_secs(60);
+
+// First wait before retrying a request that failed with a 5xx or connection error,
+// doubled for each further retry
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(30);
+
+// The longest a presigned URL can stay valid under SigV4
+pub const MAX_PRESIGN_EXPIRY: Duration = Duration::from_secs(7 * 24 * 3600);
+
+// User metadata key holding the local filename an object was uploaded from
+pub const ORIGINAL_FILENAME_METADATA: &str = "original-filename";
+
+// User metadata key set to "true" on objects uploaded encrypted
+pub const ENCRYPTED_METADATA: &str = "encrypted";
+
+// User metadata keys holding the size and mtime (Unix seconds) of the local file an
+// object was uploaded from, so sync can tell whether the file changed since
+pub const SOURCE_SIZE_METADATA: &str = "source-size";
+pub const SOURCE_MTIME_METADATA: &str = "source-mtime";
+
+// User metadata key holding the hex SHA-256 of an encrypted object's plaintext, so a
+// download can check what it decrypted
+pub const PLAINTEXT_SHA256_METADATA: &str = "plaintext-sha256";
+
+// User metadata keys the tool sets itself, which --metadata may not override
+pub const RESERVED_METADATA: &[&str] = &[
+    ORIGINAL_FILENAME_METADATA,
+    ENCRYPTED_METADATA,
+    SOURCE_SIZE_METADATA,
+    SOURCE_MTIME_METADATA,
+    PLAINTEXT_SHA256_METADATA,
+];
+
+// Content-Type of encrypted uploads, whose body is ciphertext whatever the file was
+pub const PGP_CONTENT_TYPE: &str = "application/pgp-encrypted";
+
+/// Where the ETag of a partial download at `path` is kept until the download completes.
+pub fn partial_etag_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".etag");
+    PathBuf::from(name)
+}
+
+/// The MIME type registered for a file name's extension, or None if it isn't known.
+pub fn guess_content_type(file_name: &str) -> Option<String> {
+    mime_guess::from_path(file_name)
+        .first_raw()
+        .map(str::to_string)
+}
+
+/// Optional settings applied to an upload.
+#[derive(Clone, Debug, Default)]
+pub struct UploadOptions {
+    pub metadata: BTreeMap<String, String>, // Sent as x-amz-meta-<name> headers
+    pub content_encoding: Option<String>,   // e.g. "gzip" for pre-compressed assets
+    pub content_type: Option<String>,
+    pub cache_control: Option<String>, // e.g. "public, max-age=86400" for CDN-served assets
+    pub expires: Option<DateTime<Utc>>,
+}
+
+impl UploadOptions {
+    /// Record the local filename so downloads can restore it.
+    /// The value is percent-encoded because header values must be ASCII.
+    pub fn with_original_filename(mut self, file_name: &str) -> Self {
+        self.metadata.insert(
+            ORIGINAL_FILENAME_METADATA.to_string(),
+            urlencoding::encode(file_name),
+        );
+        self
+    }
+
+    /// Record the size and modification time of the local file being uploaded.
+    pub fn with_source_stamp(mut self, size: u64, modified: u64) -> Self {
+        self.metadata
+            .insert(SOURCE_SIZE_METADATA.to_string(), size.to_string());
+        self.metadata
+            .insert(SOURCE_MTIME_METADATA.to_string(), modified.to_string());
+        self
+    }
+
+    /// Set the Content-Type from `file_name`'s extension, or to `application/pgp-encrypted`
+    /// when `encrypted`, unless one was already given. Unknown extensions leave it unset,
+    /// so R2 stores the object as `application/octet-stream`.
+    pub fn with_content_type_for(mut self, file_name: &str, encrypted: bool) -> Self {
+        if self.content_type.is_none() {
+            self.content_type = if encrypted {
+                Some(PGP_CONTENT_TYPE.to_string())
+            } else {
+                guess_content_type(file_name)
+            };
+        }
+        self
+    }
+
+    /// Record the hex SHA-256 of the plaintext of an encrypted upload.
+    pub fn with_plaintext_sha256(mut self, sha256: &str) -> Self {
+        self.metadata
+            .insert(PLAINTEXT_SHA256_METADATA.to_string(), sha256.to_string());
+        self
+    }
+
+    /// Flag the object as PGP-encrypted, so it can be recognised without reading it.
+    pub fn with_encrypted(mut self, encrypted: bool) -> Self {
+        if encrypted {
+            self.metadata
+                .insert(ENCRYPTED_METADATA.to_string(), "true".to_string());
+        }
+        self
+    }
+}
+
+// Content codings from the IANA HTTP registry that we accept on upload
+const CONTENT_ENCODINGS: &[&str] = &[
+    "gzip",
+    "x-gzip",
+    "deflate",
+    "br",
+    "compress",
+    "x-compress",
+    "zstd",
+    "identity",
+];
+
+/// Check a Content-Encoding value (possibly a comma-separated list of codings).
+pub fn validate_content_encoding(value: &str) -> Result<()> {
+    for coding in value.split(',').map(|c| c.trim().to_lowercase()) {
+        if !CONTENT_ENCODINGS.contains(&coding.as_str()) {
+            return Err(anyhow!(
+                "Unsupported Content-Encoding '{}', expected one of: {}",
+                coding,
+                CONTENT_ENCODINGS.join(", ")
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Check that a Cache-Control value is a comma-separated list of `directive` or
+/// `directive=value` entries, where a value is a token or a quoted string.
+pub fn validate_cache_control(value: &str) -> Result<()> {
+    let is_token = |s: &str| {
+        !s.is_empty()
+            && s.chars()
+                .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c))
+    };
+    for directive in value.split(',').map(str::trim) {
+        let (name, argument) = match directive.split_once('=') {
+            Some((name, argument)) => (name.trim(), Some(argument.trim())),
+            None => (directive, None),
+        };
+        let valid_argument = argument.is_none_or(|arg| {
+            is_token(arg) || (arg.len() >= 2 && arg.starts_with('"') && arg.ends_with('"'))
+        });
+        if !is_token(name) || !valid_argument {
+            return Err(anyhow!(
+                "Invalid Cache-Control directive '{}' in '{}'",
+                directive,
+                value
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Headers that store `options` with an object, shared by single and multipart uploads.
+fn upload_headers(options: &UploadOptions) -> Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    for (name, value) in &options.metadata {
+        headers.insert(
+            HeaderName::from_bytes(format!("x-amz-meta-{}", name.to_lowercase()).as_bytes())?,
+            HeaderValue::from_str(value)?,
+        );
+    }
+    if let Some(encoding) = &options.content_encoding {
+        validate_content_encoding(encoding)?;
+        headers.insert(CONTENT_ENCODING, HeaderValue::from_str(encoding)?);
+    }
+    if let Some(content_type) = &options.content_type {
+        headers.insert(CONTENT_TYPE, HeaderValue::from_str(content_type)?);
+    }
+    if let Some(cache_control) = &options.cache_control {
+        validate_cache_control(cache_control)?;
+        headers.insert(CACHE_CONTROL, HeaderValue::from_str(cache_control)?);
+    }
+    if let Some(expires) = &options.expires {
+        let http_date = expires.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+        headers.insert(EXPIRES, HeaderValue::from_str(&http_date)?);
+    }
+    Ok(headers)
+}
+
+/// How R2 bills a request. GETs on the bucket itself, without a key, are listings, and
+/// so are GETs listing the parts of a multipart upload.
+fn request_kind(method: &Method, path: &str) -> RequestKind {
+    let (resource, query) = path.split_once('?').unwrap_or((path, ""));
+    let bucket_level = !resource.trim_start_matches('/').contains('/');
+    let lists_parts = query.contains("uploadId=");
+    match *method {
+        Method::GET if bucket_level || lists_parts => RequestKind::List,
+        Method::GET => RequestKind::Get,
+        Method::PUT => RequestKind::Put,
+        Method::POST => RequestKind::Post,
+        Method::DELETE => RequestKind::Delete,
+        _ => RequestKind::Head,
+    }
+}
+
+/// A part of a multipart upload, as listed when completing it.
+#[derive(Clone, Debug)]
+pub struct CompletedPart {
+    pub part_number: u32,
+    pub etag: String,
+}
+
+/// `$RUST_R2_CACHE_DIR`, or rust-r2 in the user's cache folder.
+fn default_cache_dir() -> Option<PathBuf> {
+    if let Some(dir) = std::env::var_os("RUST_R2_CACHE_DIR") {
+        return Some(PathBuf::from(dir));
+    }
+    dirs::cache_dir().map(|dir| dir.join("rust-r2"))
+}
+
+/// The `<CompleteMultipartUpload>` request body, listing parts in ascending order.
+pub fn complete_multipart_body(parts: &[CompletedPart]) -> String {
+    let mut parts = parts.to_vec();
+    parts.sort_by_key(|p| p.part_number);
+
+    let mut body = String::from("<CompleteMultipartUpload>");
+    for part in &parts {
+        body.push_str(&format!(
+            "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>",
+            part.part_number,
+            xml_escape(&part.etag)
+        ));
+    }
+    body.push_str("</CompleteMultipartUpload>");
+    body
+}
+
+/// The SigV4 signature of `string_to_sign`, with the signing key derived from the
+/// secret key, the date and R2's "auto" region.
+fn sigv4_signature(
+    secret_access_key: &str,
+    date_short: &str,
+    string_to_sign: &str,
+) -> Result<String> {
+    let mut key = format!("AWS4{}", secret_access_key).into_bytes();
+    for item in [date_short.as_bytes(), b"auto", b"s3", b"aws4_request"] {
+        let mut mac = HmacSha256::new_from_slice(&key)?;
+        mac.update(item);
+        key = mac.finalize().into_bytes().to_vec();
+    }
+
+    let mut mac = HmacSha256::new_from_slice(&key)?;
+    mac.update(string_to_sign.as_bytes());
+    Ok(hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Most keys one DeleteObjects request may name.
+pub const DELETE_BATCH_LIMIT: usize = 1000;
+
+/// What a batch delete did with each key.
+#[derive(Clone, Debug, Default)]
+pub struct DeleteOutcome {
+    pub deleted: Vec<String>,
+    pub failed: Vec<(String, String)>, // Key and the reason R2 gave
+}
+
+/// The quiet `<Delete>` request body, so the response only lists the keys that failed.
+fn delete_objects_body(keys: &[String]) -> String {
+    let mut body = String::from("<Delete><Quiet>true</Quiet>");
+    for key in keys {
+        body.push_str(&format!("<Object><Key>{}</Key></Object>", xml_escape(key)));
+    }
+    body.push_str("</Delete>");
+    body
+}
+
+/// The `<Error>` entries of a DeleteObjects response, as key and message.
+fn parse_delete_errors(xml_text: &str) -> Result<Vec<(String, String)>> {
+    let mut reader = quick_xml::Reader::from_str(xml_text);
+    let mut errors = Vec::new();
+    let mut current: Option<(String, String, String)> = None; // Key, code, message
+    let mut element = Vec::new();
+    let mut buf = Vec::new();
+
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(ref e)) => {
+                element = e.name().as_ref().to_vec();
+                if element == b"Error" {
+                    current = Some(Default::default());
+                }
+            }
+            Ok(Event::Text(ref e)) => {
+                let text = e.unescape()?.to_string();
+                match (element.as_slice(), current.as_mut()) {
+                    (b"Key", Some(error)) => error.0 = text,
+                    (b"Code", Some(error)) => error.1 = text,
+                    (b"Message", Some(error)) => error.2 = text,
+                    _ => {}
+                }
+            }
+            Ok(Event::End(ref e)) => {
+                if e.name().as_ref() == b"Error" {
+                    if let Some((key, code, message)) = current.take() {
+                        let reason = if message.is_empty() {
+                            code
+                        } else {
+                            format!("{}: {}", code, message)
+                        };
+                        errors.push((key, reason));
+                    }
+                }
+                element.clear();
+            }
+            Ok(Event::Eof) => break,
+            Err(e) => return Err(anyhow!("XML parsing error: {}", e)),
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    Ok(errors)
+}
+
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Text of the first `<tag>` element in an XML response.
+fn xml_element(body: &str, tag: &str) -> Option<String> {
+    let open = format!("<{}>", tag);
+    let start = body.find(&open)? + open.len();
+    let end = start + body[start..].find(&format!("</{}>", tag))?;
+    Some(body[start..end].trim().to_string())
+}
+
+/// Object properties returned by a HEAD (or GET) request.
+#[derive(Clone, Debug, Default)]
+pub struct ObjectHead {
+    pub content_length: Option<u64>,
+    pub content_type: Option<String>,
+    pub content_encoding: Option<String>,
+    pub cache_control: Option<String>,
+    pub expires: Option<String>,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub metadata: BTreeMap<String, String>, // x-amz-meta-* headers with the prefix stripped
+}
+
+impl ObjectHead {
+    fn from_headers(headers: &HeaderMap) -> Self {
+        let header_str = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string)
+        };
+
+        let metadata = headers
+            .iter()
+            .filter_map(|(name, value)| {
+                let meta_name = name.as_str().strip_prefix("x-amz-meta-")?;
+                Some((meta_name.to_string(), value.to_str().ok()?.to_string()))
+            })
+            .collect();
+
+        Self {
+            content_length: header_str("content-length").and_then(|v| v.parse().ok()),
+            content_type: header_str("content-type"),
+            content_encoding: header_str("content-encoding"),
+            cache_control: header_str("cache-control"),
+            expires: header_str("expires"),
+            etag: header_str("etag"),
+            last_modified: header_str("last-modified"),
+            metadata,
+        }
+    }
+
+    /// The local file size and modification time recorded at upload time, if any.
+    pub fn source_stamp(&self) -> Option<(u64, u64)> {
+        let size = self.metadata.get(SOURCE_SIZE_METADATA)?.parse().ok()?;
+        let modified = self.metadata.get(SOURCE_MTIME_METADATA)?.parse().ok()?;
+        Some((size, modified))
+    }
+
+    /// The hex SHA-256 of the plaintext recorded when an encrypted object was uploaded.
+    pub fn plaintext_sha256(&self) -> Option<&str> {
+        self.metadata
+            .get(PLAINTEXT_SHA256_METADATA)
+            .map(String::as_str)
+    }
+
+    /// The filename recorded at upload time, if any.
+    pub fn original_filename(&self) -> Option<String> {
+        self.metadata
+            .get(ORIGINAL_FILENAME_METADATA)
+            .map(|v| urlencoding::decode(v))
+    }
+}
+
+/// One entry from a bucket listing.
+#[derive(Clone, Debug, Default)]
+pub struct ObjectInfo {
+    pub key: String,
+    pub size: u64,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+/// One level of a bucket listed with a `/` delimiter: the objects directly under the
+/// prefix and the folders (common prefixes, ending in `/`) below it.
+#[derive(Clone, Debug, Default)]
+pub struct FolderListing {
+    pub objects: Vec<ObjectInfo>,
+    pub folders: Vec<String>,
+}
+
+struct ListPage {
+    objects: Vec<ObjectInfo>,
+    common_prefixes: Vec<String>,
+    is_truncated: bool,
+    next_continuation_token: Option<String>,
+}
+
+/// Parse a ListObjectsV2 page as the response body arrives, so a large listing is
+/// never buffered whole.
+async fn parse_list_response(response: Response) -> Result<ListPage> {
+    let body = response
+        .bytes_stream()
+        .map(|chunk| chunk.map_err(std::io::Error::other));
+    let mut reader =
+        quick_xml::Reader::from_reader(tokio::io::BufReader::new(StreamReader::new(body)));
+    let mut page = ListPage {
+        objects: Vec::new(),
+        common_prefixes: Vec::new(),
+        is_truncated: false,
+        next_continuation_token: None,
+    };
+    let mut current: Option<ObjectInfo> = None;
+    let mut in_common_prefixes = false;
+    let mut element = Vec::new();
+    let mut buf = Vec::new();
+
+    loop {
+        match reader.read_event_into_async(&mut buf).await {
+            Ok(Event::Start(ref e)) => {
+                element = e.name().as_ref().to_vec();
+                match element.as_slice() {
+                    b"Contents" => current = Some(ObjectInfo::default()),
+                    b"CommonPrefixes" => in_common_prefixes = true,
+                    _ => {}
+                }
+            }
+            Ok(Event::Text(ref e)) => {
+                let text = e.unescape()?.to_string();
+                match (element.as_slice(), current.as_mut()) {
+                    (b"Key", Some(obj)) => obj.key = text,
+                    (b"Size", Some(obj)) => obj.size = text.parse().unwrap_or(0),
+                    (b"ETag", Some(obj)) => obj.etag = Some(text.trim_matches('"').to_string()),
+                    (b"LastModified", Some(obj)) => obj.last_modified = Some(text),
+                    (b"Prefix", None) if in_common_prefixes => page.common_prefixes.push(text),
+                    (b"IsTruncated", None) => page.is_truncated = text == "true",
+                    (b"NextContinuationToken", None) => page.next_continuation_token = Some(text),
+                    _ => {}
+                }
+            }
+            Ok(Event::End(ref e)) => {
+                match e.name().as_ref() {
+                    b"Contents" => page.objects.extend(current.take()),
+                    b"CommonPrefixes" => in_common_prefixes = false,
+                    _ => {}
+                }
+                element.clear();
+            }
+            Ok(Event::Eof) => break,
+            Err(e) => return Err(anyhow!("XML parsing error: {}", e)),
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    Ok(page)
+}
+
+/// One version or delete marker from a versioned bucket listing.
+#[derive(Clone, Debug, Default)]
+pub struct ObjectVersion {
+    pub key: String,
+    pub version_id: String,
+    pub is_latest: bool,
+    pub is_delete_marker: bool,
+    pub size: u64,
+    pub last_modified: Option<String>,
+}
+
+struct VersionPage {
+    versions: Vec<ObjectVersion>,
+    is_truncated: bool,
+    next_key_marker: Option<String>,
+    next_version_id_marker: Option<String>,
+}
+
+fn parse_versions_response(xml_text: &str) -> Result<VersionPage> {
+    let mut reader = quick_xml::Reader::from_str(xml_text);
+    let mut page = VersionPage {
+        versions: Vec::new(),
+        is_truncated: false,
+        next_key_marker: None,
+        next_version_id_marker: None,
+    };
+    let mut current: Option<ObjectVersion> = None;
+    let mut element = Vec::new();
+    let mut buf = Vec::new();
+
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(ref e)) => {
+                element = e.name().as_ref().to_vec();
+                match element.as_slice() {
+                    b"Version" => current = Some(ObjectVersion::default()),
+                    b"DeleteMarker" => {
+                        current = Some(ObjectVersion {
+                            is_delete_marker: true,
+                            ..Default::default()
+                        })
+                    }
+                    _ => {}
+                }
+            }
+            Ok(Event::Text(ref e)) => {
+                let text = e.unescape()?.to_string();
+                match (element.as_slice(), current.as_mut()) {
+                    (b"Key", Some(version)) => version.key = text,
+                    (b"VersionId", Some(version)) => version.version_id = text,
+                    (b"IsLatest", Some(version)) => version.is_latest = text == "true",
+                    (b"Size", Some(version)) => version.size = text.parse().unwrap_or(0),
+                    (b"LastModified", Some(version)) => version.last_modified = Some(text),
+                    (b"IsTruncated", None) => page.is_truncated = text == "true",
+                    (b"NextKeyMarker", None) => page.next_key_marker = Some(text),
+                    (b"NextVersionIdMarker", None) => page.next_version_id_marker = Some(text),
+                    _ => {}
+                }
+            }
+            Ok(Event::End(ref e)) => {
+                if matches!(e.name().as_ref(), b"Version" | b"DeleteMarker") {
+                    page.versions.extend(current.take());
+                }
+                element.clear();
+            }
+            Ok(Event::Eof) => break,
+            Err(e) => return Err(anyhow!("XML parsing error: {}", e)),
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    Ok(page)
+}
+
+/// A multipart upload that was started but never completed or aborted. Its parts are
+/// stored, and billed, until it is.
+#[derive(Clone, Debug, Default)]
+pub struct MultipartUploadInfo {
+    pub key: String,
+    pub upload_id: String,
+    pub initiated: Option<String>,
+}
+
+struct UploadsPage {
+    uploads: Vec<MultipartUploadInfo>,
+    is_truncated: bool,
+    next_key_marker: Option<String>,
+    next_upload_id_marker: Option<String>,
+}
+
+fn parse_uploads_response(xml_text: &str) -> Result<UploadsPage> {
+    let mut reader = quick_xml::Reader::from_str(xml_text);
+    let mut page = UploadsPage {
+        uploads: Vec::new(),
+        is_truncated: false,
+        next_key_marker: None,
+        next_upload_id_marker: None,
+    };
+    let mut current: Option<MultipartUploadInfo> = None;
+    let mut element = Vec::new();
+    let mut buf = Vec::new();
+
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(ref e)) => {
+                element = e.name().as_ref().to_vec();
+                if element.as_slice() == b"Upload" {
+                    current = Some(MultipartUploadInfo::default());
+                }
+            }
+            Ok(Event::Text(ref e)) => {
+                let text = e.unescape()?.to_string();
+                match (element.as_slice(), current.as_mut()) {
+                    (b"Key", Some(upload)) => upload.key = text,
+                    (b"UploadId", Some(upload)) => upload.upload_id = text,
+                    (b"Initiated", Some(upload)) => upload.initiated = Some(text),
+                    (b"IsTruncated", None) => page.is_truncated = text == "true",
+                    (b"NextKeyMarker", None) => page.next_key_marker = Some(text),
+                    (b"NextUploadIdMarker", None) => page.next_upload_id_marker = Some(text),
+                    _ => {}
+                }
+            }
+            Ok(Event::End(ref e)) => {
+                if e.name().as_ref() == b"Upload" {
+                    page.uploads.extend(current.take());
+                }
+                element.clear();
+            }
+            Ok(Event::Eof) => break,
+            Err(e) => return Err(anyhow!("XML parsing error: {}", e)),
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    Ok(page)
+}
+
+/// Turn a rejected version listing into an error that says versioning is the problem.
+fn versioning_unsupported(error: anyhow::Error) -> anyhow::Error {
+    let unsupported = R2Error::from_anyhow(&error).is_some_and(|e| {
+        e.status() == Some(501) || matches!(e.code(), Some("NotImplemented" | "InvalidArgument"))
+    });
+    if unsupported {
+        error.context("Bucket is not versioned, so deleted objects cannot be restored")
+    } else {
+        error
+    }
+}
+
+/// The canned ACLs R2 can be asked for. Anything finer-grained needs bucket settings.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CannedAcl {
+    Private,
+    PublicRead,
+}
+
+impl CannedAcl {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CannedAcl::Private => "private",
+            CannedAcl::PublicRead => "public-read",
+        }
+    }
+}
+
+impl std::str::FromStr for CannedAcl {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "private" => Ok(CannedAcl::Private),
+            "public-read" => Ok(CannedAcl::PublicRead),
+            other => Err(anyhow!(
+                "Unsupported ACL '{}': only private and public-read can be set",
+                other
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for CannedAcl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// An `<AccessControlPolicy>` grants public read if the AllUsers group can READ.
+fn parse_acl_response(xml_text: &str) -> CannedAcl {
+    let public = xml_text.split("<Grant>").skip(1).any(|grant| {
+        grant.contains("http://acs.amazonaws.com/groups/global/AllUsers")
+            && xml_element(grant, "Permission").is_some_and(|p| p == "READ" || p == "FULL_CONTROL")
+    });
+    if public {
+        CannedAcl::PublicRead
+    } else {
+        CannedAcl::Private
+    }
+}
+
+/// Turn a rejected ACL request into an error that says where public access is set instead.
+fn acl_unsupported(error: anyhow::Error) -> anyhow::Error {
+    let unsupported = R2Error::from_anyhow(&error)
+        .is_some_and(|e| e.status() == Some(501) || e.code() == Some("NotImplemented"));
+    if unsupported {
+        error.context(
+            "R2 does not support per-object ACLs; make objects public through the bucket's \
+             public access (r2.dev or a custom domain) instead",
+        )
+    } else {
+        error
+    }
+}
+
+/// What a ping found out about the endpoint and bucket.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PingDiagnosis {
+    Ok,
+    DnsFailure(String), // Host didn't resolve: wrong account ID or jurisdiction
+    ConnectionFailed(String), // Resolved but unreachable: network, proxy or TLS problem
+    InvalidCredentials(u16), // 401/403: bad access key, secret, or missing permission
+    BucketNotFound,     // 404: the account is right but the bucket name is not
+    UnexpectedStatus(u16),
+}
+
+impl PingDiagnosis {
+    fn from_status(status: StatusCode) -> Self {
+        match status.as_u16() {
+            _ if status.is_success() => PingDiagnosis::Ok,
+            code @ (401 | 403) => PingDiagnosis::InvalidCredentials(code),
+            404 => PingDiagnosis::BucketNotFound,
+            code => PingDiagnosis::UnexpectedStatus(code),
+        }
+    }
+}
+
+impl std::fmt::Display for PingDiagnosis {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PingDiagnosis::Ok => write!(f, "bucket reachable and accessible"),
+            PingDiagnosis::DnsFailure(e) => write!(
+                f,
+                "endpoint did not resolve, check the account ID and jurisdiction ({})",
+                e
+            ),
+            PingDiagnosis::ConnectionFailed(e) => {
+                write!(f, "could not connect to endpoint ({})", e)
+            }
+            PingDiagnosis::InvalidCredentials(code) => write!(
+                f,
+                "credentials rejected with status {}, check the access key and secret",
+                code
+            ),
+            PingDiagnosis::BucketNotFound => write!(f, "bucket does not exist in this account"),
+            PingDiagnosis::UnexpectedStatus(code) => write!(f, "unexpected status {}", code),
+        }
+    }
+}
+
+/// An access a batch operation needs, so a probe can say which one is missing.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Permission {
+    List,
+    Read,
+    Delete,
+}
+
+impl std::fmt::Display for Permission {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Permission::List => write!(f, "list"),
+            Permission::Read => write!(f, "read"),
+            Permission::Delete => write!(f, "delete"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PingInfo {
+    pub endpoint: String,
+    pub addresses: Vec<std::net::IpAddr>, // What the endpoint host resolved to
+    pub latency: Option<Duration>,        // Round trip of the signed request, if one was made
+    pub diagnosis: PingDiagnosis,
+}
+
+impl PingInfo {
+    pub fn is_ok(&self) -> bool {
+        self.diagnosis == PingDiagnosis::Ok
+    }
+}
+
+/// Timeouts and retries of the HTTP requests an `R2Client` sends.
+#[derive(Clone, Debug)]
+pub struct R2ClientConfig {
+    pub connect_timeout: Duration,
+    /// Limit on a whole request, including its body, for requests that don't transfer
+    /// object content (listings, HEADs, deletes and the like). A large upload or download
+    /// can rightly take much longer, so object GETs and PUTs are only cut off once no data
+    /// has moved for this long. `None` means no limit.
+    pub request_timeout: Option<Duration>,
+    /// Times a request that failed with a 5xx status or a connection error is sent
+    /// again, with exponential backoff. 4xx responses are never retried.
+    pub max_retries: u32,
+    /// Where to send requests instead of the account's R2 endpoint, such as a local
+    /// S3-compatible server. `None` uses `https://<account_id>.r2.cloudflarestorage.com`.
+    pub endpoint: Option<String>,
+}
+
+impl Default for R2ClientConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(10),
+            request_timeout: Some(Duration::from_secs(60)),
+            max_retries: 3,
+            endpoint: None,
+        }
+    }
+}
+
+impl R2ClientConfig {
+    fn build_client(&self) -> Result<Client> {
+        Client::builder()
+            .connect_timeout(self.connect_timeout)
+            .build()
+            .context("Failed to build HTTP client")
+    }
+}
+
 pub struct R2Client {
     client: Client,
-    access_key_id: String,
-    secret_access_key: String,
-    account_id: String,
+    client_config: R2ClientConfig,
+    credentials: RwLock<Credentials>,
+    credential_source: CredentialSource,
     bucket_name: String,
     endpoint: String,
+    cache_dir: Option<PathBuf>, // Where resumable transfers record their sessions
+    metrics: Metrics,
+    part_stall_timeout: Duration,
+    retry_budget: RetryBudget,
 }
 
 impl R2Client {
@@ -27,14 +884,113 @@ impl R2Client {
         bucket_name: String,
     ) -> Result<Self> {
         let endpoint = format!("https://{}.r2.cloudflarestorage.com", account_id);
+        let client_config = R2ClientConfig::default();
 
         Ok(Self {
-            client: Client::new(),
-            access_key_id,
-            secret_access_key,
-            account_id,
+            client: client_config.build_client()?,
+            client_config,
+            credentials: RwLock::new(Credentials {
+                access_key_id,
+                secret_access_key,
+                session_token: None,
+            }),
+            credential_source: CredentialSource::Static,
             bucket_name,
             endpoint,
+            cache_dir: default_cache_dir(),
+            metrics: Metrics::default(),
+            part_stall_timeout: DEFAULT_PART_STALL_TIMEOUT,
+            retry_budget: RetryBudget::unlimited(),
+        })
+    }
+
+    /// Requests sent and bytes moved by this client so far.
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// The endpoint and bucket this client talks to.
+    pub fn bucket_url(&self) -> String {
+        format!("{}/{}", self.endpoint, self.bucket_name)
+    }
+
+    /// Send requests to `endpoint` instead of R2, such as a local mock server.
+    pub fn with_endpoint(mut self, endpoint: &str) -> Self {
+        self.endpoint = endpoint.to_string();
+        self
+    }
+
+    /// Record resumable transfer sessions under `dir` instead of the user's cache folder.
+    pub fn with_cache_dir(mut self, dir: &Path) -> Self {
+        self.cache_dir = Some(dir.to_path_buf());
+        self
+    }
+
+    /// Where resumable transfers made with this client record their sessions.
+    pub fn cache_dir(&self) -> Result<&Path> {
+        self.cache_dir
+            .as_deref()
+            .ok_or_else(|| anyhow!("No cache folder found for transfer sessions"))
+    }
+
+    /// Record where the credentials came from so they can be reloaded if R2 reports them expired.
+    pub fn with_credential_source(mut self, source: CredentialSource) -> Self {
+        self.credential_source = source;
+        self
+    }
+
+    /// Use these timeouts, per-request retries and endpoint instead of the defaults.
+    pub fn with_client_config(mut self, config: R2ClientConfig) -> Result<Self> {
+        self.client = config.build_client()?;
+        if let Some(endpoint) = &config.endpoint {
+            self.endpoint = endpoint.trim_end_matches('/').to_string();
+        }
+        self.client_config = config;
+        Ok(self)
+    }
+
+    /// How long a part upload may go without moving any data before it is retried.
+    pub fn with_part_stall_timeout(mut self, timeout: Duration) -> Self {
+        self.part_stall_timeout = timeout;
+        self
+    }
+
+    /// Share one retry budget and circuit breaker across every request this client sends.
+    /// Without it retries are unlimited, which suits clients that outlive one operation.
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_budget = RetryBudget::new(policy);
+        self
+    }
+
+    /// Spend one retry from the operation's budget before trying a request again. Fails
+    /// when the budget is used up or R2 looks unavailable, so the caller stops retrying.
+    pub fn start_retry(&self) -> Result<()> {
+        self.retry_budget.take_retry()?;
+        self.metrics.record_retry();
+        Ok(())
+    }
+
+    /// Reload credentials from the credential source. Returns false if the source is static.
+    pub fn refresh_credentials(&self) -> Result<bool> {
+        match self.credential_source.load()? {
+            Some(credentials) => {
+                *self.credentials.write().unwrap() = credentials;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// The host requests go to, with the port unless it is the scheme's default. It is
+    /// signed, so it must match the endpoint, such as a jurisdiction's or a local server's.
+    fn host(&self) -> Result<String> {
+        let endpoint = url::Url::parse(&self.endpoint).context("Invalid R2 endpoint")?;
+        let host = endpoint
+            .host_str()
+            .ok_or_else(|| anyhow!("R2 endpoint {} has no host", self.endpoint))?;
+        Ok(match endpoint.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.to_string(),
         })
     }
 
@@ -46,18 +1002,26 @@ impl R2Client {
         payload: &[u8],
         datetime: &DateTime<Utc>,
     ) -> Result<()> {
+        let credentials = self.credentials.read().unwrap().clone();
         let date_str = datetime.format("%Y%m%dT%H%M%SZ").to_string();
         let date_short = datetime.format("%Y%m%d").to_string();
 
-        let payload_hash = hex::encode(Sha256::digest(payload));
+        // A caller streaming a body it can't hash passes UNSIGNED_PAYLOAD in the header
+        let payload_hash = match headers.get("x-amz-content-sha256") {
+            Some(value) => value.to_str()?.to_string(),
+            None => hex::encode(Sha256::digest(payload)),
+        };
 
         headers.insert("x-amz-date", HeaderValue::from_str(&date_str)?);
         headers.insert(
             "x-amz-content-sha256",
             HeaderValue::from_str(&payload_hash)?,
         );
+        if let Some(token) = &credentials.session_token {
+            headers.insert("x-amz-security-token", HeaderValue::from_str(token)?);
+        }
 
-        let host = format!("{}.r2.cloudflarestorage.com", self.account_id);
+        let host = self.host()?;
         headers.insert("host", HeaderValue::from_str(&host)?);
 
         // Extract query string from path if present
@@ -67,17 +1031,33 @@ impl R2Client {
             (path, "")
         };
 
-        let canonical_headers = format!(
-            "host:{}\nx-amz-content-sha256:{}\nx-amz-date:{}",
-            host, payload_hash, date_str
-        );
+        // Sign host, every x-amz-* header (including user metadata) and any content
+        // headers that affect how the object is served, sorted by name
+        let mut signed: BTreeMap<String, String> = headers
+            .iter()
+            .filter(|(name, _)| {
+                name.as_str().starts_with("x-amz-")
+                    || SIGNED_CONTENT_HEADERS.contains(&name.as_str())
+            })
+            .map(|(name, value)| {
+                let value = canonical_header_value(value.to_str().unwrap_or_default());
+                (name.as_str().to_string(), value)
+            })
+            .collect();
+        signed.insert("host".to_string(), host);
 
-        let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+        let canonical_headers = signed
+            .iter()
+            .map(|(name, value)| format!("{}:{}", name, value))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let signed_headers = signed.keys().cloned().collect::<Vec<_>>().join(";");
 
         let canonical_request = format!(
             "{}\n{}\n{}\n{}\n\n{}\n{}",
             method.as_str(),
-            path_only,  // Path is already properly encoded by the caller
+            path_only, // Path is already properly encoded by the caller
             query_string,
             canonical_headers,
             signed_headers,
@@ -93,21 +1073,12 @@ impl R2Client {
             date_str, credential_scope, canonical_request_hash
         );
 
-        let mut key = format!("AWS4{}", self.secret_access_key).into_bytes();
-
-        for item in [date_short.as_bytes(), b"auto", b"s3", b"aws4_request"] {
-            let mut mac = HmacSha256::new_from_slice(&key)?;
-            mac.update(item);
-            key = mac.finalize().into_bytes().to_vec();
-        }
-
-        let mut mac = HmacSha256::new_from_slice(&key)?;
-        mac.update(string_to_sign.as_bytes());
-        let signature = hex::encode(mac.finalize().into_bytes());
+        let signature =
+            sigv4_signature(&credentials.secret_access_key, &date_short, &string_to_sign)?;
 
         let authorization = format!(
             "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
-            self.access_key_id, credential_scope, signed_headers, signature
+            credentials.access_key_id, credential_scope, signed_headers, signature
         );
 
         headers.insert("authorization", HeaderValue::from_str(&authorization)?);
@@ -115,175 +1086,1394 @@ impl R2Client {
         Ok(())
     }
 
+    /// A URL anyone can GET `key` from until `expires_in` has passed, without credentials.
+    pub fn presign_get_url(&self, key: &str, expires_in: Duration) -> Result<String> {
+        self.presign_url(Method::GET, key, expires_in)
+    }
 
-    pub async fn download_object(&self, key: &str) -> Result<Bytes> {
-        // Encode the key segments for both URL and canonical path
-        let encoded_key = key.split('/').map(|s| urlencoding::encode(s)).collect::<Vec<_>>().join("/");
-        // Build the path with encoded key for signing
-        let path = format!("/{}/{}", self.bucket_name, encoded_key);
-        // Build the URL
-        let url = format!("{}{}", self.endpoint, path);
+    /// A URL anyone can PUT an object to at `key` until `expires_in` has passed.
+    pub fn presign_put_url(&self, key: &str, expires_in: Duration) -> Result<String> {
+        self.presign_url(Method::PUT, key, expires_in)
+    }
 
-        let mut headers = HeaderMap::new();
+    /// Sign a request in its query string rather than its headers, so the URL alone
+    /// authorizes it. Only the host is signed and the payload isn't, so the URL works
+    /// for any body.
+    fn presign_url(&self, method: Method, key: &str, expires_in: Duration) -> Result<String> {
+        let expires = expires_in.as_secs();
+        if !(1..=MAX_PRESIGN_EXPIRY.as_secs()).contains(&expires) {
+            return Err(anyhow!(
+                "A presigned URL must expire after 1 second to 7 days, not {}s",
+                expires
+            ));
+        }
+
+        let credentials = self.credentials.read().unwrap().clone();
         let datetime = Utc::now();
+        let date_str = datetime.format("%Y%m%dT%H%M%SZ").to_string();
+        let date_short = datetime.format("%Y%m%d").to_string();
+        let credential_scope = format!("{}/auto/s3/aws4_request", date_short);
+        let host = self.host()?;
+        let path = self.object_path(key);
 
-        self.sign_request(&Method::GET, &path, &mut headers, b"", &datetime)?;
+        // Query parameters must be sorted by name for signing
+        let mut params = BTreeMap::new();
+        params.insert("X-Amz-Algorithm", "AWS4-HMAC-SHA256".to_string());
+        params.insert(
+            "X-Amz-Credential",
+            format!("{}/{}", credentials.access_key_id, credential_scope),
+        );
+        params.insert("X-Amz-Date", date_str.clone());
+        params.insert("X-Amz-Expires", expires.to_string());
+        if let Some(token) = &credentials.session_token {
+            params.insert("X-Amz-Security-Token", token.clone());
+        }
+        params.insert("X-Amz-SignedHeaders", "host".to_string());
+        let query_string = params
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, urlencoding::encode(value)))
+            .collect::<Vec<_>>()
+            .join("&");
 
-        let response = self
-            .client
-            .get(&url)
-            .headers(headers)
-            .send()
-            .await
-            .context("Failed to download object from R2")?;
+        let canonical_request = format!(
+            "{}\n{}\n{}\nhost:{}\n\nhost\n{}",
+            method.as_str(),
+            path,
+            query_string,
+            host,
+            UNSIGNED_PAYLOAD
+        );
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            date_str,
+            credential_scope,
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+        let signature =
+            sigv4_signature(&credentials.secret_access_key, &date_short, &string_to_sign)?;
+
+        Ok(format!(
+            "{}{}?{}&X-Amz-Signature={}",
+            self.endpoint, path, query_string, signature
+        ))
+    }
+
+    /// Sign and send a request, returning the response only if it succeeded.
+    /// When R2 rejects the credentials as expired or rotated, they are reloaded from
+    /// the credential source and the request is re-signed and retried once.
+    async fn send_signed<B>(
+        &self,
+        operation: &'static str,
+        method: Method,
+        path: &str,
+        payload: &[u8],
+        extra_headers: HeaderMap,
+        make_body: B,
+    ) -> Result<Response>
+    where
+        B: Fn() -> Option<reqwest::Body>,
+    {
+        // Object transfers can take as long as their size needs, but not stall
+        let stall_timeout = match request_kind(&method, path) {
+            RequestKind::Get | RequestKind::Put => self.client_config.request_timeout,
+            _ => None,
+        };
+        self.send_signed_with_retries(
+            operation,
+            method,
+            path,
+            payload,
+            extra_headers,
+            make_body,
+            true,
+            stall_timeout,
+        )
+        .await
+    }
+
+    /// `send_signed`, except that with `replayable` false the request is sent only once,
+    /// for bodies that can't be produced a second time: neither failures nor rejected
+    /// credentials are retried.
+    ///
+    /// With a `stall_timeout`, each attempt fails as stalled (and is retried like a
+    /// dropped connection) once its body, or a GET's response, moves no data for that
+    /// long. The clock starts when the attempt is sent, so back-off and circuit breaker
+    /// pauses don't count. Once the whole body is sent, R2's response only has to arrive
+    /// within `request_timeout`. Without one, `request_timeout` limits the whole request.
+    #[allow(clippy::too_many_arguments)]
+    async fn send_signed_with_retries<B>(
+        &self,
+        operation: &'static str,
+        method: Method,
+        path: &str,
+        payload: &[u8],
+        extra_headers: HeaderMap,
+        make_body: B,
+        replayable: bool,
+        stall_timeout: Option<Duration>,
+    ) -> Result<Response>
+    where
+        B: Fn() -> Option<reqwest::Body>,
+    {
+        let url = format!("{}{}", self.endpoint, path);
+        let kind = request_kind(&method, path);
+        let mut refreshed = false;
+        let mut retries = 0;
+        // Streamed bodies carry their size in Content-Length rather than the payload
+        let body_length = extra_headers
+            .get(CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok()?.parse().ok())
+            .unwrap_or(payload.len() as u64);
+
+        loop {
+            self.retry_budget.before_request().await?;
+            let mut headers = extra_headers.clone();
+            self.sign_request(&method, path, &mut headers, payload, &Utc::now())?;
+
+            let mut request = self.client.request(method.clone(), &url).headers(headers);
+            let body = make_body();
+            // A GET has no body; waiting for its response is part of the transfer
+            let progress = Arc::new(Mutex::new(TransferProgress {
+                at: Instant::now(),
+                body_sent: method != Method::GET && (body.is_none() || body_length == 0),
+            }));
+            if let Some(body) = body {
+                request = request.body(match stall_timeout {
+                    Some(_) => progress_tracked_body(body, body_length, progress.clone()),
+                    None => body,
+                });
+            }
+            if stall_timeout.is_none() {
+                if let Some(timeout) = self.client_config.request_timeout {
+                    request = request.timeout(timeout);
+                }
+            }
+
+            self.metrics.record_request(kind);
+            let sent = async { Ok(request.send().await?) };
+            let result = match stall_timeout {
+                Some(timeout) => {
+                    fail_on_stall(sent, &progress, timeout, self.client_config.request_timeout)
+                        .await
+                }
+                None => sent.await,
+            };
+            let response = match result {
+                Ok(response) => response,
+                Err(e) => {
+                    let e = e.context(format!("Failed to send {} request to R2", operation));
+                    if error::is_connection_error(&e) {
+                        self.retry_budget.record_failure();
+                        if replayable && retries < self.client_config.max_retries {
+                            retries += 1;
+                            self.back_off(operation, retries, &format!("{:#}", e))
+                                .await?;
+                            continue;
+                        }
+                    }
+                    return Err(e);
+                }
+            };
 
-        if !response.status().is_success() {
             let status = response.status();
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(anyhow!(
-                "R2 download failed with status {}: {}",
-                status,
-                error_text
-            ));
+            if status.is_server_error() {
+                self.retry_budget.record_failure();
+                if replayable && retries < self.client_config.max_retries {
+                    retries += 1;
+                    self.back_off(operation, retries, &format!("status {}", status))
+                        .await?;
+                    continue;
+                }
+            } else {
+                self.retry_budget.record_success();
+            }
+            if status.is_success() {
+                self.metrics.record_uploaded(body_length);
+                if method == Method::GET {
+                    self.metrics
+                        .record_downloaded(response.content_length().unwrap_or(0));
+                }
+                return Ok(match stall_timeout {
+                    Some(timeout) if method == Method::GET => with_idle_timeout(response, timeout),
+                    _ => response,
+                });
+            }
+
+            let body = response.text().await.unwrap_or_default();
+            let code = error::parse_error_code(&body);
+
+            if let Some(code) = code.as_deref().filter(|c| error::is_credential_error(c)) {
+                if replayable && !refreshed && self.credential_source.can_refresh() {
+                    tracing::info!("R2 rejected credentials ({}), reloading and retrying", code);
+                    self.refresh_credentials()?;
+                    self.start_retry()?;
+                    refreshed = true;
+                    continue;
+                }
+                return Err(R2Error::CredentialsExpired {
+                    code: code.to_string(),
+                }
+                .into());
+            }
+
+            return Err(R2Error::Service {
+                operation,
+                status: status.as_u16(),
+                code,
+                body,
+            }
+            .into());
         }
+    }
+
+    /// Spend a retry and wait before sending a failed request for the `retry`th time.
+    async fn back_off(&self, operation: &str, retry: u32, reason: &str) -> Result<()> {
+        self.start_retry()?;
+        let delay = RETRY_BASE_DELAY
+            .saturating_mul(1 << (retry - 1).min(16))
+            .min(RETRY_MAX_DELAY);
+        tracing::warn!(
+            "R2 {} request failed ({}), retrying in {:?} ({}/{})",
+            operation,
+            reason,
+            delay,
+            retry,
+            self.client_config.max_retries
+        );
+        tokio::time::sleep(delay).await;
+        Ok(())
+    }
+
+    fn object_path(&self, key: &str) -> String {
+        // Encode the key segments for both URL and canonical path
+        let encoded_key = key
+            .split('/')
+            .map(urlencoding::encode)
+            .collect::<Vec<_>>()
+            .join("/");
+        format!("/{}/{}", self.bucket_name, encoded_key)
+    }
+
+    pub async fn download_object(&self, key: &str) -> Result<Bytes> {
+        let (data, _) = self.download_object_with_metadata(key).await?;
+        Ok(data)
+    }
 
+    /// Download bytes `start..=end` of an object. The result is shorter if the object is.
+    pub async fn download_range(&self, key: &str, start: u64, end: u64) -> Result<Bytes> {
+        let path = self.object_path(key);
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            RANGE,
+            HeaderValue::from_str(&format!("bytes={}-{}", start, end))?,
+        );
+
+        let response = self
+            .send_signed("download", Method::GET, &path, b"", headers, || None)
+            .await?;
         let data = response
             .bytes()
             .await
             .context("Failed to read response body")?;
 
+        // A server that ignores Range sends the whole object
+        if data.len() as u64 > end - start + 1 {
+            let start = (start as usize).min(data.len());
+            let end = (end as usize + 1).min(data.len());
+            return Ok(data.slice(start..end));
+        }
         Ok(data)
     }
 
-    pub async fn upload_object(&self, key: &str, data: Bytes) -> Result<()> {
-        // Encode the key segments for both URL and canonical path
-        let encoded_key = key.split('/').map(|s| urlencoding::encode(s)).collect::<Vec<_>>().join("/");
-        // Build the path with encoded key for signing
-        let path = format!("/{}/{}", self.bucket_name, encoded_key);
-        // Build the URL
-        let url = format!("{}{}", self.endpoint, path);
+    /// Download an object along with its headers and user metadata.
+    pub async fn download_object_with_metadata(&self, key: &str) -> Result<(Bytes, ObjectHead)> {
+        let path = self.object_path(key);
+
+        let response = self
+            .send_signed(
+                "download",
+                Method::GET,
+                &path,
+                b"",
+                HeaderMap::new(),
+                || None,
+            )
+            .await?;
+
+        let head = ObjectHead::from_headers(response.headers());
+        let data = response
+            .bytes()
+            .await
+            .context("Failed to read response body")?;
 
+        Ok((data, head))
+    }
+
+    /// Download an object unless its ETag still matches `etag`, in which case R2 answers
+    /// 304 Not Modified and this returns None without transferring the body. `etag` is the
+    /// value from an earlier `ObjectHead`, quotes included.
+    pub async fn download_object_if_changed(
+        &self,
+        key: &str,
+        etag: Option<&str>,
+    ) -> Result<Option<(Bytes, ObjectHead)>> {
+        let Some(etag) = etag else {
+            return self.download_object_with_metadata(key).await.map(Some);
+        };
+        let path = self.object_path(key);
         let mut headers = HeaderMap::new();
-        let datetime = Utc::now();
+        headers.insert(IF_NONE_MATCH, HeaderValue::from_str(etag)?);
 
-        self.sign_request(&Method::PUT, &path, &mut headers, &data, &datetime)?;
+        let result = self
+            .send_signed("download", Method::GET, &path, b"", headers, || None)
+            .await;
+        let response = match result {
+            Ok(response) => response,
+            Err(e) if R2Error::from_anyhow(&e).and_then(R2Error::status) == Some(304) => {
+                return Ok(None)
+            }
+            Err(e) => return Err(e),
+        };
 
-        let response = self
-            .client
-            .put(&url)
-            .headers(headers)
-            .body(data)
-            .send()
+        let head = ObjectHead::from_headers(response.headers());
+        let data = response
+            .bytes()
             .await
-            .context("Failed to upload object to R2")?;
+            .context("Failed to read response body")?;
+        Ok(Some((data, head)))
+    }
 
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(anyhow!(
-                "R2 upload failed with status {}: {}",
-                status,
-                error_text
-            ));
+    pub async fn head_object(&self, key: &str) -> Result<ObjectHead> {
+        let path = self.object_path(key);
+
+        let response = self
+            .send_signed("head", Method::HEAD, &path, b"", HeaderMap::new(), || None)
+            .await?;
+
+        Ok(ObjectHead::from_headers(response.headers()))
+    }
+
+    /// True if `key` exists, false on a 404.
+    pub async fn object_exists(&self, key: &str) -> Result<bool> {
+        match self.head_object(key).await {
+            Ok(_) => Ok(true),
+            Err(e) if error::is_not_found(&e) => Ok(false),
+            Err(e) => Err(e),
         }
+    }
+
+    /// Copy an object within the bucket on the server side, keeping its metadata.
+    pub async fn copy_object(&self, source_key: &str, dest_key: &str) -> Result<()> {
+        let path = self.object_path(dest_key);
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            HeaderName::from_static("x-amz-copy-source"),
+            HeaderValue::from_str(&self.object_path(source_key))?,
+        );
+
+        self.send_signed("copy", Method::PUT, &path, b"", headers, || None)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn upload_object(&self, key: &str, data: Bytes) -> Result<()> {
+        self.upload_object_with_options(key, data, &UploadOptions::default())
+            .await?;
+        Ok(())
+    }
+
+    /// Upload an object, returning the ETag R2 assigned to it.
+    pub async fn upload_object_with_options(
+        &self,
+        key: &str,
+        data: Bytes,
+        options: &UploadOptions,
+    ) -> Result<Option<String>> {
+        self.put_object(key, &data, options, || reqwest::Body::from(data.clone()))
+            .await
+    }
 
+    /// Upload an object, calling `progress(bytes_sent, total_bytes)` as the body is streamed out.
+    pub async fn upload_object_with_progress<F>(
+        &self,
+        key: &str,
+        data: Bytes,
+        options: &UploadOptions,
+        progress: F,
+    ) -> Result<Option<String>>
+    where
+        F: Fn(u64, u64) + Send + Sync + 'static,
+    {
+        let progress = Arc::new(progress);
+        let total = data.len() as u64;
+
+        // Build a fresh stream per attempt so a retried request starts from zero
+        let make_body = || {
+            let progress = progress.clone();
+            streamed_body(&data, move |sent| progress(sent, total))
+        };
+
+        self.put_object(key, &data, options, make_body).await
+    }
+
+    /// Upload `content_length` bytes read from `reader` without holding them in memory.
+    /// See `upload_object_streaming_with_options`.
+    pub async fn upload_object_streaming<R>(
+        &self,
+        key: &str,
+        reader: R,
+        content_length: u64,
+    ) -> Result<()>
+    where
+        R: AsyncRead + Send + 'static,
+    {
+        self.upload_object_streaming_with_options(
+            key,
+            reader,
+            content_length,
+            &UploadOptions::default(),
+        )
+        .await?;
         Ok(())
     }
 
+    /// Upload `content_length` bytes read from `reader`, returning the ETag R2 assigned.
+    ///
+    /// The body is signed as `UNSIGNED-PAYLOAD` instead of by its SHA-256, which would
+    /// mean reading it twice. The signature still covers the key, headers and length, and
+    /// TLS protects the body in transit, but R2 can't check the body against the
+    /// signature. The reader can only be read once, so a failed upload isn't retried;
+    /// call again with a fresh reader. Single PUTs are limited to 5 GB.
+    pub async fn upload_object_streaming_with_options<R>(
+        &self,
+        key: &str,
+        reader: R,
+        content_length: u64,
+        options: &UploadOptions,
+    ) -> Result<Option<String>>
+    where
+        R: AsyncRead + Send + 'static,
+    {
+        self.upload_object_streaming_with_progress(key, reader, content_length, options, |_, _| {})
+            .await
+    }
+
+    /// Upload `content_length` bytes read from `reader` like
+    /// `upload_object_streaming_with_options`, calling `progress(bytes_sent, total_bytes)`
+    /// as each chunk of the body goes out.
+    pub async fn upload_object_streaming_with_progress<R, F>(
+        &self,
+        key: &str,
+        reader: R,
+        content_length: u64,
+        options: &UploadOptions,
+        progress: F,
+    ) -> Result<Option<String>>
+    where
+        R: AsyncRead + Send + 'static,
+        F: Fn(u64, u64) + Send + Sync + 'static,
+    {
+        let path = self.object_path(key);
+
+        let mut headers = upload_headers(options)?;
+        // R2 rejects chunked PUTs, so the length has to be known up front
+        headers.insert(CONTENT_LENGTH, HeaderValue::from(content_length));
+        headers.insert(
+            "x-amz-content-sha256",
+            HeaderValue::from_static(UNSIGNED_PAYLOAD),
+        );
+
+        let mut sent = 0u64;
+        let chunks = ReaderStream::new(reader).inspect(move |chunk| {
+            if let Ok(chunk) = chunk {
+                sent += chunk.len() as u64;
+                progress(sent, content_length);
+            }
+        });
+        let body = Mutex::new(Some(reqwest::Body::wrap_stream(chunks)));
+        let response = self
+            .send_signed_with_retries(
+                "upload",
+                Method::PUT,
+                &path,
+                b"",
+                headers,
+                || body.lock().unwrap().take(),
+                false,
+                self.client_config.request_timeout,
+            )
+            .await?;
+
+        Ok(response
+            .headers()
+            .get(ETAG)
+            .and_then(|v| v.to_str().ok())
+            .map(|etag| etag.trim_matches('"').to_string()))
+    }
+
+    async fn put_object<B>(
+        &self,
+        key: &str,
+        data: &[u8],
+        options: &UploadOptions,
+        make_body: B,
+    ) -> Result<Option<String>>
+    where
+        B: Fn() -> reqwest::Body,
+    {
+        let path = self.object_path(key);
+
+        let mut headers = upload_headers(options)?;
+        // Streamed bodies have no implicit length, and R2 rejects chunked PUTs
+        headers.insert(CONTENT_LENGTH, HeaderValue::from(data.len() as u64));
+
+        let response = self
+            .send_signed("upload", Method::PUT, &path, data, headers, || {
+                Some(make_body())
+            })
+            .await?;
+
+        Ok(response
+            .headers()
+            .get(ETAG)
+            .and_then(|v| v.to_str().ok())
+            .map(|etag| etag.trim_matches('"').to_string()))
+    }
+
     pub async fn list_objects(&self, prefix: Option<&str>) -> Result<Vec<String>> {
-        let query_params = if let Some(p) = prefix {
-            format!("list-type=2&prefix={}", urlencoding::encode(p))
-        } else {
-            "list-type=2".to_string()
+        let objects = self.list_objects_detailed(prefix).await?;
+        Ok(objects.into_iter().map(|o| o.key).collect())
+    }
+
+    /// List every object under `prefix` with its size and ETag, following continuation tokens.
+    pub async fn list_objects_detailed(&self, prefix: Option<&str>) -> Result<Vec<ObjectInfo>> {
+        let (objects, _) = self.list_all_pages(prefix, None).await?;
+        Ok(objects)
+    }
+
+    /// List one level below `prefix` (e.g. `photos/2024/`), with a `/` delimiter so R2
+    /// rolls deeper keys up into folders instead of returning them all.
+    pub async fn list_folder(&self, prefix: Option<&str>) -> Result<FolderListing> {
+        let (objects, folders) = self.list_all_pages(prefix, Some("/")).await?;
+        Ok(FolderListing { objects, folders })
+    }
+
+    /// Run a ListObjectsV2 listing to the end, returning the objects and common prefixes.
+    async fn list_all_pages(
+        &self,
+        prefix: Option<&str>,
+        delimiter: Option<&str>,
+    ) -> Result<(Vec<ObjectInfo>, Vec<String>)> {
+        let mut objects = Vec::new();
+        let mut common_prefixes = Vec::new();
+        let mut continuation_token: Option<String> = None;
+
+        loop {
+            // Query parameters must be sorted by name for signing
+            let mut params = BTreeMap::new();
+            params.insert("list-type", "2".to_string());
+            if let Some(p) = prefix {
+                params.insert("prefix", urlencoding::encode(p));
+            }
+            if let Some(d) = delimiter {
+                params.insert("delimiter", urlencoding::encode(d));
+            }
+            if let Some(token) = &continuation_token {
+                params.insert("continuation-token", urlencoding::encode(token));
+            }
+            let query_params = params
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect::<Vec<_>>()
+                .join("&");
+
+            let path = format!("/{}?{}", self.bucket_name, query_params);
+
+            let response = self
+                .send_signed("list", Method::GET, &path, b"", HeaderMap::new(), || None)
+                .await?;
+
+            let page = parse_list_response(response).await?;
+            objects.extend(page.objects);
+            common_prefixes.extend(page.common_prefixes);
+
+            match page.next_continuation_token {
+                Some(token) if page.is_truncated => continuation_token = Some(token),
+                _ => break,
+            }
+        }
+
+        Ok((objects, common_prefixes))
+    }
+
+    /// Stream an object to `path`, resuming from the end of the file if it already
+    /// holds a partial download. `on_chunk` is called with the size of each chunk written.
+    ///
+    /// The ETag a partial download came from is kept beside it (see `partial_etag_path`)
+    /// and sent as If-Range, so the rest is only appended if the object is unchanged. A
+    /// partial file without a recorded ETag, a 200 response, or a 206 for another ETag
+    /// starts the download over.
+    pub async fn download_object_to_file<F>(
+        &self,
+        key: &str,
+        path: &Path,
+        mut on_chunk: F,
+    ) -> Result<ObjectHead>
+    where
+        F: FnMut(u64),
+    {
+        let object_path = self.object_path(key);
+        let etag_path = partial_etag_path(path);
+        let mut existing = tokio::fs::metadata(path)
+            .await
+            .map(|m| m.len())
+            .unwrap_or(0);
+        let partial_etag = tokio::fs::read_to_string(&etag_path).await.ok();
+        if existing > 0 && partial_etag.is_none() {
+            tracing::info!(
+                "No ETag recorded for {}, downloading it again",
+                path.display()
+            );
+            existing = 0;
+        }
+
+        let mut headers = HeaderMap::new();
+        if let (true, Some(etag)) = (existing > 0, &partial_etag) {
+            headers.insert(
+                RANGE,
+                HeaderValue::from_str(&format!("bytes={}-", existing))?,
+            );
+            headers.insert(IF_RANGE, HeaderValue::from_str(etag)?);
+        }
+
+        let result = self
+            .send_signed("download", Method::GET, &object_path, b"", headers, || None)
+            .await;
+
+        let mut response = match result {
+            Ok(response) => response,
+            // The partial file is already complete (or longer than the object); start over
+            Err(e)
+                if existing > 0
+                    && R2Error::from_anyhow(&e).and_then(R2Error::status) == Some(416) =>
+            {
+                self.send_signed(
+                    "download",
+                    Method::GET,
+                    &object_path,
+                    b"",
+                    HeaderMap::new(),
+                    || None,
+                )
+                .await?
+            }
+            Err(e) => return Err(e),
+        };
+
+        // A server that ignored If-Range may send the rest of a different object
+        let etag = |response: &Response| {
+            response
+                .headers()
+                .get(ETAG)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string)
         };
+        if response.status() == StatusCode::PARTIAL_CONTENT && etag(&response) != partial_etag {
+            tracing::info!("{} changed since the partial download, starting over", key);
+            response = self
+                .send_signed(
+                    "download",
+                    Method::GET,
+                    &object_path,
+                    b"",
+                    HeaderMap::new(),
+                    || None,
+                )
+                .await?;
+        }
+
+        // A 200 instead of 206 means the server ignored the range, so rewrite from the start
+        let resumed = response.status() == StatusCode::PARTIAL_CONTENT;
+        if resumed {
+            on_chunk(existing);
+        }
+        let mut head = ObjectHead::from_headers(response.headers());
+        if resumed {
+            head.content_length = head.content_length.map(|len| len + existing);
+        }
 
-        let path = format!("/{}?{}", self.bucket_name, query_params);
+        // Record what the partial file holds before any of it is written
+        match etag(&response) {
+            Some(etag) if !resumed => tokio::fs::write(&etag_path, etag)
+                .await
+                .context("Failed to record the ETag of the download")?,
+            Some(_) => {}
+            None => {
+                let _ = tokio::fs::remove_file(&etag_path).await;
+            }
+        }
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(resumed)
+            .truncate(!resumed)
+            .open(path)
+            .await
+            .context("Failed to open download file")?;
+
+        let mut body = response.bytes_stream();
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk.context("Failed to read response body")?;
+            file.write_all(&chunk).await?;
+            on_chunk(chunk.len() as u64);
+        }
+        file.flush().await?;
+        let _ = tokio::fs::remove_file(&etag_path).await;
+
+        Ok(head)
+    }
+
+    /// Check that the endpoint resolves and the bucket is accessible with a signed
+    /// bucket HEAD. Connectivity and auth problems are reported in the diagnosis
+    /// rather than as errors.
+    pub async fn ping(&self) -> Result<PingInfo> {
+        let endpoint = url::Url::parse(&self.endpoint).context("Invalid R2 endpoint")?;
+        let host = endpoint.host_str().unwrap_or_default().to_string();
+        let port = endpoint.port_or_known_default().unwrap_or(443);
+        let mut info = PingInfo {
+            endpoint: self.endpoint.clone(),
+            addresses: Vec::new(),
+            latency: None,
+            diagnosis: PingDiagnosis::Ok,
+        };
+
+        match tokio::net::lookup_host((host.as_str(), port)).await {
+            Ok(addrs) => info.addresses = addrs.map(|a| a.ip()).collect(),
+            Err(e) => {
+                info.diagnosis = PingDiagnosis::DnsFailure(e.to_string());
+                return Ok(info);
+            }
+        }
+
+        let path = format!("/{}", self.bucket_name);
         let url = format!("{}{}", self.endpoint, path);
+        let mut headers = HeaderMap::new();
+        self.sign_request(&Method::HEAD, &path, &mut headers, b"", &Utc::now())?;
+
+        let started = Instant::now();
+        match self.client.head(&url).headers(headers).send().await {
+            Ok(response) => {
+                info.latency = Some(started.elapsed());
+                info.diagnosis = PingDiagnosis::from_status(response.status());
+            }
+            Err(e) => {
+                info.diagnosis = PingDiagnosis::ConnectionFailed(e.to_string());
+            }
+        }
+
+        Ok(info)
+    }
+
+    /// List at most one key, which only succeeds if the credentials are valid and allowed
+    /// to list the bucket. Used to check new credentials before they are saved.
+    pub async fn verify_credentials(&self) -> Result<()> {
+        let path = format!("/{}?list-type=2&max-keys=1", self.bucket_name);
+        self.send_signed("list", Method::GET, &path, b"", HeaderMap::new(), || None)
+            .await?;
+        Ok(())
+    }
+
+    /// Find out which of list, read and delete are allowed under `prefix` without
+    /// touching any real object: a one-key listing, then a HEAD and a DELETE of a random
+    /// key that doesn't exist. The HEAD answers 404 and the DELETE 204 when allowed, and
+    /// both are refused with 401/403 otherwise. In a versioned bucket the DELETE leaves a
+    /// delete marker on the probe key, which is removed again straight away. `None` means
+    /// the probe couldn't tell: a 404 on the listing means the bucket itself wasn't found,
+    /// so nothing can be told, and if the probe key turns out to exist it isn't this run's
+    /// to delete.
+    pub async fn probe_permissions(&self, prefix: &str) -> Result<Vec<(Permission, Option<bool>)>> {
+        let id: [u8; 16] = rand::random();
+        let probe_key = format!("{}.r2-permission-probe-{}", prefix, hex::encode(id));
+        let list_path = format!(
+            "/{}?list-type=2&max-keys=1&prefix={}",
+            self.bucket_name,
+            urlencoding::encode(prefix)
+        );
+        let object_path = self.object_path(&probe_key);
+
+        let list = match self.send_probe(Method::GET, &list_path).await? {
+            Ok(_) => Some(true),
+            Err(404) => {
+                return Ok(vec![
+                    (Permission::List, None),
+                    (Permission::Read, None),
+                    (Permission::Delete, None),
+                ])
+            }
+            Err(_) => Some(false),
+        };
+
+        let head = self.send_probe(Method::HEAD, &object_path).await?;
+        let read = Some(matches!(head, Ok(_) | Err(404)));
+
+        let delete = if head.is_ok() {
+            None
+        } else {
+            match self.send_probe(Method::DELETE, &object_path).await? {
+                Ok(response) => {
+                    if let Some(version_id) = delete_marker_version(&response) {
+                        self.remove_probe_marker(&probe_key, &version_id).await;
+                    }
+                    Some(true)
+                }
+                Err(status) => Some(status == 404),
+            }
+        };
+
+        Ok(vec![
+            (Permission::List, list),
+            (Permission::Read, read),
+            (Permission::Delete, delete),
+        ])
+    }
+
+    /// Send one permission probe. A refusal or a 404 comes back as its status.
+    async fn send_probe(
+        &self,
+        method: Method,
+        path: &str,
+    ) -> Result<std::result::Result<Response, u16>> {
+        let result = self
+            .send_signed(
+                "permission probe",
+                method,
+                path,
+                b"",
+                HeaderMap::new(),
+                || None,
+            )
+            .await;
+        match result {
+            Ok(response) => Ok(Ok(response)),
+            Err(e) => match R2Error::from_anyhow(&e).and_then(R2Error::status) {
+                Some(status @ (401 | 403 | 404)) => Ok(Err(status)),
+                _ => Err(e),
+            },
+        }
+    }
+
+    /// Delete the marker a permission probe left in a versioned bucket. Failing only
+    /// leaves an empty marker behind, so it is logged rather than returned.
+    async fn remove_probe_marker(&self, probe_key: &str, version_id: &str) {
+        let path = format!(
+            "{}?versionId={}",
+            self.object_path(probe_key),
+            urlencoding::encode(version_id)
+        );
+        let result = self
+            .send_signed(
+                "permission probe",
+                Method::DELETE,
+                &path,
+                b"",
+                HeaderMap::new(),
+                || None,
+            )
+            .await;
+        if let Err(e) = result {
+            tracing::warn!(
+                "Could not remove the delete marker on {}: {:#}",
+                probe_key,
+                e
+            );
+        }
+    }
+
+    /// Fail unless every permission in `needed` is allowed under `prefix`, naming the
+    /// missing ones, or the ones the probe couldn't confirm. Run before a destructive batch so it never stops partway through.
+    pub async fn check_permissions(&self, prefix: &str, needed: &[Permission]) -> Result<()> {
+        let permissions = self.probe_permissions(prefix).await?;
+        let with = |outcome: Option<bool>| -> Vec<String> {
+            permissions
+                .iter()
+                .filter(|(permission, allowed)| *allowed == outcome && needed.contains(permission))
+                .map(|(permission, _)| permission.to_string())
+                .collect()
+        };
+        let (missing, unknown) = (with(Some(false)), with(None));
+        if !missing.is_empty() {
+            Err(anyhow!(
+                "Missing {} permission for '{}' in bucket {}",
+                missing.join(" and "),
+                prefix,
+                self.bucket_name
+            ))
+        } else if !unknown.is_empty() {
+            Err(anyhow!(
+                "Could not confirm {} permission for '{}' in bucket {}",
+                unknown.join(" and "),
+                prefix,
+                self.bucket_name
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub async fn delete_object(&self, key: &str) -> Result<()> {
+        let path = self.object_path(key);
 
+        let result = self
+            .send_signed(
+                "delete",
+                Method::DELETE,
+                &path,
+                b"",
+                HeaderMap::new(),
+                || None,
+            )
+            .await;
+
+        match result {
+            Ok(_) => Ok(()),
+            // Deleting an object that is already gone counts as success
+            Err(e) if error::is_not_found(&e) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Set a canned ACL on `key` with `x-amz-acl`.
+    pub async fn put_object_acl(&self, key: &str, acl: CannedAcl) -> Result<()> {
+        let path = format!("{}?acl=", self.object_path(key));
         let mut headers = HeaderMap::new();
-        let datetime = Utc::now();
+        headers.insert("x-amz-acl", HeaderValue::from_static(acl.as_str()));
+
+        self.send_signed("put object ACL", Method::PUT, &path, b"", headers, || None)
+            .await
+            .map_err(acl_unsupported)?;
+        Ok(())
+    }
 
-        self.sign_request(&Method::GET, &path, &mut headers, b"", &datetime)?;
+    /// Read back the ACL of `key`, reduced to the canned ACL it amounts to.
+    pub async fn get_object_acl(&self, key: &str) -> Result<CannedAcl> {
+        let path = format!("{}?acl=", self.object_path(key));
 
         let response = self
-            .client
-            .get(&url)
-            .headers(headers)
-            .send()
+            .send_signed(
+                "get object ACL",
+                Method::GET,
+                &path,
+                b"",
+                HeaderMap::new(),
+                || None,
+            )
+            .await
+            .map_err(acl_unsupported)?;
+        let text = response
+            .text()
             .await
-            .context("Failed to list objects in R2")?;
+            .context("Failed to read response body")?;
+        Ok(parse_acl_response(&text))
+    }
 
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_default();
+    /// Delete `keys` with one DeleteObjects request per `DELETE_BATCH_LIMIT` keys. Keys R2
+    /// refuses are reported in the outcome rather than failing the whole call; a request
+    /// that fails outright is an error.
+    pub async fn delete_objects(&self, keys: &[String]) -> Result<DeleteOutcome> {
+        use base64::Engine;
+        use md5::Md5;
+
+        let path = format!("/{}?delete=", self.bucket_name);
+        let mut outcome = DeleteOutcome::default();
+
+        for batch in keys.chunks(DELETE_BATCH_LIMIT) {
+            let body = delete_objects_body(batch);
+            let mut headers = HeaderMap::new();
+            // DeleteObjects requires a checksum of the body
+            let md5 = base64::engine::general_purpose::STANDARD.encode(Md5::digest(&body));
+            headers.insert("content-md5", HeaderValue::from_str(&md5)?);
+            headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/xml"));
+
+            let response = self
+                .send_signed(
+                    "delete objects",
+                    Method::POST,
+                    &path,
+                    body.as_bytes(),
+                    headers,
+                    || Some(reqwest::Body::from(body.clone())),
+                )
+                .await?;
+            let text = response
+                .text()
+                .await
+                .context("Failed to read response body")?;
+
+            let errors = parse_delete_errors(&text)?;
+            outcome.deleted.extend(
+                batch
+                    .iter()
+                    .filter(|key| !errors.iter().any(|(failed, _)| failed == *key))
+                    .cloned(),
+            );
+            outcome.failed.extend(errors);
+        }
+
+        Ok(outcome)
+    }
+
+    /// List every version and delete marker under `prefix`, following the key and
+    /// version markers. Fails with a clear message if the bucket isn't versioned.
+    pub async fn list_object_versions(&self, prefix: Option<&str>) -> Result<Vec<ObjectVersion>> {
+        let mut versions = Vec::new();
+        let mut key_marker: Option<String> = None;
+        let mut version_id_marker: Option<String> = None;
+
+        loop {
+            // Query parameters must be sorted by name for signing
+            let mut params = BTreeMap::new();
+            params.insert("versions", String::new());
+            if let Some(p) = prefix {
+                params.insert("prefix", urlencoding::encode(p));
+            }
+            if let Some(marker) = &key_marker {
+                params.insert("key-marker", urlencoding::encode(marker));
+            }
+            if let Some(marker) = &version_id_marker {
+                params.insert("version-id-marker", urlencoding::encode(marker));
+            }
+            let query_params = params
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect::<Vec<_>>()
+                .join("&");
+
+            let path = format!("/{}?{}", self.bucket_name, query_params);
+
+            let response = self
+                .send_signed(
+                    "list versions",
+                    Method::GET,
+                    &path,
+                    b"",
+                    HeaderMap::new(),
+                    || None,
+                )
+                .await
+                .map_err(versioning_unsupported)?;
+
+            let xml_text = response.text().await?;
+            let page = parse_versions_response(&xml_text)?;
+            versions.extend(page.versions);
+
+            match page.next_key_marker {
+                Some(marker) if page.is_truncated => {
+                    key_marker = Some(marker);
+                    version_id_marker = page.next_version_id_marker;
+                }
+                _ => break,
+            }
+        }
+
+        Ok(versions)
+    }
+
+    /// Undelete `key` by removing the delete marker that hides its previous version.
+    /// Returns the version ID of the removed marker.
+    pub async fn restore_object(&self, key: &str) -> Result<String> {
+        let versions: Vec<ObjectVersion> = self
+            .list_object_versions(Some(key))
+            .await?
+            .into_iter()
+            .filter(|v| v.key == key)
+            .collect();
+
+        if versions.is_empty() {
+            return Err(anyhow!("No versions of {} found", key));
+        }
+        // An unversioned bucket only ever reports the "null" version, with no markers
+        if versions
+            .iter()
+            .all(|v| v.version_id == "null" && !v.is_delete_marker)
+        {
             return Err(anyhow!(
-                "R2 list failed with status {}: {}",
-                status,
-                error_text
+                "Bucket is not versioned, so {} cannot be restored",
+                key
             ));
         }
 
-        let xml_text = response.text().await?;
+        let marker = match versions.iter().find(|v| v.is_latest) {
+            Some(latest) if latest.is_delete_marker => latest,
+            Some(_) => return Err(anyhow!("{} is not deleted", key)),
+            None => return Err(anyhow!("No delete marker found for {}", key)),
+        };
 
-        let mut reader = quick_xml::Reader::from_str(&xml_text);
-        let mut objects = Vec::new();
-        let mut in_key = false;
-        let mut buf = Vec::new();
+        let path = format!(
+            "{}?versionId={}",
+            self.object_path(key),
+            urlencoding::encode(&marker.version_id)
+        );
+        self.send_signed(
+            "restore",
+            Method::DELETE,
+            &path,
+            b"",
+            HeaderMap::new(),
+            || None,
+        )
+        .await?;
+
+        Ok(marker.version_id.clone())
+    }
+
+    /// Start a multipart upload, returning its upload ID.
+    pub async fn create_multipart_upload(
+        &self,
+        key: &str,
+        options: &UploadOptions,
+    ) -> Result<String> {
+        let path = format!("{}?uploads=", self.object_path(key));
+        let headers = upload_headers(options)?;
+
+        let response = self
+            .send_signed(
+                "create multipart upload",
+                Method::POST,
+                &path,
+                b"",
+                headers,
+                || None,
+            )
+            .await?;
+        let body = response
+            .text()
+            .await
+            .context("Failed to read response body")?;
+
+        xml_element(&body, "UploadId")
+            .ok_or_else(|| anyhow!("R2 did not return an upload ID: {}", body))
+    }
+
+    /// Upload one part of a multipart upload, returning the ETag R2 assigned it.
+    pub async fn upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        part_number: u32,
+        data: Bytes,
+    ) -> Result<String> {
+        let path = format!(
+            "{}?partNumber={}&uploadId={}",
+            self.object_path(key),
+            part_number,
+            urlencoding::encode(upload_id)
+        );
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_LENGTH, HeaderValue::from(data.len() as u64));
+
+        // A connection that stops taking the part is noticed after the part stall timeout,
+        // well before any overall limit, and the part is sent again
+        let response = self
+            .send_signed_with_retries(
+                "upload part",
+                Method::PUT,
+                &path,
+                &data,
+                headers,
+                || Some(reqwest::Body::from(data.clone())),
+                true,
+                Some(self.part_stall_timeout),
+            )
+            .await?;
+
+        response
+            .headers()
+            .get(ETAG)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string)
+            .ok_or_else(|| anyhow!("R2 did not return an ETag for part {}", part_number))
+    }
+
+    /// The parts R2 holds for an unfinished multipart upload, following the part-number
+    /// marker across pages. Fails with a NoSuchUpload service error once the upload has
+    /// been completed, aborted or expired.
+    pub async fn list_parts(&self, key: &str, upload_id: &str) -> Result<Vec<CompletedPart>> {
+        let mut parts = Vec::new();
+        let mut marker: Option<String> = None;
 
         loop {
-            match reader.read_event_into(&mut buf) {
-                Ok(quick_xml::events::Event::Start(ref e)) if e.name().as_ref() == b"Key" => {
-                    in_key = true;
-                }
-                Ok(quick_xml::events::Event::Text(ref e)) if in_key => {
-                    objects.push(e.unescape()?.to_string());
-                }
-                Ok(quick_xml::events::Event::End(ref e)) if e.name().as_ref() == b"Key" => {
-                    in_key = false;
+            let mut path = format!("{}?", self.object_path(key));
+            if let Some(marker) = &marker {
+                path.push_str(&format!("part-number-marker={}&", marker));
+            }
+            path.push_str(&format!("uploadId={}", urlencoding::encode(upload_id)));
+
+            let response = self
+                .send_signed(
+                    "list parts",
+                    Method::GET,
+                    &path,
+                    b"",
+                    HeaderMap::new(),
+                    || None,
+                )
+                .await?;
+            let text = response
+                .text()
+                .await
+                .context("Failed to read response body")?;
+
+            for part in text.split("<Part>").skip(1) {
+                let number = xml_element(part, "PartNumber").and_then(|n| n.parse().ok());
+                if let (Some(part_number), Some(etag)) = (number, xml_element(part, "ETag")) {
+                    parts.push(CompletedPart {
+                        part_number,
+                        etag: etag.replace("&quot;", "\""),
+                    });
                 }
-                Ok(quick_xml::events::Event::Eof) => break,
-                Err(e) => return Err(anyhow!("XML parsing error: {}", e)),
-                _ => {}
             }
-            buf.clear();
+
+            marker = xml_element(&text, "NextPartNumberMarker");
+            if xml_element(&text, "IsTruncated").as_deref() != Some("true") || marker.is_none() {
+                break;
+            }
         }
 
-        Ok(objects)
+        Ok(parts)
     }
 
-    pub async fn delete_object(&self, key: &str) -> Result<()> {
-        // Encode the key segments for both URL and canonical path
-        let encoded_key = key.split('/').map(|s| urlencoding::encode(s)).collect::<Vec<_>>().join("/");
-        // Build the path with encoded key for signing
-        let path = format!("/{}/{}", self.bucket_name, encoded_key);
-        // Build the URL
-        let url = format!("{}{}", self.endpoint, path);
+    /// Every multipart upload under `prefix` that is still in progress, following the
+    /// key and upload ID markers. Interrupted uploads that were never resumed or aborted
+    /// show up here, oldest first within each key.
+    pub async fn list_multipart_uploads(
+        &self,
+        prefix: Option<&str>,
+    ) -> Result<Vec<MultipartUploadInfo>> {
+        let mut uploads = Vec::new();
+        let mut key_marker: Option<String> = None;
+        let mut upload_id_marker: Option<String> = None;
 
-        let mut headers = HeaderMap::new();
-        let datetime = Utc::now();
+        loop {
+            // Query parameters must be sorted by name for signing
+            let mut params = BTreeMap::new();
+            params.insert("uploads", String::new());
+            if let Some(p) = prefix {
+                params.insert("prefix", urlencoding::encode(p));
+            }
+            if let Some(marker) = &key_marker {
+                params.insert("key-marker", urlencoding::encode(marker));
+            }
+            if let Some(marker) = &upload_id_marker {
+                params.insert("upload-id-marker", urlencoding::encode(marker));
+            }
+            let query_params = params
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect::<Vec<_>>()
+                .join("&");
+
+            let path = format!("/{}?{}", self.bucket_name, query_params);
+
+            let response = self
+                .send_signed(
+                    "list multipart uploads",
+                    Method::GET,
+                    &path,
+                    b"",
+                    HeaderMap::new(),
+                    || None,
+                )
+                .await?;
 
-        self.sign_request(&Method::DELETE, &path, &mut headers, b"", &datetime)?;
+            let xml_text = response
+                .text()
+                .await
+                .context("Failed to read response body")?;
+            let page = parse_uploads_response(&xml_text)?;
+            uploads.extend(page.uploads);
+
+            match page.next_key_marker {
+                Some(marker) if page.is_truncated => {
+                    key_marker = Some(marker);
+                    upload_id_marker = page.next_upload_id_marker;
+                }
+                _ => break,
+            }
+        }
+
+        Ok(uploads)
+    }
+
+    /// Assemble the uploaded parts into the final object.
+    pub async fn complete_multipart_upload(
+        &self,
+        key: &str,
+        upload_id: &str,
+        parts: &[CompletedPart],
+    ) -> Result<()> {
+        let path = format!(
+            "{}?uploadId={}",
+            self.object_path(key),
+            urlencoding::encode(upload_id)
+        );
+        let body = complete_multipart_body(parts);
 
         let response = self
-            .client
-            .delete(&url)
-            .headers(headers)
-            .send()
-            .await
-            .context("Failed to delete object from R2")?;
+            .send_signed(
+                "complete multipart upload",
+                Method::POST,
+                &path,
+                body.as_bytes(),
+                HeaderMap::new(),
+                || Some(reqwest::Body::from(body.clone())),
+            )
+            .await?;
 
-        if !response.status().is_success() && response.status().as_u16() != 404 {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(anyhow!(
-                "R2 delete failed with status {}: {}",
+        // Completion can fail after the 200 status has been sent, with the error in the body
+        let status = response.status().as_u16();
+        let text = response
+            .text()
+            .await
+            .context("Failed to read response body")?;
+        if text.contains("<Error>") {
+            return Err(R2Error::Service {
+                operation: "complete multipart upload",
                 status,
-                error_text
-            ));
+                code: error::parse_error_code(&text),
+                body: text,
+            }
+            .into());
         }
-
         Ok(())
     }
+
+    /// Discard a multipart upload and any parts already stored for it.
+    pub async fn abort_multipart_upload(&self, key: &str, upload_id: &str) -> Result<()> {
+        let path = format!(
+            "{}?uploadId={}",
+            self.object_path(key),
+            urlencoding::encode(upload_id)
+        );
+
+        let result = self
+            .send_signed(
+                "abort multipart upload",
+                Method::DELETE,
+                &path,
+                b"",
+                HeaderMap::new(),
+                || None,
+            )
+            .await;
+
+        match result {
+            Ok(_) => Ok(()),
+            // Already completed or aborted
+            Err(e) if error::is_not_found(&e) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
 }
 
 #[allow(dead_code)]
@@ -291,7 +2481,12 @@ mod urlencoding {
     pub fn encode(s: &str) -> String {
         s.bytes()
             .map(|byte| {
-                if byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_' || byte == b'.' || byte == b'~' {
+                if byte.is_ascii_alphanumeric()
+                    || byte == b'-'
+                    || byte == b'_'
+                    || byte == b'.'
+                    || byte == b'~'
+                {
                     char::from(byte).to_string()
                 } else {
                     format!("%{:02X}", byte)
@@ -299,4 +2494,657 @@ mod urlencoding {
             })
             .collect()
     }
+
+    pub fn decode(s: &str) -> String {
+        let bytes = s.as_bytes();
+        let mut decoded = Vec::with_capacity(bytes.len());
+        let mut i = 0;
+        while i < bytes.len() {
+            let hex = bytes
+                .get(i + 1..i + 3)
+                .and_then(|h| std::str::from_utf8(h).ok())
+                .and_then(|h| u8::from_str_radix(h, 16).ok());
+            match (bytes[i], hex) {
+                (b'%', Some(byte)) => {
+                    decoded.push(byte);
+                    i += 3;
+                }
+                (byte, _) => {
+                    decoded.push(byte);
+                    i += 1;
+                }
+            }
+        }
+        String::from_utf8_lossy(&decoded).into_owned()
+    }
+}
+
+/// A header value as SigV4 canonicalizes it ("trimall"): trimmed, with each run of
+/// whitespace inside it collapsed to one space. R2 signs what it receives this way, so a
+/// value such as metadata typed with two spaces only verifies if it is collapsed too.
+fn canonical_header_value(value: &str) -> String {
+    value.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// The version ID of the delete marker a DELETE created, if it created one.
+fn delete_marker_version(response: &Response) -> Option<String> {
+    let headers = response.headers();
+    let is_marker = headers
+        .get("x-amz-delete-marker")
+        .is_some_and(|v| v.as_bytes().eq_ignore_ascii_case(b"true"));
+    headers
+        .get("x-amz-version-id")
+        .and_then(|v| v.to_str().ok())
+        .filter(|_| is_marker)
+        .map(str::to_string)
+}
+
+/// A request body that streams `data` in chunks, calling `on_sent` with the bytes sent
+/// so far as each chunk goes out.
+fn streamed_body<F>(data: &Bytes, mut on_sent: F) -> reqwest::Body
+where
+    F: FnMut(u64) + Send + Sync + 'static,
+{
+    let chunks: Vec<Bytes> = (0..data.len())
+        .step_by(UPLOAD_CHUNK_SIZE)
+        .map(|start| data.slice(start..(start + UPLOAD_CHUNK_SIZE).min(data.len())))
+        .collect();
+    let mut sent = 0u64;
+    reqwest::Body::wrap_stream(stream::iter(chunks).map(move |chunk| {
+        sent += chunk.len() as u64;
+        on_sent(sent);
+        Ok::<Bytes, std::io::Error>(chunk)
+    }))
+}
+
+/// When a request last moved data, and whether its whole body has been handed over.
+#[derive(Clone, Copy)]
+struct TransferProgress {
+    at: Instant,
+    body_sent: bool,
+}
+
+/// `body` cut into chunks of at most `UPLOAD_CHUNK_SIZE`, noting in `progress` each time
+/// the connection takes the next one, and when all `length` bytes have been taken.
+fn progress_tracked_body(
+    body: reqwest::Body,
+    length: u64,
+    progress: Arc<Mutex<TransferProgress>>,
+) -> reqwest::Body {
+    let mut taken = 0u64;
+    let chunks = BodyDataStream::new(body)
+        .flat_map(|frame| {
+            let chunks: Vec<Result<Bytes, reqwest::Error>> = match frame {
+                Ok(data) => (0..data.len())
+                    .step_by(UPLOAD_CHUNK_SIZE)
+                    .map(|start| {
+                        let end = (start + UPLOAD_CHUNK_SIZE).min(data.len());
+                        Ok(data.slice(start..end))
+                    })
+                    .collect(),
+                Err(e) => vec![Err(e)],
+            };
+            stream::iter(chunks)
+        })
+        .inspect(move |chunk| {
+            taken += chunk.as_ref().map_or(0, |chunk| chunk.len() as u64);
+            let mut progress = progress.lock().unwrap();
+            progress.at = Instant::now();
+            progress.body_sent = taken >= length;
+        });
+    reqwest::Body::wrap_stream(chunks)
+}
+
+/// `response` with a body that fails with `TransferStalled` once nothing has arrived for
+/// `timeout` while it is being read.
+fn with_idle_timeout(response: Response, timeout: Duration) -> Response {
+    let (parts, body) = http::Response::from(response).into_parts();
+    let chunks = stream::unfold(Some(BodyDataStream::new(body)), move |chunks| async move {
+        let mut chunks = chunks?;
+        match tokio::time::timeout(timeout, chunks.next()).await {
+            Ok(Some(chunk)) => Some((chunk.map_err(std::io::Error::other), Some(chunks))),
+            Ok(None) => None,
+            Err(_) => {
+                let stalled =
+                    std::io::Error::new(std::io::ErrorKind::TimedOut, TransferStalled(timeout));
+                Some((Err(stalled), None))
+            }
+        }
+    });
+    Response::from(http::Response::from_parts(
+        parts,
+        reqwest::Body::wrap_stream(chunks),
+    ))
+}
+
+/// Wait for `request`, failing with `TransferStalled` once `progress` is more than
+/// `timeout` in the past. Whatever moves the request along must keep it up to date. Once
+/// the body is sent, the response is given `response_timeout` from then instead.
+async fn fail_on_stall<T>(
+    request: impl std::future::Future<Output = Result<T>>,
+    progress: &Mutex<TransferProgress>,
+    timeout: Duration,
+    response_timeout: Option<Duration>,
+) -> Result<T> {
+    tokio::pin!(request);
+    loop {
+        let TransferProgress { at, body_sent } = *progress.lock().unwrap();
+        let limit = match (body_sent, response_timeout) {
+            (false, _) => timeout,
+            (true, Some(response_timeout)) => response_timeout,
+            (true, None) => return request.await,
+        };
+        let idle = at.elapsed();
+        if idle >= limit {
+            return Err(TransferStalled(limit).into());
+        }
+        tokio::select! {
+            result = &mut request => return result,
+            _ = tokio::time::sleep(limit - idle) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_r2::MockR2;
+    use crate::test_support::TempDir;
+
+    #[tokio::test]
+    async fn caching_headers_round_trip_through_upload_and_head() {
+        let mock = MockR2::start().await;
+        let client = mock.client().await;
+        let expires = DateTime::parse_from_rfc3339("2030-06-01T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let options = UploadOptions {
+            cache_control: Some("public, max-age=86400, stale-if-error=\"60\"".to_string()),
+            expires: Some(expires),
+            ..Default::default()
+        };
+        client
+            .upload_object_with_options("site/app.js", Bytes::from_static(b"app"), &options)
+            .await
+            .unwrap();
+
+        let head = client.head_object("site/app.js").await.unwrap();
+        assert_eq!(
+            head.cache_control.as_deref(),
+            Some("public, max-age=86400, stale-if-error=\"60\"")
+        );
+        assert_eq!(
+            head.expires.as_deref(),
+            Some("Sat, 01 Jun 2030 12:00:00 GMT")
+        );
+
+        // A malformed directive is refused before anything is sent
+        let options = UploadOptions {
+            cache_control: Some("max-age=86400; public".to_string()),
+            ..Default::default()
+        };
+        let error = client
+            .upload_object_with_options("site/bad.js", Bytes::from_static(b"bad"), &options)
+            .await
+            .unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("Invalid Cache-Control directive"));
+        assert_eq!(mock.keys(), ["site/app.js"]);
+    }
+
+    #[tokio::test]
+    async fn permission_probe_touches_no_real_object() {
+        let mock = MockR2::start().await;
+        mock.put("reports/a.txt", b"a");
+        let client = mock.client().await;
+
+        let permissions = client.probe_permissions("reports/").await.unwrap();
+        assert!(permissions
+            .iter()
+            .all(|(_, allowed)| *allowed == Some(true)));
+        assert_eq!(mock.keys(), ["reports/a.txt"]);
+    }
+
+    #[tokio::test]
+    async fn permission_probes_of_a_missing_bucket_are_inconclusive() {
+        let mock = MockR2::start().await;
+        let client = R2Client::new(
+            "test-access-key".into(),
+            "test-secret-key".into(),
+            "test-account".into(),
+            "missing".into(),
+        )
+        .await
+        .unwrap()
+        .with_endpoint(mock.endpoint());
+
+        let permissions = client.probe_permissions("").await.unwrap();
+        assert!(permissions.iter().all(|(_, allowed)| allowed.is_none()));
+        let error = client
+            .check_permissions("", &[Permission::List])
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("Could not confirm list"));
+
+        // A refusal is still told apart from a missing bucket
+        mock.deny("DELETE");
+        let permissions = mock.client().await.probe_permissions("").await.unwrap();
+        assert_eq!(
+            permissions,
+            [
+                (Permission::List, Some(true)),
+                (Permission::Read, Some(true)),
+                (Permission::Delete, Some(false)),
+            ]
+        );
+    }
+
+    #[test]
+    fn canonical_header_values_collapse_inner_whitespace() {
+        assert_eq!(
+            canonical_header_value("  Q1  report\t final "),
+            "Q1 report final"
+        );
+        assert_eq!(canonical_header_value("plain"), "plain");
+        assert_eq!(canonical_header_value("   "), "");
+    }
+
+    #[test]
+    fn delete_markers_are_recognised_by_their_headers() {
+        let response = |headers: &[(&str, &str)]| {
+            let mut builder = http::Response::builder();
+            for (name, value) in headers {
+                builder = builder.header(*name, *value);
+            }
+            Response::from(builder.body(reqwest::Body::from("")).unwrap())
+        };
+
+        let marker = response(&[("x-amz-delete-marker", "true"), ("x-amz-version-id", "v1")]);
+        assert_eq!(delete_marker_version(&marker).as_deref(), Some("v1"));
+        let unversioned = response(&[]);
+        assert_eq!(delete_marker_version(&unversioned), None);
+    }
+
+    #[tokio::test]
+    async fn restoring_removes_the_delete_marker() {
+        let mock = MockR2::start().await;
+        mock.enable_versioning();
+        mock.put("docs/report.txt", b"version one");
+        mock.put("docs/kept.txt", b"still here");
+        let client = mock.client().await;
+
+        client.delete_object("docs/report.txt").await.unwrap();
+        assert!(client.head_object("docs/report.txt").await.is_err());
+        let marker = client.restore_object("docs/report.txt").await.unwrap();
+        assert_eq!(marker, "marker-1");
+        let data = client.download_object("docs/report.txt").await.unwrap();
+        assert_eq!(&data[..], b"version one");
+
+        let e = client.restore_object("docs/kept.txt").await.unwrap_err();
+        assert_eq!(e.to_string(), "docs/kept.txt is not deleted");
+        let e = client.restore_object("docs/never.txt").await.unwrap_err();
+        assert_eq!(e.to_string(), "No versions of docs/never.txt found");
+    }
+
+    #[tokio::test]
+    async fn restoring_needs_a_versioned_bucket() {
+        let mock = MockR2::start().await;
+        mock.put("docs/report.txt", b"unversioned");
+        let client = mock.client().await;
+
+        let e = client.restore_object("docs/report.txt").await.unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Bucket is not versioned, so docs/report.txt cannot be restored"
+        );
+    }
+
+    #[tokio::test]
+    async fn large_listings_are_parsed_as_they_stream_in() {
+        const OBJECTS: usize = 100_000;
+        // Generated a chunk at a time, so the body is never held whole
+        let chunks = (0..=OBJECTS + 1).map(|i| {
+            let xml = match i {
+                0 => "<ListBucketResult><Name>bucket</Name><IsTruncated>true</IsTruncated>"
+                    .to_string(),
+                i if i > OBJECTS => "<NextContinuationToken>next&amp;page</NextContinuationToken>\
+                     <CommonPrefixes><Prefix>logs/</Prefix></CommonPrefixes></ListBucketResult>"
+                    .to_string(),
+                i => format!(
+                    "<Contents><Key>data/R&amp;D {:06}.csv</Key><Size>{}</Size>\
+                     <ETag>&quot;etag{}&quot;</ETag>\
+                     <LastModified>2025-01-01T00:00:00.000Z</LastModified></Contents>",
+                    i, i, i
+                ),
+            };
+            Ok::<Bytes, std::io::Error>(Bytes::from(xml))
+        });
+        let body = reqwest::Body::wrap_stream(stream::iter(chunks));
+        let response = Response::from(http::Response::builder().body(body).unwrap());
+
+        let page = parse_list_response(response).await.unwrap();
+        assert_eq!(page.objects.len(), OBJECTS);
+        let last = &page.objects[OBJECTS - 1];
+        assert_eq!(last.key, format!("data/R&D {:06}.csv", OBJECTS));
+        assert_eq!(last.size, OBJECTS as u64);
+        assert_eq!(last.etag.as_deref(), Some("etag100000"));
+        assert_eq!(
+            last.last_modified.as_deref(),
+            Some("2025-01-01T00:00:00.000Z")
+        );
+        assert!(page.is_truncated);
+        assert_eq!(page.next_continuation_token.as_deref(), Some("next&page"));
+        assert_eq!(page.common_prefixes, ["logs/"]);
+    }
+
+    #[tokio::test]
+    async fn batch_refuses_to_start_without_delete_permission() {
+        let mock = MockR2::start().await;
+        mock.put("reports/a.txt", b"a");
+        mock.deny("DELETE");
+        let client = mock.client().await;
+
+        let needed = [Permission::List, Permission::Read, Permission::Delete];
+        let e = client
+            .check_permissions("reports/", &needed)
+            .await
+            .unwrap_err();
+        assert!(e.to_string().contains("Missing delete permission"), "{}", e);
+        assert_eq!(mock.keys(), ["reports/a.txt"]);
+    }
+
+    #[tokio::test]
+    async fn idle_download_bodies_fail_as_stalled() {
+        let chunks = stream::iter([Ok::<_, std::io::Error>(Bytes::from_static(b"first"))])
+            .chain(stream::pending());
+        let response = Response::from(http::Response::new(reqwest::Body::wrap_stream(chunks)));
+
+        let mut body = with_idle_timeout(response, Duration::from_millis(50)).bytes_stream();
+        assert_eq!(body.next().await.unwrap().unwrap(), "first");
+        let e = anyhow::Error::from(body.next().await.unwrap().unwrap_err());
+        assert!(error::is_connection_error(&e), "{:#}", e);
+        assert!(body.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn upload_progress_ends_at_the_body_length() {
+        let mock = MockR2::start().await;
+        let client = mock.client().await;
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let data = Bytes::from(vec![7u8; 3 * UPLOAD_CHUNK_SIZE + 5]);
+
+        let recorder = reports.clone();
+        client
+            .upload_object_with_progress("a.bin", data.clone(), &UploadOptions::default(), {
+                move |sent, total| recorder.lock().unwrap().push((sent, total))
+            })
+            .await
+            .unwrap();
+
+        let reports = reports.lock().unwrap();
+        assert!(reports.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+        assert_eq!(
+            reports.last(),
+            Some(&(data.len() as u64, data.len() as u64))
+        );
+        assert_eq!(mock.object("a.bin").unwrap().data, data);
+    }
+
+    #[tokio::test]
+    async fn original_filenames_with_spaces_round_trip_through_metadata() {
+        let mock = MockR2::start().await;
+        let client = mock.client().await;
+        let options = UploadOptions::default().with_original_filename("Quarterly report 2024.xlsx");
+        client
+            .upload_object_with_options("reports/q.pgp", Bytes::from_static(b"x"), &options)
+            .await
+            .unwrap();
+
+        let head = client.head_object("reports/q.pgp").await.unwrap();
+        assert_eq!(
+            head.original_filename().as_deref(),
+            Some("Quarterly report 2024.xlsx")
+        );
+    }
+
+    #[tokio::test]
+    async fn expired_credentials_are_reloaded_and_the_request_retried() {
+        let mock = MockR2::start().await;
+        mock.put("a.txt", b"still here");
+        let folder = TempDir::new();
+        let config = folder.write(
+            "config.json",
+            br#"{"r2": {"access_key_id": "rotated-key", "secret_access_key": "rotated-secret",
+                "account_id": "test-account", "bucket_name": "bucket"}}"#,
+        );
+        let client = mock
+            .client()
+            .await
+            .with_credential_source(CredentialSource::ConfigFile(config, None));
+
+        // The mock only accepts the rotated key from here on
+        mock.expire_access_key("test-access-key");
+        assert_eq!(client.download_object("a.txt").await.unwrap(), "still here");
+    }
+
+    #[tokio::test]
+    async fn expired_static_credentials_fail_without_a_retry() {
+        let mock = MockR2::start().await;
+        mock.put("a.txt", b"still here");
+        let client = mock.client().await;
+
+        mock.expire_access_key("test-access-key");
+        let e = client.download_object("a.txt").await.unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<R2Error>(),
+            Some(R2Error::CredentialsExpired { .. })
+        ));
+    }
+
+    async fn ping(endpoint: &str, bucket: &str) -> PingDiagnosis {
+        let client = R2Client::new(
+            "test-access-key".into(),
+            "test-secret-key".into(),
+            "test-account".into(),
+            bucket.into(),
+        )
+        .await
+        .unwrap()
+        .with_endpoint(endpoint);
+        client.ping().await.unwrap().diagnosis
+    }
+
+    #[tokio::test]
+    async fn ping_tells_failure_modes_apart() {
+        let mock = MockR2::start().await;
+        assert_eq!(ping(mock.endpoint(), "bucket").await, PingDiagnosis::Ok);
+        assert_eq!(
+            ping(mock.endpoint(), "other-bucket").await,
+            PingDiagnosis::BucketNotFound
+        );
+        mock.deny("HEAD");
+        assert_eq!(
+            ping(mock.endpoint(), "bucket").await,
+            PingDiagnosis::InvalidCredentials(403)
+        );
+
+        // .invalid never resolves
+        let diagnosis = ping("http://r2-ping-test.invalid", "bucket").await;
+        assert!(
+            matches!(diagnosis, PingDiagnosis::DnsFailure(_)),
+            "{:?}",
+            diagnosis
+        );
+
+        // A port nothing listens on any more
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let closed = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+        let diagnosis = ping(&closed, "bucket").await;
+        assert!(
+            matches!(diagnosis, PingDiagnosis::ConnectionFailed(_)),
+            "{:?}",
+            diagnosis
+        );
+    }
+
+    #[test]
+    fn ping_statuses_map_to_diagnoses() {
+        let diagnose = |code| PingDiagnosis::from_status(StatusCode::from_u16(code).unwrap());
+        assert_eq!(diagnose(200), PingDiagnosis::Ok);
+        assert_eq!(diagnose(401), PingDiagnosis::InvalidCredentials(401));
+        assert_eq!(diagnose(403), PingDiagnosis::InvalidCredentials(403));
+        assert_eq!(diagnose(404), PingDiagnosis::BucketNotFound);
+        assert_eq!(diagnose(500), PingDiagnosis::UnexpectedStatus(500));
+    }
+
+    #[test]
+    fn completion_bodies_list_parts_in_order_with_escaped_etags() {
+        let part = |part_number, etag: &str| CompletedPart {
+            part_number,
+            etag: etag.to_string(),
+        };
+        let body =
+            complete_multipart_body(&[part(3, "\"c3\""), part(1, "\"a1\""), part(2, "b&<2>")]);
+        assert_eq!(
+            body,
+            "<CompleteMultipartUpload>\
+             <Part><PartNumber>1</PartNumber><ETag>&quot;a1&quot;</ETag></Part>\
+             <Part><PartNumber>2</PartNumber><ETag>b&amp;&lt;2&gt;</ETag></Part>\
+             <Part><PartNumber>3</PartNumber><ETag>&quot;c3&quot;</ETag></Part>\
+             </CompleteMultipartUpload>"
+        );
+        assert_eq!(
+            complete_multipart_body(&[]),
+            "<CompleteMultipartUpload></CompleteMultipartUpload>"
+        );
+    }
+
+    #[tokio::test]
+    async fn parts_uploaded_out_of_order_complete_in_order() {
+        let mock = MockR2::start().await;
+        let client = mock.client().await;
+        let upload_id = client
+            .create_multipart_upload("big.bin", &UploadOptions::default())
+            .await
+            .unwrap();
+        let mut parts = Vec::new();
+        for (part_number, data) in [(2, "second "), (3, "third"), (1, "first ")] {
+            let etag = client
+                .upload_part("big.bin", &upload_id, part_number, Bytes::from(data))
+                .await
+                .unwrap();
+            parts.push(CompletedPart { part_number, etag });
+        }
+        client
+            .complete_multipart_upload("big.bin", &upload_id, &parts)
+            .await
+            .unwrap();
+        assert_eq!(mock.object("big.bin").unwrap().data, b"first second third");
+        assert!(mock.open_uploads().is_empty());
+    }
+
+    /// The SigV4 signature of `canonical_request` made at `date_str` with `secret`.
+    fn expected_signature(secret: &str, date_str: &str, canonical_request: &str) -> String {
+        let date_short = &date_str[..8];
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}/auto/s3/aws4_request\n{}",
+            date_str,
+            date_short,
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+        sigv4_signature(secret, date_short, &string_to_sign).unwrap()
+    }
+
+    #[tokio::test]
+    async fn requests_are_signed_for_the_endpoint_host() {
+        let client = R2Client::new(
+            "AKIDEXAMPLE".into(),
+            "secret".into(),
+            "acct".into(),
+            "backups".into(),
+        )
+        .await
+        .unwrap()
+        .with_client_config(R2ClientConfig {
+            endpoint: Some("https://acct.eu.r2.cloudflarestorage.com/".into()),
+            ..Default::default()
+        })
+        .unwrap();
+        let datetime = DateTime::parse_from_rfc3339("2024-06-30T09:05:07Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let mut headers = HeaderMap::new();
+        client
+            .sign_request(&Method::GET, "/backups/a.txt", &mut headers, b"", &datetime)
+            .unwrap();
+        assert_eq!(headers["host"], "acct.eu.r2.cloudflarestorage.com");
+
+        let empty_hash = hex::encode(Sha256::digest(b""));
+        let canonical_request = format!(
+            "GET\n/backups/a.txt\n\n\
+             host:acct.eu.r2.cloudflarestorage.com\n\
+             x-amz-content-sha256:{}\n\
+             x-amz-date:20240630T090507Z\n\n\
+             host;x-amz-content-sha256;x-amz-date\n{}",
+            empty_hash, empty_hash
+        );
+        let signature = expected_signature("secret", "20240630T090507Z", &canonical_request);
+        assert_eq!(
+            headers["authorization"],
+            format!(
+                "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20240630/auto/s3/aws4_request, \
+                 SignedHeaders=host;x-amz-content-sha256;x-amz-date, Signature={}",
+                signature
+            )
+        );
+
+        // A port that isn't the scheme's default is part of the host
+        let local = client.with_endpoint("http://localhost:9000");
+        let mut headers = HeaderMap::new();
+        local
+            .sign_request(&Method::GET, "/backups/a.txt", &mut headers, b"", &datetime)
+            .unwrap();
+        assert_eq!(headers["host"], "localhost:9000");
+    }
+
+    #[tokio::test]
+    async fn presigned_urls_are_signed_for_the_endpoint_host() {
+        let client = R2Client::new(
+            "AKIDEXAMPLE".into(),
+            "secret".into(),
+            "acct".into(),
+            "backups".into(),
+        )
+        .await
+        .unwrap()
+        .with_endpoint("https://acct.eu.r2.cloudflarestorage.com");
+        let url = client
+            .presign_get_url("a.txt", Duration::from_secs(60))
+            .unwrap();
+        let (unsigned, signature) = url.split_once("&X-Amz-Signature=").unwrap();
+        let (address, query_string) = unsigned.split_once('?').unwrap();
+        assert_eq!(
+            address,
+            "https://acct.eu.r2.cloudflarestorage.com/backups/a.txt"
+        );
+
+        // Signed now, so the date comes from the URL
+        let date_str = url::Url::parse(&url)
+            .unwrap()
+            .query_pairs()
+            .find(|(name, _)| name == "X-Amz-Date")
+            .unwrap()
+            .1
+            .into_owned();
+        let canonical_request = format!(
+            "GET\n/backups/a.txt\n{}\nhost:acct.eu.r2.cloudflarestorage.com\n\nhost\n{}",
+            query_string, UNSIGNED_PAYLOAD
+        );
+        assert_eq!(
+            signature,
+            expected_signature("secret", &date_str, &canonical_request)
+        );
+    }
 }
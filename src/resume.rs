@@ -0,0 +1,372 @@
+use crate::error::{self, R2Error};
+use crate::folder_download::{self, DownloadSession};
+use crate::multipart_upload::{MultipartUploader, UploadSession};
+use crate::progress::{ItemOutcome, ItemResult, ProgressReporter};
+use crate::r2_client::{self, R2Client, UploadOptions};
+use anyhow::Result;
+use futures_util::stream::{self, StreamExt};
+use serde::Serialize;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferKind {
+    Upload,
+    Download,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResumeStatus {
+    Completed,
+    Stale,  // Couldn't be resumed, so its session (and upload or partial file) was removed
+    Failed, // Kept for a later attempt
+}
+
+/// What `resume_all` did with one saved session.
+#[derive(Clone, Debug, Serialize)]
+pub struct ResumeResult {
+    pub kind: TransferKind,
+    pub key: String,
+    pub local_path: Option<PathBuf>,
+    pub status: ResumeStatus,
+    pub detail: Option<String>,
+}
+
+impl ResumeResult {
+    fn outcome(&self, size: u64) -> ItemOutcome {
+        let action = match self.kind {
+            TransferKind::Upload => "resume-upload",
+            TransferKind::Download => "resume-download",
+        };
+        let (result, bytes) = match self.status {
+            ResumeStatus::Completed => (ItemResult::Ok, size),
+            ResumeStatus::Stale => (ItemResult::Skipped, 0),
+            ResumeStatus::Failed => (ItemResult::Failed, 0),
+        };
+        ItemOutcome {
+            path: self.local_path.clone(),
+            key: self.key.clone(),
+            action,
+            bytes,
+            result,
+            error: self.detail.clone().filter(|_| result != ItemResult::Ok),
+        }
+    }
+}
+
+enum Session {
+    Upload(PathBuf, UploadSession),
+    Download(PathBuf, DownloadSession),
+}
+
+impl Session {
+    fn size(&self) -> u64 {
+        match self {
+            Session::Upload(_, session) => session.file_size,
+            Session::Download(_, session) => session.expected_size,
+        }
+    }
+}
+
+/// Adds one transfer's position to an overall progress bar shared by several transfers.
+struct ItemProgress<'a> {
+    overall: &'a dyn ProgressReporter,
+    done: &'a AtomicU64,
+    last: AtomicU64,
+}
+
+impl ProgressReporter for ItemProgress<'_> {
+    fn set_length(&self, _length: u64) {}
+
+    fn set_position(&self, position: u64) {
+        let previous = self.last.swap(position, Ordering::SeqCst);
+        let done = if position >= previous {
+            self.done.fetch_add(position - previous, Ordering::SeqCst) + position - previous
+        } else {
+            self.done.fetch_sub(previous - position, Ordering::SeqCst) - (previous - position)
+        };
+        self.overall.set_position(done);
+    }
+
+    fn set_message(&self, _message: &str) {}
+
+    fn finish(&self, _message: &str) {}
+}
+
+/// Resume every interrupted upload and download recorded in the cache folder, up to
+/// `concurrency` at a time, with up to `part_concurrency` parts of each upload in flight. Each session is checked against R2 first: uploads whose
+/// multipart upload is gone or whose local file changed, and downloads whose object
+/// was deleted or replaced, are stale and cleaned up rather than resumed.
+pub async fn resume_all(
+    client: &R2Client,
+    concurrency: usize,
+    part_concurrency: usize,
+    progress: &dyn ProgressReporter,
+) -> Result<Vec<ResumeResult>> {
+    let mut sessions: Vec<Session> = UploadSession::list_all(client)?
+        .into_iter()
+        .map(|(path, session)| Session::Upload(path, session))
+        .collect();
+    sessions.extend(
+        DownloadSession::list_all(client)?
+            .into_iter()
+            .map(|(path, session)| Session::Download(path, session)),
+    );
+
+    progress.set_length(sessions.iter().map(Session::size).sum());
+    let done = AtomicU64::new(0);
+
+    let results = stream::iter(sessions)
+        .map(|session| {
+            let item = ItemProgress {
+                overall: progress,
+                done: &done,
+                last: AtomicU64::new(0),
+            };
+            async move {
+                let size = session.size();
+                let result = match session {
+                    Session::Upload(path, session) => {
+                        resume_upload(client, path, session, part_concurrency, &item).await
+                    }
+                    Session::Download(path, session) => {
+                        resume_download(client, path, session, &item).await
+                    }
+                };
+                // Finished or not, this transfer no longer holds the overall bar back
+                item.set_position(size);
+                progress.item_finished(&result.outcome(size));
+                result
+            }
+        })
+        .buffer_unordered(concurrency.max(1))
+        .collect()
+        .await;
+
+    Ok(results)
+}
+
+async fn resume_upload(
+    client: &R2Client,
+    session_path: PathBuf,
+    session: UploadSession,
+    part_concurrency: usize,
+    progress: &dyn ProgressReporter,
+) -> ResumeResult {
+    let result = |status, detail: &str| ResumeResult {
+        kind: TransferKind::Upload,
+        key: session.key.clone(),
+        local_path: session.local_path.clone(),
+        status,
+        detail: Some(detail.to_string()).filter(|d| !d.is_empty()),
+    };
+    let discard = || async {
+        if let Err(e) = client
+            .abort_multipart_upload(&session.key, &session.upload_id)
+            .await
+        {
+            tracing::warn!(
+                "Failed to abort multipart upload of {}: {:#}",
+                session.key,
+                e
+            );
+        }
+        let _ = fs::remove_file(&session_path);
+    };
+
+    // Sessions saved before the local path was recorded can only be resumed by the original command
+    let Some(local_path) = session.local_path.clone() else {
+        return result(
+            ResumeStatus::Failed,
+            "local file unknown; re-run the original upload to resume it",
+        );
+    };
+    if !session.matches_file(&local_path) {
+        discard().await;
+        return result(ResumeStatus::Stale, "local file changed or was removed");
+    }
+
+    let stored = match client.list_parts(&session.key, &session.upload_id).await {
+        Ok(parts) => parts,
+        Err(e) if R2Error::from_anyhow(&e).and_then(R2Error::code) == Some("NoSuchUpload") => {
+            let _ = fs::remove_file(&session_path);
+            return result(ResumeStatus::Stale, "multipart upload no longer exists");
+        }
+        Err(e) => return result(ResumeStatus::Failed, &format!("{:#}", e)),
+    };
+    let all_stored = session.parts.iter().all(|part| {
+        stored
+            .iter()
+            .any(|s| s.part_number == part.part_number && s.etag == part.etag)
+    });
+    if !all_stored {
+        discard().await;
+        return result(ResumeStatus::Stale, "uploaded parts are missing on R2");
+    }
+
+    match MultipartUploader::upload_file(
+        client,
+        &local_path,
+        &session.key,
+        &UploadOptions::default(),
+        session.part_size,
+        part_concurrency,
+        None,
+        progress,
+    )
+    .await
+    {
+        Ok(_) => result(ResumeStatus::Completed, ""),
+        Err(e) => result(ResumeStatus::Failed, &format!("{:#}", e)),
+    }
+}
+
+async fn resume_download(
+    client: &R2Client,
+    session_path: PathBuf,
+    session: DownloadSession,
+    progress: &dyn ProgressReporter,
+) -> ResumeResult {
+    let final_path = session.final_path();
+    let result = |status, detail: &str| ResumeResult {
+        kind: TransferKind::Download,
+        key: session.key.clone(),
+        local_path: Some(final_path.clone()),
+        status,
+        detail: Some(detail.to_string()).filter(|d| !d.is_empty()),
+    };
+    let discard = || {
+        let _ = fs::remove_file(&session.partial);
+        let _ = fs::remove_file(r2_client::partial_etag_path(&session.partial));
+        let _ = fs::remove_file(&session_path);
+    };
+
+    if !session.partial.exists() {
+        let _ = fs::remove_file(&session_path);
+        return result(ResumeStatus::Stale, "partial file was removed");
+    }
+    match client.head_object(&session.key).await {
+        Ok(head) if head.content_length == Some(session.expected_size) => {}
+        Ok(_) => {
+            discard();
+            return result(
+                ResumeStatus::Stale,
+                "object changed since the download started",
+            );
+        }
+        Err(e) if error::is_not_found(&e) => {
+            discard();
+            return result(ResumeStatus::Stale, "object no longer exists");
+        }
+        Err(e) => return result(ResumeStatus::Failed, &format!("{:#}", e)),
+    }
+
+    let downloaded = folder_download::download_resumable(
+        client,
+        &session.key,
+        &session.partial,
+        session.expected_size,
+        progress,
+    )
+    .await;
+    if let Err(e) = downloaded {
+        return result(ResumeStatus::Failed, &format!("{:#}", e));
+    }
+    if let Err(e) = fs::rename(&session.partial, &final_path) {
+        return result(
+            ResumeStatus::Failed,
+            &format!("Failed to write output file: {}", e),
+        );
+    }
+
+    // The download command decrypts after downloading; resuming here only restores the bytes
+    if folder_download::file_looks_encrypted(&final_path).unwrap_or(false) {
+        result(
+            ResumeStatus::Completed,
+            "still encrypted; decrypt it locally",
+        )
+    } else {
+        result(ResumeStatus::Completed, "")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_r2::MockR2;
+    use crate::multipart_upload::MIN_PART_SIZE;
+    use crate::progress::ConsoleProgress;
+    use crate::r2_client::R2ClientConfig;
+    use crate::test_support::TempDir;
+    use std::path::Path;
+
+    // Upload `path` until part 2 fails, leaving a saved session behind
+    async fn interrupt_upload(client: &R2Client, mock: &MockR2, path: &Path, key: &str) {
+        mock.fail_part(2);
+        let uploaded = MultipartUploader::upload_file(
+            client,
+            path,
+            key,
+            &UploadOptions::default(),
+            MIN_PART_SIZE,
+            1,
+            None,
+            &ConsoleProgress::new(0),
+        )
+        .await;
+        assert!(uploaded.is_err());
+    }
+
+    #[tokio::test]
+    async fn resumable_sessions_finish_and_stale_ones_are_cleaned_up() {
+        let data: Vec<u8> = (0..2 * MIN_PART_SIZE + 1000)
+            .map(|i| (i % 253) as u8)
+            .collect();
+        let folder = TempDir::new();
+        let resumable = folder.write("resumable.bin", &data);
+        let stale = folder.write("stale.bin", &data);
+        let mock = MockR2::start().await;
+        let no_retries = R2ClientConfig {
+            max_retries: 0,
+            ..Default::default()
+        };
+        let client = mock.client().await.with_client_config(no_retries).unwrap();
+        interrupt_upload(&client, &mock, &resumable, "resumable.bin").await;
+        interrupt_upload(&client, &mock, &stale, "stale.bin").await;
+
+        // R2 drops multipart uploads left unfinished for too long
+        let sessions = UploadSession::list_all(&client).unwrap();
+        assert_eq!(sessions.len(), 2);
+        let (_, expired) = sessions.iter().find(|(_, s)| s.key == "stale.bin").unwrap();
+        client
+            .abort_multipart_upload("stale.bin", &expired.upload_id)
+            .await
+            .unwrap();
+
+        let mut results = resume_all(&client, 2, 1, &ConsoleProgress::new(0))
+            .await
+            .unwrap();
+        results.sort_by(|a, b| a.key.cmp(&b.key));
+        let statuses: Vec<(&str, ResumeStatus)> =
+            results.iter().map(|r| (r.key.as_str(), r.status)).collect();
+        assert_eq!(
+            statuses,
+            [
+                ("resumable.bin", ResumeStatus::Completed),
+                ("stale.bin", ResumeStatus::Stale)
+            ]
+        );
+        assert_eq!(
+            results[1].detail.as_deref(),
+            Some("multipart upload no longer exists")
+        );
+
+        assert!(mock.object("resumable.bin").unwrap().data == data);
+        assert!(mock.object("stale.bin").is_none());
+        assert!(mock.open_uploads().is_empty());
+        assert!(UploadSession::list_all(&client).unwrap().is_empty());
+    }
+}
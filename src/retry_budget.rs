@@ -0,0 +1,185 @@
+use crate::error::{R2Unavailable, RetryBudgetExhausted};
+use anyhow::Result;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_MAX_RETRIES: u32 = 100;
+pub const DEFAULT_BREAKER_THRESHOLD: u32 = 10;
+pub const DEFAULT_BREAKER_PAUSE: Duration = Duration::from_secs(30);
+
+/// Limits on retrying across a whole operation, such as one CLI command.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    pub max_retries: u32,       // Retries of all requests together
+    pub breaker_threshold: u32, // Failures in a row that trip the breaker
+    pub breaker_pause: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: DEFAULT_MAX_RETRIES,
+            breaker_threshold: DEFAULT_BREAKER_THRESHOLD,
+            breaker_pause: DEFAULT_BREAKER_PAUSE,
+        }
+    }
+}
+
+/// Retries left and the state of the circuit breaker for one operation.
+///
+/// Each retry anywhere in the operation spends from one shared budget, so a batch of
+/// failing requests can't multiply its per-request retries into thousands of attempts.
+/// Connection errors and 5xx responses count as failures; any other response means R2
+/// is answering and resets the count. After `breaker_threshold` failures in a row,
+/// requests wait out `breaker_pause`; if the next one fails too, the breaker opens and
+/// every later request fails at once with `R2Unavailable`.
+#[derive(Debug)]
+pub struct RetryBudget {
+    policy: RetryPolicy,
+    state: Mutex<BreakerState>,
+}
+
+#[derive(Debug, Default)]
+struct BreakerState {
+    retries: u32,
+    failures: u32,                 // In a row
+    paused_until: Option<Instant>, // Set when the breaker trips
+    open: bool,
+}
+
+impl RetryBudget {
+    pub fn new(policy: RetryPolicy) -> Self {
+        Self {
+            policy,
+            state: Mutex::new(BreakerState::default()),
+        }
+    }
+
+    /// No budget and no breaker, for clients that outlive any single operation.
+    pub fn unlimited() -> Self {
+        Self::new(RetryPolicy {
+            max_retries: u32::MAX,
+            breaker_threshold: u32::MAX,
+            breaker_pause: Duration::ZERO,
+        })
+    }
+
+    /// Call before sending a request: waits out a tripped breaker's pause, and fails
+    /// once the breaker is open.
+    pub async fn before_request(&self) -> Result<()> {
+        let paused_until = {
+            let state = self.state.lock().unwrap();
+            if state.open {
+                return Err(R2Unavailable {
+                    failures: state.failures,
+                }
+                .into());
+            }
+            state.paused_until
+        };
+        if let Some(until) = paused_until {
+            tokio::time::sleep_until(until.into()).await;
+        }
+        Ok(())
+    }
+
+    /// Spend one retry, failing if the operation has used them all or the breaker is open.
+    pub fn take_retry(&self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.open {
+            return Err(R2Unavailable {
+                failures: state.failures,
+            }
+            .into());
+        }
+        if state.retries >= self.policy.max_retries {
+            return Err(RetryBudgetExhausted(self.policy.max_retries).into());
+        }
+        state.retries += 1;
+        Ok(())
+    }
+
+    /// R2 answered, so it is reachable: clear the failure count and any pause.
+    pub fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.failures = 0;
+        state.paused_until = None;
+    }
+
+    /// A request failed to reach R2 or got a server error.
+    pub fn record_failure(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.failures = state.failures.saturating_add(1);
+        if state.open {
+            return;
+        }
+        match state.paused_until {
+            // Still failing after the pause
+            Some(until) if Instant::now() >= until => {
+                tracing::warn!(
+                    "R2 still failing after a {}s pause, giving up",
+                    self.policy.breaker_pause.as_secs()
+                );
+                state.open = true;
+            }
+            Some(_) => {}
+            None if state.failures >= self.policy.breaker_threshold => {
+                tracing::warn!(
+                    "{} requests to R2 failed in a row, pausing for {}s",
+                    state.failures,
+                    self.policy.breaker_pause.as_secs()
+                );
+                state.paused_until = Some(Instant::now() + self.policy.breaker_pause);
+            }
+            None => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_r2::MockR2;
+    use crate::r2_client::R2ClientConfig;
+
+    #[tokio::test]
+    async fn an_outage_trips_the_breaker_instead_of_retrying_every_item() {
+        let mock = MockR2::start().await;
+        let keys: Vec<String> = (0..10).map(|i| format!("batch/{}.txt", i)).collect();
+        for key in &keys {
+            mock.put(key, b"data");
+        }
+        mock.go_down();
+        let client = mock
+            .client()
+            .await
+            .with_client_config(R2ClientConfig {
+                max_retries: 2,
+                ..Default::default()
+            })
+            .unwrap()
+            .with_retry_policy(RetryPolicy {
+                max_retries: 100,
+                breaker_threshold: 3,
+                breaker_pause: Duration::from_millis(50),
+            });
+
+        let mut errors = Vec::new();
+        for key in &keys {
+            errors.push(client.download_object(key).await.unwrap_err());
+        }
+
+        // The first item's attempts trip the breaker, the attempt after the pause opens
+        // it, and every later item fails without a request
+        assert!(errors[0].downcast_ref::<R2Unavailable>().is_none());
+        for error in &errors[1..] {
+            assert!(
+                error.downcast_ref::<R2Unavailable>().is_some(),
+                "{:#}",
+                error
+            );
+        }
+        assert_eq!(mock.request_count(), 4);
+        // Per-request retries alone would have sent 30
+    }
+}
@@ -0,0 +1,176 @@
+use crate::chunked;
+use crate::crypto::PgpHandler;
+use crate::encryption_filter;
+use crate::progress::format_size;
+use crate::r2_client::{ObjectInfo, R2Client};
+use anyhow::anyhow;
+
+// Objects encrypted as one PGP message can only be decrypted whole; smaller ones are
+// downloaded for a sample, bigger ones are reported instead
+pub const SAMPLE_WHOLE_DECRYPT_LIMIT: u64 = 16 * 1024 * 1024;
+
+pub struct ObjectSample {
+    pub key: String,
+    pub size: u64,
+    pub data: Vec<u8>,
+    pub short: bool, // The object (or its plaintext) is smaller than the sample size
+    pub decrypted: bool, // `data` is plaintext of an encrypted object
+    pub note: Option<String>,
+}
+
+/// Read the first `bytes` of an object. Encrypted objects are decrypted unless `raw` is
+/// set: chunked ones one chunk at a time, single messages only up to
+/// SAMPLE_WHOLE_DECRYPT_LIMIT. Failures are noted in the sample rather than returned.
+pub async fn sample_object(
+    client: &R2Client,
+    pgp_handler: &PgpHandler,
+    object: ObjectInfo,
+    bytes: usize,
+    raw: bool,
+) -> ObjectSample {
+    let mut sample = ObjectSample {
+        key: object.key,
+        size: object.size,
+        data: Vec::new(),
+        short: false,
+        decrypted: false,
+        note: None,
+    };
+    if bytes == 0 || object.size == 0 {
+        sample.short = bytes > 0;
+        return sample;
+    }
+
+    // At least enough to recognise an encrypted object
+    let probe = bytes.max(chunked::FIRST_MESSAGE_OFFSET) as u64;
+    let start = match client.download_range(&sample.key, 0, probe - 1).await {
+        Ok(start) => start,
+        Err(e) => {
+            sample.note = Some(format!("Failed to read: {:#}", e));
+            return sample;
+        }
+    };
+    let encrypted = chunked::is_chunked(&start)
+        || PgpHandler::is_pgp_encrypted(&start)
+        || encryption_filter::is_encrypted_extension(&sample.key);
+    if raw || !encrypted {
+        sample.data = start[..start.len().min(bytes)].to_vec();
+        sample.short = sample.data.len() < bytes;
+        return sample;
+    }
+
+    let plaintext = if chunked::is_chunked(&start) {
+        chunked::decrypt_object_prefix(client, pgp_handler, &sample.key, bytes).await
+    } else if !PgpHandler::is_pgp_encrypted(&start) {
+        Err(anyhow!("encrypted extension, but not PGP data"))
+    } else if sample.size > SAMPLE_WHOLE_DECRYPT_LIMIT {
+        Err(anyhow!(
+            "encrypted as a single PGP message of {}, which can't be decrypted in part",
+            format_size(sample.size)
+        ))
+    } else {
+        match client.download_object(&sample.key).await {
+            Ok(data) => pgp_handler.decrypt(&data),
+            Err(e) => Err(e),
+        }
+    };
+    match plaintext {
+        Ok(mut plaintext) => {
+            plaintext.truncate(bytes);
+            sample.short = plaintext.len() < bytes;
+            sample.data = plaintext;
+            sample.decrypted = true;
+        }
+        Err(e) => sample.note = Some(format!("Cannot decrypt: {:#}", e)),
+    }
+    sample
+}
+
+/// A sample as text if it is, otherwise as a hex dump.
+pub fn printable_sample(data: &[u8]) -> String {
+    if let Ok(text) = std::str::from_utf8(data) {
+        if !text.contains('\0') {
+            return text.to_string();
+        }
+    }
+    data.chunks(16)
+        .enumerate()
+        .map(|(row, bytes)| {
+            let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+            let ascii: String = bytes
+                .iter()
+                .map(|&b| if b.is_ascii_graphic() { b as char } else { '.' })
+                .collect();
+            format!("{:08x}  {:<48}  {}", row * 16, hex.join(" "), ascii)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunked::ChunkedEncryptor;
+    use crate::mock_r2::MockR2;
+    use crate::test_support;
+
+    #[tokio::test]
+    async fn samples_cover_plain_short_and_encrypted_objects() {
+        let key = test_support::key("Sampler");
+        let handler = test_support::handler(&[&key], &[&key]);
+        let csv = b"id,name,email\n1,Ada,ada@example.com\n".repeat(100);
+        let mut encryptor = ChunkedEncryptor::new(&handler, "big.csv", 1000).unwrap();
+        let mut chunked_csv = encryptor.update(&csv).unwrap();
+        chunked_csv.extend(encryptor.finish().unwrap());
+
+        let mock = MockR2::start().await;
+        mock.put("data/plain.csv", &csv);
+        mock.put("data/tiny.csv", b"id\n");
+        mock.put("data/empty.csv", b"");
+        mock.put("data/chunked.csv.pgp", &chunked_csv);
+        mock.put("data/message.csv.pgp", &handler.encrypt(&csv).unwrap());
+        mock.put("data/fake.csv.pgp", b"just a misnamed file");
+        let mut huge = b"-----BEGIN PGP MESSAGE-----\n".to_vec();
+        huge.resize(SAMPLE_WHOLE_DECRYPT_LIMIT as usize + 1, b'A');
+        mock.put("data/huge.csv.asc", &huge);
+        let client = mock.client().await;
+        let objects = client.list_objects_detailed(Some("data/")).await.unwrap();
+        let mut samples = Vec::new();
+        for object in objects {
+            samples.push(sample_object(&client, &handler, object, 14, false).await);
+        }
+        let sample = |key: &str| samples.iter().find(|s| s.key == key).unwrap();
+
+        for key in [
+            "data/plain.csv",
+            "data/chunked.csv.pgp",
+            "data/message.csv.pgp",
+        ] {
+            let sample = sample(key);
+            assert_eq!(sample.data, b"id,name,email\n", "{}", key);
+            assert!(!sample.short && sample.note.is_none(), "{}", key);
+            assert_eq!(sample.decrypted, key.ends_with(".pgp"), "{}", key);
+        }
+
+        // Too small for the sample: whatever there is, flagged as short
+        assert_eq!(sample("data/tiny.csv").data, b"id\n");
+        assert!(sample("data/tiny.csv").short);
+        assert!(sample("data/empty.csv").short);
+
+        // Encryption that can't be decrypted in part is noted rather than sampled
+        let note = sample("data/huge.csv.asc").note.as_deref().unwrap();
+        assert!(note.contains("single PGP message"), "{}", note);
+        assert!(sample("data/huge.csv.asc").data.is_empty());
+        let note = sample("data/fake.csv.pgp").note.as_deref().unwrap();
+        assert!(note.contains("not PGP data"), "{}", note);
+
+        // Raw samples are the stored bytes
+        let objects = client
+            .list_objects_detailed(Some("data/chunked"))
+            .await
+            .unwrap();
+        let raw = sample_object(&client, &handler, objects[0].clone(), 8, true).await;
+        assert_eq!(raw.data, chunked::MAGIC);
+        assert!(!raw.decrypted);
+    }
+}
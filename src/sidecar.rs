@@ -0,0 +1,101 @@
+use crate::crypto::PgpHandler;
+use crate::error;
+use crate::r2_client::{R2Client, UploadOptions};
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// Private names: the object is stored under a random key in the same folder, and
+// everything that would identify it (the key it was uploaded as, its filename, content
+// type and user metadata) goes into an encrypted JSON sidecar next to it:
+//
+//   backups/3f9c...e1.pgp       the encrypted content, with no identifying metadata
+//   backups/3f9c...e1.pgp.meta.pgp   the encrypted PrivateMetadata
+pub const SIDECAR_SUFFIX: &str = ".meta.pgp";
+
+// Hex digits in the random part of an opaque key
+const OPAQUE_ID_BYTES: usize = 16;
+
+/// What a sidecar hides about the object next to it.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PrivateMetadata {
+    pub key: String, // The key the object was uploaded as
+    pub file_name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+}
+
+pub fn sidecar_key(object_key: &str) -> String {
+    format!("{}{}", object_key, SIDECAR_SUFFIX)
+}
+
+pub fn is_sidecar(key: &str) -> bool {
+    key.ends_with(SIDECAR_SUFFIX)
+}
+
+/// A random key in the same folder as `key`, ending in `.pgp`.
+pub fn opaque_key(key: &str) -> String {
+    let id: [u8; OPAQUE_ID_BYTES] = rand::thread_rng().gen();
+    match key.rsplit_once('/') {
+        Some((folder, _)) => format!("{}/{}.pgp", folder, hex::encode(id)),
+        None => format!("{}.pgp", hex::encode(id)),
+    }
+}
+
+/// Upload options for an object stored under a private name: only the encrypted flag and
+/// the generic encrypted Content-Type, since the filename, real content type and user
+/// metadata belong in the sidecar.
+pub fn private_upload_options(options: &UploadOptions) -> UploadOptions {
+    UploadOptions {
+        metadata: BTreeMap::new(),
+        content_type: None,
+        ..options.clone()
+    }
+    .with_encrypted(true)
+    .with_content_type_for("", true)
+}
+
+/// Encrypt `metadata` and store it as the sidecar of `object_key`.
+pub async fn upload_sidecar(
+    client: &R2Client,
+    handler: &PgpHandler,
+    object_key: &str,
+    metadata: &PrivateMetadata,
+) -> Result<()> {
+    let json = serde_json::to_vec(metadata).context("Failed to serialize object metadata")?;
+    let encrypted = handler.encrypt(&json)?;
+    client
+        .upload_object_with_options(
+            &sidecar_key(object_key),
+            Bytes::from(encrypted),
+            &UploadOptions::default()
+                .with_encrypted(true)
+                .with_content_type_for("", true),
+        )
+        .await
+        .with_context(|| format!("Failed to store the metadata sidecar of {}", object_key))?;
+    Ok(())
+}
+
+/// Read and decrypt the sidecar of `object_key`, or None if it has none.
+pub async fn fetch_sidecar(
+    client: &R2Client,
+    handler: &PgpHandler,
+    object_key: &str,
+) -> Result<Option<PrivateMetadata>> {
+    let data = match client.download_object(&sidecar_key(object_key)).await {
+        Ok(data) => data,
+        Err(e) if error::is_not_found(&e) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let json = handler
+        .decrypt(&data)
+        .with_context(|| format!("Failed to decrypt the metadata sidecar of {}", object_key))?;
+    let metadata = serde_json::from_slice(&json)
+        .with_context(|| format!("Invalid metadata sidecar for {}", object_key))?;
+    Ok(Some(metadata))
+}
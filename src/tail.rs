@@ -0,0 +1,95 @@
+use crate::r2_client::{ObjectInfo, R2Client};
+use anyhow::Result;
+use std::collections::HashMap;
+
+/// Remembers which objects under a prefix have been seen, so each poll reports only
+/// objects that weren't there before or that were overwritten since.
+pub struct PrefixWatcher {
+    prefix: String,
+    seen: HashMap<String, Option<String>>, // Last-Modified of every object seen
+    first_poll: bool,
+    from_start: bool, // Report what is already there on the first poll instead of skipping it
+}
+
+impl PrefixWatcher {
+    pub fn new(prefix: &str, from_start: bool) -> Self {
+        Self {
+            prefix: prefix.to_string(),
+            seen: HashMap::new(),
+            first_poll: true,
+            from_start,
+        }
+    }
+
+    /// List the prefix once and return its new objects, oldest first. Unless watching
+    /// from the start, the first poll only sets the baseline and returns nothing.
+    pub async fn poll(&mut self, client: &R2Client) -> Result<Vec<ObjectInfo>> {
+        let objects = client.list_objects_detailed(Some(&self.prefix)).await?;
+        let mut new_objects: Vec<ObjectInfo> = objects
+            .into_iter()
+            .filter(|o| !o.key.ends_with('/'))
+            .filter(|o| self.seen.get(&o.key) != Some(&o.last_modified))
+            .collect();
+        for object in &new_objects {
+            self.seen
+                .insert(object.key.clone(), object.last_modified.clone());
+        }
+        if self.first_poll && !self.from_start {
+            tracing::info!("{} objects already present", new_objects.len());
+            new_objects.clear();
+        }
+        self.first_poll = false;
+
+        new_objects.sort_by(|a, b| (&a.last_modified, &a.key).cmp(&(&b.last_modified, &b.key)));
+        Ok(new_objects)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_r2::MockR2;
+
+    fn keys(objects: &[ObjectInfo]) -> Vec<&str> {
+        objects.iter().map(|o| o.key.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn only_objects_that_land_between_polls_are_reported() {
+        let mock = MockR2::start().await;
+        mock.put("incoming/backlog.csv", b"old");
+        let client = mock.client().await;
+
+        // The backlog only sets the baseline
+        let mut watcher = PrefixWatcher::new("incoming/", false);
+        assert!(watcher.poll(&client).await.unwrap().is_empty());
+
+        mock.put("incoming/b.csv", b"b");
+        mock.put("incoming/a.csv", b"a");
+        mock.put("incoming/sub/", b"");
+        mock.put("other/x.csv", b"x");
+        let landed = watcher.poll(&client).await.unwrap();
+        assert_eq!(keys(&landed), ["incoming/a.csv", "incoming/b.csv"]);
+
+        // Nothing new, nothing reported again
+        assert!(watcher.poll(&client).await.unwrap().is_empty());
+
+        mock.put("incoming/c.csv", b"c");
+        let landed = watcher.poll(&client).await.unwrap();
+        assert_eq!(keys(&landed), ["incoming/c.csv"]);
+
+        // From the start, the backlog is the first batch
+        let mut watcher = PrefixWatcher::new("incoming/", true);
+        let landed = watcher.poll(&client).await.unwrap();
+        assert_eq!(
+            keys(&landed),
+            [
+                "incoming/a.csv",
+                "incoming/b.csv",
+                "incoming/backlog.csv",
+                "incoming/c.csv"
+            ]
+        );
+        assert!(watcher.poll(&client).await.unwrap().is_empty());
+    }
+}
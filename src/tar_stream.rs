@@ -0,0 +1,530 @@
+use crate::chunked::{self, ChunkedEncryptor};
+use crate::crypto::PgpHandler;
+use crate::multipart_upload::MultipartUploader;
+use crate::progress::ProgressReporter;
+use crate::r2_client::{R2Client, UploadOptions};
+use crate::transfer;
+use anyhow::{anyhow, Context, Result};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+// Directories are archived as ustar streams: each regular file is a 512-byte header
+// followed by its data padded to a whole block, and two zero blocks end the archive.
+// Names too long for the header's name and prefix fields are carried in a GNU
+// "././@LongLink" entry, and sizes of 8 GiB or more use GNU base-256 numbers.
+const BLOCK_SIZE: usize = 512;
+
+const NAME_LEN: usize = 100;
+const PREFIX_LEN: usize = 155;
+
+const LONG_NAME: &str = "././@LongLink";
+
+// Read buffer used while archiving, so memory stays bounded whatever the file sizes
+const READ_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Totals of one archive uploaded by `upload_tar`.
+#[derive(Debug, Clone)]
+pub struct TarUpload {
+    pub files: usize,
+    pub tar_bytes: u64,    // Size of the archive before encryption
+    pub stored_bytes: u64, // Size of the object in R2
+}
+
+/// Stream `dir` to `key` as a tar archive, through chunked encryption when `encryption`
+/// is given, without ever writing the archive to disk. Files are read one buffer at a
+/// time and the multipart upload holds at most `concurrency` parts being sent plus the
+/// one filling, so memory use doesn't depend on the size of the directory. Hidden directories are skipped like a folder upload
+/// does, and empty directories aren't stored. A failed upload is aborted.
+#[allow(clippy::too_many_arguments)]
+pub async fn upload_tar(
+    client: &R2Client,
+    dir: &Path,
+    key: &str,
+    options: &UploadOptions,
+    part_size: usize,
+    concurrency: usize,
+    encryption: Option<&PgpHandler>,
+    progress: &dyn ProgressReporter,
+) -> Result<TarUpload> {
+    if !dir.is_dir() {
+        return Err(anyhow!("{} is not a directory", dir.display()));
+    }
+    let files = transfer::scan_directory(dir);
+    progress.set_length(files.iter().map(|f| f.size).sum());
+
+    let archive_name = format!(
+        "{}.tar",
+        dir.file_name()
+            .map_or_else(|| "archive".into(), |n| n.to_string_lossy())
+    );
+    let encryptor = encryption
+        .map(|handler| ChunkedEncryptor::new(handler, &archive_name, chunked::DEFAULT_CHUNK_SIZE))
+        .transpose()?;
+
+    let mut sink = TarSink {
+        uploader: MultipartUploader::start(client, key, options, part_size, concurrency).await?,
+        encryptor,
+        tar_bytes: 0,
+    };
+    let result = async {
+        let mut buf = vec![0u8; READ_BUFFER_SIZE];
+        let mut read = 0u64;
+        for local in &files {
+            let mut file = File::open(&local.path)
+                .with_context(|| format!("Failed to open {}", local.path.display()))?;
+            let metadata = file.metadata()?;
+            let size = metadata.len();
+            sink.send(&file_header(
+                &local.relative_path,
+                size,
+                file_mode(&metadata),
+                mtime(&metadata),
+            )?)
+            .await?;
+
+            // The header already promised `size` bytes, so a file that changes while it
+            // is archived would corrupt everything after it
+            let mut remaining = size;
+            while remaining > 0 {
+                let n = file.read(&mut buf[..(remaining as usize).min(READ_BUFFER_SIZE)])?;
+                if n == 0 {
+                    return Err(anyhow!(
+                        "{} shrank while it was being archived",
+                        local.path.display()
+                    ));
+                }
+                sink.send(&buf[..n]).await?;
+                remaining -= n as u64;
+                read += n as u64;
+                progress.set_position(read);
+            }
+            sink.send(&[0u8; BLOCK_SIZE][..padding(size)]).await?;
+        }
+        sink.send(&[0u8; 2 * BLOCK_SIZE]).await?;
+
+        if let Some(encryptor) = sink.encryptor.take() {
+            sink.uploader.write(&encryptor.finish()?).await?;
+        }
+        Ok(())
+    }
+    .await;
+
+    let TarSink {
+        uploader,
+        tar_bytes,
+        ..
+    } = sink;
+    if let Err(e) = result {
+        // Don't leave orphaned parts consuming storage
+        if let Err(abort_error) = uploader.abort().await {
+            tracing::warn!("Failed to abort multipart upload: {}", abort_error);
+        }
+        return Err(e);
+    }
+    let stored_bytes = uploader.finish().await?;
+    Ok(TarUpload {
+        files: files.len(),
+        tar_bytes,
+        stored_bytes,
+    })
+}
+
+/// Where archive bytes go: through the encryptor, if any, into the multipart upload.
+struct TarSink<'a> {
+    uploader: MultipartUploader<'a>,
+    encryptor: Option<ChunkedEncryptor<'a>>,
+    tar_bytes: u64,
+}
+
+impl TarSink<'_> {
+    async fn send(&mut self, data: &[u8]) -> Result<()> {
+        self.tar_bytes += data.len() as u64;
+        match self.encryptor.as_mut() {
+            Some(encryptor) => self.uploader.write(&encryptor.update(data)?).await,
+            None => self.uploader.write(data).await,
+        }
+    }
+}
+
+/// Bytes of zero padding after `size` bytes of file data.
+fn padding(size: u64) -> usize {
+    (BLOCK_SIZE - (size % BLOCK_SIZE as u64) as usize) % BLOCK_SIZE
+}
+
+#[cfg(unix)]
+fn file_mode(metadata: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn file_mode(metadata: &fs::Metadata) -> u32 {
+    if metadata.permissions().readonly() {
+        0o444
+    } else {
+        0o644
+    }
+}
+
+fn mtime(metadata: &fs::Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs())
+}
+
+/// The header block(s) for a regular file: a GNU long-name entry first when the path
+/// doesn't fit the ustar name and prefix fields.
+fn file_header(path: &str, size: u64, mode: u32, mtime: u64) -> Result<Vec<u8>> {
+    if path.is_empty() || path.contains('\0') {
+        return Err(anyhow!("Invalid path in archive: {:?}", path));
+    }
+    let mut out = Vec::with_capacity(BLOCK_SIZE);
+    let (name, prefix) = match split_path(path) {
+        Some(split) => split,
+        None => {
+            let mut long_name = path.as_bytes().to_vec();
+            long_name.push(0);
+            out.extend(header_block(
+                LONG_NAME,
+                "",
+                long_name.len() as u64,
+                0o644,
+                0,
+                b'L',
+            ));
+            let padded = long_name.len() + padding(long_name.len() as u64);
+            long_name.resize(padded, 0);
+            out.extend(long_name);
+            // Readers that don't know the long-name entry still get something usable
+            (truncate(path, NAME_LEN), "")
+        }
+    };
+    out.extend(header_block(name, prefix, size, mode, mtime, b'0'));
+    Ok(out)
+}
+
+/// Split a path into the ustar name and prefix fields, if it fits.
+fn split_path(path: &str) -> Option<(&str, &str)> {
+    if path.len() <= NAME_LEN {
+        return Some((path, ""));
+    }
+    path.match_indices('/')
+        .map(|(i, _)| (&path[i + 1..], &path[..i]))
+        .find(|(name, prefix)| {
+            !name.is_empty() && name.len() <= NAME_LEN && prefix.len() <= PREFIX_LEN
+        })
+}
+
+fn truncate(s: &str, max: usize) -> &str {
+    let mut end = max.min(s.len());
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+fn header_block(
+    name: &str,
+    prefix: &str,
+    size: u64,
+    mode: u32,
+    mtime: u64,
+    type_flag: u8,
+) -> [u8; BLOCK_SIZE] {
+    let mut block = [0u8; BLOCK_SIZE];
+    block[..name.len()].copy_from_slice(name.as_bytes());
+    write_octal(&mut block[100..108], mode as u64);
+    write_octal(&mut block[108..116], 0); // uid
+    write_octal(&mut block[116..124], 0); // gid
+    write_number(&mut block[124..136], size);
+    write_number(&mut block[136..148], mtime);
+    block[156] = type_flag;
+    block[257..263].copy_from_slice(b"ustar\0");
+    block[263..265].copy_from_slice(b"00");
+    block[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+
+    // The checksum is computed with its own field filled with spaces
+    block[148..156].fill(b' ');
+    let checksum: u32 = block.iter().map(|&b| b as u32).sum();
+    block[148..155].copy_from_slice(format!("{:06o}\0", checksum).as_bytes());
+    block
+}
+
+fn write_octal(field: &mut [u8], value: u64) {
+    let digits = field.len() - 1;
+    field[..digits].copy_from_slice(format!("{:0width$o}", value, width = digits).as_bytes());
+    field[digits] = 0;
+}
+
+/// Octal when the value fits the field, GNU base-256 (high bit set, big-endian) otherwise.
+fn write_number(field: &mut [u8], value: u64) {
+    if value < 1u64 << (3 * (field.len() - 1)) {
+        write_octal(field, value);
+    } else {
+        field.fill(0);
+        let len = field.len();
+        field[len - 8..].copy_from_slice(&value.to_be_bytes());
+        field[0] |= 0x80;
+    }
+}
+
+fn parse_number(field: &[u8]) -> Result<u64> {
+    if field[0] & 0x80 != 0 {
+        let mut value = (field[0] & 0x7f) as u64;
+        for &b in &field[1..] {
+            value = value
+                .checked_mul(256)
+                .and_then(|v| v.checked_add(b as u64))
+                .ok_or_else(|| anyhow!("Number too large in tar header"))?;
+        }
+        return Ok(value);
+    }
+    let text = std::str::from_utf8(field)
+        .map_err(|_| anyhow!("Invalid number in tar header"))?
+        .trim_matches(|c| c == '\0' || c == ' ');
+    if text.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(text, 8).map_err(|_| anyhow!("Invalid number in tar header: {:?}", text))
+}
+
+fn field_str(field: &[u8]) -> String {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+enum Entry {
+    File(File),
+    LongName(Vec<u8>),
+    Skip,
+}
+
+/// Extracts a tar stream into a folder as it is written, so an archive can be unpacked
+/// straight out of a download or decryption without being stored first. Regular files
+/// and directories are created; links and other special entries are skipped with a
+/// warning. Entries whose path is absolute or climbs out of the folder are refused.
+pub struct TarExtractor {
+    dest: PathBuf,
+    header: Vec<u8>,
+    entry: Option<Entry>,
+    remaining: u64, // Data bytes left in the current entry
+    padding: usize, // Padding bytes left after the current entry's data
+    long_name: Option<String>,
+    pending: Option<(PathBuf, u32, u64)>, // File being written, with its mode and mtime
+    files: Vec<PathBuf>,
+    ended: bool,
+}
+
+impl TarExtractor {
+    pub fn new(dest: &Path) -> Result<Self> {
+        fs::create_dir_all(dest).with_context(|| format!("Failed to create {}", dest.display()))?;
+        Ok(Self {
+            dest: dest.to_path_buf(),
+            header: Vec::with_capacity(BLOCK_SIZE),
+            entry: None,
+            remaining: 0,
+            padding: 0,
+            long_name: None,
+            pending: None,
+            files: Vec::new(),
+            ended: false,
+        })
+    }
+
+    /// Check the archive was complete and return the files extracted from it.
+    pub fn finish(mut self) -> Result<Vec<PathBuf>> {
+        if self.entry.is_some() || self.padding > 0 || !self.header.is_empty() {
+            return Err(anyhow!("Tar archive is truncated"));
+        }
+        if !self.ended {
+            return Err(anyhow!(
+                "Tar archive has no end marker, it may be truncated"
+            ));
+        }
+        self.close_entry()?;
+        Ok(self.files)
+    }
+
+    fn process(&mut self, mut data: &[u8]) -> Result<()> {
+        while !data.is_empty() {
+            if self.ended {
+                // Anything after the end marker is padding
+                return Ok(());
+            }
+            if self.remaining > 0 {
+                let n = (self.remaining as usize).min(data.len());
+                match self.entry.as_mut() {
+                    Some(Entry::File(file)) => file.write_all(&data[..n])?,
+                    Some(Entry::LongName(name)) => name.extend_from_slice(&data[..n]),
+                    _ => {}
+                }
+                self.remaining -= n as u64;
+                data = &data[n..];
+                if self.remaining == 0 {
+                    self.close_entry()?;
+                }
+            } else if self.padding > 0 {
+                let n = self.padding.min(data.len());
+                self.padding -= n;
+                data = &data[n..];
+            } else {
+                let n = (BLOCK_SIZE - self.header.len()).min(data.len());
+                self.header.extend_from_slice(&data[..n]);
+                data = &data[n..];
+                if self.header.len() == BLOCK_SIZE {
+                    let header = std::mem::take(&mut self.header);
+                    self.start_entry(&header)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn start_entry(&mut self, header: &[u8]) -> Result<()> {
+        if header.iter().all(|&b| b == 0) {
+            self.ended = true;
+            return Ok(());
+        }
+        let stored = parse_number(&header[148..156])?;
+        let checksum: u64 = header
+            .iter()
+            .enumerate()
+            .map(|(i, &b)| {
+                if (148..156).contains(&i) {
+                    b' ' as u64
+                } else {
+                    b as u64
+                }
+            })
+            .sum();
+        if stored != checksum {
+            return Err(anyhow!("Corrupt tar header (checksum mismatch)"));
+        }
+
+        let size = parse_number(&header[124..136])?;
+        let name = match self.long_name.take() {
+            Some(name) => name,
+            None => {
+                let name = field_str(&header[..NAME_LEN]);
+                let prefix = if &header[257..262] == b"ustar" {
+                    field_str(&header[345..345 + PREFIX_LEN])
+                } else {
+                    String::new()
+                };
+                if prefix.is_empty() {
+                    name
+                } else {
+                    format!("{}/{}", prefix, name)
+                }
+            }
+        };
+
+        self.entry = Some(match header[156] {
+            b'0' | 0 | b'7' => {
+                let path = self.entry_path(&name)?;
+                if path == self.dest {
+                    return Err(anyhow!("Refusing file with an empty path in archive"));
+                }
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                let file = File::create(&path)
+                    .with_context(|| format!("Failed to create {}", path.display()))?;
+                let mode = parse_number(&header[100..108])? as u32;
+                let mtime = parse_number(&header[136..148])?;
+                self.pending = Some((path, mode, mtime));
+                Entry::File(file)
+            }
+            b'5' => {
+                let path = self.entry_path(&name)?;
+                fs::create_dir_all(&path)
+                    .with_context(|| format!("Failed to create {}", path.display()))?;
+                Entry::Skip
+            }
+            b'L' => Entry::LongName(Vec::new()),
+            b'x' | b'g' => Entry::Skip, // pax attributes aren't needed for plain files
+            other => {
+                tracing::warn!(
+                    "Skipping {} (unsupported tar entry type '{}')",
+                    name,
+                    other as char
+                );
+                Entry::Skip
+            }
+        });
+        self.remaining = size;
+        self.padding = padding(size);
+        if size == 0 {
+            self.close_entry()?;
+        }
+        Ok(())
+    }
+
+    fn close_entry(&mut self) -> Result<()> {
+        match self.entry.take() {
+            Some(Entry::File(file)) => {
+                let Some((path, mode, mtime)) = self.pending.take() else {
+                    return Ok(());
+                };
+                let _ = file.set_modified(UNIX_EPOCH + Duration::from_secs(mtime));
+                drop(file);
+                set_mode(&path, mode);
+                self.files.push(path);
+            }
+            Some(Entry::LongName(name)) => {
+                let end = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+                self.long_name = Some(String::from_utf8_lossy(&name[..end]).into_owned());
+            }
+            Some(Entry::Skip) | None => {}
+        }
+        Ok(())
+    }
+
+    /// Where an entry goes under the destination, refusing paths that would escape it.
+    /// An empty path (like the "./" entry tar adds for the archived folder) is the
+    /// destination itself.
+    fn entry_path(&self, name: &str) -> Result<PathBuf> {
+        let relative = Path::new(name.trim_end_matches('/'));
+        let mut path = self.dest.clone();
+        for component in relative.components() {
+            match component {
+                Component::Normal(part) => path.push(part),
+                Component::CurDir => {}
+                _ => return Err(anyhow!("Refusing unsafe path in archive: {}", name)),
+            }
+        }
+        Ok(path)
+    }
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) {
+    use std::os::unix::fs::PermissionsExt;
+    let _ = fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o777));
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) {}
+
+impl Write for TarExtractor {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.process(buf).map_err(io::Error::other)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Extract a local tar archive into `dest`, returning the files created.
+pub fn extract_file(archive: &Path, dest: &Path) -> Result<Vec<PathBuf>> {
+    let mut input = File::open(archive).context("Failed to read downloaded file")?;
+    let mut extractor = TarExtractor::new(dest)?;
+    io::copy(&mut input, &mut extractor)?;
+    extractor.finish()
+}
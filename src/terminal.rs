@@ -0,0 +1,150 @@
+/// Terminal echo turned off for as long as this lives, so a typed secret isn't shown.
+/// Echo comes back when it is dropped, and also when the process is interrupted
+/// (Ctrl-C, Ctrl-\ or a termination signal) while it lives.
+pub struct EchoGuard {
+    _state: imp::State, // Puts echo back when dropped
+}
+
+impl EchoGuard {
+    /// Turn echo off on the terminal attached to stdin. None where that isn't possible,
+    /// such as when stdin isn't a terminal.
+    pub fn disable() -> Option<Self> {
+        imp::disable().map(|state| EchoGuard { _state: state })
+    }
+}
+
+#[cfg(unix)]
+mod imp {
+    use std::sync::atomic::{AtomicPtr, Ordering};
+
+    // Settings from before echo was turned off, for the signal handler to put back
+    static SAVED: AtomicPtr<libc::termios> = AtomicPtr::new(std::ptr::null_mut());
+
+    const SIGNALS: [libc::c_int; 4] = [libc::SIGINT, libc::SIGQUIT, libc::SIGTERM, libc::SIGHUP];
+
+    pub struct State {
+        saved: libc::termios,
+        handlers: [libc::sigaction; SIGNALS.len()], // To put back once echo is on again
+    }
+
+    pub fn disable() -> Option<State> {
+        // SAFETY: termios is plain data and tcgetattr only writes through the pointer
+        let mut saved: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut saved) } != 0 {
+            return None;
+        }
+        let previous = SAVED.swap(Box::into_raw(Box::new(saved)), Ordering::SeqCst);
+        if !previous.is_null() {
+            // SAFETY: it came from Box::into_raw above, and no guard is alive to use it
+            drop(unsafe { Box::from_raw(previous) });
+        }
+
+        // SAFETY: sigaction is plain data; each call gets valid pointers for the signal
+        let mut handlers: [libc::sigaction; SIGNALS.len()] = unsafe { std::mem::zeroed() };
+        let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+        action.sa_sigaction = restore_and_reraise as extern "C" fn(libc::c_int) as usize;
+        for (signal, handler) in SIGNALS.iter().zip(handlers.iter_mut()) {
+            unsafe { libc::sigaction(*signal, &action, handler) };
+        }
+
+        let mut quiet = saved;
+        quiet.c_lflag &= !libc::ECHO;
+        // SAFETY: quiet is a valid termios read from this terminal
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &quiet) } != 0 {
+            restore_handlers(&handlers);
+            return None;
+        }
+        Some(State { saved, handlers })
+    }
+
+    impl Drop for State {
+        fn drop(&mut self) {
+            // SAFETY: saved is the terminal's own earlier settings
+            unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.saved) };
+            restore_handlers(&self.handlers);
+        }
+    }
+
+    fn restore_handlers(handlers: &[libc::sigaction; SIGNALS.len()]) {
+        for (signal, handler) in SIGNALS.iter().zip(handlers) {
+            // SAFETY: handler is what sigaction reported for this signal
+            unsafe { libc::sigaction(*signal, handler, std::ptr::null_mut()) };
+        }
+    }
+
+    /// Put echo back, then let the signal do what it would have done without us.
+    extern "C" fn restore_and_reraise(signal: libc::c_int) {
+        let saved = SAVED.load(Ordering::SeqCst);
+        // SAFETY: tcsetattr, signal and raise are async-signal-safe, and saved stays
+        // allocated while a guard is alive
+        unsafe {
+            if !saved.is_null() {
+                libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, saved);
+            }
+            libc::signal(signal, libc::SIG_DFL);
+            libc::raise(signal);
+        }
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use windows_sys::Win32::System::Console::{
+        GetConsoleMode, GetStdHandle, SetConsoleCtrlHandler, SetConsoleMode, ENABLE_ECHO_INPUT,
+        STD_INPUT_HANDLE,
+    };
+
+    // Console mode from before echo was turned off, for the Ctrl-C handler to put back
+    static SAVED_MODE: AtomicU32 = AtomicU32::new(0);
+
+    pub struct State {
+        mode: u32,
+    }
+
+    pub fn disable() -> Option<State> {
+        let mut mode = 0;
+        // SAFETY: the handle is checked by GetConsoleMode, and mode is a valid out-pointer
+        unsafe {
+            let input = GetStdHandle(STD_INPUT_HANDLE);
+            if GetConsoleMode(input, &mut mode) == 0 {
+                return None;
+            }
+            SAVED_MODE.store(mode, Ordering::SeqCst);
+            SetConsoleCtrlHandler(Some(restore_on_ctrl), 1);
+            if SetConsoleMode(input, mode & !ENABLE_ECHO_INPUT) == 0 {
+                SetConsoleCtrlHandler(Some(restore_on_ctrl), 0);
+                return None;
+            }
+        }
+        Some(State { mode })
+    }
+
+    impl Drop for State {
+        fn drop(&mut self) {
+            // SAFETY: mode is the console's own earlier mode
+            unsafe {
+                SetConsoleMode(GetStdHandle(STD_INPUT_HANDLE), self.mode);
+                SetConsoleCtrlHandler(Some(restore_on_ctrl), 0);
+            }
+        }
+    }
+
+    /// Put echo back, then let the default handler end the process.
+    unsafe extern "system" fn restore_on_ctrl(_ctrl_type: u32) -> i32 {
+        SetConsoleMode(
+            GetStdHandle(STD_INPUT_HANDLE),
+            SAVED_MODE.load(Ordering::SeqCst),
+        );
+        0
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod imp {
+    pub struct State;
+
+    pub fn disable() -> Option<State> {
+        None
+    }
+}
@@ -0,0 +1,113 @@
+use crate::crypto::PgpHandler;
+use pgp::composed::{KeyType, SecretKeyParamsBuilder, SignedPublicKey};
+use pgp::crypto::sym::SymmetricKeyAlgorithm;
+use pgp::ArmorOptions;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+pub const PASSPHRASE: &str = "secret";
+
+// Generated keys by name, since RSA key generation takes seconds in debug builds
+static KEYS: Mutex<Option<HashMap<String, TestKey>>> = Mutex::new(None);
+
+/// A passphrase-protected key pair, armored as it would be read from key files.
+#[derive(Clone)]
+pub struct TestKey {
+    pub public: Vec<u8>,
+    pub secret: Vec<u8>,
+    pub fingerprint: String,
+}
+
+/// An RSA key for `name` that encrypts with its primary key, as the handler expects.
+pub fn key(name: &str) -> TestKey {
+    key_preferring(name, &[])
+}
+
+/// A key for `name` advertising `ciphers` in its self-signature; empty means no preference.
+pub fn key_preferring(name: &str, ciphers: &[SymmetricKeyAlgorithm]) -> TestKey {
+    let mut keys = KEYS.lock().unwrap();
+    let cache_key = format!("{}{:?}", name, ciphers);
+    if let Some(key) = keys.get_or_insert_with(HashMap::new).get(&cache_key) {
+        return key.clone();
+    }
+
+    let params = SecretKeyParamsBuilder::default()
+        .key_type(KeyType::Rsa(2048))
+        .can_certify(true)
+        .can_sign(true)
+        .can_encrypt(true)
+        .primary_user_id(format!("{} <{}@example.com>", name, name.to_lowercase()))
+        .preferred_symmetric_algorithms(ciphers.to_vec().into())
+        .passphrase(Some(PASSPHRASE.into()))
+        .build()
+        .unwrap();
+    let secret_key = params
+        .generate()
+        .unwrap()
+        .sign(|| PASSPHRASE.into())
+        .unwrap();
+    let public_key = SignedPublicKey::from(secret_key.clone());
+
+    let public = public_key
+        .to_armored_bytes(ArmorOptions::default())
+        .unwrap();
+    let key = TestKey {
+        fingerprint: PgpHandler::get_key_info_from_bytes(&public)
+            .unwrap()
+            .fingerprint,
+        secret: secret_key
+            .to_armored_bytes(ArmorOptions::default())
+            .unwrap(),
+        public,
+    };
+    keys.get_or_insert_with(HashMap::new)
+        .insert(cache_key, key.clone());
+    key
+}
+
+/// A handler that encrypts to every key in `public` and holds the secret halves of `secret`.
+pub fn handler(public: &[&TestKey], secret: &[&TestKey]) -> PgpHandler {
+    let mut handler = PgpHandler::new();
+    for key in public {
+        handler.load_public_key(&key.public).unwrap();
+    }
+    for key in secret {
+        handler
+            .load_secret_key(&key.secret, Some(PASSPHRASE))
+            .unwrap();
+    }
+    handler
+}
+
+/// A fresh folder under the system temp folder, removed with its contents on drop.
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new() -> Self {
+        let id: [u8; 8] = rand::random();
+        let path = std::env::temp_dir().join(format!("rust-r2-test-{}", hex::encode(id)));
+        std::fs::create_dir_all(&path).unwrap();
+        Self { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Write `data` to `relative` inside the folder, creating parent folders as needed.
+    pub fn write(&self, relative: &str, data: &[u8]) -> PathBuf {
+        let path = self.path.join(relative);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, data).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
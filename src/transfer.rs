@@ -0,0 +1,432 @@
+use crate::encryption_filter;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Debug)]
+pub struct LocalFile {
+    pub path: PathBuf,
+    pub relative_path: String, // Always '/'-separated so it can be used in object keys
+    pub size: u64,
+    pub modified: u64, // Modification time in seconds since the Unix epoch
+}
+
+/// A file's modification time in whole seconds since the Unix epoch, or 0 if unknown.
+pub fn modified_secs(metadata: &std::fs::Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Recursively collect the files under `root`, skipping hidden directories like .git
+pub fn scan_directory(root: &Path) -> Vec<LocalFile> {
+    let mut files = Vec::new();
+    scan_directory_with(root, &mut |file| {
+        files.push(file);
+        true
+    });
+    files
+}
+
+/// Walk `root` like `scan_directory`, handing each file to `on_file` as it is found.
+/// The walk stops early once `on_file` returns false.
+pub fn scan_directory_with(root: &Path, on_file: &mut dyn FnMut(LocalFile) -> bool) {
+    scan_directory_recursive(root, "", on_file);
+}
+
+fn scan_directory_recursive(
+    current_folder: &Path,
+    prefix: &str,
+    on_file: &mut dyn FnMut(LocalFile) -> bool,
+) -> bool {
+    let Ok(entries) = std::fs::read_dir(current_folder) else {
+        return true;
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Some(name) = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .map(str::to_string)
+        else {
+            continue;
+        };
+        let relative_path = if prefix.is_empty() {
+            name.clone()
+        } else {
+            format!("{}/{}", prefix, name)
+        };
+
+        if path.is_file() {
+            if let Ok(metadata) = entry.metadata() {
+                let file = LocalFile {
+                    path,
+                    relative_path,
+                    size: metadata.len(),
+                    modified: modified_secs(&metadata),
+                };
+                if !on_file(file) {
+                    return false;
+                }
+            }
+        } else if path.is_dir()
+            && !name.starts_with('.')
+            && !scan_directory_recursive(&path, &relative_path, on_file)
+        {
+            return false;
+        }
+    }
+    true
+}
+
+/// Join a key prefix and a relative path with exactly one '/' between them.
+pub fn join_key(prefix: &str, relative_path: &str) -> String {
+    let prefix = prefix.trim_end_matches('/');
+    if prefix.is_empty() {
+        relative_path.to_string()
+    } else {
+        format!("{}/{}", prefix, relative_path)
+    }
+}
+
+/// The filename recorded on an uploaded object: the local file's own name.
+pub fn upload_file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "data".to_string())
+}
+
+/// Pick the local filename for a downloaded object. A restored original filename
+/// wins over the key's last segment; its encrypted extension is stripped from the
+/// latter when the data is being decrypted.
+pub fn download_file_name(key: &str, restored_name: Option<&str>, decrypt: bool) -> String {
+    if let Some(name) = restored_name.and_then(sanitize_file_name) {
+        return name;
+    }
+
+    let base_name = key.rsplit('/').next().unwrap_or(key);
+    match encryption_filter::strip_encrypted_extension(base_name) {
+        Some(stripped) if decrypt && !stripped.is_empty() => stripped.to_string(),
+        _ => base_name.to_string(),
+    }
+}
+
+/// Reduce an untrusted name to a bare filename so it cannot escape the target folder.
+fn sanitize_file_name(name: &str) -> Option<String> {
+    let name = name.rsplit(['/', '\\']).next()?.trim();
+    if name.is_empty() || name == "." || name == ".." {
+        None
+    } else {
+        Some(name.to_string())
+    }
+}
+
+/// The part of `key` beneath `prefix`. Keys outside the prefix (or equal to it) are returned whole.
+pub fn relative_key<'a>(key: &'a str, prefix: &str) -> &'a str {
+    match key.strip_prefix(prefix) {
+        Some(rest) if !rest.is_empty() => rest,
+        _ => key,
+    }
+}
+
+/// True if a Content-Encoding header value says the body is gzip-compressed.
+pub fn is_gzip_encoding(content_encoding: Option<&str>) -> bool {
+    content_encoding.is_some_and(|encoding| {
+        encoding
+            .split(',')
+            .any(|coding| matches!(coding.trim().to_lowercase().as_str(), "gzip" | "x-gzip"))
+    })
+}
+
+/// Inflate a gzip body, including bodies made of several concatenated gzip members.
+pub fn gunzip(data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    use anyhow::Context;
+    use std::io::Read;
+
+    let mut decompressed = Vec::new();
+    flate2::read::MultiGzDecoder::new(data)
+        .read_to_end(&mut decompressed)
+        .context("Failed to decompress gzip body")?;
+    Ok(decompressed)
+}
+
+/// Parse a duration such as "90s", "10m", "1h30m" or "500ms". A bare number is seconds.
+pub fn parse_duration(input: &str) -> anyhow::Result<std::time::Duration> {
+    let input = input.trim();
+    if let Ok(secs) = input.parse::<u64>() {
+        return Ok(std::time::Duration::from_secs(secs));
+    }
+
+    let mut total = std::time::Duration::ZERO;
+    let mut rest = input;
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let value: u64 = rest[..digits]
+            .parse()
+            .map_err(|_| anyhow::anyhow!("Invalid duration '{}'", input))?;
+        rest = &rest[digits..];
+
+        let unit_len = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let unit = match &rest[..unit_len] {
+            "ms" => std::time::Duration::from_millis(value),
+            "s" => std::time::Duration::from_secs(value),
+            "m" => std::time::Duration::from_secs(value * 60),
+            "h" => std::time::Duration::from_secs(value * 3600),
+            "d" => std::time::Duration::from_secs(value * 86400),
+            _ => anyhow::bail!(
+                "Invalid duration '{}', use units like 90s, 10m or 1h30m",
+                input
+            ),
+        };
+        total += unit;
+        rest = &rest[unit_len..];
+    }
+
+    if total.is_zero() {
+        anyhow::bail!("Invalid duration '{}'", input);
+    }
+    Ok(total)
+}
+
+/// Parse a size such as "512M", "2G" or "1.5GiB". Units are binary (K = 1024 bytes) and
+/// case-insensitive; a bare number is bytes.
+pub fn parse_size(input: &str) -> anyhow::Result<u64> {
+    let input = input.trim();
+    let digits = input
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(input.len());
+    let value: f64 = input[..digits]
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Invalid size '{}'", input))?;
+    let multiplier: u64 = match input[digits..].trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => 1 << 10,
+        "m" | "mb" | "mib" => 1 << 20,
+        "g" | "gb" | "gib" => 1 << 30,
+        "t" | "tb" | "tib" => 1 << 40,
+        _ => anyhow::bail!("Invalid size '{}', use units like 512M or 2G", input),
+    };
+    let bytes = value * multiplier as f64;
+    if !bytes.is_finite() || bytes < 1.0 || bytes > u64::MAX as f64 {
+        anyhow::bail!("Invalid size '{}'", input);
+    }
+    Ok(bytes as u64)
+}
+
+/// Render an object key template such as "backups/{Y}/{m}/{d}/{name}" for an upload of
+/// `path` at time `at`. Date placeholders follow strftime: {Y} year, {y} two-digit year,
+/// {m} month, {d} day, {H} hour, {M} minute, {S} second and {j} day of the year. {name}
+/// is the file name, {basename} the file name without its extension and {ext} the
+/// extension without the dot. Write {{ and }} for literal braces.
+pub fn render_key_template(
+    template: &str,
+    path: &Path,
+    at: chrono::DateTime<chrono::Local>,
+) -> anyhow::Result<String> {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let (basename, ext) = match name.rsplit_once('.') {
+        Some((basename, ext)) if !basename.is_empty() => (basename.to_string(), ext.to_string()),
+        _ => (name.clone(), String::new()),
+    };
+
+    let mut key = String::new();
+    let mut rest = template;
+    while let Some(i) = rest.find(['{', '}']) {
+        key.push_str(&rest[..i]);
+        let brace = &rest[i..];
+        if let Some(escaped) = brace
+            .strip_prefix("{{")
+            .or_else(|| brace.strip_prefix("}}"))
+        {
+            key.push_str(&brace[..1]);
+            rest = escaped;
+            continue;
+        }
+        let Some(end) = brace.strip_prefix('{').and_then(|b| b.find('}')) else {
+            return Err(anyhow::anyhow!(
+                "Unbalanced brace in key template '{}', write {{{{ or }}}} for a literal brace",
+                template
+            ));
+        };
+        let placeholder = &brace[1..end + 1];
+        let value = match placeholder {
+            "Y" | "y" | "m" | "d" | "H" | "M" | "S" | "j" => {
+                at.format(&format!("%{}", placeholder)).to_string()
+            }
+            "name" => name.clone(),
+            "basename" => basename.clone(),
+            "ext" => ext.clone(),
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "Unknown placeholder {{{}}} in key template '{}', use {{Y}}, {{y}}, {{m}}, {{d}}, {{H}}, {{M}}, {{S}}, {{j}}, {{name}}, {{basename}} or {{ext}}",
+                    placeholder,
+                    template
+                ))
+            }
+        };
+        key.push_str(&value);
+        rest = &brace[end + 2..];
+    }
+    key.push_str(rest);
+
+    if key.is_empty() {
+        return Err(anyhow::anyhow!(
+            "Key template '{}' renders an empty key",
+            template
+        ));
+    }
+    Ok(key)
+}
+
+/// Parse a `name=value` pair of user metadata, stored as an `x-amz-meta-<name>` header.
+/// Names are lowercased and limited to letters, digits, '-' and '_'; values must be
+/// printable ASCII, since they travel as header values.
+pub fn parse_metadata(input: &str) -> anyhow::Result<(String, String)> {
+    let (name, value) = input
+        .split_once('=')
+        .ok_or_else(|| anyhow::anyhow!("Invalid metadata '{}', expected KEY=VALUE", input))?;
+    let name = name.trim().to_lowercase();
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(anyhow::anyhow!(
+            "Invalid metadata name '{}': use letters, digits, '-' and '_'",
+            name
+        ));
+    }
+    if crate::r2_client::RESERVED_METADATA.contains(&name.as_str()) {
+        return Err(anyhow::anyhow!(
+            "Metadata name '{}' is set by the upload itself",
+            name
+        ));
+    }
+    if !value.chars().all(|c| c == ' ' || c.is_ascii_graphic()) {
+        return Err(anyhow::anyhow!(
+            "Invalid value for metadata '{}': only printable ASCII is allowed",
+            name
+        ));
+    }
+    Ok((name, value.trim().to_string()))
+}
+
+/// Parse an Expires time: an RFC 3339 timestamp ("2025-01-31T00:00:00Z"), an HTTP date
+/// ("Fri, 31 Jan 2025 00:00:00 GMT"), or a duration from now such as "7d".
+pub fn parse_expires(input: &str) -> anyhow::Result<chrono::DateTime<chrono::Utc>> {
+    let input = input.trim();
+    if let Ok(time) = chrono::DateTime::parse_from_rfc3339(input) {
+        return Ok(time.with_timezone(&chrono::Utc));
+    }
+    if let Ok(time) = chrono::DateTime::parse_from_rfc2822(input) {
+        return Ok(time.with_timezone(&chrono::Utc));
+    }
+    let duration = parse_duration(input).map_err(|_| {
+        anyhow::anyhow!(
+            "Invalid expiry '{}', use a date like 2025-01-31T00:00:00Z or a duration like 7d",
+            input
+        )
+    })?;
+    Ok(chrono::Utc::now() + chrono::Duration::from_std(duration)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_keys_drop_the_listed_prefix() {
+        assert_eq!(relative_key("logs/2024/app.log", "logs/"), "2024/app.log");
+        assert_eq!(relative_key("logs/2024/app.log", "logs/20"), "24/app.log");
+        // Nothing would be left to show, or the key isn't under the prefix at all
+        assert_eq!(relative_key("logs/", "logs/"), "logs/");
+        assert_eq!(relative_key("other/app.log", "logs/"), "other/app.log");
+    }
+
+    #[test]
+    fn download_names_drop_every_encrypted_extension_when_decrypting() {
+        for ext in [".pgp", ".gpg", ".asc", ".GPG"] {
+            let key = format!("reports/q3.pdf{}", ext);
+            assert_eq!(download_file_name(&key, None, true), "q3.pdf");
+            assert_eq!(
+                download_file_name(&key, None, false),
+                format!("q3.pdf{}", ext)
+            );
+        }
+        assert_eq!(download_file_name("reports/q3.pdf", None, true), "q3.pdf");
+        // A restored original name wins, and a bare extension stays a name
+        assert_eq!(
+            download_file_name("reports/q3.pdf.gpg", Some("Q3 final.pdf"), true),
+            "Q3 final.pdf"
+        );
+        assert_eq!(download_file_name("reports/.asc", None, true), ".asc");
+    }
+
+    #[test]
+    fn key_templates_render_dates_and_file_names() {
+        use chrono::TimeZone;
+        let at = chrono::Local
+            .with_ymd_and_hms(2024, 6, 30, 9, 5, 7)
+            .unwrap();
+        let render =
+            |template: &str, path: &str| render_key_template(template, Path::new(path), at);
+
+        assert_eq!(
+            render("backups/{Y}/{m}/{d}/{name}", "/var/dumps/db.sql").unwrap(),
+            "backups/2024/06/30/db.sql"
+        );
+        assert_eq!(
+            render("{basename}-{y}{j}T{H}{M}{S}.{ext}", "db.sql").unwrap(),
+            "db-24182T090507.sql"
+        );
+        // Dotfiles and extensionless names have no extension
+        assert_eq!(render("{basename}|{ext}", ".env").unwrap(), ".env|");
+        assert_eq!(render("{basename}|{ext}", "Makefile").unwrap(), "Makefile|");
+        assert_eq!(render("{{{name}}}", "a.txt").unwrap(), "{a.txt}");
+        // The encrypted extension is added after rendering, once
+        let key = render("backups/{name}", "db.sql.gpg").unwrap();
+        assert_eq!(
+            encryption_filter::with_encrypted_extension(&key),
+            "backups/db.sql.gpg"
+        );
+
+        let error = render("backups/{date}/{name}", "db.sql").unwrap_err();
+        assert!(
+            error.to_string().contains("Unknown placeholder {date}"),
+            "{}",
+            error
+        );
+        assert!(render("backups/{Y/{name}", "db.sql").is_err());
+        assert!(render("backups/}", "db.sql").is_err());
+        assert!(render("{ext}", "Makefile").is_err());
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        use std::io::Write;
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn gzip_bodies_are_recognised_and_inflated() {
+        assert!(is_gzip_encoding(Some("gzip")));
+        assert!(is_gzip_encoding(Some("identity, X-GZIP")));
+        assert!(!is_gzip_encoding(Some("br")));
+        assert!(!is_gzip_encoding(None));
+
+        // Concatenated members inflate to the concatenated data
+        let mut body = gzip(b"first member, ");
+        body.extend(gzip(b"second member"));
+        assert_eq!(gunzip(&body).unwrap(), b"first member, second member");
+        assert!(gunzip(b"not gzip").is_err());
+    }
+}
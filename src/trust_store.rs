@@ -0,0 +1,200 @@
+use crate::crypto::KeyInfo;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Trust-on-first-use: the first time a public key is loaded its fingerprint must be
+// confirmed, and the decision is recorded here. Later loads of the same fingerprint are
+// silent, while a new fingerprint for an email we already trust is flagged as a change.
+
+/// A recorded decision about one key.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TrustRecord {
+    pub fingerprint: String,
+    pub name: String,
+    pub email: String,
+    pub trusted: bool,
+    pub decided_at: String, // RFC 3339
+}
+
+/// What the trust store says about a loaded key.
+#[derive(Clone, Debug, PartialEq)]
+pub enum KeyTrust {
+    Trusted,
+    Rejected,
+    New,
+    Changed { previous: Vec<String> }, // Trusted fingerprints recorded for the same email
+}
+
+pub struct TrustStore {
+    path: PathBuf,
+    records: Vec<TrustRecord>,
+}
+
+impl TrustStore {
+    /// `trusted_keys.json` in the per-user config folder.
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("rust-r2").join("trusted_keys.json"))
+    }
+
+    pub fn open_default() -> Result<Self> {
+        let path = Self::default_path()
+            .ok_or_else(|| anyhow::anyhow!("No config folder found for the key trust store"))?;
+        Self::open(&path)
+    }
+
+    /// Load the store at `path`. A missing file is an empty store.
+    pub fn open(path: &Path) -> Result<Self> {
+        let records = if path.exists() {
+            let content = fs::read_to_string(path).context("Failed to read key trust store")?;
+            serde_json::from_str(&content).context("Failed to parse key trust store")?
+        } else {
+            Vec::new()
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            records,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn check(&self, key: &KeyInfo) -> KeyTrust {
+        let fingerprint = normalize_fingerprint(&key.fingerprint);
+        if let Some(record) = self.records.iter().find(|r| r.fingerprint == fingerprint) {
+            return if record.trusted {
+                KeyTrust::Trusted
+            } else {
+                KeyTrust::Rejected
+            };
+        }
+
+        let previous: Vec<String> = self
+            .records
+            .iter()
+            .filter(|r| {
+                r.trusted && !key.email.is_empty() && r.email.eq_ignore_ascii_case(&key.email)
+            })
+            .map(|r| r.fingerprint.clone())
+            .collect();
+        if previous.is_empty() {
+            KeyTrust::New
+        } else {
+            KeyTrust::Changed { previous }
+        }
+    }
+
+    /// Record whether `key` is trusted, replacing any earlier decision about it.
+    pub fn record(&mut self, key: &KeyInfo, trusted: bool) {
+        let fingerprint = normalize_fingerprint(&key.fingerprint);
+        self.records.retain(|r| r.fingerprint != fingerprint);
+        self.records.push(TrustRecord {
+            fingerprint,
+            name: key.name.clone(),
+            email: key.email.clone(),
+            trusted,
+            decided_at: chrono::Utc::now().to_rfc3339(),
+        });
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).context("Failed to create key trust store folder")?;
+        }
+        let content = serde_json::to_string_pretty(&self.records)
+            .context("Failed to serialize key trust store")?;
+        fs::write(&self.path, content).context("Failed to write key trust store")
+    }
+}
+
+/// Fingerprints are compared as uppercase hex without spaces.
+pub fn normalize_fingerprint(fingerprint: &str) -> String {
+    fingerprint
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_uppercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    fn key_info(email: &str, fingerprint: &str) -> KeyInfo {
+        KeyInfo {
+            name: "Alice".to_string(),
+            email: email.to_string(),
+            key_id: fingerprint[fingerprint.len() - 16..].to_string(),
+            fingerprint: fingerprint.to_string(),
+            expires_at: None,
+        }
+    }
+
+    #[test]
+    fn new_known_and_changed_fingerprints() {
+        let folder = TempDir::new();
+        let path = folder.path().join("trust").join("trusted_keys.json");
+        let original = key_info(
+            "alice@example.com",
+            "0123456789ABCDEF0123456789ABCDEF01234567",
+        );
+        let mut store = TrustStore::open(&path).unwrap();
+        assert_eq!(store.check(&original), KeyTrust::New);
+
+        store.record(&original, true);
+        store.save().unwrap();
+        let store = TrustStore::open(&path).unwrap();
+        assert_eq!(store.check(&original), KeyTrust::Trusted);
+        // Fingerprints match however they are spaced or cased
+        let spaced = key_info(
+            "alice@example.com",
+            "0123 4567 89ab cdef 0123 4567 89ab cdef 0123 4567",
+        );
+        assert_eq!(store.check(&spaced), KeyTrust::Trusted);
+
+        let substituted = key_info(
+            "Alice@Example.com",
+            "FEDCBA9876543210FEDCBA9876543210FEDCBA98",
+        );
+        assert_eq!(
+            store.check(&substituted),
+            KeyTrust::Changed {
+                previous: vec![original.fingerprint.clone()]
+            }
+        );
+        let unrelated = key_info(
+            "bob@example.com",
+            "FEDCBA9876543210FEDCBA9876543210FEDCBA98",
+        );
+        assert_eq!(store.check(&unrelated), KeyTrust::New);
+    }
+
+    #[test]
+    fn rejected_keys_stay_rejected_and_are_not_a_baseline() {
+        let folder = TempDir::new();
+        let path = folder.path().join("trusted_keys.json");
+        let rejected = key_info(
+            "mallory@example.com",
+            "0123456789ABCDEF0123456789ABCDEF01234567",
+        );
+        let mut store = TrustStore::open(&path).unwrap();
+        store.record(&rejected, false);
+        assert_eq!(store.check(&rejected), KeyTrust::Rejected);
+
+        // Only trusted fingerprints count as the previous key for an email
+        let other = key_info(
+            "mallory@example.com",
+            "FEDCBA9876543210FEDCBA9876543210FEDCBA98",
+        );
+        assert_eq!(store.check(&other), KeyTrust::New);
+
+        // A later decision replaces the earlier one
+        store.record(&rejected, true);
+        assert_eq!(store.check(&rejected), KeyTrust::Trusted);
+        assert_eq!(store.records.len(), 1);
+    }
+}